@@ -3,7 +3,17 @@ use std::collections::HashMap;
 const CSV_SEPARATOR: char = ',';
 const SCOPE_SEPARATOR: &str = "::";
 const COUNTER_SCOPE: &str = "counters";
-const START_TOKEN: &str = "START BENCHMARK";
+/// Marks the start of the results in the output of a benchmark run.
+pub const START_TOKEN: &str = "START BENCHMARK";
+/// Marks the start of a latency histogram, followed by one `<bound>,<count>` line per bucket.
+pub const HISTOGRAM_TOKEN: &str = "START HISTOGRAM";
+/// Marks the start of the raw samples of a latency histogram, encoded as hexadecimal 64 bits
+/// little endian integers.
+pub const SAMPLES_TOKEN: &str = "START SAMPLES";
+/// Marks the end of the raw samples of a latency histogram.
+pub const END_SAMPLES_TOKEN: &str = "END SAMPLES";
+/// The percentiles reported for latency histograms.
+const REPORTED_PERCENTILES: [f64; 5] = [50.0, 90.0, 99.0, 99.9, 100.0];
 
 /// Parse a benchmark file in order to get a map from tags to list of usize values.
 pub fn parse_content(
@@ -13,7 +23,8 @@ pub fn parse_content(
     let mut results = content
         .iter()
         .skip_while(|s| !s.contains(START_TOKEN))
-        .skip(1);
+        .skip(1)
+        .take_while(|s| !s.contains(HISTOGRAM_TOKEN));
 
     // Retrieve statistics names
     let stats: Vec<&str> = results
@@ -44,12 +55,108 @@ pub fn parse_content(
     });
 }
 
+/// Parse the raw latency samples of a benchmark output, and append them to the samples of the
+/// same latency.
+pub fn parse_samples(content: &[String], samples: &mut HashMap<String, Vec<u64>>) {
+    let mut current: Option<&mut Vec<u64>> = None;
+    for line in content {
+        if let Some((_, header)) = line.split_once(SAMPLES_TOKEN) {
+            let name = header
+                .split_whitespace()
+                .next()
+                .expect("Missing latency name");
+            current = Some(samples.entry(name.to_string()).or_default());
+        } else if line.contains(END_SAMPLES_TOKEN) {
+            current = None;
+        } else if let Some(ref mut values) = current {
+            let bytes = decode_hex(line.trim()).expect("Wrong samples format: invalid hex");
+            assert!(
+                bytes.len() % 8 == 0,
+                "Wrong samples format: truncated sample"
+            );
+            values.extend(
+                bytes
+                    .chunks(8)
+                    .map(|sample| u64::from_le_bytes(sample.try_into().unwrap())),
+            );
+        }
+    }
+}
+
+fn decode_hex(line: &str) -> Option<Vec<u8>> {
+    if line.len() % 2 != 0 {
+        return None;
+    }
+    (0..line.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(line.get(idx..idx + 2)?, 16).ok())
+        .collect()
+}
+
+/// Returns the smallest sample such that at least `percent` percents of the samples are lower or
+/// equal (nearest-rank method). The samples must be sorted.
+pub fn percentile(sorted_samples: &[u64], percent: f64) -> Option<u64> {
+    if sorted_samples.is_empty() {
+        return None;
+    }
+    let rank = (percent / 100.0 * sorted_samples.len() as f64).ceil() as usize;
+    Some(sorted_samples[rank.clamp(1, sorted_samples.len()) - 1])
+}
+
+/// Print the main percentiles of the latency samples.
+pub fn print_percentiles(samples: &HashMap<String, Vec<u64>>) {
+    for (name, values) in samples {
+        let mut sorted = values.clone();
+        sorted.sort_unstable();
+        println!("╔{:─>30}╗", "");
+        println!("│{:^30}│", format!("{} ({} samples)", name, sorted.len()));
+        for percent in REPORTED_PERCENTILES {
+            if let Some(value) = percentile(&sorted, percent) {
+                println!("│  p{:<6}: {:>19} │", percent, value);
+            }
+        }
+        println!("╚{:─>30}╝", "");
+    }
+}
+
+/// Two-sided 95% quantiles of the Student t-distribution, indexed by degrees of freedom minus one.
+const T_95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+    2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+    2.052, 2.048, 2.045, 2.042,
+];
+
+/// Quantile of the normal distribution, used for more than 30 degrees of freedom.
+const Z_95: f64 = 1.960;
+
 #[derive(Default, Debug)]
 struct CounterStats {
     min: usize,
     max: usize,
     mean: usize,
     avg_sum: usize,
+    /// Half-width of the 95% confidence interval of the mean, if there are at least two runs.
+    mean_ci: Option<f64>,
+}
+
+/// Returns the mean of the values and the half-width of its 95% confidence interval.
+///
+/// The values are assumed to be independent samples (e.g. one per run), the interval is computed
+/// with the Student t-distribution and requires at least two values.
+pub fn confidence_interval(values: &[usize]) -> Option<(f64, f64)> {
+    if values.len() < 2 {
+        return None;
+    }
+
+    let n = values.len() as f64;
+    let mean = values.iter().map(|&v| v as f64).sum::<f64>() / n;
+    let variance = values
+        .iter()
+        .map(|&v| (v as f64 - mean).powi(2))
+        .sum::<f64>()
+        / (n - 1.0);
+    let t = T_95.get(values.len() - 2).copied().unwrap_or(Z_95);
+    Some((mean, t * (variance / n).sqrt()))
 }
 
 /// Compute average of all parameters to have statistics over all runs.
@@ -80,6 +187,7 @@ pub fn compute_statistics(stat_counter_values_map: &HashMap<String, HashMap<Stri
                 a.avg_sum = values.iter().sum::<usize>() / values.len();
             } else if stat == "mean" {
                 a.mean = values.iter().sum::<usize>() / values.len();
+                a.mean_ci = confidence_interval(values).map(|(_, ci)| ci);
             }
         }
     }
@@ -103,6 +211,9 @@ fn print_stats(scope_stats_counters: &HashMap<String, HashMap<String, CounterSta
                 println!("││  Max: {:>20} ││", stats.max);
                 println!("││  Avg. sum: {:>15} ││", stats.avg_sum);
                 println!("││  Mean: {:>19} ││", stats.mean);
+                if let Some(ci) = stats.mean_ci {
+                    println!("││  95% CI: {:>17} ││", format!("±{:.1}", ci));
+                }
             }
 
             println!("│╚{:─>28}╝│", "");
@@ -110,3 +221,60 @@ fn print_stats(scope_stats_counters: &HashMap<String, HashMap<String, CounterSta
         println!("╚{:─>30}╝", "");
     }
 }
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn confidence_interval_of_the_mean() {
+        assert_eq!(confidence_interval(&[]), None);
+        assert_eq!(confidence_interval(&[42]), None);
+        assert_eq!(confidence_interval(&[7, 7, 7]), Some((7.0, 0.0)));
+
+        // Mean 10, sample variance 2, standard error 1
+        let (mean, ci) = confidence_interval(&[9, 11]).unwrap();
+        assert_eq!(mean, 10.0);
+        assert!((ci - T_95[0]).abs() < 1e-9);
+
+        // The interval narrows with more samples
+        let (_, narrow) = confidence_interval(&[9, 11, 9, 11, 9, 11, 9, 11]).unwrap();
+        assert!(narrow < ci);
+    }
+
+    #[test]
+    fn latency_samples() {
+        let content: Vec<String> = [
+            "START BENCHMARK",
+            "counter,min,max,sum,mean",
+            "Total exits,3,3,3,3",
+            "START HISTOGRAM world_switch 2",
+            "1024,3",
+            "inf,0",
+            "START SAMPLES world_switch 3",
+            "01000000000000000302000000000000",
+            "0400000000000000",
+            "END SAMPLES world_switch",
+        ]
+        .iter()
+        .map(|line| line.to_string())
+        .collect();
+
+        let mut samples = HashMap::new();
+        parse_samples(&content, &mut samples);
+        assert_eq!(samples["world_switch"], [1, 0x0203, 4]);
+
+        // The histograms are not part of the counters
+        let mut counters = HashMap::new();
+        parse_content(content, &mut counters);
+        assert_eq!(counters["max"].len(), 1);
+
+        let sorted = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        assert_eq!(percentile(&sorted, 50.0), Some(5));
+        assert_eq!(percentile(&sorted, 99.0), Some(10));
+        assert_eq!(percentile(&sorted, 0.0), Some(1));
+        assert_eq!(percentile(&[], 50.0), None);
+    }
+}
@@ -12,7 +12,12 @@ use core::hint;
 
 pub use config_helpers::{is_enabled, parse_usize_or};
 use log::Level;
-use miralis_core::{abi, abi_protect_payload};
+pub use miralis_core::abi::audit::AuditEntry;
+pub use miralis_core::abi::memory_layout::MemoryRegion;
+pub use miralis_core::abi::pmp_view::{self, PmpEntry};
+pub use miralis_core::abi_protect_payload::report as payload_report;
+pub use miralis_core::abi_vendor::benchmark as benchmark_masks;
+use miralis_core::{abi, abi_protect_domains, abi_protect_payload, abi_vendor};
 
 use crate::logger::StackBuffer;
 
@@ -42,6 +47,32 @@ pub fn failure() -> ! {
     }
 }
 
+/// Report a panic to Miralis, which logs it along with the registers of the caller and exits with
+/// an error code.
+///
+/// Messages that do not fit in the internal buffer are truncated.
+pub fn report_panic(info: &core::panic::PanicInfo) -> ! {
+    let mut buff: StackBuffer<512> = StackBuffer::new();
+    // The buffer keeps what fits if the message is too long
+    write!(buff, "{}", info).ok();
+    let message = buff.as_str();
+    unsafe {
+        ecall3(
+            abi::MIRALIS_EID,
+            abi::MIRALIS_PANIC_FID,
+            message.as_ptr() as usize,
+            message.len(),
+            0,
+        )
+        .ok()
+    };
+
+    // Loop forever, this should never happen as Miralis will terminate the execution before.
+    loop {
+        hint::spin_loop();
+    }
+}
+
 /// Ask Miralis to end benchmark and print results.
 pub fn miralis_end_benchmark() -> ! {
     unsafe { miralis_ecall(abi::MIRALIS_BENCHMARK_FID).ok() };
@@ -63,6 +94,222 @@ pub fn lock_payload() {
     };
 }
 
+/// Ask Miralis for the attestation report of the payload, bound to the given nonce.
+///
+/// See [payload_report] for the layout of the report. Returns the size of the report.
+pub fn read_payload_report(
+    nonce: &[u8; payload_report::NONCE_SIZE],
+    report: &mut [u8; payload_report::SIZE],
+) -> Result<usize, usize> {
+    unsafe {
+        ecall3(
+            abi_protect_payload::MIRALIS_PROTECT_PAYLOAD_EID,
+            abi_protect_payload::MIRALIS_PROTECT_PAYLOAD_REPORT_FID,
+            report.as_mut_ptr() as usize,
+            nonce.as_ptr() as usize,
+            0,
+        )
+    }
+}
+
+/// Ask Miralis to protect a memory region from the firmware, returns the ID of the new domain.
+///
+/// The size must be a power of two and the start address aligned to the size, and the domain must
+/// lie within the payload memory. The domain is enforced on all harts when the call returns.
+pub fn register_domain(start: usize, size: usize) -> Result<usize, usize> {
+    unsafe {
+        ecall3(
+            abi_protect_domains::MIRALIS_PROTECT_DOMAINS_EID,
+            abi_protect_domains::MIRALIS_PROTECT_DOMAINS_REGISTER_FID,
+            start,
+            size,
+            0,
+        )
+    }
+}
+
+/// Ask Miralis to stop protecting a domain.
+pub fn unregister_domain(domain: usize) -> Result<usize, usize> {
+    unsafe {
+        ecall3(
+            abi_protect_domains::MIRALIS_PROTECT_DOMAINS_EID,
+            abi_protect_domains::MIRALIS_PROTECT_DOMAINS_UNREGISTER_FID,
+            domain,
+            0,
+            0,
+        )
+    }
+}
+
+/// Ask Miralis to copy the audit log, starting from the entry with sequence number `first`.
+///
+/// Returns the number of entries copied into `buffer`. Entries older than the retained part of the
+/// log are skipped, the caller can detect it from the sequence numbers.
+pub fn read_audit_log(first: u64, buffer: &mut [AuditEntry]) -> Result<usize, usize> {
+    unsafe {
+        ecall3(
+            abi::MIRALIS_EID,
+            abi::MIRALIS_AUDIT_LOG_READ_FID,
+            first as usize,
+            buffer.as_mut_ptr() as usize,
+            buffer.len(),
+        )
+    }
+}
+
+/// Ask Miralis to copy its capability report, one `key=value` pair per line.
+///
+/// Returns the full length of the report, which is truncated if it does not fit in `buffer`.
+pub fn read_capabilities(buffer: &mut [u8]) -> Result<usize, usize> {
+    unsafe {
+        ecall3(
+            abi::MIRALIS_EID,
+            abi::MIRALIS_CAPABILITIES_FID,
+            buffer.as_mut_ptr() as usize,
+            buffer.len(),
+            0,
+        )
+    }
+}
+
+/// Ask Miralis for the memory layout, such that the caller can avoid the regions it does not own.
+///
+/// Returns the total number of regions, only the first `buffer.len()` are copied.
+pub fn read_memory_layout(buffer: &mut [MemoryRegion]) -> Result<usize, usize> {
+    unsafe {
+        ecall3(
+            abi::MIRALIS_EID,
+            abi::MIRALIS_MEMORY_LAYOUT_FID,
+            buffer.as_mut_ptr() as usize,
+            buffer.len(),
+            0,
+        )
+    }
+}
+
+/// Ask Miralis for the PMP entries in effect for the caller, to debug unexpected access faults.
+///
+/// Returns the total number of entries, only the first `buffer.len()` are copied.
+pub fn read_pmp_view(buffer: &mut [PmpEntry]) -> Result<usize, usize> {
+    unsafe {
+        ecall3(
+            abi::MIRALIS_EID,
+            abi::MIRALIS_PMP_VIEW_FID,
+            buffer.as_mut_ptr() as usize,
+            buffer.len(),
+            0,
+        )
+    }
+}
+
+/// Ask Miralis to copy its build information, one `key=value` pair per line.
+///
+/// Returns the full length of the build information, which is truncated if it does not fit in
+/// `buffer`.
+pub fn read_build_info(buffer: &mut [u8]) -> Result<usize, usize> {
+    unsafe {
+        ecall3(
+            abi::MIRALIS_EID,
+            abi::MIRALIS_BUILD_INFO_FID,
+            buffer.as_mut_ptr() as usize,
+            buffer.len(),
+            0,
+        )
+    }
+}
+
+/// Ask Miralis to dump the trap log of the current hart on its console, see `runner trap-log`.
+pub fn dump_trap_log() -> Result<usize, usize> {
+    unsafe { ecall3(abi::MIRALIS_EID, abi::MIRALIS_TRAP_LOG_DUMP_FID, 0, 0, 0) }
+}
+
+/// Ask Miralis to update its log filters, e.g. `virt=debug,ace=info`.
+pub fn set_log_filters(spec: &str) -> Result<usize, usize> {
+    unsafe {
+        ecall3(
+            abi::MIRALIS_EID,
+            abi::MIRALIS_LOG_FILTER_FID,
+            spec.as_ptr() as usize,
+            spec.len(),
+            0,
+        )
+    }
+}
+
+/// Ask Miralis for its version, encoded as `major << 24 | minor << 12 | patch`.
+pub fn vendor_version() -> Result<usize, usize> {
+    unsafe {
+        ecall0(
+            abi_vendor::MIRALIS_VENDOR_EID,
+            abi_vendor::MIRALIS_VENDOR_GET_VERSION_FID,
+        )
+    }
+}
+
+/// Ask Miralis for the number of firmware exits on the given hart.
+pub fn vendor_firmware_exits(hart: usize) -> Result<usize, usize> {
+    unsafe {
+        ecall3(
+            abi_vendor::MIRALIS_VENDOR_EID,
+            abi_vendor::MIRALIS_VENDOR_GET_FIRMWARE_EXITS_FID,
+            hart,
+            0,
+            0,
+        )
+    }
+}
+
+/// Ask Miralis to copy the name of the active policy.
+///
+/// Returns the full length of the name, which is truncated if it does not fit in `buffer`.
+pub fn vendor_policy_name(buffer: &mut [u8]) -> Result<usize, usize> {
+    unsafe {
+        ecall3(
+            abi_vendor::MIRALIS_VENDOR_EID,
+            abi_vendor::MIRALIS_VENDOR_GET_POLICY_NAME_FID,
+            buffer.as_mut_ptr() as usize,
+            buffer.len(),
+            0,
+        )
+    }
+}
+
+/// Ask Miralis to dump its benchmark counters, fails if Miralis is not built for benchmarking.
+pub fn vendor_dump_benchmark() -> Result<usize, usize> {
+    unsafe {
+        ecall0(
+            abi_vendor::MIRALIS_VENDOR_EID,
+            abi_vendor::MIRALIS_VENDOR_DUMP_BENCHMARK_FID,
+        )
+    }
+}
+
+/// Ask Miralis to record only the given benchmark counters and scopes, as masks of
+/// [benchmark_masks] constants.
+///
+/// Returns the previous mask of counters, fails if Miralis is not built for benchmarking.
+pub fn vendor_set_benchmark(counters: usize, scopes: usize) -> Result<usize, usize> {
+    unsafe {
+        ecall3(
+            abi_vendor::MIRALIS_VENDOR_EID,
+            abi_vendor::MIRALIS_VENDOR_SET_BENCHMARK_FID,
+            counters,
+            scopes,
+            0,
+        )
+    }
+}
+
+/// Ask Miralis to reset its benchmark counters, fails if Miralis is not built for benchmarking.
+pub fn vendor_reset_benchmark() -> Result<usize, usize> {
+    unsafe {
+        ecall0(
+            abi_vendor::MIRALIS_VENDOR_EID,
+            abi_vendor::MIRALIS_VENDOR_RESET_BENCHMARK_FID,
+        )
+    }
+}
+
 /// Ask Miralis to log a string with the provided log level.
 pub fn miralis_log(level: Level, message: &str) {
     // Prepare ecall arguments
@@ -182,14 +429,13 @@ macro_rules! setup_binary {
 
 /// Configure a panic handler for a Miralis firmware.
 ///
-/// The handler uses the Miralis ABI to gracefully exit with an error.
+/// The handler uses the Miralis ABI to report the panic and gracefully exit with an error.
 #[macro_export]
 macro_rules! firmware_panic {
     () => {
         #[panic_handler]
         fn panic(info: &core::panic::PanicInfo) -> ! {
-            $crate::log::error!("Firmware: {:#?} ", info);
-            $crate::failure();
+            $crate::report_panic(info);
         }
     };
 }
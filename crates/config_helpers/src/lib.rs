@@ -122,3 +122,19 @@ pub const fn parse_usize_or(env_var: Option<&str>, default: usize) -> usize {
         None => default,
     }
 }
+
+/// Compares two strings, usable in constant expressions.
+pub const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
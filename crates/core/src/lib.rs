@@ -24,6 +24,27 @@ pub mod abi {
     pub const MIRALIS_LOG_FID: usize = 2;
     /// Benchmark prints and exit.
     pub const MIRALIS_BENCHMARK_FID: usize = 3;
+    /// Copy entries of the audit log into a payload buffer.
+    pub const MIRALIS_AUDIT_LOG_READ_FID: usize = 4;
+    /// Copy the capability report into a buffer.
+    pub const MIRALIS_CAPABILITIES_FID: usize = 5;
+    /// Copy the memory layout into a buffer of `MemoryRegion`.
+    pub const MIRALIS_MEMORY_LAYOUT_FID: usize = 6;
+    /// Report a panic and exit with an error, arguments are the address and length of the panic
+    /// message.
+    pub const MIRALIS_PANIC_FID: usize = 7;
+    /// Update the log filters, arguments are the address and length of a filter specification
+    /// such as `virt=debug,ace=info`. Requires the `debug` feature.
+    pub const MIRALIS_LOG_FILTER_FID: usize = 8;
+    /// Copy the build information (git revision, configuration hash, and features) into a buffer,
+    /// one `key=value` pair per line.
+    pub const MIRALIS_BUILD_INFO_FID: usize = 9;
+    /// Copy the PMP entries in effect for the caller into a buffer of `PmpEntry`. Requires the
+    /// `debug` feature.
+    pub const MIRALIS_PMP_VIEW_FID: usize = 10;
+    /// Dump the trap log of the calling hart on the console. Requires the `debug` feature and a
+    /// non-zero `debug.trap_log_entries` configuration.
+    pub const MIRALIS_TRAP_LOG_DUMP_FID: usize = 11;
 
     /// Log level constants, with the same semantic as the `log` crate.
     pub mod log {
@@ -33,6 +54,252 @@ pub mod abi {
         pub const MIRALIS_DEBUG: usize = 4;
         pub const MIRALIS_TRACE: usize = 5;
     }
+
+    /// Audit log definitions, see `MIRALIS_AUDIT_LOG_READ_FID`.
+    pub mod audit {
+        /// A firmware write to a pmpcfg CSR, arguments are the CSR index and the value.
+        pub const PMPCFG_WRITE: u64 = 1;
+        /// A firmware write to a pmpaddr CSR, arguments are the CSR index and the value.
+        pub const PMPADDR_WRITE: u64 = 2;
+        /// A firmware write to medeleg, the argument is the value.
+        pub const MEDELEG_WRITE: u64 = 3;
+        /// A firmware write to mideleg, the argument is the value.
+        pub const MIDELEG_WRITE: u64 = 4;
+        /// The policy overrode the handling of a trap, arguments are mcause and the SBI
+        /// extension ID in a7.
+        pub const POLICY_DECISION: u64 = 5;
+        /// A world switch, the argument is 0 when entering the firmware and 1 when entering the
+        /// payload.
+        pub const WORLD_SWITCH: u64 = 6;
+
+        /// An entry of the audit log.
+        ///
+        /// The hash of each entry is the SHA-256 of the hash of the previous entry followed by the
+        /// little-endian encoding of `seq`, `hart`, `kind`, and `args`. The first entry is chained
+        /// to an all-zero hash.
+        #[repr(C)]
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        pub struct AuditEntry {
+            /// Sequence number, starting at 0.
+            pub seq: u64,
+            /// The hart on which the event happened.
+            pub hart: u64,
+            /// The kind of event.
+            pub kind: u64,
+            /// Event arguments, depending on the kind.
+            pub args: [u64; 2],
+            /// Hash chaining this entry to the previous one.
+            pub hash: [u8; 32],
+        }
+    }
+
+    /// Memory layout definitions, see `MIRALIS_MEMORY_LAYOUT_FID`.
+    pub mod memory_layout {
+        /// Memory used by Miralis.
+        pub const MIRALIS: u64 = 1;
+        /// Memory in which the firmware is loaded.
+        pub const FIRMWARE: u64 = 2;
+        /// Memory in which the payload is loaded.
+        pub const PAYLOAD: u64 = 3;
+        /// Memory reserved by the policy, such as confidential memory.
+        pub const RESERVED: u64 = 4;
+
+        /// A region of physical memory.
+        #[repr(C)]
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        pub struct MemoryRegion {
+            pub start: u64,
+            /// Size in bytes, 0 if unknown (the region then extends up to the next region or the
+            /// end of memory).
+            pub size: u64,
+            /// The owner of the region.
+            pub kind: u64,
+        }
+    }
+
+    /// PMP view definitions, see `MIRALIS_PMP_VIEW_FID`.
+    pub mod pmp_view {
+        /// Entry reserved by Miralis, to protect itself or to emulate the virtual PMPs.
+        pub const MIRALIS: u64 = 1;
+        /// Entry protecting a virtual device.
+        pub const DEVICE: u64 = 2;
+        /// Entry reserved by the policy.
+        pub const POLICY: u64 = 3;
+        /// Virtual entry programmed by the firmware, which applies to the payload.
+        pub const VIRTUAL: u64 = 4;
+
+        /// A PMP entry, entries are reported by decreasing priority.
+        #[repr(C)]
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        pub struct PmpEntry {
+            /// The index of the entry, among the physical entries or among the virtual ones.
+            pub index: u64,
+            /// The raw value of the pmpaddr register.
+            pub addr: u64,
+            /// The raw value of the pmpcfg byte.
+            pub cfg: u64,
+            /// Start of the region matched by the entry.
+            pub start: u64,
+            /// Size in bytes of the region matched by the entry, 0 if the entry is inactive or
+            /// matches no address.
+            pub size: u64,
+            /// The owner of the entry.
+            pub owner: u64,
+        }
+    }
+
+    /// Trap log definitions, see `MIRALIS_TRAP_LOG_DUMP_FID`.
+    ///
+    /// The trap log is dumped on the console, one record per line. Each line contains the prefix,
+    /// the hart ID in decimal, followed by the `timestamp`, `mepc`, and `info` words of the
+    /// record as 16 hexadecimal digits, separated by spaces.
+    pub mod trap_log {
+        /// Prefix of the console lines holding a trap record.
+        pub const LINE_PREFIX: &str = "[trap-log]";
+
+        /// Exception or interrupt code, the low bits of mcause.
+        pub const CODE_OFFSET: u64 = 0;
+        pub const CODE_FILTER: u64 = 0xffff << CODE_OFFSET;
+        /// Set for interrupts, the most significant bit of mcause.
+        pub const INTERRUPT_OFFSET: u64 = 16;
+        /// Virtual mode (0 for U, 1 for S, and 3 for M) in which the guest trapped.
+        pub const FROM_MODE_OFFSET: u64 = 20;
+        /// Virtual mode in which the guest resumed after handling the trap.
+        pub const TO_MODE_OFFSET: u64 = 22;
+        pub const MODE_FILTER: u64 = 0b11;
+        /// Cycles spent handling the trap in Miralis, saturated to 32 bits.
+        pub const CYCLES_OFFSET: u64 = 32;
+
+        /// A trap record.
+        #[repr(C)]
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        pub struct TrapRecord {
+            /// Value of the mcycle counter when Miralis started handling the trap.
+            pub timestamp: u64,
+            /// The pc at which the guest trapped.
+            pub mepc: u64,
+            /// The cause, mode transition, and handling cycles, see the `*_OFFSET` constants.
+            pub info: u64,
+        }
+    }
+
+    /// Layout of the firmware health status page.
+    ///
+    /// All registers are 64 bits wide and read-only, they can be read with 4 or 8 bytes aligned
+    /// loads.
+    pub mod status {
+        /// Size of the status page, in bytes.
+        pub const SIZE: usize = 0x1000;
+        /// The magic value identifying the status page: "MIRASTAT" in little endian.
+        pub const MAGIC: u64 = u64::from_le_bytes(*b"MIRASTAT");
+        /// The version of the status page layout.
+        pub const VERSION: u64 = 1;
+
+        pub const MAGIC_OFFSET: usize = 0x0;
+        pub const VERSION_OFFSET: usize = 0x8;
+        pub const NB_HARTS_OFFSET: usize = 0x10;
+        /// The reason of the last reset, one of the `RESET_*` values.
+        pub const RESET_REASON_OFFSET: usize = 0x18;
+        /// The hart on which the last reset happened.
+        pub const RESET_HART_OFFSET: usize = 0x20;
+        /// Total number of resets, including the cold boot.
+        pub const RESET_COUNT_OFFSET: usize = 0x28;
+        /// Number of operations blocked by Miralis or the policy module, on all harts.
+        pub const VIOLATIONS_OFFSET: usize = 0x30;
+        /// Per-hart exit counters, the number of firmware exits followed by the number of payload
+        /// exits.
+        pub const HART_OFFSET: usize = 0x100;
+        pub const HART_STRIDE: usize = 0x10;
+
+        /// Miralis booted the platform.
+        pub const RESET_COLD_BOOT: u64 = 0;
+        /// A hart was brought online after boot.
+        pub const RESET_HART_RESTART: u64 = 1;
+        /// A hart woke up from a non-retentive suspend, its state has been lost.
+        pub const RESET_SUSPEND_RESUME: u64 = 2;
+    }
+
+    /// Layout of the trace buffer, see the `trace` feature.
+    ///
+    /// The buffer starts with a `TraceHeader`, followed by one ring per hart. Each ring starts with
+    /// the number of events ever written by the hart as a `u64`, followed by `entries_per_hart`
+    /// `TraceEvent`s. The n-th event of a hart is stored at index `n % entries_per_hart`.
+    pub mod trace {
+        /// The magic value identifying the trace buffer: "MIRATRCE" in little endian.
+        pub const MAGIC: u64 = u64::from_le_bytes(*b"MIRATRCE");
+        /// The version of the trace buffer layout.
+        pub const VERSION: u64 = 1;
+
+        /// The hart enters the virtual CPU, arguments are the pc and the world (0 for the firmware
+        /// and 1 for the payload).
+        pub const RUN_VCPU_ENTER: u64 = 1;
+        /// The hart leaves the virtual CPU, arguments are mcause and mtval.
+        pub const RUN_VCPU_EXIT: u64 = 2;
+
+        /// The header of the trace buffer.
+        #[repr(C)]
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        pub struct TraceHeader {
+            pub magic: u64,
+            pub version: u64,
+            pub nb_harts: u64,
+            pub entries_per_hart: u64,
+        }
+
+        /// An event of the trace buffer.
+        #[repr(C)]
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        pub struct TraceEvent {
+            /// Value of the mcycle counter of the hart.
+            pub timestamp: u64,
+            /// The kind of event.
+            pub kind: u64,
+            /// Event arguments, depending on the kind.
+            pub args: [u64; 2],
+        }
+    }
+}
+
+pub mod abi_vendor {
+    /// Miralis vendor SBI Extension ID, in the range reserved for vendor extensions ("\tMRL").
+    ///
+    /// Unlike the Miralis ABI, which is meant for Miralis' own tooling, the vendor extension is a
+    /// stable interface for the payload. Calls are handled by Miralis and never reach the firmware.
+    pub const MIRALIS_VENDOR_EID: usize = 0x094D524C;
+    /// Returns the version of Miralis, encoded as `major << 24 | minor << 12 | patch`.
+    pub const MIRALIS_VENDOR_GET_VERSION_FID: usize = 0x0;
+    /// Returns the number of firmware exits of the hart passed as argument.
+    pub const MIRALIS_VENDOR_GET_FIRMWARE_EXITS_FID: usize = 0x1;
+    /// Copies the name of the active policy into a buffer, arguments are the address of the buffer
+    /// and its size. Returns the full length of the name, even if it is truncated.
+    pub const MIRALIS_VENDOR_GET_POLICY_NAME_FID: usize = 0x2;
+    /// Dumps the benchmark counters on the Miralis console, only available in benchmark builds.
+    pub const MIRALIS_VENDOR_DUMP_BENCHMARK_FID: usize = 0x3;
+    /// Selects the benchmark counters and scopes recorded from now on, arguments are a mask of
+    /// counters and a mask of scopes (see [benchmark]). Returns the previous mask of counters, only
+    /// available in benchmark builds.
+    pub const MIRALIS_VENDOR_SET_BENCHMARK_FID: usize = 0x4;
+    /// Resets the benchmark counters, only available in benchmark builds.
+    pub const MIRALIS_VENDOR_RESET_BENCHMARK_FID: usize = 0x5;
+
+    /// Masks of the benchmark counters and scopes that can be selected at runtime.
+    ///
+    /// Counters and scopes disabled in the build configuration are never recorded, whatever the
+    /// runtime selection. All of them are selected at boot.
+    pub mod benchmark {
+        pub const TOTAL_EXITS: usize = 1 << 0;
+        pub const FIRMWARE_EXITS: usize = 1 << 1;
+        pub const WORLD_SWITCHES: usize = 1 << 2;
+        pub const SCRUBBED_PAGES: usize = 1 << 3;
+        pub const EXECUTION_TIME: usize = 1 << 4;
+        pub const INSTRUCTION_RET: usize = 1 << 5;
+        pub const ALL_COUNTERS: usize = (1 << 6) - 1;
+
+        pub const SCOPE_HANDLE_TRAP: usize = 1 << 0;
+        pub const SCOPE_RUN_VCPU: usize = 1 << 1;
+        pub const SCOPE_POLICY_HOOK: usize = 1 << 2;
+        pub const ALL_SCOPES: usize = (1 << 3) - 1;
+    }
 }
 
 pub mod abi_protect_payload {
@@ -42,4 +309,50 @@ pub mod abi_protect_payload {
     pub const MIRALIS_PROTECT_PAYLOAD_EID: usize = MIRALIS_EID + 1;
     /// Ecall to lock the payload
     pub const MIRALIS_PROTECT_PAYLOAD_LOCK_FID: usize = 0x1;
+    /// Copy the attestation report of the payload, arguments are the address of a buffer of
+    /// `report::SIZE` bytes and the address of a `report::NONCE_SIZE` bytes nonce.
+    pub const MIRALIS_PROTECT_PAYLOAD_REPORT_FID: usize = 0x2;
+
+    /// Layout of the attestation report.
+    ///
+    /// All measurements are SHA3-256 digests. The report is hash-chained: the digest is computed
+    /// over the build measurement, the payload measurement, and the nonce, such that a verifier
+    /// knowing the expected build and payload can check the report is fresh and consistent. The
+    /// report is not signed, its authenticity depends on the channel it is obtained from.
+    pub mod report {
+        /// Size of the report, in bytes.
+        pub const SIZE: usize = 0x90;
+        /// The magic value identifying the report: "MIRAREPT" in little endian.
+        pub const MAGIC: u64 = u64::from_le_bytes(*b"MIRAREPT");
+        /// The version of the report layout.
+        pub const VERSION: u64 = 1;
+        /// Size of the measurements and of the nonce, in bytes.
+        pub const MEASUREMENT_SIZE: usize = 32;
+        pub const NONCE_SIZE: usize = 32;
+
+        pub const MAGIC_OFFSET: usize = 0x0;
+        pub const VERSION_OFFSET: usize = 0x8;
+        /// Hash of the Miralis build information, as returned by `MIRALIS_BUILD_INFO_FID`.
+        pub const BUILD_MEASUREMENT_OFFSET: usize = 0x10;
+        /// Hash of the payload image followed by its entry point, taken before its first
+        /// instruction runs.
+        pub const PAYLOAD_MEASUREMENT_OFFSET: usize = 0x30;
+        /// The nonce provided by the caller.
+        pub const NONCE_OFFSET: usize = 0x50;
+        /// `H(H(build measurement || payload measurement) || nonce)`.
+        pub const DIGEST_OFFSET: usize = 0x70;
+    }
+}
+
+pub mod abi_protect_domains {
+    use crate::abi::MIRALIS_EID;
+
+    /// Protect domains SBI Extension ID.
+    pub const MIRALIS_PROTECT_DOMAINS_EID: usize = MIRALIS_EID + 2;
+    /// Register a new domain, arguments are the start address and the size of the region. The size
+    /// must be a power of two and the start address aligned to the size, and the region must lie
+    /// within the payload memory. Returns the domain ID once the domain is enforced on all harts.
+    pub const MIRALIS_PROTECT_DOMAINS_REGISTER_FID: usize = 0x0;
+    /// Unregister a domain, the argument is the domain ID.
+    pub const MIRALIS_PROTECT_DOMAINS_UNREGISTER_FID: usize = 0x1;
 }
@@ -12,4 +12,6 @@ pub enum FdtError {
     FdtErrorParsing(#[from] DevTreeError),
     #[error("No memory node")]
     NoMemoryNode(),
+    #[error("No timebase frequency")]
+    NoTimebaseFrequency(),
 }
@@ -77,6 +77,17 @@ impl<'a> FlattenedDeviceTree<'a> {
             size: reg_prop.u64(1)?,
         })
     }
+
+    /// Returns the frequency of the machine timer in ticks per second, as given by the `timebase-frequency` property of the `cpus`
+    /// node.
+    pub fn timebase_frequency(&self) -> Result<u64, FdtError> {
+        let prop = self
+            .inner
+            .props()
+            .find(|p| Ok(p.name()? == "timebase-frequency"))?
+            .ok_or_else(|| FdtError::NoTimebaseFrequency())?;
+        Ok(prop.u32(0)? as u64)
+    }
 }
 
 #[derive(Copy, Clone, Debug, Default)]
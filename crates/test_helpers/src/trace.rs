@@ -0,0 +1,77 @@
+//! Architectural event traces
+//!
+//! Firmware used for golden-trace comparison (see `runner golden-trace`) run both on bare QEMU and
+//! under Miralis, so they can not rely on the Miralis ABI. The functions of this module write
+//! trace events directly to the UART and exit through the QEMU test device, assuming a QEMU
+//! virt-like layout.
+//!
+//! Each event is emitted on its own line, prefixed by [TRACE_PREFIX], so that the runner can
+//! extract the trace from the rest of the console output.
+
+use core::fmt::{self, Write};
+
+/// Prefix of the trace lines.
+pub const TRACE_PREFIX: &str = "[trace]";
+/// Name of the measurement events.
+pub const MEASURE_EVENT: &str = "measure";
+
+/// UART base, assuming a QEMU virt-like layout.
+const UART_BASE: usize = 0x10000000;
+/// Offset of the line status register.
+const UART_LSR_OFFSET: usize = 5;
+/// Transmitter holding register empty.
+const UART_LSR_THRE: u8 = 1 << 5;
+
+/// QEMU test device base, assuming a QEMU virt-like layout.
+const TEST_DEVICE_BASE: usize = 0x100000;
+const TEST_DEVICE_PASS: u32 = 0x5555;
+const TEST_DEVICE_FAIL: u32 = 0x3333;
+
+struct Uart;
+
+impl Write for Uart {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            let lsr = (UART_BASE + UART_LSR_OFFSET) as *const u8;
+            let thr = UART_BASE as *mut u8;
+            unsafe {
+                while lsr.read_volatile() & UART_LSR_THRE == 0 {}
+                thr.write_volatile(byte);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Emit a trace event.
+pub fn event(args: fmt::Arguments) {
+    let _ = writeln!(Uart, "{} {}", TRACE_PREFIX, args);
+}
+
+/// Emit the value of a CSR.
+pub fn csr(name: &str, value: usize) {
+    event(format_args!("csr {} 0x{:x}", name, value));
+}
+
+/// Emit a measurement, such as a latency in cycles.
+///
+/// Measurements are expected to differ between runs, the runner reports them side by side instead
+/// of comparing them.
+pub fn measure(name: &str, value: usize) {
+    event(format_args!("{} {} {}", MEASURE_EVENT, name, value));
+}
+
+/// Emit the exit code and terminate QEMU with it.
+pub fn exit(code: u16) -> ! {
+    event(format_args!("exit {}", code));
+    let value = match code {
+        0 => TEST_DEVICE_PASS,
+        _ => ((code as u32) << 16) | TEST_DEVICE_FAIL,
+    };
+    unsafe { (TEST_DEVICE_BASE as *mut u32).write_volatile(value) };
+
+    // QEMU terminates on the write above.
+    loop {
+        core::hint::spin_loop();
+    }
+}
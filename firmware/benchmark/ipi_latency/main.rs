@@ -0,0 +1,112 @@
+//! IPI latency benchmark firmware
+//!
+//! This firmware measures the round-trip latency of an inter-processor interrupt between two harts,
+//! sent through the CLINT. Hart 0 sends an MSI to hart 1, which answers with an MSI back to hart 0.
+//! Under Miralis both the MSIs and the waits go through the virtual CLINT, the difference with a
+//! native run is the overhead of SMP virtualization.
+//!
+//! The firmware does not use the Miralis ABI, such that it also runs without Miralis. The results
+//! are emitted as measurements of the `test_helpers::trace` module, `runner golden-trace` reports
+//! them for both the native run and the run under Miralis:
+//!
+//! ```sh
+//! cargo run -- golden-trace ipi_latency --config config/test/qemu-virt-2harts.toml
+//! ```
+
+#![no_std]
+#![no_main]
+
+use core::arch::asm;
+
+use miralis_abi::setup_binary;
+use test_helpers::{clint, trace};
+
+setup_binary!(main);
+
+/// Number of measured round trips.
+const NB_ROUNDS: usize = 256;
+/// Number of round trips before the measure, to warm up the caches.
+const NB_WARMUP_ROUNDS: usize = 16;
+
+const MIE_MSIE: usize = 1 << 3;
+const MIP_MSIP: usize = 1 << 3;
+
+fn main() -> ! {
+    let hart_id: usize;
+    unsafe {
+        asm!(
+            "csrr {hart_id}, mhartid",
+            // Interrupts stay globally disabled, but a pending MSI wakes up the hart from wfi
+            "csrs mie, {msie}",
+            hart_id = out(reg) hart_id,
+            msie = in(reg) MIE_MSIE,
+        );
+    }
+
+    match hart_id {
+        0 => ping(),
+        1 => pong(),
+        // Additional harts do not take part in the benchmark
+        _ => loop {
+            core::hint::spin_loop();
+        },
+    }
+}
+
+/// Sends the IPIs and measures the round trips.
+fn ping() -> ! {
+    for _ in 0..NB_WARMUP_ROUNDS {
+        round_trip();
+    }
+
+    let mut samples = [0; NB_ROUNDS];
+    for sample in samples.iter_mut() {
+        *sample = round_trip();
+    }
+
+    samples.sort_unstable();
+    let percentile = |per: usize| samples[(NB_ROUNDS * per / 100).min(NB_ROUNDS - 1)];
+    trace::measure("ipi_round_trip_min", samples[0]);
+    trace::measure("ipi_round_trip_p50", percentile(50));
+    trace::measure("ipi_round_trip_p99", percentile(99));
+    trace::measure("ipi_round_trip_max", samples[NB_ROUNDS - 1]);
+    trace::measure(
+        "ipi_round_trip_mean",
+        samples.iter().sum::<usize>() / NB_ROUNDS,
+    );
+    trace::exit(0);
+}
+
+/// Answers the IPIs of hart 0.
+fn pong() -> ! {
+    loop {
+        wait_for_msi(1);
+        clint::send_msi(0);
+    }
+}
+
+/// Returns the number of cycles from sending an MSI to hart 1 to receiving its answer.
+fn round_trip() -> usize {
+    let start = read_cycle();
+    clint::send_msi(1);
+    wait_for_msi(0);
+    read_cycle() - start
+}
+
+/// Waits for an MSI, then clears it.
+fn wait_for_msi(hart: usize) {
+    loop {
+        let mip: usize;
+        unsafe { asm!("wfi", "csrr {}, mip", out(reg) mip) };
+        if mip & MIP_MSIP != 0 {
+            break;
+        }
+    }
+    clint::clear_msi(hart);
+}
+
+fn read_cycle() -> usize {
+    let cycle: usize;
+    unsafe { asm!("csrr {}, cycle", out(reg) cycle) };
+    cycle
+}
@@ -46,6 +46,22 @@ fn test_simple_regs() {
     }
     assert_eq!(res, 0);
 
+    // Inhibited counters are frozen
+    let (first, second): (usize, usize);
+    unsafe {
+        asm!(
+            "li {0}, 0b101",
+            "csrw mcountinhibit, {0}",
+            "csrr {1}, mcycle",
+            "csrr {2}, mcycle",
+            "csrw mcountinhibit, zero",
+            out(reg) _,
+            out(reg) first,
+            out(reg) second,
+        );
+    }
+    assert_eq!(first, second);
+
     // Test mcounteren
     unsafe {
         asm!(
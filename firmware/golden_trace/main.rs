@@ -0,0 +1,71 @@
+//! Golden trace firmware
+//!
+//! This firmware dumps a selection of CSRs, at reset and after writing them, such that its trace
+//! on bare QEMU can be compared with its trace under Miralis by `runner golden-trace`. It does not
+//! use the Miralis ABI, as it must also run without Miralis.
+//!
+//! Some CSRs, such as `misa`, have bits which legitimately differ under Miralis. The runner ignores
+//! those bits, new CSRs with such differences must be added to its list of expected differences.
+
+#![no_std]
+#![no_main]
+
+use core::arch::asm;
+
+use miralis_abi::setup_binary;
+use test_helpers::trace;
+
+setup_binary!(main);
+
+const MSTATUS_MIE: usize = 1 << 3;
+const MSTATUS_MPRV: usize = 1 << 17;
+
+/// Reads a CSR and emit its value.
+macro_rules! trace_csr {
+    ($csr:literal) => {{
+        let value: usize;
+        unsafe { asm!(concat!("csrr {}, ", $csr), out(reg) value) };
+        trace::csr($csr, value);
+    }};
+}
+
+/// Writes a CSR, then reads it back and emit its value.
+macro_rules! trace_csr_write {
+    ($csr:literal, $value:expr) => {{
+        let value: usize = $value;
+        unsafe { asm!(concat!("csrw ", $csr, ", {}"), in(reg) value) };
+        trace_csr!($csr);
+    }};
+}
+
+fn main() -> ! {
+    // Identification and reset values
+    trace_csr!("misa");
+    trace_csr!("mvendorid");
+    trace_csr!("marchid");
+    trace_csr!("mimpid");
+    trace_csr!("mhartid");
+    trace_csr!("mstatus");
+    trace_csr!("mie");
+    trace_csr!("mip");
+
+    // WARL fields
+    trace_csr_write!("mscratch", 0xdeadbeef);
+    trace_csr_write!("mtvec", 0x80001000);
+    trace_csr_write!("mtvec", 0x80001001);
+    trace_csr_write!("medeleg", usize::MAX);
+    trace_csr_write!("mideleg", usize::MAX);
+    trace_csr_write!("mie", usize::MAX);
+    trace_csr_write!("mcounteren", usize::MAX);
+    // Keep MIE and MPRV cleared, the firmware must neither take interrupts nor change the
+    // translation of its own accesses.
+    trace_csr_write!("mstatus", usize::MAX & !MSTATUS_MIE & !MSTATUS_MPRV);
+    trace_csr_write!("mstatus", 0);
+
+    // PMP
+    trace_csr_write!("pmpaddr0", usize::MAX);
+    trace_csr_write!("pmpcfg0", 0x1f);
+    trace_csr_write!("pmpcfg0", 0);
+
+    trace::exit(0);
+}
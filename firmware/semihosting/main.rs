@@ -0,0 +1,52 @@
+//! This firmware prints through semihosting calls and exits with the semihosting exit operation,
+//! without relying on a UART or on the Miralis ABI.
+#![no_std]
+#![no_main]
+
+use core::arch::asm;
+
+use miralis_abi::setup_binary;
+
+setup_binary!(main);
+
+const SYS_WRITEC: usize = 0x03;
+const SYS_WRITE0: usize = 0x04;
+const SYS_WRITE: usize = 0x05;
+const SYS_EXIT: usize = 0x18;
+const SYS_OPEN: usize = 0x01;
+
+const STDOUT: usize = 1;
+const ADP_STOPPED_APPLICATION_EXIT: usize = 0x20026;
+
+fn main() -> ! {
+    semihosting_call(SYS_WRITEC, b"S".as_ptr() as usize);
+    semihosting_call(SYS_WRITE0, b"emihosting: hello\n\0".as_ptr() as usize);
+
+    let message = b"Semihosting: write to stdout\n";
+    let block = [STDOUT, message.as_ptr() as usize, message.len()];
+    assert_eq!(semihosting_call(SYS_WRITE, block.as_ptr() as usize), 0);
+
+    // Unsupported operations fail
+    assert_eq!(semihosting_call(SYS_OPEN, 0), usize::MAX);
+
+    let block = [ADP_STOPPED_APPLICATION_EXIT, 0];
+    semihosting_call(SYS_EXIT, block.as_ptr() as usize);
+    panic!("Semihosting exit returned");
+}
+
+fn semihosting_call(operation: usize, parameter: usize) -> usize {
+    let result: usize;
+    unsafe {
+        asm!(
+            ".option push",
+            ".option norvc",
+            "slli zero, zero, 0x1f",
+            "ebreak",
+            "srai zero, zero, 0x7",
+            ".option pop",
+            inout("a0") operation => result,
+            in("a1") parameter,
+        );
+    }
+    result
+}
@@ -18,6 +18,11 @@ SECTIONS
     *(.rodata.*)
   }
 
+  /* The build information of Miralis, kept for provenance */
+  .miralis_build_info : ALIGN(0x8) {
+    KEEP(*(.miralis_build_info))
+  }
+
   /* Finally, all data                                         */
   /* NOTE: no need to page-align bss, both bss and data are RW */
   .data : ALIGN(0x8) {
@@ -0,0 +1,78 @@
+//! Drivers for the SBI firmware interface and the UART
+//!
+//! The UART driver assumes a 16550-compatible UART located at the same address as on the QEMU virt
+//! platform, which we use for our tests.
+
+// —————————————————————————————————— SBI ——————————————————————————————————— //
+
+pub mod sbi {
+    use miralis_abi::ecall3;
+
+    const TIME_EID: usize = 0x54494D45;
+    const TIME_SET_TIMER_FID: usize = 0;
+
+    const IPI_EID: usize = 0x735049;
+    const IPI_SEND_IPI_FID: usize = 0;
+
+    const HSM_EID: usize = 0x48534D;
+    const HSM_HART_START_FID: usize = 0;
+    const HSM_HART_STOP_FID: usize = 1;
+    const HSM_HART_GET_STATUS_FID: usize = 2;
+
+    /// The hart is running.
+    pub const HART_STARTED: usize = 0;
+    /// The hart is stopped, and can be started with [hart_start].
+    pub const HART_STOPPED: usize = 1;
+
+    pub fn set_timer(deadline: u64) -> Result<usize, usize> {
+        unsafe { ecall3(TIME_EID, TIME_SET_TIMER_FID, deadline as usize, 0, 0) }
+    }
+
+    /// Sends an IPI to a single hart.
+    pub fn send_ipi(hart: usize) -> Result<usize, usize> {
+        unsafe { ecall3(IPI_EID, IPI_SEND_IPI_FID, 1, hart, 0) }
+    }
+
+    pub fn hart_start(hart: usize, start_addr: usize, opaque: usize) -> Result<usize, usize> {
+        unsafe { ecall3(HSM_EID, HSM_HART_START_FID, hart, start_addr, opaque) }
+    }
+
+    pub fn hart_stop() -> Result<usize, usize> {
+        unsafe { ecall3(HSM_EID, HSM_HART_STOP_FID, 0, 0, 0) }
+    }
+
+    pub fn hart_get_status(hart: usize) -> Result<usize, usize> {
+        unsafe { ecall3(HSM_EID, HSM_HART_GET_STATUS_FID, hart, 0, 0) }
+    }
+}
+
+// —————————————————————————————————— UART —————————————————————————————————— //
+
+pub mod uart {
+    const UART_BASE: usize = 0x10000000;
+    const THR: usize = 0;
+    const LSR: usize = 5;
+    const LSR_THRE: u8 = 1 << 5;
+
+    /// Number of polls of the line status before giving up on the transmitter.
+    const MAX_POLLS: usize = 1_000_000;
+
+    /// Writes a string to the UART, returns an error if the transmitter never becomes ready.
+    pub fn write_str(s: &str) -> Result<(), ()> {
+        for byte in s.bytes() {
+            wait_for_transmitter()?;
+            unsafe { ((UART_BASE + THR) as *mut u8).write_volatile(byte) };
+        }
+        Ok(())
+    }
+
+    fn wait_for_transmitter() -> Result<(), ()> {
+        for _ in 0..MAX_POLLS {
+            let lsr = unsafe { ((UART_BASE + LSR) as *const u8).read_volatile() };
+            if lsr & LSR_THRE != 0 {
+                return Ok(());
+            }
+        }
+        Err(())
+    }
+}
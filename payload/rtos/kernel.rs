@@ -0,0 +1,280 @@
+//! A minimal cooperative kernel
+//!
+//! Each hart runs a set of tasks, which are polled in turn until they complete. When no task can
+//! make progress the hart sleeps with `wfi` until the next interrupt. The trap handler only
+//! updates the per-hart interrupt counters, tasks observe the counters when they are polled.
+//!
+//! Interrupts are masked (`sstatus.SIE` is cleared) while tasks run and are only enabled after
+//! `wfi`, such that an interrupt can not be taken between the last poll and the `wfi`, which
+//! would otherwise put the hart to sleep with a pending wake-up.
+
+use core::arch::{asm, global_asm};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use miralis_abi::log;
+
+use crate::drivers::sbi;
+
+/// Maximum number of harts supported by the kernel.
+pub const MAX_HARTS: usize = 8;
+
+/// Maximum number of tasks per hart.
+const MAX_TASKS: usize = 8;
+
+const SSTATUS_SIE: usize = 1 << 1;
+const SIE_SSIE: usize = 1 << 1;
+const SIE_STIE: usize = 1 << 5;
+const SIP_SSIP: usize = 1 << 1;
+const SCAUSE_INTERRUPT: usize = 1 << 63;
+const SUPERVISOR_SOFTWARE_INTERRUPT: usize = SCAUSE_INTERRUPT | 1;
+const SUPERVISOR_TIMER_INTERRUPT: usize = SCAUSE_INTERRUPT | 5;
+
+/// Stack size of the secondary harts.
+const STACK_SIZE: usize = 0x4000;
+
+// ————————————————————————————————— Tasks —————————————————————————————————— //
+
+/// The result of polling a task.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Poll {
+    Pending,
+    Ready,
+}
+
+/// A task, which is polled until it returns [Poll::Ready].
+pub struct Task {
+    pub name: &'static str,
+    pub poll: fn() -> Poll,
+}
+
+/// Runs the tasks on the current hart until they all complete.
+pub fn run(tasks: &[Task]) {
+    assert!(tasks.len() <= MAX_TASKS, "Too many tasks");
+    let mut done = [false; MAX_TASKS];
+
+    loop {
+        let mut pending = 0;
+        for (task, done) in tasks.iter().zip(done.iter_mut()) {
+            if *done {
+                continue;
+            }
+            match (task.poll)() {
+                Poll::Ready => {
+                    log::info!("Task '{}' completed on hart {}", task.name, hart_id());
+                    *done = true;
+                }
+                Poll::Pending => pending += 1,
+            }
+        }
+
+        if pending == 0 {
+            return;
+        }
+        wait_for_interrupt();
+    }
+}
+
+/// Sleeps until an interrupt is pending, then handles it.
+fn wait_for_interrupt() {
+    unsafe {
+        // wfi wakes up on pending interrupts enabled in sie, even when sstatus.SIE is cleared
+        asm!(
+            "wfi",
+            "csrs sstatus, {sie}",
+            "csrc sstatus, {sie}",
+            sie = in(reg) SSTATUS_SIE,
+        );
+    }
+}
+
+// ————————————————————————————————— Harts —————————————————————————————————— //
+
+/// The interrupt counters of a hart.
+struct HartState {
+    ticks: AtomicUsize,
+    ipis: AtomicUsize,
+    /// The time of the pending timer interrupt.
+    deadline: AtomicU64,
+}
+
+impl HartState {
+    const fn new() -> Self {
+        HartState {
+            ticks: AtomicUsize::new(0),
+            ipis: AtomicUsize::new(0),
+            deadline: AtomicU64::new(u64::MAX),
+        }
+    }
+}
+
+static HARTS: [HartState; MAX_HARTS] = [const { HartState::new() }; MAX_HARTS];
+
+/// The stacks of the secondary harts.
+#[repr(C, align(16))]
+struct Stacks([[u8; STACK_SIZE]; MAX_HARTS]);
+
+static mut STACKS: Stacks = Stacks([[0; STACK_SIZE]; MAX_HARTS]);
+
+/// Prepares the current hart to run tasks: installs the trap handler and enables the timer and
+/// software interrupts.
+pub fn init_hart(hart: usize) {
+    assert!(hart < MAX_HARTS, "Unsupported hart id {}", hart);
+    unsafe {
+        asm!(
+            "mv tp, {hart}",
+            "csrw stvec, {handler}",
+            "csrw sie, {sie}",
+            "csrc sstatus, {sstatus_sie}",
+            hart = in(reg) hart,
+            handler = in(reg) _rtos_trap_entry as usize,
+            sie = in(reg) SIE_SSIE | SIE_STIE,
+            sstatus_sie = in(reg) SSTATUS_SIE,
+        );
+    }
+}
+
+/// Starts a secondary hart, which runs the entry point on its own stack.
+pub fn start_hart(hart: usize, entry: extern "C" fn(usize) -> !) -> Result<(), usize> {
+    assert!(hart < MAX_HARTS, "Unsupported hart id {}", hart);
+    SECONDARY_ENTRY.store(entry as usize, Ordering::SeqCst);
+    let stack_top = unsafe { core::ptr::addr_of_mut!(STACKS.0[hart]) as usize + STACK_SIZE };
+    sbi::hart_start(hart, _rtos_secondary_entry as usize, stack_top).map(|_| ())
+}
+
+/// The entry point of the secondary harts, called by [secondary_entry].
+static SECONDARY_ENTRY: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn secondary_entry(hart: usize) -> ! {
+    let entry = SECONDARY_ENTRY.load(Ordering::SeqCst);
+    // SAFETY: the entry point is set before starting the hart.
+    let entry: extern "C" fn(usize) -> ! = unsafe { core::mem::transmute(entry) };
+    entry(hart)
+}
+
+/// Returns the id of the current hart, which the kernel keeps in the tp register.
+pub fn hart_id() -> usize {
+    let hart: usize;
+    unsafe { asm!("mv {}, tp", out(reg) hart) };
+    hart
+}
+
+/// Returns the number of timer interrupts received by the hart.
+pub fn ticks(hart: usize) -> usize {
+    HARTS[hart].ticks.load(Ordering::SeqCst)
+}
+
+/// Returns the number of software interrupts (IPIs) received by the hart.
+pub fn ipis(hart: usize) -> usize {
+    HARTS[hart].ipis.load(Ordering::SeqCst)
+}
+
+// —————————————————————————————————— Time —————————————————————————————————— //
+
+/// Returns the current time, in timebase ticks.
+pub fn time() -> u64 {
+    let time: u64;
+    unsafe { asm!("rdtime {}", out(reg) time) };
+    time
+}
+
+/// Requests a timer interrupt on the current hart at the given time.
+pub fn set_timer(deadline: u64) {
+    HARTS[hart_id()].deadline.store(deadline, Ordering::SeqCst);
+    sbi::set_timer(deadline).expect("Failed to set timer");
+}
+
+// —————————————————————————————— Trap Handler —————————————————————————————— //
+
+extern "C" fn rtos_trap_handler(scause: usize) {
+    let hart = hart_id();
+    let state = &HARTS[hart];
+    match scause {
+        SUPERVISOR_TIMER_INTERRUPT => {
+            let now = time();
+            let deadline = state.deadline.swap(u64::MAX, Ordering::SeqCst);
+            assert!(
+                now >= deadline,
+                "Timer interrupt at {} before its deadline {}",
+                now,
+                deadline
+            );
+            // Setting a new timer clears the pending interrupt
+            sbi::set_timer(u64::MAX).expect("Failed to clear timer");
+            state.ticks.fetch_add(1, Ordering::SeqCst);
+        }
+        SUPERVISOR_SOFTWARE_INTERRUPT => {
+            unsafe { asm!("csrc sip, {}", in(reg) SIP_SSIP) };
+            state.ipis.fetch_add(1, Ordering::SeqCst);
+        }
+        _ => {
+            let (sepc, stval): (usize, usize);
+            unsafe { asm!("csrr {}, sepc", "csrr {}, stval", out(reg) sepc, out(reg) stval) };
+            panic!(
+                "Unexpected trap on hart {}: scause 0x{:x}, sepc 0x{:x}, stval 0x{:x}",
+                hart, scause, sepc, stval
+            );
+        }
+    }
+}
+
+global_asm!(
+    r#"
+.text
+.align 4
+.global _rtos_trap_entry
+_rtos_trap_entry:
+    addi sp, sp, -128
+    sd ra, 0(sp)
+    sd t0, 8(sp)
+    sd t1, 16(sp)
+    sd t2, 24(sp)
+    sd t3, 32(sp)
+    sd t4, 40(sp)
+    sd t5, 48(sp)
+    sd t6, 56(sp)
+    sd a0, 64(sp)
+    sd a1, 72(sp)
+    sd a2, 80(sp)
+    sd a3, 88(sp)
+    sd a4, 96(sp)
+    sd a5, 104(sp)
+    sd a6, 112(sp)
+    sd a7, 120(sp)
+
+    csrr a0, scause
+    call {handler}
+
+    ld ra, 0(sp)
+    ld t0, 8(sp)
+    ld t1, 16(sp)
+    ld t2, 24(sp)
+    ld t3, 32(sp)
+    ld t4, 40(sp)
+    ld t5, 48(sp)
+    ld t6, 56(sp)
+    ld a0, 64(sp)
+    ld a1, 72(sp)
+    ld a2, 80(sp)
+    ld a3, 88(sp)
+    ld a4, 96(sp)
+    ld a5, 104(sp)
+    ld a6, 112(sp)
+    ld a7, 120(sp)
+    addi sp, sp, 128
+    sret
+
+// Started by the SBI HSM extension with the hart id in a0 and the stack top in a1
+.align 4
+.global _rtos_secondary_entry
+_rtos_secondary_entry:
+    mv sp, a1
+    j {secondary}
+"#,
+    handler = sym rtos_trap_handler,
+    secondary = sym secondary_entry,
+);
+
+extern "C" {
+    fn _rtos_trap_entry();
+    fn _rtos_secondary_entry();
+}
@@ -0,0 +1,163 @@
+//! A small RTOS payload
+//!
+//! This payload runs a minimal cooperative kernel on top of the virtualized firmware, and checks
+//! that the services an RTOS relies on behave as expected under Miralis:
+//!
+//! - Timer interrupts, through the SBI TIME extension, which must never fire before their deadline.
+//! - IPIs, through the SBI IPI extension. If the platform has a second hart it is started with the
+//!   SBI HSM extension and the IPIs bounce between the harts, otherwise the boot hart sends them to
+//!   itself.
+//! - The UART, which the payload accesses directly.
+
+#![no_std]
+#![no_main]
+
+mod drivers;
+mod kernel;
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use drivers::{sbi, uart};
+use kernel::{Poll, Task, MAX_HARTS};
+use miralis_abi::{log, setup_binary, success};
+
+setup_binary!(main);
+
+/// Number of timer interrupts received by the ticker task.
+const NB_TICKS: usize = 10;
+/// Period of the ticker task, 1ms with the 10MHz timebase of QEMU virt.
+const TICK_PERIOD: u64 = 10_000;
+/// Number of IPIs sent by the ping task.
+const NB_PINGS: usize = 10;
+
+/// Marker for the absence of a peer hart.
+const NO_HART: usize = usize::MAX;
+
+static BOOT_HART: AtomicUsize = AtomicUsize::new(NO_HART);
+static PEER_HART: AtomicUsize = AtomicUsize::new(NO_HART);
+static PEER_READY: AtomicBool = AtomicBool::new(false);
+
+fn main() -> ! {
+    log::info!("Hello from the RTOS payload");
+
+    // The boot hart is the only one running, other harts are stopped until started by the payload
+    let boot_hart = (0..MAX_HARTS)
+        .find(|hart| sbi::hart_get_status(*hart) == Ok(sbi::HART_STARTED))
+        .expect("Could not find the boot hart");
+    BOOT_HART.store(boot_hart, Ordering::SeqCst);
+    kernel::init_hart(boot_hart);
+
+    let peer = (0..MAX_HARTS).find(|hart| sbi::hart_get_status(*hart) == Ok(sbi::HART_STOPPED));
+    match peer {
+        Some(peer) => {
+            log::info!("Starting hart {}", peer);
+            PEER_HART.store(peer, Ordering::SeqCst);
+            kernel::start_hart(peer, secondary_main).expect("Failed to start hart");
+        }
+        None => log::info!("No other hart, IPIs are sent to self"),
+    }
+
+    kernel::run(&[
+        Task {
+            name: "ticker",
+            poll: ticker,
+        },
+        Task {
+            name: "ping",
+            poll: ping,
+        },
+        Task {
+            name: "console",
+            poll: console,
+        },
+    ]);
+
+    assert_eq!(kernel::ticks(boot_hart), NB_TICKS);
+    assert_eq!(kernel::ipis(boot_hart), NB_PINGS);
+    if let Some(peer) = peer {
+        assert_eq!(kernel::ipis(peer), NB_PINGS);
+        assert_eq!(kernel::ticks(peer), 0);
+    }
+
+    log::info!("All RTOS tasks completed");
+    success()
+}
+
+extern "C" fn secondary_main(hart: usize) -> ! {
+    kernel::init_hart(hart);
+    PEER_READY.store(true, Ordering::SeqCst);
+
+    kernel::run(&[Task {
+        name: "pong",
+        poll: pong,
+    }]);
+
+    sbi::hart_stop().ok();
+    panic!("Hart {} did not stop", hart);
+}
+
+// ————————————————————————————————— Tasks —————————————————————————————————— //
+
+/// Waits for periodic timer interrupts.
+fn ticker() -> Poll {
+    static ARMED: AtomicUsize = AtomicUsize::new(0);
+
+    let ticks = kernel::ticks(kernel::hart_id());
+    if ticks == NB_TICKS {
+        return Poll::Ready;
+    }
+    // Arm the timer for the next tick once the previous one has been received
+    if ARMED.load(Ordering::SeqCst) == ticks {
+        kernel::set_timer(kernel::time() + TICK_PERIOD);
+        ARMED.store(ticks + 1, Ordering::SeqCst);
+    }
+    Poll::Pending
+}
+
+/// Sends IPIs to the peer hart (or to self), one at a time, waiting for the reply in between.
+fn ping() -> Poll {
+    static SENT: AtomicUsize = AtomicUsize::new(0);
+
+    let target = match PEER_HART.load(Ordering::SeqCst) {
+        NO_HART => kernel::hart_id(),
+        peer if PEER_READY.load(Ordering::SeqCst) => peer,
+        _ => return Poll::Pending,
+    };
+
+    let sent = SENT.load(Ordering::SeqCst);
+    if kernel::ipis(kernel::hart_id()) < sent {
+        return Poll::Pending;
+    }
+    if sent == NB_PINGS {
+        return Poll::Ready;
+    }
+    SENT.store(sent + 1, Ordering::SeqCst);
+    sbi::send_ipi(target).expect("Failed to send IPI");
+    Poll::Pending
+}
+
+/// Replies to each IPI received from the boot hart.
+fn pong() -> Poll {
+    static REPLIED: AtomicUsize = AtomicUsize::new(0);
+
+    let received = kernel::ipis(kernel::hart_id());
+    let mut replied = REPLIED.load(Ordering::SeqCst);
+    while replied < received {
+        replied += 1;
+        sbi::send_ipi(BOOT_HART.load(Ordering::SeqCst)).expect("Failed to send IPI");
+    }
+    REPLIED.store(replied, Ordering::SeqCst);
+
+    match replied {
+        NB_PINGS => Poll::Ready,
+        _ => Poll::Pending,
+    }
+}
+
+/// Writes to the UART directly.
+fn console() -> Poll {
+    for line in ["[rtos] console task running\n", "[rtos] UART is ready\n"] {
+        uart::write_str(line).expect("UART transmitter is not ready");
+    }
+    Poll::Ready
+}
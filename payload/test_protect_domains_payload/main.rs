@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+#![feature(start)]
+// ———————————————————————————————— Guest OS ———————————————————————————————— //
+
+use miralis_abi::{log, register_domain, setup_binary, success, unregister_domain};
+
+setup_binary!(main);
+
+const INVALID_PARAM: usize = -3isize as usize;
+const INVALID_ADDRESS: usize = -5isize as usize;
+
+/// A page of secrets, protected from the firmware.
+#[repr(C, align(4096))]
+struct Secret([u8; 4096]);
+
+static SECRET: Secret = Secret([0x42; 4096]);
+
+fn main() -> ! {
+    log::info!("Hello from test protect domains payload");
+
+    let start = &SECRET as *const Secret as usize;
+    let size = core::mem::size_of::<Secret>();
+
+    // Register as many domains as possible, the payload must still be able to access them
+    let mut domains = [0; 4];
+    for domain in &mut domains {
+        *domain = register_domain(start, size).expect("Failed to register domain");
+    }
+    register_domain(start, size).expect_err("Registered more domains than available");
+    assert_eq!(unsafe { core::ptr::read_volatile(&SECRET.0[0]) }, 0x42);
+
+    // Domains must be naturally aligned powers of two
+    assert_eq!(register_domain(start + 8, size), Err(INVALID_PARAM));
+    assert_eq!(register_domain(start, 3 * size), Err(INVALID_PARAM));
+    // Domains must lie within the payload memory
+    assert_eq!(register_domain(0x1000, size), Err(INVALID_ADDRESS));
+
+    for domain in domains {
+        unregister_domain(domain).expect("Failed to unregister domain");
+    }
+    assert_eq!(unregister_domain(domains[0]), Err(INVALID_PARAM));
+    assert_eq!(unregister_domain(42), Err(INVALID_PARAM));
+
+    success()
+}
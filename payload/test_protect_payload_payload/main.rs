@@ -10,7 +10,9 @@
 
 use core::arch::asm;
 
-use miralis_abi::{lock_payload, log, setup_binary, success};
+use miralis_abi::{
+    lock_payload, log, payload_report as report, read_payload_report, setup_binary, success,
+};
 
 setup_binary!(main);
 
@@ -24,6 +26,9 @@ fn main() -> ! {
     // Make sure the ecall parameters goes through
     assert!(test_ecall_rule(), "Ecall test failed");
 
+    // The payload has been measured, the report must be bound to our nonce
+    assert!(test_attestation_report(), "Attestation report test failed");
+
     // and exit
     success();
 }
@@ -56,3 +61,15 @@ fn test_ecall_rule() -> bool {
 
     ret_value_1 == 0xdeadbeef && ret_value_2 == 0xdeadbeef && s2_value != 0xdeadbeef
 }
+
+fn test_attestation_report() -> bool {
+    let nonce = [0x5a; report::NONCE_SIZE];
+    let mut bytes = [0; report::SIZE];
+    if read_payload_report(&nonce, &mut bytes) != Ok(report::SIZE) {
+        return false;
+    }
+
+    let magic = &bytes[report::MAGIC_OFFSET..report::MAGIC_OFFSET + 8];
+    let report_nonce = &bytes[report::NONCE_OFFSET..report::NONCE_OFFSET + report::NONCE_SIZE];
+    magic == report::MAGIC.to_le_bytes() && report_nonce == nonce
+}
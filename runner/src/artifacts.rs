@@ -17,6 +17,7 @@ use crate::path::{
     get_target_config_path, get_target_dir_path, get_workspace_path, is_file_present, is_older,
     remove_file_extention, GZ_COMPRESSION, IMG_EXTENSION, XZ_COMPRESSION, ZST_COMPRESSION,
 };
+use crate::record::short_revision;
 use crate::ArtifactArgs;
 
 // —————————————————————————— Target & Build Info ——————————————————————————— //
@@ -337,9 +338,18 @@ pub fn build_target(target: Target, cfg: &Config) -> PathBuf {
             let linker_args = format!("-C link-arg=-Tmisc/linker-script.x -C link-arg=--defsym=_start_address={start_address}");
             build_cmd.arg("--package").arg("miralis");
             build_cmd.env("RUSTFLAGS", linker_args);
+            if let Some(features) = &cfg.target.miralis.features {
+                build_cmd.arg("--no-default-features");
+                build_cmd.arg("--features").arg(features.join(","));
+            }
+            if cfg.fault_injection.enable.unwrap_or(false) {
+                build_cmd.arg("--features").arg("fault_injection");
+            }
 
             // Environment variables
             build_cmd.envs(cfg.build_envs());
+            build_cmd.env("MIRALIS_BUILD_REVISION", short_revision());
+            build_cmd.env("MIRALIS_BUILD_CONFIG_HASH", cfg.build_hash());
         }
 
         Target::Firmware(ref firmware) => {
@@ -0,0 +1,216 @@
+//! Benchmark subcommand
+//!
+//! The benchmark subcommand runs a benchmark firmware multiple times, in parallel across the host
+//! cores, and aggregates the statistics printed by Miralis over all runs. A configurable number of
+//! warmup runs is executed first and their results are discarded, and the 95% confidence interval
+//! of the mean is reported, such that small differences can be told apart from noise.
+//!
+//! Miralis must be built with benchmarks enabled, and with the csv output format. If latency
+//! histograms are enabled the raw samples of all runs are aggregated as well, and their
+//! percentiles can be written to csv files for plotting.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::{Command, ExitCode, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::{fs, thread};
+
+use ::benchmark::{
+    compute_statistics, parse_content, parse_samples, percentile, print_percentiles, START_TOKEN,
+};
+
+use crate::artifacts::{build_target, prepare_firmware_artifact, Target};
+use crate::config::read_config;
+use crate::run::get_qemu_cmd;
+use crate::BenchmarkArgs;
+
+/// Default number of measured runs.
+const DEFAULT_RUNS: usize = 10;
+
+// ——————————————————————————————— Benchmark ———————————————————————————————— //
+
+/// The benchmark command, runs a firmware multiple times and reports aggregated statistics.
+pub fn benchmark(args: &BenchmarkArgs) -> ExitCode {
+    let cfg = read_config(&args.config);
+    if !cfg.benchmark.enable.unwrap_or(false) {
+        log::warn!("Benchmarks are not enabled in the config, no statistics will be collected");
+    }
+    if !cfg.benchmark.csv_format.unwrap_or(false) {
+        log::warn!("The benchmark output is not in csv format, it can not be analyzed");
+    }
+
+    let runs = args.runs.or(cfg.benchmark.runs).unwrap_or(DEFAULT_RUNS);
+    let warmup = args.warmup.or(cfg.benchmark.warmup).unwrap_or(0);
+    let jobs = args
+        .jobs
+        .or(cfg.benchmark.jobs)
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+        .max(1);
+
+    // Build the artifacts once, all runs share them
+    let miralis = build_target(Target::Miralis, &cfg);
+    let firmware_name = args
+        .firmware
+        .as_deref()
+        .or(cfg.target.firmware.name.as_deref())
+        .unwrap_or("default");
+    let Some(firmware) = prepare_firmware_artifact(firmware_name, &cfg) else {
+        log::error!("Invalid firmware '{}'", firmware_name);
+        return ExitCode::FAILURE;
+    };
+    let run = |_: usize| {
+        let Ok(cmd) = get_qemu_cmd(&cfg, miralis.clone(), firmware.clone(), None, false, false)
+        else {
+            return None;
+        };
+        run_once(cmd)
+    };
+
+    if warmup > 0 {
+        log::info!("Running {} warmup iterations", warmup);
+        run_parallel(warmup, jobs, run);
+    }
+    log::info!(
+        "Running {} iterations of '{}' on {} jobs",
+        runs,
+        firmware_name,
+        jobs
+    );
+    let outputs = run_parallel(runs, jobs, run);
+
+    // Aggregate the results of the successful runs
+    let mut map_type_tag_values: HashMap<String, HashMap<String, Vec<usize>>> = HashMap::new();
+    let mut samples: HashMap<String, Vec<u64>> = HashMap::new();
+    let mut failures = 0;
+    for (idx, output) in outputs.into_iter().enumerate() {
+        match output {
+            Some(lines) => {
+                parse_samples(&lines, &mut samples);
+                parse_content(lines, &mut map_type_tag_values);
+            }
+            None => {
+                log::error!("Run {} failed or did not report any benchmark", idx);
+                failures += 1;
+            }
+        }
+    }
+
+    compute_statistics(&map_type_tag_values);
+    if !samples.is_empty() {
+        print_percentiles(&samples);
+        if let Some(dir) = &args.percentiles {
+            if let Err(err) = write_percentiles(dir, &samples) {
+                log::error!("Failed to write percentiles to {}: {}", dir.display(), err);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    if failures > 0 {
+        log::error!("{} out of {} runs failed", failures, runs);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+/// Runs the command, and returns its output if it succeeded and reported a benchmark.
+fn run_once(mut cmd: Command) -> Option<Vec<String>> {
+    log::debug!("{:?}", cmd);
+    let output = cmd
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let lines: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(String::from)
+        .collect();
+    if lines.iter().any(|line| line.contains(START_TOKEN)) {
+        Some(lines)
+    } else {
+        None
+    }
+}
+
+/// Writes the percentile curve of each latency to `<dir>/<latency>.csv`.
+fn write_percentiles(dir: &Path, samples: &HashMap<String, Vec<u64>>) -> std::io::Result<()> {
+    fs::create_dir_all(dir)?;
+    for (name, values) in samples {
+        let mut sorted = values.clone();
+        sorted.sort_unstable();
+        let mut csv = String::from("percentile,cycles\n");
+        for percent in percentile_steps() {
+            if let Some(value) = percentile(&sorted, percent) {
+                csv.push_str(&format!("{},{}\n", percent, value));
+            }
+        }
+        let path = dir.join(format!("{}.csv", name));
+        fs::write(&path, csv)?;
+        log::info!("Percentiles of {} written to {}", name, path.display());
+    }
+    Ok(())
+}
+
+/// The percentiles of the csv curves, finer grained in the tail of the distribution.
+fn percentile_steps() -> impl Iterator<Item = f64> {
+    (1..=99)
+        .map(f64::from)
+        .chain((990..=999).map(|p| f64::from(p) / 10.0))
+        .chain([99.99, 100.0])
+}
+
+/// Executes `nb_runs` runs on up to `jobs` threads, and returns their results in run order.
+fn run_parallel<T, F>(nb_runs: usize, jobs: usize, run: F) -> Vec<T>
+where
+    T: Send,
+    F: Fn(usize) -> T + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new((0..nb_runs).map(|_| None).collect::<Vec<Option<T>>>());
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(nb_runs) {
+            scope.spawn(|| loop {
+                let idx = next.fetch_add(1, Ordering::Relaxed);
+                if idx >= nb_runs {
+                    break;
+                }
+                let result = run(idx);
+                results.lock().unwrap()[idx] = Some(result);
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("Missing run result"))
+        .collect()
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parallel_runs_keep_order() {
+        let calls = AtomicUsize::new(0);
+        let results = run_parallel(17, 4, |idx| {
+            calls.fetch_add(1, Ordering::Relaxed);
+            idx * 2
+        });
+        assert_eq!(calls.load(Ordering::Relaxed), 17);
+        assert_eq!(results, (0..17).map(|idx| idx * 2).collect::<Vec<_>>());
+
+        // More jobs than runs, and no runs at all
+        assert_eq!(run_parallel(2, 8, |idx| idx), vec![0, 1]);
+        assert!(run_parallel(0, 4, |idx| idx).is_empty());
+    }
+}
@@ -4,7 +4,7 @@
 //! appropriate environment variables during Miralis's build.
 
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
 use std::{fmt, fs};
 
@@ -16,7 +16,7 @@ use crate::CheckConfigArgs;
 
 // ——————————————————————————— Config Definition ———————————————————————————— //
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Default)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
     #[serde(default)]
@@ -35,6 +35,8 @@ pub struct Config {
     pub target: Targets,
     #[serde(default)]
     pub policy: Policy,
+    #[serde(default)]
+    pub fault_injection: FaultInjection,
 }
 
 #[derive(Deserialize, Debug, Default)]
@@ -42,6 +44,8 @@ pub struct Config {
 pub struct Log {
     pub level: Option<String>,
     pub color: Option<bool>,
+    pub console_tags: Option<bool>,
+    pub filters: Option<String>,
     pub error: Option<Vec<String>>,
     pub warn: Option<Vec<String>>,
     pub info: Option<Vec<String>>,
@@ -53,13 +57,35 @@ pub struct Log {
 #[serde(deny_unknown_fields)]
 pub struct Debug {
     pub max_firmware_exits: Option<usize>,
+    pub coverage: Option<bool>,
+    pub trace_entries: Option<usize>,
+    pub trap_log_entries: Option<usize>,
+    pub stub: Option<bool>,
+    pub semihosting: Option<bool>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct FaultInjection {
+    pub enable: Option<bool>,
+    pub seed: Option<usize>,
+    pub csr_flip_ppm: Option<usize>,
+    pub interrupt_delay_ppm: Option<usize>,
+    pub pmp_flush_ppm: Option<usize>,
 }
 
 #[derive(Deserialize, Debug, Default)]
 #[serde(deny_unknown_fields)]
 pub struct VCpu {
     pub max_pmp: Option<usize>,
+    pub pmp_coalescing: Option<bool>,
+    pub triggers: Option<usize>,
     pub delegate_perf_counters: Option<bool>,
+    pub counter_poll_threshold: Option<usize>,
+    pub trap_hpm_counters: Option<bool>,
+    pub emulate_misaligned: Option<bool>,
+    pub raw_counters: Option<bool>,
+    pub freeze_counters: Option<bool>,
 }
 
 #[derive(Deserialize, Debug, Default)]
@@ -68,6 +94,10 @@ pub struct Platform {
     pub name: Option<Platforms>,
     pub nb_harts: Option<usize>,
     pub boot_hart_id: Option<usize>,
+    pub dtb_patch_address: Option<usize>,
+    pub dtb_hidden_devices: Option<Vec<String>>,
+    pub dtb_bootargs: Option<String>,
+    pub firmware_pflash: Option<bool>,
 }
 
 #[derive(Deserialize, Debug, Default)]
@@ -85,6 +115,10 @@ pub enum Platforms {
     QemuVirt,
     #[serde(rename = "visionfive2")]
     VisionFive2,
+    #[serde(rename = "unmatched")]
+    Unmatched,
+    #[serde(rename = "sifive_u")]
+    SifiveU,
     #[serde(rename = "spike")]
     Spike,
 }
@@ -94,6 +128,8 @@ impl fmt::Display for Platforms {
         match self {
             Platforms::QemuVirt => write!(f, "qemu_virt"),
             Platforms::VisionFive2 => write!(f, "visionfive2"),
+            Platforms::Unmatched => write!(f, "unmatched"),
+            Platforms::SifiveU => write!(f, "sifive_u"),
             Platforms::Spike => write!(f, "spike"),
         }
     }
@@ -109,7 +145,21 @@ pub struct Benchmark {
     pub nb_exits: Option<bool>,
     pub nb_firmware_exits: Option<bool>,
     pub world_switches: Option<bool>,
+    /// Count the confidential pages zeroed by the ACE page scrubber
+    pub scrubbed_pages: Option<bool>,
+    /// Collect latency histograms of firmware exits and world switches
+    pub histogram: Option<bool>,
+    /// Upper bounds of the histogram buckets, in cycles
+    pub histogram_buckets: Option<Vec<usize>>,
+    /// Number of raw samples retained per histogram, used to compute percentiles
+    pub histogram_samples: Option<usize>,
     pub nb_iter: Option<usize>,
+    /// Number of measured runs of the benchmark subcommand
+    pub runs: Option<usize>,
+    /// Number of runs executed before the measured ones, whose results are discarded
+    pub warmup: Option<usize>,
+    /// Maximum number of runs executed in parallel
+    pub jobs: Option<usize>,
 }
 
 #[derive(Deserialize, Debug, Default)]
@@ -127,13 +177,26 @@ pub struct Target {
     pub profile: Option<Profiles>,
     pub start_address: Option<usize>,
     pub stack_size: Option<usize>,
+    pub features: Option<Vec<String>>,
 }
 
 #[derive(Deserialize, Debug, Default)]
 #[serde(deny_unknown_fields)]
 pub struct Policy {
     pub name: Option<PolicyModule>,
+    pub stack: Option<PolicyModule>,
     pub payload_size: Option<usize>,
+    pub ace_device_secret: Option<String>,
+    pub ace_migration_secret: Option<String>,
+    pub ace_promotion_entry_points: Option<Vec<String>>,
+    pub ace_promotion_zeroed_regions: Option<Vec<String>>,
+    pub ace_promotion_check_fdt: Option<bool>,
+    pub ace_confidential_memory: Option<Vec<String>>,
+    pub ace_response_timeout_ms: Option<usize>,
+    pub sbi_deny_list: Option<Vec<String>>,
+    /// Options of the policy modules, as `<policy>.<option>=<value>` entries
+    pub options: Option<Vec<String>>,
+    pub audit_log_entries: Option<usize>,
 }
 
 #[derive(Deserialize, Debug, Clone, Copy)]
@@ -144,6 +207,12 @@ pub enum PolicyModule {
     Keystone,
     #[serde(rename = "protect_payload")]
     ProtectPayload,
+    #[serde(rename = "protect_domains")]
+    ProtectDomains,
+    #[serde(rename = "isolate_payload")]
+    IsolatePayload,
+    #[serde(rename = "sbi_firewall")]
+    SbiFirewall,
     #[serde(rename = "ace")]
     Ace,
 }
@@ -154,6 +223,9 @@ impl fmt::Display for PolicyModule {
             PolicyModule::Default => write!(f, "default"),
             PolicyModule::Keystone => write!(f, "keystone"),
             PolicyModule::ProtectPayload => write!(f, "protect_payload"),
+            PolicyModule::ProtectDomains => write!(f, "protect_domains"),
+            PolicyModule::IsolatePayload => write!(f, "isolate_payload"),
+            PolicyModule::SbiFirewall => write!(f, "sbi_firewall"),
             PolicyModule::Ace => write!(f, "ace"),
         }
     }
@@ -180,8 +252,35 @@ impl Config {
         envs.extend(self.benchmark.build_envs());
         envs.extend(self.target.build_envs());
         envs.extend(self.policy.buid_envs());
+        envs.extend(self.fault_injection.build_envs());
         envs
     }
+
+    /// Returns a hash of the configuration options which affect the build of Miralis, such that
+    /// a binary can be tied to its build configuration.
+    ///
+    /// This is a 64 bits FNV-1a hash, which unlike the standard library hasher is stable across
+    /// Rust versions.
+    pub fn build_hash(&self) -> String {
+        let mut envs: Vec<_> = self.build_envs().into_iter().collect();
+        envs.sort();
+        let features = self.target.miralis.features.clone().unwrap_or_default();
+        let fault_injection = self.fault_injection.enable.unwrap_or(false);
+
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let mut update = |bytes: &[u8]| {
+            for byte in bytes.iter().chain(b"\n") {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        };
+        for (name, value) in &envs {
+            update(format!("{}={}", name, value).as_bytes());
+        }
+        update(format!("features={}", features.join(",")).as_bytes());
+        update(format!("fault_injection={}", fault_injection).as_bytes());
+        format!("{:016x}", hash)
+    }
 }
 
 struct EnvVars {
@@ -202,8 +301,9 @@ impl EnvVars {
         }
     }
 
-    pub fn insert_array(&mut self, var_name: &str, option: &Option<Vec<String>>) {
+    pub fn insert_array<T: std::fmt::Display>(&mut self, var_name: &str, option: &Option<Vec<T>>) {
         if let Some(values) = option {
+            let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
             self.envs
                 .insert(String::from(var_name), values.join(",").to_string());
         }
@@ -220,6 +320,12 @@ impl Log {
         // Decides between colored and gray output
         envs.insert("MIRALIS_LOG_COLOR", &self.color);
 
+        // Tags console lines with the world which produced them
+        envs.insert("MIRALIS_LOG_CONSOLE_TAGS", &self.console_tags);
+
+        // Per-module log filters
+        envs.insert("MIRALIS_LOG_FILTERS", &self.filters);
+
         // Modules logged at error level
         envs.insert_array("MIRALIS_LOG_ERROR", &self.error);
 
@@ -243,6 +349,25 @@ impl Debug {
     fn build_envs(&self) -> HashMap<String, String> {
         let mut envs = EnvVars::new();
         envs.insert("MIRALIS_DEBUG_MAX_FIRMWARE_EXITS", &self.max_firmware_exits);
+        envs.insert("MIRALIS_DEBUG_COVERAGE", &self.coverage);
+        envs.insert("MIRALIS_DEBUG_TRACE_ENTRIES", &self.trace_entries);
+        envs.insert("MIRALIS_DEBUG_TRAP_LOG_ENTRIES", &self.trap_log_entries);
+        envs.insert("MIRALIS_DEBUG_STUB", &self.stub);
+        envs.insert("MIRALIS_DEBUG_SEMIHOSTING", &self.semihosting);
+        envs.envs
+    }
+}
+
+impl FaultInjection {
+    fn build_envs(&self) -> HashMap<String, String> {
+        let mut envs = EnvVars::new();
+        envs.insert("MIRALIS_FAULT_INJECTION_SEED", &self.seed);
+        envs.insert("MIRALIS_FAULT_INJECTION_CSR_FLIP_PPM", &self.csr_flip_ppm);
+        envs.insert(
+            "MIRALIS_FAULT_INJECTION_INTERRUPT_DELAY_PPM",
+            &self.interrupt_delay_ppm,
+        );
+        envs.insert("MIRALIS_FAULT_INJECTION_PMP_FLUSH_PPM", &self.pmp_flush_ppm);
         envs.envs
     }
 }
@@ -251,10 +376,20 @@ impl VCpu {
     fn build_envs(&self) -> HashMap<String, String> {
         let mut envs = EnvVars::new();
         envs.insert("MIRALIS_VCPU_MAX_PMP", &self.max_pmp);
+        envs.insert("MIRALIS_VCPU_PMP_COALESCING", &self.pmp_coalescing);
+        envs.insert("MIRALIS_VCPU_TRIGGERS", &self.triggers);
         envs.insert(
             "MIRALIS_DELEGATE_PERF_COUNTER",
             &self.delegate_perf_counters,
         );
+        envs.insert(
+            "MIRALIS_VCPU_COUNTER_POLL_THRESHOLD",
+            &self.counter_poll_threshold,
+        );
+        envs.insert("MIRALIS_VCPU_TRAP_HPM_COUNTERS", &self.trap_hpm_counters);
+        envs.insert("MIRALIS_VCPU_EMULATE_MISALIGNED", &self.emulate_misaligned);
+        envs.insert("MIRALIS_VCPU_RAW_COUNTERS", &self.raw_counters);
+        envs.insert("MIRALIS_VCPU_FREEZE_COUNTERS", &self.freeze_counters);
         envs.envs
     }
 }
@@ -265,6 +400,16 @@ impl Platform {
         envs.insert("MIRALIS_PLATFORM_NAME", &self.name);
         envs.insert("MIRALIS_PLATFORM_NB_HARTS", &self.nb_harts);
         envs.insert("MIRALIS_PLATFORM_BOOT_HART_ID", &self.boot_hart_id);
+        envs.insert(
+            "MIRALIS_PLATFORM_DTB_PATCH_ADDRESS",
+            &self.dtb_patch_address,
+        );
+        envs.insert_array(
+            "MIRALIS_PLATFORM_DTB_HIDDEN_DEVICES",
+            &self.dtb_hidden_devices,
+        );
+        envs.insert("MIRALIS_PLATFORM_DTB_BOOTARGS", &self.dtb_bootargs);
+        envs.insert("MIRALIS_PLATFORM_FIRMWARE_PFLASH", &self.firmware_pflash);
         envs.envs
     }
 }
@@ -282,6 +427,16 @@ impl Benchmark {
             &self.nb_firmware_exits,
         );
         envs.insert("MIRALIS_BENCHMARK_WORLD_SWITCHES", &self.world_switches);
+        envs.insert("MIRALIS_BENCHMARK_SCRUBBED_PAGES", &self.scrubbed_pages);
+        envs.insert("MIRALIS_BENCHMARK_HISTOGRAM", &self.histogram);
+        envs.insert_array(
+            "MIRALIS_BENCHMARK_HISTOGRAM_BUCKETS",
+            &self.histogram_buckets,
+        );
+        envs.insert(
+            "MIRALIS_BENCHMARK_HISTOGRAM_SAMPLES",
+            &self.histogram_samples,
+        );
         envs.insert("MIRALIS_BENCHMARK_NB_ITER", &self.nb_iter);
         envs.envs
     }
@@ -315,7 +470,33 @@ impl Policy {
     fn buid_envs(&self) -> HashMap<String, String> {
         let mut envs = EnvVars::new();
         envs.insert("MIRALIS_POLICY_NAME", &self.name);
+        envs.insert("MIRALIS_POLICY_STACK", &self.stack);
         envs.insert("PAYLOAD_HASH_SIZE", &self.payload_size);
+        envs.insert("MIRALIS_ACE_DEVICE_SECRET", &self.ace_device_secret);
+        envs.insert("MIRALIS_ACE_MIGRATION_SECRET", &self.ace_migration_secret);
+        envs.insert_array(
+            "MIRALIS_ACE_PROMOTION_ENTRY_POINTS",
+            &self.ace_promotion_entry_points,
+        );
+        envs.insert_array(
+            "MIRALIS_ACE_PROMOTION_ZEROED_REGIONS",
+            &self.ace_promotion_zeroed_regions,
+        );
+        envs.insert(
+            "MIRALIS_ACE_PROMOTION_CHECK_FDT",
+            &self.ace_promotion_check_fdt,
+        );
+        envs.insert_array(
+            "MIRALIS_ACE_CONFIDENTIAL_MEMORY",
+            &self.ace_confidential_memory,
+        );
+        envs.insert(
+            "MIRALIS_ACE_RESPONSE_TIMEOUT_MS",
+            &self.ace_response_timeout_ms,
+        );
+        envs.insert_array("MIRALIS_POLICY_SBI_DENY_LIST", &self.sbi_deny_list);
+        envs.insert_array("MIRALIS_POLICY_OPTIONS", &self.options);
+        envs.insert("MIRALIS_POLICY_AUDIT_LOG_ENTRIES", &self.audit_log_entries);
         envs.envs
     }
 }
@@ -324,26 +505,24 @@ impl Policy {
 
 pub fn read_config<P: AsRef<Path>>(path: &Option<P>) -> Config {
     // Try to read config
-    let config = if let Some(path) = path {
-        fs::read_to_string(path)
+    let config_path = if let Some(path) = path {
+        path.as_ref().to_path_buf()
     } else {
         let mut config_path = get_workspace_path();
         config_path.push("config.toml");
-        fs::read_to_string(config_path)
+        config_path
     };
-    let config = match config {
-        Ok(config) => config,
-        Err(_) => {
-            log::warn!("No config file found, using default configuration");
-            // Creating a default config
-            String::from("")
-        }
+    let config = if config_path.is_file() {
+        load_config(&config_path)
+    } else {
+        log::warn!("No config file found, using default configuration");
+        // Creating a default config
+        Ok(Config::default())
     };
 
-    // Parse the config and returns it
-    let mut cfg = match toml::from_str::<Config>(&config) {
+    let mut cfg = match config {
         Ok(config) => config,
-        Err(err) => panic!("Failed to parse configuration:\n{:#?}", err),
+        Err(err) => panic!("Failed to parse configuration:\n{}", err),
     };
 
     if cfg.qemu.cpu == Some(String::from("none")) {
@@ -353,6 +532,73 @@ pub fn read_config<P: AsRef<Path>>(path: &Option<P>) -> Config {
     cfg
 }
 
+// ————————————————————————————— Config Loading ————————————————————————————— //
+
+/// Name of the key listing the files a config inherits from.
+const INCLUDE_KEY: &str = "include";
+
+/// Loads a config file, resolving its includes.
+///
+/// A config can inherit from other config files (e.g. a base platform config and a few profiles
+/// such as `profiles/debug.toml`) with a top-level `include` list. Paths are relative to the
+/// including file. The included files are merged in order, and the values of the including file
+/// take precedence: tables are merged recursively, while other values (including arrays) are
+/// replaced.
+pub fn load_config(path: &Path) -> Result<Config, String> {
+    let table = load_table(path, &mut Vec::new())?;
+    toml::Value::Table(table)
+        .try_into::<Config>()
+        .map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+fn load_table(path: &Path, stack: &mut Vec<PathBuf>) -> Result<toml::Table, String> {
+    let canonical = path
+        .canonicalize()
+        .map_err(|err| format!("Could not read {}: {}", path.display(), err))?;
+    if stack.contains(&canonical) {
+        return Err(format!("Include cycle through {}", path.display()));
+    }
+
+    let content = fs::read_to_string(&canonical)
+        .map_err(|err| format!("Could not read {}: {}", path.display(), err))?;
+    let mut table = toml::from_str::<toml::Table>(&content)
+        .map_err(|err| format!("{}: {}", path.display(), err))?;
+
+    let includes = match table.remove(INCLUDE_KEY) {
+        None => Vec::new(),
+        Some(toml::Value::Array(includes)) => includes,
+        Some(_) => return Err(format!("{}: 'include' must be a list", path.display())),
+    };
+
+    stack.push(canonical);
+    let mut merged = toml::Table::new();
+    for include in includes {
+        let Some(include) = include.as_str() else {
+            return Err(format!("{}: includes must be paths", path.display()));
+        };
+        let include_path = path.parent().unwrap_or(Path::new(".")).join(include);
+        merge(&mut merged, load_table(&include_path, stack)?);
+    }
+    stack.pop();
+
+    merge(&mut merged, table);
+    Ok(merged)
+}
+
+/// Merges `overrides` into `base`, tables are merged recursively.
+fn merge(base: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overrides)) => {
+                merge(base, overrides)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
 // —————————————————————————————— Check Config —————————————————————————————— //
 
 /// Print an error if the config is not valid.
@@ -373,19 +619,94 @@ pub fn check_config(args: &CheckConfigArgs) -> ExitCode {
 }
 
 fn check_config_file(config: &Path) {
-    let content = match fs::read_to_string(config) {
-        Ok(content) => content,
-        Err(error) => {
-            log::error!("Could not read config: {}", error);
-            std::process::exit(1);
-        }
-    };
-
-    match toml::from_str::<Config>(&content) {
+    match load_config(config) {
         Ok(_) => log::info!("Config {} is valid", config.display()),
         Err(err) => {
-            log::error!("Config {} is not valid:\n{:?}", config.display(), err);
+            log::error!("Config {} is not valid:\n{}", config.display(), err);
             std::process::exit(1);
         }
     }
 }
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes the config files in a fresh temporary directory.
+    fn write_configs(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("miralis-config-{}", name));
+        fs::create_dir_all(dir.join("profiles")).unwrap();
+        for (file, content) in files {
+            fs::write(dir.join(file), content).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn merge_tables() {
+        let mut base: toml::Table =
+            toml::from_str("[log]\nlevel = \"info\"\ncolor = true\n[vcpu]\nmax_pmp = 8\n").unwrap();
+        let overrides: toml::Table =
+            toml::from_str("[log]\nlevel = \"debug\"\nerror = [\"virt\"]\n").unwrap();
+        merge(&mut base, overrides);
+
+        let expected: toml::Table = toml::from_str(
+            "[log]\nlevel = \"debug\"\ncolor = true\nerror = [\"virt\"]\n[vcpu]\nmax_pmp = 8\n",
+        )
+        .unwrap();
+        assert_eq!(base, expected);
+    }
+
+    #[test]
+    fn resolve_includes() {
+        let dir = write_configs(
+            "includes",
+            &[
+                (
+                    "base.toml",
+                    "[log]\nlevel = \"info\"\n[vcpu]\nmax_pmp = 8\n",
+                ),
+                ("profiles/debug.toml", "[log]\nlevel = \"debug\"\n"),
+                (
+                    "experiment.toml",
+                    "include = [\"base.toml\", \"profiles/debug.toml\"]\n[vcpu]\nmax_pmp = 4\n",
+                ),
+            ],
+        );
+
+        let cfg = load_config(&dir.join("experiment.toml")).unwrap();
+        assert_eq!(cfg.log.level.as_deref(), Some("debug"));
+        assert_eq!(cfg.vcpu.max_pmp, Some(4));
+    }
+
+    #[test]
+    fn detect_include_cycles() {
+        let dir = write_configs(
+            "cycle",
+            &[
+                ("a.toml", "include = [\"b.toml\"]\n"),
+                ("b.toml", "include = [\"a.toml\"]\n"),
+            ],
+        );
+
+        let err = load_config(&dir.join("a.toml")).unwrap_err();
+        assert!(err.contains("Include cycle"), "{}", err);
+    }
+
+    #[test]
+    fn build_hash() {
+        let parse = |content: &str| toml::from_str::<Config>(content).unwrap();
+        let base = parse("[log]\nlevel = \"info\"\n[vcpu]\nmax_pmp = 8\n");
+        assert_eq!(base.build_hash().len(), 16);
+        assert_eq!(
+            base.build_hash(),
+            parse("[vcpu]\nmax_pmp = 8\n[log]\nlevel = \"info\"\n").build_hash()
+        );
+        assert_ne!(
+            base.build_hash(),
+            parse("[log]\nlevel = \"debug\"\n[vcpu]\nmax_pmp = 8\n").build_hash()
+        );
+    }
+}
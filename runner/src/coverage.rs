@@ -0,0 +1,195 @@
+//! Coverage subcommand
+//!
+//! Runs the integration tests with the emulation coverage counters enabled, aggregates the counters
+//! printed by Miralis on exit, and reports the emulation paths (CSRs, trap causes, and virtual
+//! devices) that none of the tests reach. Those are good candidates for new test firmware.
+
+use std::collections::BTreeMap;
+use std::process::{ExitCode, Stdio};
+
+use crate::config::{read_config, Platforms};
+use crate::path::make_path_relative_to_root;
+use crate::project::read_project_config;
+use crate::run::qemu_is_available;
+use crate::test::get_test_cmd;
+use crate::CoverageArgs;
+
+/// Prefix of the coverage lines, must match `COVERAGE_PREFIX` in Miralis.
+const COVERAGE_PREFIX: &str = "[coverage]";
+
+/// The aggregated counters, indexed by kind (e.g. `csr`) and then by emulation path.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Coverage {
+    counters: BTreeMap<String, BTreeMap<String, usize>>,
+}
+
+impl Coverage {
+    /// Adds the counters found in the output of a run.
+    fn add_output(&mut self, output: &str) {
+        for line in output.lines() {
+            let Some((_, line)) = line.split_once(COVERAGE_PREFIX) else {
+                continue;
+            };
+            let mut parts = line.trim().splitn(3, ' ');
+            let (Some(kind), Some(count), Some(path)) = (parts.next(), parts.next(), parts.next())
+            else {
+                log::warn!("Invalid coverage line: '{}'", line);
+                continue;
+            };
+            let Ok(count) = count.parse::<usize>() else {
+                log::warn!("Invalid coverage count: '{}'", line);
+                continue;
+            };
+            *self
+                .counters
+                .entry(kind.to_string())
+                .or_default()
+                .entry(path.to_string())
+                .or_default() += count;
+        }
+    }
+
+    /// Returns the paths that were never reached, grouped by kind.
+    ///
+    /// A path is also considered reached if one of its sub-paths is, e.g. a device is reached if
+    /// any of its offsets is.
+    fn unreached(&self) -> BTreeMap<&str, Vec<&str>> {
+        self.counters
+            .iter()
+            .map(|(kind, paths)| {
+                let is_reached = |path: &str| {
+                    paths.iter().any(|(other, count)| {
+                        *count > 0
+                            && (other == path
+                                || other.strip_prefix(path).is_some_and(|s| s.starts_with(' ')))
+                    })
+                };
+                let unreached = paths
+                    .keys()
+                    .map(String::as_str)
+                    .filter(|path| !is_reached(path))
+                    .collect();
+                (kind.as_str(), unreached)
+            })
+            .collect()
+    }
+}
+
+// ———————————————————————————————— Coverage ———————————————————————————————— //
+
+/// The coverage command, runs the tests and reports the unreached emulation paths.
+pub fn coverage(args: &CoverageArgs) -> ExitCode {
+    let Some(config) = read_project_config() else {
+        return ExitCode::FAILURE;
+    };
+    if !qemu_is_available() {
+        log::error!("QEMU is required to collect coverage");
+        return ExitCode::FAILURE;
+    }
+
+    let mut coverage = Coverage::default();
+    let mut nb_runs = 0;
+    for (test_name, test) in &config.test {
+        if let Some(pattern) = &args.pattern {
+            if !test_name.starts_with(pattern) {
+                continue;
+            }
+        }
+        let Some(test_cfg) = config.config.get(&test.config) else {
+            log::error!(
+                "Invalid config name '{}' for test '{}'",
+                test.config,
+                test_name
+            );
+            return ExitCode::FAILURE;
+        };
+
+        let mut cfg = read_config(&Some(make_path_relative_to_root(&test_cfg.path)));
+        if !matches!(cfg.platform.name, None | Some(Platforms::QemuVirt)) {
+            log::debug!(
+                "Skipping '{}', coverage is only collected on QEMU",
+                test_name
+            );
+            continue;
+        }
+        cfg.debug.coverage = Some(true);
+
+        log::info!("Running {}", test_name);
+        let Some(mut cmd) = get_test_cmd(test, test_name, &cfg) else {
+            return ExitCode::FAILURE;
+        };
+        let output = match cmd.stderr(Stdio::inherit()).output() {
+            Ok(output) => output,
+            Err(err) => {
+                log::error!("Failed to run '{}': {}", test_name, err);
+                return ExitCode::FAILURE;
+            }
+        };
+        if !output.status.success() {
+            log::warn!("Test '{}' failed, its coverage is still counted", test_name);
+        }
+        coverage.add_output(&String::from_utf8_lossy(&output.stdout));
+        nb_runs += 1;
+    }
+
+    if nb_runs == 0 {
+        log::error!("No test to run");
+        return ExitCode::FAILURE;
+    }
+
+    let unreached = coverage.unreached();
+    log::info!("\nEmulation coverage over {} tests:", nb_runs);
+    for (kind, paths) in &coverage.counters {
+        let total = paths.len();
+        let reached = total - unreached[kind.as_str()].len();
+        log::info!("  {:<12} {}/{} reached", kind, reached, total);
+    }
+    for (kind, unreached) in unreached {
+        if unreached.is_empty() {
+            continue;
+        }
+        log::info!("\nUnreached {}:", kind);
+        for path in unreached {
+            log::info!("  {}", path);
+        }
+    }
+
+    ExitCode::SUCCESS
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aggregate_runs() {
+        let mut coverage = Coverage::default();
+        coverage.add_output(
+            "[INFO] Success!\n[coverage] csr 2 0x300\n[coverage] csr 0 0x301\n\
+             [coverage] trap 1 firmware: illegal instruction\n[coverage] device 0 TEST\n",
+        );
+        coverage.add_output(
+            "[coverage] csr 0 0x300\n[coverage] device 1 TEST 0x0\n[coverage] device 0 CLINT\n",
+        );
+
+        assert_eq!(coverage.counters["csr"]["0x300"], 2);
+        assert_eq!(
+            coverage.counters["trap"]["firmware: illegal instruction"],
+            1
+        );
+
+        let unreached = coverage.unreached();
+        assert_eq!(unreached["csr"], vec!["0x301"]);
+        assert!(unreached["trap"].is_empty());
+        assert_eq!(unreached["device"], vec!["CLINT"]);
+    }
+
+    #[test]
+    fn ignore_invalid_lines() {
+        let mut coverage = Coverage::default();
+        coverage.add_output("[coverage] csr\n[coverage] csr many 0x300\n");
+        assert_eq!(coverage, Coverage::default());
+    }
+}
@@ -0,0 +1,213 @@
+//! Debug subcommand
+//!
+//! The debug subcommand launches Miralis in QEMU, stopped and waiting for a debugger, generates a
+//! GDB script loading the symbols of Miralis, the firmware, and the payload, and attaches GDB. The
+//! console output of QEMU is redirected to a log file, such that GDB can use the terminal.
+//! With the `debug.stub` configuration GDB also stops whenever the firmware hits a breakpoint.
+//!
+//! The same flow backs `runner gdb --launch`, which additionally breaks on the trap handler.
+
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode, Stdio};
+
+use crate::artifacts::{build_target, prepare_firmware_artifact, prepare_payload_artifact, Target};
+use crate::config::read_config;
+use crate::gdb::GDB_EXECUTABLES;
+use crate::path::{get_target_dir_path, get_workspace_path};
+use crate::run::get_qemu_cmd;
+use crate::DebugArgs;
+
+/// Default address of the firmware, must match the linker arguments used to build it.
+const DEFAULT_FIRMWARE_ADDR: usize = 0x80200000;
+
+/// Default address of the payload, must match the linker arguments used to build it.
+const DEFAULT_PAYLOAD_ADDR: usize = 0x80400000;
+
+/// Symbol called by Miralis when the firmware hits a breakpoint, if the debug stub is enabled.
+const FIRMWARE_BREAKPOINT_SYMBOL: &str = "miralis_firmware_breakpoint";
+
+// ————————————————————————————————— Debug —————————————————————————————————— //
+
+/// The debug command, runs Miralis in QEMU and attaches GDB to it.
+pub fn debug(args: &DebugArgs) -> ExitCode {
+    let mut cfg = read_config(&args.config);
+    if let Some(payload) = &args.payload {
+        cfg.target.payload.get_or_insert_with(Default::default).name = Some(payload.clone());
+    }
+
+    // Build the artifacts
+    let miralis = build_target(Target::Miralis, &cfg);
+    let firmware_name = args
+        .firmware
+        .as_deref()
+        .or(cfg.target.firmware.name.as_deref())
+        .unwrap_or("default");
+    let Some(firmware) = prepare_firmware_artifact(firmware_name, &cfg) else {
+        log::error!("Invalid firmware '{}'", firmware_name);
+        return ExitCode::FAILURE;
+    };
+    let payload = cfg
+        .target
+        .payload
+        .as_ref()
+        .and_then(|payload| payload.name.as_deref())
+        .and_then(|name| prepare_payload_artifact(name, &cfg));
+
+    // Collect the symbol files, only artifacts built from sources have an ELF next to their image
+    let mode = cfg.target.miralis.profile.unwrap_or_default();
+    let mut miralis_elf = get_target_dir_path(&Target::Miralis, mode);
+    miralis_elf.push("miralis");
+    let mut symbols = Vec::new();
+    let firmware_addr = cfg
+        .target
+        .firmware
+        .start_address
+        .unwrap_or(DEFAULT_FIRMWARE_ADDR);
+    symbols.extend(elf_symbols(&firmware, firmware_addr));
+    if let Some(payload) = &payload {
+        let payload_addr = cfg
+            .target
+            .payload
+            .as_ref()
+            .and_then(|payload| payload.start_address)
+            .unwrap_or(DEFAULT_PAYLOAD_ADDR);
+        symbols.extend(elf_symbols(payload, payload_addr));
+    }
+
+    // Stop on firmware breakpoints when the debug stub is enabled
+    let mut breakpoints = args.breakpoints.clone();
+    if cfg.debug.stub == Some(true) {
+        breakpoints.push(FIRMWARE_BREAKPOINT_SYMBOL.to_string());
+    }
+
+    let mut script_path = get_workspace_path();
+    script_path.push("target");
+    script_path.push("miralis-debug.gdb");
+    if let Err(err) = fs::write(
+        &script_path,
+        gdb_script(&miralis_elf, &symbols, &breakpoints),
+    ) {
+        log::error!("Failed to write '{}': {}", script_path.display(), err);
+        return ExitCode::FAILURE;
+    }
+
+    // Start QEMU, stopped until GDB connects
+    let Ok(mut qemu_cmd) = get_qemu_cmd(&cfg, miralis, firmware, None, true, true) else {
+        log::error!("Failed to build command");
+        return ExitCode::FAILURE;
+    };
+    let mut log_path = get_workspace_path();
+    log_path.push("target");
+    log_path.push("miralis-debug.log");
+    let log_file = File::create(&log_path).expect("Failed to create QEMU log file");
+    qemu_cmd
+        .stdin(Stdio::null())
+        .stdout(log_file.try_clone().expect("Failed to clone QEMU log file"))
+        .stderr(log_file);
+    // Keep QEMU out of the terminal's process group, so that Ctrl-C only interrupts GDB
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::process::CommandExt;
+        qemu_cmd.process_group(0);
+    }
+    let mut qemu = match qemu_cmd.spawn() {
+        Ok(qemu) => qemu,
+        Err(err) => {
+            log::error!("Failed to start QEMU: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    log::info!("QEMU output is written to '{}'", log_path.display());
+
+    let status = run_gdb(&script_path);
+
+    qemu.kill().ok();
+    qemu.wait().ok();
+    match status {
+        Some(true) => ExitCode::SUCCESS,
+        Some(false) => ExitCode::FAILURE,
+        None => {
+            log::error!("Could not find a GDB binary with RISC-V support, try installing one of:");
+            for gdb in GDB_EXECUTABLES {
+                log::error!("  - {}", gdb);
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Runs the first available GDB with the provided script, returns None if none is installed.
+fn run_gdb(script: &Path) -> Option<bool> {
+    for gdb in GDB_EXECUTABLES {
+        let status = Command::new(gdb)
+            .arg("-q")
+            .arg("-x")
+            .arg(script)
+            .stdin(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .status();
+        match status {
+            Ok(status) => return Some(status.success()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => panic!("Failed to run GDB: {:?}", err),
+        }
+    }
+    None
+}
+
+/// Returns the ELF file of an image and its load address, if available.
+fn elf_symbols(image: &Path, addr: usize) -> Option<(PathBuf, usize)> {
+    let elf = image.with_extension("");
+    if image.extension().is_some_and(|ext| ext == "img") && elf.is_file() {
+        Some((elf, addr))
+    } else {
+        log::warn!("No symbols for '{}'", image.display());
+        None
+    }
+}
+
+/// Generates the GDB script, loading the symbol files before connecting to QEMU and setting the
+/// breakpoints once connected.
+fn gdb_script(miralis_elf: &Path, symbols: &[(PathBuf, usize)], breakpoints: &[String]) -> String {
+    let mut script = String::from("# Generated by `runner debug`\n");
+    script.push_str(&format!("file {}\n", miralis_elf.display()));
+    for (elf, addr) in symbols {
+        script.push_str(&format!("add-symbol-file {} 0x{:x}\n", elf.display(), addr));
+    }
+    script.push_str("source ./misc/setup.gdb\n");
+    for breakpoint in breakpoints {
+        script.push_str(&format!("break {}\n", breakpoint));
+    }
+    script
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_gdb_script() {
+        let script = gdb_script(
+            Path::new("target/miralis"),
+            &[
+                (PathBuf::from("target/firmware/default"), 0x80200000),
+                (PathBuf::from("target/payload/hello_world"), 0x80400000),
+            ],
+            &[String::from("miralis::handle_trap")],
+        );
+        assert_eq!(
+            script,
+            "# Generated by `runner debug`\n\
+             file target/miralis\n\
+             add-symbol-file target/firmware/default 0x80200000\n\
+             add-symbol-file target/payload/hello_world 0x80400000\n\
+             source ./misc/setup.gdb\n\
+             break miralis::handle_trap\n"
+        );
+    }
+}
@@ -1,25 +1,31 @@
 //! GDB subcommand
 //!
-//! The gdb subcommand launches a GDB session and attach it to a running Miralis instance.
+//! The gdb subcommand launches a GDB session and attach it to a running Miralis instance. With
+//! `--launch` it first starts Miralis in QEMU, waiting for the debugger, and loads the symbols of
+//! all the components (see the debug subcommand).
 
 use core::panic;
 use std::io;
-use std::process::{exit, Command, Stdio};
+use std::process::{exit, Command, ExitCode, Stdio};
 
 use crate::artifacts::Target;
 use crate::config::{read_config, Profiles};
+use crate::debug::debug;
 use crate::path::get_target_dir_path;
-use crate::GdbArgs;
+use crate::{DebugArgs, GdbArgs};
 
 // ——————————————————————————————— Constants ———————————————————————————————— //
 
 /// A list of GDB executables that support RISC-V 64
-static GDB_EXECUTABLES: &[&str] = &[
+pub static GDB_EXECUTABLES: &[&str] = &[
     "gdb-multiarch",
     "riscv64-elf-gdb",
     "riscv64-unknown-linux-gnu-gdb",
 ];
 
+/// Breakpoints set with `--launch` when none are provided.
+static DEFAULT_BREAKPOINTS: &[&str] = &["miralis::handle_trap"];
+
 // —————————————————————————————————— GDB ——————————————————————————————————— //
 
 /// Build a command to invoke GDB using the provided executable.
@@ -44,7 +50,11 @@ fn build_gdb_command(gdb_executable: &str, mode: Profiles) -> Command {
 }
 
 /// Start a GDB session
-pub fn gdb(args: &GdbArgs) -> ! {
+pub fn gdb(args: &GdbArgs) -> ExitCode {
+    if args.launch {
+        return launch(args);
+    }
+
     let cfg = read_config(&args.config);
     let mode = cfg.target.miralis.profile.unwrap_or_default();
 
@@ -91,3 +101,19 @@ pub fn gdb(args: &GdbArgs) -> ! {
     // Exit with non-zero exit code
     exit(1);
 }
+
+/// Start Miralis in QEMU and attach a GDB session to it.
+fn launch(args: &GdbArgs) -> ExitCode {
+    let breakpoints = if args.breakpoints.is_empty() {
+        DEFAULT_BREAKPOINTS.iter().map(|b| b.to_string()).collect()
+    } else {
+        args.breakpoints.clone()
+    };
+
+    debug(&DebugArgs {
+        firmware: args.firmware.clone(),
+        payload: None,
+        config: args.config.clone(),
+        breakpoints,
+    })
+}
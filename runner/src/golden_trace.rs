@@ -0,0 +1,389 @@
+//! Golden trace subcommand
+//!
+//! Runs the same firmware on bare QEMU and under Miralis, extracts the architectural event traces
+//! from the console output of both runs, and compares them. Any difference is a virtualization
+//! transparency violation (or an intentional deviation that must be documented).
+//!
+//! Firmware emit their trace with the `test_helpers::trace` module, which prints each event on its
+//! own line prefixed by `[trace]`. The exit code of QEMU is also part of the trace.
+//!
+//! Measurement events (such as latencies) are not compared, they are reported for both runs
+//! instead. This lets benchmark firmware compare their performance with and without Miralis.
+//!
+//! Some CSR bits legitimately differ under Miralis, those are listed in [EXPECTED_DIFFERENCES] and
+//! ignored when comparing CSR events.
+
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::{Command, ExitCode, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::artifacts::{build_target, prepare_firmware_artifact, Target};
+use crate::config::{read_config, Config};
+use crate::run::{QEMU, QEMU_ARGS};
+use crate::GoldenTraceArgs;
+
+/// Prefix of the trace lines, must match `test_helpers::trace::TRACE_PREFIX`.
+const TRACE_PREFIX: &str = "[trace]";
+/// Name of the measurement events, must match `test_helpers::trace::MEASURE_EVENT`.
+const MEASURE_EVENT: &str = "measure";
+/// Name of the CSR events, must match `test_helpers::trace::csr`.
+const CSR_EVENT: &str = "csr";
+
+/// Bits of the CSRs which legitimately differ under Miralis, by CSR name. Only the other bits are
+/// compared.
+const EXPECTED_DIFFERENCES: &[(&str, u64)] = &[
+    // Miralis disables the C and V extensions (see `misa::DISABLED`)
+    ("misa", (1 << 2) | (1 << 21)),
+    // Miralis does not model the read-only zero bits of medeleg yet, only the exceptions it can
+    // delegate are compared
+    (
+        "medeleg",
+        !(0x3ff | (1 << 12) | (1 << 13) | (1 << 15) | (0xf << 20)),
+    ),
+    // pmpaddr holds bits 55:2 of the address, Miralis clears the upper bits which QEMU keeps
+    ("pmpaddr0", 0xffc0_0000_0000_0000),
+];
+
+/// Address at which bare QEMU loads and starts the firmware.
+const BARE_FIRMWARE_ADDR: usize = 0x80000000;
+
+/// Address at which Miralis expects the firmware.
+const MIRALIS_FIRMWARE_ADDR: usize = 0x80200000;
+
+/// Default timeout of each run, in seconds.
+const DEFAULT_TIMEOUT: u64 = 10;
+
+/// The trace of a single run.
+#[derive(Debug, PartialEq, Eq)]
+struct Trace {
+    events: Vec<String>,
+    /// The name and value of the measurements.
+    measurements: Vec<(String, u64)>,
+    exit_code: Option<i32>,
+}
+
+// —————————————————————————————— Golden Trace —————————————————————————————— //
+
+/// The golden-trace command, compares the traces of a firmware with and without Miralis.
+pub fn golden_trace(args: &GoldenTraceArgs) -> ExitCode {
+    let timeout = Duration::from_secs(args.timeout.unwrap_or(DEFAULT_TIMEOUT));
+
+    // The firmware must be linked at a different address when running without Miralis, so we
+    // build it twice and keep a copy of the bare build.
+    let mut bare_cfg = read_config(&args.config);
+    bare_cfg.target.firmware.start_address = Some(BARE_FIRMWARE_ADDR);
+    let Some(bare_firmware) = prepare_firmware_artifact(&args.firmware, &bare_cfg) else {
+        log::error!("Invalid firmware '{}'", args.firmware);
+        return ExitCode::FAILURE;
+    };
+    let bare_firmware = keep_copy(bare_firmware, "bare");
+
+    let mut cfg = read_config(&args.config);
+    cfg.target.firmware.start_address = Some(MIRALIS_FIRMWARE_ADDR);
+    let miralis = build_target(Target::Miralis, &cfg);
+    let Some(firmware) = prepare_firmware_artifact(&args.firmware, &cfg) else {
+        log::error!("Invalid firmware '{}'", args.firmware);
+        return ExitCode::FAILURE;
+    };
+
+    // Run both configurations
+    let mut bare_cmd = get_qemu_cmd(&cfg);
+    bare_cmd.arg("-bios").arg(&bare_firmware);
+    let mut miralis_cmd = get_qemu_cmd(&cfg);
+    miralis_cmd
+        .arg("-bios")
+        .arg(&miralis)
+        .arg("-device")
+        .arg(format!(
+            "loader,file={},addr=0x{:x},force-raw=on",
+            firmware.display(),
+            MIRALIS_FIRMWARE_ADDR
+        ));
+
+    log::info!("Running '{}' on bare QEMU", args.firmware);
+    let Some(golden) = run_and_trace(bare_cmd, timeout) else {
+        return ExitCode::FAILURE;
+    };
+    log::info!("Running '{}' under Miralis", args.firmware);
+    let Some(trace) = run_and_trace(miralis_cmd, timeout) else {
+        return ExitCode::FAILURE;
+    };
+
+    if golden.events.is_empty() && golden.measurements.is_empty() {
+        log::warn!("The firmware did not emit any trace event");
+    }
+    report_measurements(&golden, &trace);
+
+    let differences = diff(&golden, &trace);
+    if differences.is_empty() {
+        log::info!(
+            "Traces match ({} events, exit code {:?})",
+            golden.events.len(),
+            golden.exit_code
+        );
+        return ExitCode::SUCCESS;
+    }
+
+    log::error!("Traces differ:");
+    for difference in &differences {
+        log::error!("  {}", difference);
+    }
+    ExitCode::FAILURE
+}
+
+fn get_qemu_cmd(cfg: &Config) -> Command {
+    let mut qemu_cmd = Command::new(QEMU);
+    qemu_cmd.args(QEMU_ARGS);
+    if let Some(cpu) = &cfg.qemu.cpu {
+        qemu_cmd.arg("-cpu").arg(cpu);
+    }
+    if let Some(memory) = &cfg.qemu.memory {
+        qemu_cmd.arg("-m").arg(memory);
+    }
+    if let Some(nb_harts) = cfg.platform.nb_harts {
+        qemu_cmd.arg("-smp").arg(format!("{}", nb_harts));
+    }
+    qemu_cmd
+}
+
+/// Copies a binary artifact, such that it is not overwritten by the next build.
+fn keep_copy(path: PathBuf, suffix: &str) -> PathBuf {
+    let mut copy = path.clone();
+    copy.set_extension(format!("{}.img", suffix));
+    fs::copy(&path, &copy).expect("Failed to copy firmware artifact");
+    copy
+}
+
+/// Runs the command until it exits or the timeout expires, and returns its trace.
+fn run_and_trace(mut cmd: Command, timeout: Duration) -> Option<Trace> {
+    log::debug!("{:?}", cmd);
+    let mut child = match cmd.stdout(Stdio::piped()).stderr(Stdio::null()).spawn() {
+        Ok(child) => child,
+        Err(err) => {
+            log::error!("Failed to start QEMU: {}", err);
+            return None;
+        }
+    };
+
+    // Read the output concurrently, QEMU would block on a full pipe otherwise
+    let mut stdout = child.stdout.take().expect("Missing QEMU stdout");
+    let reader = std::thread::spawn(move || {
+        let mut output = String::new();
+        stdout.read_to_string(&mut output).ok();
+        output
+    });
+
+    let start = Instant::now();
+    let exit_code = loop {
+        if let Some(status) = child.try_wait().expect("Failed to wait on QEMU") {
+            break status.code();
+        }
+        if start.elapsed() > timeout {
+            log::warn!("Run timed out after {:?}", timeout);
+            child.kill().ok();
+            child.wait().ok();
+            break None;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+
+    let output = reader.join().unwrap_or_default();
+    Some(parse_trace(&output, exit_code))
+}
+
+/// Extracts the trace events from the console output.
+fn parse_trace(output: &str, exit_code: Option<i32>) -> Trace {
+    let mut events = Vec::new();
+    let mut measurements = Vec::new();
+    for (_, event) in output
+        .lines()
+        .filter_map(|line| line.split_once(TRACE_PREFIX))
+    {
+        let event = event.trim();
+        match parse_measurement(event) {
+            Some(measurement) => measurements.push(measurement),
+            None => events.push(event.to_string()),
+        }
+    }
+    Trace {
+        events,
+        measurements,
+        exit_code,
+    }
+}
+
+/// Parses a measurement event, formatted as `measure <name> <value>`.
+fn parse_measurement(event: &str) -> Option<(String, u64)> {
+    let mut words = event.split_whitespace();
+    if words.next() != Some(MEASURE_EVENT) {
+        return None;
+    }
+    let name = words.next()?;
+    let value = words.next()?.parse().ok()?;
+    Some((name.to_string(), value))
+}
+
+/// Prints the measurements of both runs, and the overhead of Miralis.
+fn report_measurements(golden: &Trace, trace: &Trace) {
+    if golden.measurements.is_empty() {
+        return;
+    }
+
+    log::info!(
+        "{:<30} {:>12} {:>12} {:>9}",
+        "measurement",
+        "bare",
+        "miralis",
+        "overhead"
+    );
+    for (name, bare) in &golden.measurements {
+        let Some((_, value)) = trace.measurements.iter().find(|(other, _)| other == name) else {
+            log::warn!("Missing measurement '{}' under Miralis", name);
+            continue;
+        };
+        let overhead = if *bare == 0 {
+            String::from("-")
+        } else {
+            format!("x{:.2}", *value as f64 / *bare as f64)
+        };
+        log::info!("{:<30} {:>12} {:>12} {:>9}", name, bare, value, overhead);
+    }
+}
+
+/// Returns a description of each difference between the golden trace and the trace.
+fn diff(golden: &Trace, trace: &Trace) -> Vec<String> {
+    let mut differences = Vec::new();
+    let len = golden.events.len().max(trace.events.len());
+    for idx in 0..len {
+        match (golden.events.get(idx), trace.events.get(idx)) {
+            (Some(expected), Some(found)) if events_match(expected, found) => {}
+            (Some(expected), Some(found)) => differences.push(format!(
+                "event {}: expected '{}', found '{}'",
+                idx, expected, found
+            )),
+            (Some(expected), None) => {
+                differences.push(format!("event {}: missing '{}'", idx, expected))
+            }
+            (None, Some(found)) => {
+                differences.push(format!("event {}: unexpected '{}'", idx, found))
+            }
+            (None, None) => unreachable!(),
+        }
+    }
+    if golden.exit_code != trace.exit_code {
+        differences.push(format!(
+            "exit code: expected {:?}, found {:?}",
+            golden.exit_code, trace.exit_code
+        ));
+    }
+    differences
+}
+
+/// Returns true if the events are equal, up to the expected differences of CSR values.
+fn events_match(expected: &str, found: &str) -> bool {
+    if expected == found {
+        return true;
+    }
+    let (Some((csr, expected)), Some((other_csr, found))) =
+        (parse_csr_event(expected), parse_csr_event(found))
+    else {
+        return false;
+    };
+    let Some((_, mask)) = EXPECTED_DIFFERENCES.iter().find(|(name, _)| *name == csr) else {
+        return false;
+    };
+    csr == other_csr && expected & !mask == found & !mask
+}
+
+/// Parses a CSR event, formatted as `csr <name> 0x<value>`.
+fn parse_csr_event(event: &str) -> Option<(&str, u64)> {
+    let mut words = event.split_whitespace();
+    if words.next() != Some(CSR_EVENT) {
+        return None;
+    }
+    let name = words.next()?;
+    let value = u64::from_str_radix(words.next()?.strip_prefix("0x")?, 16).ok()?;
+    Some((name, value))
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_trace_events() {
+        let output = "[INFO] Hello, world!\n[trace] csr misa 0x8000\n\x1b[32m[trace] exit 0\n";
+        let trace = parse_trace(output, Some(0));
+        assert_eq!(trace.events, vec!["csr misa 0x8000", "exit 0"]);
+        assert_eq!(trace.exit_code, Some(0));
+    }
+
+    #[test]
+    fn extract_measurements() {
+        let output = "[trace] measure ipi_round_trip_p50 1200
+[trace] measure bad
+[trace] exit 0
+";
+        let trace = parse_trace(output, Some(0));
+        assert_eq!(
+            trace.measurements,
+            vec![(String::from("ipi_round_trip_p50"), 1200)]
+        );
+        // Malformed measurements are compared as regular events
+        assert_eq!(trace.events, vec!["measure bad", "exit 0"]);
+
+        // Measurements are not compared
+        let other = parse_trace(
+            "[trace] measure ipi_round_trip_p50 5000
+[trace] measure bad
+[trace] exit 0
+",
+            Some(0),
+        );
+        assert!(diff(&trace, &other).is_empty());
+    }
+
+    #[test]
+    fn diff_traces() {
+        let golden = parse_trace("[trace] a\n[trace] b\n", Some(0));
+        assert!(diff(&golden, &parse_trace("[trace] a\n[trace] b\n", Some(0))).is_empty());
+        assert_eq!(
+            diff(&golden, &parse_trace("[trace] a\n[trace] c\n", Some(0))),
+            vec!["event 1: expected 'b', found 'c'"]
+        );
+        assert_eq!(
+            diff(&golden, &parse_trace("[trace] a\n", Some(1))),
+            vec![
+                "event 1: missing 'b'",
+                "exit code: expected Some(0), found Some(1)"
+            ]
+        );
+    }
+
+    #[test]
+    fn expected_csr_differences() {
+        // Miralis disables the C extension
+        assert!(events_match(
+            "csr misa 0x800000000014112d",
+            "csr misa 0x8000000000141129"
+        ));
+        // Other bits must match
+        assert!(!events_match(
+            "csr misa 0x800000000014112d",
+            "csr misa 0x800000000014112c"
+        ));
+        assert!(!events_match(
+            "csr mscratch 0xdeadbeef",
+            "csr mscratch 0xdeadbeee"
+        ));
+        assert!(!events_match("csr misa 0x4", "csr pmpaddr0 0x0"));
+        assert!(events_match(
+            "csr pmpaddr0 0xffffffffffffffff",
+            "csr pmpaddr0 0x3fffffffffffff"
+        ));
+    }
+}
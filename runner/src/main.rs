@@ -9,14 +9,22 @@ use log::LevelFilter;
 use crate::logger::RunnerLogger;
 
 mod artifacts;
+mod benchmark;
 mod build;
 mod config;
+mod coverage;
+mod debug;
 mod gdb;
+mod golden_trace;
 mod logger;
 mod path;
 mod project;
+mod record;
 mod run;
+mod size;
 mod test;
+mod trace;
+mod trap_log;
 
 // —————————————————————————————— CLI Parsing ——————————————————————————————— //
 
@@ -40,10 +48,24 @@ enum Subcommands {
     Test(TestArgs),
     /// Exit with an error if the config is not valid
     CheckConfig(CheckConfigArgs),
-    /// Start GDB and connect to a running instance
+    /// Start GDB and connect to a running instance, or launch one with `--launch`
     Gdb(GdbArgs),
+    /// Run Miralis on QEMU and attach GDB, with the symbols of all the components
+    Debug(DebugArgs),
     /// List the artifacts
     Artifact(ArtifactArgs),
+    /// Run a benchmark firmware multiple times and report aggregated statistics
+    Benchmark(BenchmarkArgs),
+    /// Compare the traces of a firmware on bare QEMU and under Miralis
+    GoldenTrace(GoldenTraceArgs),
+    /// Report the emulation paths not reached by the tests
+    Coverage(CoverageArgs),
+    /// Report the binary size of Miralis by crate and module
+    Size(SizeArgs),
+    /// Convert a dump of the trace buffer for Perfetto
+    Trace(TraceArgs),
+    /// Decode the trap log dumped on the console into a timeline
+    TrapLog(TrapLogArgs),
 }
 
 #[derive(Args)]
@@ -65,6 +87,9 @@ struct RunArgs {
     /// An optional disk we can bind to qemu
     #[arg(long)]
     disk: Option<String>,
+    /// Record the console output, config, and command line in `target/runs`
+    #[arg(long, action)]
+    record: bool,
 }
 
 #[derive(Args)]
@@ -97,6 +122,99 @@ struct GdbArgs {
     #[arg(long)]
     /// Path to the configuration file to use
     config: Option<PathBuf>,
+    #[arg(long, action)]
+    /// Start Miralis in QEMU and attach to it, instead of connecting to a running instance
+    launch: bool,
+    #[arg(short, long, requires = "launch")]
+    firmware: Option<String>,
+    #[arg(short, long = "break", requires = "launch")]
+    /// Symbols to break on, `miralis::handle_trap` if none
+    breakpoints: Vec<String>,
+}
+
+#[derive(Args)]
+struct DebugArgs {
+    #[arg(short, long)]
+    firmware: Option<String>,
+    #[arg(short, long)]
+    /// Payload to load, overrides the config
+    payload: Option<String>,
+    #[arg(long)]
+    /// Path to the configuration file to use
+    config: Option<PathBuf>,
+    #[arg(short, long = "break")]
+    /// Symbols to break on
+    breakpoints: Vec<String>,
+}
+
+#[derive(Args)]
+struct BenchmarkArgs {
+    #[arg(short, long)]
+    firmware: Option<String>,
+    #[arg(long)]
+    /// Path to the configuration file to use
+    config: Option<PathBuf>,
+    #[arg(long)]
+    /// Number of measured runs, overrides the config
+    runs: Option<usize>,
+    #[arg(long)]
+    /// Number of discarded warmup runs, overrides the config
+    warmup: Option<usize>,
+    #[arg(short, long)]
+    /// Maximum number of parallel runs, overrides the config
+    jobs: Option<usize>,
+    #[arg(long)]
+    /// Directory where the percentiles of the latency histograms are written, as csv
+    percentiles: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct GoldenTraceArgs {
+    /// The firmware to run, must emit its trace with `test_helpers::trace`
+    firmware: String,
+    #[arg(long)]
+    /// Path to the configuration file to use
+    config: Option<PathBuf>,
+    #[arg(long)]
+    /// Timeout of each run, in seconds
+    timeout: Option<u64>,
+}
+
+#[derive(Args)]
+struct CoverageArgs {
+    /// Prefix of the tests to run, all if none
+    pattern: Option<String>,
+}
+
+#[derive(Args)]
+struct SizeArgs {
+    #[arg(long)]
+    /// Path to the configuration file to use
+    config: Option<PathBuf>,
+    /// Append the report to the size history in `target/size`
+    #[arg(long, action)]
+    record: bool,
+}
+
+#[derive(Args)]
+struct TraceArgs {
+    /// Path to the dump of the trace buffer
+    dump: PathBuf,
+    #[arg(long)]
+    /// Path to the output file, the trace is printed if none
+    output: Option<PathBuf>,
+    #[arg(long)]
+    /// Frequency of the cycle counter in Hz, timestamps are reported in cycles if none
+    frequency: Option<u64>,
+}
+
+#[derive(Args)]
+struct TrapLogArgs {
+    /// Path to the console output containing the dump of the trap log
+    log: PathBuf,
+    #[arg(long)]
+    /// Frequency of the cycle counter in Hz, times are reported in cycles if none
+    frequency: Option<u64>,
 }
 
 #[derive(Args)]
@@ -137,8 +255,15 @@ fn main() -> ExitCode {
         Subcommands::Build(args) => build::build(&args),
         Subcommands::Test(args) => test::run_tests(&args),
         Subcommands::Gdb(args) => gdb::gdb(&args),
+        Subcommands::Debug(args) => debug::debug(&args),
         Subcommands::CheckConfig(args) => config::check_config(&args),
         Subcommands::Artifact(args) => artifacts::list_artifacts(&args),
+        Subcommands::Benchmark(args) => benchmark::benchmark(&args),
+        Subcommands::GoldenTrace(args) => golden_trace::golden_trace(&args),
+        Subcommands::Coverage(args) => coverage::coverage(&args),
+        Subcommands::Size(args) => size::size(&args),
+        Subcommands::Trace(args) => trace::trace(&args),
+        Subcommands::TrapLog(args) => trap_log::trap_log(&args),
     }
 }
 
@@ -1,10 +1,13 @@
 //! Global project configuration
 
+use std::fs;
 use std::path::PathBuf;
 
 use indexmap::IndexMap;
 use serde::Deserialize;
 
+use crate::path::get_project_config_path;
+
 /// The global project configuration file
 #[derive(Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
@@ -31,3 +34,23 @@ pub struct Test {
     pub firmware: Option<String>,
     pub payload: Option<String>,
 }
+
+/// Reads and parses the global project configuration, logging the error on failure.
+pub fn read_project_config() -> Option<ProjectConfig> {
+    let path = get_project_config_path();
+    let config = match fs::read_to_string(&path) {
+        Ok(config) => config,
+        Err(_) => {
+            log::error!("Could not read '{}'", &path.display());
+            return None;
+        }
+    };
+
+    match toml::from_str::<ProjectConfig>(&config) {
+        Ok(config) => Some(config),
+        Err(err) => {
+            log::error!("Failed to parse configuration:\n{}", err.message());
+            None
+        }
+    }
+}
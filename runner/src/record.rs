@@ -0,0 +1,233 @@
+//! Run recording
+//!
+//! When recording is enabled the run subcommand creates a new directory under `target/runs` for
+//! each run, containing:
+//!
+//! - `serial.log`: the console output, each line prefixed by the time elapsed since the start of
+//!   the run.
+//! - `command.txt`: the command line used to start the simulator.
+//! - `config.toml`: a copy of the configuration file, if any, and `config.txt` the configuration
+//!   after includes and command line overrides have been applied.
+//! - `revision.txt`: the git revision of the repository, and whether the tree was dirty.
+//! - `status.txt`: the exit status of the simulator.
+//!
+//! This way benchmark and failure runs can be kept and compared later on.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+use crate::path::get_workspace_path;
+
+// ——————————————————————————————— Recorder ————————————————————————————————— //
+
+/// A recorder for a single run.
+pub struct Recorder {
+    dir: PathBuf,
+}
+
+impl Recorder {
+    /// Creates a fresh directory for the run, named after the current time and the firmware.
+    pub fn new(firmware: &str) -> io::Result<Self> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or(0);
+        let name = sanitize(firmware);
+
+        let mut dir = get_workspace_path();
+        dir.push("target");
+        dir.push("runs");
+        fs::create_dir_all(&dir)?;
+
+        // Several runs can start within the same second, pick the first free name
+        let mut suffix = 0;
+        loop {
+            let mut candidate = dir.clone();
+            if suffix == 0 {
+                candidate.push(format!("{}-{}", timestamp, name));
+            } else {
+                candidate.push(format!("{}-{}-{}", timestamp, name, suffix));
+            }
+            match fs::create_dir(&candidate) {
+                Ok(()) => return Ok(Recorder { dir: candidate }),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => suffix += 1,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Returns the directory of the run.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Records the configuration, command line, and git revision of the run.
+    pub fn record_context(
+        &self,
+        config_path: Option<&Path>,
+        cfg: &Config,
+        cmd: &Command,
+    ) -> io::Result<()> {
+        if let Some(config_path) = config_path.filter(|path| path.is_file()) {
+            fs::copy(config_path, self.dir.join("config.toml"))?;
+        }
+        fs::write(self.dir.join("config.txt"), format!("{:#?}\n", cfg))?;
+        fs::write(self.dir.join("command.txt"), command_line(cmd) + "\n")?;
+        fs::write(self.dir.join("revision.txt"), git_revision())?;
+        Ok(())
+    }
+
+    /// Runs the command, forwarding its standard output to the terminal and to the serial log.
+    pub fn run(&self, cmd: &mut Command) -> io::Result<ExitStatus> {
+        let mut log = File::create(self.dir.join("serial.log"))?;
+        let mut child = cmd.stdout(Stdio::piped()).spawn()?;
+        let mut output = child.stdout.take().expect("Missing standard output");
+
+        let start = Instant::now();
+        let mut stamper = LineStamper::new();
+        let mut stdout = io::stdout();
+        let mut buffer = [0; 4096];
+        loop {
+            let n = match output.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            stdout.write_all(&buffer[..n])?;
+            stdout.flush()?;
+            log.write_all(&stamper.stamp(&buffer[..n], start.elapsed()))?;
+        }
+
+        let status = child.wait()?;
+        fs::write(self.dir.join("status.txt"), format!("{}\n", status))?;
+        Ok(status)
+    }
+}
+
+// ———————————————————————————————— Helpers ————————————————————————————————— //
+
+/// Prefixes each line of a byte stream with a timestamp.
+///
+/// The output is processed in chunks, which might end in the middle of a line, so the stamper
+/// keeps track of whether the next byte starts a new line.
+struct LineStamper {
+    at_line_start: bool,
+}
+
+impl LineStamper {
+    fn new() -> Self {
+        LineStamper {
+            at_line_start: true,
+        }
+    }
+
+    /// Returns the chunk, with a timestamp inserted at the start of each line.
+    fn stamp(&mut self, chunk: &[u8], elapsed: Duration) -> Vec<u8> {
+        let mut stamped = Vec::with_capacity(chunk.len() + 16);
+        for &byte in chunk {
+            if self.at_line_start {
+                stamped.extend_from_slice(format_timestamp(elapsed).as_bytes());
+                self.at_line_start = false;
+            }
+            stamped.push(byte);
+            if byte == b'\n' {
+                self.at_line_start = true;
+            }
+        }
+        stamped
+    }
+}
+
+/// Formats a timestamp as seconds with microsecond precision, in the style of the Linux kernel.
+fn format_timestamp(elapsed: Duration) -> String {
+    format!("[{:>5}.{:06}] ", elapsed.as_secs(), elapsed.subsec_micros())
+}
+
+/// Returns the command as a single line, arguments are space separated.
+fn command_line(cmd: &Command) -> String {
+    let mut line = cmd.get_program().to_string_lossy().into_owned();
+    for arg in cmd.get_args() {
+        line.push(' ');
+        line.push_str(&arg.to_string_lossy());
+    }
+    line
+}
+
+/// Returns the current git revision, and whether the working tree has uncommitted changes.
+fn git_revision() -> String {
+    let Some(revision) = git(&["rev-parse", "HEAD"]) else {
+        return String::from("unknown\n");
+    };
+    format!("{}\ndirty={}\n", revision, is_dirty())
+}
+
+/// Returns the current abbreviated git revision, suffixed with `-dirty` if the working tree has
+/// uncommitted changes.
+pub fn short_revision() -> String {
+    let Some(revision) = git(&["rev-parse", "--short", "HEAD"]) else {
+        return String::from("unknown");
+    };
+    match is_dirty() {
+        true => format!("{}-dirty", revision),
+        false => revision,
+    }
+}
+
+fn is_dirty() -> bool {
+    git(&["status", "--porcelain"]).is_some_and(|status| !status.is_empty())
+}
+
+/// Runs a git command in the workspace, returning its output if it succeeded.
+fn git(args: &[&str]) -> Option<String> {
+    Command::new("git")
+        .args(args)
+        .current_dir(get_workspace_path())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// Replaces the characters that are not safe in a file name.
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamp_lines() {
+        let mut stamper = LineStamper::new();
+        let first = stamper.stamp(b"Hello\nWor", Duration::from_micros(1_500_000));
+        assert_eq!(first, b"[    1.500000] Hello\n[    1.500000] Wor");
+
+        // The line started in the previous chunk is not stamped again
+        let second = stamper.stamp(b"ld\r\n", Duration::from_secs(12));
+        assert_eq!(second, b"ld\r\n");
+        let third = stamper.stamp(b"!", Duration::from_secs(12));
+        assert_eq!(third, b"[   12.000000] !");
+    }
+
+    #[test]
+    fn sanitize_names() {
+        assert_eq!(sanitize("opensbi-jump"), "opensbi-jump");
+        assert_eq!(sanitize("../firmware/test.img"), "___firmware_test_img");
+    }
+}
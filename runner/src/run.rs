@@ -4,7 +4,8 @@
 //! images.
 
 use core::str;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::{Command, ExitCode};
 use std::str::FromStr;
 
@@ -13,6 +14,7 @@ use crate::artifacts::{
     prepare_payload_artifact, DiskArtifact, Target,
 };
 use crate::config::{read_config, Config, Platforms};
+use crate::record::Recorder;
 use crate::RunArgs;
 
 // ————————————————————————————— QEMU Arguments ————————————————————————————— //
@@ -26,7 +28,7 @@ pub const QEMU: &str = "/home/francois/Documents/ACE-RISCV/ace-build/qemu/bin/qe
 pub const SPIKE: &str = "spike";
 
 #[rustfmt::skip]
-const QEMU_ARGS: &[&str] = &[
+pub const QEMU_ARGS: &[&str] = &[
     "--no-reboot",
     "-nographic",
     "-machine", "virt",
@@ -37,6 +39,13 @@ const _FIRMWARE_ADDR: u64 = 0x80200000;
 /// Address at which the payload is loaded in memory.
 const PAYLOAD_ADDR: u64 = 0x80400000;
 
+/// Size of a flash bank of the QEMU virt board, images must fill the whole bank.
+const PFLASH_SIZE: usize = 0x2000000;
+
+/// Header of the firmware images stored in flash, see `src/platform/pflash.rs` in Miralis.
+const PFLASH_MAGIC: &[u8; 8] = b"MIRALIS\0";
+const PFLASH_IMAGE_OFFSET: usize = 0x100;
+
 // —————————————————————————————————— Run ——————————————————————————————————— //
 
 /// The run command, runs Miralis with the provided arguments.
@@ -52,6 +61,7 @@ pub fn run(args: &RunArgs) -> ExitCode {
     } else {
         "default"
     };
+    let firmware_name = firmware;
     log::info!("Running Miralis with '{}' firmware", firmware);
     let Some(firmware) = prepare_firmware_artifact(firmware, &cfg) else {
         return ExitCode::FAILURE;
@@ -60,10 +70,17 @@ pub fn run(args: &RunArgs) -> ExitCode {
     let cmd = match cfg.platform.name.unwrap_or(Platforms::QemuVirt) {
         Platforms::QemuVirt => get_qemu_cmd(&cfg, miralis, firmware, None, args.debug, args.stop),
         Platforms::Spike => get_spike_cmd(&cfg, miralis, firmware),
+        Platforms::SifiveU => {
+            get_qemu_sifive_u_cmd(&cfg, miralis, firmware, None, args.debug, args.stop)
+        }
         Platforms::VisionFive2 => {
             log::error!("We can't run VisionFive2 on simulator.");
             return ExitCode::FAILURE;
         }
+        Platforms::Unmatched => {
+            log::error!("We can't run the HiFive Unmatched on simulator.");
+            return ExitCode::FAILURE;
+        }
     };
     let Ok(mut cmd) = cmd else {
         log::error!("Failed to build command");
@@ -79,7 +96,23 @@ pub fn run(args: &RunArgs) -> ExitCode {
             .join(" ")
     );
 
-    let exit_status = cmd.status().expect("Failed to run");
+    let exit_status = if args.record {
+        let recorder = match Recorder::new(firmware_name) {
+            Ok(recorder) => recorder,
+            Err(err) => {
+                log::error!("Failed to create the run directory: {}", err);
+                return ExitCode::FAILURE;
+            }
+        };
+        if let Err(err) = recorder.record_context(args.config.as_deref(), &cfg, &cmd) {
+            log::error!("Failed to record the run: {}", err);
+            return ExitCode::FAILURE;
+        }
+        log::info!("Recording the run in '{}'", recorder.dir().display());
+        recorder.run(&mut cmd).expect("Failed to run")
+    } else {
+        cmd.status().expect("Failed to run")
+    };
 
     if !exit_status.success() {
         ExitCode::from(exit_status.code().unwrap_or(1) as u8)
@@ -184,6 +217,15 @@ pub fn get_qemu_cmd(
         ));
     }
 
+    // Place the firmware in the second flash bank, Miralis loads it from there on boot
+    if cfg.platform.firmware_pflash == Some(true) {
+        let image = prepare_pflash_image(&firmware)?;
+        qemu_cmd.arg("-drive").arg(format!(
+            "if=pflash,unit=1,format=raw,readonly=on,file={}",
+            image.to_str().unwrap()
+        ));
+    }
+
     // If a disk is present add the appropriate device
     if let Some(disk) = &cfg.qemu.disk {
         if let Some(DiskArtifact::Downloaded { name, url }) =
@@ -220,6 +262,103 @@ pub fn get_qemu_cmd(
     Ok(qemu_cmd)
 }
 
+/// Returns the command running Miralis on QEMU's `sifive_u` machine.
+///
+/// The machine emulates the FU540, with a monitor core (hart 0) and at least one application
+/// core. The firmware and the payload are loaded in memory by QEMU at their configured addresses.
+pub fn get_qemu_sifive_u_cmd(
+    cfg: &Config,
+    miralis: PathBuf,
+    firmware: PathBuf,
+    payload: Option<&String>,
+    debug: bool,
+    stop: bool,
+) -> Result<Command, ()> {
+    let mut qemu_cmd = Command::new(QEMU);
+    qemu_cmd
+        .args(["--no-reboot", "-nographic", "-machine", "sifive_u"])
+        .arg("-bios")
+        .arg(miralis)
+        .arg("-m")
+        .arg(cfg.qemu.memory.as_deref().unwrap_or("2048"));
+
+    let firmware_addr = cfg.target.firmware.start_address.unwrap_or(0x80200000);
+    qemu_cmd.arg("-device").arg(format!(
+        "loader,file={},addr=0x{:x},force-raw=on",
+        firmware.to_str().unwrap(),
+        firmware_addr
+    ));
+
+    let payload = payload.or_else(|| {
+        cfg.target
+            .payload
+            .as_ref()
+            .and_then(|payload| payload.name.as_ref())
+    });
+    if let Some(payload_name) = payload {
+        let Some(payload) = prepare_payload_artifact(payload_name, cfg) else {
+            log::error!("Invalid payload '{}'", payload_name);
+            return Err(());
+        };
+        qemu_cmd.arg("-device").arg(format!(
+            "loader,file={},addr=0x{:x},force-raw=on",
+            payload.to_str().unwrap(),
+            PAYLOAD_ADDR
+        ));
+    }
+
+    // The monitor core is always present, in addition to at least one application core
+    let nb_harts = cfg.platform.nb_harts.unwrap_or(2);
+    if nb_harts < 2 {
+        log::error!("The sifive_u machine has at least 2 harts");
+        return Err(());
+    }
+    qemu_cmd.arg("-smp").arg(format!("{}", nb_harts));
+    if debug {
+        qemu_cmd.arg("-s");
+    }
+    if stop {
+        qemu_cmd.arg("-S");
+    }
+
+    Ok(qemu_cmd)
+}
+
+/// Writes the flash image holding the firmware next to the firmware, returns its path.
+fn prepare_pflash_image(firmware: &Path) -> Result<PathBuf, ()> {
+    let Ok(image) = fs::read(firmware) else {
+        log::error!("Could not read firmware '{}'", firmware.display());
+        return Err(());
+    };
+    if PFLASH_IMAGE_OFFSET + image.len() > PFLASH_SIZE {
+        log::error!("Firmware '{}' does not fit in flash", firmware.display());
+        return Err(());
+    }
+
+    let flash = pflash_image(&image);
+    let path = firmware.with_extension("pflash");
+    if let Err(err) = fs::write(&path, flash) {
+        log::error!("Could not write flash image '{}': {}", path.display(), err);
+        return Err(());
+    }
+    Ok(path)
+}
+
+/// Returns the content of the flash: the header followed by the firmware image, padded with the
+/// value of erased flash.
+fn pflash_image(image: &[u8]) -> Vec<u8> {
+    let checksum = image.iter().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    });
+
+    let mut flash = vec![0xff; PFLASH_SIZE];
+    flash[..0x8].copy_from_slice(PFLASH_MAGIC);
+    flash[0x8..0x10].copy_from_slice(&(image.len() as u64).to_le_bytes());
+    flash[0x10..0x18].copy_from_slice(&checksum.to_le_bytes());
+    flash[PFLASH_IMAGE_OFFSET..PFLASH_IMAGE_OFFSET + image.len()].copy_from_slice(image);
+    flash
+}
+
 /// Return the command to run Miralis on Spike.
 pub fn get_spike_cmd(cfg: &Config, miralis: PathBuf, firmware: PathBuf) -> Result<Command, ()> {
     let mut spike_cmd = Command::new(SPIKE);
@@ -261,3 +400,22 @@ pub fn spike_is_available() -> bool {
 
     spike_cmd.status().is_ok()
 }
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pflash_image_header() {
+        let flash = pflash_image(b"a");
+        assert_eq!(flash.len(), PFLASH_SIZE);
+        assert_eq!(&flash[..0x8], PFLASH_MAGIC);
+        assert_eq!(flash[0x8..0x10], 1u64.to_le_bytes());
+        // Must match the checksum computed by Miralis
+        assert_eq!(flash[0x10..0x18], 0xaf63dc4c8601ec8cu64.to_le_bytes());
+        assert_eq!(flash[PFLASH_IMAGE_OFFSET], b'a');
+        assert_eq!(flash[PFLASH_IMAGE_OFFSET + 1], 0xff);
+    }
+}
@@ -0,0 +1,327 @@
+//! Size subcommand
+//!
+//! Miralis is part of the trusted computing base of the system, its size is therefore an important
+//! metric. This subcommand builds Miralis and breaks the size of the binary down by crate, and by
+//! module for Miralis itself, based on the symbol sizes reported by `rust-nm`.
+//!
+//! With `--record` the report is appended to `target/size/history.toml`, together with the git
+//! revision, and each report is compared against the last recorded one to track growth over time.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::artifacts::{build_target, Target};
+use crate::config::read_config;
+use crate::path::get_workspace_path;
+use crate::record::short_revision;
+use crate::SizeArgs;
+
+/// Name of the group of the symbols that do not belong to a crate (e.g. assembly symbols).
+const OTHER: &str = "[other]";
+
+// ———————————————————————————————— Symbols ————————————————————————————————— //
+
+/// A symbol of the Miralis binary, as reported by `rust-nm --print-size`.
+#[derive(Debug, PartialEq, Eq)]
+struct Symbol {
+    size: u64,
+    name: String,
+}
+
+/// Parses the output of `rust-nm --print-size --demangle`, ignoring the symbols without a size.
+fn parse_symbols(output: &str) -> Vec<Symbol> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.trim().splitn(4, ' ');
+            let (Some(_address), Some(size), Some(_kind), Some(name)) =
+                (parts.next(), parts.next(), parts.next(), parts.next())
+            else {
+                return None;
+            };
+            let size = u64::from_str_radix(size, 16).ok()?;
+            Some(Symbol {
+                size,
+                name: name.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Returns the path of the item defining the symbol, e.g. `miralis::virt::emulator` for
+/// `<miralis::virt::emulator::Foo as core::fmt::Debug>::fmt`.
+///
+/// For trait implementations the symbol is attributed to the implementing type, unless the type is
+/// generic (e.g. blanket implementations) in which case it is attributed to the trait.
+fn item_path(name: &str) -> Vec<&str> {
+    let trimmed = name.trim_start_matches(['<', '&', '*']);
+    let trimmed = trimmed
+        .strip_prefix("mut ")
+        .or_else(|| trimmed.strip_prefix("const "))
+        .unwrap_or(trimmed);
+    let end = trimmed.find([' ', '<', '>', '(']).unwrap_or(trimmed.len());
+    let path: Vec<&str> = trimmed[..end]
+        .split("::")
+        .filter(|segment| !segment.is_empty() && !is_hash(segment))
+        .collect();
+    match (path.len(), trimmed.split_once(" as ")) {
+        (1, Some((_, trait_path))) => item_path(trait_path),
+        _ => path,
+    }
+}
+
+/// Returns true if the path segment is the hash appended to legacy mangled symbols.
+fn is_hash(segment: &str) -> bool {
+    segment.len() == 17
+        && segment.starts_with('h')
+        && segment[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Returns the crate of the symbol, or [OTHER] if the symbol is not a Rust path.
+fn crate_of(name: &str) -> String {
+    match item_path(name).as_slice() {
+        [krate, _, ..] => krate.to_string(),
+        _ => OTHER.to_string(),
+    }
+}
+
+/// Returns the top-level module of a Miralis symbol, or `None` for other crates.
+fn miralis_module_of(name: &str) -> Option<String> {
+    match item_path(name).as_slice() {
+        ["miralis", module, _, ..] => Some(format!("miralis::{}", module)),
+        ["miralis", _] => Some(String::from("miralis")),
+        _ => None,
+    }
+}
+
+/// Sums the size of the symbols, grouped by the provided key.
+fn group_by(symbols: &[Symbol], key: impl Fn(&str) -> Option<String>) -> BTreeMap<String, u64> {
+    let mut groups = BTreeMap::new();
+    for symbol in symbols {
+        if let Some(key) = key(&symbol.name) {
+            *groups.entry(key).or_default() += symbol.size;
+        }
+    }
+    groups
+}
+
+// —————————————————————————————— Size Report ——————————————————————————————— //
+
+/// The size of a Miralis build, in bytes.
+#[derive(Debug, Serialize, Deserialize)]
+struct SizeReport {
+    revision: String,
+    timestamp: u64,
+    /// Size of the raw binary image.
+    image: u64,
+    crates: BTreeMap<String, u64>,
+    modules: BTreeMap<String, u64>,
+}
+
+/// The size subcommand, builds Miralis and reports the size of its binary.
+pub fn size(args: &SizeArgs) -> ExitCode {
+    let cfg = read_config(&args.config);
+    let image_path = build_target(Target::Miralis, &cfg);
+    let elf_path = image_path.with_extension("");
+
+    let image = match fs::metadata(&image_path) {
+        Ok(metadata) => metadata.len(),
+        Err(err) => {
+            log::error!("Could not read '{}': {}", image_path.display(), err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let output = match Command::new("rust-nm")
+        .arg("--print-size")
+        .arg("--demangle")
+        .arg(&elf_path)
+        .output()
+    {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            log::error!(
+                "rust-nm failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+            return ExitCode::FAILURE;
+        }
+        Err(err) => {
+            log::error!("Failed to run rust-nm: {}. Is `rust-nm` installed?", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let symbols = parse_symbols(&String::from_utf8_lossy(&output.stdout));
+    let report = SizeReport {
+        revision: short_revision(),
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or(0),
+        image,
+        crates: group_by(&symbols, |name| Some(crate_of(name))),
+        modules: group_by(&symbols, miralis_module_of),
+    };
+
+    let history_path = get_history_path();
+    let mut history = match read_history(&history_path) {
+        Ok(history) => history,
+        Err(err) => {
+            log::error!("Invalid size history: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let previous = history.reports.last();
+    if let Some(previous) = previous {
+        log::info!("Compared to revision {}", previous.revision);
+    }
+
+    log::info!(
+        "Image: {}",
+        format_size(report.image, previous.map(|p| p.image))
+    );
+    print_table("Crate", &report.crates, previous.map(|p| &p.crates));
+    print_table("Module", &report.modules, previous.map(|p| &p.modules));
+
+    if args.record {
+        history.reports.push(report);
+        if let Err(err) = write_history(&history_path, &history) {
+            log::error!("Could not record the size report: {}", err);
+            return ExitCode::FAILURE;
+        }
+        log::info!("Size report recorded in '{}'", history_path.display());
+    }
+
+    ExitCode::SUCCESS
+}
+
+/// Prints the groups from the largest to the smallest, with the difference to the previous report.
+fn print_table(
+    title: &str,
+    groups: &BTreeMap<String, u64>,
+    previous: Option<&BTreeMap<String, u64>>,
+) {
+    let total: u64 = groups.values().sum();
+    let mut groups: Vec<_> = groups.iter().collect();
+    groups.sort_by(|a, b| b.1.cmp(a.1));
+
+    println!();
+    println!("{:<32} {:>20} {:>6}", title, "Size", "%");
+    for (name, size) in groups {
+        let before = previous.map(|p| p.get(name).copied().unwrap_or(0));
+        println!(
+            "{:<32} {:>20} {:>5.1}%",
+            name,
+            format_size(*size, before),
+            *size as f64 * 100.0 / total.max(1) as f64
+        );
+    }
+    let before = previous.map(|p| p.values().sum());
+    println!("{:<32} {:>20}", "Total", format_size(total, before));
+}
+
+/// Formats a size, followed by the difference with the previous size if any.
+fn format_size(size: u64, previous: Option<u64>) -> String {
+    match previous {
+        Some(previous) if previous != size => {
+            format!("{} ({:+})", size, size as i64 - previous as i64)
+        }
+        _ => size.to_string(),
+    }
+}
+
+// ———————————————————————————————— History ————————————————————————————————— //
+
+/// The recorded size reports, from the oldest to the most recent.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SizeHistory {
+    #[serde(default, rename = "report")]
+    reports: Vec<SizeReport>,
+}
+
+fn get_history_path() -> PathBuf {
+    let mut path = get_workspace_path();
+    path.push("target");
+    path.push("size");
+    path.push("history.toml");
+    path
+}
+
+/// Reads the size history, which is empty if nothing has been recorded yet.
+fn read_history(path: &Path) -> Result<SizeHistory, String> {
+    if !path.exists() {
+        return Ok(SizeHistory::default());
+    }
+    let content = fs::read_to_string(path).map_err(|err| err.to_string())?;
+    toml::from_str(&content).map_err(|err| err.to_string())
+}
+
+fn write_history(path: &Path, history: &SizeHistory) -> Result<(), String> {
+    let content = toml::to_string(history).map_err(|err| err.to_string())?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+    }
+    fs::write(path, content).map_err(|err| err.to_string())
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NM_OUTPUT: &str = "\
+0000000080000000 0000000000000040 T _start
+00000000800001a0 0000000000000100 T miralis::virt::emulator::<impl miralis::virt::VirtContext>::emulate_csr
+00000000800002a0 0000000000000020 t <miralis::device::uart::VirtUart as miralis::device::DeviceAccess>::write_device
+00000000800002c0 0000000000000030 t <&T as core::fmt::Display>::fmt
+00000000800002f0 0000000000000010 T miralis::main::h0123456789abcdef
+0000000080000300 0000000000000008 R spin::mutex::Mutex<T>::LOCKED
+                                  U _undefined_symbol
+";
+
+    #[test]
+    fn parse_nm_output() {
+        let symbols = parse_symbols(NM_OUTPUT);
+        assert_eq!(symbols.len(), 6);
+        assert_eq!(
+            symbols[0],
+            Symbol {
+                size: 0x40,
+                name: String::from("_start")
+            }
+        );
+        assert_eq!(symbols[2].size, 0x20);
+    }
+
+    #[test]
+    fn group_symbols() {
+        let symbols = parse_symbols(NM_OUTPUT);
+
+        let crates = group_by(&symbols, |name| Some(crate_of(name)));
+        assert_eq!(crates.get("miralis"), Some(&0x130));
+        assert_eq!(crates.get("spin"), Some(&0x8));
+        assert_eq!(crates.get(OTHER), Some(&0x40));
+        // Blanket implementations are attributed to the trait's crate
+        assert_eq!(crates.get("core"), Some(&0x30));
+
+        let modules = group_by(&symbols, miralis_module_of);
+        assert_eq!(modules.get("miralis::virt"), Some(&0x100));
+        assert_eq!(modules.get("miralis::device"), Some(&0x20));
+        assert_eq!(modules.get("miralis"), Some(&0x10));
+        assert_eq!(modules.len(), 3);
+    }
+
+    #[test]
+    fn format_size_delta() {
+        assert_eq!(format_size(100, None), "100");
+        assert_eq!(format_size(100, Some(100)), "100");
+        assert_eq!(format_size(100, Some(120)), "100 (-20)");
+        assert_eq!(format_size(120, Some(100)), "120 (+20)");
+    }
+}
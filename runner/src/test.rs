@@ -1,15 +1,17 @@
 //! Miralis test runner
 
 use std::collections::HashMap;
-use std::fs;
 use std::path::PathBuf;
-use std::process::ExitCode;
+use std::process::{Command, ExitCode};
 
 use crate::artifacts::{build_target, prepare_firmware_artifact, Target};
 use crate::config::{read_config, Config, Platforms};
-use crate::path::{get_project_config_path, make_path_relative_to_root};
-use crate::project::{ProjectConfig, Test};
-use crate::run::{get_qemu_cmd, get_spike_cmd, qemu_is_available, spike_is_available, QEMU, SPIKE};
+use crate::path::make_path_relative_to_root;
+use crate::project::{read_project_config, Test};
+use crate::run::{
+    get_qemu_cmd, get_qemu_sifive_u_cmd, get_spike_cmd, qemu_is_available, spike_is_available,
+    QEMU, SPIKE,
+};
 use crate::TestArgs;
 
 #[derive(Debug, PartialEq, Eq)]
@@ -38,22 +40,8 @@ struct SkippedTests {
 /// The test command, run all the tests.
 pub fn run_tests(args: &TestArgs) -> ExitCode {
     let mut stats = TestStats::default();
-    let path = get_project_config_path();
-    let config = match fs::read_to_string(&path) {
-        Ok(config) => config,
-        Err(_) => {
-            log::error!("Could not read '{}'", &path.display());
-            return ExitCode::FAILURE;
-        }
-    };
-
-    // Parse the config
-    let config = match toml::from_str::<ProjectConfig>(&config) {
-        Ok(config) => config,
-        Err(err) => {
-            log::error!("Failed to parse configuration:\n{}", err.message());
-            return ExitCode::FAILURE;
-        }
+    let Some(config) = read_project_config() else {
+        return ExitCode::FAILURE;
     };
 
     // Group tests by config files
@@ -99,7 +87,7 @@ pub fn run_tests(args: &TestArgs) -> ExitCode {
 
             // Skip tests if emulator not available
             match cfg.platform.name {
-                None | Some(Platforms::QemuVirt) if !qemu_available => {
+                None | Some(Platforms::QemuVirt | Platforms::SifiveU) if !qemu_available => {
                     stats.skipped.qemu += 1;
                     continue;
                 }
@@ -159,29 +147,7 @@ pub fn run_tests(args: &TestArgs) -> ExitCode {
 pub fn run_one_test(test: &Test, test_name: &str, cfg: &Config) -> Result<(), Option<String>> {
     log::info!("Running {}", test_name);
 
-    // Build or retrieve the artifacts to run
-    let miralis = build_target(Target::Miralis, cfg);
-    let Some(firmware) = test.firmware.as_ref().or(cfg.target.firmware.name.as_ref()) else {
-        log::error!("No firmware specified for test '{}'", test_name);
-        return Err(None);
-    };
-    let Some(firmware) = prepare_firmware_artifact(firmware, cfg) else {
-        log::error!("Failed to prepare firmware artifact '{}'", test_name);
-        return Err(None);
-    };
-
-    let cmd = match cfg.platform.name.unwrap_or(Platforms::QemuVirt) {
-        Platforms::QemuVirt => {
-            get_qemu_cmd(cfg, miralis, firmware, test.payload.as_ref(), false, false)
-        }
-        Platforms::Spike => get_spike_cmd(cfg, miralis, firmware),
-        invalid_platform => {
-            log::error!("Invalid test platform: '{}'", invalid_platform);
-            return Err(None);
-        }
-    };
-    let Ok(mut cmd) = cmd else {
-        log::error!("Failed to build command");
+    let Some(mut cmd) = get_test_cmd(test, test_name, cfg) else {
         return Err(None);
     };
 
@@ -210,3 +176,36 @@ pub fn run_one_test(test: &Test, test_name: &str, cfg: &Config) -> Result<(), Op
         Ok(())
     }
 }
+
+/// Returns the command running a test, building the required artifacts as needed.
+pub fn get_test_cmd(test: &Test, test_name: &str, cfg: &Config) -> Option<Command> {
+    // Build or retrieve the artifacts to run
+    let miralis = build_target(Target::Miralis, cfg);
+    let Some(firmware) = test.firmware.as_ref().or(cfg.target.firmware.name.as_ref()) else {
+        log::error!("No firmware specified for test '{}'", test_name);
+        return None;
+    };
+    let Some(firmware) = prepare_firmware_artifact(firmware, cfg) else {
+        log::error!("Failed to prepare firmware artifact '{}'", test_name);
+        return None;
+    };
+
+    let cmd = match cfg.platform.name.unwrap_or(Platforms::QemuVirt) {
+        Platforms::QemuVirt => {
+            get_qemu_cmd(cfg, miralis, firmware, test.payload.as_ref(), false, false)
+        }
+        Platforms::Spike => get_spike_cmd(cfg, miralis, firmware),
+        Platforms::SifiveU => {
+            get_qemu_sifive_u_cmd(cfg, miralis, firmware, test.payload.as_ref(), false, false)
+        }
+        invalid_platform => {
+            log::error!("Invalid test platform: '{}'", invalid_platform);
+            return None;
+        }
+    };
+    let Ok(cmd) = cmd else {
+        log::error!("Failed to build command");
+        return None;
+    };
+    Some(cmd)
+}
@@ -0,0 +1,272 @@
+//! Trace subcommand
+//!
+//! Converts a dump of the Miralis trace buffer (see the `trace` feature) into the Chrome JSON trace
+//! format, which can be imported in Perfetto to build a timeline of each hart. The dump can be
+//! taken from GDB with `dump binary memory` at the `MIRALIS_TRACE_BUFFER` symbol, or from the QEMU
+//! monitor with `pmemsave`.
+//!
+//! Each hart is displayed as a thread, alternating between the world (firmware or payload) running
+//! on the virtual CPU and the handling of its exits by Miralis.
+
+use std::fmt::Write;
+use std::fs;
+use std::process::ExitCode;
+
+use crate::TraceArgs;
+
+/// The magic value of the trace buffer, must match `miralis_core::abi::trace::MAGIC`.
+const MAGIC: &[u8; 8] = b"MIRATRCE";
+/// The supported version of the trace buffer layout.
+const VERSION: u64 = 1;
+/// Size of the trace buffer header.
+const HEADER_SIZE: usize = 4 * 8;
+/// Size of a trace event.
+const EVENT_SIZE: usize = 4 * 8;
+
+/// Event kinds, must match `miralis_core::abi::trace`.
+const RUN_VCPU_ENTER: u64 = 1;
+const RUN_VCPU_EXIT: u64 = 2;
+
+/// An event of the trace buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Event {
+    timestamp: u64,
+    kind: u64,
+    args: [u64; 2],
+}
+
+// ——————————————————————————————— Trace Dump ——————————————————————————————— //
+
+/// The trace command, converts a trace buffer dump for Perfetto.
+pub fn trace(args: &TraceArgs) -> ExitCode {
+    let dump = match fs::read(&args.dump) {
+        Ok(dump) => dump,
+        Err(err) => {
+            log::error!("Could not read '{}': {}", args.dump.display(), err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let harts = match parse_dump(&dump) {
+        Ok(harts) => harts,
+        Err(err) => {
+            log::error!("Invalid trace buffer dump: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let json = to_chrome_json(&harts, args.frequency);
+    match &args.output {
+        Some(output) => {
+            if let Err(err) = fs::write(output, json) {
+                log::error!("Could not write '{}': {}", output.display(), err);
+                return ExitCode::FAILURE;
+            }
+        }
+        None => println!("{}", json),
+    }
+
+    ExitCode::SUCCESS
+}
+
+fn read_u64(dump: &[u8], offset: usize) -> Result<u64, String> {
+    dump.get(offset..offset + 8)
+        .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| format!("truncated at offset 0x{:x}", offset))
+}
+
+/// Returns the events of each hart, from the oldest to the most recent.
+fn parse_dump(dump: &[u8]) -> Result<Vec<Vec<Event>>, String> {
+    if dump.get(..8) != Some(MAGIC.as_slice()) {
+        return Err("missing magic value".to_string());
+    }
+    let version = read_u64(dump, 8)?;
+    if version != VERSION {
+        return Err(format!("unsupported version {}", version));
+    }
+    let nb_harts = read_u64(dump, 16)? as usize;
+    let entries = read_u64(dump, 24)? as usize;
+
+    let ring_size = 8 + entries * EVENT_SIZE;
+    let mut harts = Vec::with_capacity(nb_harts);
+    for hart in 0..nb_harts {
+        let ring = HEADER_SIZE + hart * ring_size;
+        let head = read_u64(dump, ring)? as usize;
+
+        // Only the most recent events are retained
+        let first = head.saturating_sub(entries);
+        let mut events = Vec::with_capacity(head - first);
+        for seq in first..head {
+            let offset = ring + 8 + (seq % entries) * EVENT_SIZE;
+            events.push(Event {
+                timestamp: read_u64(dump, offset)?,
+                kind: read_u64(dump, offset + 8)?,
+                args: [read_u64(dump, offset + 16)?, read_u64(dump, offset + 24)?],
+            });
+        }
+        harts.push(events);
+    }
+
+    Ok(harts)
+}
+
+// ——————————————————————————————— Chrome JSON —————————————————————————————— //
+
+/// Converts the events into the Chrome JSON trace format.
+///
+/// Timestamps are converted to microseconds given the frequency of the cycle counter, if known,
+/// and are otherwise displayed in cycles.
+fn to_chrome_json(harts: &[Vec<Event>], frequency: Option<u64>) -> String {
+    let timestamp = |cycles: u64| match frequency {
+        Some(frequency) => cycles as f64 * 1_000_000.0 / frequency as f64,
+        None => cycles as f64,
+    };
+
+    let mut entries = Vec::new();
+    for (hart, events) in harts.iter().enumerate() {
+        entries.push(format!(
+            r#"{{"name":"thread_name","ph":"M","pid":0,"tid":{},"args":{{"name":"hart {}"}}}}"#,
+            hart, hart
+        ));
+
+        // The trace may start in the middle of an exit, skip events until the first entry
+        let mut last_world = None;
+        for event in events {
+            let ts = timestamp(event.timestamp);
+            match (event.kind, last_world) {
+                (RUN_VCPU_ENTER, _) => {
+                    let world = if event.args[1] == 0 {
+                        "firmware"
+                    } else {
+                        "payload"
+                    };
+                    if last_world.is_some() {
+                        entries.push(span_end("miralis", hart, ts, String::new()));
+                    }
+                    entries.push(span_begin(
+                        world,
+                        hart,
+                        ts,
+                        format!(r#""pc":"0x{:x}""#, event.args[0]),
+                    ));
+                    last_world = Some(world);
+                }
+                (RUN_VCPU_EXIT, Some(world)) => {
+                    entries.push(span_end(
+                        world,
+                        hart,
+                        ts,
+                        format!(
+                            r#""mcause":"0x{:x}","mtval":"0x{:x}""#,
+                            event.args[0], event.args[1]
+                        ),
+                    ));
+                    entries.push(span_begin("miralis", hart, ts, String::new()));
+                }
+                _ => (),
+            }
+        }
+    }
+
+    let mut json = String::from("{\"traceEvents\":[\n");
+    for (idx, entry) in entries.iter().enumerate() {
+        let separator = if idx + 1 < entries.len() { "," } else { "" };
+        writeln!(json, "{}{}", entry, separator).unwrap();
+    }
+    json.push_str("]}");
+    json
+}
+
+fn span_begin(name: &str, hart: usize, ts: f64, args: String) -> String {
+    format!(
+        r#"{{"name":"{}","ph":"B","pid":0,"tid":{},"ts":{},"args":{{{}}}}}"#,
+        name, hart, ts, args
+    )
+}
+
+fn span_end(name: &str, hart: usize, ts: f64, args: String) -> String {
+    format!(
+        r#"{{"name":"{}","ph":"E","pid":0,"tid":{},"ts":{},"args":{{{}}}}}"#,
+        name, hart, ts, args
+    )
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dump(entries: u64, heads: &[u64], events: &[[u64; 4]]) -> Vec<u8> {
+        let mut dump = Vec::new();
+        dump.extend_from_slice(MAGIC);
+        for word in [VERSION, heads.len() as u64, entries] {
+            dump.extend_from_slice(&word.to_le_bytes());
+        }
+        let mut events = events.iter();
+        for head in heads {
+            dump.extend_from_slice(&head.to_le_bytes());
+            for _ in 0..entries {
+                for word in events.next().unwrap() {
+                    dump.extend_from_slice(&word.to_le_bytes());
+                }
+            }
+        }
+        dump
+    }
+
+    #[test]
+    fn parse_trace_dump() {
+        let dump = dump(
+            2,
+            &[3, 1],
+            &[
+                // Hart 0 wrapped around, the oldest event is at index 1
+                [30, RUN_VCPU_ENTER, 0x80200000, 0],
+                [20, RUN_VCPU_EXIT, 9, 0],
+                // Hart 1 has a single event
+                [5, RUN_VCPU_ENTER, 0x80400000, 1],
+                [0, 0, 0, 0],
+            ],
+        );
+
+        let harts = parse_dump(&dump).unwrap();
+        assert_eq!(harts.len(), 2);
+        let timestamps: Vec<u64> = harts[0].iter().map(|event| event.timestamp).collect();
+        assert_eq!(timestamps, vec![20, 30]);
+        assert_eq!(harts[1].len(), 1);
+        assert_eq!(harts[1][0].args, [0x80400000, 1]);
+
+        assert!(parse_dump(&dump[..dump.len() - 40]).is_err());
+        assert!(parse_dump(b"MIRASTAT").is_err());
+    }
+
+    #[test]
+    fn chrome_json() {
+        let events = vec![
+            // Exit without a matching entry, skipped
+            Event {
+                timestamp: 500,
+                kind: RUN_VCPU_EXIT,
+                args: [9, 0],
+            },
+            Event {
+                timestamp: 1000,
+                kind: RUN_VCPU_ENTER,
+                args: [0x80200000, 0],
+            },
+            Event {
+                timestamp: 3000,
+                kind: RUN_VCPU_EXIT,
+                args: [2, 0x30200073],
+            },
+        ];
+
+        let json = to_chrome_json(&[events], Some(1_000_000_000));
+        let lines: Vec<&str> = json.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert!(lines[2].contains(r#""name":"firmware","ph":"B","pid":0,"tid":0,"ts":1,"#));
+        assert!(lines[3].contains(r#""ph":"E""#) && lines[3].contains(r#""mcause":"0x2""#));
+        assert!(lines[4].contains(r#""name":"miralis","ph":"B""#) && !lines[4].ends_with(','));
+        assert_eq!(lines[5], "]}");
+    }
+}
@@ -0,0 +1,275 @@
+//! Trap log subcommand
+//!
+//! Decodes the trap log dumped by Miralis on the console (see the `debug.trap_log_entries`
+//! configuration) into a human-readable timeline. The log is dumped on panic and on demand with the
+//! `MIRALIS_TRAP_LOG_DUMP_FID` call, such that the timeline leading to a failure can be recovered
+//! from the serial output of a board, without attaching a debugger.
+//!
+//! The console output can contain other logs and several dumps: records are collected from all the
+//! lines holding the trap log prefix, and records already seen in a previous dump are skipped.
+
+use std::fmt::Write;
+use std::fs;
+use std::process::ExitCode;
+
+use crate::TrapLogArgs;
+
+/// Prefix of the trap log lines, must match `miralis_core::abi::trap_log::LINE_PREFIX`.
+const LINE_PREFIX: &str = "[trap-log]";
+
+/// Layout of the `info` word, must match `miralis_core::abi::trap_log`.
+const CODE_FILTER: u64 = 0xffff;
+const INTERRUPT_OFFSET: u64 = 16;
+const FROM_MODE_OFFSET: u64 = 20;
+const TO_MODE_OFFSET: u64 = 22;
+const MODE_FILTER: u64 = 0b11;
+const CYCLES_OFFSET: u64 = 32;
+
+/// A trap record of the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Record {
+    hart: usize,
+    timestamp: u64,
+    mepc: u64,
+    info: u64,
+}
+
+// ———————————————————————————————— Trap Log ———————————————————————————————— //
+
+/// The trap log command, prints the timeline of the traps of each hart.
+pub fn trap_log(args: &TrapLogArgs) -> ExitCode {
+    let log = match fs::read_to_string(&args.log) {
+        Ok(log) => log,
+        Err(err) => {
+            log::error!("Could not read '{}': {}", args.log.display(), err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let harts = match parse_log(&log) {
+        Ok(harts) => harts,
+        Err(err) => {
+            log::error!("Invalid trap log: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    if harts.iter().all(|records| records.is_empty()) {
+        log::error!("No trap log found in '{}'", args.log.display());
+        return ExitCode::FAILURE;
+    }
+
+    print!("{}", to_timeline(&harts, args.frequency));
+    ExitCode::SUCCESS
+}
+
+fn parse_hex(word: Option<&str>) -> Result<u64, String> {
+    let word = word.ok_or("missing field")?;
+    u64::from_str_radix(word, 16).map_err(|err| format!("invalid field '{}': {}", word, err))
+}
+
+fn parse_record(fields: &str) -> Result<Record, String> {
+    let mut fields = fields.split_whitespace();
+    let hart = fields.next().ok_or("missing hart ID")?;
+    let hart = hart
+        .parse()
+        .map_err(|err| format!("invalid hart ID '{}': {}", hart, err))?;
+    Ok(Record {
+        hart,
+        timestamp: parse_hex(fields.next())?,
+        mepc: parse_hex(fields.next())?,
+        info: parse_hex(fields.next())?,
+    })
+}
+
+/// Returns the records of each hart, from the oldest to the most recent.
+fn parse_log(log: &str) -> Result<Vec<Vec<Record>>, String> {
+    let mut harts: Vec<Vec<Record>> = Vec::new();
+    for (idx, line) in log.lines().enumerate() {
+        let Some((_, fields)) = line.split_once(LINE_PREFIX) else {
+            continue;
+        };
+        let record = parse_record(fields).map_err(|err| format!("line {}: {}", idx + 1, err))?;
+
+        if harts.len() <= record.hart {
+            harts.resize(record.hart + 1, Vec::new());
+        }
+        let records = &mut harts[record.hart];
+        // Later dumps repeat the records still retained in the log
+        if records
+            .last()
+            .is_none_or(|last| record.timestamp > last.timestamp)
+        {
+            records.push(record);
+        }
+    }
+
+    Ok(harts)
+}
+
+// ———————————————————————————————— Timeline ———————————————————————————————— //
+
+/// Formats the records as a timeline, with times relative to the first record of each hart.
+///
+/// The cycle counters of the harts are not synchronized, each hart is therefore displayed on its
+/// own.
+fn to_timeline(harts: &[Vec<Record>], frequency: Option<u64>) -> String {
+    let mut timeline = String::new();
+    for records in harts.iter().filter(|records| !records.is_empty()) {
+        writeln!(
+            timeline,
+            "Hart {}, {} traps",
+            records[0].hart,
+            records.len()
+        )
+        .unwrap();
+
+        let start = records[0].timestamp;
+        for record in records {
+            let info = record.info;
+            let from = (info >> FROM_MODE_OFFSET) & MODE_FILTER;
+            let to = (info >> TO_MODE_OFFSET) & MODE_FILTER;
+            let code = info & CODE_FILTER;
+            let is_interrupt = (info >> INTERRUPT_OFFSET) & 1 != 0;
+            let cause = format!("{} ({})", cause_name(code, is_interrupt), code);
+            writeln!(
+                timeline,
+                "  {:>14}  {} -> {}  mepc 0x{:016x}  {:<32} handled in {}",
+                format_time(record.timestamp - start, frequency),
+                mode_name(from),
+                mode_name(to),
+                record.mepc,
+                cause,
+                format_time(info >> CYCLES_OFFSET, frequency)
+            )
+            .unwrap();
+        }
+    }
+    timeline
+}
+
+fn format_time(cycles: u64, frequency: Option<u64>) -> String {
+    match frequency {
+        Some(frequency) => format!("{:.3} us", cycles as f64 * 1_000_000.0 / frequency as f64),
+        None => format!("{} cycles", cycles),
+    }
+}
+
+/// Returns the virtual mode and the world running in that mode.
+fn mode_name(mode: u64) -> &'static str {
+    match mode {
+        0 => "U-mode (payload)",
+        1 => "S-mode (payload)",
+        3 => "M-mode (firmware)",
+        _ => "invalid mode",
+    }
+}
+
+fn cause_name(code: u64, is_interrupt: bool) -> &'static str {
+    if is_interrupt {
+        return match code {
+            1 => "supervisor software interrupt",
+            3 => "machine software interrupt",
+            5 => "supervisor timer interrupt",
+            7 => "machine timer interrupt",
+            9 => "supervisor external interrupt",
+            11 => "machine external interrupt",
+            13 => "counter overflow interrupt",
+            _ => "unknown interrupt",
+        };
+    }
+
+    match code {
+        0 => "instruction address misaligned",
+        1 => "instruction access fault",
+        2 => "illegal instruction",
+        3 => "breakpoint",
+        4 => "load address misaligned",
+        5 => "load access fault",
+        6 => "store address misaligned",
+        7 => "store access fault",
+        8 => "ecall from U-mode",
+        9 => "ecall from S-mode",
+        10 => "ecall from VS-mode",
+        11 => "ecall from M-mode",
+        12 => "instruction page fault",
+        13 => "load page fault",
+        15 => "store page fault",
+        20 => "instruction guest page fault",
+        21 => "load guest page fault",
+        22 => "virtual instruction",
+        23 => "store guest page fault",
+        _ => "unknown exception",
+    }
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(hart: usize, timestamp: u64, mepc: u64, info: u64) -> String {
+        format!(
+            "[Error | miralis::debug] {} {} {:016x} {:016x} {:016x}",
+            LINE_PREFIX, hart, timestamp, mepc, info
+        )
+    }
+
+    #[test]
+    fn parse_trap_log() {
+        let log = [
+            "[Info  | miralis] Hello, world!".to_string(),
+            line(1, 100, 0x80200000, 9),
+            line(1, 200, 0x80200004, 9),
+            "[Info  | miralis::debug] Trap log of hart 1:".to_string(),
+            // Second dump, the first record was overwritten
+            line(1, 200, 0x80200004, 9),
+            line(1, 300, 0x80200008, 2),
+        ]
+        .join("\n");
+
+        let harts = parse_log(&log).unwrap();
+        assert_eq!(harts.len(), 2);
+        assert!(harts[0].is_empty());
+        let timestamps: Vec<u64> = harts[1].iter().map(|record| record.timestamp).collect();
+        assert_eq!(timestamps, vec![100, 200, 300]);
+        assert_eq!(harts[1][2].mepc, 0x80200008);
+
+        assert!(parse_log("[trap-log] 0 10 20").is_err());
+        assert!(parse_log("[trap-log] zero 10 20 30").is_err());
+    }
+
+    #[test]
+    fn timeline() {
+        // ecall from the payload's S-mode forwarded to the firmware, handled in 1000 cycles
+        let ecall = 9 | (1 << FROM_MODE_OFFSET) | (3 << TO_MODE_OFFSET) | (1000 << CYCLES_OFFSET);
+        // Machine timer interrupt in the firmware
+        let timer = 7 | (1 << INTERRUPT_OFFSET) | (3 << FROM_MODE_OFFSET) | (3 << TO_MODE_OFFSET);
+        let records = vec![
+            Record {
+                hart: 0,
+                timestamp: 5000,
+                mepc: 0x80200000,
+                info: ecall,
+            },
+            Record {
+                hart: 0,
+                timestamp: 8000,
+                mepc: 0x80000100,
+                info: timer,
+            },
+        ];
+
+        let timeline = to_timeline(&[records], None);
+        let lines: Vec<&str> = timeline.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "Hart 0, 2 traps");
+        assert!(lines[1].contains("0 cycles  S-mode (payload) -> M-mode (firmware)"));
+        assert!(lines[1].contains("ecall from S-mode (9)"));
+        assert!(lines[1].ends_with("handled in 1000 cycles"));
+        assert!(lines[2].contains("3000 cycles  M-mode (firmware) -> M-mode (firmware)"));
+        assert!(lines[2].contains("machine timer interrupt (7)"));
+
+        let timeline = to_timeline(&[vec![]], Some(1_000_000_000));
+        assert!(timeline.is_empty());
+    }
+}
@@ -1,9 +1,11 @@
 // SPDX-FileCopyrightText: 2023 IBM Corporation
 // SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
 // SPDX-License-Identifier: Apache-2.0
+use crate::ace::confidential_flow::handlers::attestation::{ExtendMeasurement, GetEvidence};
 use crate::ace::confidential_flow::handlers::interrupts::{
     AllowExternalInterrupt, ExposeEnabledInterrupts, HandleInterrupt,
 };
+use crate::ace::confidential_flow::handlers::lazy_page::LazyPageFault;
 use crate::ace::confidential_flow::handlers::mmio::{
     AddMmioRegion, MmioLoadRequest, MmioLoadResponse, MmioStoreRequest, MmioStoreResponse,
     RemoveMmioRegion,
@@ -13,6 +15,7 @@ use crate::ace::confidential_flow::handlers::sbi_base_extension::{
     SbiExtensionProbe, SbiGetImplId, SbiGetImplVersion, SbiGetMarchId, SbiGetMimpid,
     SbiGetMvendorid, SbiGetSpecVersion,
 };
+use crate::ace::confidential_flow::handlers::sealing::SealingKeyRequest;
 use crate::ace::confidential_flow::handlers::shared_page::{
     SharePageComplete, SharePageRequest, UnsharePageRequest,
 };
@@ -30,6 +33,7 @@ use crate::ace::core::architecture::riscv::sbi::IpiExtension::*;
 use crate::ace::core::architecture::riscv::sbi::RfenceExtension::*;
 use crate::ace::core::architecture::riscv::sbi::SbiExtension::*;
 use crate::ace::core::architecture::riscv::sbi::SrstExtension::*;
+use crate::ace::core::architecture::specification::MIE_VSEIP_MASK;
 use crate::ace::core::architecture::TrapCause::*;
 use crate::ace::core::architecture::{HartLifecycleState, TrapCause};
 use crate::ace::core::control_data::{
@@ -40,10 +44,14 @@ use crate::ace::error::Error;
 use crate::ace::non_confidential_flow::{DeclassifyToHypervisor, NonConfidentialFlow};
 use crate::debug;
 
+#[cfg(not(feature = "userspace"))]
 extern "C" {
     fn exit_to_confidential_hart_asm() -> !;
 }
 
+#[cfg(feature = "userspace")]
+use crate::ace::core::architecture::riscv::userspace::exit_to_confidential_hart_asm;
+
 /// Ensures control flow integrity within the `confidential flow` part of the finite state machine (FSM) of the security
 /// monitor.
 ///
@@ -75,13 +83,17 @@ impl<'a> ConfidentialFlow<'a> {
     unsafe extern "C" fn route_trap_from_confidential_hart(
         hardware_hart_pointer: *mut HardwareHart,
     ) -> ! {
-        let flow = Self {
-            hardware_hart: unsafe {
-                hardware_hart_pointer
-                    .as_mut()
-                    .expect(Self::CTX_SWITCH_ERROR_MSG)
-            },
-        };
+        Self::route(unsafe {
+            hardware_hart_pointer
+                .as_mut()
+                .expect(Self::CTX_SWITCH_ERROR_MSG)
+        })
+    }
+
+    /// Routes the control flow based on the trap cause of the confidential hart assigned to the hardware hart. It is the safe part of
+    /// `route_trap_from_confidential_hart`, also used to drive the finite state machine on the host.
+    pub(crate) fn route(hardware_hart: &'a mut HardwareHart) -> ! {
+        let flow = Self { hardware_hart };
         assert!(!flow.hardware_hart.confidential_hart().is_dummy());
         match TrapCause::from_hart_architectural_state(
             flow.confidential_hart().confidential_hart_state(),
@@ -165,9 +177,26 @@ impl<'a> ConfidentialFlow<'a> {
             VsEcall(Covg(UnshareMemory)) => {
                 UnsharePageRequest::from_confidential_hart(flow.confidential_hart()).handle(flow)
             }
+            VsEcall(Covg(GetSealingKey)) => {
+                SealingKeyRequest::from_confidential_hart(flow.confidential_hart()).handle(flow)
+            }
+            VsEcall(Covg(GetEvidence)) => {
+                GetEvidence::from_confidential_hart(flow.confidential_hart()).handle(flow)
+            }
+            VsEcall(Covg(ExtendMeasurement)) => {
+                ExtendMeasurement::from_confidential_hart(flow.confidential_hart()).handle(flow)
+            }
             VsEcall(_) => {
                 InvalidCall::from_confidential_hart(flow.confidential_hart()).handle(flow)
             }
+            GuestInstructionPageFault | GuestLoadPageFault | GuestStorePageFault
+                if LazyPageFault::tried_to_access_lazy_page(
+                    flow.confidential_vm_id(),
+                    flow.confidential_hart(),
+                ) =>
+            {
+                LazyPageFault::from_confidential_hart(flow.confidential_hart()).handle(flow)
+            }
             GuestLoadPageFault => {
                 MmioLoadRequest::from_confidential_hart(flow.confidential_hart()).handle(flow)
             }
@@ -253,6 +282,8 @@ impl<'a> ConfidentialFlow<'a> {
             Some(SharePage(v)) => {
                 SharePageComplete::from_hypervisor_hart(self.hypervisor_hart(), v).handle(self)
             }
+            // The confidential hart retries the faulting access, if the hypervisor did not donate the page, it faults again.
+            Some(PopulatePage(_)) => self.exit_to_confidential_hart(),
             None => self.exit_to_confidential_hart(),
         }
     }
@@ -310,8 +341,16 @@ impl<'a> ConfidentialFlow<'a> {
         // We must restore the control and status registers (CSRs) that might have changed during execution of the security monitor.
         // We call it here because it is just before exiting to the assembly context switch, so we are sure that these CSRs have their
         // final values.
-        let interrupts = self.confidential_hart().csrs().hvip.read_from_main_memory()
+        let mut interrupts = self.confidential_hart().csrs().hvip.read_from_main_memory()
             | self.confidential_hart().csrs().vsip.read_from_main_memory();
+        // External interrupts injected with the COVI extension are signaled as long as the interrupt file has a deliverable interrupt.
+        if self
+            .confidential_hart()
+            .interrupt_file()
+            .is_interrupt_pending()
+        {
+            interrupts |= MIE_VSEIP_MASK;
+        }
         let address = self.confidential_hart_mut().address();
         self.confidential_hart().csrs().hvip.write(interrupts);
         self.confidential_hart().csrs().sscratch.write(address);
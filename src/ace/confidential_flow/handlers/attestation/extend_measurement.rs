@@ -0,0 +1,49 @@
+// SPDX-FileCopyrightText: 2023 IBM Corporation
+// SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
+// SPDX-License-Identifier: Apache-2.0
+use crate::ace::confidential_flow::handlers::sbi::SbiResponse;
+use crate::ace::confidential_flow::{ApplyToConfidentialHart, ConfidentialFlow};
+use crate::ace::core::architecture::GeneralPurposeRegister;
+use crate::ace::core::control_data::{
+    ConfidentialHart, ConfidentialVmId, ControlDataStorage, MeasurementDigest,
+};
+use crate::ace::core::memory_layout::ConfidentialVmPhysicalAddress;
+use crate::ace::error::Error;
+
+/// Handles the confidential VM's request to extend a runtime measurement register with a digest, for example the digest of a kernel
+/// module it loads. The security monitor reads the digest from the confidential VM's memory.
+pub struct ExtendMeasurement {
+    register_id: usize,
+    digest_address: ConfidentialVmPhysicalAddress,
+}
+
+impl ExtendMeasurement {
+    pub fn from_confidential_hart(confidential_hart: &ConfidentialHart) -> Self {
+        Self {
+            register_id: confidential_hart.gprs().read(GeneralPurposeRegister::a0),
+            digest_address: ConfidentialVmPhysicalAddress::new(
+                confidential_hart.gprs().read(GeneralPurposeRegister::a1),
+            ),
+        }
+    }
+
+    pub fn handle(self, confidential_flow: ConfidentialFlow) -> ! {
+        let transformation = match self.extend(confidential_flow.confidential_vm_id()) {
+            Ok(_) => SbiResponse::success(),
+            Err(error) => SbiResponse::error(error),
+        };
+        confidential_flow.apply_and_exit_to_confidential_hart(ApplyToConfidentialHart::SbiResponse(
+            transformation,
+        ))
+    }
+
+    fn extend(&self, confidential_vm_id: ConfidentialVmId) -> Result<(), Error> {
+        ControlDataStorage::try_confidential_vm_mut(confidential_vm_id, |mut confidential_vm| {
+            let mut digest = MeasurementDigest::default();
+            confidential_vm
+                .memory_protector()
+                .read_from_confidential_vm(&self.digest_address, &mut digest)?;
+            confidential_vm.extend_measurement(self.register_id, &digest)
+        })
+    }
+}
@@ -0,0 +1,63 @@
+// SPDX-FileCopyrightText: 2023 IBM Corporation
+// SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
+// SPDX-License-Identifier: Apache-2.0
+use crate::ace::confidential_flow::handlers::sbi::SbiResponse;
+use crate::ace::confidential_flow::{ApplyToConfidentialHart, ConfidentialFlow};
+use crate::ace::core::architecture::GeneralPurposeRegister;
+use crate::ace::core::attestation::{Challenge, Evidence, CHALLENGE_SIZE};
+use crate::ace::core::control_data::{ConfidentialHart, ConfidentialVmId, ControlDataStorage};
+use crate::ace::core::memory_layout::ConfidentialVmPhysicalAddress;
+use crate::ace::error::Error;
+use crate::ensure;
+
+/// Handles the confidential VM's request to obtain the attestation evidence. The evidence reports the measurements of the confidential VM
+/// and the challenge chosen by the guest owner, so that the guest owner can verify the integrity of the confidential VM before
+/// provisioning it with secrets. The security monitor reads the challenge from and writes the evidence to the confidential VM's memory.
+pub struct GetEvidence {
+    challenge_address: ConfidentialVmPhysicalAddress,
+    evidence_address: ConfidentialVmPhysicalAddress,
+    evidence_size: usize,
+}
+
+impl GetEvidence {
+    pub fn from_confidential_hart(confidential_hart: &ConfidentialHart) -> Self {
+        Self {
+            challenge_address: ConfidentialVmPhysicalAddress::new(
+                confidential_hart.gprs().read(GeneralPurposeRegister::a0),
+            ),
+            evidence_address: ConfidentialVmPhysicalAddress::new(
+                confidential_hart.gprs().read(GeneralPurposeRegister::a1),
+            ),
+            evidence_size: confidential_hart.gprs().read(GeneralPurposeRegister::a2),
+        }
+    }
+
+    pub fn handle(self, confidential_flow: ConfidentialFlow) -> ! {
+        let transformation = match self.write_evidence(confidential_flow.confidential_vm_id()) {
+            Ok(_) => SbiResponse::success_with_code(Evidence::SIZE),
+            Err(error) => SbiResponse::error(error),
+        };
+        confidential_flow.apply_and_exit_to_confidential_hart(ApplyToConfidentialHart::SbiResponse(
+            transformation,
+        ))
+    }
+
+    fn write_evidence(&self, confidential_vm_id: ConfidentialVmId) -> Result<(), Error> {
+        ensure!(
+            self.evidence_size >= Evidence::SIZE,
+            Error::InvalidParameter()
+        )?;
+
+        ControlDataStorage::try_confidential_vm(confidential_vm_id, |confidential_vm| {
+            let mut challenge: Challenge = [0; CHALLENGE_SIZE];
+            let memory_protector = confidential_vm.memory_protector();
+            memory_protector.read_from_confidential_vm(&self.challenge_address, &mut challenge)?;
+            let evidence = Evidence::new(
+                confidential_vm.measurements(),
+                confidential_vm.runtime_measurements(),
+                &challenge,
+            )?;
+            memory_protector.write_to_confidential_vm(&self.evidence_address, evidence.as_bytes())
+        })
+    }
+}
@@ -0,0 +1,8 @@
+// SPDX-FileCopyrightText: 2023 IBM Corporation
+// SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
+// SPDX-License-Identifier: Apache-2.0
+pub use extend_measurement::ExtendMeasurement;
+pub use get_evidence::GetEvidence;
+
+mod extend_measurement;
+mod get_evidence;
@@ -0,0 +1,77 @@
+// SPDX-FileCopyrightText: 2023 IBM Corporation
+// SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
+// SPDX-License-Identifier: Apache-2.0
+use crate::ace::confidential_flow::handlers::mmio::MmioAccessFault;
+use crate::ace::confidential_flow::handlers::sbi::SbiResponse;
+use crate::ace::confidential_flow::ConfidentialFlow;
+use crate::ace::core::architecture::specification::*;
+use crate::ace::core::control_data::{
+    ConfidentialHart, ConfidentialVmId, ControlDataStorage, HypervisorHart, ResumableOperation,
+};
+use crate::ace::non_confidential_flow::DeclassifyToHypervisor;
+
+/// Handles a guest page fault on a page declared in the manifest of lazily populated pages. The fault is declassified to the hypervisor,
+/// which is expected to donate the page and resume the confidential hart. The confidential hart then retries the faulting access.
+#[derive(Clone)]
+pub struct LazyPageFault {
+    mcause: usize,
+    mtval: usize,
+    mtval2: usize,
+}
+
+impl LazyPageFault {
+    pub fn from_confidential_hart(confidential_hart: &ConfidentialHart) -> Self {
+        Self {
+            mcause: confidential_hart.csrs().mcause.read(),
+            mtval: confidential_hart.csrs().mtval.read(),
+            mtval2: confidential_hart.csrs().mtval2.read(),
+        }
+    }
+
+    /// Returns true if the guest page fault of the confidential hart happened on a page that has not been donated yet.
+    pub fn tried_to_access_lazy_page(
+        confidential_vm_id: ConfidentialVmId,
+        confidential_hart: &ConfidentialHart,
+    ) -> bool {
+        let fault_address = Self::from_confidential_hart(confidential_hart).fault_address();
+        ControlDataStorage::try_confidential_vm(confidential_vm_id, |confidential_vm| {
+            Ok(confidential_vm.is_lazy_page_pending(fault_address))
+        })
+        .unwrap_or(false)
+    }
+
+    pub fn handle(self, confidential_flow: ConfidentialFlow) -> ! {
+        confidential_flow
+            .set_resumable_operation(ResumableOperation::PopulatePage(self.clone()))
+            .into_non_confidential_flow()
+            .declassify_and_exit_to_hypervisor(DeclassifyToHypervisor::LazyPageFault(self))
+    }
+
+    /// Returns the access fault reported to the confidential hart when the hypervisor did not donate the page in time. The faulting
+    /// instruction is not skipped.
+    pub fn access_fault(&self) -> MmioAccessFault {
+        let cause = match self.mcause {
+            cause if cause == CAUSE_FETCH_GUEST_PAGE_FAULT.into() => CAUSE_FETCH_ACCESS,
+            cause if cause == CAUSE_LOAD_GUEST_PAGE_FAULT.into() => CAUSE_LOAD_ACCESS,
+            _ => CAUSE_STORE_ACCESS,
+        };
+        MmioAccessFault::new(cause.into(), self.mtval, 0)
+    }
+
+    pub fn declassify_to_hypervisor_hart(&self, hypervisor_hart: &mut HypervisorHart) {
+        // The hypervisor learns the guest physical address of the page, but not the faulting instruction.
+        hypervisor_hart.csrs_mut().scause.write(self.mcause);
+        hypervisor_hart.csrs_mut().stval.write(self.mtval);
+        hypervisor_hart
+            .shared_memory_mut()
+            .write_csr(CSR_HTVAL.into(), self.mtval2);
+        hypervisor_hart
+            .shared_memory_mut()
+            .write_csr(CSR_HTINST.into(), 0);
+        SbiResponse::success().declassify_to_hypervisor_hart(hypervisor_hart);
+    }
+
+    fn fault_address(&self) -> usize {
+        (self.mtval2 << 2) | (self.mtval & 0x3)
+    }
+}
@@ -0,0 +1,6 @@
+// SPDX-FileCopyrightText: 2023 IBM Corporation
+// SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
+// SPDX-License-Identifier: Apache-2.0
+pub use lazy_page_fault::LazyPageFault;
+
+mod lazy_page_fault;
@@ -36,6 +36,21 @@ impl MmioAccessFault {
         confidential_hart.csrs_mut().vstval.write(self.mtval);
     }
 
+    /// Same as `apply_to_confidential_hart` for a confidential hart that is not running on any physical hart, whose CSRs are saved in
+    /// the main memory.
+    pub fn apply_to_confidential_hart_in_main_memory(
+        &self,
+        confidential_hart: &mut ConfidentialHart,
+    ) {
+        let mepc = confidential_hart.csrs().mepc.read_from_main_memory() + self.instruction_length;
+        let trap_vector_address = confidential_hart.csrs().vstvec.read_from_main_memory();
+        let csrs = confidential_hart.csrs_mut();
+        csrs.vsepc.save_value_in_main_memory(mepc);
+        csrs.mepc.save_value_in_main_memory(trap_vector_address);
+        csrs.vscause.save_value_in_main_memory(self.cause);
+        csrs.vstval.save_value_in_main_memory(self.mtval);
+    }
+
     pub fn tried_to_access_valid_mmio_region(
         confidential_vm_id: ConfidentialVmId,
         fault_address: usize,
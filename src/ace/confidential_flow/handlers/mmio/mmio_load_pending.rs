@@ -7,13 +7,25 @@ use crate::ace::core::architecture::GeneralPurposeRegister;
 pub struct MmioLoadPending {
     instruction_length: usize,
     gpr_storing_load_result: GeneralPurposeRegister,
+    mtval: usize,
+    access_width: usize,
+    sign_extended: bool,
 }
 
 impl MmioLoadPending {
-    pub fn new(instruction_length: usize, gpr_storing_load_result: GeneralPurposeRegister) -> Self {
+    pub fn new(
+        instruction_length: usize,
+        gpr_storing_load_result: GeneralPurposeRegister,
+        mtval: usize,
+        access_width: usize,
+        sign_extended: bool,
+    ) -> Self {
         Self {
             instruction_length,
             gpr_storing_load_result,
+            mtval,
+            access_width,
+            sign_extended,
         }
     }
 
@@ -24,4 +36,60 @@ impl MmioLoadPending {
     pub fn gpr_storing_load_result(&self) -> GeneralPurposeRegister {
         self.gpr_storing_load_result
     }
+
+    pub fn mtval(&self) -> usize {
+        self.mtval
+    }
+
+    /// Validates the value returned by the hypervisor against the width of the faulted load and returns the value that must be
+    /// written to the destination register. The hypervisor might return the loaded value either zero- or sign-extended, any other
+    /// bits set above the access width indicate a malformed response and `None` is returned.
+    pub fn load_result(&self, value: usize) -> Option<usize> {
+        let bits = self.access_width * 8;
+        if bits >= usize::BITS as usize {
+            return Some(value);
+        }
+        let raw = value & ((1 << bits) - 1);
+        let sign_extended = ((raw << (usize::BITS as usize - bits)) as isize
+            >> (usize::BITS as usize - bits)) as usize;
+        if value != raw && value != sign_extended {
+            return None;
+        }
+        match self.sign_extended {
+            true => Some(sign_extended),
+            false => Some(raw),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending(access_width: usize, sign_extended: bool) -> MmioLoadPending {
+        MmioLoadPending::new(
+            4,
+            GeneralPurposeRegister::a0,
+            0,
+            access_width,
+            sign_extended,
+        )
+    }
+
+    #[test]
+    fn sign_extends_loaded_value() {
+        assert_eq!(pending(1, true).load_result(0x80), Some(usize::MAX - 0x7f));
+        assert_eq!(pending(1, false).load_result(0x80), Some(0x80));
+        assert_eq!(pending(2, true).load_result(0x7fff), Some(0x7fff));
+        assert_eq!(pending(4, true).load_result(0xffff_ffff), Some(usize::MAX));
+        assert_eq!(pending(4, false).load_result(usize::MAX), Some(0xffff_ffff));
+        assert_eq!(pending(8, false).load_result(usize::MAX), Some(usize::MAX));
+    }
+
+    #[test]
+    fn rejects_values_wider_than_access() {
+        assert_eq!(pending(1, false).load_result(0x100), None);
+        assert_eq!(pending(2, true).load_result(0x1_8000), None);
+        assert_eq!(pending(4, true).load_result(0xdead_0000_0000_0000), None);
+    }
 }
@@ -7,7 +7,9 @@ use crate::ace::confidential_flow::{ApplyToConfidentialHart, ConfidentialFlow};
 use crate::ace::core::architecture::is_bit_enabled;
 use crate::ace::core::architecture::specification::CAUSE_LOAD_ACCESS;
 use crate::ace::core::control_data::{ConfidentialHart, HypervisorHart, ResumableOperation};
+use crate::ace::error::Error;
 use crate::ace::non_confidential_flow::DeclassifyToHypervisor;
+use crate::ensure;
 
 /// Handles MMIO load request coming from the confidential hart. This request will be declassified to the hypervisor.
 pub struct MmioLoadRequest {
@@ -28,8 +30,8 @@ impl MmioLoadRequest {
     }
 
     pub fn handle(self, confidential_flow: ConfidentialFlow) -> ! {
-        // According to the RISC-V privilege spec, mtinst encodes faulted instruction (bit 0 is 1) or a pseudo instruction
-        assert!(self.mtinst & 0x1 > 0);
+        // According to the RISC-V privilege spec, mtinst encodes faulted instruction (bit 0 is 1), a pseudo instruction or zero. Only
+        // the faulted instruction can be decoded.
         let instruction = self.mtinst | 0x3;
         let instruction_length = if is_bit_enabled(self.mtinst, 1) {
             riscv_decode::instruction_length(instruction as u16)
@@ -49,11 +51,23 @@ impl MmioLoadRequest {
             );
         }
 
-        match crate::ace::core::architecture::decode_result_register(instruction) {
-            Ok(gpr) => confidential_flow
+        let decoded = ensure!(
+            is_bit_enabled(self.mtinst, 0),
+            Error::MissingTransformedInstruction(self.mtinst)
+        )
+        .and_then(|_| crate::ace::core::architecture::decode_result_register(instruction))
+        .and_then(|gpr| {
+            crate::ace::core::architecture::decode_load_width(instruction)
+                .map(|(access_width, sign_extended)| (gpr, access_width, sign_extended))
+        });
+        match decoded {
+            Ok((gpr, access_width, sign_extended)) => confidential_flow
                 .set_resumable_operation(ResumableOperation::MmioLoad(MmioLoadPending::new(
                     instruction_length,
                     gpr,
+                    self.mtval,
+                    access_width,
+                    sign_extended,
                 )))
                 .into_non_confidential_flow()
                 .declassify_and_exit_to_hypervisor(DeclassifyToHypervisor::MmioLoadRequest(self)),
@@ -1,9 +1,14 @@
 // SPDX-FileCopyrightText: 2023 IBM Corporation
 // SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
 // SPDX-License-Identifier: Apache-2.0
-use crate::ace::confidential_flow::handlers::mmio::MmioLoadPending;
-use crate::ace::confidential_flow::{ConfidentialFlow, DeclassifyToConfidentialVm};
+use crate::ace::confidential_flow::handlers::mmio::{MmioAccessFault, MmioLoadPending};
+use crate::ace::confidential_flow::{
+    ApplyToConfidentialHart, ConfidentialFlow, DeclassifyToConfidentialVm,
+};
+use crate::ace::core::architecture::specification::CAUSE_LOAD_ACCESS;
+use crate::ace::core::architecture::GeneralPurposeRegister;
 use crate::ace::core::control_data::{ConfidentialHart, HypervisorHart};
+use crate::debug;
 
 pub struct MmioLoadResponse {
     value: usize,
@@ -22,15 +27,39 @@ impl MmioLoadResponse {
     }
 
     pub fn handle(self, confidential_flow: ConfidentialFlow) -> ! {
-        confidential_flow.declassify_and_exit_to_confidential_hart(
-            DeclassifyToConfidentialVm::MmioLoadResponse(self),
-        )
+        // The hypervisor is untrusted, so the returned value must match the width of the faulted load. A malformed response is reported
+        // to the confidential hart as an access fault instead of being written to its registers.
+        match self.request.load_result(self.value) {
+            Some(value) => confidential_flow.declassify_and_exit_to_confidential_hart(
+                DeclassifyToConfidentialVm::MmioLoadResponse(Self {
+                    value,
+                    request: self.request,
+                }),
+            ),
+            None => {
+                debug!(
+                    "Malformed MMIO load response from the hypervisor: {:x}",
+                    self.value
+                );
+                let mmio_access_fault_handler = MmioAccessFault::new(
+                    CAUSE_LOAD_ACCESS.into(),
+                    self.request.mtval(),
+                    self.request.instruction_length(),
+                );
+                confidential_flow.apply_and_exit_to_confidential_hart(
+                    ApplyToConfidentialHart::MmioAccessFault(mmio_access_fault_handler),
+                )
+            }
+        }
     }
 
     pub fn declassify_to_confidential_hart(&self, confidential_hart: &mut ConfidentialHart) {
-        confidential_hart
-            .gprs_mut()
-            .write(self.request.gpr_storing_load_result(), self.value);
+        // Loads to x0 are legal but their result is discarded, the zero register must never be written.
+        if self.request.gpr_storing_load_result() != GeneralPurposeRegister::zero {
+            confidential_hart
+                .gprs_mut()
+                .write(self.request.gpr_storing_load_result(), self.value);
+        }
         confidential_hart
             .csrs_mut()
             .mepc
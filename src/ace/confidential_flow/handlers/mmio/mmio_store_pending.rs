@@ -5,14 +5,22 @@
 /// Information stored in the confidential hart that requested MMIO store and is waiting for the response.
 pub struct MmioStorePending {
     instruction_length: usize,
+    mtval: usize,
 }
 
 impl MmioStorePending {
-    pub fn new(instruction_length: usize) -> Self {
-        Self { instruction_length }
+    pub fn new(instruction_length: usize, mtval: usize) -> Self {
+        Self {
+            instruction_length,
+            mtval,
+        }
     }
 
     pub fn instruction_length(&self) -> usize {
         self.instruction_length
     }
+
+    pub fn mtval(&self) -> usize {
+        self.mtval
+    }
 }
@@ -9,6 +9,7 @@ use crate::ace::core::architecture::{is_bit_enabled, GeneralPurposeRegister};
 use crate::ace::core::control_data::{ConfidentialHart, HypervisorHart, ResumableOperation};
 use crate::ace::error::Error;
 use crate::ace::non_confidential_flow::DeclassifyToHypervisor;
+use crate::ensure;
 
 /// Handles MMIO store request coming from the confidential hart. This request will be declassified to the hypervisor.
 pub struct MmioStoreRequest {
@@ -29,15 +30,18 @@ impl MmioStoreRequest {
         let mtval2 = confidential_hart.csrs().mtval2.read();
 
         // According to the RISC-V privilege spec, mtinst encodes faulted instruction when bit 0 is 1.
-        // Otherwise it is a pseudo instruction.
-        assert!(mtinst & 0x1 > 0);
+        // Otherwise it is a pseudo instruction or zero, and the request cannot be decoded.
         let instruction = mtinst | 0x3;
         let instruction_length = if is_bit_enabled(mtinst, 1) {
             riscv_decode::instruction_length(instruction as u16)
         } else {
             2
         };
-        let gpr = crate::ace::core::architecture::decode_result_register(instruction);
+        let gpr = ensure!(
+            is_bit_enabled(mtinst, 0),
+            Error::MissingTransformedInstruction(mtinst)
+        )
+        .and_then(|_| crate::ace::core::architecture::decode_result_register(instruction));
         let gpr_value = gpr
             .as_ref()
             .and_then(|ref gpr| Ok(confidential_hart.gprs().read(**gpr)))
@@ -74,6 +78,7 @@ impl MmioStoreRequest {
             Ok(_) => confidential_flow
                 .set_resumable_operation(ResumableOperation::MmioStore(MmioStorePending::new(
                     self.instruction_length,
+                    self.mtval,
                 )))
                 .into_non_confidential_flow()
                 .declassify_and_exit_to_hypervisor(DeclassifyToHypervisor::MmioStoreRequest(self)),
@@ -1,10 +1,13 @@
 // SPDX-FileCopyrightText: 2023 IBM Corporation
 // SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
 // SPDX-License-Identifier: Apache-2.0
+pub mod attestation;
 pub mod interrupts;
+pub mod lazy_page;
 pub mod mmio;
 pub mod sbi;
 pub mod sbi_base_extension;
+pub mod sealing;
 pub mod shared_page;
 pub mod shutdown;
 pub mod symmetrical_multiprocessing;
@@ -0,0 +1,6 @@
+// SPDX-FileCopyrightText: 2023 IBM Corporation
+// SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
+// SPDX-License-Identifier: Apache-2.0
+pub use sealing_key_request::SealingKeyRequest;
+
+mod sealing_key_request;
@@ -0,0 +1,57 @@
+// SPDX-FileCopyrightText: 2023 IBM Corporation
+// SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
+// SPDX-License-Identifier: Apache-2.0
+use crate::ace::confidential_flow::handlers::sbi::SbiResponse;
+use crate::ace::confidential_flow::{ApplyToConfidentialHart, ConfidentialFlow};
+use crate::ace::core::architecture::GeneralPurposeRegister;
+use crate::ace::core::control_data::{ConfidentialHart, ConfidentialVmId, ControlDataStorage};
+use crate::ace::core::crypto::{KeyHierarchy, SealingKey};
+use crate::ace::core::memory_layout::ConfidentialVmPhysicalAddress;
+use crate::ace::error::Error;
+use crate::ensure;
+
+/// Handles the confidential VM's request to obtain a sealing key. The key is bound to the device and to the boot-time measurements of
+/// the confidential VM, so the confidential VM can persist encrypted state that only the same confidential VM on the same device can
+/// decrypt. The security monitor writes the key to the confidential VM's memory at the given guest physical address.
+pub struct SealingKeyRequest {
+    key_id: usize,
+    address: ConfidentialVmPhysicalAddress,
+    size: usize,
+}
+
+impl SealingKeyRequest {
+    pub fn from_confidential_hart(confidential_hart: &ConfidentialHart) -> Self {
+        Self {
+            key_id: confidential_hart.gprs().read(GeneralPurposeRegister::a0),
+            address: ConfidentialVmPhysicalAddress::new(
+                confidential_hart.gprs().read(GeneralPurposeRegister::a1),
+            ),
+            size: confidential_hart.gprs().read(GeneralPurposeRegister::a2),
+        }
+    }
+
+    pub fn handle(self, confidential_flow: ConfidentialFlow) -> ! {
+        let transformation = match self.write_sealing_key(confidential_flow.confidential_vm_id()) {
+            Ok(_) => SbiResponse::success_with_code(core::mem::size_of::<SealingKey>()),
+            Err(error) => SbiResponse::error(error),
+        };
+        confidential_flow.apply_and_exit_to_confidential_hart(ApplyToConfidentialHart::SbiResponse(
+            transformation,
+        ))
+    }
+
+    fn write_sealing_key(&self, confidential_vm_id: ConfidentialVmId) -> Result<(), Error> {
+        ensure!(
+            self.size >= core::mem::size_of::<SealingKey>(),
+            Error::InvalidParameter()
+        )?;
+
+        ControlDataStorage::try_confidential_vm_mut(confidential_vm_id, |mut confidential_vm| {
+            let sealing_key =
+                KeyHierarchy::sealing_key(confidential_vm.measurements(), self.key_id)?;
+            confidential_vm
+                .memory_protector_mut()
+                .write_to_confidential_vm(&self.address, &sealing_key)
+        })
+    }
+}
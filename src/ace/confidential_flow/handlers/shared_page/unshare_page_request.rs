@@ -15,7 +15,8 @@ use crate::ace::error::Error;
 use crate::ace::non_confidential_flow::DeclassifyToHypervisor;
 use crate::ensure;
 
-/// Unshared memory that has been previously shared with the hypervisor.
+/// Unshared memory that has been previously shared with the hypervisor. The memory can span multiple shared pages, in which case all
+/// pages are unmapped before a single fence is broadcast to the confidential harts.
 pub struct UnsharePageRequest {
     address: ConfidentialVmPhysicalAddress,
     size: usize,
@@ -60,23 +61,28 @@ impl UnsharePageRequest {
             Error::AddressNotAligned()
         )?;
         ensure!(
-            self.size == SharedPage::SIZE.in_bytes(),
+            self.size > 0
+                && self.size % SharedPage::SIZE.in_bytes() == 0
+                && self.address.usize().checked_add(self.size).is_some(),
             Error::InvalidParameter()
         )?;
 
         ControlDataStorage::try_confidential_vm_mut(confidential_vm_id, |mut confidential_vm| {
-            let unmapped_page_size = confidential_vm
-                .memory_protector_mut()
-                .unmap_shared_page(&self.address)?;
-            let request = RemoteHfenceGvmaVmid::all_harts(
-                &self.address,
-                unmapped_page_size,
-                confidential_vm_id,
-            );
+            // Unmapping stops at the first page that is not shared. The pages unmapped until then must be fenced nonetheless.
+            let result = (0..self.size)
+                .step_by(SharedPage::SIZE.in_bytes())
+                .try_for_each(|offset| {
+                    confidential_vm
+                        .memory_protector_mut()
+                        .unmap_shared_page(&self.address.add(offset))
+                        .map(|_| ())
+                });
+            let request =
+                RemoteHfenceGvmaVmid::all_harts_range(&self.address, self.size, confidential_vm_id);
             confidential_vm.broadcast_remote_command(
                 ConfidentialHartRemoteCommand::RemoteHfenceGvmaVmid(request),
             )?;
-            Ok(())
+            result
         })
     }
 }
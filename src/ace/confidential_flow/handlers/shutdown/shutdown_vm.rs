@@ -4,7 +4,9 @@
 use crate::ace::confidential_flow::handlers::sbi::SbiResponse;
 use crate::ace::confidential_flow::handlers::shutdown::shutdown_confidential_hart;
 use crate::ace::confidential_flow::{ApplyToConfidentialHart, ConfidentialFlow};
-use crate::ace::core::control_data::{ConfidentialHart, ConfidentialHartRemoteCommand};
+use crate::ace::core::control_data::{
+    ConfidentialHart, ConfidentialHartRemoteCommand, ControlDataStorage,
+};
 
 /// Handles the system reset call of the SBI's SRST extension. This call is a request to shutdown or reboot the
 /// confidential virtual machine. The security monitor allows only for the full shutdown of the confidential virtual
@@ -13,6 +15,9 @@ use crate::ace::core::control_data::{ConfidentialHart, ConfidentialHartRemoteCom
 /// To shutdown the entire confidential VM and remove it from the control data memory, all confidential harts must be
 /// shutdown (lifecycle state `Shutdown`). To do so, we send `Shutdown IPI` to all confidential harts. The last
 /// confidential hart that shutdowns itself, will remove the entire confidential VM from the control data.
+///
+/// All pages shared with the hypervisor are revoked before the other confidential harts are shutdown, so that no stale mapping to
+/// non-confidential memory survives the shutdown request.
 #[derive(Clone)]
 pub struct ShutdownRequest {
     calling_hart_id: usize,
@@ -26,9 +31,16 @@ impl ShutdownRequest {
     }
 
     pub fn handle(self, mut confidential_flow: ConfidentialFlow) -> ! {
-        match confidential_flow
-            .broadcast_remote_command(ConfidentialHartRemoteCommand::ShutdownRequest(self))
-        {
+        let confidential_vm_id = confidential_flow.confidential_vm_id();
+        let result = ControlDataStorage::try_confidential_vm_mut(
+            confidential_vm_id,
+            |mut confidential_vm| confidential_vm.revoke_shared_pages(),
+        )
+        .and_then(|_| {
+            confidential_flow
+                .broadcast_remote_command(ConfidentialHartRemoteCommand::ShutdownRequest(self))
+        });
+        match result {
             Ok(_) => shutdown_confidential_hart(confidential_flow),
             Err(error) => {
                 let transformation =
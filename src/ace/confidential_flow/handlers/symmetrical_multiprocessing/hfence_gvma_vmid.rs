@@ -1,7 +1,10 @@
 // SPDX-FileCopyrightText: 2023 IBM Corporation
 // SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
 // SPDX-License-Identifier: Apache-2.0
+use core::ops::Range;
+
 use crate::ace::confidential_flow::handlers::symmetrical_multiprocessing::Ipi;
+use crate::ace::core::architecture::riscv::fence::{hfence_gvma, hfence_gvma_gpa};
 use crate::ace::core::architecture::PageSize;
 use crate::ace::core::control_data::{
     ConfidentialHart, ConfidentialHartRemoteCommandExecutable, ConfidentialVmId,
@@ -9,36 +12,109 @@ use crate::ace::core::control_data::{
 use crate::ace::core::memory_layout::ConfidentialVmPhysicalAddress;
 
 /// An inter hart request sent by the security monitor to clear G-stage level cached address translations.
+///
+/// The request covers either a range of guest physical addresses or the entire address space of the confidential VM. Operations that
+/// unmap many pages at once send a single request covering all of them, and requests pending on the same confidential hart are merged,
+/// so that confidential harts are interrupted once instead of once per page.
 #[derive(Clone)]
 pub struct RemoteHfenceGvmaVmid {
     ipi: Ipi,
-    _start_address: usize,
-    _size: PageSize,
+    /// The guest physical addresses whose translations must be cleared, all translations of the confidential VM if `None`.
+    range: Option<Range<usize>>,
     _vmid: ConfidentialVmId,
 }
 
 impl RemoteHfenceGvmaVmid {
+    /// Above this number of pages, clearing all G-stage translations is cheaper than fencing every page of the range.
+    const MAX_NUMBER_OF_RANGED_FENCES: usize = 64;
+
     pub fn all_harts(
         start_address: &ConfidentialVmPhysicalAddress,
-        _size: PageSize,
+        size: PageSize,
+        vmid: ConfidentialVmId,
+    ) -> Self {
+        Self::all_harts_range(start_address, size.in_bytes(), vmid)
+    }
+
+    /// Clears the translations of `size_in_bytes` bytes of guest physical memory starting at `start_address`.
+    pub fn all_harts_range(
+        start_address: &ConfidentialVmPhysicalAddress,
+        size_in_bytes: usize,
         _vmid: ConfidentialVmId,
     ) -> Self {
+        let start = start_address.usize() & !(PageSize::Size4KiB.in_bytes() - 1);
+        Self {
+            ipi: Ipi::all_harts(),
+            range: Some(start..start_address.usize().saturating_add(size_in_bytes)),
+            _vmid,
+        }
+    }
+
+    /// Clears all translations of the confidential VM.
+    pub fn all_harts_full_vmid(_vmid: ConfidentialVmId) -> Self {
         Self {
             ipi: Ipi::all_harts(),
-            _start_address: start_address.usize(),
-            _size,
+            range: None,
             _vmid,
         }
     }
+
+    /// Extends this request to also clear the translations cleared by the `other` request.
+    pub fn merge(&mut self, other: &Self) {
+        self.range = match (&self.range, &other.range) {
+            (Some(range), Some(other_range)) => {
+                Some(range.start.min(other_range.start)..range.end.max(other_range.end))
+            }
+            _ => None,
+        };
+    }
 }
 
 impl ConfidentialHartRemoteCommandExecutable for RemoteHfenceGvmaVmid {
     fn execute_on_confidential_hart(&self, _confidential_hart: &mut ConfidentialHart) {
-        // TODO: execute a more fine grained fence. Right now, we just clear all tlbs
-        crate::ace::core::architecture::riscv::fence::hfence_gvma();
+        let page_size = PageSize::Size4KiB.in_bytes();
+        match &self.range {
+            Some(range)
+                if (range.end - range.start).div_ceil(page_size)
+                    <= Self::MAX_NUMBER_OF_RANGED_FENCES =>
+            {
+                range.clone().step_by(page_size).for_each(hfence_gvma_gpa)
+            }
+            _ => hfence_gvma(),
+        }
     }
 
     fn is_hart_selected(&self, hart_id: usize) -> bool {
         self.ipi.is_hart_selected(hart_id)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_hfence_ranges() {
+        let vmid = ConfidentialVmId::new(1);
+        let mut request = RemoteHfenceGvmaVmid::all_harts(
+            &ConfidentialVmPhysicalAddress::new(0x8000_2000),
+            PageSize::Size4KiB,
+            vmid,
+        );
+        request.merge(&RemoteHfenceGvmaVmid::all_harts_range(
+            &ConfidentialVmPhysicalAddress::new(0x8000_0010),
+            0x1000,
+            vmid,
+        ));
+        assert_eq!(request.range, Some(0x8000_0000..0x8000_3000));
+
+        request.merge(&RemoteHfenceGvmaVmid::all_harts_full_vmid(vmid));
+        assert_eq!(request.range, None);
+        request.merge(&RemoteHfenceGvmaVmid::all_harts(
+            &ConfidentialVmPhysicalAddress::new(0x8000_2000),
+            PageSize::Size4KiB,
+            vmid,
+        ));
+        assert_eq!(request.range, None);
+    }
+}
@@ -2,13 +2,18 @@
 // SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
 // SPDX-License-Identifier: Apache-2.0
 use crate::ace::confidential_flow::{ApplyToConfidentialHart, ConfidentialFlow};
-use crate::ace::core::architecture::riscv::specification::WFI_INSTRUCTION;
+use crate::ace::core::architecture::riscv::specification::{
+    CAUSE_ILLEGAL_INSTRUCTION, CSR_SIREG, CSR_STOPEI, WFI_INSTRUCTION,
+};
+use crate::ace::core::architecture::{GeneralPurposeRegister, CSR};
 use crate::ace::core::control_data::ConfidentialHart;
+use crate::debug;
 
 /// Handles virtual instruction trap that occured during execution of the confidential hart.
 pub struct VirtualInstruction {
     instruction: usize,
     instruction_length: usize,
+    imsic_access: Option<ImsicCsrAccess>,
 }
 
 impl VirtualInstruction {
@@ -19,24 +24,134 @@ impl VirtualInstruction {
         Self {
             instruction,
             instruction_length,
+            imsic_access: ImsicCsrAccess::decode(instruction),
         }
     }
 
     pub fn handle(self, confidential_flow: ConfidentialFlow) -> ! {
-        let transformation = if self.instruction == WFI_INSTRUCTION {
-            ApplyToConfidentialHart::VirtualInstruction(self)
-        } else {
-            // TODO: add support for some CSR manipulation
-            // TODO: for not supported instructions, inject illegal instruction exception to the guest
-            panic!("Not supported virtual instruction: {:x}", self.instruction);
-        };
-        confidential_flow.apply_and_exit_to_confidential_hart(transformation)
+        if !self.is_supported() {
+            debug!("Not supported virtual instruction: {:x}", self.instruction);
+        }
+        confidential_flow
+            .apply_and_exit_to_confidential_hart(ApplyToConfidentialHart::VirtualInstruction(self))
     }
 
     pub fn apply_to_confidential_hart(&self, confidential_hart: &mut ConfidentialHart) {
+        if !self.is_supported() {
+            // Not supported instructions raise an illegal instruction exception in the guest, at the faulting instruction.
+            let mepc = confidential_hart.csrs().mepc.read_from_main_memory();
+            confidential_hart.csrs_mut().vsepc.write(mepc);
+            let trap_vector_address = confidential_hart.csrs().vstvec.read();
+            confidential_hart
+                .csrs_mut()
+                .mepc
+                .save_value_in_main_memory(trap_vector_address);
+            confidential_hart
+                .csrs_mut()
+                .vscause
+                .write(CAUSE_ILLEGAL_INSTRUCTION.into());
+            confidential_hart.csrs_mut().vstval.write(self.instruction);
+            return;
+        }
+        if let Some(ref access) = self.imsic_access {
+            access.emulate(confidential_hart);
+        }
         confidential_hart
             .csrs_mut()
             .mepc
             .add(self.instruction_length);
     }
+
+    fn is_supported(&self) -> bool {
+        self.instruction == WFI_INSTRUCTION || self.imsic_access.is_some()
+    }
+}
+
+/// The kind of read-modify-write performed by a CSR instruction.
+enum CsrOperation {
+    Write,
+    Set,
+    Clear,
+}
+
+/// An access to the interrupt file CSRs (`stopei` and `sireg`). Confidential harts run without a guest interrupt file, so these
+/// accesses trap and are emulated on the confidential hart's software interrupt file.
+struct ImsicCsrAccess {
+    csr: u16,
+    operation: CsrOperation,
+    rd: usize,
+    /// Index of the source register, or the immediate value if `is_immediate` is true.
+    source: usize,
+    is_immediate: bool,
+}
+
+impl ImsicCsrAccess {
+    fn decode(instruction: usize) -> Option<Self> {
+        use riscv_decode::Instruction::{Csrrc, Csrrci, Csrrs, Csrrsi, Csrrw, Csrrwi};
+        let (csr, operation, rd, source, is_immediate) =
+            match riscv_decode::decode(instruction as u32) {
+                Ok(Csrrw(i)) => (i.csr(), CsrOperation::Write, i.rd(), i.rs1(), false),
+                Ok(Csrrs(i)) => (i.csr(), CsrOperation::Set, i.rd(), i.rs1(), false),
+                Ok(Csrrc(i)) => (i.csr(), CsrOperation::Clear, i.rd(), i.rs1(), false),
+                Ok(Csrrwi(i)) => (i.csr(), CsrOperation::Write, i.rd(), i.zimm(), true),
+                Ok(Csrrsi(i)) => (i.csr(), CsrOperation::Set, i.rd(), i.zimm(), true),
+                Ok(Csrrci(i)) => (i.csr(), CsrOperation::Clear, i.rd(), i.zimm(), true),
+                _ => return None,
+            };
+        let csr = csr as u16;
+        match csr == CSR_STOPEI || csr == CSR_SIREG {
+            true => Some(Self {
+                csr,
+                operation,
+                rd: rd as usize,
+                source: source as usize,
+                is_immediate,
+            }),
+            false => None,
+        }
+    }
+
+    fn emulate(&self, confidential_hart: &mut ConfidentialHart) {
+        let operand = match self.is_immediate {
+            true => self.source,
+            false => GeneralPurposeRegister::try_from(self.source)
+                .map_or(0, |register| confidential_hart.gprs().read(register)),
+        };
+        // Set and clear operations with the zero register or a zero immediate do not write the CSR.
+        let is_write = matches!(self.operation, CsrOperation::Write) || self.source != 0;
+
+        let interrupt_file = confidential_hart.interrupt_file_mut();
+        let old_value = if self.csr == CSR_STOPEI {
+            let value = interrupt_file.read_topei();
+            if is_write {
+                // Any write to stopei claims the reported interrupt, the written value is ignored.
+                interrupt_file.claim_top_interrupt();
+            }
+            value
+        } else {
+            // The guest's accesses to siselect are redirected by the hardware to vsiselect.
+            let select = CSR.vsiselect.read();
+            let value = interrupt_file.read_register(select).unwrap_or(0);
+            if is_write {
+                let new_value = match self.operation {
+                    CsrOperation::Write => operand,
+                    CsrOperation::Set => value | operand,
+                    CsrOperation::Clear => value & !operand,
+                };
+                if interrupt_file.write_register(select, new_value).is_err() {
+                    debug!(
+                        "Ignored write to unsupported interrupt file register {:x}",
+                        select
+                    );
+                }
+            }
+            value
+        };
+
+        if let Ok(register) = GeneralPurposeRegister::try_from(self.rd) {
+            if register != GeneralPurposeRegister::zero {
+                confidential_hart.gprs_mut().write(register, old_value);
+            }
+        }
+    }
 }
@@ -8,8 +8,11 @@
 use core::arch::asm;
 
 use super::specification::*;
+#[cfg(feature = "userspace")]
+use super::userspace;
 use crate::ace::core::architecture::riscv::sbi::NaclSharedMemory;
-use crate::ace::core::control_data::{DigestType, MeasurementDigest};
+use crate::ace::core::control_data::MeasurementDigest;
+use crate::ace::core::crypto::{Crypto, CryptoBackend, Hasher};
 
 /// Represents all control status registers (CSRs) accessible to modes less privileged than M-mode.
 pub struct ControlStatusRegisters {
@@ -237,57 +240,56 @@ impl ControlStatusRegisters {
 
     /// Extends the measurement digest with the context of all CSRs.
     pub fn measure(&self, digest: &mut MeasurementDigest) {
-        use sha2::Digest;
-        let mut hasher = DigestType::new_with_prefix(digest.clone());
-        hasher.update(self.mepc.read_from_main_memory().to_le_bytes());
-        hasher.update(self.mcause.read_from_main_memory().to_le_bytes());
-        hasher.update(self.medeleg.read_from_main_memory().to_le_bytes());
-        hasher.update(self.mideleg.read_from_main_memory().to_le_bytes());
-        hasher.update(self.mie.read_from_main_memory().to_le_bytes());
-        hasher.update(self.mstatus.read_from_main_memory().to_le_bytes());
-        hasher.update(self.mtinst.read_from_main_memory().to_le_bytes());
-        hasher.update(self.mtval.read_from_main_memory().to_le_bytes());
-        hasher.update(self.mtval2.read_from_main_memory().to_le_bytes());
-        hasher.update(self.mtvec.read_from_main_memory().to_le_bytes());
+        let mut hasher = Crypto::hasher_with_prefix(digest);
+        hasher.update(&self.mepc.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.mcause.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.medeleg.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.mideleg.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.mie.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.mstatus.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.mtinst.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.mtval.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.mtval2.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.mtvec.read_from_main_memory().to_le_bytes());
         // S-mode
-        hasher.update(self.sstatus.read_from_main_memory().to_le_bytes());
-        hasher.update(self.sie.read_from_main_memory().to_le_bytes());
-        hasher.update(self.stvec.read_from_main_memory().to_le_bytes());
-        hasher.update(self.scounteren.read_from_main_memory().to_le_bytes());
-        hasher.update(self.senvcfg.read_from_main_memory().to_le_bytes());
-        hasher.update(self.sscratch.read_from_main_memory().to_le_bytes());
-        hasher.update(self.sepc.read_from_main_memory().to_le_bytes());
-        hasher.update(self.scause.read_from_main_memory().to_le_bytes());
-        hasher.update(self.stval.read_from_main_memory().to_le_bytes());
-        hasher.update(self.sip.read_from_main_memory().to_le_bytes());
-        hasher.update(self.satp.read_from_main_memory().to_le_bytes());
-        hasher.update(self.scontext.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.sstatus.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.sie.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.stvec.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.scounteren.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.senvcfg.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.sscratch.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.sepc.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.scause.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.stval.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.sip.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.satp.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.scontext.read_from_main_memory().to_le_bytes());
         // HS-mode
-        hasher.update(self.hstatus.read_from_main_memory().to_le_bytes());
-        hasher.update(self.hedeleg.read_from_main_memory().to_le_bytes());
-        hasher.update(self.hideleg.read_from_main_memory().to_le_bytes());
-        hasher.update(self.hie.read_from_main_memory().to_le_bytes());
-        hasher.update(self.hcounteren.read_from_main_memory().to_le_bytes());
-        hasher.update(self.hgeie.read_from_main_memory().to_le_bytes());
-        hasher.update(self.htval.read_from_main_memory().to_le_bytes());
-        hasher.update(self.hip.read_from_main_memory().to_le_bytes());
-        hasher.update(self.hvip.read_from_main_memory().to_le_bytes());
-        hasher.update(self.htinst.read_from_main_memory().to_le_bytes());
-        hasher.update(self.hgeip.read_from_main_memory().to_le_bytes());
-        hasher.update(self.henvcfg.read_from_main_memory().to_le_bytes());
-        hasher.update(self.hgatp.read_from_main_memory().to_le_bytes());
-        hasher.update(self.hcontext.read_from_main_memory().to_le_bytes());
-        hasher.update(self.htimedelta.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.hstatus.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.hedeleg.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.hideleg.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.hie.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.hcounteren.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.hgeie.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.htval.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.hip.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.hvip.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.htinst.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.hgeip.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.henvcfg.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.hgatp.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.hcontext.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.htimedelta.read_from_main_memory().to_le_bytes());
         // VS-mode
-        hasher.update(self.vsstatus.read_from_main_memory().to_le_bytes());
-        hasher.update(self.vsie.read_from_main_memory().to_le_bytes());
-        hasher.update(self.vsip.read_from_main_memory().to_le_bytes());
-        hasher.update(self.vstvec.read_from_main_memory().to_le_bytes());
-        hasher.update(self.vsscratch.read_from_main_memory().to_le_bytes());
-        hasher.update(self.vsepc.read_from_main_memory().to_le_bytes());
-        hasher.update(self.vscause.read_from_main_memory().to_le_bytes());
-        hasher.update(self.vstval.read_from_main_memory().to_le_bytes());
-        hasher.update(self.vsatp.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.vsstatus.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.vsie.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.vsip.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.vstvec.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.vsscratch.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.vsepc.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.vscause.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.vstval.read_from_main_memory().to_le_bytes());
+        hasher.update(&self.vsatp.read_from_main_memory().to_le_bytes());
         hasher.finalize_into(digest);
     }
 }
@@ -302,6 +304,7 @@ pub struct ControlStatusRegister {
     pub pmpcfg0: ReadWriteRiscvCsr<CSR_PMPCFG0>,
     pub pmpaddr4: ReadWriteRiscvCsr<CSR_PMPADDR4>,
     pub pmpaddr5: ReadWriteRiscvCsr<CSR_PMPADDR5>,
+    pub vsiselect: ReadWriteRiscvCsr<CSR_VSISELECT>,
 }
 
 pub const CSR: &ControlStatusRegister = &ControlStatusRegister {
@@ -314,6 +317,7 @@ pub const CSR: &ControlStatusRegister = &ControlStatusRegister {
     pmpcfg0: ReadWriteRiscvCsr::new(),
     pmpaddr4: ReadWriteRiscvCsr::new(),
     pmpaddr5: ReadWriteRiscvCsr::new(),
+    vsiselect: ReadWriteRiscvCsr::new(),
 };
 
 #[derive(Copy, Clone)]
@@ -373,6 +377,7 @@ impl<const V: u16> ReadWriteRiscvCsr<V> {
     }
 
     #[inline]
+    #[cfg(not(feature = "userspace"))]
     pub fn read(&self) -> usize {
         let r: usize;
         unsafe {
@@ -382,6 +387,7 @@ impl<const V: u16> ReadWriteRiscvCsr<V> {
     }
 
     #[inline]
+    #[cfg(not(feature = "userspace"))]
     pub fn write(&self, val_to_set: usize) {
         unsafe {
             asm!("csrw {csr}, {rs}", rs = in(reg) val_to_set, csr = const V);
@@ -389,6 +395,7 @@ impl<const V: u16> ReadWriteRiscvCsr<V> {
     }
 
     #[inline]
+    #[cfg(not(feature = "userspace"))]
     pub fn read_and_set_bits(&self, bitmask: usize) -> usize {
         let r: usize;
         unsafe {
@@ -401,6 +408,7 @@ impl<const V: u16> ReadWriteRiscvCsr<V> {
     }
 
     #[inline]
+    #[cfg(not(feature = "userspace"))]
     pub fn read_and_clear_bits(&self, bitmask: usize) -> usize {
         let r: usize;
         unsafe {
@@ -411,6 +419,26 @@ impl<const V: u16> ReadWriteRiscvCsr<V> {
         }
         r
     }
+
+    #[cfg(feature = "userspace")]
+    pub fn read(&self) -> usize {
+        userspace::read_csr(V)
+    }
+
+    #[cfg(feature = "userspace")]
+    pub fn write(&self, val_to_set: usize) {
+        userspace::write_csr(V, val_to_set)
+    }
+
+    #[cfg(feature = "userspace")]
+    pub fn read_and_set_bits(&self, bitmask: usize) -> usize {
+        userspace::read_and_set_csr_bits(V, bitmask)
+    }
+
+    #[cfg(feature = "userspace")]
+    pub fn read_and_clear_bits(&self, bitmask: usize) -> usize {
+        userspace::read_and_clear_csr_bits(V, bitmask)
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -422,6 +450,7 @@ impl<const V: u16> ReadRiscvCsr<V> {
     }
 
     #[inline]
+    #[cfg(not(feature = "userspace"))]
     pub fn read(&self) -> usize {
         let r: usize;
         unsafe {
@@ -429,4 +458,9 @@ impl<const V: u16> ReadRiscvCsr<V> {
         }
         r
     }
+
+    #[cfg(feature = "userspace")]
+    pub fn read(&self) -> usize {
+        userspace::read_csr(V)
+    }
 }
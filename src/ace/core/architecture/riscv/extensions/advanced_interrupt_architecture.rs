@@ -0,0 +1,197 @@
+// SPDX-FileCopyrightText: 2023 IBM Corporation
+// SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
+// SPDX-License-Identifier: Apache-2.0
+use crate::ace::core::architecture::specification::{
+    IMSIC_EIDELIVERY, IMSIC_EIE0, IMSIC_EIE63, IMSIC_EIP0, IMSIC_EIP63, IMSIC_EITHRESHOLD,
+    IMSIC_MAX_NUMBER_OF_INTERRUPT_IDS, IMSIC_TOPEI_ID_SHIFT,
+};
+use crate::ace::error::Error;
+use crate::ensure;
+
+/// On RV64, only the even-numbered eip and eie registers exist and each holds 64 interrupt identities.
+const NUMBER_OF_WORDS: usize = IMSIC_MAX_NUMBER_OF_INTERRUPT_IDS / 64;
+
+/// Software implementation of an IMSIC interrupt file, as defined by the RISC-V Advanced Interrupt Architecture (AIA).
+///
+/// The security monitor delivers the external interrupts injected by the hypervisor to a confidential hart through this interrupt file
+/// instead of a hardware guest interrupt file, which the hypervisor could otherwise tamper with. Confidential harts execute with
+/// `hstatus.VGEIN` set to zero, so their accesses to `stopei` and to the interrupt file registers through `siselect`/`sireg` raise virtual
+/// instruction exceptions and are emulated on this structure.
+#[derive(Clone)]
+pub struct ImsicInterruptFile {
+    /// Number of interrupt identities, zero until the confidential VM's AIA is initialized.
+    number_of_ids: usize,
+    eidelivery: usize,
+    eithreshold: usize,
+    eip: [u64; NUMBER_OF_WORDS],
+    eie: [u64; NUMBER_OF_WORDS],
+}
+
+impl ImsicInterruptFile {
+    pub const fn empty() -> Self {
+        Self {
+            number_of_ids: 0,
+            eidelivery: 0,
+            eithreshold: 0,
+            eip: [0; NUMBER_OF_WORDS],
+            eie: [0; NUMBER_OF_WORDS],
+        }
+    }
+
+    /// Enables the interrupt identities from 1 to `number_of_ids - 1`. Returns error if the number of identities is not supported.
+    pub fn configure(&mut self, number_of_ids: usize) -> Result<(), Error> {
+        ensure!(
+            number_of_ids > 1 && number_of_ids <= IMSIC_MAX_NUMBER_OF_INTERRUPT_IDS,
+            Error::InvalidParameter()
+        )?;
+        *self = Self::empty();
+        self.number_of_ids = number_of_ids;
+        Ok(())
+    }
+
+    /// Sets the pending bit of the interrupt identity, as a write to the `seteipnum` register of a hardware interrupt file would.
+    pub fn set_pending(&mut self, id: usize) -> Result<(), Error> {
+        ensure!(id > 0 && id < self.number_of_ids, Error::InvalidParameter())?;
+        self.eip[id / 64] |= 1 << (id % 64);
+        Ok(())
+    }
+
+    /// Returns true if an external interrupt must be signaled to the confidential hart.
+    pub fn is_interrupt_pending(&self) -> bool {
+        self.eidelivery == 1 && self.top_interrupt().is_some()
+    }
+
+    /// Returns the value of the `stopei` register, which reports the pending and enabled interrupt with the highest priority.
+    pub fn read_topei(&self) -> usize {
+        self.top_interrupt()
+            .map_or(0, |id| (id << IMSIC_TOPEI_ID_SHIFT) | id)
+    }
+
+    /// Claims the interrupt reported by `stopei`, as any write to the `stopei` register does.
+    pub fn claim_top_interrupt(&mut self) {
+        if let Some(id) = self.top_interrupt() {
+            self.eip[id / 64] &= !(1 << (id % 64));
+        }
+    }
+
+    /// Reads the interrupt file register selected with `siselect`.
+    pub fn read_register(&self, select: usize) -> Result<usize, Error> {
+        match select {
+            IMSIC_EIDELIVERY => Ok(self.eidelivery),
+            IMSIC_EITHRESHOLD => Ok(self.eithreshold),
+            IMSIC_EIP0..=IMSIC_EIP63 => Ok(self.eip[Self::word(select - IMSIC_EIP0)?] as usize),
+            IMSIC_EIE0..=IMSIC_EIE63 => Ok(self.eie[Self::word(select - IMSIC_EIE0)?] as usize),
+            _ => Err(Error::InvalidParameter()),
+        }
+    }
+
+    /// Writes the interrupt file register selected with `siselect`. Bits of unimplemented interrupt identities are read-only zeros.
+    pub fn write_register(&mut self, select: usize, value: usize) -> Result<(), Error> {
+        match select {
+            IMSIC_EIDELIVERY => self.eidelivery = value & 1,
+            IMSIC_EITHRESHOLD => {
+                self.eithreshold = value & (IMSIC_MAX_NUMBER_OF_INTERRUPT_IDS - 1);
+                if self.eithreshold >= self.number_of_ids {
+                    self.eithreshold = 0;
+                }
+            }
+            IMSIC_EIP0..=IMSIC_EIP63 => {
+                let word = Self::word(select - IMSIC_EIP0)?;
+                self.eip[word] = value as u64 & self.implemented_ids(word);
+            }
+            IMSIC_EIE0..=IMSIC_EIE63 => {
+                let word = Self::word(select - IMSIC_EIE0)?;
+                self.eie[word] = value as u64 & self.implemented_ids(word);
+            }
+            _ => return Err(Error::InvalidParameter()),
+        }
+        Ok(())
+    }
+
+    /// Returns the pending and enabled interrupt with the highest priority (i.e., the lowest identity) below the threshold, if any.
+    fn top_interrupt(&self) -> Option<usize> {
+        let id = self
+            .eip
+            .iter()
+            .zip(self.eie.iter())
+            .enumerate()
+            .find(|(_, (eip, eie))| *eip & *eie != 0)
+            .map(|(word, (eip, eie))| word * 64 + (eip & eie).trailing_zeros() as usize)?;
+        match self.eithreshold == 0 || id < self.eithreshold {
+            true => Some(id),
+            false => None,
+        }
+    }
+
+    /// Returns the index of the word holding the register at the given offset from eip0 or eie0.
+    fn word(offset: usize) -> Result<usize, Error> {
+        ensure!(offset % 2 == 0, Error::InvalidParameter())?;
+        Ok(offset / 2)
+    }
+
+    /// Returns the mask of implemented interrupt identities in the given word, identity 0 is never implemented.
+    fn implemented_ids(&self, word: usize) -> u64 {
+        let first_id = word * 64;
+        let mask = match self.number_of_ids.saturating_sub(first_id) {
+            0 => 0,
+            n if n >= 64 => u64::MAX,
+            n => (1 << n) - 1,
+        };
+        match word {
+            0 => mask & !1,
+            _ => mask,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interrupt_file() -> ImsicInterruptFile {
+        let mut file = ImsicInterruptFile::empty();
+        file.configure(128).unwrap();
+        file.write_register(IMSIC_EIDELIVERY, 1).unwrap();
+        file
+    }
+
+    #[test]
+    fn delivers_enabled_interrupts_by_priority() {
+        let mut file = interrupt_file();
+        file.set_pending(70).unwrap();
+        file.set_pending(5).unwrap();
+        assert!(!file.is_interrupt_pending());
+
+        file.write_register(IMSIC_EIE0, usize::MAX).unwrap();
+        file.write_register(IMSIC_EIE0 + 2, usize::MAX).unwrap();
+        assert!(file.is_interrupt_pending());
+        assert_eq!(file.read_topei(), (5 << IMSIC_TOPEI_ID_SHIFT) | 5);
+
+        file.claim_top_interrupt();
+        assert_eq!(file.read_topei(), (70 << IMSIC_TOPEI_ID_SHIFT) | 70);
+
+        // Interrupts at or above the threshold are not delivered
+        file.write_register(IMSIC_EITHRESHOLD, 70).unwrap();
+        assert!(!file.is_interrupt_pending());
+        assert_eq!(file.read_topei(), 0);
+    }
+
+    #[test]
+    fn rejects_unimplemented_identities() {
+        let mut file = interrupt_file();
+        assert!(file.set_pending(0).is_err());
+        assert!(file.set_pending(128).is_err());
+        assert!(file
+            .configure(IMSIC_MAX_NUMBER_OF_INTERRUPT_IDS + 1)
+            .is_err());
+
+        // Identity 0 and identities above the limit are read-only zeros
+        file.write_register(IMSIC_EIE0, usize::MAX).unwrap();
+        assert_eq!(file.read_register(IMSIC_EIE0).ok(), Some(usize::MAX - 1));
+        file.write_register(IMSIC_EIE0 + 4, usize::MAX).unwrap();
+        assert_eq!(file.read_register(IMSIC_EIE0 + 4).ok(), Some(0));
+
+        // Odd-numbered registers do not exist on RV64
+        assert!(file.read_register(IMSIC_EIP0 + 1).is_err());
+    }
+}
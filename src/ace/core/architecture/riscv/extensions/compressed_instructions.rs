@@ -90,3 +90,26 @@ pub fn decode_result_register(mtinst: usize) -> Result<GeneralPurposeRegister, E
     Ok(GeneralPurposeRegister::try_from(register_index)
         .map_err(|_| Error::InvalidCompressedRiscvInstruction(mtinst))?)
 }
+
+/// Returns the number of bytes read by the load instruction and whether the loaded value is sign-extended to the register width.
+pub fn decode_load_width(mtinst: usize) -> Result<(usize, bool), Error> {
+    use riscv_decode::Instruction::{Lb, Lbu, Ld, Lh, Lhu, Lw, Lwu};
+    match riscv_decode::decode(mtinst as u32) {
+        Ok(Lb(_)) => Ok((1, true)),
+        Ok(Lbu(_)) => Ok((1, false)),
+        Ok(Lh(_)) => Ok((2, true)),
+        Ok(Lhu(_)) => Ok((2, false)),
+        Ok(Lw(_)) => Ok((4, true)),
+        Ok(Lwu(_)) => Ok((4, false)),
+        Ok(Ld(_)) => Ok((8, false)),
+        _ => {
+            // Compressed instructions only load words (c.lw, c.lwsp) and double words (c.ld, c.ldsp).
+            const INSN_MASK_C_LOAD: usize = 0xe003;
+            match mtinst & INSN_MASK_C_LOAD {
+                0x4000 | 0x4002 => Ok((4, true)),
+                0x6000 | 0x6002 => Ok((8, false)),
+                _ => Err(Error::InvalidCompressedRiscvInstruction(mtinst)),
+            }
+        }
+    }
+}
@@ -3,6 +3,7 @@
 // SPDX-License-Identifier: Apache-2.0
 use crate::ace::core::architecture::specification::{F_EXTENSION, SSTC_EXTENSION, V_EXTENSION};
 
+pub mod advanced_interrupt_architecture;
 pub mod compressed_instructions;
 pub mod floating_point_unit;
 pub mod supervisor_timer_extension;
@@ -4,21 +4,44 @@
 #![allow(unused)]
 
 pub fn fence_wo() {
-    unsafe { core::arch::asm!("fence w,o") };
+    #[cfg(not(feature = "userspace"))]
+    unsafe {
+        core::arch::asm!("fence w,o")
+    };
 }
 
 pub fn hfence_gvma() {
-    unsafe { core::arch::asm!("hfence.gvma") };
+    #[cfg(not(feature = "userspace"))]
+    unsafe {
+        core::arch::asm!("hfence.gvma")
+    };
+}
+
+/// Clears the G-stage address translations of the given guest physical address for all VMIDs.
+pub fn hfence_gvma_gpa(guest_physical_address: usize) {
+    #[cfg(not(feature = "userspace"))]
+    unsafe {
+        core::arch::asm!("hfence.gvma {}, zero", in(reg) guest_physical_address >> 2)
+    };
 }
 
 pub fn hfence_vvma() {
-    unsafe { core::arch::asm!("hfence.vvma") };
+    #[cfg(not(feature = "userspace"))]
+    unsafe {
+        core::arch::asm!("hfence.vvma")
+    };
 }
 
 pub fn sfence_vma() {
-    unsafe { core::arch::asm!("sfence.vma") };
+    #[cfg(not(feature = "userspace"))]
+    unsafe {
+        core::arch::asm!("sfence.vma")
+    };
 }
 
 pub fn fence_i() {
-    unsafe { core::arch::asm!("fence.i") };
+    #[cfg(not(feature = "userspace"))]
+    unsafe {
+        core::arch::asm!("fence.i")
+    };
 }
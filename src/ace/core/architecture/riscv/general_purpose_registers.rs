@@ -4,13 +4,14 @@
 use alloc::vec::Vec;
 use core::ops::Range;
 
-use crate::ace::core::control_data::{DigestType, MeasurementDigest};
+use crate::ace::core::control_data::MeasurementDigest;
+use crate::ace::core::crypto::{Crypto, CryptoBackend, Hasher};
 
 #[repr(C)]
 pub struct GeneralPurposeRegisters(pub(crate) [usize; 32]);
 
 impl GeneralPurposeRegisters {
-    const LEN: usize = 32;
+    pub const LEN: usize = 32;
 
     pub fn empty() -> Self {
         Self([0; Self::LEN])
@@ -26,11 +27,10 @@ impl GeneralPurposeRegisters {
 
     /// Extends the measurement digest with the context of all GPRs.
     pub fn measure(&self, digest: &mut MeasurementDigest) {
-        use sha2::Digest;
-        let mut hasher = DigestType::new_with_prefix(digest.clone());
+        let mut hasher = Crypto::hasher_with_prefix(digest);
         self.0
             .iter()
-            .for_each(|gpr_value| hasher.update(gpr_value.to_le_bytes()));
+            .for_each(|gpr_value| hasher.update(&gpr_value.to_le_bytes()));
         hasher.finalize_into(digest);
     }
 
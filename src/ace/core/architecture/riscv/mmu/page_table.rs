@@ -56,6 +56,10 @@ pub struct PageTable {
 /// - if input to copy_.. is not a valid page table, fail correctly
 
 impl PageTable {
+    /// The largest data page into which smaller data pages are coalesced. Larger pages are unlikely to be available in the confidential
+    /// memory.
+    const LARGEST_COALESCED_PAGE_SIZE: PageSize = PageSize::Size1GiB;
+
     /// This functions copies recursively page table structure from non-confidential memory to confidential memory. It
     /// allocated a page in confidential memory for every page table. After this function executes, a valid page table
     /// configuration is in the confidential memory. Returns error if there is not enough memory to allocate this data structure.
@@ -163,7 +167,8 @@ impl PageTable {
 
     /// This function maps the given page shared with the hypervisor into the address space of the confidential VM. A previous mapping at
     /// the given guest physical address is overwritten. If the previous mapping pointed to a page in confidential memory, this page is
-    /// deallocated and returned to the page allocator.
+    /// deallocated and returned to the page allocator. If the previous mapping is a larger page of confidential memory (see
+    /// `coalesce_pages`), this page is first split into smaller pages, so that only the memory of the shared page is unmapped.
     ///
     /// This is a recursive function, which deepest execution is not larger than the number of paging system levels.
     ///
@@ -202,6 +207,11 @@ impl PageTable {
                         LogicalPageTableEntry::PointerToNextPageTable(Box::new(next_page_table)),
                     );
                 }
+                LogicalPageTableEntry::PageWithConfidentialVmData(_) => {
+                    // The shared page is inside a coalesced page, which we split so that only the shared page is overwritten.
+                    self.split_entry(virtual_page_number)?;
+                    return self.map_shared_page(shared_page);
+                }
                 _ => return Err(Error::PageTableConfiguration()),
             }
         } else {
@@ -215,6 +225,64 @@ impl PageTable {
         Ok(())
     }
 
+    /// This function maps the given page of confidential memory into the address space of the confidential VM at the given guest physical
+    /// address. Returns error if there is already a mapping at this address, in which case the page is given back to the caller.
+    ///
+    /// This is a recursive function, which deepest execution is not larger than the number of paging system levels.
+    ///
+    /// # Confidential VM execution correctness
+    ///
+    /// The caller of this function must ensure that he synchronizes changes to page table configuration, i.e., by clearing address
+    /// translation caches.
+    pub fn map_confidential_page(
+        &mut self,
+        address: &ConfidentialVmPhysicalAddress,
+        page: Page<Allocated>,
+    ) -> Result<(), (Page<Allocated>, Error)> {
+        let page_size_at_current_level = self.paging_system.data_page_size(self.level);
+        if page_size_at_current_level < *page.size() {
+            return Err((page, Error::InvalidParameter()));
+        }
+
+        let virtual_page_number = self.paging_system.vpn(address, self.level);
+        let Some(entry) = self.logical_representation.get_mut(virtual_page_number) else {
+            return Err((page, Error::PageTableConfiguration()));
+        };
+        match entry {
+            LogicalPageTableEntry::PointerToNextPageTable(next_page_table)
+                if page_size_at_current_level > *page.size() =>
+            {
+                next_page_table.map_confidential_page(address, page)
+            }
+            LogicalPageTableEntry::NotMapped if page_size_at_current_level > *page.size() => {
+                // We are at the intermediary page table. We create the next page table because it does not exist yet.
+                let lower_level = match self.level.lower() {
+                    Some(lower_level) => lower_level,
+                    None => return Err((page, Error::PageTableCorrupted())),
+                };
+                let mut next_page_table = match PageTable::empty(self.paging_system, lower_level) {
+                    Ok(next_page_table) => next_page_table,
+                    Err(error) => return Err((page, error)),
+                };
+                next_page_table.map_confidential_page(address, page)?;
+                self.set_entry(
+                    virtual_page_number,
+                    LogicalPageTableEntry::PointerToNextPageTable(Box::new(next_page_table)),
+                );
+                Ok(())
+            }
+            LogicalPageTableEntry::NotMapped => {
+                // We are at the correct page table level and there is no mapping yet. We end the recursion here.
+                self.set_entry(
+                    virtual_page_number,
+                    LogicalPageTableEntry::PageWithConfidentialVmData(Box::new(page)),
+                );
+                Ok(())
+            }
+            _ => Err((page, Error::PageTableConfiguration())),
+        }
+    }
+
     /// Removes a shared page from the address space of the confidential VM. Returns error if there is no shared page mapped at the given
     /// address. Returns the size of the unmapped shared page on succeess.
     ///
@@ -301,6 +369,123 @@ impl PageTable {
             })
     }
 
+    /// Recursively calls the function with the guest physical address and the page of all data pages, in the order from the page with the
+    /// lowest to the highest guest physical address. Returns error if the page table contains a shared page mapping.
+    pub fn for_each_confidential_page<F>(&self, address: usize, f: &mut F) -> Result<(), Error>
+    where
+        F: FnMut(usize, &Page<Allocated>) -> Result<(), Error>,
+    {
+        self.logical_representation
+            .iter()
+            .enumerate()
+            .try_for_each(|(i, entry)| {
+                let guest_physical_address =
+                    address + i * self.paging_system.data_page_size(self.level).in_bytes();
+                match entry {
+                    LogicalPageTableEntry::PointerToNextPageTable(next_page_table) => {
+                        next_page_table.for_each_confidential_page(guest_physical_address, f)
+                    }
+                    LogicalPageTableEntry::PageWithConfidentialVmData(page) => {
+                        f(guest_physical_address, page)
+                    }
+                    LogicalPageTableEntry::PageSharedWithHypervisor(_) => {
+                        Err(Error::PageTableConfiguration())
+                    }
+                    LogicalPageTableEntry::NotMapped => Ok(()),
+                }
+            })
+    }
+
+    /// Recursively replaces every page table that maps only data pages with a single data page of the size mapped by the entry pointing to
+    /// this page table, e.g., 512 pages of 4KiB with a page of 2MiB. This reduces the TLB pressure and the memory used by the page table
+    /// configuration. The content of the data pages is copied, so the memory of the confidential VM is unchanged. A page table is kept when
+    /// the page allocator has no page of the larger size available.
+    ///
+    /// This is a recursive function, which deepest execution is not larger than the number of paging system levels.
+    ///
+    /// # Confidential VM execution correctness
+    ///
+    /// The replaced data pages are returned to the page allocator, so this function must be called before the confidential VM executes.
+    /// Otherwise, confidential harts might still access the replaced pages through their address translation caches.
+    pub fn coalesce_pages(&mut self) {
+        (0..self.logical_representation.len()).for_each(|index| {
+            if let LogicalPageTableEntry::PointerToNextPageTable(next_page_table) =
+                &mut self.logical_representation[index]
+            {
+                next_page_table.coalesce_pages();
+                self.coalesce_entry(index);
+            }
+        });
+    }
+
+    /// Replaces the page table pointed to by the entry at the given index with a single data page, if the page table maps only data pages
+    /// and a page of the larger size is available.
+    fn coalesce_entry(&mut self, index: usize) {
+        let page_size = self.paging_system.data_page_size(self.level);
+        if page_size > Self::LARGEST_COALESCED_PAGE_SIZE {
+            return;
+        }
+        let LogicalPageTableEntry::PointerToNextPageTable(next_page_table) =
+            &self.logical_representation[index]
+        else {
+            return;
+        };
+        let Some(pages) = next_page_table
+            .logical_representation
+            .iter()
+            .map(|entry| match entry {
+                LogicalPageTableEntry::PageWithConfidentialVmData(page) => Some(page.as_ref()),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+        else {
+            return;
+        };
+        let Ok(page) = PageAllocator::acquire_page(page_size) else {
+            return;
+        };
+        let page = page.copy_from_confidential_pages(&pages);
+        // Below replaces the page table and releases it together with its data pages to the page allocator.
+        self.set_entry(
+            index,
+            LogicalPageTableEntry::PageWithConfidentialVmData(Box::new(page)),
+        );
+    }
+
+    /// Replaces the data page at the given index with a page table mapping smaller data pages of the same content, reverting
+    /// `coalesce_pages`. Returns error if there is not enough memory to allocate the smaller pages.
+    fn split_entry(&mut self, index: usize) -> Result<(), Error> {
+        let LogicalPageTableEntry::PageWithConfidentialVmData(page) =
+            &self.logical_representation[index]
+        else {
+            return Err(Error::PageTableConfiguration());
+        };
+        let lower_level = self.level.lower().ok_or(Error::PageTableCorrupted())?;
+        let smaller_page_size = self.paging_system.data_page_size(lower_level);
+        let mut next_page_table = PageTable::empty(self.paging_system, lower_level)?;
+        for i in 0..next_page_table.logical_representation.len() {
+            let smaller_page = match PageAllocator::acquire_page(smaller_page_size) {
+                Ok(smaller_page) => smaller_page,
+                Err(error) => {
+                    next_page_table.deallocate();
+                    return Err(error);
+                }
+            };
+            let smaller_page =
+                smaller_page.copy_from_confidential_page(page, i * smaller_page_size.in_bytes());
+            next_page_table.set_entry(
+                i,
+                LogicalPageTableEntry::PageWithConfidentialVmData(Box::new(smaller_page)),
+            );
+        }
+        // Below replaces the data page and releases it to the page allocator.
+        self.set_entry(
+            index,
+            LogicalPageTableEntry::PointerToNextPageTable(Box::new(next_page_table)),
+        );
+        Ok(())
+    }
+
     /// Returns the physical address in confidential memory of the page table configuration.
     pub fn address(&self) -> usize {
         self.serialized_representation.start_address()
@@ -310,7 +495,8 @@ impl PageTable {
         self.paging_system.hgatp_mode()
     }
 
-    /// Set a new page table entry at the given index, replacing whatever was there before.
+    /// Set a new page table entry at the given index, replacing whatever was there before. The replaced data page or page table is
+    /// released to the page allocator.
     /// Precondition: The vpn is valid for the number of entries of this page table.
     /// Postcondition: The entry has been set correctly.
     fn set_entry(&mut self, virtual_page_number: usize, entry: LogicalPageTableEntry) {
@@ -322,8 +508,12 @@ impl PageTable {
             .unwrap();
         let entry_to_remove =
             core::mem::replace(&mut self.logical_representation[virtual_page_number], entry);
-        if let LogicalPageTableEntry::PageWithConfidentialVmData(page) = entry_to_remove {
-            PageAllocator::release_pages(alloc::vec![page.deallocate()]);
+        match entry_to_remove {
+            LogicalPageTableEntry::PageWithConfidentialVmData(page) => {
+                PageAllocator::release_pages(alloc::vec![page.deallocate()])
+            }
+            LogicalPageTableEntry::PointerToNextPageTable(page_table) => page_table.deallocate(),
+            _ => {}
         }
     }
 
@@ -3,7 +3,8 @@
 // SPDX-License-Identifier: Apache-2.0
 #![allow(unused)]
 pub use control_status_registers::{ControlStatusRegister, ControlStatusRegisters, CSR};
-pub use extensions::compressed_instructions::decode_result_register;
+pub use extensions::advanced_interrupt_architecture::ImsicInterruptFile;
+pub use extensions::compressed_instructions::{decode_load_width, decode_result_register};
 pub use extensions::floating_point_unit::FloatingPointUnit;
 pub use extensions::supervisor_timer_extension::SupervisorTimerExtension;
 pub use extensions::HardwareExtension;
@@ -21,6 +22,8 @@ pub mod pmp;
 pub mod sbi;
 pub mod specification;
 pub mod tlb;
+#[cfg(feature = "userspace")]
+pub mod userspace;
 
 pub mod control_status_registers;
 mod extensions;
@@ -30,6 +33,7 @@ mod trap_cause;
 
 pub fn put_hart_to_sleep() {
     // careful: if interrupts are disabled, this hart will never trap
+    #[cfg(not(feature = "userspace"))]
     unsafe {
         core::arch::asm!("wfi");
     }
@@ -5,20 +5,27 @@ use crate::ace::core::architecture::riscv::specification::{
     PMP_ADDRESS_SHIFT, PMP_CONFIG_SHIFT, PMP_OFF_MASK, PMP_PERMISSION_RWX_MASK, PMP_TOR_MASK,
 };
 use crate::ace::core::architecture::CSR;
+use crate::ace::core::memory_layout::MemoryLayout;
 use crate::ace::debug::__print_pmp_configuration;
 use crate::ace::error::Error;
+use crate::arch::pmp::pmplayout::POLICY_OFFSET;
 use crate::{debug, ensure};
 use crate::host::MiralisContext;
 use crate::virt::VirtContext;
 
+/// Number of PMP entries whose configuration is stored in `pmpcfg0`.
+const NUMBER_OF_PMPS_IN_PMPCFG0: usize = 8;
+
 // OpenSBI set already PMPs to isolate OpenSBI firmware from the rest of the
 // system PMP0 protects OpenSBI memory region while PMP1 defines the system
 // range We will use PMP0 and PMP1 to protect the confidential memory region,
 // PMP2 to protect the OpenSBI, and PMP3 to define the system range.
+//
+// MODIFIED CODE FOR MIRALIS: Every confidential memory region is protected by a pair of PMP entries reserved for the policy, the first
+// one holds the start of the region and the second one the end of the region (in TOR mode).
 pub fn split_memory_into_confidential_and_non_confidential(
     mctx: &mut MiralisContext,
-    confidential_memory_start: usize,
-    confidential_memory_end: usize,
+    confidential_memory_regions: impl Iterator<Item = (usize, usize)>,
 ) -> Result<(), Error> {
     // TODO: read how many PMPs are supported
     const MINIMUM_NUMBER_OF_PMP_REQUIRED: usize = 4;
@@ -30,10 +37,17 @@ pub fn split_memory_into_confidential_and_non_confidential(
     )?;
 
     // TODO: simplify use of PMP by using a single PMP entry to isolate the confidential memory.
-    // We assume here that the first two PMPs are not used by anyone else, e.g., OpenSBI firmware
+    // We assume here that the policy PMPs are not used by anyone else, e.g., OpenSBI firmware
     // MODIFIED CODE FOR MIRALIS
-    mctx.pmp.set_pmpaddr(4, confidential_memory_start);
-    mctx.pmp.set_pmpaddr( 5, confidential_memory_end);
+    for (region, (confidential_memory_start, confidential_memory_end)) in
+        confidential_memory_regions.enumerate()
+    {
+        let start_pmp = confidential_memory_region_first_pmp(region);
+        // We only modify pmpcfg0 when opening and closing the access to the confidential memory
+        ensure!(start_pmp + 1 < NUMBER_OF_PMPS_IN_PMPCFG0, Error::NotEnoughPmps())?;
+        mctx.pmp.set_pmpaddr(start_pmp, confidential_memory_start);
+        mctx.pmp.set_pmpaddr(start_pmp + 1, confidential_memory_end);
+    }
 
     // CSR.pmpaddr4.write(confidential_memory_start >> PMP_ADDRESS_SHIFT);
     // CSR.pmpaddr5.write(confidential_memory_end >> PMP_ADDRESS_SHIFT);
@@ -46,7 +60,10 @@ pub fn split_memory_into_confidential_and_non_confidential(
 // 0x180000000 0x280000000
 pub fn open_access_to_confidential_memory() {
     // MODIFIED CODE FOR MIRALIS
-    let mask = (PMP_PERMISSION_RWX_MASK << 32) | ((PMP_TOR_MASK | PMP_PERMISSION_RWX_MASK) << 40);
+    let mask = confidential_memory_pmpcfg_mask(
+        PMP_PERMISSION_RWX_MASK,
+        PMP_TOR_MASK | PMP_PERMISSION_RWX_MASK,
+    );
     CSR.pmpcfg0.read_and_set_bits(mask);
     clear_caches();
     // END MODIFIED CODE
@@ -54,12 +71,29 @@ pub fn open_access_to_confidential_memory() {
 
 pub fn close_access_to_confidential_memory() {
     // MODIFIED CODE FOR MIRALIS
-    let mask = (PMP_PERMISSION_RWX_MASK << 32) | ((PMP_PERMISSION_RWX_MASK) << 40);
+    let mask = confidential_memory_pmpcfg_mask(PMP_PERMISSION_RWX_MASK, PMP_PERMISSION_RWX_MASK);
     CSR.pmpcfg0.read_and_clear_bits(mask);
     clear_caches();
     // END MODIFIED CODE
 }
 
+/// Returns the first of the two PMP entries protecting the given confidential memory region.
+fn confidential_memory_region_first_pmp(region: usize) -> usize {
+    POLICY_OFFSET + 2 * region
+}
+
+/// Returns the mask of `pmpcfg0` setting the given configuration to the two PMP entries of every confidential memory region. Regions
+/// whose PMP entries are not configured by `pmpcfg0` have been rejected when splitting the memory and are skipped.
+fn confidential_memory_pmpcfg_mask(start_pmp_config: usize, end_pmp_config: usize) -> usize {
+    (0..MemoryLayout::read().confidential_memory_regions().count())
+        .map(confidential_memory_region_first_pmp)
+        .filter(|start_pmp| start_pmp + 1 < NUMBER_OF_PMPS_IN_PMPCFG0)
+        .fold(0, |mask, start_pmp| {
+            mask | (start_pmp_config << (start_pmp * PMP_CONFIG_SHIFT))
+                | (end_pmp_config << ((start_pmp + 1) * PMP_CONFIG_SHIFT))
+        })
+}
+
 fn clear_caches() {
     // See Section 3.7.2 of RISC-V privileged specification v1.12.
     // PMP translations can be cached and address translation can be done speculatively. Thus, it is adviced to flush caching structures.
@@ -10,6 +10,9 @@ pub enum CovgExtension {
     UnshareMemory,
     AllowExternalInterrupt,
     DenyExternalInterrupt,
+    GetSealingKey,
+    GetEvidence,
+    ExtendMeasurement,
     Unknown(usize, usize),
 }
 
@@ -21,6 +24,9 @@ impl CovgExtension {
     pub const SBI_EXT_COVG_UNSHARE_MEMORY: usize = 3;
     pub const SBI_EXT_COVG_ALLOW_EXT_INTERRUPT: usize = 4;
     pub const SBI_EXT_COVG_DENY_EXT_INTERRUPT: usize = 5;
+    pub const SBI_EXT_COVG_GET_SEALING_KEY: usize = 6;
+    pub const SBI_EXT_COVG_GET_EVIDENCE: usize = 7;
+    pub const SBI_EXT_COVG_EXTEND_MEASUREMENT: usize = 8;
 
     pub fn from_function_id(function_id: usize) -> Self {
         match function_id {
@@ -30,6 +36,9 @@ impl CovgExtension {
             Self::SBI_EXT_COVG_UNSHARE_MEMORY => Self::UnshareMemory,
             Self::SBI_EXT_COVG_ALLOW_EXT_INTERRUPT => Self::AllowExternalInterrupt,
             Self::SBI_EXT_COVG_DENY_EXT_INTERRUPT => Self::DenyExternalInterrupt,
+            Self::SBI_EXT_COVG_GET_SEALING_KEY => Self::GetSealingKey,
+            Self::SBI_EXT_COVG_GET_EVIDENCE => Self::GetEvidence,
+            Self::SBI_EXT_COVG_EXTEND_MEASUREMENT => Self::ExtendMeasurement,
             _ => Self::Unknown(Self::EXTID, function_id),
         }
     }
@@ -6,8 +6,12 @@
 pub enum CovhExtension {
     TsmGetInfo,
     PromoteToTvm,
+    PromoteToTvmLazy,
     DestroyTvm,
+    TvmPopulatePage,
     TvmVcpuRun,
+    TvmExport,
+    TvmImport,
     Unknown(usize, usize),
 }
 
@@ -35,6 +39,10 @@ impl CovhExtension {
     pub const SBI_EXT_COVH_TVM_DEMOTE_PAGE: usize = 19;
     pub const SBI_EXT_COVH_TVM_REMOVE_PAGES: usize = 20;
     pub const SBI_EXT_COVH_PROMOTE_TO_TVM: usize = 21;
+    pub const SBI_EXT_COVH_PROMOTE_TO_TVM_LAZY: usize = 22;
+    pub const SBI_EXT_COVH_TVM_POPULATE_PAGE: usize = 23;
+    pub const SBI_EXT_COVH_TVM_EXPORT: usize = 24;
+    pub const SBI_EXT_COVH_TVM_IMPORT: usize = 25;
 
     pub fn from_function_id(function_id: usize) -> Self {
         match function_id {
@@ -42,6 +50,10 @@ impl CovhExtension {
             Self::SBI_EXT_COVH_DESTROY_TVM => Self::DestroyTvm,
             Self::SBI_EXT_COVH_TVM_VCPU_RUN => Self::TvmVcpuRun,
             Self::SBI_EXT_COVH_PROMOTE_TO_TVM => Self::PromoteToTvm,
+            Self::SBI_EXT_COVH_PROMOTE_TO_TVM_LAZY => Self::PromoteToTvmLazy,
+            Self::SBI_EXT_COVH_TVM_POPULATE_PAGE => Self::TvmPopulatePage,
+            Self::SBI_EXT_COVH_TVM_EXPORT => Self::TvmExport,
+            Self::SBI_EXT_COVH_TVM_IMPORT => Self::TvmImport,
             _ => Self::Unknown(Self::EXTID, function_id),
         }
     }
@@ -5,6 +5,13 @@
 #[derive(Debug)]
 pub enum CoviExtension {
     Unknown(usize, usize),
+    AiaInit,
+    SetImsicAddress,
+    ConvertImsic,
+    ReclaimImsic,
+    BindImsic,
+    UnbindImsicBegin,
+    UnbindImsicEnd,
     InjectExternalInterrupt,
 }
 
@@ -24,6 +31,13 @@ impl CoviExtension {
 
     pub fn from_function_id(function_id: usize) -> Self {
         match function_id {
+            Self::SBI_EXT_COVI_TVM_AIA_INIT => Self::AiaInit,
+            Self::SBI_EXT_COVI_TVM_CPU_SET_IMSIC_ADDR => Self::SetImsicAddress,
+            Self::SBI_EXT_COVI_TVM_CONVERT_IMSIC => Self::ConvertImsic,
+            Self::SBI_EXT_COVI_TVM_RECLAIM_IMSIC => Self::ReclaimImsic,
+            Self::SBI_EXT_COVI_TVM_CPU_BIND_IMSIC => Self::BindImsic,
+            Self::SBI_EXT_COVI_TVM_CPU_UNBIND_IMSIC_BEGIN => Self::UnbindImsicBegin,
+            Self::SBI_EXT_COVI_TVM_CPU_UNBIND_IMSIC_END => Self::UnbindImsicEnd,
             Self::SBI_EXT_COVI_TVM_CPU_INJECT_EXT_INTERRUPT => Self::InjectExternalInterrupt,
             _ => Self::Unknown(Self::EXTID, function_id),
         }
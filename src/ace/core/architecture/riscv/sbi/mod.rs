@@ -39,36 +39,28 @@ pub enum SbiExtension {
 }
 
 impl SbiExtension {
+    /// Decodes the SBI call. The extension is resolved through the SBI routing table shared with Miralis, extensions that are not
+    /// implemented by the security monitor or disabled by the configuration decode as `Unknown`.
     pub fn decode(a7: usize, a6: usize) -> Self {
-        match (a7, a6) {
-            (BaseExtension::EXTID, function_id) => {
-                Self::Base(BaseExtension::from_function_id(function_id))
-            }
-            (IpiExtension::EXTID, function_id) => {
-                Self::Ipi(IpiExtension::from_function_id(function_id))
-            }
-            (RfenceExtension::EXTID, function_id) => {
-                Self::Rfence(RfenceExtension::from_function_id(function_id))
-            }
-            (HsmExtension::EXTID, function_id) => {
-                Self::Hsm(HsmExtension::from_function_id(function_id))
-            }
-            (SrstExtension::EXTID, function_id) => {
-                Self::Srst(SrstExtension::from_function_id(function_id))
-            }
-            (NaclExtension::EXTID, function_id) => {
-                Self::Nacl(NaclExtension::from_function_id(function_id))
-            }
-            (CovhExtension::EXTID, function_id) => {
-                Self::Covh(CovhExtension::from_function_id(function_id))
-            }
-            (CoviExtension::EXTID, function_id) => {
-                Self::Covi(CoviExtension::from_function_id(function_id))
-            }
-            (CovgExtension::EXTID, function_id) => {
-                Self::Covg(CovgExtension::from_function_id(function_id))
-            }
-            (extension_id, function_id) => Self::Unknown(extension_id, function_id),
+        use crate::sbi::{route, SbiExtension as Route};
+        match route(a7) {
+            Some(Route::Base) => Self::Base(BaseExtension::from_function_id(a6)),
+            Some(Route::Ipi) => Self::Ipi(IpiExtension::from_function_id(a6)),
+            Some(Route::Rfence) => Self::Rfence(RfenceExtension::from_function_id(a6)),
+            Some(Route::Hsm) => Self::Hsm(HsmExtension::from_function_id(a6)),
+            Some(Route::Srst) => Self::Srst(SrstExtension::from_function_id(a6)),
+            Some(Route::Nacl) => Self::Nacl(NaclExtension::from_function_id(a6)),
+            Some(Route::Covh) => Self::Covh(CovhExtension::from_function_id(a6)),
+            Some(Route::Covi) => Self::Covi(CoviExtension::from_function_id(a6)),
+            Some(Route::Covg) => Self::Covg(CovgExtension::from_function_id(a6)),
+            Some(
+                Route::Susp
+                | Route::Miralis
+                | Route::Vendor
+                | Route::ProtectPayload
+                | Route::ProtectDomains,
+            )
+            | None => Self::Unknown(a7, a6),
         }
     }
 }
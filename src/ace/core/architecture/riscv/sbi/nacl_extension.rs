@@ -1,9 +1,10 @@
 // SPDX-FileCopyrightText: 2024 IBM Corporation
 // SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
 // SPDX-License-Identifier: Apache-2.0
-use crate::ace::core::architecture::{GeneralPurposeRegister, GeneralPurposeRegisters};
+use crate::ace::core::architecture::{GeneralPurposeRegister, GeneralPurposeRegisters, PageSize};
 use crate::ace::core::memory_layout::{MemoryLayout, NonConfidentialMemoryAddress};
 use crate::ace::error::Error;
+use crate::ensure;
 
 #[derive(Debug)]
 pub enum NaclExtension {
@@ -26,6 +27,12 @@ impl NaclExtension {
     pub const SBI_NACL_FEAT_SYNC_SRET: usize = 2;
     pub const SBI_NACL_FEAT_AUTOSWAP_CSR: usize = 3;
 
+    /// Bitmap of the NACL features implemented by the security monitor, indexed by feature ID.
+    pub const SUPPORTED_FEATURES: usize = 0;
+
+    /// Writing this address to the shared memory register disables the shared memory, as defined by the SBI spec.
+    pub const SHMEM_DISABLE: usize = usize::MAX;
+
     pub fn from_function_id(function_id: usize) -> Self {
         match function_id {
             Self::SBI_EXT_NACL_PROBE_FEATURE => Self::ProbeFeature,
@@ -60,21 +67,63 @@ impl NaclSharedMemory {
     const SCRATCH_SPACE_SIZE: usize = 4096;
     // Below constant is defined in the RISC-V SBI NACL extension spec.
     const CSR_SPACE_SIZE: usize = 8 * 1024;
+    // Offset of the layout version word. The scratch space layout of the RISC-V SBI NACL extension spec starts with a 512-byte area
+    // (0x000-0x1FF) for the SRET context, of which only the 32 GPR slots (0x000-0x0FF) are defined and the rest is reserved. The
+    // version word is the last word of this reserved area, so it never overlaps the GPRs, nor the HFENCE entries and the CSR dirty
+    // bitmap at the end of the scratch space.
+    const VERSION_OFFSET: usize = 0x1F8;
+    /// Version of the shared memory layout implemented by the security monitor. It is reported in the lower 32 bits of the version
+    /// word, the upper 32 bits contain the bitmap of supported NACL features.
+    pub const LAYOUT_VERSION: usize = 1;
 
     pub fn uninitialized() -> Self {
         Self { region: None }
     }
 
-    /// Sets up the NACL shared memory located in the non-confidential memory. Returns error if the NACL shared memory does not fit entirely
-    /// in the non-confidential memory.
+    /// Total size in bytes of the NACL shared memory.
+    pub const fn size() -> usize {
+        Self::SCRATCH_SPACE_SIZE + Self::CSR_SPACE_SIZE
+    }
+
+    /// Sets up the NACL shared memory located in the non-confidential memory. Returns error if the NACL shared memory is not page
+    /// aligned or does not fit entirely in the non-confidential memory. A previously registered shared memory is torn down first, even
+    /// if the new one is rejected.
     pub fn set(&mut self, base_address: NonConfidentialMemoryAddress) -> Result<(), Error> {
-        let nacl_shared_memory_size = Self::SCRATCH_SPACE_SIZE + Self::CSR_SPACE_SIZE;
-        let end_address = MemoryLayout::read()
-            .non_confidential_address_at_offset(&base_address, nacl_shared_memory_size)?;
+        self.teardown();
+        // The RISC-V SBI NACL extension spec requires the shared memory to be aligned to a 4KiB page boundary.
+        ensure!(
+            base_address.usize() % PageSize::Size4KiB.in_bytes() == 0,
+            Error::AddressNotAligned()
+        )?;
+        let memory_layout = MemoryLayout::read();
+        let end_address =
+            memory_layout.non_confidential_address_at_offset(&base_address, Self::size())?;
+        // The shared memory must not wrap around or cross into the confidential memory. Because the non-confidential memory is a
+        // single contiguous region, it is enough to check that the last byte of the shared memory is in the non-confidential memory.
+        let last_byte = (end_address.usize() - 1) as *const usize;
+        ensure!(
+            end_address.usize() > base_address.usize()
+                && memory_layout.is_in_non_confidential_range(last_byte),
+            Error::AddressNotInNonConfidentialMemory()
+        )?;
         self.region = Some((base_address, end_address));
+        self.write_at_offset(Self::VERSION_OFFSET, Self::version_word());
         Ok(())
     }
 
+    /// Unregisters the NACL shared memory, if any. The version word is cleared so that the hypervisor can detect that the security
+    /// monitor no longer uses this memory region.
+    pub fn teardown(&mut self) {
+        if self.region.is_some() {
+            self.write_at_offset(Self::VERSION_OFFSET, 0);
+            self.region = None;
+        }
+    }
+
+    fn version_word() -> usize {
+        Self::LAYOUT_VERSION | (NaclExtension::SUPPORTED_FEATURES << 32)
+    }
+
     pub fn csr(&self, csr_code: usize) -> usize {
         self.read_at_offset(
             Self::SCRATCH_SPACE_SIZE + Self::csr_index(csr_code) * core::mem::size_of::<usize>(),
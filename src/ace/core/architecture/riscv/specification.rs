@@ -409,6 +409,16 @@ pub const MIE_VSEIP_MASK: usize = 1 << MIE_VSEIP;
 pub const MIE_MEIP: usize = 11;
 pub const MIE_MEIP_MASK: usize = 1 << MIE_MEIP;
 
+// IMSIC interrupt file registers, accessed indirectly with siselect and sireg
+pub const IMSIC_EIDELIVERY: usize = 0x70;
+pub const IMSIC_EITHRESHOLD: usize = 0x72;
+pub const IMSIC_EIP0: usize = 0x80;
+pub const IMSIC_EIP63: usize = 0xbf;
+pub const IMSIC_EIE0: usize = 0xc0;
+pub const IMSIC_EIE63: usize = 0xff;
+pub const IMSIC_MAX_NUMBER_OF_INTERRUPT_IDS: usize = 2048;
+pub const IMSIC_TOPEI_ID_SHIFT: usize = 16;
+
 pub const PMP_OFF_MASK: usize = 0b0;
 pub const PMP_TOR_MASK: usize = 0b01000;
 pub const PMP_NA4_MASK: usize = 0b10000;
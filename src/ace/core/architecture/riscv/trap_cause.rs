@@ -30,7 +30,12 @@ impl TrapCause {
         let mcause = hart_state.csrs().mcause.read();
         let extension_id = hart_state.gprs().read(GeneralPurposeRegister::a7);
         let function_id = hart_state.gprs().read(GeneralPurposeRegister::a6);
+        Self::decode(mcause, extension_id, function_id)
+    }
 
+    /// Decodes the trap cause from the value of `mcause` and, for environment calls, from the SBI extension and function ids passed in
+    /// `a7` and `a6`.
+    pub fn decode(mcause: usize, extension_id: usize, function_id: usize) -> Self {
         if is_bit_enabled(mcause, CAUSE_INTERRUPT_BIT) {
             Self::Interrupt
         } else {
@@ -0,0 +1,69 @@
+// SPDX-FileCopyrightText: 2023 IBM Corporation
+// SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
+// SPDX-License-Identifier: Apache-2.0
+//! Stand-ins for the hardware accessed by the security monitor when it runs as a userspace application on the host, such as when
+//! running unit tests or the fuzzer.
+//!
+//! The CSRs of the physical hart are emulated by a register file in memory. The assembly context switches do not exist on the host: the
+//! exit nodes of the finite state machine (FSM) unwind instead, so that the caller of a dispatcher observes where the control flow left
+//! the security monitor.
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Number of CSRs addressable by the 12-bit CSR address space.
+const NUMBER_OF_CSRS: usize = 1 << 12;
+
+static CSRS: [AtomicUsize; NUMBER_OF_CSRS] = [const { AtomicUsize::new(0) }; NUMBER_OF_CSRS];
+
+/// The exit nodes of the finite state machine (FSM).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HostExit {
+    /// Resumes the hypervisor hart, see `exit_to_hypervisor_asm`.
+    Hypervisor,
+    /// Resumes the confidential hart, see `exit_to_confidential_hart_asm`.
+    ConfidentialHart,
+    /// Hands over the hypervisor's request to Miralis, see `ace_to_miralis_ctx_switch`.
+    Miralis,
+}
+
+pub fn read_csr(csr: u16) -> usize {
+    CSRS[csr as usize].load(Ordering::SeqCst)
+}
+
+pub fn write_csr(csr: u16, value: usize) {
+    CSRS[csr as usize].store(value, Ordering::SeqCst);
+}
+
+pub fn read_and_set_csr_bits(csr: u16, bitmask: usize) -> usize {
+    CSRS[csr as usize].fetch_or(bitmask, Ordering::SeqCst)
+}
+
+pub fn read_and_clear_csr_bits(csr: u16, bitmask: usize) -> usize {
+    CSRS[csr as usize].fetch_and(!bitmask, Ordering::SeqCst)
+}
+
+/// Leaves the security monitor by unwinding with the exit node as payload. Unit tests catch the unwind without going through the panic
+/// hook, so that reaching an exit node is not reported as a failure.
+pub fn exit(exit: HostExit) -> ! {
+    #[cfg(test)]
+    std::panic::resume_unwind(std::boxed::Box::new(exit));
+    #[cfg(not(test))]
+    panic!("Exited the security monitor: {:?}", exit)
+}
+
+pub unsafe fn exit_to_hypervisor_asm() -> ! {
+    exit(HostExit::Hypervisor)
+}
+
+pub unsafe fn exit_to_confidential_hart_asm() -> ! {
+    exit(HostExit::ConfidentialHart)
+}
+
+/// The trap vector of the hypervisor, it is never executed on the host and only its address is used.
+pub extern "C" fn enter_from_hypervisor_or_vm_asm() -> ! {
+    unreachable!("The context switch from the hypervisor does not exist on the host")
+}
+
+/// The trap vector of confidential harts, it is never executed on the host and only its address is used.
+pub extern "C" fn enter_from_confidential_hart_asm() {
+    unreachable!("The context switch from a confidential hart does not exist on the host")
+}
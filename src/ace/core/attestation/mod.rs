@@ -0,0 +1,108 @@
+// SPDX-FileCopyrightText: 2023 IBM Corporation
+// SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
+// SPDX-License-Identifier: Apache-2.0
+use crate::ace::core::control_data::{
+    MeasurementDigest, RuntimeMeasurements, StaticMeasurements, NUMBER_OF_REGISTERS,
+};
+use crate::ace::core::crypto::{
+    zeroize, Crypto, CryptoBackend, KeyHierarchy, Signature, SigningKey,
+};
+use crate::ace::error::Error;
+
+/// Size of the challenge chosen by the verifier, which guarantees the freshness of the evidence.
+pub const CHALLENGE_SIZE: usize = 64;
+
+pub type Challenge = [u8; CHALLENGE_SIZE];
+
+/// How the evidence is authenticated.
+#[derive(Clone, Copy, Debug)]
+#[repr(u32)]
+pub enum EvidenceFormat {
+    /// Ed25519 signature with the device attestation key.
+    Signed = 1,
+    /// HMAC-SHA384 with the device attestation key, for crypto backends that cannot sign. Only a verifier that shares the device
+    /// secret (e.g., the device manufacturer) can verify such evidence.
+    Authenticated = 2,
+}
+
+/// Attestation evidence of a confidential VM, reporting its measurement registers together with the verifier's challenge.
+///
+/// The evidence is serialized in little endian as follows:
+/// * magic (8 bytes) and version (4 bytes),
+/// * format (4 bytes), see `EvidenceFormat`,
+/// * challenge (64 bytes),
+/// * measurement registers (8 registers of 48 bytes), runtime registers replacing the static ones they extend,
+/// * authentication tag (64 bytes), the signature or the MAC padded with zeros, computed over all preceding bytes.
+pub struct Evidence([u8; Self::SIZE]);
+
+impl Evidence {
+    const MAGIC: &'static [u8; 8] = b"ACEEVID\0";
+    const VERSION: u32 = 1;
+    const HEADER_SIZE: usize = 16;
+    const REGISTERS_OFFSET: usize = Self::HEADER_SIZE + CHALLENGE_SIZE;
+    const TAG_OFFSET: usize =
+        Self::REGISTERS_OFFSET + NUMBER_OF_REGISTERS * core::mem::size_of::<MeasurementDigest>();
+    const TAG_SIZE: usize = core::mem::size_of::<Signature>();
+    pub const SIZE: usize = Self::TAG_OFFSET + Self::TAG_SIZE;
+
+    /// Creates the evidence of a confidential VM. Returns error if the key hierarchy is not available.
+    pub fn new(
+        measurements: &StaticMeasurements,
+        runtime_measurements: &RuntimeMeasurements,
+        challenge: &Challenge,
+    ) -> Result<Self, Error> {
+        let mut attestation_key = KeyHierarchy::attestation_key()?;
+        let mut evidence = Self([0; Self::SIZE]);
+        evidence.0[..8].copy_from_slice(Self::MAGIC);
+        evidence.0[8..12].copy_from_slice(&Self::VERSION.to_le_bytes());
+        evidence.0[Self::HEADER_SIZE..Self::REGISTERS_OFFSET].copy_from_slice(challenge);
+        (0..NUMBER_OF_REGISTERS)
+            .filter_map(|id| {
+                runtime_measurements
+                    .register(id)
+                    .or_else(|| measurements.register(id))
+            })
+            .zip(
+                evidence.0[Self::REGISTERS_OFFSET..Self::TAG_OFFSET]
+                    .chunks_mut(core::mem::size_of::<MeasurementDigest>()),
+            )
+            .for_each(|(register, chunk)| chunk.copy_from_slice(register));
+
+        let mut signing_key = SigningKey::default();
+        signing_key.copy_from_slice(&attestation_key[..core::mem::size_of::<SigningKey>()]);
+        let result = evidence
+            .authenticate(EvidenceFormat::Signed, |body| {
+                Crypto::sign(&signing_key, body)
+            })
+            .or_else(|error| match error {
+                Error::CryptoOperationNotSupported() => {
+                    evidence.authenticate(EvidenceFormat::Authenticated, |body| {
+                        let mut tag = [0; Self::TAG_SIZE];
+                        let mac = Crypto::mac(&attestation_key, body);
+                        tag[..mac.len()].copy_from_slice(&mac);
+                        Ok(tag)
+                    })
+                }
+                error => Err(error),
+            });
+        // The keys are copies of the device secrets on the stack, they must not outlive the evidence computation.
+        zeroize(&mut signing_key);
+        zeroize(&mut attestation_key);
+        result.map(|_| evidence)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Sets the format and computes the authentication tag over the evidence body.
+    fn authenticate<F>(&mut self, format: EvidenceFormat, tag: F) -> Result<(), Error>
+    where
+        F: FnOnce(&[u8]) -> Result<[u8; Self::TAG_SIZE], Error>,
+    {
+        self.0[12..Self::HEADER_SIZE].copy_from_slice(&(format as u32).to_le_bytes());
+        let tag = tag(&self.0[..Self::TAG_OFFSET])?;
+        self.0[Self::TAG_OFFSET..].copy_from_slice(&tag);
+        Ok(())
+    }
+}
@@ -5,7 +5,7 @@ use crate::ace::core::architecture::riscv::sbi::NaclSharedMemory;
 use crate::ace::core::architecture::riscv::specification::*;
 use crate::ace::core::architecture::{
     ControlStatusRegisters, GeneralPurposeRegister, GeneralPurposeRegisters, HardwareExtension,
-    HartArchitecturalState, HartLifecycleState, SupervisorTimerExtension,
+    HartArchitecturalState, HartLifecycleState, ImsicInterruptFile, SupervisorTimerExtension, CSR,
 };
 use crate::ace::core::control_data::confidential_hart_remote_command::ConfidentialHartRemoteCommandExecutable;
 use crate::ace::core::control_data::{
@@ -13,13 +13,19 @@ use crate::ace::core::control_data::{
 };
 use crate::ace::core::hardware_setup::HardwareSetup;
 use crate::ace::error::Error;
-use crate::{ensure, ensure_not};
+use crate::platform::{Plat, Platform};
+use crate::timer::TimerEvent;
+use crate::{debug, ensure, ensure_not};
 
+#[cfg(not(feature = "userspace"))]
 extern "C" {
     // Assembly function that is an entry point to the security monitor from the hypervisor or a virtual machine.
     fn enter_from_confidential_hart_asm();
 }
 
+#[cfg(feature = "userspace")]
+use crate::ace::core::architecture::riscv::userspace::enter_from_confidential_hart_asm;
+
 /// ConfidentialHart represents the dump state of the confidential VM's hart (aka vcpu). The only publicly exposed way
 /// to modify the confidential hart architectural state (registers/CSRs) is by calling the constructor or applying a
 /// transformation.
@@ -40,6 +46,10 @@ pub struct ConfidentialHart {
     /// A pending request indicates that the confidential hart sent a request to the hypervisor and is waiting for its
     /// reply. The pending request defines the expected response.
     resumable_operation: Option<ResumableOperation>,
+    /// Value of mtime after which the pending request expires, usize::MAX if it never expires.
+    resumable_operation_deadline: usize,
+    /// Interrupt file through which the hypervisor injects external interrupts, see the COVI extension.
+    interrupt_file: ImsicInterruptFile,
 }
 
 impl ConfidentialHart {
@@ -82,6 +92,8 @@ impl ConfidentialHart {
             confidential_hart_state: HartArchitecturalState::empty(),
             lifecycle_state: HartLifecycleState::Started,
             resumable_operation: None,
+            resumable_operation_deadline: usize::MAX,
+            interrupt_file: ImsicInterruptFile::empty(),
             id: hardware_hart_id,
         }
     }
@@ -186,6 +198,8 @@ impl ConfidentialHart {
             confidential_hart_state,
             lifecycle_state: HartLifecycleState::Stopped,
             resumable_operation: None,
+            resumable_operation_deadline: usize::MAX,
+            interrupt_file: ImsicInterruptFile::empty(),
             id,
         }
     }
@@ -355,6 +369,14 @@ impl ConfidentialHart {
         &self.confidential_hart_state
     }
 
+    pub fn interrupt_file(&self) -> &ImsicInterruptFile {
+        &self.interrupt_file
+    }
+
+    pub fn interrupt_file_mut(&mut self) -> &mut ImsicInterruptFile {
+        &mut self.interrupt_file
+    }
+
     pub fn is_dummy(&self) -> bool {
         self.confidential_vm_id.is_none()
     }
@@ -383,8 +405,47 @@ impl ConfidentialHart {
     /// confidential hart's execution. Before exiting to the hypervisor, the security monitor can set up the resumable operation only once.
     pub fn set_resumable_operation(&mut self, request: ResumableOperation) {
         assert!(self.resumable_operation.is_none());
+        self.resumable_operation_deadline = usize::MAX;
+        if request.expects_hypervisor_response() {
+            let deadline = Plat::get_clint()
+                .lock()
+                .read_mtime()
+                .saturating_add(ResumableOperation::response_timeout());
+            Self::arm_resumable_operation_deadline(deadline);
+            self.resumable_operation_deadline = deadline;
+        }
         self.resumable_operation = Some(request);
     }
+
+    /// Arms the policy timer of this physical hart such that it fires no later than the deadline, even if the hypervisor never resumes
+    /// the confidential hart. The policy then cancels the expired operations, see `expire_resumable_operation`.
+    fn arm_resumable_operation_deadline(deadline: usize) {
+        let hart_id = CSR.mhartid.read();
+        let vclint = Plat::get_vclint();
+        let deadline = vclint.deadline(hart_id, TimerEvent::Policy).min(deadline);
+        if let Err(error) = vclint.set_deadline(hart_id, TimerEvent::Policy, deadline) {
+            debug!("Failed to arm the resumable operation deadline: {}", error);
+        }
+    }
+
+    /// Cancels the pending request if the hypervisor did not respond to it before its deadline. Instead of the hypervisor's response,
+    /// the confidential hart observes that the request failed. Returns the deadline of the request that is still pending, usize::MAX if
+    /// none.
+    ///
+    /// The confidential hart must not be running on any physical hart.
+    pub fn expire_resumable_operation(&mut self, now: usize) -> usize {
+        if self.resumable_operation.is_none() {
+            return usize::MAX;
+        }
+        if now <= self.resumable_operation_deadline {
+            return self.resumable_operation_deadline;
+        }
+        debug!("Hypervisor did not respond in time, cancelling the pending request");
+        if let Some(operation) = self.resumable_operation.take() {
+            operation.cancel(self);
+        }
+        usize::MAX
+    }
 }
 
 // Methods related to lifecycle state transitions of the confidential hart. These methods manipulate the internal hart
@@ -488,6 +549,107 @@ impl ConfidentialHart {
     pub fn transition_to_shutdown(&mut self) {
         assert!(!self.is_dummy());
         self.lifecycle_state = HartLifecycleState::PoweredOff;
+        // A powered off hart never resumes, so any pending request is dropped.
+        self.resumable_operation = None;
+    }
+}
+
+// Methods related to the migration of the confidential hart to another security monitor. Only the state that the confidential VM controls
+// is migrated, the configuration of the confidential hart is the one after a reset on the destination security monitor.
+impl ConfidentialHart {
+    /// Number of values in the migrated state: the GPRs, the VS-level CSRs, the program counter, the pending virtual interrupts, the
+    /// timer, the lifecycle state, and the resumable operation.
+    pub const MIGRATED_STATE_LEN: usize = GeneralPurposeRegisters::LEN + 13;
+
+    /// Returns the state of the confidential hart that is transferred when migrating the confidential VM. Returns error if the confidential
+    /// hart is suspended or waits for an operation other than an SBI request, because such operations refer to the state of the source
+    /// security monitor.
+    pub fn migrated_state(&self) -> Result<[usize; Self::MIGRATED_STATE_LEN], Error> {
+        let lifecycle_state = match self.lifecycle_state {
+            HartLifecycleState::Started => 0,
+            HartLifecycleState::Stopped => 1,
+            HartLifecycleState::PoweredOff => 2,
+            HartLifecycleState::Suspended => return Err(Error::MigrationNotSupported()),
+        };
+        let resumable_operation = match self.resumable_operation {
+            None => 0,
+            Some(ResumableOperation::SbiRequest()) => 1,
+            Some(_) => return Err(Error::MigrationNotSupported()),
+        };
+        let csrs = self.csrs();
+        let mut state = [0; Self::MIGRATED_STATE_LEN];
+        state[..GeneralPurposeRegisters::LEN].copy_from_slice(&self.gprs().0);
+        state[GeneralPurposeRegisters::LEN..].copy_from_slice(&[
+            csrs.vsstatus.read_from_main_memory(),
+            csrs.vsie.read_from_main_memory(),
+            csrs.vstvec.read_from_main_memory(),
+            csrs.vsscratch.read_from_main_memory(),
+            csrs.vsepc.read_from_main_memory(),
+            csrs.vscause.read_from_main_memory(),
+            csrs.vstval.read_from_main_memory(),
+            csrs.vsatp.read_from_main_memory(),
+            csrs.mepc.read_from_main_memory(),
+            csrs.hvip.read_from_main_memory(),
+            self.sstc().vstimecmp.read_from_main_memory(),
+            lifecycle_state,
+            resumable_operation,
+        ]);
+        Ok(state)
+    }
+
+    /// Constructs a confidential hart from the state returned by `migrated_state` on the source security monitor. The timer of the
+    /// confidential hart is offset by `htimedelta`. Returns error if the state is malformed.
+    pub fn from_migrated_state(
+        id: usize,
+        htimedelta: usize,
+        shared_memory: &NaclSharedMemory,
+        state: &[usize; Self::MIGRATED_STATE_LEN],
+    ) -> Result<Self, Error> {
+        let (gprs, values) = state.split_at(GeneralPurposeRegisters::LEN);
+        let [
+            vsstatus,
+            vsie,
+            vstvec,
+            vsscratch,
+            vsepc,
+            vscause,
+            vstval,
+            vsatp,
+            mepc,
+            hvip,
+            vstimecmp,
+            lifecycle_state,
+            resumable_operation,
+        ]: [usize; 13] = values.try_into()?;
+        let mut confidential_hart = Self::from_vm_hart_reset(id, htimedelta, shared_memory);
+        confidential_hart.lifecycle_state = match lifecycle_state {
+            0 => HartLifecycleState::Started,
+            1 => HartLifecycleState::Stopped,
+            2 => HartLifecycleState::PoweredOff,
+            _ => return Err(Error::InvalidMigrationImage()),
+        };
+        match resumable_operation {
+            0 => {}
+            1 => confidential_hart.set_resumable_operation(ResumableOperation::SbiRequest()),
+            _ => return Err(Error::InvalidMigrationImage()),
+        }
+        confidential_hart.gprs_mut().0.copy_from_slice(gprs);
+        let csrs = confidential_hart.csrs_mut();
+        csrs.vsstatus.save_value_in_main_memory(vsstatus);
+        csrs.vsie.save_value_in_main_memory(vsie);
+        csrs.vstvec.save_value_in_main_memory(vstvec);
+        csrs.vsscratch.save_value_in_main_memory(vsscratch);
+        csrs.vsepc.save_value_in_main_memory(vsepc);
+        csrs.vscause.save_value_in_main_memory(vscause);
+        csrs.vstval.save_value_in_main_memory(vstval);
+        csrs.vsatp.save_value_in_main_memory(vsatp);
+        csrs.mepc.save_value_in_main_memory(mepc);
+        csrs.hvip.save_value_in_main_memory(hvip);
+        confidential_hart
+            .sstc_mut()
+            .vstimecmp
+            .save_value_in_main_memory(vstimecmp);
+        Ok(confidential_hart)
     }
 }
 
@@ -505,6 +667,9 @@ impl ConfidentialHart {
             ConfidentialHartRemoteCommand::RemoteHfenceGvmaVmid(v) => {
                 v.execute_on_confidential_hart(self)
             }
+            ConfidentialHartRemoteCommand::InjectExternalInterrupt(v) => {
+                v.execute_on_confidential_hart(self)
+            }
             ConfidentialHartRemoteCommand::ShutdownRequest(_) => self.transition_to_shutdown(),
         }
     }
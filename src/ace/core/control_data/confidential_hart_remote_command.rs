@@ -6,6 +6,7 @@ use crate::ace::confidential_flow::handlers::symmetrical_multiprocessing::{
     Ipi, RemoteFenceI, RemoteHfenceGvmaVmid, RemoteSfenceVma, RemoteSfenceVmaAsid,
 };
 use crate::ace::core::control_data::ConfidentialHart;
+use crate::ace::non_confidential_flow::handlers::cove_interrupt_extension::InjectExternalInterrupt;
 
 /// Represents a command that must be executed on a confidential hart. Typically this is an inter hart request that was sent from one
 /// confidential hart (sender) to another confidential hart (receiver), both sender and receiver belong to the same confidential VM.
@@ -20,6 +21,7 @@ pub enum ConfidentialHartRemoteCommand {
     RemoteSfenceVma(RemoteSfenceVma),
     RemoteSfenceVmaAsid(RemoteSfenceVmaAsid),
     RemoteHfenceGvmaVmid(RemoteHfenceGvmaVmid),
+    InjectExternalInterrupt(InjectExternalInterrupt),
     ShutdownRequest(ShutdownRequest),
 }
 
@@ -31,9 +33,22 @@ impl ConfidentialHartRemoteCommand {
             Self::RemoteSfenceVma(v) => v.is_hart_selected(confidential_hart_id),
             Self::RemoteSfenceVmaAsid(v) => v.is_hart_selected(confidential_hart_id),
             Self::RemoteHfenceGvmaVmid(v) => v.is_hart_selected(confidential_hart_id),
+            Self::InjectExternalInterrupt(v) => v.is_hart_selected(confidential_hart_id),
             Self::ShutdownRequest(v) => v.is_hart_selected(confidential_hart_id),
         }
     }
+
+    /// Merges the given command into this pending command, so that the receiver executes both at once. Returns false if the commands
+    /// cannot be merged. Only fences of G-stage translations are merged, because they are sent in bulk when unmapping pages.
+    pub fn try_merge(&mut self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::RemoteHfenceGvmaVmid(pending), Self::RemoteHfenceGvmaVmid(other)) => {
+                pending.merge(other);
+                true
+            }
+            _ => false,
+        }
+    }
 }
 
 pub trait ConfidentialHartRemoteCommandExecutable {
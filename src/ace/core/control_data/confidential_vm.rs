@@ -6,24 +6,42 @@ use alloc::vec::Vec;
 
 use spin::{Mutex, MutexGuard};
 
-use crate::ace::core::architecture::HartLifecycleState;
+use crate::ace::confidential_flow::handlers::symmetrical_multiprocessing::RemoteHfenceGvmaVmid;
+use crate::ace::core::architecture::riscv::sbi::NaclSharedMemory;
+use crate::ace::core::architecture::specification::{
+    IMSIC_MAX_NUMBER_OF_INTERRUPT_IDS, MIE_VSEIP_MASK,
+};
+use crate::ace::core::architecture::{HartLifecycleState, PageSize, CSR};
+use crate::ace::core::control_data::confidential_vm_migration::{
+    page_size_from_bytes, MigrationReader, MigrationWriter, Record, MAX_RECORD_SIZE,
+};
 use crate::ace::core::control_data::{
-    ConfidentialHart, ConfidentialHartRemoteCommand, ConfidentialVmId, ConfidentialVmMmioRegion,
-    HardwareHart, StaticMeasurements,
+    AiaParams, ConfidentialHart, ConfidentialHartRemoteCommand, ConfidentialVmAia,
+    ConfidentialVmId, ConfidentialVmMmioRegion, HardwareHart, LazyPageManifest, MeasurementDigest,
+    MigrationBuffer, RuntimeMeasurements, StaticMeasurements,
 };
 use crate::ace::core::interrupt_controller::InterruptController;
+use crate::ace::core::memory_layout::{
+    ConfidentialVmPhysicalAddress, NonConfidentialMemoryAddress,
+};
 use crate::ace::core::memory_protector::ConfidentialVmMemoryProtector;
+use crate::ace::core::page_allocator::PageAllocator;
 use crate::ace::error::Error;
-use crate::{ensure, ensure_not};
+use crate::ace::non_confidential_flow::handlers::cove_interrupt_extension::InjectExternalInterrupt;
+use crate::platform::{Plat, Platform};
+use crate::{debug, ensure, ensure_not};
 
 pub struct ConfidentialVm {
     id: ConfidentialVmId,
-    _measurements: StaticMeasurements,
+    measurements: StaticMeasurements,
+    runtime_measurements: RuntimeMeasurements,
     confidential_harts: Vec<ConfidentialHart>,
     remote_commands: BTreeMap<usize, Mutex<Vec<ConfidentialHartRemoteCommand>>>,
     memory_protector: ConfidentialVmMemoryProtector,
+    lazy_pages: LazyPageManifest,
     allowed_external_interrupts: usize,
     mmio_regions: Vec<ConfidentialVmMmioRegion>,
+    aia: Option<ConfidentialVmAia>,
 }
 
 impl ConfidentialVm {
@@ -45,6 +63,7 @@ impl ConfidentialVm {
         mut confidential_harts: Vec<ConfidentialHart>,
         measurements: StaticMeasurements,
         mut memory_protector: ConfidentialVmMemoryProtector,
+        lazy_pages: LazyPageManifest,
     ) -> Self {
         memory_protector.set_confidential_vm_id(id);
         let remote_commands = confidential_harts
@@ -59,12 +78,15 @@ impl ConfidentialVm {
             .collect();
         Self {
             id,
-            _measurements: measurements,
+            measurements,
+            runtime_measurements: RuntimeMeasurements::new(),
             confidential_harts,
             memory_protector,
+            lazy_pages,
             remote_commands,
             allowed_external_interrupts: 0,
             mmio_regions: Vec::with_capacity(8),
+            aia: None,
         }
     }
 
@@ -72,10 +94,47 @@ impl ConfidentialVm {
         self.id
     }
 
+    pub fn measurements(&self) -> &StaticMeasurements {
+        &self.measurements
+    }
+
+    pub fn runtime_measurements(&self) -> &RuntimeMeasurements {
+        &self.runtime_measurements
+    }
+
+    /// Extends the runtime measurement register with the digest provided by the confidential VM.
+    pub fn extend_measurement(
+        &mut self,
+        register_id: usize,
+        digest: &MeasurementDigest,
+    ) -> Result<(), Error> {
+        self.runtime_measurements.extend(register_id, digest)
+    }
+
+    pub fn memory_protector(&self) -> &ConfidentialVmMemoryProtector {
+        &self.memory_protector
+    }
+
     pub fn memory_protector_mut(&mut self) -> &mut ConfidentialVmMemoryProtector {
         &mut self.memory_protector
     }
 
+    /// Revokes all pages shared with the hypervisor, so that no confidential hart can access them anymore. This is called when the
+    /// confidential VM shuts down, the hypervisor then learns from the shutdown that it can reclaim all pages it shared with the
+    /// confidential VM.
+    pub fn revoke_shared_pages(&mut self) -> Result<(), Error> {
+        let number_of_revoked_pages = self.memory_protector.revoke_shared_pages();
+        if number_of_revoked_pages == 0 {
+            return Ok(());
+        }
+        debug!(
+            "Revoked {} shared pages of ConfidentialVM[{:?}]",
+            number_of_revoked_pages, self.id
+        );
+        let request = RemoteHfenceGvmaVmid::all_harts_full_vmid(self.id);
+        self.broadcast_remote_command(ConfidentialHartRemoteCommand::RemoteHfenceGvmaVmid(request))
+    }
+
     pub(super) fn deallocate(self) {
         self.memory_protector.into_root_page_table().deallocate();
     }
@@ -109,6 +168,18 @@ impl ConfidentialVm {
             confidential_hart.is_executable(),
             Error::HartNotExecutable()
         )?;
+        // Once the hypervisor configured the AIA, a confidential hart can only run on the physical hart it is bound to.
+        if let Some(ref aia) = self.aia {
+            ensure!(
+                aia.is_bound_to(confidential_hart_id, CSR.mhartid.read()),
+                Error::ImsicNotBound()
+            )?;
+        }
+
+        // The hypervisor has a limited amount of time to respond to a request. After the deadline, the response is not accepted anymore
+        // and the confidential hart observes a failure instead.
+        let now = Plat::get_clint().lock().read_mtime();
+        self.confidential_harts[confidential_hart_id].expire_resumable_operation(now);
 
         // Heavy context switch:
         // 1) Dump control and status registers (CSRs) of the hypervisor hart to the main memory.
@@ -180,6 +251,86 @@ impl ConfidentialVm {
     pub fn allow_external_interrupt(&mut self, external_interrupt: usize) {
         self.allowed_external_interrupts |= external_interrupt;
     }
+
+    /// Initializes the AIA of the confidential VM and the interrupt files of its confidential harts. Returns error if the AIA is already
+    /// initialized or if any confidential hart is running.
+    pub fn init_aia(&mut self, params: AiaParams) -> Result<(), Error> {
+        ensure!(self.aia.is_none(), Error::InvalidParameter())?;
+        ensure_not!(
+            self.confidential_harts.iter().any(|hart| hart.is_dummy()),
+            Error::HartAlreadyRunning()
+        )?;
+        // The interrupt files are implemented in software, so they all implement the maximum number of interrupt identities.
+        self.confidential_harts.iter_mut().try_for_each(|hart| {
+            hart.interrupt_file_mut()
+                .configure(IMSIC_MAX_NUMBER_OF_INTERRUPT_IDS)
+        })?;
+        self.aia = Some(ConfidentialVmAia::new(
+            params,
+            self.confidential_harts.len(),
+        ));
+        Ok(())
+    }
+
+    pub fn set_imsic_address(
+        &mut self,
+        confidential_hart_id: usize,
+        address: usize,
+    ) -> Result<(), Error> {
+        self.aia_mut()?
+            .set_imsic_address(confidential_hart_id, address)
+    }
+
+    /// Binds the confidential hart to the guest interrupt files of the physical hart executing this code.
+    pub fn bind_imsic(
+        &mut self,
+        confidential_hart_id: usize,
+        guest_interrupt_files: usize,
+    ) -> Result<(), Error> {
+        let hardware_hart_id = CSR.mhartid.read();
+        self.aia_mut()?.bind(
+            confidential_hart_id,
+            hardware_hart_id,
+            guest_interrupt_files,
+        )
+    }
+
+    pub fn unbind_imsic_begin(&mut self, confidential_hart_id: usize) -> Result<(), Error> {
+        self.aia_mut()?.unbind_begin(confidential_hart_id)
+    }
+
+    pub fn unbind_imsic_end(&mut self, confidential_hart_id: usize) -> Result<(), Error> {
+        self.aia_mut()?.unbind_end(confidential_hart_id)
+    }
+
+    /// Injects the external interrupt into the interrupt file of the confidential hart. Returns error if the AIA is not initialized, if
+    /// the interrupt identity is not implemented, or if the confidential VM did not allow the injection of external interrupts.
+    pub fn inject_external_interrupt(
+        &mut self,
+        request: InjectExternalInterrupt,
+    ) -> Result<(), Error> {
+        ensure!(self.aia.is_some(), Error::AiaNotInitialized())?;
+        ensure!(
+            request.confidential_hart_id() < self.confidential_harts.len(),
+            Error::InvalidHartId()
+        )?;
+        ensure!(
+            request.interrupt_id() > 0
+                && request.interrupt_id() < IMSIC_MAX_NUMBER_OF_INTERRUPT_IDS,
+            Error::InvalidParameter()
+        )?;
+        ensure!(
+            self.allowed_external_interrupts & MIE_VSEIP_MASK != 0,
+            Error::ExternalInterruptNotAllowed()
+        )?;
+        self.broadcast_remote_command(ConfidentialHartRemoteCommand::InjectExternalInterrupt(
+            request,
+        ))
+    }
+
+    fn aia_mut(&mut self) -> Result<&mut ConfidentialVmAia, Error> {
+        self.aia.as_mut().ok_or(Error::AiaNotInitialized())
+    }
 }
 
 /* Management of MMIO regions */
@@ -205,8 +356,293 @@ impl ConfidentialVm {
     }
 }
 
+/* Lazily populated pages */
+impl ConfidentialVm {
+    pub fn is_lazy_page_pending(&self, address: usize) -> bool {
+        self.lazy_pages.is_pending(address)
+    }
+
+    /// Copies the page donated by the hypervisor into confidential memory and maps it into the address space of the confidential VM.
+    /// Returns error if the page is not pending or its content does not match the manifest, in which case the page is not mapped.
+    ///
+    /// The caller must ensure that all confidential harts observe the new mapping, i.e., by clearing address translation caches.
+    pub fn populate_lazy_page(
+        &mut self,
+        address: &ConfidentialVmPhysicalAddress,
+        source_address: NonConfidentialMemoryAddress,
+    ) -> Result<PageSize, Error> {
+        let page = PageAllocator::acquire_page(LazyPageManifest::PAGE_SIZE)?
+            .copy_from_non_confidential_memory(source_address)?;
+        if let Err(error) = self.lazy_pages.verify(address, &page) {
+            PageAllocator::release_pages(alloc::vec![page.deallocate()]);
+            return Err(error);
+        }
+        let page_size = self.memory_protector.map_confidential_page(address, page)?;
+        self.lazy_pages.remove(address);
+        Ok(page_size)
+    }
+}
+
+/* Migration */
+impl ConfidentialVm {
+    /// Exports the state of the confidential VM into a migration image stored in the given buffer, see `MigrationWriter`. Returns the size
+    /// of the image. Returns error if any confidential hart is running or cannot be migrated, or if the confidential VM uses the AIA or
+    /// shares pages with the hypervisor, because this state refers to the source host.
+    ///
+    /// Once the image is written, all confidential harts are shut down, so that the confidential VM never runs again on this security
+    /// monitor. Otherwise the hypervisor could run two copies of the same confidential VM. The hypervisor can then destroy it.
+    pub fn export(&mut self, buffer: MigrationBuffer) -> Result<usize, Error> {
+        ensure_not!(
+            self.confidential_harts.iter().any(|hart| hart.is_dummy()),
+            Error::HartAlreadyRunning()
+        )?;
+        ensure_not!(
+            self.are_all_harts_shutdown(),
+            Error::MigrationNotSupported()
+        )?;
+        ensure!(self.aia.is_none(), Error::MigrationNotSupported())?;
+        let harts_states = self
+            .confidential_harts
+            .iter()
+            .map(|hart| hart.migrated_state())
+            .collect::<Result<Vec<_>, Error>>()?;
+        let mut number_of_pages = 0;
+        self.memory_protector
+            .for_each_confidential_page(|_, _| {
+                number_of_pages += 1;
+                Ok(())
+            })
+            .map_err(|_| Error::MigrationNotSupported())?;
+        // The time observed by the confidential VM, so that it does not go backward on the destination.
+        let guest_time = Plat::get_clint().lock().read_mtime().wrapping_add(
+            self.confidential_harts[0]
+                .csrs()
+                .htimedelta
+                .read_from_main_memory(),
+        );
+
+        let mut writer = MigrationWriter::new(buffer, &self.measurements.digest())?;
+        let mut header = Record::with_capacity(MAX_RECORD_SIZE);
+        header.push_usize(harts_states.len());
+        header.push_usize(number_of_pages);
+        header.push_usize(self.lazy_pages.pending_pages().count());
+        header.push_usize(self.mmio_regions.len());
+        header.push_usize(self.allowed_external_interrupts);
+        header.push_usize(guest_time);
+        self.measurements
+            .registers()
+            .iter()
+            .chain(self.runtime_measurements.registers())
+            .for_each(|register| header.push_digest(register));
+        writer.write_record(header)?;
+
+        harts_states.iter().try_for_each(|state| {
+            let mut record = Record::with_capacity(MAX_RECORD_SIZE);
+            state.iter().for_each(|value| record.push_usize(*value));
+            writer.write_record(record)
+        })?;
+        self.lazy_pages
+            .pending_pages()
+            .try_for_each(|(address, digest)| {
+                let mut record = Record::with_capacity(MAX_RECORD_SIZE);
+                record.push_usize(*address);
+                record.push_digest(digest);
+                writer.write_record(record)
+            })?;
+        self.mmio_regions.iter().try_for_each(|region| {
+            let mut record = Record::with_capacity(MAX_RECORD_SIZE);
+            record.push_usize(region.base_address.usize());
+            record.push_usize(region.one_past_the_end_address.usize());
+            writer.write_record(record)
+        })?;
+        self.memory_protector
+            .for_each_confidential_page(|address, page| {
+                let mut record = Record::with_capacity(MAX_RECORD_SIZE);
+                record.push_usize(address);
+                record.push_usize(page.size().in_bytes());
+                writer.write_record(record)?;
+                writer.write_page_content(page)
+            })?;
+        let image_size = writer.finish();
+
+        self.confidential_harts
+            .iter_mut()
+            .for_each(|hart| hart.transition_to_shutdown());
+        debug!(
+            "Exported ConfidentialVM[{:?}], image of {} bytes",
+            self.id, image_size
+        );
+        Ok(image_size)
+    }
+
+    /// Reconstructs a confidential VM from a migration image stored in the given buffer, see `export`. The confidential harts are
+    /// configured like during the promotion, with the shared memory of the hypervisor hart. Returns error if the image is malformed, has
+    /// been modified, or was not produced by a security monitor sharing the migration secret.
+    ///
+    /// The image is authenticated, thus the confidential VM keeps the measurements of the exported confidential VM without being measured
+    /// again.
+    pub fn import(
+        buffer: MigrationBuffer,
+        shared_memory: &NaclSharedMemory,
+    ) -> Result<MigratedConfidentialVm, Error> {
+        let mut reader = MigrationReader::new(buffer)?;
+        let mut header = reader.read_record()?;
+        let number_of_harts = header.next_usize()?;
+        let number_of_pages = header.next_usize()?;
+        let number_of_lazy_pages = header.next_usize()?;
+        let number_of_mmio_regions = header.next_usize()?;
+        let allowed_external_interrupts = header.next_usize()?;
+        let guest_time = header.next_usize()?;
+        let mut measurements =
+            StaticMeasurements::new(MeasurementDigest::default(), MeasurementDigest::default());
+        let mut runtime_measurements = RuntimeMeasurements::new();
+        measurements
+            .registers_mut()
+            .iter_mut()
+            .chain(runtime_measurements.registers_mut())
+            .try_for_each(|register| {
+                *register = header.next_digest()?;
+                Ok::<(), Error>(())
+            })?;
+        header.finish()?;
+        ensure!(
+            &measurements.digest() == reader.identity(),
+            Error::MigrationImageNotAuthentic()
+        )?;
+        ensure!(
+            number_of_harts > 0 && number_of_harts <= Self::MAX_NUMBER_OF_HARTS_PER_VM,
+            Error::InvalidMigrationImage()
+        )?;
+        ensure!(
+            number_of_mmio_regions <= Self::MAX_NUMBER_OF_MMIO_REGIONS,
+            Error::InvalidMigrationImage()
+        )?;
+
+        let htimedelta = guest_time.wrapping_sub(Plat::get_clint().lock().read_mtime());
+        let confidential_harts = (0..number_of_harts)
+            .map(|confidential_hart_id| {
+                let mut record = reader.read_record()?;
+                let mut state = [0; ConfidentialHart::MIGRATED_STATE_LEN];
+                state.iter_mut().try_for_each(|value| {
+                    *value = record.next_usize()?;
+                    Ok::<(), Error>(())
+                })?;
+                record.finish()?;
+                ConfidentialHart::from_migrated_state(
+                    confidential_hart_id,
+                    htimedelta,
+                    shared_memory,
+                    &state,
+                )
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let mut lazy_pages = LazyPageManifest::empty();
+        (0..number_of_lazy_pages).try_for_each(|_| {
+            let mut record = reader.read_record()?;
+            let address = record.next_usize()?;
+            let digest = record.next_digest()?;
+            record.finish()?;
+            lazy_pages.insert(address, digest)
+        })?;
+
+        let mmio_regions = (0..number_of_mmio_regions)
+            .map(|_| {
+                let mut record = reader.read_record()?;
+                let base_address = record.next_usize()?;
+                let one_past_the_end_address = record.next_usize()?;
+                record.finish()?;
+                ensure!(
+                    base_address < one_past_the_end_address,
+                    Error::InvalidMigrationImage()
+                )?;
+                Ok(ConfidentialVmMmioRegion::new(
+                    base_address,
+                    one_past_the_end_address - base_address,
+                ))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        // Pages are imported last, so that no page must be released if the rest of the image is malformed.
+        let mut memory_protector = ConfidentialVmMemoryProtector::empty()?;
+        let result = (0..number_of_pages).try_for_each(|_| {
+            let mut record = reader.read_record()?;
+            let address = record.next_usize()?;
+            let page_size = page_size_from_bytes(record.next_usize()?)?;
+            record.finish()?;
+            ensure!(
+                address % page_size.in_bytes() == 0,
+                Error::InvalidMigrationImage()
+            )?;
+            let page = reader.read_page_content(page_size)?;
+            memory_protector
+                .map_confidential_page(&ConfidentialVmPhysicalAddress::new(address), page)
+                .map(|_| ())
+        });
+        if let Err(error) = result {
+            memory_protector.into_root_page_table().deallocate();
+            return Err(error);
+        }
+        // The source security monitor might have lacked large pages, this one might have them.
+        memory_protector.coalesce_pages();
+
+        Ok(MigratedConfidentialVm {
+            confidential_harts,
+            measurements,
+            runtime_measurements,
+            memory_protector,
+            lazy_pages,
+            allowed_external_interrupts,
+            mmio_regions,
+        })
+    }
+
+    /// Constructs the confidential VM imported from a migration image, see `import`.
+    ///
+    /// # Safety
+    ///
+    /// The id of the confidential VM must be unique.
+    pub fn from_migrated(id: ConfidentialVmId, migrated: MigratedConfidentialVm) -> Self {
+        let mut confidential_vm = Self::new(
+            id,
+            migrated.confidential_harts,
+            migrated.measurements,
+            migrated.memory_protector,
+            migrated.lazy_pages,
+        );
+        confidential_vm.runtime_measurements = migrated.runtime_measurements;
+        confidential_vm.allowed_external_interrupts = migrated.allowed_external_interrupts;
+        confidential_vm.mmio_regions = migrated.mmio_regions;
+        confidential_vm
+    }
+}
+
+/// The state of a confidential VM read from a migration image, which becomes a confidential VM once it gets a unique id.
+pub struct MigratedConfidentialVm {
+    confidential_harts: Vec<ConfidentialHart>,
+    measurements: StaticMeasurements,
+    runtime_measurements: RuntimeMeasurements,
+    memory_protector: ConfidentialVmMemoryProtector,
+    lazy_pages: LazyPageManifest,
+    allowed_external_interrupts: usize,
+    mmio_regions: Vec<ConfidentialVmMmioRegion>,
+}
+
 /* Lifecycle related */
 impl ConfidentialVm {
+    /// Cancels the requests of the confidential harts the hypervisor did not respond to before their deadline, see
+    /// `ConfidentialHart::expire_resumable_operation`. Returns the earliest deadline of the requests that are still pending, usize::MAX
+    /// if none.
+    ///
+    /// Confidential harts running on a physical hart are dummy harts in the confidential VM, they do not wait for any request.
+    pub fn expire_resumable_operations(&mut self, now: usize) -> usize {
+        self.confidential_harts
+            .iter_mut()
+            .map(|confidential_hart| confidential_hart.expire_resumable_operation(now))
+            .min()
+            .unwrap_or(usize::MAX)
+    }
+
     pub fn are_all_harts_shutdown(&self) -> bool {
         self.confidential_harts
             .iter()
@@ -267,16 +703,27 @@ impl ConfidentialVm {
                         // hardware hart with IPI. Consequently, the hardware hart running the target confidential hart will
                         // trap into the security monitor, which will execute ConfidentialHartRemoteCommands on the targetted
                         // confidential hart.
-                        self.try_confidential_hart_remote_commands(
+                        let merged = self.try_confidential_hart_remote_commands(
                             confidential_hart_id,
                             |ref mut remote_commands| {
+                                if remote_commands
+                                    .iter_mut()
+                                    .any(|pending| pending.try_merge(&remote_command))
+                                {
+                                    return Ok(true);
+                                }
                                 ensure!(
                                     remote_commands.len() < Self::MAX_NUMBER_OF_COMMANDS,
                                     Error::ReachedMaxNumberOfRemoteCommands()
                                 )?;
-                                Ok(remote_commands.push(remote_command.clone()))
+                                remote_commands.push(remote_command.clone());
+                                Ok(false)
                             },
                         )?;
+                        if merged {
+                            // The command was merged into a pending one, the hardware hart has already been interrupted to execute it.
+                            return Ok(());
+                        }
                         InterruptController::try_read(|controller| {
                             controller.send_ipi(id_of_hardware_hart_running_confidential_hart)
                         })
@@ -0,0 +1,196 @@
+// SPDX-FileCopyrightText: 2023 IBM Corporation
+// SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
+// SPDX-License-Identifier: Apache-2.0
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::ace::core::architecture::PageSize;
+use crate::ace::error::Error;
+use crate::{ensure, ensure_not};
+
+/// Parameters of the confidential VM's Advanced Interrupt Architecture (AIA), provided by the hypervisor. They describe the layout of the
+/// virtual IMSICs in the confidential VM's guest physical address space, see the `sbi_cove_tvm_aia_params` structure of the COVI ABI.
+pub struct AiaParams {
+    pub imsic_base_address: usize,
+    pub group_index_bits: usize,
+    pub group_index_shift: usize,
+    pub hart_index_bits: usize,
+    pub guest_index_bits: usize,
+    pub guests_per_hart: usize,
+}
+
+impl AiaParams {
+    /// Size of the `sbi_cove_tvm_aia_params` structure in the hypervisor memory, in bytes.
+    pub const SIZE_IN_BYTES: usize = 4 * core::mem::size_of::<usize>();
+    /// The group index is located above the bits selecting the hart and guest interrupt files.
+    const MIN_GROUP_INDEX_SHIFT: usize = 24;
+
+    /// Decodes the parameters from the 64-bit words of the `sbi_cove_tvm_aia_params` structure, whose fields after the base address
+    /// are 32-bit wide.
+    pub fn from_words(words: [usize; 4]) -> Result<Self, Error> {
+        let params = Self {
+            imsic_base_address: words[0],
+            group_index_bits: words[1] & 0xffff_ffff,
+            group_index_shift: words[1] >> 32,
+            hart_index_bits: words[2] & 0xffff_ffff,
+            guest_index_bits: words[2] >> 32,
+            guests_per_hart: words[3] & 0xffff_ffff,
+        };
+        ensure!(
+            params.imsic_base_address % PageSize::Size4KiB.in_bytes() == 0,
+            Error::AddressNotAligned()
+        )?;
+        ensure!(
+            params.group_index_bits <= 8
+                && params.group_index_shift >= Self::MIN_GROUP_INDEX_SHIFT
+                && params.group_index_shift + params.group_index_bits <= 56
+                && params.hart_index_bits <= 14
+                && params.guest_index_bits <= 6,
+            Error::InvalidParameter()
+        )?;
+        // The guest index must be able to select the supervisor interrupt file and all guest interrupt files of a hart
+        ensure!(
+            params.guests_per_hart < (1 << params.guest_index_bits),
+            Error::InvalidParameter()
+        )?;
+        Ok(params)
+    }
+
+    /// Returns true if the address is the address of a virtual IMSIC of a confidential hart, according to the layout of the IMSICs.
+    fn is_imsic_address(&self, address: usize) -> bool {
+        let page_shift = PageSize::Size4KiB.in_bytes().trailing_zeros() as usize;
+        let group_mask = ((1 << self.group_index_bits) - 1) << self.group_index_shift;
+        let hart_mask = ((1 << self.hart_index_bits) - 1) << (page_shift + self.guest_index_bits);
+        let offset = address.wrapping_sub(self.imsic_base_address) & !group_mask;
+        address >= self.imsic_base_address
+            && address % PageSize::Size4KiB.in_bytes() == 0
+            && offset & !hart_mask == 0
+    }
+}
+
+/// The physical hart and guest interrupt files to which a confidential hart is bound.
+#[derive(Clone, Copy, PartialEq)]
+enum ImsicBinding {
+    Unbound,
+    Bound {
+        hardware_hart_id: usize,
+        guest_interrupt_files: usize,
+    },
+    /// The hypervisor started to unbind the confidential hart, which cannot run until bound again.
+    Unbinding,
+}
+
+/// State of the confidential VM's AIA, configured by the hypervisor with the COVI extension.
+///
+/// External interrupts injected by the hypervisor are delivered through the software interrupt files of the confidential harts, the
+/// bindings only determine the physical harts on which the hypervisor is allowed to run each confidential hart.
+pub struct ConfidentialVmAia {
+    params: AiaParams,
+    /// Guest physical address of the virtual IMSIC of each confidential hart, indexed by the confidential hart id.
+    imsic_addresses: Vec<Option<usize>>,
+    /// Binding of each confidential hart, indexed by the confidential hart id.
+    bindings: Vec<ImsicBinding>,
+}
+
+impl ConfidentialVmAia {
+    pub fn new(params: AiaParams, number_of_confidential_harts: usize) -> Self {
+        Self {
+            params,
+            imsic_addresses: vec![None; number_of_confidential_harts],
+            bindings: vec![ImsicBinding::Unbound; number_of_confidential_harts],
+        }
+    }
+
+    /// Sets the guest physical address of the confidential hart's virtual IMSIC. Returns error if the address does not match the layout
+    /// of the IMSICs or is already used by another confidential hart.
+    pub fn set_imsic_address(
+        &mut self,
+        confidential_hart_id: usize,
+        address: usize,
+    ) -> Result<(), Error> {
+        ensure!(
+            confidential_hart_id < self.imsic_addresses.len(),
+            Error::InvalidHartId()
+        )?;
+        ensure!(
+            self.params.is_imsic_address(address),
+            Error::InvalidParameter()
+        )?;
+        ensure_not!(
+            self.imsic_addresses
+                .iter()
+                .enumerate()
+                .any(|(id, other)| id != confidential_hart_id && *other == Some(address)),
+            Error::InvalidParameter()
+        )?;
+        self.imsic_addresses[confidential_hart_id] = Some(address);
+        Ok(())
+    }
+
+    /// Binds the confidential hart to guest interrupt files of the physical hart. The confidential hart must have a virtual IMSIC and
+    /// must not be bound already.
+    pub fn bind(
+        &mut self,
+        confidential_hart_id: usize,
+        hardware_hart_id: usize,
+        guest_interrupt_files: usize,
+    ) -> Result<(), Error> {
+        ensure!(
+            self.imsic_addresses
+                .get(confidential_hart_id)
+                .ok_or(Error::InvalidHartId())?
+                .is_some(),
+            Error::ImsicNotConfigured()
+        )?;
+        // Guest interrupt files are numbered from 1
+        ensure!(
+            guest_interrupt_files != 0 && guest_interrupt_files & 1 == 0,
+            Error::InvalidParameter()
+        )?;
+        ensure!(
+            self.bindings[confidential_hart_id] == ImsicBinding::Unbound,
+            Error::InvalidParameter()
+        )?;
+        self.bindings[confidential_hart_id] = ImsicBinding::Bound {
+            hardware_hart_id,
+            guest_interrupt_files,
+        };
+        Ok(())
+    }
+
+    /// Starts unbinding the confidential hart from its guest interrupt files.
+    pub fn unbind_begin(&mut self, confidential_hart_id: usize) -> Result<(), Error> {
+        let binding = self
+            .bindings
+            .get_mut(confidential_hart_id)
+            .ok_or(Error::InvalidHartId())?;
+        ensure!(
+            matches!(binding, ImsicBinding::Bound { .. }),
+            Error::ImsicNotBound()
+        )?;
+        *binding = ImsicBinding::Unbinding;
+        Ok(())
+    }
+
+    /// Completes unbinding the confidential hart, which can then be bound to another physical hart.
+    pub fn unbind_end(&mut self, confidential_hart_id: usize) -> Result<(), Error> {
+        let binding = self
+            .bindings
+            .get_mut(confidential_hart_id)
+            .ok_or(Error::InvalidHartId())?;
+        ensure!(
+            *binding == ImsicBinding::Unbinding,
+            Error::InvalidParameter()
+        )?;
+        *binding = ImsicBinding::Unbound;
+        Ok(())
+    }
+
+    /// Returns true if the confidential hart is bound to the physical hart.
+    pub fn is_bound_to(&self, confidential_hart_id: usize, hardware_hart_id: usize) -> bool {
+        matches!(
+            self.bindings.get(confidential_hart_id),
+            Some(ImsicBinding::Bound { hardware_hart_id: id, .. }) if *id == hardware_hart_id
+        )
+    }
+}
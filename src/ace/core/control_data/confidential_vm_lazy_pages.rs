@@ -0,0 +1,185 @@
+// SPDX-FileCopyrightText: 2023 IBM Corporation
+// SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
+// SPDX-License-Identifier: Apache-2.0
+use alloc::collections::BTreeMap;
+use core::mem::size_of;
+
+use crate::ace::core::architecture::PageSize;
+use crate::ace::core::control_data::MeasurementDigest;
+use crate::ace::core::crypto::{Crypto, CryptoBackend, Hasher};
+use crate::ace::core::memory_layout::ConfidentialVmPhysicalAddress;
+use crate::ace::core::memory_protector::ConfidentialVmMemoryProtector;
+use crate::ace::core::page_allocator::{Allocated, Page};
+use crate::ace::error::Error;
+use crate::{ensure, ensure_not};
+
+/// The pages of a confidential VM that are not part of its initial state but are donated by the hypervisor on first access. Populating
+/// pages lazily reduces the start latency of large confidential VMs, because the security monitor does not have to copy and measure the
+/// entire VM memory during the promotion.
+///
+/// The hypervisor declares the lazy pages with a manifest located in the VM memory at the time of the promotion. The manifest is a list of
+/// the guest physical addresses of the lazy pages together with the expected measurements of their content. The manifest is part of the
+/// static measurements, thus the hypervisor cannot donate a page whose content differs from the one attested.
+///
+/// The manifest starts with the number of entries (8 bytes, little endian) followed by the entries. Each entry consists of the guest
+/// physical address of the page (8 bytes, little endian) and the digest of the page, as computed by `Page::measure` starting from an
+/// empty digest.
+pub struct LazyPageManifest {
+    pending_pages: BTreeMap<usize, MeasurementDigest>,
+}
+
+impl LazyPageManifest {
+    /// Lazy pages are always 4KiB pages, like pages shared with the hypervisor.
+    pub const PAGE_SIZE: PageSize = PageSize::Size4KiB;
+    /// A maximum number of lazy pages, which bounds the memory the security monitor needs to store the manifest.
+    const MAX_NUMBER_OF_PAGES: usize = 65536;
+    const ENTRY_SIZE: usize = size_of::<usize>() + size_of::<MeasurementDigest>();
+
+    pub fn empty() -> Self {
+        Self {
+            pending_pages: BTreeMap::new(),
+        }
+    }
+
+    /// Reads the manifest located at the given guest physical address. Must be called after the VM's data has been copied to the
+    /// confidential memory, so that the hypervisor cannot modify the manifest after it was read. Returns error if the manifest is malformed
+    /// or declares a page that is already part of the VM's initial state.
+    pub fn from_confidential_vm_memory(
+        memory_protector: &ConfidentialVmMemoryProtector,
+        address: &ConfidentialVmPhysicalAddress,
+    ) -> Result<Self, Error> {
+        let mut number_of_pages = [0u8; size_of::<usize>()];
+        memory_protector.read_from_confidential_vm(address, &mut number_of_pages)?;
+        let number_of_pages = usize::from_le_bytes(number_of_pages);
+        ensure!(
+            number_of_pages <= Self::MAX_NUMBER_OF_PAGES,
+            Error::InvalidLazyPageManifest()
+        )?;
+
+        let mut manifest = Self::empty();
+        let mut entry = [0u8; Self::ENTRY_SIZE];
+        (0..number_of_pages).try_for_each(|i| {
+            let entry_address = address.add(size_of::<usize>() + i * Self::ENTRY_SIZE);
+            memory_protector.read_from_confidential_vm(&entry_address, &mut entry)?;
+            let (page_address, digest) = entry.split_at(size_of::<usize>());
+            let page_address = usize::from_le_bytes(page_address.try_into()?);
+            ensure!(
+                page_address % Self::PAGE_SIZE.in_bytes() == 0,
+                Error::InvalidLazyPageManifest()
+            )?;
+            ensure_not!(
+                memory_protector
+                    .translate_address(&ConfidentialVmPhysicalAddress::new(page_address))
+                    .is_ok(),
+                Error::InvalidLazyPageManifest()
+            )?;
+            ensure!(
+                manifest
+                    .pending_pages
+                    .insert(page_address, MeasurementDigest::clone_from_slice(digest))
+                    .is_none(),
+                Error::InvalidLazyPageManifest()
+            )
+        })?;
+        Ok(manifest)
+    }
+
+    /// Returns true if the page containing the given guest physical address is declared in the manifest and was not donated yet.
+    pub fn is_pending(&self, address: usize) -> bool {
+        self.pending_pages
+            .contains_key(&(address & !(Self::PAGE_SIZE.in_bytes() - 1)))
+    }
+
+    /// Extends the measurement with all entries of the manifest, in the order from the lowest to the highest guest physical address.
+    pub fn measure(&self, digest: &mut MeasurementDigest) {
+        self.pending_pages
+            .iter()
+            .for_each(|(address, page_digest)| {
+                let mut hasher = Crypto::hasher_with_prefix(digest);
+                hasher.update(&address.to_le_bytes());
+                hasher.update(page_digest);
+                hasher.finalize_into(digest);
+            });
+    }
+
+    /// Returns error if the page is not pending or if its content does not match the measurement declared in the manifest.
+    pub fn verify(
+        &self,
+        address: &ConfidentialVmPhysicalAddress,
+        page: &Page<Allocated>,
+    ) -> Result<(), Error> {
+        let expected_digest = self
+            .pending_pages
+            .get(&address.usize())
+            .ok_or(Error::LazyPageNotPending(address.usize()))?;
+        let mut digest = MeasurementDigest::default();
+        page.measure(&mut digest, address.usize());
+        ensure!(
+            &digest == expected_digest,
+            Error::LazyPageMeasurementMismatch(address.usize())
+        )
+    }
+
+    /// Removes the page from the manifest once it has been mapped into the address space of the confidential VM.
+    pub fn remove(&mut self, address: &ConfidentialVmPhysicalAddress) {
+        self.pending_pages.remove(&address.usize());
+    }
+
+    /// Returns the pending pages and their expected measurements, used to migrate the confidential VM.
+    pub(super) fn pending_pages(&self) -> impl Iterator<Item = (&usize, &MeasurementDigest)> {
+        self.pending_pages.iter()
+    }
+
+    /// Declares a pending page of a migrated confidential VM. Returns error if the page is not aligned, already declared, or if the
+    /// manifest is full.
+    pub(super) fn insert(
+        &mut self,
+        address: usize,
+        digest: MeasurementDigest,
+    ) -> Result<(), Error> {
+        ensure!(
+            self.pending_pages.len() < Self::MAX_NUMBER_OF_PAGES,
+            Error::InvalidLazyPageManifest()
+        )?;
+        ensure!(
+            address % Self::PAGE_SIZE.in_bytes() == 0,
+            Error::InvalidLazyPageManifest()
+        )?;
+        ensure!(
+            self.pending_pages.insert(address, digest).is_none(),
+            Error::InvalidLazyPageManifest()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lazy_page_lookup_and_measurement() {
+        let mut manifest = LazyPageManifest::empty();
+        manifest
+            .pending_pages
+            .insert(0x8020_0000, MeasurementDigest::default());
+        manifest
+            .pending_pages
+            .insert(0x8000_0000, MeasurementDigest::default());
+
+        assert!(manifest.is_pending(0x8000_0000));
+        assert!(manifest.is_pending(0x8000_0fff));
+        assert!(!manifest.is_pending(0x8000_1000));
+        assert!(manifest.is_pending(0x8020_0008));
+
+        let mut digest = MeasurementDigest::default();
+        manifest.measure(&mut digest);
+        assert_ne!(digest, MeasurementDigest::default());
+
+        // The measurement changes once a page has been donated.
+        manifest.remove(&ConfidentialVmPhysicalAddress::new(0x8020_0000));
+        assert!(!manifest.is_pending(0x8020_0008));
+        let mut remaining_digest = MeasurementDigest::default();
+        manifest.measure(&mut remaining_digest);
+        assert_ne!(digest, remaining_digest);
+    }
+}
@@ -3,16 +3,21 @@
 // SPDX-License-Identifier: Apache-2.0
 use sha2::digest::crypto_common::generic_array::GenericArray;
 
+use crate::ace::core::crypto::{Crypto, CryptoBackend, Hasher};
+use crate::ace::error::Error;
+
 pub type DigestType = sha2::Sha384;
 pub type MeasurementDigest =
     GenericArray<u8, <DigestType as sha2::digest::OutputSizeUser>::OutputSize>;
 
 /// Number of registers storing boottime integrity measurements. CoVE spec requires at least 1 and maximum 8.
-const NUMBER_OF_REGISTERS: usize = 8;
+pub const NUMBER_OF_REGISTERS: usize = 8;
 /// The number of the register that stores the measurement of confidential VM code and static data
 const TVM_CODE_AND_STATIC_DATA_REGISTER_ID: usize = 4;
 /// The number of the register that stores the measurement of confidential boot hart state
 const TVM_CONFIGURATION_REGISTER_ID: usize = 5;
+/// The first register that the confidential VM can extend at runtime, all following registers are runtime registers as well.
+const FIRST_RUNTIME_REGISTER_ID: usize = 6;
 
 pub struct StaticMeasurements([MeasurementDigest; NUMBER_OF_REGISTERS]);
 
@@ -23,6 +28,28 @@ impl StaticMeasurements {
         measurements.0[TVM_CONFIGURATION_REGISTER_ID] = configuration;
         measurements
     }
+
+    pub fn register(&self, register_id: usize) -> Option<&MeasurementDigest> {
+        self.0.get(register_id)
+    }
+
+    /// Returns all measurement registers, used to migrate the confidential VM.
+    pub(super) fn registers(&self) -> &[MeasurementDigest] {
+        &self.0
+    }
+
+    pub(super) fn registers_mut(&mut self) -> &mut [MeasurementDigest] {
+        &mut self.0
+    }
+
+    /// Returns a digest over all measurement registers, identifying the confidential VM.
+    pub fn digest(&self) -> MeasurementDigest {
+        let mut digest = MeasurementDigest::default();
+        let mut hasher = Crypto::hasher_with_prefix(&MeasurementDigest::default());
+        self.0.iter().for_each(|register| hasher.update(register));
+        hasher.finalize_into(&mut digest);
+        digest
+    }
 }
 
 impl core::fmt::Debug for StaticMeasurements {
@@ -33,3 +60,78 @@ impl core::fmt::Debug for StaticMeasurements {
         write!(f, "")
     }
 }
+
+/// Measurement registers extended by the confidential VM after boot, for example with the measurements of the software it loads. They
+/// are reported in the attestation evidence but, unlike the static measurements, do not change the identity of the confidential VM.
+pub struct RuntimeMeasurements(
+    [MeasurementDigest; NUMBER_OF_REGISTERS - FIRST_RUNTIME_REGISTER_ID],
+);
+
+impl RuntimeMeasurements {
+    pub fn new() -> Self {
+        Self([MeasurementDigest::default(); NUMBER_OF_REGISTERS - FIRST_RUNTIME_REGISTER_ID])
+    }
+
+    /// Extends the register with the given digest, i.e., the register becomes the hash of its previous value and of the digest. Returns
+    /// error if the register is not a runtime register.
+    pub fn extend(&mut self, register_id: usize, digest: &MeasurementDigest) -> Result<(), Error> {
+        let register = register_id
+            .checked_sub(FIRST_RUNTIME_REGISTER_ID)
+            .and_then(|index| self.0.get_mut(index))
+            .ok_or(Error::InvalidParameter())?;
+        let mut hasher = Crypto::hasher_with_prefix(register);
+        hasher.update(digest);
+        hasher.finalize_into(register);
+        Ok(())
+    }
+
+    /// Returns the register, or `None` if the register is not a runtime register.
+    pub fn register(&self, register_id: usize) -> Option<&MeasurementDigest> {
+        register_id
+            .checked_sub(FIRST_RUNTIME_REGISTER_ID)
+            .and_then(|index| self.0.get(index))
+    }
+
+    /// Returns all runtime registers, used to migrate the confidential VM.
+    pub(super) fn registers(&self) -> &[MeasurementDigest] {
+        &self.0
+    }
+
+    pub(super) fn registers_mut(&mut self) -> &mut [MeasurementDigest] {
+        &mut self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extend_runtime_registers() {
+        let mut measurements = RuntimeMeasurements::new();
+        let digest = Crypto::hash(b"runtime component");
+        measurements
+            .extend(FIRST_RUNTIME_REGISTER_ID, &digest)
+            .unwrap();
+
+        let mut expected = MeasurementDigest::default();
+        let mut hasher = Crypto::hasher_with_prefix(&MeasurementDigest::default());
+        hasher.update(&digest);
+        hasher.finalize_into(&mut expected);
+        assert_eq!(
+            measurements.register(FIRST_RUNTIME_REGISTER_ID),
+            Some(&expected)
+        );
+        assert_eq!(
+            measurements.register(NUMBER_OF_REGISTERS - 1),
+            Some(&MeasurementDigest::default())
+        );
+
+        // Static registers cannot be extended
+        assert!(measurements
+            .extend(TVM_CODE_AND_STATIC_DATA_REGISTER_ID, &digest)
+            .is_err());
+        assert!(measurements.extend(NUMBER_OF_REGISTERS, &digest).is_err());
+        assert_eq!(measurements.register(TVM_CONFIGURATION_REGISTER_ID), None);
+    }
+}
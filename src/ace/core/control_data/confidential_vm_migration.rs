@@ -0,0 +1,466 @@
+// SPDX-FileCopyrightText: 2023 IBM Corporation
+// SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
+// SPDX-License-Identifier: Apache-2.0
+//! A migration image carries the state of a confidential VM between two security monitors, e.g., to live migrate the confidential VM to
+//! another host. The hypervisor transfers the image, thus the image is encrypted and authenticated with a migration key that only security
+//! monitors sharing the same migration secret can derive (see `KeyHierarchy::migration_key`). The migration key is bound to the static
+//! measurements of the confidential VM, so the image of one confidential VM cannot be presented as the image of another one.
+//!
+//! The image starts with a preamble in clear: the magic value, the version of the format, the digest of the static measurements
+//! identifying the confidential VM, and a nonce unique to the image. The preamble is followed by a sequence of records, each consisting of
+//! the length of the record (8 bytes, little endian), the encrypted record, and the authentication tag. Records are numbered, so the
+//! hypervisor can neither reorder, drop, nor replay records within an image.
+//!
+//! Records are encrypted with a keystream derived from the migration key, the nonce, and the number of the record. The tag authenticates
+//! the encrypted record together with its number and length. The record is copied into confidential memory before being authenticated,
+//! so the hypervisor cannot modify it after the verification.
+
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::ace::core::architecture::{PageSize, CSR};
+use crate::ace::core::control_data::MeasurementDigest;
+use crate::ace::core::crypto::{Crypto, CryptoBackend, KeyHierarchy};
+use crate::ace::core::memory_layout::{MemoryLayout, NonConfidentialMemoryAddress};
+use crate::ace::core::page_allocator::{Allocated, Page, PageAllocator};
+use crate::ace::error::Error;
+use crate::platform::{Plat, Platform};
+use crate::{ensure, ensure_not};
+
+/// Counter distinguishing the migration images produced by this security monitor within the same timer tick.
+static NONCE_COUNTER: AtomicU32 = AtomicU32::new(0);
+
+const MAGIC: usize = usize::from_le_bytes(*b"ACEMIGR\0");
+const VERSION: usize = 1;
+const NONCE_SIZE: usize = 16;
+const TAG_SIZE: usize = size_of::<MeasurementDigest>();
+/// A maximum size of a record, which bounds the memory the security monitor needs to process a record. The content of pages is split into
+/// records of this size, the size of the smallest page.
+pub const MAX_RECORD_SIZE: usize = 4096;
+const KEYSTREAM_LABEL: &[u8] = b"ACE migration keystream";
+const TAG_LABEL: &[u8] = b"ACE migration tag";
+
+/// A buffer in non-confidential memory provided by the hypervisor to store a migration image. The buffer is accessed word by word, with
+/// every access checked to be within the non-confidential memory.
+pub struct MigrationBuffer {
+    address: NonConfidentialMemoryAddress,
+    size: usize,
+    offset: usize,
+}
+
+impl MigrationBuffer {
+    /// Returns error if the buffer does not start in non-confidential memory or is not aligned to a word. The end of the buffer is checked
+    /// on every access.
+    pub fn new(address: usize, size: usize) -> Result<Self, Error> {
+        ensure!(
+            address % size_of::<usize>() == 0,
+            Error::AddressNotAligned()
+        )?;
+        let address = NonConfidentialMemoryAddress::new(address as *mut usize)?;
+        Ok(Self {
+            address,
+            size,
+            offset: 0,
+        })
+    }
+
+    /// Returns the number of bytes written to or read from the buffer so far.
+    pub fn len(&self) -> usize {
+        self.offset
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        ensure!(
+            bytes.len() % size_of::<usize>() == 0,
+            Error::InvalidParameter()
+        )?;
+        ensure!(
+            self.offset + bytes.len() <= self.size,
+            Error::MigrationBufferTooSmall()
+        )?;
+        bytes.chunks_exact(size_of::<usize>()).try_for_each(|word| {
+            let pointer = MemoryLayout::read()
+                .non_confidential_address_at_offset(&self.address, self.offset)?;
+            // Below unsafe is ok because the pointer is in the non-confidential memory, which the security monitor never uses.
+            unsafe { pointer.write(usize::from_le_bytes(word.try_into()?)) };
+            self.offset += size_of::<usize>();
+            Ok(())
+        })
+    }
+
+    fn read(&mut self, bytes: &mut [u8]) -> Result<(), Error> {
+        ensure!(
+            bytes.len() % size_of::<usize>() == 0,
+            Error::InvalidParameter()
+        )?;
+        ensure!(
+            self.offset + bytes.len() <= self.size,
+            Error::InvalidMigrationImage()
+        )?;
+        bytes
+            .chunks_exact_mut(size_of::<usize>())
+            .try_for_each(|word| {
+                let pointer = MemoryLayout::read()
+                    .non_confidential_address_at_offset(&self.address, self.offset)?;
+                // Below unsafe is ok because the pointer is in the non-confidential memory, which the security monitor never uses.
+                word.copy_from_slice(&unsafe { pointer.read() }.to_le_bytes());
+                self.offset += size_of::<usize>();
+                Ok(())
+            })
+    }
+
+    fn read_usize(&mut self) -> Result<usize, Error> {
+        let mut word = [0u8; size_of::<usize>()];
+        self.read(&mut word)?;
+        Ok(usize::from_le_bytes(word))
+    }
+}
+
+/// Encrypts and authenticates the records of a migration image, see the module documentation.
+struct RecordCipher {
+    key: MeasurementDigest,
+    nonce: [u8; NONCE_SIZE],
+    sequence_number: usize,
+}
+
+impl RecordCipher {
+    fn new(key: MeasurementDigest, nonce: [u8; NONCE_SIZE]) -> Self {
+        Self {
+            key,
+            nonce,
+            sequence_number: 0,
+        }
+    }
+
+    /// Encrypts the record in place and returns its authentication tag.
+    fn seal(&mut self, record: &mut [u8]) -> MeasurementDigest {
+        self.apply_keystream(record);
+        let tag = self.tag(record);
+        self.sequence_number += 1;
+        tag
+    }
+
+    /// Authenticates and decrypts the record in place. Returns error if the record or its position in the image was modified, in which
+    /// case the record is left encrypted.
+    fn open(&mut self, record: &mut [u8], tag: &[u8]) -> Result<(), Error> {
+        let expected_tag = self.tag(record);
+        // The comparison takes the same time whatever the position of the first differing byte.
+        let difference = expected_tag
+            .iter()
+            .zip(tag.iter())
+            .fold(0, |difference, (a, b)| difference | (a ^ b));
+        ensure!(
+            difference == 0 && tag.len() == expected_tag.len(),
+            Error::MigrationImageNotAuthentic()
+        )?;
+        self.apply_keystream(record);
+        self.sequence_number += 1;
+        Ok(())
+    }
+
+    fn apply_keystream(&self, record: &mut [u8]) {
+        const BLOCK_INPUT_SIZE: usize = KEYSTREAM_LABEL.len() + NONCE_SIZE + 2 * size_of::<usize>();
+        const BLOCK_NUMBER_OFFSET: usize = BLOCK_INPUT_SIZE - size_of::<usize>();
+        let mut block_input = [0u8; BLOCK_INPUT_SIZE];
+        block_input[..KEYSTREAM_LABEL.len()].copy_from_slice(KEYSTREAM_LABEL);
+        block_input[KEYSTREAM_LABEL.len()..KEYSTREAM_LABEL.len() + NONCE_SIZE]
+            .copy_from_slice(&self.nonce);
+        block_input[KEYSTREAM_LABEL.len() + NONCE_SIZE..BLOCK_NUMBER_OFFSET]
+            .copy_from_slice(&self.sequence_number.to_le_bytes());
+        record
+            .chunks_mut(size_of::<MeasurementDigest>())
+            .enumerate()
+            .for_each(|(i, block)| {
+                block_input[BLOCK_NUMBER_OFFSET..].copy_from_slice(&i.to_le_bytes());
+                let keystream = Crypto::mac(&self.key, &block_input);
+                block
+                    .iter_mut()
+                    .zip(keystream.iter())
+                    .for_each(|(byte, key)| *byte ^= key);
+            });
+    }
+
+    fn tag(&self, record: &[u8]) -> MeasurementDigest {
+        let mut message = Vec::with_capacity(
+            TAG_LABEL.len() + NONCE_SIZE + 2 * size_of::<usize>() + record.len(),
+        );
+        message.extend_from_slice(TAG_LABEL);
+        message.extend_from_slice(&self.nonce);
+        message.extend_from_slice(&self.sequence_number.to_le_bytes());
+        message.extend_from_slice(&record.len().to_le_bytes());
+        message.extend_from_slice(record);
+        Crypto::mac(&self.key, &message)
+    }
+}
+
+/// Writes the records of a migration image to a buffer in non-confidential memory.
+pub struct MigrationWriter {
+    buffer: MigrationBuffer,
+    cipher: RecordCipher,
+}
+
+impl MigrationWriter {
+    /// Writes the preamble of the image of the confidential VM identified by the digest of its static measurements. Returns error if the
+    /// migration key is not available or the buffer is too small.
+    pub fn new(mut buffer: MigrationBuffer, identity: &MeasurementDigest) -> Result<Self, Error> {
+        let key = KeyHierarchy::migration_key(identity)?;
+        let nonce = Self::unique_nonce();
+        buffer.write(&MAGIC.to_le_bytes())?;
+        buffer.write(&VERSION.to_le_bytes())?;
+        buffer.write(identity)?;
+        buffer.write(&nonce)?;
+        Ok(Self {
+            buffer,
+            cipher: RecordCipher::new(key, nonce),
+        })
+    }
+
+    pub fn write_record(&mut self, mut record: Record) -> Result<(), Error> {
+        ensure!(
+            record.data.len() <= MAX_RECORD_SIZE,
+            Error::InvalidParameter()
+        )?;
+        let tag = self.cipher.seal(&mut record.data);
+        self.buffer.write(&record.data.len().to_le_bytes())?;
+        self.buffer.write(&record.data)?;
+        self.buffer.write(&tag)
+    }
+
+    /// Writes the content of the page as a sequence of records, see `MigrationReader::read_page_content`.
+    pub fn write_page_content(&mut self, page: &Page<Allocated>) -> Result<(), Error> {
+        (0..page.size().in_bytes())
+            .step_by(MAX_RECORD_SIZE)
+            .try_for_each(|record_offset| {
+                let mut record = Record::with_capacity(MAX_RECORD_SIZE);
+                (record_offset..record_offset + MAX_RECORD_SIZE)
+                    .step_by(Page::<Allocated>::ENTRY_SIZE)
+                    .try_for_each(|offset| {
+                        record.push_usize(page.read(offset)?);
+                        Ok::<(), Error>(())
+                    })?;
+                self.write_record(record)
+            })
+    }
+
+    /// Returns the size of the image in bytes.
+    pub fn finish(self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Returns a nonce unique to the image, built from the current time, the id of the physical hart, and a counter. The keystream of two
+    /// images is the same only if the same confidential VM is exported with the same nonce, which requires two security monitors sharing
+    /// the migration secret to export it on harts with the same id at the same time.
+    fn unique_nonce() -> [u8; NONCE_SIZE] {
+        let mut nonce = [0u8; NONCE_SIZE];
+        let time = Plat::get_clint().lock().read_mtime();
+        nonce[..8].copy_from_slice(&(time as u64).to_le_bytes());
+        nonce[8..12].copy_from_slice(&(CSR.mhartid.read() as u32).to_le_bytes());
+        nonce[12..].copy_from_slice(&NONCE_COUNTER.fetch_add(1, Ordering::Relaxed).to_le_bytes());
+        nonce
+    }
+}
+
+/// Reads the records of a migration image from a buffer in non-confidential memory.
+pub struct MigrationReader {
+    buffer: MigrationBuffer,
+    cipher: RecordCipher,
+    identity: MeasurementDigest,
+}
+
+impl MigrationReader {
+    /// Reads the preamble of the image. Returns error if the image was not produced by a compatible security monitor or the migration key
+    /// is not available.
+    pub fn new(mut buffer: MigrationBuffer) -> Result<Self, Error> {
+        ensure!(
+            buffer.read_usize()? == MAGIC,
+            Error::InvalidMigrationImage()
+        )?;
+        ensure!(
+            buffer.read_usize()? == VERSION,
+            Error::InvalidMigrationImage()
+        )?;
+        let mut identity = MeasurementDigest::default();
+        buffer.read(&mut identity)?;
+        let mut nonce = [0u8; NONCE_SIZE];
+        buffer.read(&mut nonce)?;
+        // A forged identity results in a different key, so the authentication of the first record fails.
+        let key = KeyHierarchy::migration_key(&identity)?;
+        Ok(Self {
+            buffer,
+            cipher: RecordCipher::new(key, nonce),
+            identity,
+        })
+    }
+
+    /// Returns the digest of the static measurements of the confidential VM, as claimed by the preamble. The claim is authentic only if the
+    /// digest matches the measurements read from the first record.
+    pub fn identity(&self) -> &MeasurementDigest {
+        &self.identity
+    }
+
+    pub fn read_record(&mut self) -> Result<Record, Error> {
+        let length = self.buffer.read_usize()?;
+        ensure!(
+            length <= MAX_RECORD_SIZE && length % size_of::<usize>() == 0,
+            Error::InvalidMigrationImage()
+        )?;
+        let mut data = alloc::vec![0u8; length];
+        self.buffer.read(&mut data)?;
+        let mut tag = [0u8; TAG_SIZE];
+        self.buffer.read(&mut tag)?;
+        self.cipher.open(&mut data, &tag)?;
+        Ok(Record { data, offset: 0 })
+    }
+
+    /// Reads a page of the given size from a sequence of records, see `MigrationWriter::write_page_content`.
+    pub fn read_page_content(&mut self, page_size: PageSize) -> Result<Page<Allocated>, Error> {
+        let mut page = PageAllocator::acquire_page(page_size)?.zeroize();
+        let result = (0..page_size.in_bytes())
+            .step_by(MAX_RECORD_SIZE)
+            .try_for_each(|record_offset| {
+                let mut record = self.read_record()?;
+                (record_offset..record_offset + MAX_RECORD_SIZE)
+                    .step_by(Page::<Allocated>::ENTRY_SIZE)
+                    .try_for_each(|offset| page.write(offset, record.next_usize()?))?;
+                record.finish()
+            });
+        match result {
+            Ok(()) => Ok(page),
+            Err(error) => {
+                PageAllocator::release_pages(alloc::vec![page.deallocate()]);
+                Err(error)
+            }
+        }
+    }
+}
+
+/// The plaintext of a record, a sequence of words and digests.
+pub struct Record {
+    data: Vec<u8>,
+    offset: usize,
+}
+
+impl Record {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            data: Vec::with_capacity(capacity),
+            offset: 0,
+        }
+    }
+
+    pub fn push_usize(&mut self, value: usize) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn push_digest(&mut self, digest: &MeasurementDigest) {
+        self.data.extend_from_slice(digest);
+    }
+
+    pub fn next_usize(&mut self) -> Result<usize, Error> {
+        let bytes = self.next(size_of::<usize>())?;
+        Ok(usize::from_le_bytes(bytes.try_into()?))
+    }
+
+    pub fn next_digest(&mut self) -> Result<MeasurementDigest, Error> {
+        Ok(MeasurementDigest::clone_from_slice(
+            self.next(size_of::<MeasurementDigest>())?,
+        ))
+    }
+
+    /// Returns error if the record contains more data than what has been read.
+    pub fn finish(self) -> Result<(), Error> {
+        ensure_not!(
+            self.offset < self.data.len(),
+            Error::InvalidMigrationImage()
+        )
+    }
+
+    fn next(&mut self, size: usize) -> Result<&[u8], Error> {
+        let bytes = self
+            .data
+            .get(self.offset..self.offset + size)
+            .ok_or(Error::InvalidMigrationImage())?;
+        self.offset += size;
+        Ok(bytes)
+    }
+}
+
+/// Returns the page size whose size in bytes is given. Returns error for sizes that are not page sizes.
+pub fn page_size_from_bytes(size_in_bytes: usize) -> Result<PageSize, Error> {
+    let mut page_size = PageSize::smallest();
+    while page_size.in_bytes() != size_in_bytes {
+        page_size = page_size.larger().ok_or(Error::InvalidMigrationImage())?;
+    }
+    Ok(page_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_cipher_detects_tampering() {
+        let key = Crypto::hash(b"migration key");
+        let nonce = [7u8; NONCE_SIZE];
+        let mut writer = RecordCipher::new(key, nonce);
+        let mut reader = RecordCipher::new(key, nonce);
+
+        let plaintext: Vec<u8> = (0..100u8).collect();
+        let mut first = plaintext.clone();
+        let first_tag = writer.seal(&mut first);
+        assert_ne!(first, plaintext);
+        let mut second = plaintext.clone();
+        let second_tag = writer.seal(&mut second);
+        // The same record is encrypted differently depending on its position in the image.
+        assert_ne!(first, second);
+
+        // Records cannot be reordered or modified.
+        let mut swapped = second.clone();
+        assert!(reader.open(&mut swapped, &second_tag).is_err());
+        let mut modified = first.clone();
+        modified[42] ^= 1;
+        assert!(reader.open(&mut modified, &first_tag).is_err());
+
+        reader.open(&mut first, &first_tag).unwrap();
+        assert_eq!(first, plaintext);
+        reader.open(&mut second, &second_tag).unwrap();
+        assert_eq!(second, plaintext);
+
+        // A different key does not authenticate the record.
+        let mut other_reader = RecordCipher::new(Crypto::hash(b"other key"), nonce);
+        let mut record = plaintext.clone();
+        let tag = RecordCipher::new(key, nonce).seal(&mut record);
+        assert!(other_reader.open(&mut record, &tag).is_err());
+    }
+
+    #[test]
+    fn record_encoding() {
+        let digest = Crypto::hash(b"measurement");
+        let mut record = Record::with_capacity(64);
+        record.push_usize(0x8020_0000);
+        record.push_digest(&digest);
+        record.push_usize(3);
+
+        assert_eq!(record.next_usize().unwrap(), 0x8020_0000);
+        assert_eq!(record.next_digest().unwrap(), digest);
+        // Records with trailing data are rejected.
+        assert!(Record {
+            data: record.data.clone(),
+            offset: record.offset
+        }
+        .finish()
+        .is_err());
+        assert!(record.next_digest().is_err());
+        assert_eq!(record.next_usize().unwrap(), 3);
+        assert!(record.next_usize().is_err());
+        assert!(record.finish().is_ok());
+
+        assert!(matches!(page_size_from_bytes(4096), Ok(PageSize::Size4KiB)));
+        assert!(matches!(
+            page_size_from_bytes(2 * 1024 * 1024),
+            Ok(PageSize::Size2MiB)
+        ));
+        assert!(page_size_from_bytes(4097).is_err());
+    }
+}
@@ -79,6 +79,10 @@ impl HypervisorHart {
         self.shared_memory.set(base_address)
     }
 
+    pub fn teardown_shared_memory(&mut self) {
+        self.shared_memory.teardown()
+    }
+
     pub unsafe fn enable_hypervisor_memory_protector(&self) {
         use crate::ace::core::architecture::Hgatp;
         let hgatp = Hgatp::from(self.csrs().hgatp.read_from_main_memory());
@@ -5,22 +5,32 @@ pub use confidential_hart::ConfidentialHart;
 pub use confidential_hart_remote_command::{
     ConfidentialHartRemoteCommand, ConfidentialHartRemoteCommandExecutable,
 };
-pub use confidential_vm::ConfidentialVm;
+pub use confidential_vm::{ConfidentialVm, MigratedConfidentialVm};
+pub use confidential_vm_aia::{AiaParams, ConfidentialVmAia};
 pub use confidential_vm_id::ConfidentialVmId;
-pub use confidential_vm_measurement::{DigestType, MeasurementDigest, StaticMeasurements};
+pub use confidential_vm_lazy_pages::LazyPageManifest;
+pub use confidential_vm_measurement::{
+    DigestType, MeasurementDigest, RuntimeMeasurements, StaticMeasurements, NUMBER_OF_REGISTERS,
+};
+pub use confidential_vm_migration::MigrationBuffer;
 pub use confidential_vm_mmio_region::ConfidentialVmMmioRegion;
 pub use hardware_hart::{HardwareHart, HART_STACK_ADDRESS_OFFSET};
 pub use hypervisor_hart::HypervisorHart;
+pub use promotion_template::PromotionTemplate;
 pub use resumable_operation::ResumableOperation;
 pub use storage::ControlDataStorage;
 
 mod confidential_hart;
 mod confidential_hart_remote_command;
 mod confidential_vm;
+mod confidential_vm_aia;
 mod confidential_vm_id;
+mod confidential_vm_lazy_pages;
 mod confidential_vm_measurement;
+mod confidential_vm_migration;
 mod confidential_vm_mmio_region;
 pub mod hardware_hart;
 pub mod hypervisor_hart;
+mod promotion_template;
 mod resumable_operation;
 mod storage;
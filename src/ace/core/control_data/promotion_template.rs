@@ -0,0 +1,200 @@
+// SPDX-FileCopyrightText: 2023 IBM Corporation
+// SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
+// SPDX-License-Identifier: Apache-2.0
+use alloc::vec::Vec;
+
+use flattened_device_tree::FlattenedDeviceTree;
+use spin::Once;
+
+use crate::ace::core::memory_layout::ConfidentialVmPhysicalAddress;
+use crate::ace::core::memory_protector::ConfidentialVmMemoryProtector;
+use crate::ace::error::Error;
+use crate::{ensure, ensure_not};
+
+static TEMPLATE: Once<PromotionTemplate> = Once::new();
+
+/// The template used when the security monitor has not been configured with one, it accepts all VMs.
+static EMPTY_TEMPLATE: PromotionTemplate = PromotionTemplate {
+    entry_points: Vec::new(),
+    zeroed_regions: Vec::new(),
+    check_device_tree: false,
+};
+
+/// A range of guest physical addresses, the end is exclusive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GuestPhysicalRange {
+    start: usize,
+    end: usize,
+}
+
+impl GuestPhysicalRange {
+    /// Parses a range in the `<start>-<end>` format, where both addresses are in hexadecimal. Returns error if the range is empty.
+    pub fn parse(range: &str) -> Result<Self, Error> {
+        let parse_address = |address: &str| {
+            let address = address.trim();
+            let address = address.strip_prefix("0x").unwrap_or(address);
+            usize::from_str_radix(address, 16).map_err(|_| Error::InvalidPromotionTemplate())
+        };
+        let (start, end) = range
+            .split_once('-')
+            .ok_or(Error::InvalidPromotionTemplate())?;
+        let range = Self {
+            start: parse_address(start)?,
+            end: parse_address(end)?,
+        };
+        ensure!(range.start < range.end, Error::InvalidPromotionTemplate())?;
+        Ok(range)
+    }
+
+    pub fn contains(&self, address: usize) -> bool {
+        self.start <= address && address < self.end
+    }
+}
+
+/// A template the initial state of a VM must match to be promoted into a confidential VM. Validating the initial state before
+/// measuring it ensures that the measurements presented during attestation correspond to well-formed guests: a verifier does not have to
+/// reason about, for example, a boot hart starting in the middle of the FDT or leftover data in regions the guest expects to be empty.
+///
+/// Each part of the template is optional, the checks of an empty template always succeed.
+pub struct PromotionTemplate {
+    /// The ranges of guest physical addresses the boot hart is allowed to start at, any address if empty.
+    entry_points: Vec<GuestPhysicalRange>,
+    /// The ranges of guest physical addresses that must be zero. Guest pages that are not mapped are not part of the VM and are skipped.
+    zeroed_regions: Vec<GuestPhysicalRange>,
+    /// Whether to check that the FDT describes the memory of the VM, which contains the entry point and the FDT itself, and numbers the
+    /// harts from 0 without duplicates.
+    check_device_tree: bool,
+}
+
+impl PromotionTemplate {
+    /// Initializes the template from the configuration of the security monitor. Returns error if a range is not well formed.
+    pub fn initialize(
+        entry_points: &[&str],
+        zeroed_regions: &[&str],
+        check_device_tree: bool,
+    ) -> Result<(), Error> {
+        ensure_not!(TEMPLATE.is_completed(), Error::Reinitialization())?;
+        let parse_ranges = |ranges: &[&str]| {
+            ranges
+                .iter()
+                .filter(|range| !range.is_empty())
+                .map(|range| GuestPhysicalRange::parse(range))
+                .collect::<Result<Vec<_>, Error>>()
+        };
+        let template = Self {
+            entry_points: parse_ranges(entry_points)?,
+            zeroed_regions: parse_ranges(zeroed_regions)?,
+            check_device_tree,
+        };
+        TEMPLATE.call_once(|| template);
+        Ok(())
+    }
+
+    fn get() -> &'static Self {
+        TEMPLATE.get().unwrap_or(&EMPTY_TEMPLATE)
+    }
+
+    /// Returns error if the boot hart does not start within one of the allowed entry point ranges.
+    pub fn validate_entry_point(program_counter: usize) -> Result<(), Error> {
+        let template = Self::get();
+        ensure!(
+            template.entry_points.is_empty()
+                || template
+                    .entry_points
+                    .iter()
+                    .any(|range| range.contains(program_counter)),
+            Error::EntryPointNotAllowed(program_counter)
+        )
+    }
+
+    /// Returns error if a region required to be zero contains non-zero data. Must be called after the VM's data has been copied to the
+    /// confidential memory, so that the hypervisor cannot modify the data after it was checked.
+    pub fn validate_zeroed_regions(
+        memory_protector: &ConfidentialVmMemoryProtector,
+    ) -> Result<(), Error> {
+        const WORD_SIZE: usize = core::mem::size_of::<usize>();
+        Self::get().zeroed_regions.iter().try_for_each(|range| {
+            (range.start & !(WORD_SIZE - 1)..range.end)
+                .step_by(WORD_SIZE)
+                .try_for_each(|address| {
+                    let Ok(confidential_memory_address) = memory_protector
+                        .translate_address(&ConfidentialVmPhysicalAddress::new(address))
+                    else {
+                        return Ok(());
+                    };
+                    // Below unsafe is ok because the address is aligned to usize and points to a page owned by the not-yet-created
+                    // confidential VM.
+                    let value: usize = unsafe { confidential_memory_address.read_volatile() };
+                    ensure!(value == 0, Error::RegionNotZeroed(address))
+                })
+        })
+    }
+
+    /// Returns error if the template requires a well-formed FDT and the FDT of the VM is not. The FDT is located at `fdt_address` and
+    /// spans `fdt_size` bytes of guest physical memory.
+    pub fn validate_device_tree(
+        device_tree: &FlattenedDeviceTree,
+        fdt_address: &ConfidentialVmPhysicalAddress,
+        fdt_size: usize,
+        program_counter: usize,
+    ) -> Result<(), Error> {
+        if !Self::get().check_device_tree {
+            return Ok(());
+        }
+
+        let memory = device_tree.memory()?;
+        let memory = GuestPhysicalRange {
+            start: memory.base as usize,
+            end: memory.base.saturating_add(memory.size) as usize,
+        };
+        let fdt = GuestPhysicalRange {
+            start: fdt_address.usize(),
+            end: fdt_address.usize() + fdt_size,
+        };
+        ensure!(memory.contains(program_counter), Error::FdtNotWellFormed())?;
+        ensure!(
+            memory.contains(fdt.start) && fdt.end <= memory.end,
+            Error::FdtNotWellFormed()
+        )?;
+        ensure_not!(fdt.contains(program_counter), Error::FdtNotWellFormed())?;
+
+        // The security monitor creates harts with consecutive ids starting from 0, the FDT must describe the same harts.
+        let mut hart_ids: Vec<u64> = device_tree
+            .harts()
+            .map(|hart| hart.hart_id().ok_or(Error::FdtNotWellFormed()))
+            .collect::<Result<_, _>>()?;
+        hart_ids.sort_unstable();
+        ensure!(
+            hart_ids
+                .iter()
+                .enumerate()
+                .all(|(index, id)| index as u64 == *id),
+            Error::FdtNotWellFormed()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_guest_physical_range() {
+        let range = GuestPhysicalRange::parse("0x80000000-0x80200000").unwrap();
+        assert!(range.contains(0x80000000));
+        assert!(range.contains(0x801fffff));
+        assert!(!range.contains(0x80200000));
+        assert!(!range.contains(0x7fffffff));
+        assert_eq!(
+            GuestPhysicalRange::parse("1000 - 2000").unwrap(),
+            GuestPhysicalRange {
+                start: 0x1000,
+                end: 0x2000
+            }
+        );
+
+        assert!(GuestPhysicalRange::parse("0x2000-0x1000").is_err());
+        assert!(GuestPhysicalRange::parse("0x1000").is_err());
+        assert!(GuestPhysicalRange::parse("0x1000-end").is_err());
+    }
+}
@@ -1,9 +1,22 @@
 // SPDX-FileCopyrightText: 2023 IBM Corporation
 // SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
 // SPDX-License-Identifier: Apache-2.0
-use crate::ace::confidential_flow::handlers::mmio::{MmioLoadPending, MmioStorePending};
+use spin::Once;
+
+use crate::ace::confidential_flow::handlers::lazy_page::LazyPageFault;
+use crate::ace::confidential_flow::handlers::mmio::{
+    MmioAccessFault, MmioLoadPending, MmioStorePending,
+};
+use crate::ace::confidential_flow::handlers::sbi::SbiResponse;
 use crate::ace::confidential_flow::handlers::shared_page::SharePageRequest;
 use crate::ace::confidential_flow::handlers::symmetrical_multiprocessing::SbiHsmHartResume;
+use crate::ace::core::architecture::riscv::specification::{CAUSE_LOAD_ACCESS, CAUSE_STORE_ACCESS};
+use crate::ace::core::control_data::ConfidentialHart;
+use crate::ace::error::Error;
+use crate::ensure_not;
+
+/// Number of timer ticks the hypervisor has to respond to a resumable operation, derived from the timebase frequency at boot.
+static RESPONSE_TIMEOUT: Once<usize> = Once::new();
 
 /// Indicates an intermediate state of the confidential hart that requested certain operation from the hypervisor and is waiting for the
 /// response. Thus, the hypervisor must provide the response when resuming execution of this confidential hart.
@@ -22,4 +35,58 @@ pub enum ResumableOperation {
     MmioLoad(MmioLoadPending),
     /// The confidential hart requested to store data in a MMIO address and now is waiting for the hypervisor to emulate this operation.
     MmioStore(MmioStorePending),
+    /// The confidential hart accessed a lazily populated page and is waiting for the hypervisor to donate it.
+    PopulatePage(LazyPageFault),
+}
+
+impl ResumableOperation {
+    /// Sets the time the hypervisor has to respond to a resumable operation. A malicious hypervisor could otherwise keep the confidential
+    /// hart waiting for a response forever. The timeout is converted into timer ticks using the timebase frequency of the platform.
+    pub fn initialize_response_timeout(
+        timebase_frequency: usize,
+        timeout_ms: usize,
+    ) -> Result<(), Error> {
+        ensure_not!(RESPONSE_TIMEOUT.is_completed(), Error::Reinitialization())?;
+        let ticks = timebase_frequency.saturating_mul(timeout_ms) / 1000;
+        RESPONSE_TIMEOUT.call_once(|| ticks);
+        Ok(())
+    }
+
+    /// Returns the number of timer ticks the hypervisor has to respond to a resumable operation.
+    pub fn response_timeout() -> usize {
+        *RESPONSE_TIMEOUT
+            .get()
+            .expect("Bug: resumable operation timeout not initialized")
+    }
+
+    /// Returns true if completing this operation requires a response from the hypervisor, in which case the operation can expire.
+    pub fn expects_hypervisor_response(&self) -> bool {
+        !matches!(self, Self::ResumeHart(_))
+    }
+
+    /// Completes the operation without the hypervisor's response, reporting a failure to the confidential hart. SBI requests return an
+    /// error code while MMIO accesses raise an access fault at the faulting guest address.
+    ///
+    /// The confidential hart must not be running on any physical hart, the failure is reported in its state saved in the main memory.
+    pub fn cancel(self, confidential_hart: &mut ConfidentialHart) {
+        match self {
+            Self::SbiRequest() | Self::SharePage(_) => {
+                SbiResponse::error(Error::ResumableOperationExpired())
+                    .apply_to_confidential_hart(confidential_hart)
+            }
+            Self::MmioLoad(v) => {
+                MmioAccessFault::new(CAUSE_LOAD_ACCESS.into(), v.mtval(), v.instruction_length())
+                    .apply_to_confidential_hart_in_main_memory(confidential_hart)
+            }
+            Self::MmioStore(v) => {
+                MmioAccessFault::new(CAUSE_STORE_ACCESS.into(), v.mtval(), v.instruction_length())
+                    .apply_to_confidential_hart_in_main_memory(confidential_hart)
+            }
+            Self::PopulatePage(v) => v
+                .access_fault()
+                .apply_to_confidential_hart_in_main_memory(confidential_hart),
+            // Resuming a suspended hart does not depend on the hypervisor, there is nothing to cancel.
+            Self::ResumeHart(_) => {}
+        }
+    }
 }
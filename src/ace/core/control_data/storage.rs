@@ -89,6 +89,21 @@ impl ControlDataStorage {
         .and_then(|vm| Ok(vm.into_inner().deallocate()))
     }
 
+    /// Cancels the requests the hypervisor did not respond to before their deadline in all confidential VMs. Returns the earliest
+    /// deadline of the requests that are still pending, usize::MAX if none or if the security monitor is not initialized.
+    pub fn expire_resumable_operations(now: usize) -> usize {
+        let Some(control_data) = CONTROL_DATA_STORAGE.get() else {
+            return usize::MAX;
+        };
+        control_data
+            .read()
+            .confidential_vms
+            .values()
+            .map(|confidential_vm| confidential_vm.lock().expire_resumable_operations(now))
+            .min()
+            .unwrap_or(usize::MAX)
+    }
+
     fn try_read<F, O>(op: O) -> Result<F, Error>
     where
         O: FnOnce(&RwLockReadGuard<'_, ControlDataStorage>) -> Result<F, Error>,
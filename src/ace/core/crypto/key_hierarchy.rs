@@ -0,0 +1,199 @@
+// SPDX-FileCopyrightText: 2023 IBM Corporation
+// SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
+// SPDX-License-Identifier: Apache-2.0
+use spin::Once;
+
+use crate::ace::core::control_data::{MeasurementDigest, StaticMeasurements};
+use crate::ace::core::crypto::{Crypto, CryptoBackend};
+use crate::ace::error::Error;
+use crate::ensure_not;
+
+/// The root of the key hierarchy, derived from the device secret at boot. It never leaves the security monitor.
+static ROOT_KEY: Once<MeasurementDigest> = Once::new();
+/// The root of the migration keys, derived from the migration secret at boot. Unlike the root key, it is shared by all security monitors
+/// configured with the same migration secret, such that they can migrate confidential VMs between each other.
+static MIGRATION_ROOT_KEY: Once<MeasurementDigest> = Once::new();
+
+pub type SealingKey = MeasurementDigest;
+
+/// Derives keys bound to the device and to the identity of a confidential VM. All keys are derived from a root key, which is itself
+/// derived from the device secret, such that the device secret is used only once at boot.
+pub struct KeyHierarchy {}
+
+impl KeyHierarchy {
+    const ROOT_KEY_LABEL: &'static [u8] = b"ACE root key";
+    const SEALING_KEY_LABEL: &'static [u8] = b"ACE sealing key";
+    const ATTESTATION_KEY_LABEL: &'static [u8] = b"ACE attestation key";
+    const MIGRATION_ROOT_KEY_LABEL: &'static [u8] = b"ACE migration root key";
+    const MIGRATION_KEY_LABEL: &'static [u8] = b"ACE migration key";
+
+    /// Initializes the key hierarchy. Without a device secret, the security monitor cannot derive keys and all requests for keys fail.
+    /// Without a migration secret, confidential VMs cannot be migrated.
+    pub fn initialize(
+        device_secret: Option<&[u8]>,
+        migration_secret: Option<&[u8]>,
+    ) -> Result<(), Error> {
+        ensure_not!(ROOT_KEY.is_completed(), Error::Reinitialization())?;
+        ensure_not!(MIGRATION_ROOT_KEY.is_completed(), Error::Reinitialization())?;
+        if let Some(device_secret) = device_secret {
+            ROOT_KEY.call_once(|| Self::derive_root_key(device_secret));
+        }
+        if let Some(migration_secret) = migration_secret {
+            MIGRATION_ROOT_KEY.call_once(|| Self::derive_migration_root_key(migration_secret));
+        }
+        Ok(())
+    }
+
+    /// Returns the sealing key of a confidential VM. The key depends on the device, on the boot-time measurements of the confidential
+    /// VM, and on the key identifier chosen by the confidential VM. Thus, a different device or a modified confidential VM never obtains
+    /// the same key.
+    pub fn sealing_key(
+        measurements: &StaticMeasurements,
+        key_id: usize,
+    ) -> Result<SealingKey, Error> {
+        let root_key = ROOT_KEY.get().ok_or(Error::KeyHierarchyNotAvailable())?;
+        Ok(Self::derive_sealing_key(
+            root_key,
+            &measurements.digest(),
+            key_id,
+        ))
+    }
+
+    /// Returns the attestation key of the device, which authenticates the evidence produced by the security monitor. Unlike the sealing
+    /// key, it does not depend on the confidential VM.
+    pub fn attestation_key() -> Result<MeasurementDigest, Error> {
+        let root_key = ROOT_KEY.get().ok_or(Error::KeyHierarchyNotAvailable())?;
+        Ok(Self::derive_attestation_key(root_key))
+    }
+
+    /// Returns the key protecting the migration image of a confidential VM, see `MigrationWriter`. The key depends on the migration secret
+    /// and on the boot-time measurements of the confidential VM, thus the image can only be imported by a security monitor sharing the
+    /// migration secret and only as the same confidential VM.
+    pub fn migration_key(identity: &MeasurementDigest) -> Result<MeasurementDigest, Error> {
+        let migration_root_key = MIGRATION_ROOT_KEY
+            .get()
+            .ok_or(Error::MigrationKeyNotAvailable())?;
+        Ok(Self::derive_migration_key(migration_root_key, identity))
+    }
+}
+
+// The derivations of the key hierarchy, independent of the keys stored at boot.
+impl KeyHierarchy {
+    fn derive_root_key(device_secret: &[u8]) -> MeasurementDigest {
+        Crypto::mac(device_secret, Self::ROOT_KEY_LABEL)
+    }
+
+    fn derive_sealing_key(
+        root_key: &MeasurementDigest,
+        identity: &MeasurementDigest,
+        key_id: usize,
+    ) -> SealingKey {
+        let mut label = [0u8; Self::SEALING_KEY_LABEL.len() + core::mem::size_of::<usize>()];
+        label[..Self::SEALING_KEY_LABEL.len()].copy_from_slice(Self::SEALING_KEY_LABEL);
+        label[Self::SEALING_KEY_LABEL.len()..].copy_from_slice(&key_id.to_le_bytes());
+        let identity_key = Crypto::mac(root_key, identity);
+        Crypto::mac(&identity_key, &label)
+    }
+
+    fn derive_attestation_key(root_key: &MeasurementDigest) -> MeasurementDigest {
+        Crypto::mac(root_key, Self::ATTESTATION_KEY_LABEL)
+    }
+
+    fn derive_migration_root_key(migration_secret: &[u8]) -> MeasurementDigest {
+        Crypto::mac(migration_secret, Self::MIGRATION_ROOT_KEY_LABEL)
+    }
+
+    fn derive_migration_key(
+        migration_root_key: &MeasurementDigest,
+        identity: &MeasurementDigest,
+    ) -> MeasurementDigest {
+        let identity_key = Crypto::mac(migration_root_key, identity);
+        Crypto::mac(&identity_key, Self::MIGRATION_KEY_LABEL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(measurement: &[u8]) -> MeasurementDigest {
+        Crypto::hash(measurement)
+    }
+
+    #[test]
+    fn derivation_is_deterministic() {
+        let root_key = KeyHierarchy::derive_root_key(b"device secret");
+        assert_eq!(root_key, KeyHierarchy::derive_root_key(b"device secret"));
+        assert_eq!(
+            KeyHierarchy::derive_sealing_key(&root_key, &identity(b"vm"), 1),
+            KeyHierarchy::derive_sealing_key(&root_key, &identity(b"vm"), 1)
+        );
+        assert_eq!(
+            KeyHierarchy::derive_attestation_key(&root_key),
+            KeyHierarchy::derive_attestation_key(&root_key)
+        );
+    }
+
+    #[test]
+    fn sealing_keys_are_bound_to_the_device_and_the_vm() {
+        let root_key = KeyHierarchy::derive_root_key(b"device secret");
+        let other_root_key = KeyHierarchy::derive_root_key(b"other device secret");
+        let key = KeyHierarchy::derive_sealing_key(&root_key, &identity(b"vm"), 1);
+
+        assert_ne!(root_key, other_root_key);
+        assert_ne!(
+            key,
+            KeyHierarchy::derive_sealing_key(&other_root_key, &identity(b"vm"), 1)
+        );
+        assert_ne!(
+            key,
+            KeyHierarchy::derive_sealing_key(&root_key, &identity(b"modified vm"), 1)
+        );
+        assert_ne!(
+            key,
+            KeyHierarchy::derive_sealing_key(&root_key, &identity(b"vm"), 2)
+        );
+    }
+
+    #[test]
+    fn keys_are_domain_separated() {
+        let root_key = KeyHierarchy::derive_root_key(b"secret");
+        let migration_root_key = KeyHierarchy::derive_migration_root_key(b"secret");
+        let attestation_key = KeyHierarchy::derive_attestation_key(&root_key);
+
+        // The same secret used as device and migration secret does not yield related keys.
+        assert_ne!(root_key, migration_root_key);
+        assert_ne!(
+            KeyHierarchy::derive_sealing_key(&root_key, &identity(b"vm"), 0),
+            KeyHierarchy::derive_migration_key(&migration_root_key, &identity(b"vm"))
+        );
+        assert_ne!(attestation_key, root_key);
+        assert_ne!(
+            attestation_key,
+            KeyHierarchy::derive_sealing_key(&root_key, &identity(b"vm"), 0)
+        );
+    }
+
+    #[test]
+    fn migration_keys_are_bound_to_the_secret_and_the_vm() {
+        let migration_root_key = KeyHierarchy::derive_migration_root_key(b"migration secret");
+        let key = KeyHierarchy::derive_migration_key(&migration_root_key, &identity(b"vm"));
+
+        // Security monitors sharing the migration secret derive the same key.
+        let same_root_key = KeyHierarchy::derive_migration_root_key(b"migration secret");
+        assert_eq!(
+            key,
+            KeyHierarchy::derive_migration_key(&same_root_key, &identity(b"vm"))
+        );
+
+        let other_root_key = KeyHierarchy::derive_migration_root_key(b"other secret");
+        assert_ne!(
+            key,
+            KeyHierarchy::derive_migration_key(&other_root_key, &identity(b"vm"))
+        );
+        assert_ne!(
+            key,
+            KeyHierarchy::derive_migration_key(&migration_root_key, &identity(b"other vm"))
+        );
+    }
+}
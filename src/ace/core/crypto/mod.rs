@@ -0,0 +1,60 @@
+// SPDX-FileCopyrightText: 2023 IBM Corporation
+// SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
+// SPDX-License-Identifier: Apache-2.0
+pub use key_hierarchy::{KeyHierarchy, SealingKey};
+pub use software::SoftwareCrypto;
+
+use crate::ace::core::control_data::MeasurementDigest;
+use crate::ace::error::Error;
+
+mod key_hierarchy;
+mod software;
+
+/// The crypto backend used by the security monitor. Platforms with a hardware accelerator or a vendor crypto engine can select their own
+/// backend here, all platforms currently use the software implementation.
+pub type Crypto = SoftwareCrypto;
+
+pub type SigningKey = [u8; 32];
+pub type VerifyingKey = [u8; 32];
+pub type Signature = [u8; 64];
+
+/// Overwrites a copy of a secret, such that it does not remain in memory after its use. The writes are volatile, so that the compiler
+/// does not elide them even though the secret is never read again.
+pub fn zeroize(secret: &mut [u8]) {
+    secret
+        .iter_mut()
+        .for_each(|byte| unsafe { core::ptr::write_volatile(byte, 0) });
+    core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+}
+
+/// Cryptographic operations used by the security monitor for measurements, attestation, and key derivation. All digests have the size
+/// of the `MeasurementDigest`, so that measurements do not depend on the backend.
+pub trait CryptoBackend {
+    type Hasher: Hasher;
+
+    /// Returns a hasher whose state is chained to the given digest. This is used to extend measurements.
+    fn hasher_with_prefix(prefix: &MeasurementDigest) -> Self::Hasher;
+
+    /// Returns the digest of the given data.
+    fn hash(data: &[u8]) -> MeasurementDigest;
+
+    /// Returns the keyed message authentication code (HMAC) of the message.
+    fn mac(key: &[u8], message: &[u8]) -> MeasurementDigest;
+
+    /// Signs the message with the Ed25519 secret key.
+    fn sign(secret_key: &SigningKey, message: &[u8]) -> Result<Signature, Error>;
+
+    /// Verifies the Ed25519 signature of the message, returns error if the signature is invalid.
+    fn verify(
+        public_key: &VerifyingKey,
+        message: &[u8],
+        signature: &Signature,
+    ) -> Result<(), Error>;
+}
+
+/// Incremental hash computation.
+pub trait Hasher {
+    fn update(&mut self, data: &[u8]);
+
+    fn finalize_into(self, digest: &mut MeasurementDigest);
+}
@@ -0,0 +1,150 @@
+// SPDX-FileCopyrightText: 2023 IBM Corporation
+// SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
+// SPDX-License-Identifier: Apache-2.0
+use ed25519_dalek::Signer;
+use sha2::Digest;
+
+use crate::ace::core::control_data::{DigestType, MeasurementDigest};
+use crate::ace::core::crypto::{CryptoBackend, Hasher, Signature, SigningKey, VerifyingKey};
+use crate::ace::error::Error;
+
+/// Software implementation of the crypto backend, based on the SHA-2 family of hash functions and on Ed25519 signatures.
+pub struct SoftwareCrypto {}
+
+impl SoftwareCrypto {
+    /// Block size of SHA-384 in bytes, defined in RFC 2104 for the HMAC construction.
+    const HMAC_BLOCK_SIZE: usize = 128;
+    const HMAC_INNER_PAD: u8 = 0x36;
+    const HMAC_OUTER_PAD: u8 = 0x5c;
+}
+
+impl CryptoBackend for SoftwareCrypto {
+    type Hasher = DigestType;
+
+    fn hasher_with_prefix(prefix: &MeasurementDigest) -> Self::Hasher {
+        DigestType::new_with_prefix(prefix)
+    }
+
+    fn hash(data: &[u8]) -> MeasurementDigest {
+        DigestType::digest(data)
+    }
+
+    fn mac(key: &[u8], message: &[u8]) -> MeasurementDigest {
+        // Keys longer than the block size are hashed first, shorter keys are padded with zeros.
+        let mut key_block = [0u8; Self::HMAC_BLOCK_SIZE];
+        if key.len() > Self::HMAC_BLOCK_SIZE {
+            let digest = DigestType::digest(key);
+            key_block[..digest.len()].copy_from_slice(&digest);
+        } else {
+            key_block[..key.len()].copy_from_slice(key);
+        }
+
+        let mut inner_pad = [Self::HMAC_INNER_PAD; Self::HMAC_BLOCK_SIZE];
+        let mut outer_pad = [Self::HMAC_OUTER_PAD; Self::HMAC_BLOCK_SIZE];
+        for ((inner, outer), key) in inner_pad
+            .iter_mut()
+            .zip(outer_pad.iter_mut())
+            .zip(key_block)
+        {
+            *inner ^= key;
+            *outer ^= key;
+        }
+
+        let inner = DigestType::new()
+            .chain_update(inner_pad)
+            .chain_update(message)
+            .finalize();
+        DigestType::new()
+            .chain_update(outer_pad)
+            .chain_update(inner)
+            .finalize()
+    }
+
+    fn sign(secret_key: &SigningKey, message: &[u8]) -> Result<Signature, Error> {
+        let signing_key = ed25519_dalek::SigningKey::from_bytes(secret_key);
+        Ok(signing_key.sign(message).to_bytes())
+    }
+
+    fn verify(
+        public_key: &VerifyingKey,
+        message: &[u8],
+        signature: &Signature,
+    ) -> Result<(), Error> {
+        let verifying_key = ed25519_dalek::VerifyingKey::from_bytes(public_key)
+            .map_err(|_| Error::InvalidSignature())?;
+        verifying_key
+            .verify_strict(message, &ed25519_dalek::Signature::from_bytes(signature))
+            .map_err(|_| Error::InvalidSignature())
+    }
+}
+
+impl Hasher for DigestType {
+    fn update(&mut self, data: &[u8]) {
+        Digest::update(self, data)
+    }
+
+    fn finalize_into(self, digest: &mut MeasurementDigest) {
+        Digest::finalize_into(self, digest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hmac_test_vector() {
+        // RFC 4231, test case 1.
+        let mac = SoftwareCrypto::mac(&[0x0b; 20], b"Hi There");
+        let expected = [
+            0xaf, 0xd0, 0x39, 0x44, 0xd8, 0x48, 0x95, 0x62, 0x6b, 0x08, 0x25, 0xf4, 0xab, 0x46,
+            0x90, 0x7f, 0x15, 0xf9, 0xda, 0xdb, 0xe4, 0x10, 0x1e, 0xc6, 0x82, 0xaa, 0x03, 0x4c,
+            0x7c, 0xeb, 0xc5, 0x9c, 0xfa, 0xea, 0x9e, 0xa9, 0x07, 0x6e, 0xde, 0x7f, 0x4a, 0xf1,
+            0x52, 0xe8, 0xb2, 0xfa, 0x9c, 0xb6,
+        ];
+        assert_eq!(mac.as_slice(), &expected);
+    }
+
+    #[test]
+    fn hash_test_vector() {
+        // FIPS 180-4 example, SHA-384 of "abc".
+        let expected = [
+            0xcb, 0x00, 0x75, 0x3f, 0x45, 0xa3, 0x5e, 0x8b, 0xb5, 0xa0, 0x3d, 0x69, 0x9a, 0xc6,
+            0x50, 0x07, 0x27, 0x2c, 0x32, 0xab, 0x0e, 0xde, 0xd1, 0x63, 0x1a, 0x8b, 0x60, 0x5a,
+            0x43, 0xff, 0x5b, 0xed, 0x80, 0x86, 0x07, 0x2b, 0xa1, 0xe7, 0xcc, 0x23, 0x58, 0xba,
+            0xec, 0xa1, 0x34, 0xc8, 0x25, 0xa7,
+        ];
+        assert_eq!(SoftwareCrypto::hash(b"abc").as_slice(), &expected);
+    }
+
+    #[test]
+    fn ed25519_test_vector() {
+        // RFC 8032, section 7.1, test 1.
+        let secret_key = [
+            0x9d, 0x61, 0xb1, 0x9d, 0xef, 0xfd, 0x5a, 0x60, 0xba, 0x84, 0x4a, 0xf4, 0x92, 0xec,
+            0x2c, 0xc4, 0x44, 0x49, 0xc5, 0x69, 0x7b, 0x32, 0x69, 0x19, 0x70, 0x3b, 0xac, 0x03,
+            0x1c, 0xae, 0x7f, 0x60,
+        ];
+        let public_key = [
+            0xd7, 0x5a, 0x98, 0x01, 0x82, 0xb1, 0x0a, 0xb7, 0xd5, 0x4b, 0xfe, 0xd3, 0xc9, 0x64,
+            0x07, 0x3a, 0x0e, 0xe1, 0x72, 0xf3, 0xda, 0xa6, 0x23, 0x25, 0xaf, 0x02, 0x1a, 0x68,
+            0xf7, 0x07, 0x51, 0x1a,
+        ];
+        let expected = [
+            0xe5, 0x56, 0x43, 0x00, 0xc3, 0x60, 0xac, 0x72, 0x90, 0x86, 0xe2, 0xcc, 0x80, 0x6e,
+            0x82, 0x8a, 0x84, 0x87, 0x7f, 0x1e, 0xb8, 0xe5, 0xd9, 0x74, 0xd8, 0x73, 0xe0, 0x65,
+            0x22, 0x49, 0x01, 0x55, 0x5f, 0xb8, 0x82, 0x15, 0x90, 0xa3, 0x3b, 0xac, 0xc6, 0x1e,
+            0x39, 0x70, 0x1c, 0xf9, 0xb4, 0x6b, 0xd2, 0x5b, 0xf5, 0xf0, 0x59, 0x5b, 0xbe, 0x24,
+            0x65, 0x51, 0x41, 0x43, 0x8e, 0x7a, 0x10, 0x0b,
+        ];
+
+        let signature = SoftwareCrypto::sign(&secret_key, b"").unwrap();
+        assert_eq!(signature, expected);
+        assert!(SoftwareCrypto::verify(&public_key, b"", &signature).is_ok());
+
+        let mut tampered = signature;
+        tampered[0] ^= 1;
+        assert!(SoftwareCrypto::verify(&public_key, b"", &tampered).is_err());
+        assert!(SoftwareCrypto::verify(&public_key, b"message", &signature).is_err());
+    }
+}
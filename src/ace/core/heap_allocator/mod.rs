@@ -6,8 +6,8 @@ use allocator::HeapAllocator;
 use crate::ace::core::memory_layout::ConfidentialMemoryAddress;
 mod allocator;
 
-/// global allocator allocates memory on the security monitor's heap.
-#[global_allocator]
+/// global allocator allocates memory on the security monitor's heap. Unit tests run with the allocator of the host.
+#[cfg_attr(not(test), global_allocator)]
 static mut HEAP_ALLOCATOR: HeapAllocator = HeapAllocator::empty();
 
 pub(super) fn init_heap(start_address: ConfidentialMemoryAddress, heap_size: usize) {
@@ -11,10 +11,15 @@ use spin::{Mutex, Once};
 use crate::ace::core::architecture::riscv::fence::fence_wo;
 use crate::ace::core::architecture::riscv::specification::*;
 use crate::ace::core::architecture::{HardwareExtension, PageSize};
-use crate::ace::core::control_data::{ControlDataStorage, HardwareHart};
+use crate::ace::core::control_data::{
+    ControlDataStorage, HardwareHart, PromotionTemplate, ResumableOperation,
+};
+use crate::ace::core::crypto::KeyHierarchy;
 use crate::ace::core::hardware_setup::HardwareSetup;
 use crate::ace::core::interrupt_controller::InterruptController;
-use crate::ace::core::memory_layout::{ConfidentialMemoryAddress, MemoryLayout};
+use crate::ace::core::memory_layout::{
+    ConfidentialMemoryRegions, MemoryLayout, MAX_CONFIDENTIAL_MEMORY_REGIONS,
+};
 use crate::ace::core::memory_protector::HypervisorMemoryProtector;
 use crate::ace::core::page_allocator::{Page, PageAllocator, UnAllocated};
 use crate::ace::error::Error;
@@ -61,10 +66,10 @@ pub fn init_security_monitor(flattened_device_tree_address: *const u8) -> Result
     let fdt = unsafe { FlattenedDeviceTree::from_raw_pointer(flattened_device_tree_address)? };
 
     // TODO: make sure the system has enough physical memory
-    let (confidential_memory_start, confidential_memory_end) = initialize_memory_layout(&fdt)?;
+    let confidential_memory_regions = initialize_memory_layout(&fdt)?;
 
     // Creates page tokens, heap, page allocator
-    initalize_security_monitor_state(confidential_memory_start, confidential_memory_end)?;
+    initalize_security_monitor_state(confidential_memory_regions)?;
     // From now on, we can use heap.
 
     // Make sure harts implement all required extension
@@ -73,7 +78,25 @@ pub fn init_security_monitor(flattened_device_tree_address: *const u8) -> Result
     // Prepares memory required to store physical harts states during context switches
     prepare_harts(number_of_harts)?;
 
+    // The time the hypervisor has to respond to requests of confidential harts, in timer ticks
+    ResumableOperation::initialize_response_timeout(
+        fdt.timebase_frequency()? as usize,
+        crate::config::ACE_RESPONSE_TIMEOUT_MS,
+    )?;
+
+    // Derives the roots of the key hierarchy. From now on, the device and migration secrets are not used anymore.
     // TODO: lock access to attestation keys/seed/credentials.
+    KeyHierarchy::initialize(
+        crate::config::ACE_DEVICE_SECRET.map(str::as_bytes),
+        crate::config::ACE_MIGRATION_SECRET.map(str::as_bytes),
+    )?;
+
+    // The template that VMs must match to be promoted into confidential VMs
+    PromotionTemplate::initialize(
+        crate::config::ACE_PROMOTION_ENTRY_POINTS,
+        crate::config::ACE_PROMOTION_ZEROED_REGIONS,
+        crate::config::ACE_PROMOTION_CHECK_FDT,
+    )?;
 
     // If we reached this line, then the security monitor control data has been correctly initialized, attestation keys have been created,
     // access to attestation seed has been restricted.
@@ -117,12 +140,37 @@ fn verify_harts(fdt: &FlattenedDeviceTree) -> Result<usize, Error> {
 /// Reads the layout of physical memory from the flattened device tree (FDT), splitting it (logically) into confidential and
 /// non-confidential memory region. The FDT content is trusted.
 ///
+/// When the confidential memory regions are set in the build configuration (see `ACE_CONFIDENTIAL_MEMORY`), the non-confidential
+/// memory spans from the start of the memory described by the FDT up to the first confidential memory region.
+///
 /// # Guarantees
 ///
-/// The end of the confidential memory is not lower than the start of the confidential memory
-fn initialize_memory_layout(
-    fdt: &FlattenedDeviceTree,
-) -> Result<(ConfidentialMemoryAddress, *const usize), Error> {
+/// The end of every confidential memory region is not lower than its start
+fn initialize_memory_layout(fdt: &FlattenedDeviceTree) -> Result<ConfidentialMemoryRegions, Error> {
+    let (configured_regions, number_of_configured_regions) =
+        parse_confidential_memory_regions(crate::config::ACE_CONFIDENTIAL_MEMORY)?;
+    if number_of_configured_regions > 0 {
+        let memory_start = fdt.memory()?.base as *mut usize;
+        let regions =
+            configured_regions.map(|(start, end)| (start as *mut usize, end as *const usize));
+        let regions = &regions[..number_of_configured_regions];
+        // The regions are sorted, so the non-confidential memory ends at the start of the first one
+        let non_confidential_memory_end = regions[0].0 as *const usize;
+        ensure!(
+            (memory_start as *const usize) < non_confidential_memory_end,
+            Error::InvalidMemoryBoundary()
+        )?;
+        log::info!(
+            "Non-confidential memory 0x{:#?}-0x{:#?}",
+            memory_start,
+            non_confidential_memory_end
+        );
+        regions
+            .iter()
+            .for_each(|(start, end)| log::info!("Confidential memory 0x{:#?}-0x{:#?}", start, end));
+        return unsafe { MemoryLayout::init(memory_start, non_confidential_memory_end, regions) };
+    }
+
     // TODO: FDT may contain multiple regions. For now, we assume there is only one region in the FDT.
     // This assumption is fine for the emulated environment (QEMU).
 
@@ -170,34 +218,68 @@ fn initialize_memory_layout(
         MemoryLayout::init(
             non_confidential_memory_start,
             non_confidential_memory_end,
-            confidential_memory_start,
-            confidential_memory_end,
+            &[(confidential_memory_start, confidential_memory_end)],
         )
     }
 }
 
+/// Parses the confidential memory regions from the build configuration, each written "<start>-<end>" in hexadecimal. Returns the
+/// regions and their number, which is 0 if no region is configured.
+fn parse_confidential_memory_regions(
+    ranges: &[&str],
+) -> Result<([(usize, usize); MAX_CONFIDENTIAL_MEMORY_REGIONS], usize), Error> {
+    let parse_address = |address: &str| {
+        let address = address.trim();
+        let address = address.strip_prefix("0x").unwrap_or(address);
+        usize::from_str_radix(address, 16).map_err(|_| Error::InvalidMemoryBoundary())
+    };
+    let mut regions = [(0, 0); MAX_CONFIDENTIAL_MEMORY_REGIONS];
+    let mut number_of_regions = 0;
+    for range in ranges.iter().filter(|range| !range.is_empty()) {
+        let region = regions
+            .get_mut(number_of_regions)
+            .ok_or(Error::TooManyConfidentialMemoryRegions())?;
+        let (start, end) = range.split_once('-').ok_or(Error::InvalidMemoryBoundary())?;
+        *region = (parse_address(start)?, parse_address(end)?);
+        ensure!(region.0 < region.1, Error::InvalidMemoryBoundary())?;
+        number_of_regions += 1;
+    }
+    Ok((regions, number_of_regions))
+}
+
 /// This function is called only once during the initialization of the security
 /// monitor during the boot process. This function initializes secure monitor's
 /// memory management like allocators.
 fn initalize_security_monitor_state(
-    confidential_memory_start: ConfidentialMemoryAddress,
-    confidential_memory_end: *const usize,
+    confidential_memory_regions: ConfidentialMemoryRegions,
 ) -> Result<(), Error> {
     // Safety: initialization order is crucial for safety because at some point we
     // start allocating objects on heap, e.g., page tokens. We have to first
     // initialize the global allocator, which permits us to use heap. To initialize heap
     // we need to decide what is the confidential memory address range and split this memory
     // into regions owned by heap allocator and page allocator.
+    let total_confidential_memory_size: isize = confidential_memory_regions
+        .iter()
+        .flatten()
+        .map(|(start, end)| start.offset_from(*end))
+        .sum();
+    let mut confidential_memory_regions = confidential_memory_regions.into_iter().flatten();
+    let (confidential_memory_start, confidential_memory_end) =
+        confidential_memory_regions.next().ok_or(Error::NotEnoughMemory())?;
     let confidential_memory_size = confidential_memory_start.offset_from(confidential_memory_end);
     assert!(confidential_memory_size > 0);
 
     let number_of_pages = confidential_memory_size as usize / PageSize::smallest().in_bytes();
+    let total_number_of_pages =
+        total_confidential_memory_size as usize / PageSize::smallest().in_bytes();
     // Calculate if we have enough memory in the system to store page tokens. In the worst case we
     // have one page token for every possible page in the confidential memory.
     let size_of_a_page_token_in_bytes = size_of::<Page<UnAllocated>>();
-    let bytes_required_to_store_page_tokens = number_of_pages * size_of_a_page_token_in_bytes;
+    let bytes_required_to_store_page_tokens =
+        total_number_of_pages * size_of_a_page_token_in_bytes;
     let heap_pages = NUMBER_OF_HEAP_PAGES
         + (bytes_required_to_store_page_tokens / PageSize::smallest().in_bytes());
+    // The heap is located in the first confidential memory region
     ensure!(number_of_pages > heap_pages, Error::NotEnoughMemory())?;
     // Set up the global allocator so we can start using alloc::*.
     let heap_size_in_bytes = heap_pages * PageSize::smallest().in_bytes();
@@ -211,9 +293,14 @@ fn initalize_security_monitor_state(
     assert!(page_allocator_start_address.is_aligned_to(PageSize::smallest().in_bytes()));
     // PageAllocator takes ownership of the rest of the confidential memory.
     let page_allocator_end_address = confidential_memory_end;
-    // It is safe to construct the PageAllocator because we own the corresponding memory region and pass this
+    // It is safe to construct the PageAllocator because we own the corresponding memory regions and pass this
     // ownership to the PageAllocator.
-    unsafe { PageAllocator::initialize(page_allocator_start_address, page_allocator_end_address)? };
+    unsafe {
+        PageAllocator::initialize(
+            core::iter::once((page_allocator_start_address, page_allocator_end_address))
+                .chain(confidential_memory_regions),
+        )?
+    };
 
     InterruptController::initialize()?;
     ControlDataStorage::initialize()?;
@@ -303,3 +390,30 @@ pub(crate) fn ace_setup_this_hart(mctx: &mut MiralisContext) {
         .mtvec
         .write((trap_vector_address >> MTVEC_BASE_SHIFT) << MTVEC_BASE_SHIFT);*/
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_confidential_memory_ranges() {
+        let (regions, number_of_regions) =
+            parse_confidential_memory_regions(&["0xc0000000-0x100000000", "180000000 - 200000000"])
+                .unwrap();
+        assert_eq!(number_of_regions, 2);
+        assert_eq!(
+            regions[..2],
+            [(0xc0000000, 0x100000000), (0x180000000, 0x200000000)]
+        );
+        assert_eq!(parse_confidential_memory_regions(&[""]).unwrap().1, 0);
+
+        assert!(parse_confidential_memory_regions(&["0x2000-0x1000"]).is_err());
+        assert!(parse_confidential_memory_regions(&["0x1000"]).is_err());
+        assert!(parse_confidential_memory_regions(&[
+            "0x1000-0x2000",
+            "0x3000-0x4000",
+            "0x5000-0x6000"
+        ])
+        .is_err());
+    }
+}
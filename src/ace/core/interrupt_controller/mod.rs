@@ -21,10 +21,8 @@ static INTERRUPT_CONTROLLER: Once<RwLock<InterruptController>> = Once::new();
 }*/
 
 fn sbi_ipi_send_smode(_hmask: usize, _hbase: usize) -> usize {
-    let mut clint = Plat::get_clint().lock();
-
     // TODO: Implement error handling here
-    clint.write_msip(_hbase, _hmask as u32).unwrap();
+    Plat::get_msip().write(_hbase, _hmask as u32).unwrap();
 
     0
 }
@@ -15,6 +15,14 @@ mod confidential_memory_address;
 mod confidential_vm_physical_address;
 mod non_confidential_memory_address;
 
+/// The maximum number of disjoint regions of confidential memory, e.g., one per DRAM bank. Each region is protected by two PMP entries.
+pub const MAX_CONFIDENTIAL_MEMORY_REGIONS: usize = 2;
+
+/// The confidential memory regions returned by `MemoryLayout::init`, in increasing order of addresses. Each region is given by its first
+/// page-aligned address and its end address.
+pub type ConfidentialMemoryRegions =
+    [Option<(ConfidentialMemoryAddress, *const usize)>; MAX_CONFIDENTIAL_MEMORY_REGIONS];
+
 /// MEMORY_LAYOUT is a static variable (private to this module) that is set during the system boot and never changes
 /// later -- this is guaranteed by Once<>. It stores an instance of the `MemoryLayout`. The only way to get a shared
 /// access to this instance is by calling `MemoryLayout::read()` function.
@@ -25,11 +33,34 @@ static MEMORY_LAYOUT: Once<MemoryLayout> = Once::new();
 ///
 /// Model: A Coq `memory_layout` record containing the memory ranges for confidential and
 /// non-confidential memory.
+///
+/// The confidential memory can consist of several disjoint regions, for example on boards with multiple DRAM banks or with holes in the
+/// memory map. Offsetting a confidential address never crosses the boundary of the region containing it.
 pub struct MemoryLayout {
     non_confidential_memory_start: *mut usize,
     non_confidential_memory_end: *const usize,
-    confidential_memory_start: *mut usize,
-    confidential_memory_end: *const usize,
+    // Invariant: the first `number_of_confidential_memory_regions` regions are sorted, disjoint, and located after the non-confidential
+    // memory.
+    confidential_memory_regions: [ConfidentialMemoryRegion; MAX_CONFIDENTIAL_MEMORY_REGIONS],
+    number_of_confidential_memory_regions: usize,
+}
+
+/// A region of confidential memory, the start and end are aligned to the smallest page size.
+#[derive(Clone, Copy)]
+struct ConfidentialMemoryRegion {
+    start: *mut usize,
+    end: *const usize,
+}
+
+impl ConfidentialMemoryRegion {
+    const EMPTY: Self = Self {
+        start: core::ptr::null_mut(),
+        end: core::ptr::null(),
+    };
+
+    fn contains(&self, address: usize) -> bool {
+        self.start as usize <= address && address < self.end as usize
+    }
 }
 
 /// Send+Sync are not automatically declared on the `MemoryLayout` type because it stores internally raw pointers that
@@ -44,9 +75,9 @@ impl MemoryLayout {
     const NOT_INITIALIZED_MEMORY_LAYOUT: &'static str =
         "Bug. Could not access MemoryLayout because is has not been initialized";
 
-    /// Constructs the `MemoryLayout` where the confidential memory is within the memory range defined by
-    /// `confidential_memory_start` and `confidential_memory_end`. Returns the `MemoryLayout` and the first alligned
-    /// address in the confidential memory.
+    /// Constructs the `MemoryLayout` where the confidential memory consists of the given memory regions, each defined by its start and
+    /// end addresses. Regions must be sorted, disjoint, and located after the non-confidential memory. Returns the confidential memory
+    /// regions, whose start and end are aligned to the smallest page size.
     ///
     /// # Safety
     ///
@@ -54,15 +85,51 @@ impl MemoryLayout {
     pub unsafe fn init(
         non_confidential_memory_start: *mut usize,
         non_confidential_memory_end: *const usize,
+        confidential_memory_regions: &[(*mut usize, *const usize)],
+    ) -> Result<ConfidentialMemoryRegions, Error> {
+        assert!((non_confidential_memory_start as *const usize) < non_confidential_memory_end);
+        ensure!(
+            !confidential_memory_regions.is_empty(),
+            Error::NotEnoughMemory()
+        )?;
+        ensure!(
+            confidential_memory_regions.len() <= MAX_CONFIDENTIAL_MEMORY_REGIONS,
+            Error::TooManyConfidentialMemoryRegions()
+        )?;
+
+        let mut regions = [ConfidentialMemoryRegion::EMPTY; MAX_CONFIDENTIAL_MEMORY_REGIONS];
+        let mut aligned_regions: ConfidentialMemoryRegions = core::array::from_fn(|_| None);
+        let mut previous_region_end = non_confidential_memory_end;
+        for (index, &(start, end)) in confidential_memory_regions.iter().enumerate() {
+            ensure!(
+                previous_region_end <= (start as *const usize),
+                Error::InvalidMemoryBoundary()
+            )?;
+            let region = Self::align_confidential_memory_region(start, end)?;
+            regions[index] = region;
+            aligned_regions[index] =
+                Some((ConfidentialMemoryAddress::new(region.start), region.end));
+            previous_region_end = end;
+        }
+
+        MEMORY_LAYOUT.call_once(|| MemoryLayout {
+            non_confidential_memory_start,
+            non_confidential_memory_end,
+            confidential_memory_regions: regions,
+            number_of_confidential_memory_regions: confidential_memory_regions.len(),
+        });
+
+        Ok(aligned_regions)
+    }
+
+    /// Aligns the start of the confidential memory region to the smallest possible page size (4KiB on RISC-V) and makes sure that its
+    /// size is the multiply of this page size.
+    fn align_confidential_memory_region(
         confidential_memory_start: *mut usize,
         mut confidential_memory_end: *const usize,
-    ) -> Result<(ConfidentialMemoryAddress, *const usize), Error> {
-        assert!((non_confidential_memory_start as *const usize) < non_confidential_memory_end);
-        assert!(non_confidential_memory_end <= (confidential_memory_start as *const usize));
+    ) -> Result<ConfidentialMemoryRegion, Error> {
         assert!((confidential_memory_start as *const usize) < confidential_memory_end);
 
-        // We align the start of the confidential memory to the smallest possible page size (4KiB on RISC-V) and make
-        // sure that its size is the multiply of this page size.
         let smalles_page_size_in_bytes = PageSize::smallest().in_bytes();
         let confidential_memory_start = ptr_align(
             confidential_memory_start,
@@ -75,6 +142,7 @@ impl MemoryLayout {
         let memory_size = ptr_byte_offset(confidential_memory_end, confidential_memory_start);
         let memory_size = usize::try_from(memory_size).map_err(|_| Error::NotEnoughMemory())?;
         let number_of_pages = memory_size / smalles_page_size_in_bytes;
+        ensure!(number_of_pages > 0, Error::NotEnoughMemory())?;
         let memory_size_in_bytes = number_of_pages * smalles_page_size_in_bytes;
         if memory_size > memory_size_in_bytes {
             // We must modify the end_address because the current one is not a multiply of the smallest page size
@@ -85,47 +153,51 @@ impl MemoryLayout {
             )?;
         }
 
-        MEMORY_LAYOUT.call_once(|| MemoryLayout {
-            non_confidential_memory_start,
-            non_confidential_memory_end,
-            confidential_memory_start,
-            confidential_memory_end,
-        });
-
-        Ok((
-            ConfidentialMemoryAddress::new(confidential_memory_start),
-            confidential_memory_end,
-        ))
+        Ok(ConfidentialMemoryRegion {
+            start: confidential_memory_start,
+            end: confidential_memory_end,
+        })
     }
 
     /// Offsets an address in the confidential memory by a given number of bytes. Returns an error if the resulting
-    /// address is not in the confidential memory region.
+    /// address is not in the confidential memory region containing the address.
     pub fn confidential_address_at_offset(
         &self,
         address: &ConfidentialMemoryAddress,
         offset_in_bytes: usize,
     ) -> Result<ConfidentialMemoryAddress, Error> {
-        Ok(
-            unsafe { address.add(offset_in_bytes, self.confidential_memory_end) }
-                .map_err(|_| Error::AddressNotInConfidentialMemory())?,
-        )
+        let region = self.confidential_memory_region(address)?;
+        Ok(unsafe { address.add(offset_in_bytes, region.end) }
+            .map_err(|_| Error::AddressNotInConfidentialMemory())?)
     }
 
     /// Offsets an address in the confidential memory by a given number of bytes. Returns an error if the resulting
-    /// address is outside the confidential memory region or exceeds the given upper bound.
+    /// address is outside the confidential memory region containing the address or exceeds the given upper bound.
     pub fn confidential_address_at_offset_bounded(
         &self,
         address: &ConfidentialMemoryAddress,
         offset_in_bytes: usize,
         upper_bound: *const usize,
     ) -> Result<ConfidentialMemoryAddress, Error> {
+        let region = self.confidential_memory_region(address)?;
         ensure!(
-            upper_bound <= self.confidential_memory_end,
+            upper_bound <= region.end,
             Error::AddressNotInConfidentialMemory()
         )?;
         Ok(self.confidential_address_at_offset(address, offset_in_bytes)?)
     }
 
+    /// Returns the confidential memory region containing the given address.
+    fn confidential_memory_region(
+        &self,
+        address: &ConfidentialMemoryAddress,
+    ) -> Result<&ConfidentialMemoryRegion, Error> {
+        self.confidential_memory_regions[..self.number_of_confidential_memory_regions]
+            .iter()
+            .find(|region| region.contains(address.as_usize()))
+            .ok_or(Error::AddressNotInConfidentialMemory())
+    }
+
     /// Offsets an address in the non-confidential memory by given number of bytes. Returns an error if the resulting
     /// address is outside the non-confidential memory region.
     pub fn non_confidential_address_at_offset(
@@ -155,19 +227,19 @@ impl MemoryLayout {
     // TODO: Add this in the panic handler of Miralis
     #[allow(dead_code)]
     pub unsafe fn clear_confidential_memory(&self) {
-        // We can safely cast the below offset to usize because the constructor guarantees that the confidential memory
-        // range is valid, and so the memory size must be a valid usize
-        let memory_size =
-            ptr_byte_offset(self.confidential_memory_end, self.confidential_memory_start) as usize;
-        let usize_alligned_offsets = (0..memory_size).step_by(core::mem::size_of::<usize>());
-        usize_alligned_offsets.for_each(|offset_in_bytes| {
-            let _ = ptr_byte_add_mut(
-                self.confidential_memory_start,
-                offset_in_bytes,
-                self.confidential_memory_end,
-            )
-            .and_then(|ptr| Ok(ptr.write_volatile(0)));
-        });
+        self.confidential_memory_regions[..self.number_of_confidential_memory_regions]
+            .iter()
+            .for_each(|region| {
+                // We can safely cast the below offset to usize because the constructor guarantees that the confidential memory
+                // range is valid, and so the memory size must be a valid usize
+                let memory_size = ptr_byte_offset(region.end, region.start) as usize;
+                let usize_alligned_offsets =
+                    (0..memory_size).step_by(core::mem::size_of::<usize>());
+                usize_alligned_offsets.for_each(|offset_in_bytes| {
+                    let _ = ptr_byte_add_mut(region.start, offset_in_bytes, region.end)
+                        .and_then(|ptr| Ok(ptr.write_volatile(0)));
+                });
+            });
     }
 
     /// Get a pointer to the globally initialized `MemoryLayout`.
@@ -178,11 +250,19 @@ impl MemoryLayout {
             .expect(Self::NOT_INITIALIZED_MEMORY_LAYOUT)
     }
 
-    /// Get the boundaries of confidential memory as a (start, end) tuple.
+    /// Get the boundaries of confidential memory as a (start, end) tuple. When the confidential memory consists of several regions, the
+    /// boundaries span all of them, including the holes between the regions.
     pub fn confidential_memory_boundary(&self) -> (usize, usize) {
-        (
-            self.confidential_memory_start as usize,
-            self.confidential_memory_end as usize,
-        )
+        let regions = self.confidential_memory_regions();
+        let start = regions.clone().map(|(start, _)| start).min().unwrap_or(0);
+        let end = regions.map(|(_, end)| end).max().unwrap_or(0);
+        (start, end)
+    }
+
+    /// Returns the (start, end) tuples of the confidential memory regions, in increasing order of addresses.
+    pub fn confidential_memory_regions(&self) -> impl Iterator<Item = (usize, usize)> + Clone + '_ {
+        self.confidential_memory_regions[..self.number_of_confidential_memory_regions]
+            .iter()
+            .map(|region| (region.start as usize, region.end as usize))
     }
 }
@@ -1,14 +1,18 @@
 // SPDX-FileCopyrightText: 2023 IBM Corporation
 // SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
 // SPDX-License-Identifier: Apache-2.0
-use crate::ace::core::architecture::mmu::{Hgatp, PageTable};
+use alloc::collections::BTreeSet;
+
+use crate::ace::core::architecture::mmu::{Hgatp, PageTable, PagingSystem};
 use crate::ace::core::architecture::riscv::{mmu, pmp, tlb};
 use crate::ace::core::architecture::{PageSize, SharedPage};
 use crate::ace::core::control_data::{ConfidentialVmId, MeasurementDigest};
 use crate::ace::core::memory_layout::{
     ConfidentialMemoryAddress, ConfidentialVmPhysicalAddress, NonConfidentialMemoryAddress,
 };
+use crate::ace::core::page_allocator::{Allocated, Page, PageAllocator};
 use crate::ace::error::Error;
+use crate::ensure;
 
 /// Exposes an interface to configure the hardware memory isolation component in a way that
 /// the confidential VM can access only memory it owns.
@@ -17,9 +21,14 @@ pub struct ConfidentialVmMemoryProtector {
     root_page_table: PageTable,
     // Stores the value of the hypervisor G-stage address translation protocol register.
     hgatp: Hgatp,
+    // Stores the guest physical addresses of all pages currently shared with the hypervisor.
+    shared_pages: BTreeSet<usize>,
 }
 
 impl ConfidentialVmMemoryProtector {
+    /// A maximum number of pages that a confidential VM can share with the hypervisor at the same time.
+    const MAX_NUMBER_OF_SHARED_PAGES: usize = 4096;
+
     /// Constructs the memory protector of a confidential VM from the dumped state of a hart that was running a
     /// non-confidential VM at the time it requested to be converted in a confidential VM. This function copies the
     /// entire configuration of the underlying hardware memory isolation component into the confidential memory.
@@ -32,6 +41,19 @@ impl ConfidentialVmMemoryProtector {
         Ok(Self {
             root_page_table,
             hgatp: Hgatp::disabled(),
+            shared_pages: BTreeSet::new(),
+        })
+    }
+
+    /// Constructs the memory protector of a confidential VM with an empty address space, into which the pages of a migrated confidential
+    /// VM are then mapped. Returns error if there is not enough confidential memory to allocate the root page table.
+    pub fn empty() -> Result<Self, Error> {
+        let paging_system = PagingSystem::Sv57x4;
+        let root_page_table = PageTable::empty(paging_system, paging_system.levels())?;
+        Ok(Self {
+            root_page_table,
+            hgatp: Hgatp::disabled(),
+            shared_pages: BTreeSet::new(),
         })
     }
 
@@ -57,12 +79,40 @@ impl ConfidentialVmMemoryProtector {
         hypervisor_address: NonConfidentialMemoryAddress,
         confidential_vm_physical_address: ConfidentialVmPhysicalAddress,
     ) -> Result<PageSize, Error> {
+        let address = confidential_vm_physical_address.usize();
+        ensure!(
+            self.shared_pages.len() < Self::MAX_NUMBER_OF_SHARED_PAGES
+                || self.shared_pages.contains(&address),
+            Error::ReachedMaxNumberOfSharedPages()
+        )?;
         let shared_page = SharedPage::new(hypervisor_address, confidential_vm_physical_address)?;
         let shared_page_size = shared_page.page_size();
         self.root_page_table.map_shared_page(shared_page)?;
+        self.shared_pages.insert(address);
         Ok(shared_page_size)
     }
 
+    /// Modifies the configuration of the underlying hardware memory isolation component (e.g., MMU) in a way that a
+    /// page of confidential memory is mapped into the address space of the confidential VM. Returns error if the guest physical address
+    /// is already mapped, in which case the page is released to the page allocator.
+    ///
+    /// To guarantee confidential VM's correctness, the caller must ensure that he will perform `TLB shutdown` on all confidential harts, so
+    /// that all confidential harts observe the new mapping.
+    pub fn map_confidential_page(
+        &mut self,
+        confidential_vm_physical_address: &ConfidentialVmPhysicalAddress,
+        page: Page<Allocated>,
+    ) -> Result<PageSize, Error> {
+        let page_size = *page.size();
+        self.root_page_table
+            .map_confidential_page(confidential_vm_physical_address, page)
+            .map_err(|(page, error)| {
+                PageAllocator::release_pages(alloc::vec![page.deallocate()]);
+                error
+            })?;
+        Ok(page_size)
+    }
+
     /// Modifies the configuration of the underlying hardware memory isolation component (e.g., MMU) in a way that a
     /// shared page is unmapped from the address space of the confidential VM.
     ///
@@ -72,8 +122,28 @@ impl ConfidentialVmMemoryProtector {
         &mut self,
         confidential_vm_physical_address: &ConfidentialVmPhysicalAddress,
     ) -> Result<PageSize, Error> {
-        self.root_page_table
-            .unmap_shared_page(confidential_vm_physical_address)
+        let page_size = self
+            .root_page_table
+            .unmap_shared_page(confidential_vm_physical_address)?;
+        self.shared_pages
+            .remove(&confidential_vm_physical_address.usize());
+        Ok(page_size)
+    }
+
+    /// Unmaps all pages shared with the hypervisor from the address space of the confidential VM. Returns the number of revoked shared
+    /// pages.
+    ///
+    /// The same requirements as for `unmap_shared_page` apply, the caller must ensure that all confidential harts do not use the revoked
+    /// shared pages anymore.
+    pub fn revoke_shared_pages(&mut self) -> usize {
+        let shared_pages = core::mem::take(&mut self.shared_pages);
+        shared_pages.iter().for_each(|address| {
+            // Below unmapping cannot fail because we track all shared pages mapped in the page table.
+            let _ = self
+                .root_page_table
+                .unmap_shared_page(&ConfidentialVmPhysicalAddress::new(*address));
+        });
+        shared_pages.len()
     }
 
     /// Translates guest physical address into a real physical address in the confidential memory. Returns error if the guest physical
@@ -85,6 +155,78 @@ impl ConfidentialVmMemoryProtector {
         self.root_page_table.translate(address)
     }
 
+    /// Copies the confidential VM's memory at the given guest physical address into the buffer. The memory might span multiple guest
+    /// physical pages, so we translate the address of every word separately. Translation only succeeds for pages owned by the
+    /// confidential VM, thus memory shared with the hypervisor is never read. Returns error if the address is not aligned to usize or the
+    /// buffer size is not a multiple of usize.
+    pub fn read_from_confidential_vm(
+        &self,
+        address: &ConfidentialVmPhysicalAddress,
+        buffer: &mut [u8],
+    ) -> Result<(), Error> {
+        Self::ensure_word_aligned(address, buffer.len())?;
+        buffer
+            .chunks_mut(core::mem::size_of::<usize>())
+            .enumerate()
+            .try_for_each(|(i, chunk)| {
+                let confidential_memory_address =
+                    self.translate_address(&address.add(i * core::mem::size_of::<usize>()))?;
+                // Below unsafe is ok because the address is aligned to usize and points to a page owned by the confidential VM.
+                let value = unsafe { confidential_memory_address.read_volatile() };
+                chunk.copy_from_slice(&value.to_le_bytes());
+                Ok(())
+            })
+    }
+
+    /// Copies the data to the confidential VM's memory at the given guest physical address. The same requirements as for
+    /// `read_from_confidential_vm` apply, so the data is never written to memory shared with the hypervisor.
+    pub fn write_to_confidential_vm(
+        &self,
+        address: &ConfidentialVmPhysicalAddress,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        Self::ensure_word_aligned(address, data.len())?;
+        data.chunks(core::mem::size_of::<usize>())
+            .enumerate()
+            .try_for_each(|(i, chunk)| {
+                let confidential_memory_address =
+                    self.translate_address(&address.add(i * core::mem::size_of::<usize>()))?;
+                let value = usize::from_le_bytes(chunk.try_into()?);
+                // Below unsafe is ok because the address is aligned to usize and points to a page owned by the confidential VM.
+                unsafe { confidential_memory_address.write_volatile(value) };
+                Ok(())
+            })
+    }
+
+    fn ensure_word_aligned(
+        address: &ConfidentialVmPhysicalAddress,
+        size: usize,
+    ) -> Result<(), Error> {
+        ensure!(
+            address.usize() % core::mem::size_of::<usize>() == 0,
+            Error::AddressNotAligned()
+        )?;
+        ensure!(
+            size % core::mem::size_of::<usize>() == 0,
+            Error::InvalidParameter()
+        )
+    }
+
+    /// Calls the function with the guest physical address and the page of all pages of confidential memory mapped into the address space
+    /// of the confidential VM, in the order of their guest physical addresses. Returns error if pages are shared with the hypervisor.
+    pub fn for_each_confidential_page<F>(&self, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(usize, &Page<Allocated>) -> Result<(), Error>,
+    {
+        self.root_page_table.for_each_confidential_page(0, &mut f)
+    }
+
+    /// Maps the memory of the confidential VM with the largest pages possible, see `PageTable::coalesce_pages`. Must be called before the
+    /// confidential VM executes. The measurements depend on the size of the mapped pages, so the confidential VM must be measured before.
+    pub fn coalesce_pages(&mut self) {
+        self.root_page_table.coalesce_pages();
+    }
+
     pub fn measure(&self) -> Result<MeasurementDigest, Error> {
         let mut initial_digest = MeasurementDigest::default();
         self.root_page_table.measure(&mut initial_digest, 0)?;
@@ -2,7 +2,9 @@
 // SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
 // SPDX-License-Identifier: Apache-2.0
 pub mod architecture;
+pub mod attestation;
 pub mod control_data;
+pub mod crypto;
 pub mod memory_layout;
 pub mod memory_protector;
 pub mod page_allocator;
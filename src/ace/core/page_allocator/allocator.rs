@@ -10,6 +10,7 @@ use super::page::{Page, UnAllocated};
 use crate::ace::core::architecture::PageSize;
 use crate::ace::core::memory_layout::{ConfidentialMemoryAddress, MemoryLayout};
 use crate::ace::error::Error;
+use crate::benchmark::{Benchmark, Counter};
 use crate::{debug, ensure, ensure_not};
 
 /// A static global structure containing unallocated pages. Once<> guarantees that the PageAllocator can only be initialized once.
@@ -21,34 +22,40 @@ static PAGE_ALLOCATOR: Once<RwLock<PageAllocator>> = Once::new();
 /// Specification:
 /// We give a "ghost name" γ to the allocator, which is used to link up page tokens allocated
 /// with this allocator.
-
+///
+/// Released pages are not zeroed synchronously, which would make the teardown of confidential VMs slow. They are instead queued as
+/// dirty pages and scrubbed (zeroed and stored in the tree) lazily, when an allocation cannot be served from the clean pages, or in
+/// the background when the hart is idle.
 pub struct PageAllocator {
     base_address: usize,
     page_size: PageSize,
     root: PageStorageTreeNode,
+    // Invariant: the tree only stores scrubbed page tokens, page tokens released since they were scrubbed are stored here.
+    dirty_pages: Vec<Page<UnAllocated>>,
 }
 
 impl PageAllocator {
     const NOT_INITIALIZED: &'static str = "Bug. Page allocator not initialized.";
 
-    /// Initializes the global memory allocator with the given memory region as confidential memory. Must be called only once during the
-    /// system initialization.
+    /// Initializes the global memory allocator with the given memory regions as confidential memory. Must be called only once during
+    /// the system initialization.
     ///
     /// # Arguments
     ///
-    /// Both `memory_start` and `memory_end` must be aligned to 4KiB page boundaries.
+    /// The start and end of every memory region must be aligned to 4KiB page boundaries.
     ///
     /// # Safety
     ///
-    /// Caller must pass the ownership of the memory region [memory_start, memory_end).
+    /// Caller must pass the ownership of the memory regions [memory_start, memory_end).
 
     pub unsafe fn initialize(
-        memory_start: ConfidentialMemoryAddress,
-        memory_end: *const usize,
+        memory_regions: impl IntoIterator<Item = (ConfidentialMemoryAddress, *const usize)>,
     ) -> Result<(), Error> {
         ensure_not!(PAGE_ALLOCATOR.is_completed(), Error::Reinitialization())?;
         let mut page_allocator = Self::empty();
-        page_allocator.add_memory_region(memory_start, memory_end)?;
+        for (memory_start, memory_end) in memory_regions {
+            page_allocator.add_memory_region(memory_start, memory_end)?;
+        }
         // NOTE: We initialize the invariant here.
         PAGE_ALLOCATOR.call_once(|| RwLock::new(page_allocator));
         Ok(())
@@ -62,6 +69,7 @@ impl PageAllocator {
             root: PageStorageTreeNode::empty(),
             base_address: 0,
             page_size: PageSize::largest(),
+            dirty_pages: vec![],
         }
     }
 
@@ -170,31 +178,35 @@ impl PageAllocator {
     /// Returns a page token that has ownership over an unallocated memory region of the requested size. Returns error if it could not
     /// obtain write access to the global instance of the page allocator or if there are not enough page tokens satisfying the requested
     /// criteria.
+    ///
+    /// Dirty pages are scrubbed one by one as long as the request cannot be satisfied, because they might be merged into a page token of
+    /// the requested size.
     /// Specification:
     pub fn acquire_page(page_size_to_allocate: PageSize) -> Result<Page<UnAllocated>, Error> {
-        Self::try_write(|page_allocator| {
+        Self::try_write(|page_allocator| loop {
             let base_address = page_allocator.base_address;
             let page_size = page_allocator.page_size;
-            Ok(page_allocator.root.acquire_page_token(
+            match page_allocator.root.acquire_page_token(
                 base_address,
                 page_size,
                 page_size_to_allocate,
-            ))
-        })?
+            ) {
+                Ok(page_token) => return Ok(page_token),
+                Err(error) => ensure!(
+                    page_allocator.scrub_dirty_page(page_size_to_allocate),
+                    error
+                )?,
+            }
+        })
     }
 
     /// Consumes the page tokens given by the caller, allowing for their further acquisition. This is equivalent to deallocation of the
     /// physical memory region owned by the returned page tokens. Given vector of pages might contains pages of arbitrary sizes.
+    ///
+    /// The released pages are marked dirty, they will be zeroed before being acquired again.
     pub fn release_pages(released_pages: Vec<Page<UnAllocated>>) {
         let _ = Self::try_write(|page_allocator| {
-            let base_address = page_allocator.base_address;
-            let page_size = page_allocator.page_size;
-            released_pages.into_iter().for_each(|page_token| {
-                // NOTE: we show that the token is within range of the allocator.
-                page_allocator
-                    .root
-                    .store_page_token(base_address, page_size, page_token);
-            });
+            page_allocator.dirty_pages.extend(released_pages);
             Ok(())
         })
         .inspect_err(|_| {
@@ -202,6 +214,38 @@ impl PageAllocator {
         });
     }
 
+    /// Scrubs at most `max_pages` dirty pages of the smallest size, returns the number of scrubbed pages. This is the background scrubber,
+    /// called when the hart is idle. The number of pages bounds the time during which the hart is busy, and thus the latency of the
+    /// interrupt that wakes it up.
+    pub fn scrub_dirty_pages(max_pages: usize) -> usize {
+        Self::try_write(|page_allocator| {
+            Ok((0..max_pages)
+                .take_while(|_| page_allocator.scrub_dirty_page(PageSize::smallest()))
+                .count())
+        })
+        .unwrap_or(0)
+    }
+
+    /// Zeroes a dirty page and stores it in the tree, where it can be merged with its neighbours and acquired again. Dirty pages larger
+    /// than `max_page_size` are first divided, so that a single invocation never zeroes more memory than a page of `max_page_size`.
+    /// Returns false if there are no dirty pages.
+    fn scrub_dirty_page(&mut self, max_page_size: PageSize) -> bool {
+        let Some(mut page_token) = self.dirty_pages.pop() else {
+            return false;
+        };
+        while *page_token.size() > max_page_size {
+            // Below unwrap is ok because a page larger than another page size always divides into at least one smaller page.
+            let mut smaller_pages = page_token.divide();
+            page_token = smaller_pages.pop().unwrap();
+            self.dirty_pages.append(&mut smaller_pages);
+        }
+        // NOTE: we show that the token is within range of the allocator.
+        self.root
+            .store_page_token(self.base_address, self.page_size, page_token.scrub());
+        Benchmark::increment_counter(Counter::ScrubbedPages);
+        true
+    }
+
     /// returns a mutable reference to the PageAllocator after obtaining a lock on the mutex
     fn try_write<F, O>(op: O) -> Result<F, Error>
     where
@@ -7,7 +7,8 @@ use core::mem;
 use core::ops::Range;
 
 use crate::ace::core::architecture::PageSize;
-use crate::ace::core::control_data::{DigestType, MeasurementDigest};
+use crate::ace::core::control_data::MeasurementDigest;
+use crate::ace::core::crypto::{Crypto, CryptoBackend, Hasher};
 use crate::ace::core::memory_layout::{
     ConfidentialMemoryAddress, MemoryLayout, NonConfidentialMemoryAddress,
 };
@@ -46,6 +47,13 @@ impl Page<UnAllocated> {
         }
     }
 
+    /// Clears the entire memory content by writing 0s to it. The page allocator scrubs released pages this way before they can be
+    /// acquired again.
+    pub(super) fn scrub(mut self) -> Self {
+        self.clear();
+        self
+    }
+
     /// Specification:
     pub fn zeroize(mut self) -> Page<Allocated> {
         self.clear();
@@ -78,6 +86,48 @@ impl Page<UnAllocated> {
         })
     }
 
+    /// Moves a page to the Allocated state after filling its content with the content of the given pages of confidential memory, copied
+    /// one after the other. The given pages must cover exactly the size of this page.
+    pub fn copy_from_confidential_pages(mut self, pages: &[&Page<Allocated>]) -> Page<Allocated> {
+        assert!(
+            pages.iter().map(|page| page.size.in_bytes()).sum::<usize>() == self.size.in_bytes()
+        );
+        let mut offset_in_bytes = 0;
+        pages.iter().for_each(|page| {
+            // Below unwraps are ok because we iterate over the offsets of the page, which are all within this page.
+            page.offsets().for_each(|offset| {
+                self.write(offset_in_bytes + offset, page.read(offset).unwrap())
+                    .unwrap()
+            });
+            offset_in_bytes += page.size.in_bytes();
+        });
+        Page {
+            address: self.address,
+            size: self.size,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Moves a page to the Allocated state after filling its content with the content of the given page of confidential memory, starting
+    /// at the given offset. The given page must be large enough to fill this page entirely.
+    pub fn copy_from_confidential_page(
+        mut self,
+        page: &Page<Allocated>,
+        offset_in_bytes: usize,
+    ) -> Page<Allocated> {
+        assert!(offset_in_bytes + self.size.in_bytes() <= page.size.in_bytes());
+        // Below unwraps are ok because we iterate over the offsets of this page, which are all within the given page.
+        self.offsets().for_each(|offset| {
+            self.write(offset, page.read(offset_in_bytes + offset).unwrap())
+                .unwrap()
+        });
+        Page {
+            address: self.address,
+            size: self.size,
+            _marker: PhantomData,
+        }
+    }
+
     /// Returns a collection of all smaller pages that fit within the current page and
     /// are correctly aligned. If this page is the smallest page (4KiB for RISC-V), then
     /// the same page is returned.
@@ -128,10 +178,9 @@ impl Page<UnAllocated> {
 }
 
 impl Page<Allocated> {
-    /// Clears the entire memory content by writing 0s to it and then converts the Page from Allocated to UnAllocated so it can be returned
-    /// to the page allocator.
-    pub fn deallocate(mut self) -> Page<UnAllocated> {
-        self.clear();
+    /// Converts the Page from Allocated to UnAllocated so it can be returned to the page allocator. The memory content is not cleared
+    /// here, the page allocator zeroes released pages lazily, either before they are acquired again or when the hart is idle.
+    pub fn deallocate(self) -> Page<UnAllocated> {
         Page {
             address: self.address,
             size: self.size,
@@ -221,9 +270,8 @@ impl<T: PageState> Page<T> {
 
     /// Extends the digest with the guest physical address and the content of the page.
     pub fn measure(&self, digest: &mut MeasurementDigest, guest_physical_address: usize) {
-        use sha2::Digest;
-        let mut hasher = DigestType::new_with_prefix(digest.clone());
-        hasher.update(guest_physical_address.to_le_bytes());
+        let mut hasher = Crypto::hasher_with_prefix(digest);
+        hasher.update(&guest_physical_address.to_le_bytes());
         // below unsafe is ok because the page has been initialized and it owns the entire memory region.
         // We are creating a slice of bytes, so the number of elements in the slice is the same as the size of the page.
         let slice: &[u8] =
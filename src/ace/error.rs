@@ -14,6 +14,8 @@ pub enum Error {
     TooMuchMemory(),
     #[error("Security monitor initialization error: Invalid memory boundaries")]
     InvalidMemoryBoundary(),
+    #[error("Security monitor initialization error: Too many confidential memory regions")]
+    TooManyConfidentialMemoryRegions(),
     #[error("BUG: Cannot reinitialize static components")]
     Reinitialization(),
     #[error("Not supported hardware: ACE requires 64-bit processor")]
@@ -44,6 +46,32 @@ pub enum Error {
     AuthBlobNotAlignedTo64Bits(),
     #[error("Authentication blob size is invalid.")]
     AuthBlobInvalidSize(),
+    #[error("Invalid promotion template in the security monitor configuration")]
+    InvalidPromotionTemplate(),
+    #[error("The entry point {0:x} is not allowed by the promotion template")]
+    EntryPointNotAllowed(usize),
+    #[error("The region required to be zeroed by the promotion template contains data at {0:x}")]
+    RegionNotZeroed(usize),
+    #[error("The FDT does not match the promotion template")]
+    FdtNotWellFormed(),
+    #[error("The manifest of lazily populated pages is malformed")]
+    InvalidLazyPageManifest(),
+    #[error("The page {0:x} is not a pending lazily populated page")]
+    LazyPageNotPending(usize),
+    #[error("The content of the lazily populated page {0:x} does not match the manifest")]
+    LazyPageMeasurementMismatch(usize),
+
+    /* Migration-related errors */
+    #[error("The state of the confidential VM cannot be migrated")]
+    MigrationNotSupported(),
+    #[error("The buffer is too small to store the migration image")]
+    MigrationBufferTooSmall(),
+    #[error("The migration image is malformed")]
+    InvalidMigrationImage(),
+    #[error("The migration image was not produced for this confidential VM or has been modified")]
+    MigrationImageNotAuthentic(),
+    #[error("The migration key is not available, no migration secret was provided")]
+    MigrationKeyNotAvailable(),
 
     /* SBI invalid address */
     #[error("Address is not aligned")]
@@ -73,6 +101,16 @@ pub enum Error {
     #[error("Mmio region overlaps with a region already defined in the past")]
     OverlappingMmioRegion(),
 
+    /* COVI extension-related errors */
+    #[error("The AIA of the confidential VM is not initialized")]
+    AiaNotInitialized(),
+    #[error("The confidential hart has no virtual IMSIC")]
+    ImsicNotConfigured(),
+    #[error("The confidential hart is not bound to an interrupt file of this hart")]
+    ImsicNotBound(),
+    #[error("The confidential hart does not allow the injection of external interrupts")]
+    ExternalInterruptNotAllowed(),
+
     /* SBI HSM extension-related errors */
     #[error("Cannot start a confidential hart because it is not in the Stopped state.")]
     CannotStartNotStoppedHart(),
@@ -86,6 +124,8 @@ pub enum Error {
     /* MMIO-related errors */
     #[error("Could not decode compressed RISC-V instruction: {0:x}")]
     InvalidCompressedRiscvInstruction(usize),
+    #[error("The hardware did not provide the faulting instruction in mtinst: {0:x}")]
+    MissingTransformedInstruction(usize),
 
     /* Internal errors exposed to the outside as a failure */
     #[error("The operation failed for unknown reasons")]
@@ -98,12 +138,22 @@ pub enum Error {
     ReachedMaxNumberOfRemoteCommands(),
     #[error("Reached max number of registered MMIO regions")]
     ReachedMaxNumberOfMmioRegions(),
+    #[error("Reached max number of pages shared with the hypervisor")]
+    ReachedMaxNumberOfSharedPages(),
+    #[error("The hypervisor did not respond to the request in time")]
+    ResumableOperationExpired(),
     #[error("Could not send an IPI, error code: {0}")]
     InterruptSendingError(usize),
     #[error("Slice to array conversion error")]
     HashingError(#[from] core::array::TryFromSliceError),
     #[error("Invalid id of a general purpouse register")]
     InvalidGprId(),
+    #[error("The crypto backend does not support this operation")]
+    CryptoOperationNotSupported(),
+    #[error("Invalid signature")]
+    InvalidSignature(),
+    #[error("The key hierarchy is not available, no device secret was provided")]
+    KeyHierarchyNotAvailable(),
 }
 
 impl Error {
@@ -128,12 +178,29 @@ impl Error {
             Self::AuthBlobNotAlignedTo64Bits() => SBI_ERR_INVALID_PARAM as usize,
             Self::AuthBlobInvalidSize() => SBI_ERR_INVALID_PARAM as usize,
             Self::DeviceTreeError(_) => SBI_ERR_INVALID_PARAM as usize,
+            Self::FdtNotWellFormed() => SBI_ERR_INVALID_PARAM as usize,
+            Self::InvalidLazyPageManifest() => SBI_ERR_INVALID_PARAM as usize,
+            Self::LazyPageNotPending(_) => SBI_ERR_INVALID_PARAM as usize,
+            Self::ImsicNotConfigured() => SBI_ERR_INVALID_PARAM as usize,
+            Self::MigrationBufferTooSmall() => SBI_ERR_INVALID_PARAM as usize,
+            Self::InvalidMigrationImage() => SBI_ERR_INVALID_PARAM as usize,
 
             Self::CannotStartNotStoppedHart() => SBI_ERR_ALREADY_AVAILABLE as usize,
             Self::CannotStopNotStartedHart() => SBI_ERR_ALREADY_AVAILABLE as usize,
             Self::CannotSuspedNotStartedHart() => SBI_ERR_ALREADY_AVAILABLE as usize,
             Self::CannotStartNotSuspendedHart() => SBI_ERR_ALREADY_AVAILABLE as usize,
 
+            Self::AiaNotInitialized() => SBI_ERR_NOT_SUPPORTED as usize,
+            Self::MigrationNotSupported() => SBI_ERR_NOT_SUPPORTED as usize,
+            Self::MigrationKeyNotAvailable() => SBI_ERR_NOT_SUPPORTED as usize,
+            Self::ImsicNotBound() => SBI_ERR_DENIED as usize,
+            Self::EntryPointNotAllowed(_) => SBI_ERR_DENIED as usize,
+            Self::RegionNotZeroed(_) => SBI_ERR_DENIED as usize,
+            Self::LazyPageMeasurementMismatch(_) => SBI_ERR_DENIED as usize,
+            Self::ExternalInterruptNotAllowed() => SBI_ERR_DENIED as usize,
+            Self::MigrationImageNotAuthentic() => SBI_ERR_DENIED as usize,
+            Self::InvalidSignature() => SBI_ERR_DENIED as usize,
+
             _ => SBI_ERR_FAILED as usize,
         }
     }
@@ -0,0 +1,614 @@
+//! Host-runnable fuzzer for the untrusted-input boundary of the security monitor.
+//!
+//! Every value that the hypervisor or a confidential VM places in a0-a7, in the CSRs describing the trap, or in the NACL shared memory is
+//! untrusted. This harness initializes the security monitor over memory of the host and drives the non-confidential and the confidential
+//! flow dispatchers with randomized states. It checks that the dispatchers never panic, that they only leave through an exit node of the
+//! finite state machine (FSM), and that calls returning to their caller report either success or an SBI error code. The CSRs and the context
+//! switches are emulated, see `architecture::riscv::userspace`. Run it with
+//! `cargo test --no-default-features --features ace,userspace -p miralis ace::fuzz`. Set `ACE_FUZZ_SEED` to replay a failing run, the seed
+//! is part of every failure message.
+use std::alloc::{alloc_zeroed, Layout};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::ace::confidential_flow::handlers::mmio::MmioLoadPending;
+use crate::ace::confidential_flow::ConfidentialFlow;
+use crate::ace::core::architecture::mmu::{Hgatp, HgatpMode};
+use crate::ace::core::architecture::riscv::sbi::*;
+use crate::ace::core::architecture::riscv::userspace::{write_csr, HostExit};
+use crate::ace::core::architecture::specification::*;
+use crate::ace::core::architecture::{
+    decode_load_width, decode_result_register, GeneralPurposeRegister, HardwareExtension, PageSize,
+    TrapCause,
+};
+use crate::ace::core::control_data::{
+    ConfidentialHart, ConfidentialVm, ConfidentialVmId, ControlDataStorage, HardwareHart,
+    LazyPageManifest, MeasurementDigest, ResumableOperation, StaticMeasurements,
+};
+use crate::ace::core::crypto::KeyHierarchy;
+use crate::ace::core::hardware_setup::HardwareSetup;
+use crate::ace::core::interrupt_controller::InterruptController;
+use crate::ace::core::memory_layout::{MemoryLayout, NonConfidentialMemoryAddress};
+use crate::ace::core::memory_protector::{
+    ConfidentialVmMemoryProtector, HypervisorMemoryProtector,
+};
+use crate::ace::core::page_allocator::PageAllocator;
+use crate::ace::non_confidential_flow::NonConfidentialFlow;
+use crate::platform::{Plat, Platform};
+
+const ITERATIONS: usize = 100_000;
+/// Every iteration traverses the finite state machine, including heavy context switches, so the dispatchers run fewer iterations.
+const DISPATCH_ITERATIONS: usize = 10_000;
+
+const NON_CONFIDENTIAL_MEMORY_SIZE: usize = 4 * 1024 * 1024;
+const CONFIDENTIAL_MEMORY_SIZE: usize = 32 * 1024 * 1024;
+const CLINT_SIZE: usize = 0x10000;
+
+/// Extension ids the security monitor dispatches on, the fuzzer picks them more often than random values.
+const EXTENSION_IDS: [usize; 9] = [
+    BaseExtension::EXTID,
+    IpiExtension::EXTID,
+    RfenceExtension::EXTID,
+    HsmExtension::EXTID,
+    SrstExtension::EXTID,
+    NaclExtension::EXTID,
+    CovhExtension::EXTID,
+    CoviExtension::EXTID,
+    CovgExtension::EXTID,
+];
+
+const TRAP_CAUSES: [u8; 6] = [
+    CAUSE_SUPERVISOR_ECALL,
+    CAUSE_VIRTUAL_SUPERVISOR_ECALL,
+    CAUSE_LOAD_GUEST_PAGE_FAULT,
+    CAUSE_STORE_GUEST_PAGE_FAULT,
+    CAUSE_VIRTUAL_INSTRUCTION,
+    CAUSE_ILLEGAL_INSTRUCTION,
+];
+
+/// Causes with which the hypervisor traps into the security monitor, the other causes are delegated to the hypervisor.
+const HYPERVISOR_TRAP_CAUSES: [u8; 6] = [
+    CAUSE_SUPERVISOR_ECALL,
+    CAUSE_ILLEGAL_INSTRUCTION,
+    CAUSE_MISALIGNED_LOAD,
+    CAUSE_LOAD_ACCESS,
+    CAUSE_MISALIGNED_STORE,
+    CAUSE_STORE_ACCESS,
+];
+
+/// Causes with which a confidential hart traps into the security monitor.
+const CONFIDENTIAL_HART_TRAP_CAUSES: [u8; 5] = [
+    CAUSE_VIRTUAL_SUPERVISOR_ECALL,
+    CAUSE_FETCH_GUEST_PAGE_FAULT,
+    CAUSE_LOAD_GUEST_PAGE_FAULT,
+    CAUSE_STORE_GUEST_PAGE_FAULT,
+    CAUSE_VIRTUAL_INSTRUCTION,
+];
+
+/// The state of the security monitor on the host. It is shared by all tests, which the mutex serializes because there is only one
+/// emulated physical hart.
+static HOST: Mutex<Option<Host>> = Mutex::new(None);
+
+/// A xorshift pseudo-random generator, the harness must not depend on external crates to run on the host.
+struct Rng {
+    seed: u64,
+    state: u64,
+}
+
+impl Rng {
+    fn new() -> Self {
+        let seed = std::env::var("ACE_FUZZ_SEED")
+            .ok()
+            .and_then(|seed| seed.parse().ok())
+            .unwrap_or(0x9e37_79b9_7f4a_7c15);
+        Self {
+            seed,
+            state: seed | 1,
+        }
+    }
+
+    fn next(&mut self) -> usize {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state as usize
+    }
+
+    fn pick<T: Copy>(&mut self, values: &[T]) -> T {
+        values[self.next() % values.len()]
+    }
+
+    /// Small values are more likely to hit the function ids and register indices the dispatchers match on.
+    fn small_or_random(&mut self) -> usize {
+        match self.next() % 2 {
+            0 => self.next() % 64,
+            _ => self.next(),
+        }
+    }
+
+    /// Randomized SBI arguments a0-a7, biased towards the extension ids implemented by the security monitor.
+    fn sbi_registers(&mut self) -> [usize; 8] {
+        let mut registers = [0; 8];
+        for register in registers.iter_mut() {
+            *register = self.small_or_random();
+        }
+        if self.next() % 4 != 0 {
+            registers[7] = self.pick(&EXTENSION_IDS);
+        }
+        registers
+    }
+
+    /// Randomized SBI arguments a0-a5, of which some are addresses in the non-confidential memory so that the handlers get past the
+    /// address checks.
+    fn hypervisor_registers(&mut self, host: &Host) -> [usize; 8] {
+        let mut registers = self.sbi_registers();
+        for register in registers.iter_mut().take(6) {
+            if self.next() % 3 == 0 {
+                *register = host.non_confidential_address(self);
+            }
+        }
+        registers
+    }
+}
+
+/// The security monitor initialized over memory of the host, with the state of its only physical hart.
+struct Host {
+    hardware_hart: HardwareHart,
+    non_confidential_memory_start: usize,
+    confidential_vm_id: Option<ConfidentialVmId>,
+}
+
+impl Host {
+    /// Initializes the security monitor like `init_security_monitor` does with a device tree describing the memory of the host.
+    fn initialize() -> Self {
+        let size = NON_CONFIDENTIAL_MEMORY_SIZE + CONFIDENTIAL_MEMORY_SIZE;
+        let alignment = PageSize::Size2MiB.in_bytes();
+        // The memory is never freed because the security monitor owns it until the process terminates.
+        let memory = unsafe { alloc_zeroed(Layout::from_size_align(size, alignment).unwrap()) };
+        let clint =
+            unsafe { alloc_zeroed(Layout::from_size_align(CLINT_SIZE, alignment).unwrap()) };
+        assert!(!memory.is_null() && !clint.is_null());
+        unsafe { Plat::get_clint().lock().relocate(clint as usize) };
+
+        let non_confidential_memory_start = memory as *mut usize;
+        let confidential_memory_start =
+            memory.wrapping_add(NON_CONFIDENTIAL_MEMORY_SIZE) as *mut usize;
+        let confidential_memory_end = memory.wrapping_add(size) as *const usize;
+        let confidential_memory_regions = unsafe {
+            MemoryLayout::init(
+                non_confidential_memory_start,
+                confidential_memory_start,
+                &[(confidential_memory_start, confidential_memory_end)],
+            )
+            .unwrap()
+        };
+        // Unit tests run with the allocator of the host, so the entire confidential memory goes to the page allocator.
+        unsafe {
+            PageAllocator::initialize(confidential_memory_regions.into_iter().flatten()).unwrap()
+        };
+        InterruptController::initialize().unwrap();
+        ControlDataStorage::initialize().unwrap();
+        HardwareSetup::initialize().unwrap();
+        // The emulated hart implements every extension the security monitor supports.
+        HardwareExtension::all()
+            .into_iter()
+            .for_each(|extension| HardwareSetup::add_extension(extension).unwrap());
+        ResumableOperation::initialize_response_timeout(10_000_000, 100).unwrap();
+        KeyHierarchy::initialize(Some(b"device secret"), Some(b"migration secret")).unwrap();
+
+        let stack = PageAllocator::acquire_page(PageSize::Size2MiB).unwrap();
+        Self {
+            hardware_hart: HardwareHart::init(0, stack, HypervisorMemoryProtector::create()),
+            non_confidential_memory_start: non_confidential_memory_start as usize,
+            confidential_vm_id: None,
+        }
+    }
+
+    fn lock() -> MutexGuard<'static, Option<Self>> {
+        // A failing test leaves the hart in the middle of the finite state machine, so the other tests cannot reuse it.
+        let mut host = HOST
+            .lock()
+            .expect("The state of the security monitor was corrupted by a failing test");
+        if host.is_none() {
+            *host = Some(Self::initialize());
+        }
+        host
+    }
+
+    fn non_confidential_address(&self, rng: &mut Rng) -> usize {
+        let offset = rng.next() % NON_CONFIDENTIAL_MEMORY_SIZE;
+        let alignment = match rng.next() % 2 {
+            0 => PageSize::Size4KiB.in_bytes(),
+            _ => core::mem::size_of::<usize>(),
+        };
+        self.non_confidential_memory_start + offset - offset % alignment
+    }
+
+    /// Registers the NACL shared memory of the hypervisor at the start of the non-confidential memory and fills it with random content.
+    fn randomize_shared_memory(&mut self, rng: &mut Rng) {
+        let address =
+            NonConfidentialMemoryAddress::new(self.non_confidential_memory_start as *mut usize)
+                .unwrap();
+        let words = NaclSharedMemory::size() / core::mem::size_of::<usize>();
+        for word in 0..words {
+            unsafe {
+                (self.non_confidential_memory_start as *mut usize)
+                    .add(word)
+                    .write(rng.next())
+            };
+        }
+        self.hardware_hart
+            .hypervisor_hart_mut()
+            .set_shared_memory(address)
+            .unwrap();
+    }
+
+    /// Enters the non-confidential flow like the trap vector of the hypervisor does and returns the reached exit node.
+    fn route_trap_from_hypervisor(&mut self) -> Result<HostExit, String> {
+        let hardware_hart = &mut self.hardware_hart;
+        Self::exit_node(catch_unwind(AssertUnwindSafe(|| {
+            NonConfidentialFlow::route(hardware_hart)
+        })))
+    }
+
+    /// Enters the confidential flow like the trap vector of confidential harts does and returns the reached exit node.
+    fn route_trap_from_confidential_hart(&mut self) -> Result<HostExit, String> {
+        let hardware_hart = &mut self.hardware_hart;
+        Self::exit_node(catch_unwind(AssertUnwindSafe(|| {
+            ConfidentialFlow::route(hardware_hart)
+        })))
+    }
+
+    fn exit_node(result: std::thread::Result<()>) -> Result<HostExit, String> {
+        let payload = result.expect_err("Bug: the dispatcher returned");
+        match payload.downcast::<HostExit>() {
+            Ok(exit) => Ok(*exit),
+            Err(payload) => Err(payload
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| {
+                    payload
+                        .downcast_ref::<&str>()
+                        .map(|message| message.to_string())
+                })
+                .unwrap_or_default()),
+        }
+    }
+
+    /// Makes a call to the security monitor on behalf of a well-behaved hypervisor.
+    fn hypervisor_call(
+        &mut self,
+        extension_id: usize,
+        function_id: usize,
+        arguments: &[usize],
+    ) -> HostExit {
+        let gprs = self.hardware_hart.hypervisor_hart_mut().gprs_mut();
+        arguments.iter().enumerate().for_each(|(index, value)| {
+            gprs.write(
+                GeneralPurposeRegister::try_from(10 + index).unwrap(),
+                *value,
+            )
+        });
+        gprs.write(GeneralPurposeRegister::a6, function_id);
+        gprs.write(GeneralPurposeRegister::a7, extension_id);
+        write_csr(CSR_MCAUSE, CAUSE_SUPERVISOR_ECALL.into());
+        self.route_trap_from_hypervisor().unwrap_or_else(|message| {
+            panic!(
+                "Hypervisor call {:x}:{:x} panicked: {}",
+                extension_id, function_id, message
+            )
+        })
+    }
+
+    /// Makes sure that a confidential hart is assigned to the physical hart, creating a new confidential VM when the hypervisor cannot
+    /// resume the previous one, e.g., because the fuzzer shut it down.
+    fn run_confidential_hart(&mut self) {
+        if !self.hardware_hart.confidential_hart().is_dummy() {
+            return;
+        }
+        if let Some(id) = self.confidential_vm_id.take() {
+            if self.hypervisor_call(
+                CovhExtension::EXTID,
+                CovhExtension::SBI_EXT_COVH_TVM_VCPU_RUN,
+                &[id.usize(), 0],
+            ) == HostExit::ConfidentialHart
+            {
+                self.confidential_vm_id = Some(id);
+                return;
+            }
+            self.hypervisor_call(
+                CovhExtension::EXTID,
+                CovhExtension::SBI_EXT_COVH_DESTROY_TVM,
+                &[id.usize()],
+            );
+        }
+        let id = self.create_confidential_vm();
+        assert_eq!(
+            self.hypervisor_call(
+                CovhExtension::EXTID,
+                CovhExtension::SBI_EXT_COVH_TVM_VCPU_RUN,
+                &[id.usize(), 0]
+            ),
+            HostExit::ConfidentialHart
+        );
+        self.confidential_vm_id = Some(id);
+    }
+
+    /// Creates a confidential VM with a single hart and an empty address space, skipping the promotion that is not the target of this
+    /// harness.
+    fn create_confidential_vm(&mut self) -> ConfidentialVmId {
+        let shared_memory = self.hardware_hart.hypervisor_hart().shared_memory();
+        let confidential_harts = vec![ConfidentialHart::from_vm_hart(
+            0,
+            0x8020_0000,
+            0,
+            shared_memory,
+        )];
+        let measurements =
+            StaticMeasurements::new(MeasurementDigest::default(), MeasurementDigest::default());
+        let memory_protector = ConfidentialVmMemoryProtector::empty().unwrap();
+        ControlDataStorage::try_write(|control_data| {
+            let id = control_data.unique_id()?;
+            control_data.insert_confidential_vm(ConfidentialVm::new(
+                id,
+                confidential_harts,
+                measurements,
+                memory_protector,
+                LazyPageManifest::empty(),
+            ))
+        })
+        .unwrap()
+    }
+
+    /// Gives the physical hart back to the hypervisor by interrupting the confidential hart with the M-mode timer.
+    fn stop_confidential_hart(&mut self) {
+        if self.hardware_hart.confidential_hart().is_dummy() {
+            return;
+        }
+        write_csr(CSR_MIP, MIE_MTIP_MASK);
+        write_csr(CSR_MCAUSE, (1 << CAUSE_INTERRUPT_BIT) | MIE_MTIP);
+        assert_eq!(
+            self.route_trap_from_confidential_hart(),
+            Ok(HostExit::Hypervisor)
+        );
+    }
+
+    fn destroy_confidential_vm(&mut self) {
+        self.stop_confidential_hart();
+        if let Some(id) = self.confidential_vm_id.take() {
+            self.hypervisor_call(
+                CovhExtension::EXTID,
+                CovhExtension::SBI_EXT_COVH_DESTROY_TVM,
+                &[id.usize()],
+            );
+        }
+    }
+}
+
+/// Returns true if the value in a0 is either success or one of the error codes defined by the SBI specification.
+fn is_sbi_return_code(a0: usize) -> bool {
+    (SBI_ERR_BAD_RANGE as isize..=SBI_SUCCESS as isize).contains(&(a0 as isize))
+}
+
+/// Returns true if the call is not implemented by the extension it targets, so it must fail with `SBI_ERR_INVALID_PARAM`.
+fn is_invalid_call(cause: &TrapCause) -> bool {
+    matches!(
+        cause,
+        TrapCause::HsEcall(SbiExtension::Covh(CovhExtension::Unknown(_, _)))
+            | TrapCause::HsEcall(SbiExtension::Covi(CoviExtension::Unknown(_, _)))
+            | TrapCause::HsEcall(SbiExtension::Nacl(NaclExtension::Unknown(_, _)))
+            | TrapCause::VsEcall(SbiExtension::Covg(CovgExtension::Unknown(_, _)))
+    )
+}
+
+#[test]
+fn hypervisor_calls_return_sbi_errors() {
+    let mut host = Host::lock();
+    let host = host.as_mut().unwrap();
+    let mut rng = Rng::new();
+    for iteration in 0..DISPATCH_ITERATIONS {
+        host.randomize_shared_memory(&mut rng);
+        let registers = rng.hypervisor_registers(host);
+        let gprs = host.hardware_hart.hypervisor_hart_mut().gprs_mut();
+        for (index, value) in registers.iter().enumerate() {
+            gprs.write(
+                GeneralPurposeRegister::try_from(10 + index).unwrap(),
+                *value,
+            );
+        }
+        let mcause = rng.pick(&HYPERVISOR_TRAP_CAUSES).into();
+        write_csr(CSR_MCAUSE, mcause);
+        // The promotion copies the page tables pointed by hgatp from the non-confidential memory.
+        let root_page_table = host.non_confidential_address(&mut rng);
+        write_csr(
+            CSR_HGATP,
+            Hgatp::new(root_page_table, HgatpMode::Sv57x4, 0).bits(),
+        );
+        let cause = TrapCause::decode(mcause, registers[7], registers[6]);
+
+        let context = format!(
+            "seed {}, iteration {}, {:?} with a0-a7 {:x?}",
+            rng.seed, iteration, cause, registers
+        );
+        let exit = host.route_trap_from_hypervisor().unwrap_or_else(|message| {
+            panic!(
+                "The non-confidential flow panicked ({}): {}",
+                context, message
+            )
+        });
+        match exit {
+            HostExit::Hypervisor => {
+                let a0 = host
+                    .hardware_hart
+                    .hypervisor_hart()
+                    .gprs()
+                    .read(GeneralPurposeRegister::a0);
+                assert!(is_sbi_return_code(a0), "Returned a0={:x} ({})", a0, context);
+                if is_invalid_call(&cause) {
+                    assert_eq!(
+                        a0, SBI_ERR_INVALID_PARAM as usize,
+                        "Invalid call not rejected ({})",
+                        context
+                    );
+                }
+            }
+            // The fuzzer resumed a confidential hart of a confidential VM that it guessed the id of.
+            HostExit::ConfidentialHart => host.stop_confidential_hart(),
+            HostExit::Miralis => assert!(
+                !matches!(
+                    cause,
+                    TrapCause::HsEcall(
+                        SbiExtension::Covh(_) | SbiExtension::Covi(_) | SbiExtension::Nacl(_)
+                    )
+                ),
+                "Call to the security monitor handed over to Miralis ({})",
+                context
+            ),
+        }
+    }
+}
+
+#[test]
+fn confidential_hart_calls_return_sbi_errors() {
+    let mut host = Host::lock();
+    let host = host.as_mut().unwrap();
+    let mut rng = Rng::new();
+    for iteration in 0..DISPATCH_ITERATIONS {
+        host.randomize_shared_memory(&mut rng);
+        host.run_confidential_hart();
+        let registers = rng.sbi_registers();
+        let gprs = host.hardware_hart.confidential_hart_mut().gprs_mut();
+        for (index, value) in registers.iter().enumerate() {
+            gprs.write(
+                GeneralPurposeRegister::try_from(10 + index).unwrap(),
+                *value,
+            );
+        }
+        let mcause = match rng.next() % 8 {
+            0 => (1 << CAUSE_INTERRUPT_BIT) | rng.pick(&[MIE_SSIP, MIE_MTIP]),
+            _ => rng.pick(&CONFIDENTIAL_HART_TRAP_CAUSES).into(),
+        };
+        write_csr(CSR_MCAUSE, mcause);
+        write_csr(CSR_MIP, rng.pick(&[0, MIE_SSIP_MASK, MIE_MTIP_MASK]));
+        // The faulting guest address and the trapped instruction used by the MMIO and virtual instruction handlers.
+        write_csr(CSR_MTVAL, rng.small_or_random());
+        write_csr(CSR_MTVAL2, rng.small_or_random());
+        write_csr(CSR_HTVAL, rng.small_or_random());
+        write_csr(CSR_MTINST, rng.next() & 0xffff_ffff);
+        let cause = TrapCause::decode(mcause, registers[7], registers[6]);
+
+        let context = format!(
+            "seed {}, iteration {}, {:?} with a0-a7 {:x?}",
+            rng.seed, iteration, cause, registers
+        );
+        let exit = host
+            .route_trap_from_confidential_hart()
+            .unwrap_or_else(|message| {
+                panic!("The confidential flow panicked ({}): {}", context, message)
+            });
+        match exit {
+            HostExit::ConfidentialHart if matches!(cause, TrapCause::VsEcall(_)) => {
+                let a0 = host
+                    .hardware_hart
+                    .confidential_hart()
+                    .gprs()
+                    .read(GeneralPurposeRegister::a0);
+                assert!(is_sbi_return_code(a0), "Returned a0={:x} ({})", a0, context);
+                if is_invalid_call(&cause) {
+                    assert_eq!(
+                        a0, SBI_ERR_INVALID_PARAM as usize,
+                        "Invalid call not rejected ({})",
+                        context
+                    );
+                }
+            }
+            HostExit::ConfidentialHart | HostExit::Hypervisor => {}
+            HostExit::Miralis => panic!("A confidential hart reached Miralis ({})", context),
+        }
+    }
+    host.destroy_confidential_vm();
+}
+
+#[test]
+fn trap_decoding_never_panics() {
+    let mut rng = Rng::new();
+    for _ in 0..ITERATIONS {
+        let registers = rng.sbi_registers();
+        let mcause = match rng.next() % 4 {
+            0 => rng.next(),
+            _ => rng.pick(&TRAP_CAUSES).into(),
+        };
+        let cause = TrapCause::decode(mcause, registers[7], registers[6]);
+
+        if mcause == CAUSE_SUPERVISOR_ECALL.into() {
+            match (&cause, registers[7]) {
+                (TrapCause::HsEcall(SbiExtension::Covh(_)), CovhExtension::EXTID) => {}
+                (TrapCause::HsEcall(SbiExtension::Nacl(_)), NaclExtension::EXTID) => {}
+                (TrapCause::HsEcall(_), id)
+                    if id != CovhExtension::EXTID && id != NaclExtension::EXTID => {}
+                _ => panic!(
+                    "Hypervisor ecall {:x?} decoded as {:?} (seed {})",
+                    registers, cause, rng.seed
+                ),
+            }
+        }
+        if mcause == CAUSE_VIRTUAL_SUPERVISOR_ECALL.into() {
+            match (&cause, registers[7]) {
+                (TrapCause::VsEcall(SbiExtension::Covg(_)), CovgExtension::EXTID) => {}
+                (TrapCause::VsEcall(_), id) if id != CovgExtension::EXTID => {}
+                _ => panic!(
+                    "Confidential VM ecall {:x?} decoded as {:?} (seed {})",
+                    registers, cause, rng.seed
+                ),
+            }
+        }
+    }
+}
+
+#[test]
+fn mmio_instruction_decoding_never_panics() {
+    let mut rng = Rng::new();
+    for _ in 0..ITERATIONS {
+        // The confidential flow always sets the two lowest bits of the transformed instruction provided in mtinst.
+        let mtinst = match rng.next() % 2 {
+            0 => rng.next() & 0xffff_ffff,
+            _ => (rng.next() & 0xffff_ffff) | 0x3,
+        };
+        let gpr = decode_result_register(mtinst);
+        let width = decode_load_width(mtinst);
+        if let Err(error) = &gpr {
+            assert_ne!(
+                error.sbi_error_code(),
+                SBI_SUCCESS as usize,
+                "mtinst {:x} (seed {})",
+                mtinst,
+                rng.seed
+            );
+        }
+        if let (Ok(gpr), Ok((access_width, sign_extended))) = (gpr, width) {
+            assert!(
+                [1, 2, 4, 8].contains(&access_width),
+                "mtinst {:x} (seed {})",
+                mtinst,
+                rng.seed
+            );
+
+            // Whatever the hypervisor responds, the value written to the confidential hart must fit the access width.
+            let pending = MmioLoadPending::new(2, gpr, 0, access_width, sign_extended);
+            let response = rng.small_or_random();
+            if let Some(value) = pending.load_result(response) {
+                let bits = access_width * 8;
+                if bits < usize::BITS as usize && !sign_extended {
+                    assert_eq!(
+                        value >> bits,
+                        0,
+                        "mtinst {:x}, response {:x} (seed {})",
+                        mtinst,
+                        response,
+                        rng.seed
+                    );
+                }
+            }
+        }
+    }
+}
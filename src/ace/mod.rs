@@ -11,4 +11,6 @@ pub mod core;
 pub mod confidential_flow;
 pub mod debug;
 pub mod error;
+#[cfg(test)]
+mod fuzz;
 pub mod non_confidential_flow;
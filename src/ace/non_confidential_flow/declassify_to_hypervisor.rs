@@ -4,6 +4,7 @@
 use crate::ace::confidential_flow::handlers::interrupts::{
     ExposeEnabledInterrupts, HandleInterrupt,
 };
+use crate::ace::confidential_flow::handlers::lazy_page::LazyPageFault;
 use crate::ace::confidential_flow::handlers::mmio::{MmioLoadRequest, MmioStoreRequest};
 use crate::ace::confidential_flow::handlers::sbi::{SbiRequest, SbiResponse};
 
@@ -14,5 +15,6 @@ pub enum DeclassifyToHypervisor {
     Interrupt(HandleInterrupt),
     MmioLoadRequest(MmioLoadRequest),
     MmioStoreRequest(MmioStoreRequest),
+    LazyPageFault(LazyPageFault),
     EnabledInterrupts(ExposeEnabledInterrupts),
 }
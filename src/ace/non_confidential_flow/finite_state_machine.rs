@@ -4,16 +4,21 @@
 use crate::ace::confidential_flow::ConfidentialFlow;
 use crate::ace::core::architecture::riscv::sbi::BaseExtension::*;
 use crate::ace::core::architecture::riscv::sbi::CovhExtension::*;
+use crate::ace::core::architecture::riscv::sbi::CoviExtension::*;
 use crate::ace::core::architecture::riscv::sbi::NaclExtension::*;
 use crate::ace::core::architecture::riscv::sbi::NaclSharedMemory;
 use crate::ace::core::architecture::riscv::sbi::SbiExtension::*;
-use crate::ace::core::architecture::sbi::{CovhExtension, NaclExtension};
 use crate::ace::core::architecture::TrapCause;
 use crate::ace::core::architecture::TrapCause::*;
 use crate::ace::core::control_data::{ConfidentialVmId, HardwareHart, HypervisorHart};
 use crate::ace::error::Error;
 use crate::ace::non_confidential_flow::handlers::cove_hypervisor_extension::{
-    DestroyConfidentialVm, GetSecurityMonitorInfo, PromoteToConfidentialVm, RunConfidentialHart,
+    DestroyConfidentialVm, ExportConfidentialVm, GetSecurityMonitorInfo, ImportConfidentialVm,
+    PopulateConfidentialVmPage, PromoteToConfidentialVm, RunConfidentialHart,
+};
+use crate::ace::non_confidential_flow::handlers::cove_interrupt_extension::{
+    AiaInit, BindImsic, ConvertImsic, InjectExternalInterrupt, ReclaimImsic, SetImsicAddress,
+    UnbindImsicBegin, UnbindImsicEnd,
 };
 use crate::ace::non_confidential_flow::handlers::nested_acceleration_extension::{
     NaclProbeFeature, NaclSetupSharedMemory,
@@ -25,6 +30,7 @@ use crate::ace::non_confidential_flow::handlers::supervisor_binary_interface::{
 use crate::ace::non_confidential_flow::{ApplyToHypervisorHart, DeclassifyToHypervisor};
 use crate::policy::ace::ace_to_miralis_ctx_switch;
 
+#[cfg(not(feature = "userspace"))]
 extern "C" {
     /// To ensure safety, specify all possible valid states that KVM expects to see and prove that security monitor
     /// never returns to KVM with other state. For example, only a subset of exceptions/interrupts can be handled by KVM.
@@ -32,6 +38,9 @@ extern "C" {
     fn exit_to_hypervisor_asm() -> !;
 }
 
+#[cfg(feature = "userspace")]
+use crate::ace::core::architecture::riscv::userspace::{self, exit_to_hypervisor_asm};
+
 /// Represents the non-confidential part of the finite state machine (FSM), implementing router and exit nodes. It encapsulates the
 /// HardwareHart instance, which is never exposed. It invokes handlers providing them temporary read access to hypervisor hart state.
 pub struct NonConfidentialFlow<'a> {
@@ -62,8 +71,13 @@ impl<'a> NonConfidentialFlow<'a> {
         // hardware hart's dump area in main memory. This area in main memory is exclusively owned by the physical hart executing this code.
         // Specifically, every physical hart has its own are in the main memory and its `mscratch` register stores the address. See the
         // `initialization` procedure for more details.
-        let flow = unsafe { Self::create(hart_ptr.as_mut().expect(Self::CTX_SWITCH_ERROR_MSG)) };
+        Self::route(unsafe { hart_ptr.as_mut().expect(Self::CTX_SWITCH_ERROR_MSG) })
+    }
 
+    /// Routes control flow execution based on the trap cause of the hypervisor hart. It is the safe part of
+    /// `route_trap_from_hypervisor_or_vm`, also used to drive the finite state machine on the host.
+    pub(crate) fn route(hardware_hart: &'a mut HardwareHart) -> ! {
+        let flow = Self::create(hardware_hart);
         let current_cause = TrapCause::from_hart_architectural_state(
             flow.hypervisor_hart().hypervisor_hart_state(),
         );
@@ -78,9 +92,14 @@ impl<'a> NonConfidentialFlow<'a> {
             StoreAccessFault => ace_to_miralis_ctx_switch(flow.hardware_hart), //DelegateToOpensbi::from_hypervisor_hart(flow.hypervisor_hart()).handle(flow),
             HsEcall(Base(ProbeExtension)) => {
                 let extension = ProbeSbiExtension::from_hypervisor_hart(flow.hypervisor_hart());
-                if extension.extension_id == CovhExtension::EXTID
-                    || extension.extension_id == NaclExtension::EXTID
-                {
+                if matches!(
+                    crate::sbi::route(extension.extension_id),
+                    Some(
+                        crate::sbi::SbiExtension::Covh
+                            | crate::sbi::SbiExtension::Covi
+                            | crate::sbi::SbiExtension::Nacl
+                    )
+                ) {
                     flow.apply_and_exit_to_hypervisor(ApplyToHypervisorHart::SbiResponse(
                         SbiResponse::success_with_code(1),
                     ))
@@ -94,15 +113,56 @@ impl<'a> NonConfidentialFlow<'a> {
             HsEcall(Covh(PromoteToTvm)) => {
                 PromoteToConfidentialVm::from_hypervisor_hart(flow.hypervisor_hart()).handle(flow)
             }
+            HsEcall(Covh(PromoteToTvmLazy)) => {
+                PromoteToConfidentialVm::from_hypervisor_hart_lazy(flow.hypervisor_hart())
+                    .handle(flow)
+            }
+            HsEcall(Covh(TvmPopulatePage)) => {
+                PopulateConfidentialVmPage::from_hypervisor_hart(flow.hypervisor_hart())
+                    .handle(flow)
+            }
             HsEcall(Covh(TvmVcpuRun)) => {
                 RunConfidentialHart::from_hypervisor_hart(flow.hypervisor_hart()).handle(flow)
             }
             HsEcall(Covh(DestroyTvm)) => {
                 DestroyConfidentialVm::from_hypervisor_hart(flow.hypervisor_hart()).handle(flow)
             }
+            HsEcall(Covh(TvmExport)) => {
+                ExportConfidentialVm::from_hypervisor_hart(flow.hypervisor_hart()).handle(flow)
+            }
+            HsEcall(Covh(TvmImport)) => {
+                ImportConfidentialVm::from_hypervisor_hart(flow.hypervisor_hart()).handle(flow)
+            }
             HsEcall(Covh(_)) => {
                 InvalidCall::from_hypervisor_hart(flow.hypervisor_hart()).handle(flow)
             }
+            HsEcall(Covi(AiaInit)) => {
+                AiaInit::from_hypervisor_hart(flow.hypervisor_hart()).handle(flow)
+            }
+            HsEcall(Covi(SetImsicAddress)) => {
+                SetImsicAddress::from_hypervisor_hart(flow.hypervisor_hart()).handle(flow)
+            }
+            HsEcall(Covi(ConvertImsic)) => {
+                ConvertImsic::from_hypervisor_hart(flow.hypervisor_hart()).handle(flow)
+            }
+            HsEcall(Covi(ReclaimImsic)) => {
+                ReclaimImsic::from_hypervisor_hart(flow.hypervisor_hart()).handle(flow)
+            }
+            HsEcall(Covi(BindImsic)) => {
+                BindImsic::from_hypervisor_hart(flow.hypervisor_hart()).handle(flow)
+            }
+            HsEcall(Covi(UnbindImsicBegin)) => {
+                UnbindImsicBegin::from_hypervisor_hart(flow.hypervisor_hart()).handle(flow)
+            }
+            HsEcall(Covi(UnbindImsicEnd)) => {
+                UnbindImsicEnd::from_hypervisor_hart(flow.hypervisor_hart()).handle(flow)
+            }
+            HsEcall(Covi(InjectExternalInterrupt)) => {
+                InjectExternalInterrupt::from_hypervisor_hart(flow.hypervisor_hart()).handle(flow)
+            }
+            HsEcall(Covi(_)) => {
+                InvalidCall::from_hypervisor_hart(flow.hypervisor_hart()).handle(flow)
+            }
             HsEcall(Nacl(ProbeFeature)) => {
                 NaclProbeFeature::from_hypervisor_hart(flow.hypervisor_hart()).handle(flow)
             }
@@ -159,6 +219,9 @@ impl<'a> NonConfidentialFlow<'a> {
             DeclassifyToHypervisor::MmioStoreRequest(v) => {
                 v.declassify_to_hypervisor_hart(self.hypervisor_hart_mut())
             }
+            DeclassifyToHypervisor::LazyPageFault(v) => {
+                v.declassify_to_hypervisor_hart(self.hypervisor_hart_mut())
+            }
             DeclassifyToHypervisor::EnabledInterrupts(v) => {
                 v.declassify_to_hypervisor_hart(self.hypervisor_hart_mut())
             }
@@ -215,6 +278,7 @@ impl<'a> NonConfidentialFlow<'a> {
 
 
 
+#[cfg(not(feature = "userspace"))]
 use core::arch::asm;
 
 use crate::arch::pmp::pmpcfg;
@@ -223,6 +287,11 @@ use crate::arch::pmp::pmpcfg;
 /// Returns the value of the specified `pmpcfgx` register as a 64-bit unsigned integer.
 fn read_pmpcfg(idx: usize) -> usize {
     let value: usize;
+    #[cfg(feature = "userspace")]
+    {
+        value = userspace::read_csr(if idx < 8 { 0x3A0 } else { 0x3A2 });
+    }
+    #[cfg(not(feature = "userspace"))]
     unsafe {
         match idx {
             0 => asm!("csrr {}, pmpcfg0", out(reg) value),
@@ -260,6 +329,11 @@ pub fn read_pmpaddr(index: usize) -> u64 {
 
     // Read the CSR value using inline assembly.
     let value: u64;
+    #[cfg(feature = "userspace")]
+    {
+        value = userspace::read_csr(csr_address as u16) as u64;
+    }
+    #[cfg(not(feature = "userspace"))]
     unsafe {
         match index {
             0 => asm!("csrr {}, pmpaddr0", out(reg) value),
@@ -0,0 +1,51 @@
+// SPDX-FileCopyrightText: 2023 IBM Corporation
+// SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
+// SPDX-License-Identifier: Apache-2.0
+use crate::ace::core::architecture::GeneralPurposeRegister;
+use crate::ace::core::control_data::{
+    ConfidentialVmId, ControlDataStorage, HypervisorHart, MigrationBuffer,
+};
+use crate::ace::error::Error;
+use crate::ace::non_confidential_flow::handlers::supervisor_binary_interface::SbiResponse;
+use crate::ace::non_confidential_flow::{ApplyToHypervisorHart, NonConfidentialFlow};
+
+/// Exports a paused confidential VM into a migration image written to a buffer in non-confidential memory, so that the hypervisor can
+/// transfer it to another host and import it there, see `ImportConfidentialVm`. The image is encrypted and authenticated, thus it
+/// discloses nothing about the confidential VM but its size. Returns the size of the image to the caller.
+///
+/// Returns error if a confidential hart is running, if the confidential VM cannot be migrated, or if the buffer is too small. On success,
+/// the confidential VM is shut down on this host and the hypervisor can only destroy it.
+pub struct ExportConfidentialVm {
+    confidential_vm_id: ConfidentialVmId,
+    buffer_address: usize,
+    buffer_size: usize,
+}
+
+impl ExportConfidentialVm {
+    pub fn from_hypervisor_hart(hypervisor_hart: &HypervisorHart) -> Self {
+        Self {
+            confidential_vm_id: ConfidentialVmId::new(
+                hypervisor_hart.gprs().read(GeneralPurposeRegister::a0),
+            ),
+            buffer_address: hypervisor_hart.gprs().read(GeneralPurposeRegister::a1),
+            buffer_size: hypervisor_hart.gprs().read(GeneralPurposeRegister::a2),
+        }
+    }
+
+    pub fn handle(self, non_confidential_flow: NonConfidentialFlow) -> ! {
+        let sbi_response = self.export().map_or_else(
+            |error| SbiResponse::error(error),
+            |image_size| SbiResponse::success_with_code(image_size),
+        );
+        non_confidential_flow
+            .apply_and_exit_to_hypervisor(ApplyToHypervisorHart::SbiResponse(sbi_response))
+    }
+
+    fn export(&self) -> Result<usize, Error> {
+        let buffer = MigrationBuffer::new(self.buffer_address, self.buffer_size)?;
+        ControlDataStorage::try_confidential_vm_mut(
+            self.confidential_vm_id,
+            |mut confidential_vm| confidential_vm.export(buffer),
+        )
+    }
+}
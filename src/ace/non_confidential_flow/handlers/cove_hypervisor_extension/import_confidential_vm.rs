@@ -0,0 +1,54 @@
+// SPDX-FileCopyrightText: 2023 IBM Corporation
+// SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
+// SPDX-License-Identifier: Apache-2.0
+use crate::ace::core::architecture::riscv::sbi::NaclSharedMemory;
+use crate::ace::core::architecture::GeneralPurposeRegister;
+use crate::ace::core::control_data::{
+    ConfidentialVm, ConfidentialVmId, ControlDataStorage, HypervisorHart, MigrationBuffer,
+};
+use crate::ace::error::Error;
+use crate::ace::non_confidential_flow::handlers::supervisor_binary_interface::SbiResponse;
+use crate::ace::non_confidential_flow::{ApplyToHypervisorHart, NonConfidentialFlow};
+use crate::debug;
+
+/// Imports a confidential VM from a migration image produced by `ExportConfidentialVm` on another host, and returns the id of the new
+/// confidential VM to the caller. The hypervisor then runs the confidential harts as after the promotion.
+///
+/// Returns error if the image is malformed, has been modified, or was exported by a security monitor that does not share the migration
+/// secret of this security monitor.
+pub struct ImportConfidentialVm {
+    buffer_address: usize,
+    buffer_size: usize,
+}
+
+impl ImportConfidentialVm {
+    pub fn from_hypervisor_hart(hypervisor_hart: &HypervisorHart) -> Self {
+        Self {
+            buffer_address: hypervisor_hart.gprs().read(GeneralPurposeRegister::a0),
+            buffer_size: hypervisor_hart.gprs().read(GeneralPurposeRegister::a1),
+        }
+    }
+
+    pub fn handle(self, non_confidential_flow: NonConfidentialFlow) -> ! {
+        let sbi_response = self
+            .import(non_confidential_flow.shared_memory())
+            .map_or_else(
+                |error| SbiResponse::error(error),
+                |id| SbiResponse::success_with_code(id.usize()),
+            );
+        non_confidential_flow
+            .apply_and_exit_to_hypervisor(ApplyToHypervisorHart::SbiResponse(sbi_response))
+    }
+
+    fn import(&self, shared_memory: &NaclSharedMemory) -> Result<ConfidentialVmId, Error> {
+        debug!("Importing a confidential VM");
+        // The image is read into the confidential memory before the control data is locked, because reading it takes time.
+        let buffer = MigrationBuffer::new(self.buffer_address, self.buffer_size)?;
+        let migrated_confidential_vm = ConfidentialVm::import(buffer, shared_memory)?;
+        ControlDataStorage::try_write(|control_data| {
+            let id = control_data.unique_id()?;
+            control_data
+                .insert_confidential_vm(ConfidentialVm::from_migrated(id, migrated_confidential_vm))
+        })
+    }
+}
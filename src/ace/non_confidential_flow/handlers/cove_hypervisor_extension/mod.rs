@@ -5,11 +5,17 @@
 //! This module implements a subset of the CoVE's COVH ABI required to implement the CoVE's deployment model 3.
 
 pub use destroy_confidential_vm::DestroyConfidentialVm;
+pub use export_confidential_vm::ExportConfidentialVm;
 pub use get_security_monitor_info::GetSecurityMonitorInfo;
+pub use import_confidential_vm::ImportConfidentialVm;
+pub use populate_confidential_vm_page::PopulateConfidentialVmPage;
 pub use promote_to_confidential_vm::PromoteToConfidentialVm;
 pub use run_confidential_hart::RunConfidentialHart;
 
 mod destroy_confidential_vm;
+mod export_confidential_vm;
 mod get_security_monitor_info;
+mod import_confidential_vm;
+mod populate_confidential_vm_page;
 mod promote_to_confidential_vm;
 mod run_confidential_hart;
@@ -0,0 +1,71 @@
+// SPDX-FileCopyrightText: 2023 IBM Corporation
+// SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
+// SPDX-License-Identifier: Apache-2.0
+use crate::ace::confidential_flow::handlers::symmetrical_multiprocessing::RemoteHfenceGvmaVmid;
+use crate::ace::core::architecture::GeneralPurposeRegister;
+use crate::ace::core::control_data::{
+    ConfidentialHartRemoteCommand, ConfidentialVmId, ControlDataStorage, HypervisorHart,
+};
+use crate::ace::core::memory_layout::{
+    ConfidentialVmPhysicalAddress, NonConfidentialMemoryAddress,
+};
+use crate::ace::error::Error;
+use crate::ace::non_confidential_flow::handlers::supervisor_binary_interface::SbiResponse;
+use crate::ace::non_confidential_flow::{ApplyToHypervisorHart, NonConfidentialFlow};
+
+/// Donates a lazily populated page to a confidential VM. The hypervisor calls it after a confidential hart faulted on a page declared in
+/// the manifest presented during the promotion, and then resumes the confidential hart, which retries the faulting access.
+///
+/// The security monitor copies the page from non-confidential memory into confidential memory, checks that its content matches the
+/// measurement declared in the manifest, and maps it into the address space of the confidential VM. Returns error if the page is not
+/// pending or its content does not match the manifest.
+pub struct PopulateConfidentialVmPage {
+    confidential_vm_id: ConfidentialVmId,
+    confidential_vm_address: ConfidentialVmPhysicalAddress,
+    hypervisor_page_address: usize,
+}
+
+impl PopulateConfidentialVmPage {
+    pub fn from_hypervisor_hart(hypervisor_hart: &HypervisorHart) -> Self {
+        Self {
+            confidential_vm_id: ConfidentialVmId::new(
+                hypervisor_hart.gprs().read(GeneralPurposeRegister::a0),
+            ),
+            confidential_vm_address: ConfidentialVmPhysicalAddress::new(
+                hypervisor_hart.gprs().read(GeneralPurposeRegister::a1),
+            ),
+            hypervisor_page_address: hypervisor_hart.gprs().read(GeneralPurposeRegister::a2),
+        }
+    }
+
+    pub fn handle(self, non_confidential_flow: NonConfidentialFlow) -> ! {
+        let sbi_response = self.populate_page().map_or_else(
+            |error| SbiResponse::error(error),
+            |_| SbiResponse::success(),
+        );
+        non_confidential_flow
+            .apply_and_exit_to_hypervisor(ApplyToHypervisorHart::SbiResponse(sbi_response))
+    }
+
+    fn populate_page(&self) -> Result<(), Error> {
+        // Security: check that the start address is located in the non-confidential memory, the end address is checked when copying.
+        let hypervisor_address =
+            NonConfidentialMemoryAddress::new(self.hypervisor_page_address as *mut usize)?;
+
+        ControlDataStorage::try_confidential_vm_mut(
+            self.confidential_vm_id,
+            |mut confidential_vm| {
+                let page_size = confidential_vm
+                    .populate_lazy_page(&self.confidential_vm_address, hypervisor_address)?;
+                let request = RemoteHfenceGvmaVmid::all_harts(
+                    &self.confidential_vm_address,
+                    page_size,
+                    self.confidential_vm_id,
+                );
+                confidential_vm.broadcast_remote_command(
+                    ConfidentialHartRemoteCommand::RemoteHfenceGvmaVmid(request),
+                )
+            },
+        )
+    }
+}
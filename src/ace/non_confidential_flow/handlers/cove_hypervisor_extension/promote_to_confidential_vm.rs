@@ -9,7 +9,7 @@ use crate::ace::core::architecture::riscv::sbi::NaclSharedMemory;
 use crate::ace::core::architecture::{GeneralPurposeRegister, Hgatp, PageSize};
 use crate::ace::core::control_data::{
     ConfidentialHart, ConfidentialVm, ConfidentialVmId, ControlDataStorage, HypervisorHart,
-    StaticMeasurements,
+    LazyPageManifest, PromotionTemplate, StaticMeasurements,
 };
 use crate::ace::core::memory_layout::ConfidentialVmPhysicalAddress;
 use crate::ace::core::memory_protector::ConfidentialVmMemoryProtector;
@@ -21,8 +21,11 @@ use crate::{debug, ensure};
 
 /// Creates a confidential VM in a single-step. This handler implements the Promote to TVM call defined by the COVH ABI in the CoVE
 /// specification. With this call, the hypervisor presents a state of a virtual machine, requesting the security monitor to promote it to a
-/// confidential VM. The security monitor copies the VM state (data, page tables, boot hart state) into the confidential memory and measures
-/// it.
+/// confidential VM. The security monitor copies the VM state (data, page tables, boot hart state) into the confidential memory, validates
+/// it against the promotion template, and measures it.
+///
+/// With the lazy variant of the call, the hypervisor additionally presents a manifest of pages that are not part of the VM state yet. These
+/// pages are donated by the hypervisor on first access (see `PopulateConfidentialVmPage`), and the manifest is measured instead of them.
 ///
 /// # Safety
 ///
@@ -34,6 +37,7 @@ pub struct PromoteToConfidentialVm {
     auth_blob_address: Option<ConfidentialVmPhysicalAddress>,
     program_counter: usize,
     hgatp: Hgatp,
+    lazy_page_manifest_address: Option<ConfidentialVmPhysicalAddress>,
 }
 
 impl PromoteToConfidentialVm {
@@ -56,6 +60,19 @@ impl PromoteToConfidentialVm {
             auth_blob_address,
             program_counter,
             hgatp,
+            lazy_page_manifest_address: None,
+        }
+    }
+
+    /// Reads the arguments of the Promote to TVM call, extended with the guest physical address of the manifest of lazily populated
+    /// pages.
+    pub fn from_hypervisor_hart_lazy(hypervisor_hart: &HypervisorHart) -> Self {
+        let lazy_page_manifest_address = ConfidentialVmPhysicalAddress::new(
+            hypervisor_hart.gprs().read(GeneralPurposeRegister::a3),
+        );
+        Self {
+            lazy_page_manifest_address: Some(lazy_page_manifest_address),
+            ..Self::from_hypervisor_hart(hypervisor_hart)
         }
     }
 
@@ -80,8 +97,23 @@ impl PromoteToConfidentialVm {
         shared_memory: &NaclSharedMemory,
     ) -> Result<ConfidentialVmId, Error> {
         debug!("Promoting a VM into a confidential VM");
+        PromotionTemplate::validate_entry_point(self.program_counter)?;
+
         // Copy the entire VM's state to the confidential memory, recreating the MMU configuration.
-        let memory_protector = ConfidentialVmMemoryProtector::from_vm_state(&self.hgatp)?;
+        let mut memory_protector = ConfidentialVmMemoryProtector::from_vm_state(&self.hgatp)?;
+
+        // The VM's data can only be validated once it is in the confidential memory, otherwise the hypervisor could modify it after the
+        // validation.
+        PromotionTemplate::validate_zeroed_regions(&memory_protector)?;
+
+        // The manifest is read from the confidential memory for the same reason. It declares pages that must not be part of the VM's
+        // state yet, they will be donated by the hypervisor on first access.
+        let lazy_pages = match self.lazy_page_manifest_address {
+            Some(ref address) => {
+                LazyPageManifest::from_confidential_vm_memory(&memory_protector, address)?
+            }
+            None => LazyPageManifest::empty(),
+        };
 
         // The pointer to the flattened device tree (FDT) as well as the entire FDT must be treated as an untrusted input, which measurement
         // is reflected during attestation. We can parse FDT only after moving VM's data (and the FDT) to the confidential memory.
@@ -107,13 +139,17 @@ impl PromoteToConfidentialVm {
             })
             .collect();
 
-        let measured_pages_digest = memory_protector.measure()?;
+        let mut measured_pages_digest = memory_protector.measure()?;
+        lazy_pages.measure(&mut measured_pages_digest);
         let confidential_hart_digest = confidential_harts[Self::BOOT_HART_ID].measure();
         let measurements = StaticMeasurements::new(measured_pages_digest, confidential_hart_digest);
         debug!("VM measurements: {:?}", measurements);
 
         self.authenticate_and_authorize_vm(&memory_protector, &measurements)?;
 
+        // The measurements depend on the size of the mapped pages, so we map large pages only once the VM is measured.
+        memory_protector.coalesce_pages();
+
         ControlDataStorage::try_write(|control_data| {
             // We have a write lock on the entire control data! Spend here as little time as possible because we are
             // blocking all other harts from accessing the control data. This influences all confidential VMs in the system!
@@ -123,6 +159,7 @@ impl PromoteToConfidentialVm {
                 confidential_harts,
                 measurements,
                 memory_protector,
+                lazy_pages,
             ))
         })
     }
@@ -160,12 +197,19 @@ impl PromoteToConfidentialVm {
         // memory. See the safety requirements of `FlattenedDeviceTree::from_raw_pointer`.
         let number_of_confidential_harts =
             match unsafe { FlattenedDeviceTree::from_raw_pointer(large_page.address().to_ptr()) } {
-                Ok(device_tree) => device_tree.harts().count(),
-                Err(_) => 0,
+                Ok(device_tree) => PromotionTemplate::validate_device_tree(
+                    &device_tree,
+                    &self.fdt_address,
+                    fdt_total_size,
+                    self.program_counter,
+                )
+                .map(|_| device_tree.harts().count()),
+                Err(_) => Ok(0),
             };
 
         // Clean up, deallocate pages
         PageAllocator::release_pages(alloc::vec![large_page.deallocate()]);
+        let number_of_confidential_harts = number_of_confidential_harts?;
 
         ensure!(
             number_of_confidential_harts > 0,
@@ -0,0 +1,70 @@
+// SPDX-FileCopyrightText: 2023 IBM Corporation
+// SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
+// SPDX-License-Identifier: Apache-2.0
+use crate::ace::core::architecture::GeneralPurposeRegister;
+use crate::ace::core::control_data::{
+    AiaParams, ConfidentialVmId, ControlDataStorage, HypervisorHart,
+};
+use crate::ace::core::memory_layout::NonConfidentialMemoryAddress;
+use crate::ace::error::Error;
+use crate::ace::non_confidential_flow::handlers::supervisor_binary_interface::SbiResponse;
+use crate::ace::non_confidential_flow::{ApplyToHypervisorHart, NonConfidentialFlow};
+use crate::ensure;
+
+/// This handler implements the `TVM AIA Init` function of the CoVE Interrupt ABI.
+///
+/// Configures the layout of the confidential VM's virtual IMSICs. Returns error if the parameters are not in the non-confidential memory,
+/// are invalid, or if the AIA of the confidential VM has been already initialized.
+pub struct AiaInit {
+    confidential_vm_id: ConfidentialVmId,
+    params_address: usize,
+    params_len: usize,
+}
+
+impl AiaInit {
+    pub fn from_hypervisor_hart(hypervisor_hart: &HypervisorHart) -> Self {
+        Self {
+            confidential_vm_id: ConfidentialVmId::new(
+                hypervisor_hart.gprs().read(GeneralPurposeRegister::a0),
+            ),
+            params_address: hypervisor_hart.gprs().read(GeneralPurposeRegister::a1),
+            params_len: hypervisor_hart.gprs().read(GeneralPurposeRegister::a2),
+        }
+    }
+
+    pub fn handle(self, non_confidential_flow: NonConfidentialFlow) -> ! {
+        let sbi_response = self
+            .read_params()
+            .and_then(|params| {
+                ControlDataStorage::try_confidential_vm(
+                    self.confidential_vm_id,
+                    |mut confidential_vm| confidential_vm.init_aia(params),
+                )
+            })
+            .map_or_else(
+                |error| SbiResponse::error(error),
+                |_| SbiResponse::success(),
+            );
+        non_confidential_flow
+            .apply_and_exit_to_hypervisor(ApplyToHypervisorHart::SbiResponse(sbi_response))
+    }
+
+    fn read_params(&self) -> Result<AiaParams, Error> {
+        ensure!(
+            self.params_len >= AiaParams::SIZE_IN_BYTES,
+            Error::InvalidParameter()
+        )?;
+        ensure!(
+            self.params_address % core::mem::size_of::<usize>() == 0,
+            Error::AddressNotAligned()
+        )?;
+        let mut words = [0; 4];
+        for (index, word) in words.iter_mut().enumerate() {
+            let address = self.params_address + index * core::mem::size_of::<usize>();
+            let pointer = NonConfidentialMemoryAddress::new(address as *mut usize)?;
+            // below unsafe operation is ok because the pointer is an aligned address in the non-confidential memory.
+            *word = unsafe { pointer.read() };
+        }
+        AiaParams::from_words(words)
+    }
+}
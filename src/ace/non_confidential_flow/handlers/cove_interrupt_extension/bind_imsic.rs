@@ -0,0 +1,44 @@
+// SPDX-FileCopyrightText: 2023 IBM Corporation
+// SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
+// SPDX-License-Identifier: Apache-2.0
+use crate::ace::core::architecture::GeneralPurposeRegister;
+use crate::ace::core::control_data::{ConfidentialVmId, ControlDataStorage, HypervisorHart};
+use crate::ace::non_confidential_flow::handlers::supervisor_binary_interface::SbiResponse;
+use crate::ace::non_confidential_flow::{ApplyToHypervisorHart, NonConfidentialFlow};
+
+/// This handler implements the `TVM CPU Bind IMSIC` function of the CoVE Interrupt ABI.
+///
+/// Binds the confidential hart to the guest interrupt files of the physical hart executing this call. Once the AIA of the confidential VM
+/// is initialized, the hypervisor can only run a confidential hart on the physical hart it is bound to.
+pub struct BindImsic {
+    confidential_vm_id: ConfidentialVmId,
+    confidential_hart_id: usize,
+    guest_interrupt_files: usize,
+}
+
+impl BindImsic {
+    pub fn from_hypervisor_hart(hypervisor_hart: &HypervisorHart) -> Self {
+        Self {
+            confidential_vm_id: ConfidentialVmId::new(
+                hypervisor_hart.gprs().read(GeneralPurposeRegister::a0),
+            ),
+            confidential_hart_id: hypervisor_hart.gprs().read(GeneralPurposeRegister::a1),
+            guest_interrupt_files: hypervisor_hart.gprs().read(GeneralPurposeRegister::a2),
+        }
+    }
+
+    pub fn handle(self, non_confidential_flow: NonConfidentialFlow) -> ! {
+        let sbi_response = ControlDataStorage::try_confidential_vm(
+            self.confidential_vm_id,
+            |mut confidential_vm| {
+                confidential_vm.bind_imsic(self.confidential_hart_id, self.guest_interrupt_files)
+            },
+        )
+        .map_or_else(
+            |error| SbiResponse::error(error),
+            |_| SbiResponse::success(),
+        );
+        non_confidential_flow
+            .apply_and_exit_to_hypervisor(ApplyToHypervisorHart::SbiResponse(sbi_response))
+    }
+}
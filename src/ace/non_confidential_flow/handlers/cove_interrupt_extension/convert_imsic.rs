@@ -0,0 +1,68 @@
+// SPDX-FileCopyrightText: 2023 IBM Corporation
+// SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
+// SPDX-License-Identifier: Apache-2.0
+use crate::ace::core::architecture::{GeneralPurposeRegister, PageSize};
+use crate::ace::core::control_data::HypervisorHart;
+use crate::ace::core::memory_layout::NonConfidentialMemoryAddress;
+use crate::ace::error::Error;
+use crate::ace::non_confidential_flow::handlers::supervisor_binary_interface::SbiResponse;
+use crate::ace::non_confidential_flow::{ApplyToHypervisorHart, NonConfidentialFlow};
+use crate::ensure;
+
+/// This handler implements the `TVM Convert IMSIC` function of the CoVE Interrupt ABI.
+///
+/// The security monitor delivers external interrupts to confidential harts through software interrupt files, the hardware guest
+/// interrupt files stay under the control of the hypervisor and are never used by confidential harts. Converting an interrupt file is thus
+/// accepted as long as the address is a valid interrupt file address.
+pub struct ConvertImsic {
+    imsic_address: usize,
+}
+
+impl ConvertImsic {
+    pub fn from_hypervisor_hart(hypervisor_hart: &HypervisorHart) -> Self {
+        Self {
+            imsic_address: hypervisor_hart.gprs().read(GeneralPurposeRegister::a0),
+        }
+    }
+
+    pub fn handle(self, non_confidential_flow: NonConfidentialFlow) -> ! {
+        let sbi_response = validate_imsic_address(self.imsic_address).map_or_else(
+            |error| SbiResponse::error(error),
+            |_| SbiResponse::success(),
+        );
+        non_confidential_flow
+            .apply_and_exit_to_hypervisor(ApplyToHypervisorHart::SbiResponse(sbi_response))
+    }
+}
+
+/// This handler implements the `TVM Reclaim IMSIC` function of the CoVE Interrupt ABI, which reverts the `TVM Convert IMSIC` call.
+pub struct ReclaimImsic {
+    imsic_address: usize,
+}
+
+impl ReclaimImsic {
+    pub fn from_hypervisor_hart(hypervisor_hart: &HypervisorHart) -> Self {
+        Self {
+            imsic_address: hypervisor_hart.gprs().read(GeneralPurposeRegister::a0),
+        }
+    }
+
+    pub fn handle(self, non_confidential_flow: NonConfidentialFlow) -> ! {
+        let sbi_response = validate_imsic_address(self.imsic_address).map_or_else(
+            |error| SbiResponse::error(error),
+            |_| SbiResponse::success(),
+        );
+        non_confidential_flow
+            .apply_and_exit_to_hypervisor(ApplyToHypervisorHart::SbiResponse(sbi_response))
+    }
+}
+
+/// Interrupt files are page-sized and never located in the confidential memory.
+fn validate_imsic_address(imsic_address: usize) -> Result<(), Error> {
+    ensure!(
+        imsic_address % PageSize::Size4KiB.in_bytes() == 0,
+        Error::AddressNotAligned()
+    )?;
+    NonConfidentialMemoryAddress::new(imsic_address as *mut usize)?;
+    Ok(())
+}
@@ -0,0 +1,82 @@
+// SPDX-FileCopyrightText: 2023 IBM Corporation
+// SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
+// SPDX-License-Identifier: Apache-2.0
+use crate::ace::core::architecture::GeneralPurposeRegister;
+use crate::ace::core::control_data::{
+    ConfidentialHart, ConfidentialHartRemoteCommandExecutable, ConfidentialVmId,
+    ControlDataStorage, HypervisorHart,
+};
+use crate::ace::non_confidential_flow::handlers::supervisor_binary_interface::SbiResponse;
+use crate::ace::non_confidential_flow::{ApplyToHypervisorHart, NonConfidentialFlow};
+use crate::debug;
+
+/// This handler implements the `TVM CPU Inject External Interrupt` function of the CoVE Interrupt ABI.
+///
+/// The interrupt is set pending in the software interrupt file of the confidential hart, which then signals a virtual supervisor external
+/// interrupt. Returns error if the AIA of the confidential VM is not initialized or if the confidential VM did not allow the injection of
+/// external interrupts.
+#[derive(Clone)]
+pub struct InjectExternalInterrupt {
+    confidential_vm_id: ConfidentialVmId,
+    confidential_hart_id: usize,
+    interrupt_id: usize,
+}
+
+impl InjectExternalInterrupt {
+    pub fn new(
+        confidential_vm_id: ConfidentialVmId,
+        confidential_hart_id: usize,
+        interrupt_id: usize,
+    ) -> Self {
+        Self {
+            confidential_vm_id,
+            confidential_hart_id,
+            interrupt_id,
+        }
+    }
+
+    pub fn from_hypervisor_hart(hypervisor_hart: &HypervisorHart) -> Self {
+        Self::new(
+            ConfidentialVmId::new(hypervisor_hart.gprs().read(GeneralPurposeRegister::a0)),
+            hypervisor_hart.gprs().read(GeneralPurposeRegister::a1),
+            hypervisor_hart.gprs().read(GeneralPurposeRegister::a2),
+        )
+    }
+
+    pub fn handle(self, non_confidential_flow: NonConfidentialFlow) -> ! {
+        let confidential_vm_id = self.confidential_vm_id;
+        let sbi_response =
+            ControlDataStorage::try_confidential_vm(confidential_vm_id, |mut confidential_vm| {
+                confidential_vm.inject_external_interrupt(self)
+            })
+            .map_or_else(
+                |error| SbiResponse::error(error),
+                |_| SbiResponse::success(),
+            );
+        non_confidential_flow
+            .apply_and_exit_to_hypervisor(ApplyToHypervisorHart::SbiResponse(sbi_response))
+    }
+
+    pub fn confidential_hart_id(&self) -> usize {
+        self.confidential_hart_id
+    }
+
+    pub fn interrupt_id(&self) -> usize {
+        self.interrupt_id
+    }
+}
+
+impl ConfidentialHartRemoteCommandExecutable for InjectExternalInterrupt {
+    fn execute_on_confidential_hart(&self, confidential_hart: &mut ConfidentialHart) {
+        if let Err(error) = confidential_hart
+            .interrupt_file_mut()
+            .set_pending(self.interrupt_id)
+        {
+            debug!("Could not inject external interrupt: {:?}", error);
+        }
+    }
+
+    fn is_hart_selected(&self, confidential_hart_id: usize) -> bool {
+        self.confidential_hart_id == confidential_hart_id
+    }
+}
@@ -0,0 +1,20 @@
+// SPDX-FileCopyrightText: 2023 IBM Corporation
+// SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module implements a subset of the CoVE's COVI ABI, which allows the hypervisor to configure the Advanced Interrupt Architecture
+//! (AIA) of a confidential VM and to inject external interrupts into its confidential harts.
+
+pub use aia_init::AiaInit;
+pub use bind_imsic::BindImsic;
+pub use convert_imsic::{ConvertImsic, ReclaimImsic};
+pub use inject_external_interrupt::InjectExternalInterrupt;
+pub use set_imsic_address::SetImsicAddress;
+pub use unbind_imsic::{UnbindImsicBegin, UnbindImsicEnd};
+
+mod aia_init;
+mod bind_imsic;
+mod convert_imsic;
+mod inject_external_interrupt;
+mod set_imsic_address;
+mod unbind_imsic;
@@ -0,0 +1,69 @@
+// SPDX-FileCopyrightText: 2023 IBM Corporation
+// SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
+// SPDX-License-Identifier: Apache-2.0
+use crate::ace::core::architecture::GeneralPurposeRegister;
+use crate::ace::core::control_data::{ConfidentialVmId, ControlDataStorage, HypervisorHart};
+use crate::ace::non_confidential_flow::handlers::supervisor_binary_interface::SbiResponse;
+use crate::ace::non_confidential_flow::{ApplyToHypervisorHart, NonConfidentialFlow};
+
+/// This handler implements the `TVM CPU Unbind IMSIC Begin` function of the CoVE Interrupt ABI. The confidential hart cannot run
+/// until it is bound again.
+pub struct UnbindImsicBegin {
+    confidential_vm_id: ConfidentialVmId,
+    confidential_hart_id: usize,
+}
+
+impl UnbindImsicBegin {
+    pub fn from_hypervisor_hart(hypervisor_hart: &HypervisorHart) -> Self {
+        Self {
+            confidential_vm_id: ConfidentialVmId::new(
+                hypervisor_hart.gprs().read(GeneralPurposeRegister::a0),
+            ),
+            confidential_hart_id: hypervisor_hart.gprs().read(GeneralPurposeRegister::a1),
+        }
+    }
+
+    pub fn handle(self, non_confidential_flow: NonConfidentialFlow) -> ! {
+        let sbi_response = ControlDataStorage::try_confidential_vm(
+            self.confidential_vm_id,
+            |mut confidential_vm| confidential_vm.unbind_imsic_begin(self.confidential_hart_id),
+        )
+        .map_or_else(
+            |error| SbiResponse::error(error),
+            |_| SbiResponse::success(),
+        );
+        non_confidential_flow
+            .apply_and_exit_to_hypervisor(ApplyToHypervisorHart::SbiResponse(sbi_response))
+    }
+}
+
+/// This handler implements the `TVM CPU Unbind IMSIC End` function of the CoVE Interrupt ABI. Once it succeeds, the hypervisor can bind
+/// the confidential hart to another physical hart.
+pub struct UnbindImsicEnd {
+    confidential_vm_id: ConfidentialVmId,
+    confidential_hart_id: usize,
+}
+
+impl UnbindImsicEnd {
+    pub fn from_hypervisor_hart(hypervisor_hart: &HypervisorHart) -> Self {
+        Self {
+            confidential_vm_id: ConfidentialVmId::new(
+                hypervisor_hart.gprs().read(GeneralPurposeRegister::a0),
+            ),
+            confidential_hart_id: hypervisor_hart.gprs().read(GeneralPurposeRegister::a1),
+        }
+    }
+
+    pub fn handle(self, non_confidential_flow: NonConfidentialFlow) -> ! {
+        let sbi_response = ControlDataStorage::try_confidential_vm(
+            self.confidential_vm_id,
+            |mut confidential_vm| confidential_vm.unbind_imsic_end(self.confidential_hart_id),
+        )
+        .map_or_else(
+            |error| SbiResponse::error(error),
+            |_| SbiResponse::success(),
+        );
+        non_confidential_flow
+            .apply_and_exit_to_hypervisor(ApplyToHypervisorHart::SbiResponse(sbi_response))
+    }
+}
@@ -2,6 +2,7 @@
 // SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
 // SPDX-License-Identifier: Apache-2.0
 pub mod cove_hypervisor_extension;
+pub mod cove_interrupt_extension;
 pub mod nested_acceleration_extension;
 pub mod opensbi;
 pub mod supervisor_binary_interface;
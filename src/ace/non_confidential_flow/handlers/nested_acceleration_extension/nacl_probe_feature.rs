@@ -1,6 +1,7 @@
 // SPDX-FileCopyrightText: 2023 IBM Corporation
 // SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
 // SPDX-License-Identifier: Apache-2.0
+use crate::ace::core::architecture::riscv::sbi::NaclExtension;
 use crate::ace::core::architecture::GeneralPurposeRegister;
 use crate::ace::core::control_data::HypervisorHart;
 use crate::ace::non_confidential_flow::handlers::supervisor_binary_interface::SbiResponse;
@@ -8,20 +9,27 @@ use crate::ace::non_confidential_flow::{ApplyToHypervisorHart, NonConfidentialFl
 
 /// Returns information on supported nested acceleration (NACL) features that security monitor implements.
 pub struct NaclProbeFeature {
-    _feature_id: usize,
+    feature_id: usize,
 }
 
 impl NaclProbeFeature {
     const FEATURE_NOT_AVAILABLE: usize = 0;
+    const FEATURE_AVAILABLE: usize = 1;
 
     pub fn from_hypervisor_hart(hypervisor_hart: &HypervisorHart) -> Self {
         Self {
-            _feature_id: hypervisor_hart.gprs().read(GeneralPurposeRegister::a0),
+            feature_id: hypervisor_hart.gprs().read(GeneralPurposeRegister::a0),
         }
     }
 
     pub fn handle(self, non_confidential_flow: NonConfidentialFlow) -> ! {
-        let response = SbiResponse::success_with_code(Self::FEATURE_NOT_AVAILABLE);
+        let available = self.feature_id < usize::BITS as usize
+            && NaclExtension::SUPPORTED_FEATURES & (1 << self.feature_id) != 0;
+        let code = match available {
+            true => Self::FEATURE_AVAILABLE,
+            false => Self::FEATURE_NOT_AVAILABLE,
+        };
+        let response = SbiResponse::success_with_code(code);
         non_confidential_flow
             .apply_and_exit_to_hypervisor(ApplyToHypervisorHart::SbiResponse(response))
     }
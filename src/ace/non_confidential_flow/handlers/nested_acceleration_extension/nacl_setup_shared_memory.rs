@@ -1,6 +1,7 @@
 // SPDX-FileCopyrightText: 2023 IBM Corporation
 // SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
 // SPDX-License-Identifier: Apache-2.0
+use crate::ace::core::architecture::riscv::sbi::NaclExtension;
 use crate::ace::core::architecture::GeneralPurposeRegister;
 use crate::ace::core::control_data::HypervisorHart;
 use crate::ace::core::memory_layout::NonConfidentialMemoryAddress;
@@ -26,6 +27,13 @@ impl NaclSetupSharedMemory {
     }
 
     pub fn apply_to_hypervisor_hart(&self, hypervisor_hart: &mut HypervisorHart) {
+        if self.shared_memory_base_address == NaclExtension::SHMEM_DISABLE {
+            debug!("Unregistering NACL shared memory");
+            hypervisor_hart.teardown_shared_memory();
+            SbiResponse::success().apply_to_hypervisor_hart(hypervisor_hart);
+            return;
+        }
+
         debug!(
             "Registering NACL shared memory at {:x}",
             self.shared_memory_base_address
@@ -2,6 +2,8 @@
 // SPDX-FileContributor: Wojciech Ozga <woz@zurich.ibm.com>, IBM Research - Zurich
 // SPDX-License-Identifier: Apache-2.0
 
+// The context switches only exist on the hardware, see `architecture::riscv::userspace` for the host.
+#[cfg(not(feature = "userspace"))]
 core::arch::global_asm!(
     core::include_str!("enter_from_hypervisor_or_vm.S"),
     core::include_str!("exit_to_hypervisor.S"),
@@ -0,0 +1,143 @@
+//! CSR Bitfields
+//!
+//! The fields of the CSRs are declared with the [csr_fields] macro, which derives the masks from
+//! the position of the fields. Each field is exposed both as a [Field], with getters and setters,
+//! and as the `<NAME>_OFFSET` and `<NAME>_FILTER` constants used throughout Miralis:
+//!
+//! ```ignore
+//! csr_fields! {
+//!     /// Supervisor Interrupt Enable
+//!     SIE(SIE_OFFSET, SIE_FILTER) = bit 1;
+//!     /// Vector extension state
+//!     VS(VS_OFFSET, VS_FILTER) = bits 9..=10;
+//! }
+//! ```
+//!
+//! Masks are never written by hand, which avoids the copy-paste errors of offsets and filters
+//! getting out of sync.
+
+/// A field of a CSR.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Field {
+    /// Position of the least significant bit of the field.
+    pub offset: usize,
+    /// Number of bits of the field.
+    pub width: usize,
+}
+
+impl Field {
+    pub const fn new(offset: usize, width: usize) -> Self {
+        assert!(
+            width > 0 && offset + width <= usize::BITS as usize,
+            "Invalid CSR field"
+        );
+        Field { offset, width }
+    }
+
+    /// Returns the mask of the field within the CSR.
+    pub const fn filter(self) -> usize {
+        (usize::MAX >> (usize::BITS as usize - self.width)) << self.offset
+    }
+
+    /// Returns the value of the field.
+    pub const fn get(self, csr: usize) -> usize {
+        (csr & self.filter()) >> self.offset
+    }
+
+    /// Returns true if any bit of the field is set.
+    pub const fn is_set(self, csr: usize) -> bool {
+        csr & self.filter() != 0
+    }
+
+    /// Returns the CSR with the field set to `value`, which must fit in the field.
+    pub const fn set(self, csr: usize, value: usize) -> usize {
+        debug_assert!(
+            value & !(self.filter() >> self.offset) == 0,
+            "Value does not fit in the CSR field"
+        );
+        (csr & !self.filter()) | (value << self.offset)
+    }
+
+    /// Legalizes a WARL field: returns the CSR with the field set to `fallback` if its value is
+    /// not legal.
+    pub fn legalize(self, csr: usize, fallback: usize, is_legal: impl Fn(usize) -> bool) -> usize {
+        if is_legal(self.get(csr)) {
+            csr
+        } else {
+            self.set(csr, fallback)
+        }
+    }
+}
+
+/// Declares a single CSR field, see [csr_fields].
+macro_rules! csr_field {
+    (
+        $(#[$attr:meta])*
+        $name:ident, $offset_name:ident, $filter_name:ident, $offset:literal, $width:expr
+    ) => {
+        $(#[$attr])*
+        pub const $name: $crate::arch::Field = $crate::arch::Field::new($offset, $width);
+        pub const $offset_name: usize = $name.offset;
+        pub const $filter_name: usize = $name.filter();
+    };
+}
+
+/// Declares the fields of a CSR, either single bits (`bit N`) or ranges of bits (`bits N..=M`).
+macro_rules! csr_fields {
+    () => {};
+    (
+        $(#[$attr:meta])*
+        $name:ident($offset_name:ident, $filter_name:ident) = bit $offset:literal;
+        $($rest:tt)*
+    ) => {
+        $crate::arch::bitfield::csr_field!(
+            $(#[$attr])* $name, $offset_name, $filter_name, $offset, 1
+        );
+        $crate::arch::bitfield::csr_fields!($($rest)*);
+    };
+    (
+        $(#[$attr:meta])*
+        $name:ident($offset_name:ident, $filter_name:ident) = bits $start:literal..=$end:literal;
+        $($rest:tt)*
+    ) => {
+        $crate::arch::bitfield::csr_field!(
+            $(#[$attr])* $name, $offset_name, $filter_name, $start, $end - $start + 1
+        );
+        $crate::arch::bitfield::csr_fields!($($rest)*);
+    };
+}
+
+pub(crate) use csr_field;
+pub(crate) use csr_fields;
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::{hstatus, mie, mstatus};
+
+    #[test]
+    fn csr_fields() {
+        // The masks match the specification
+        assert_eq!(mstatus::SSTATUS_FILTER, 0x8000_0003_000d_e762);
+        assert_eq!(mstatus::MPP_FILTER, 0b11 << 11);
+        assert_eq!(mstatus::MPV_FILTER, 1 << 39);
+        assert_eq!(mie::LCOFIE_FILTER, 1 << 13);
+        assert_eq!(hstatus::VSXL_FILTER, 0b11 << 32);
+        assert_eq!(hstatus::VTW_OFFSET, 21);
+
+        let mstatus = mstatus::MPP.set(mstatus::SIE_FILTER, 0b11);
+        assert_eq!(mstatus::MPP.get(mstatus), 0b11);
+        assert!(mstatus::SIE.is_set(mstatus));
+        assert!(!mstatus::SPIE.is_set(mstatus));
+        assert_eq!(mstatus::MPP.set(mstatus, 0), mstatus::SIE_FILTER);
+
+        // MPP is WARL, 2 is not a legal mode
+        let illegal = mstatus::MPP.set(0, 2);
+        assert_eq!(mstatus::MPP.legalize(illegal, 0, |mpp| mpp != 2), 0);
+        assert_eq!(mstatus::MPP.legalize(mstatus, 0, |mpp| mpp != 2), mstatus);
+
+        assert_eq!(Field::new(0, 64).filter(), usize::MAX);
+    }
+}
@@ -0,0 +1,511 @@
+//! RISC-V Interpreter
+//!
+//! The userspace architecture can not run the guest natively, it interprets its instructions
+//! instead. The interpreter supports the RV64IMA instructions, which is enough for the guest code
+//! to compute, access memory, and synchronize, and executes them until the guest traps. As on the
+//! hardware, privileged instructions trap to Miralis for emulation, and the ecalls and breakpoints
+//! are reported with their usual causes.
+//!
+//! The guest addresses are host addresses: the guest code and data are regular host buffers, and
+//! memory accesses are not checked against the PMP. Atomic instructions are executed with the
+//! atomics of the host. Compressed and floating point instructions are not supported and trap as
+//! illegal instructions.
+
+use core::ptr;
+use core::sync::atomic::{AtomicI32, AtomicI64, AtomicU32, AtomicU64, Ordering};
+
+use crate::arch::{MCause, Mode, Width};
+use crate::utils::extend_load;
+use crate::virt::VirtContext;
+
+const LUI: usize = 0b0110111;
+const AUIPC: usize = 0b0010111;
+const JAL: usize = 0b1101111;
+const JALR: usize = 0b1100111;
+const BRANCH: usize = 0b1100011;
+const LOAD: usize = 0b0000011;
+const STORE: usize = 0b0100011;
+const OP_IMM: usize = 0b0010011;
+const OP: usize = 0b0110011;
+const OP_IMM_32: usize = 0b0011011;
+const OP_32: usize = 0b0111011;
+const MISC_MEM: usize = 0b0001111;
+const AMO: usize = 0b0101111;
+const SYSTEM: usize = 0b1110011;
+
+const ECALL: usize = 0x00000073;
+const EBREAK: usize = 0x00100073;
+
+/// Funct7 of the M extension instructions.
+const MULDIV: usize = 0b0000001;
+/// Funct7 of the alternate arithmetic instructions (`sub` and `sra`).
+const ALT: usize = 0b0100000;
+
+// Funct5 of the A extension instructions
+const LR: usize = 0b00010;
+const SC: usize = 0b00011;
+const AMOSWAP: usize = 0b00001;
+const AMOADD: usize = 0b00000;
+const AMOXOR: usize = 0b00100;
+const AMOAND: usize = 0b01100;
+const AMOOR: usize = 0b01000;
+const AMOMIN: usize = 0b10000;
+const AMOMAX: usize = 0b10100;
+const AMOMINU: usize = 0b11000;
+const AMOMAXU: usize = 0b11100;
+
+/// A trap raised by the guest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trap {
+    pub cause: MCause,
+    pub tval: usize,
+}
+
+impl Trap {
+    fn new(cause: MCause, tval: usize) -> Self {
+        Trap { cause, tval }
+    }
+}
+
+/// The reservation of a load-reserved instruction, as the address and the value read.
+///
+/// Traps invalidate the reservation, which is allowed by the specification: the guest must be
+/// able to handle store-conditionals failing.
+#[derive(Debug, Default)]
+pub struct Reservation(Option<(usize, usize)>);
+
+/// Executes the guest from its pc until it traps, the pc is left on the trapping instruction.
+///
+/// # Safety
+///
+/// The guest code and all the memory it accesses must be valid host memory.
+pub unsafe fn run(ctx: &mut VirtContext) -> Trap {
+    let mut reservation = Reservation::default();
+    loop {
+        if let Err(trap) = step(ctx, &mut reservation) {
+            return trap;
+        }
+    }
+}
+
+/// Executes a single instruction of the guest.
+///
+/// # Safety
+///
+/// See [run].
+pub unsafe fn step(ctx: &mut VirtContext, reservation: &mut Reservation) -> Result<(), Trap> {
+    if ctx.pc & 0b11 != 0 {
+        return Err(Trap::new(MCause::InstrAddrMisaligned, ctx.pc));
+    }
+    let raw = ptr::read(ctx.pc as *const u32) as usize;
+    let illegal = Trap::new(MCause::IllegalInstr, raw);
+
+    let rd = (raw >> 7) & 0b11111;
+    let funct3 = (raw >> 12) & 0b111;
+    let rs1 = reg(ctx, (raw >> 15) & 0b11111);
+    let rs2 = reg(ctx, (raw >> 20) & 0b11111);
+    let funct7 = raw >> 25;
+    let imm_i = (raw as i32 >> 20) as usize;
+
+    let mut next_pc = ctx.pc.wrapping_add(4);
+    match raw & 0b1111111 {
+        LUI => set_reg(ctx, rd, imm_u(raw)),
+        AUIPC => set_reg(ctx, rd, ctx.pc.wrapping_add(imm_u(raw))),
+        JAL => {
+            let target = ctx.pc.wrapping_add(imm_j(raw));
+            jump(target)?;
+            set_reg(ctx, rd, next_pc);
+            next_pc = target;
+        }
+        JALR if funct3 == 0 => {
+            let target = rs1.wrapping_add(imm_i) & !1;
+            jump(target)?;
+            set_reg(ctx, rd, next_pc);
+            next_pc = target;
+        }
+        BRANCH => {
+            let taken = match funct3 {
+                0b000 => rs1 == rs2,
+                0b001 => rs1 != rs2,
+                0b100 => (rs1 as isize) < (rs2 as isize),
+                0b101 => (rs1 as isize) >= (rs2 as isize),
+                0b110 => rs1 < rs2,
+                0b111 => rs1 >= rs2,
+                _ => return Err(illegal),
+            };
+            if taken {
+                next_pc = ctx.pc.wrapping_add(imm_b(raw));
+                jump(next_pc)?;
+            }
+        }
+        LOAD => {
+            let addr = rs1.wrapping_add(imm_i);
+            let (width, is_unsigned) = match funct3 {
+                0b000 => (Width::Byte, false),
+                0b001 => (Width::Byte2, false),
+                0b010 => (Width::Byte4, false),
+                0b011 => (Width::Byte8, false),
+                0b100 => (Width::Byte, true),
+                0b101 => (Width::Byte2, true),
+                0b110 => (Width::Byte4, true),
+                _ => return Err(illegal),
+            };
+            let value = match width {
+                Width::Byte => ptr::read(addr as *const u8) as usize,
+                Width::Byte2 => ptr::read_unaligned(addr as *const u16) as usize,
+                Width::Byte4 => ptr::read_unaligned(addr as *const u32) as usize,
+                Width::Byte8 => ptr::read_unaligned(addr as *const u64) as usize,
+            };
+            set_reg(ctx, rd, extend_load(value, width, is_unsigned));
+        }
+        STORE => {
+            let imm_s = (((raw as i32 >> 25) << 5) as usize) | ((raw >> 7) & 0b11111);
+            let addr = rs1.wrapping_add(imm_s);
+            match funct3 {
+                0b000 => ptr::write(addr as *mut u8, rs2 as u8),
+                0b001 => ptr::write_unaligned(addr as *mut u16, rs2 as u16),
+                0b010 => ptr::write_unaligned(addr as *mut u32, rs2 as u32),
+                0b011 => ptr::write_unaligned(addr as *mut u64, rs2 as u64),
+                _ => return Err(illegal),
+            }
+        }
+        OP_IMM => {
+            let shamt = (imm_i & 0b111111) as u32;
+            let value = match (funct3, (imm_i >> 6) & 0b111111) {
+                (0b000, _) => rs1.wrapping_add(imm_i),
+                (0b001, 0) => rs1 << shamt,
+                (0b010, _) => ((rs1 as isize) < (imm_i as isize)) as usize,
+                (0b011, _) => (rs1 < imm_i) as usize,
+                (0b100, _) => rs1 ^ imm_i,
+                (0b101, 0) => rs1 >> shamt,
+                (0b101, 0b010000) => ((rs1 as isize) >> shamt) as usize,
+                (0b110, _) => rs1 | imm_i,
+                (0b111, _) => rs1 & imm_i,
+                _ => return Err(illegal),
+            };
+            set_reg(ctx, rd, value);
+        }
+        OP => {
+            let value = match funct7 {
+                MULDIV => muldiv(funct3, rs1, rs2),
+                _ => alu(funct3, funct7, rs1, rs2),
+            };
+            set_reg(ctx, rd, value.ok_or(illegal)?);
+        }
+        OP_IMM_32 => {
+            let (rs1, shamt) = (rs1 as u32, (imm_i & 0b11111) as u32);
+            let value = match (funct3, funct7) {
+                (0b000, _) => rs1.wrapping_add(imm_i as u32),
+                (0b001, 0) => rs1 << shamt,
+                (0b101, 0) => rs1 >> shamt,
+                (0b101, ALT) => ((rs1 as i32) >> shamt) as u32,
+                _ => return Err(illegal),
+            };
+            set_reg(ctx, rd, value as i32 as usize);
+        }
+        OP_32 => {
+            let value = match funct7 {
+                MULDIV => muldiv_32(funct3, rs1 as u32, rs2 as u32),
+                _ => alu_32(funct3, funct7, rs1 as u32, rs2 as u32),
+            };
+            set_reg(ctx, rd, value.ok_or(illegal)? as i32 as usize);
+        }
+        AMO => {
+            let value = atomic(raw, rs1, rs2, reservation)?;
+            set_reg(ctx, rd, value);
+        }
+        // The interpreter is sequentially consistent
+        MISC_MEM if funct3 <= 0b001 => {}
+        SYSTEM if raw == ECALL => {
+            let cause = match physical_mode(ctx) {
+                Mode::S => MCause::EcallFromSMode,
+                _ => MCause::EcallFromUMode,
+            };
+            return Err(Trap::new(cause, 0));
+        }
+        SYSTEM if raw == EBREAK => return Err(Trap::new(MCause::Breakpoint, ctx.pc)),
+        // Privileged instructions are emulated by Miralis
+        _ => return Err(illegal),
+    }
+
+    ctx.pc = next_pc;
+    Ok(())
+}
+
+/// Returns the mode in which the hardware would run the guest, the firmware runs in U-mode.
+pub fn physical_mode(ctx: &VirtContext) -> Mode {
+    match ctx.mode {
+        Mode::M => Mode::U,
+        mode => mode,
+    }
+}
+
+// ————————————————————————————— Instructions ——————————————————————————————— //
+
+fn reg(ctx: &VirtContext, idx: usize) -> usize {
+    ctx.regs[idx]
+}
+
+fn set_reg(ctx: &mut VirtContext, idx: usize, value: usize) {
+    // x0 is hardwired to zero
+    if idx != 0 {
+        ctx.regs[idx] = value;
+    }
+}
+
+/// Checks the alignment of a jump target.
+fn jump(target: usize) -> Result<(), Trap> {
+    if target & 0b11 != 0 {
+        return Err(Trap::new(MCause::InstrAddrMisaligned, target));
+    }
+    Ok(())
+}
+
+fn imm_u(raw: usize) -> usize {
+    (raw as i32 & !0xfff) as usize
+}
+
+fn imm_b(raw: usize) -> usize {
+    let sign = (raw as i32 >> 31) as usize;
+    (sign << 12)
+        | (((raw >> 7) & 0b1) << 11)
+        | (((raw >> 25) & 0b111111) << 5)
+        | (((raw >> 8) & 0b1111) << 1)
+}
+
+fn imm_j(raw: usize) -> usize {
+    let sign = (raw as i32 >> 31) as usize;
+    (sign << 20)
+        | (((raw >> 12) & 0xff) << 12)
+        | (((raw >> 20) & 0b1) << 11)
+        | (((raw >> 21) & 0x3ff) << 1)
+}
+
+fn alu(funct3: usize, funct7: usize, rs1: usize, rs2: usize) -> Option<usize> {
+    let shamt = (rs2 & 0b111111) as u32;
+    let value = match (funct3, funct7) {
+        (0b000, 0) => rs1.wrapping_add(rs2),
+        (0b000, ALT) => rs1.wrapping_sub(rs2),
+        (0b001, 0) => rs1 << shamt,
+        (0b010, 0) => ((rs1 as isize) < (rs2 as isize)) as usize,
+        (0b011, 0) => (rs1 < rs2) as usize,
+        (0b100, 0) => rs1 ^ rs2,
+        (0b101, 0) => rs1 >> shamt,
+        (0b101, ALT) => ((rs1 as isize) >> shamt) as usize,
+        (0b110, 0) => rs1 | rs2,
+        (0b111, 0) => rs1 & rs2,
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn alu_32(funct3: usize, funct7: usize, rs1: u32, rs2: u32) -> Option<u32> {
+    let shamt = rs2 & 0b11111;
+    let value = match (funct3, funct7) {
+        (0b000, 0) => rs1.wrapping_add(rs2),
+        (0b000, ALT) => rs1.wrapping_sub(rs2),
+        (0b001, 0) => rs1 << shamt,
+        (0b101, 0) => rs1 >> shamt,
+        (0b101, ALT) => ((rs1 as i32) >> shamt) as u32,
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// The M extension, division by zero and overflows follow the RISC-V semantic.
+fn muldiv(funct3: usize, rs1: usize, rs2: usize) -> Option<usize> {
+    let (signed1, signed2) = (rs1 as i64 as i128, rs2 as i64 as i128);
+    let value = match funct3 {
+        0b000 => rs1.wrapping_mul(rs2),
+        0b001 => ((signed1 * signed2) >> 64) as usize,
+        0b010 => ((signed1 * rs2 as i128) >> 64) as usize,
+        0b011 => ((rs1 as u128 * rs2 as u128) >> 64) as usize,
+        0b100 if rs2 == 0 => usize::MAX,
+        0b100 => (rs1 as isize).wrapping_div(rs2 as isize) as usize,
+        0b101 => rs1.checked_div(rs2).unwrap_or(usize::MAX),
+        0b110 if rs2 == 0 => rs1,
+        0b110 => (rs1 as isize).wrapping_rem(rs2 as isize) as usize,
+        0b111 => rs1.checked_rem(rs2).unwrap_or(rs1),
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn muldiv_32(funct3: usize, rs1: u32, rs2: u32) -> Option<u32> {
+    let value = match funct3 {
+        0b000 => rs1.wrapping_mul(rs2),
+        0b100 if rs2 == 0 => u32::MAX,
+        0b100 => (rs1 as i32).wrapping_div(rs2 as i32) as u32,
+        0b101 => rs1.checked_div(rs2).unwrap_or(u32::MAX),
+        0b110 if rs2 == 0 => rs1,
+        0b110 => (rs1 as i32).wrapping_rem(rs2 as i32) as u32,
+        0b111 => rs1.checked_rem(rs2).unwrap_or(rs1),
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// Executes an instruction of the A extension, returns the value of rd.
+///
+/// A store-conditional succeeds if the memory still holds the value read by the load-reserved,
+/// which is checked with a compare-and-swap such that concurrent guests are supported.
+unsafe fn atomic(
+    raw: usize,
+    addr: usize,
+    src: usize,
+    reservation: &mut Reservation,
+) -> Result<usize, Trap> {
+    let funct5 = raw >> 27;
+    if funct5 == LR && (raw >> 20) & 0b11111 != 0 {
+        return Err(Trap::new(MCause::IllegalInstr, raw));
+    }
+
+    macro_rules! execute {
+        ($atomic:ty, $signed:ty, $int:ty, $sint:ty) => {{
+            if addr % core::mem::size_of::<$int>() != 0 {
+                let cause = match funct5 {
+                    LR => MCause::LoadAddrMisaligned,
+                    _ => MCause::StoreAddrMisaligned,
+                };
+                return Err(Trap::new(cause, addr));
+            }
+            let atomic = &*(addr as *const $atomic);
+            let signed = &*(addr as *const $signed);
+            let src = src as $int;
+            let order = Ordering::SeqCst;
+            let previous = match funct5 {
+                LR => {
+                    let value = atomic.load(order);
+                    reservation.0 = Some((addr, value as usize));
+                    value
+                }
+                SC => {
+                    let success = match reservation.0.take() {
+                        Some((reserved, value)) if reserved == addr => atomic
+                            .compare_exchange(value as $int, src, order, order)
+                            .is_ok(),
+                        _ => false,
+                    };
+                    return Ok(!success as usize);
+                }
+                AMOSWAP => atomic.swap(src, order),
+                AMOADD => atomic.fetch_add(src, order),
+                AMOXOR => atomic.fetch_xor(src, order),
+                AMOAND => atomic.fetch_and(src, order),
+                AMOOR => atomic.fetch_or(src, order),
+                AMOMIN => signed.fetch_min(src as $sint, order) as $int,
+                AMOMAX => signed.fetch_max(src as $sint, order) as $int,
+                AMOMINU => atomic.fetch_min(src, order),
+                AMOMAXU => atomic.fetch_max(src, order),
+                _ => return Err(Trap::new(MCause::IllegalInstr, raw)),
+            };
+            // Loaded values are sign-extended
+            previous as $sint as isize as usize
+        }};
+    }
+
+    let value = match (raw >> 12) & 0b111 {
+        0b010 => execute!(AtomicU32, AtomicI32, u32, i32),
+        0b011 => execute!(AtomicU64, AtomicI64, u64, i64),
+        _ => return Err(Trap::new(MCause::IllegalInstr, raw)),
+    };
+    Ok(value)
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::{Arch, Architecture};
+
+    fn new_ctx(code: &[u32]) -> VirtContext {
+        let hw = unsafe { Arch::detect_hardware() };
+        let mut ctx = VirtContext::new(0, hw.available_reg.nb_pmp, hw.extensions);
+        ctx.pc = code.as_ptr() as usize;
+        ctx
+    }
+
+    #[test]
+    fn arithmetic_and_branches() {
+        // Computes the sum of 1 to 10 in a0, then ecalls
+        let code = [
+            0x00000513, // li a0, 0
+            0x00a00593, // li a1, 10
+            0x00b50533, // loop: add a0, a0, a1
+            0xfff58593, // addi a1, a1, -1
+            0xfe059ce3, // bnez a1, loop
+            0x00000073, // ecall
+        ];
+        let mut ctx = new_ctx(&code);
+        ctx.mode = Mode::M;
+        let trap = unsafe { run(&mut ctx) };
+
+        assert_eq!(trap, Trap::new(MCause::EcallFromUMode, 0));
+        assert_eq!(ctx.regs[10], 55);
+        assert_eq!(ctx.pc, &code[5] as *const u32 as usize);
+    }
+
+    #[test]
+    fn memory_and_muldiv() {
+        let mut data: [u64; 2] = [0xffff_ffff_8000_0001, 0];
+        let code = [
+            0x0005b603, // ld a2, 0(a1)
+            0x0045e683, // lwu a3, 4(a1)
+            0x02d60733, // mul a4, a2, a3
+            0x00e5b423, // sd a4, 8(a1)
+            0x0005c783, // lbu a5, 0(a1)
+            0x0207c833, // div a6, a5, zero
+            0x30200073, // mret
+        ];
+        let mut ctx = new_ctx(&code);
+        ctx.regs[11] = data.as_mut_ptr() as usize;
+        let trap = unsafe { run(&mut ctx) };
+
+        // Privileged instructions trap to Miralis, divisions by zero do not
+        assert_eq!(trap, Trap::new(MCause::IllegalInstr, 0x30200073));
+        assert_eq!(ctx.regs[13], 0xffff_ffff);
+        assert_eq!(data[1], 0xffff_ffff_8000_0001u64.wrapping_mul(0xffff_ffff));
+        assert_eq!(ctx.regs[15], 1);
+        assert_eq!(ctx.regs[16], usize::MAX);
+    }
+
+    #[test]
+    fn atomics() {
+        let mut data: [u64; 2] = [41, 0xffff_fff0];
+        let code = [
+            0x1005362f, // lr.d a2, (a0)
+            0x00160613, // addi a2, a2, 1
+            0x18c536af, // sc.d a3, a2, (a0)
+            0x18c5372f, // sc.d a4, a2, (a0)
+            0x00500793, // li a5, 5
+            0x00f5a82f, // amoadd.w a6, a5, (a1)
+            0x80f5a8af, // amomin.w a7, a5, (a1)
+            0xe0f532af, // amomaxu.d t0, a5, (a0)
+            0x0805332f, // amoswap.d t1, zero, (a0)
+            0x00000073, // ecall
+        ];
+        let mut ctx = new_ctx(&code);
+        ctx.regs[10] = &mut data[0] as *mut u64 as usize;
+        ctx.regs[11] = &mut data[1] as *mut u64 as usize;
+        let trap = unsafe { run(&mut ctx) };
+
+        assert_eq!(trap, Trap::new(MCause::EcallFromUMode, 0));
+        // The first store-conditional consumes the reservation
+        assert_eq!(ctx.regs[13], 0);
+        assert_eq!(ctx.regs[14], 1);
+        // 32-bit values are sign-extended
+        assert_eq!(ctx.regs[16], 0xffff_ffff_ffff_fff0);
+        assert_eq!(ctx.regs[17], 0xffff_ffff_ffff_fff5);
+        assert_eq!(ctx.regs[5], 42);
+        assert_eq!(ctx.regs[6], 42);
+        assert_eq!(data, [0, 0xffff_fff5]);
+
+        // Misaligned atomics raise an exception
+        let code = [0x1005362f]; // lr.d a2, (a0)
+        let mut ctx = new_ctx(&code);
+        ctx.regs[10] = 0x1004;
+        let trap = unsafe { run(&mut ctx) };
+        assert_eq!(trap, Trap::new(MCause::LoadAddrMisaligned, 0x1004));
+    }
+}
@@ -6,8 +6,11 @@ use core::{ptr, usize};
 use super::{
     Arch, Architecture, Csr, ExtensionsCapability, MCause, Mode, RegistersCapability, TrapInfo,
 };
-use crate::arch::pmp::PmpFlush;
-use crate::arch::{mie, mstatus, parse_mpp_return_mode, HardwareCapability, PmpGroup, Width};
+use crate::arch::page_table::{self, AccessType, TranslationConfig, PAGE_SIZE};
+use crate::arch::pmp::{mseccfg, PmpFlush};
+use crate::arch::{
+    hstatus, mie, mstatus, parse_mpp_return_mode, satp, tdata1, HardwareCapability, PmpGroup, Width,
+};
 use crate::config::{PLATFORM_BOOT_HART_ID, TARGET_STACK_SIZE};
 use crate::decoder::Instr;
 use crate::virt::VirtContext;
@@ -101,6 +104,7 @@ impl Architecture for MetalArch {
             Csr::Sip => asm_write_csr!("sip"),
             Csr::Satp => asm_write_csr!("satp"),
             Csr::Scontext => asm_write_csr!("scontext"),
+            Csr::Stimecmp => asm_write_csr!("stimecmp"),
             Csr::Hstatus => asm_write_csr!("hstatus"),
             Csr::Hedeleg => asm_write_csr!("hedeleg"),
             Csr::Hideleg => asm_write_csr!("hideleg"),
@@ -124,6 +128,7 @@ impl Architecture for MetalArch {
             Csr::Vstval => asm_write_csr!("vstval"),
             Csr::Vsip => asm_write_csr!("vsip"),
             Csr::Vsatp => asm_write_csr!("vsatp"),
+            Csr::Cycle | Csr::Time | Csr::Instret | Csr::Hpmcounter(_) => {} // Read-only registers
             Csr::Unknown => (),
         };
 
@@ -195,6 +200,7 @@ impl Architecture for MetalArch {
             Csr::Sip => asm_read_csr!("sip"),
             Csr::Satp => asm_read_csr!("satp"),
             Csr::Scontext => asm_read_csr!("scontext"),
+            Csr::Stimecmp => asm_read_csr!("stimecmp"),
             Csr::Hstatus => asm_read_csr!("hstatus"),
             Csr::Hedeleg => asm_read_csr!("hedeleg"),
             Csr::Hideleg => asm_read_csr!("hideleg"),
@@ -218,6 +224,10 @@ impl Architecture for MetalArch {
             Csr::Vstval => asm_read_csr!("vstval"),
             Csr::Vsip => asm_read_csr!("vsip"),
             Csr::Vsatp => asm_read_csr!("vsatp"),
+            Csr::Cycle => asm_read_csr!("cycle"),
+            Csr::Time => asm_read_csr!("time"),
+            Csr::Instret => asm_read_csr!("instret"),
+            Csr::Hpmcounter(_) => value = 0, // Emulated as read-only zero
             Csr::Unknown => value = 0,
         };
 
@@ -256,11 +266,12 @@ impl Architecture for MetalArch {
         // Hint: to simulate a missing register, one can add "ecall" after the first line in asm! of the macro
         let is_menvcfg_present: bool = register_present!("menvcfg");
         let is_senvcfg_present: bool = register_present!("senvcfg");
-        log::debug!(
-            "Detecting available envcfg registers [menvcfg : {} | senvcfg : {} ]",
-            is_menvcfg_present,
-            is_senvcfg_present,
-        );
+
+        // Sstc is configured through menvcfg.STCE, stimecmp is accessible from M-mode regardless
+        let is_stimecmp_present: bool = is_menvcfg_present && register_present!("stimecmp");
+
+        // Smepmp is the only extension defining mseccfg
+        let is_mseccfg_present: bool = register_present!("mseccfg");
 
         // Detect available PMP registers:
         // - On RV64 platforms only even-numbered pmpcfg registers are present
@@ -282,7 +293,11 @@ impl Architecture for MetalArch {
 
         assert!(nb_pmp == 16, "PMP should be 16");
 
-        log::debug!("Number of PMP: {}", nb_pmp);
+        // Disable the hardware triggers possibly left armed by a previous boot stage, the firmware
+        // only gets access to virtual triggers.
+        if register_present!("tselect") {
+            disable_hardware_triggers();
+        }
 
         // Save current CSRs
         let mstatus = Self::read_csr(Csr::Mstatus);
@@ -309,6 +324,7 @@ impl Architecture for MetalArch {
         Self::write_csr(Csr::Mtvec, mtvec);
 
         let misa = Self::read_csr(Csr::Misa);
+        let has_s_extension = (misa as usize & misa::S) != 0;
 
         // Return hardware configuration
         HardwareCapability {
@@ -322,10 +338,14 @@ impl Architecture for MetalArch {
             },
             extensions: ExtensionsCapability {
                 has_h_extension: (misa as usize & misa::H) != 0,
-                has_s_extension: (misa as usize & misa::S) != 0,
+                has_s_extension,
                 _has_f_extension: (misa as usize & misa::S) != 0,
                 _has_d_extension: (misa as usize & misa::D) != 0,
                 _has_q_extension: (misa as usize & misa::Q) != 0,
+                has_sstc_extension: is_stimecmp_present,
+                has_smepmp_extension: is_mseccfg_present,
+                has_sv48_extension: has_s_extension && is_satp_mode_supported(satp::MODE_SV48),
+                has_sv57_extension: has_s_extension && is_satp_mode_supported(satp::MODE_SV57),
             },
         }
     }
@@ -347,6 +367,13 @@ impl Architecture for MetalArch {
             "Invalid number of PMP registers"
         );
 
+        // Rule locking bypass must be enabled before updating locked entries, while the sticky
+        // bits are set last so that they never apply to a partially written configuration.
+        let seccfg = pmp.mseccfg();
+        if pmp.has_smepmp() {
+            Self::set_csr_bits(Csr::Mseccfg, seccfg & mseccfg::RLB);
+        }
+
         for idx in 0..nb_pmp {
             write_pmpaddr(idx, pmpaddr[idx]);
         }
@@ -355,6 +382,10 @@ impl Architecture for MetalArch {
             write_pmpcfg(idx * 2, cfg);
         }
 
+        if pmp.has_smepmp() {
+            Self::write_csr(Csr::Mseccfg, seccfg);
+        }
+
         PmpFlush()
     }
 
@@ -578,6 +609,7 @@ impl Architecture for MetalArch {
             Csr::Sip => asm_clear_csr_bits!("sip"),
             Csr::Satp => asm_clear_csr_bits!("satp"),
             Csr::Scontext => asm_clear_csr_bits!("scontext"),
+            Csr::Stimecmp => asm_clear_csr_bits!("stimecmp"),
             Csr::Hstatus => asm_clear_csr_bits!("hstatus"),
             Csr::Hedeleg => asm_clear_csr_bits!("hedeleg"),
             Csr::Hideleg => asm_clear_csr_bits!("hideleg"),
@@ -601,6 +633,7 @@ impl Architecture for MetalArch {
             Csr::Vstval => asm_clear_csr_bits!("vstval"),
             Csr::Vsip => asm_clear_csr_bits!("vsip"),
             Csr::Vsatp => asm_clear_csr_bits!("vsatp"),
+            Csr::Cycle | Csr::Time | Csr::Instret | Csr::Hpmcounter(_) => (), // Read-only registers
             Csr::Unknown => (),
         };
     }
@@ -668,6 +701,7 @@ impl Architecture for MetalArch {
             Csr::Sip => asm_set_csr_bits!("sip"),
             Csr::Satp => asm_set_csr_bits!("satp"),
             Csr::Scontext => asm_set_csr_bits!("scontext"),
+            Csr::Stimecmp => asm_set_csr_bits!("stimecmp"),
             Csr::Hstatus => asm_set_csr_bits!("hstatus"),
             Csr::Hedeleg => asm_set_csr_bits!("hedeleg"),
             Csr::Hideleg => asm_set_csr_bits!("hideleg"),
@@ -691,6 +725,7 @@ impl Architecture for MetalArch {
             Csr::Vstval => asm_set_csr_bits!("vstval"),
             Csr::Vsip => asm_set_csr_bits!("vsip"),
             Csr::Vsatp => asm_set_csr_bits!("vsatp"),
+            Csr::Cycle | Csr::Time | Csr::Instret | Csr::Hpmcounter(_) => (), // Read-only registers
             Csr::Unknown => (),
         };
     }
@@ -702,20 +737,114 @@ impl Architecture for MetalArch {
         // Zero out mcause to check if a trap occured during emulation
         Self::write_csr(Csr::Mcause, 0);
 
+        // Hypervisor loads and stores access the guest memory with the privilege of hstatus.SPVP,
+        // other accesses use the vMPP and the vMPV as with a native mstatus.MPRV.
+        let spvp = match ctx.h_csr(Csr::Hstatus) & hstatus::SPVP_FILTER {
+            0 => Mode::U,
+            _ => Mode::S,
+        };
+        let (mode, virtualized, is_exec) = match instr {
+            Instr::Hlv { is_exec, .. } => (spvp, true, is_exec),
+            Instr::Hsv { .. } => (spvp, true, false),
+            _ => (
+                parse_mpp_return_mode(ctx.csr.mstatus),
+                ctx.extensions.has_h_extension && ctx.csr.mstatus & mstatus::MPV_FILTER != 0,
+                false,
+            ),
+        };
+        // Once the privilege is known, they are emulated as regular loads and stores
+        let instr = match instr {
+            Instr::Hlv {
+                rd,
+                rs1,
+                len,
+                is_unsigned,
+                ..
+            } => Instr::Load {
+                rd,
+                rs1,
+                imm: 0,
+                len,
+                is_compressed: false,
+                is_unsigned,
+            },
+            Instr::Hsv { rs1, rs2, len } => Instr::Store {
+                rs2,
+                rs1,
+                imm: 0,
+                len,
+                is_compressed: false,
+            },
+            instr => instr,
+        };
+
+        // Without virtualization, the firmware's page tables are walked in software and the access
+        // is performed on the physical address, leaving only the PMP checks to the hardware.
+        let vaddr = match instr {
+            Instr::Load { rs1, imm, .. } | Instr::Store { rs1, imm, .. } => {
+                utils::calculate_addr(ctx.get(rs1), imm)
+            }
+            _ => {
+                // Only loads and stores can be emulated, report anything else as a failed access
+                log::warn!("Unsupported MPRV instruction: {:?}", instr);
+                ctx.trap_info.mcause = MCause::LoadAccessFault as usize;
+                ctx.trap_info.mtval = 0;
+                ctx.emulate_jump_trap_handler();
+                return;
+            }
+        };
+        let (addr, satp) = if virtualized {
+            (vaddr, ctx.csr.satp)
+        } else {
+            let config = TranslationConfig::new(ctx.csr.satp, mode, ctx.csr.mstatus);
+            match translate_access(vaddr, &instr, &config) {
+                Ok(paddr) => (paddr, 0),
+                Err(cause) => {
+                    log::trace!("Emulated access to 0x{:x} raised {}", vaddr, cause);
+                    ctx.trap_info.mcause = cause as usize;
+                    ctx.trap_info.mtval = vaddr;
+                    ctx.emulate_jump_trap_handler();
+                    return;
+                }
+            }
+        };
+
         // Set the MPP mode to match the vMPP
-        let prev_mpp = Self::set_mpp(parse_mpp_return_mode(ctx.csr.mstatus));
-        let prev_satp = Self::write_csr(Csr::Satp, ctx.csr.satp);
+        let prev_mpp = Self::set_mpp(mode);
+        let prev_satp = Self::write_csr(Csr::Satp, satp);
+
+        // Guest accesses go through the two-stage translation configured by the firmware
+        let prev_guest_csrs = if virtualized {
+            let prev = (
+                Self::write_csr(Csr::Vsatp, ctx.h_csr(Csr::Vsatp)),
+                Self::write_csr(Csr::Hgatp, ctx.h_csr(Csr::Hgatp)),
+                Self::write_csr(Csr::Vsstatus, ctx.h_csr(Csr::Vsstatus)),
+            );
+            Self::hfencegvma(None, None);
+            Some(prev)
+        } else {
+            None
+        };
+        // HLVX requires execute permission, which is approximated by making executable pages
+        // readable with mstatus.MXR
+        let mut mstatus_bits = if virtualized { mstatus::MPV_FILTER } else { 0 };
+        if is_exec {
+            mstatus_bits |= mstatus::MXR_FILTER;
+        }
+        let prev_mstatus = Self::read_csr(Csr::Mstatus);
+        Self::set_csr_bits(Csr::Mstatus, mstatus_bits);
 
         // Changes to SATP require an sfence instruction to take effect
         Self::sfencevma(None, None);
 
         let mut rd_value: usize;
-        let addr: usize;
         let mut cause: usize;
         let mut mtval: usize;
         let mut mstatus: usize;
         let mut mip: usize;
         let fw_pc = ctx.trap_info.mepc;
+        // Translated accesses run on the physical address, but the firmware expects the virtual one
+        let fault_addr = |mtval: usize| if virtualized { mtval } else { vaddr };
 
         macro_rules! construct_asm {
             ($instr:literal) => {
@@ -751,13 +880,11 @@ impl Architecture for MetalArch {
         match instr {
             Instr::Load {
                 rd,
-                rs1,
-                imm,
                 len,
                 is_compressed,
                 is_unsigned,
+                ..
             } => {
-                addr = utils::calculate_addr(ctx.get(rs1), imm);
                 rd_value = 0;
 
                 match (len, is_unsigned) {
@@ -774,7 +901,7 @@ impl Architecture for MetalArch {
                 if Self::read_csr(Csr::Mcause) != 0 {
                     ctx.trap_info.mcause = cause;
                     ctx.trap_info.mstatus = mstatus;
-                    ctx.trap_info.mtval = mtval;
+                    ctx.trap_info.mtval = fault_addr(mtval);
                     ctx.trap_info.mepc = fw_pc;
                     ctx.trap_info.mip = mip;
 
@@ -786,12 +913,10 @@ impl Architecture for MetalArch {
             }
             Instr::Store {
                 rs2,
-                rs1,
-                imm,
                 len,
                 is_compressed,
+                ..
             } => {
-                addr = utils::calculate_addr(ctx.get(rs1), imm);
                 rd_value = ctx.get(rs2);
 
                 match len {
@@ -806,7 +931,7 @@ impl Architecture for MetalArch {
                 if Self::read_csr(Csr::Mcause) != 0 {
                     ctx.trap_info.mcause = cause;
                     ctx.trap_info.mstatus = mstatus;
-                    ctx.trap_info.mtval = mtval;
+                    ctx.trap_info.mtval = fault_addr(mtval);
                     ctx.trap_info.mepc = fw_pc;
                     ctx.trap_info.mip = mip;
 
@@ -815,10 +940,17 @@ impl Architecture for MetalArch {
                     ctx.pc += if is_compressed { 2 } else { 4 };
                 }
             }
-            _ => todo!("Instruction not yet implemented: {:?}", instr),
+            _ => unreachable!("Not a load or store: {:?}", instr),
         }
 
         // Restore the original values
+        Self::clear_csr_bits(Csr::Mstatus, mstatus_bits & !prev_mstatus);
+        if let Some((vsatp, hgatp, vsstatus)) = prev_guest_csrs {
+            Self::write_csr(Csr::Vsatp, vsatp);
+            Self::write_csr(Csr::Hgatp, hgatp);
+            Self::write_csr(Csr::Vsstatus, vsstatus);
+            Self::hfencegvma(None, None);
+        }
         Self::write_csr(Csr::Satp, prev_satp);
         Self::set_mpp(prev_mpp);
 
@@ -827,125 +959,260 @@ impl Architecture for MetalArch {
     }
 
     unsafe fn read_bytes_from_mode(src: *const u8, dest: &mut [u8], mode: Mode) -> Result<(), ()> {
-        let mut src = src as usize;
-        let mut success: usize = 1;
+        // Addresses are translated in software, the hardware only checks the PMP
+        let config = TranslationConfig::new(
+            Self::read_csr(Csr::Satp),
+            mode,
+            Self::read_csr(Csr::Mstatus),
+        );
+        let prev_satp = Self::write_csr(Csr::Satp, 0);
+        let result = for_each_physical_chunk(
+            src as usize,
+            dest.len(),
+            AccessType::Load,
+            &config,
+            |paddr, range| read_bytes_physical(paddr as *const u8, &mut dest[range], mode),
+        );
+        Self::write_csr(Csr::Satp, prev_satp);
+        result
+    }
 
-        // Save the state of exception-related CSRs, as we might overwrite them if an error occurs
-        let prev_mepc = Self::read_csr(Csr::Mepc);
-        let prev_mcause = Self::read_csr(Csr::Mcause);
-        let prev_mstatus = Self::read_csr(Csr::Mstatus);
+    unsafe fn store_bytes_from_mode(src: &mut [u8], dest: *const u8, mode: Mode) -> Result<(), ()> {
+        // Addresses are translated in software, the hardware only checks the PMP
+        let config = TranslationConfig::new(
+            Self::read_csr(Csr::Satp),
+            mode,
+            Self::read_csr(Csr::Mstatus),
+        );
+        let prev_satp = Self::write_csr(Csr::Satp, 0);
+        let result = for_each_physical_chunk(
+            dest as usize,
+            src.len(),
+            AccessType::Store,
+            &config,
+            |paddr, range| store_bytes_physical(&mut src[range], paddr as *const u8, mode),
+        );
+        Self::write_csr(Csr::Satp, prev_satp);
+        result
+    }
+}
 
-        // Set mstatus.MPP to mode
-        let prev_mode = Self::set_mpp(mode);
-        for i in 0..dest.len() {
-            let mut byte_read: u8 = 0;
-            unsafe {
-                asm!(
-                // Try
-                "la {r_mtvec}, 0f",
-                "csrrw {r_mtvec}, mtvec, {r_mtvec}",  // Trap to catch-block if an exception occurs
-
-                // Set the mstatus.MPRV bit to 1
-                "csrs mstatus, {mprv_filter}",
-                // Read byte at src
-                "lb {byte}, 0x00({src})",
-                // Set the mstatus.MPRV bit to 0
-                "csrc mstatus, {mprv_filter}",
-                "j 1f", // Jump to finally if the read was successful
-
-                // Catch
-                ".align 4",
-                "0:",
-                "li {success}, 0",
-                "la {byte}, 1f",
-                "csrw mepc, {byte}",
-                "mret",  // Jump to finally and set mstatus.MPRV to 0
-
-                // Finally
-                ".align 4",
-                "1:",
-                "csrw mtvec, {r_mtvec}", // Restore mtvec
-                src = in(reg) src,
-                mprv_filter = in(reg) mstatus::MPRV_FILTER,
-                byte = inout(reg) byte_read,
-                success = inout(reg) success,
-                r_mtvec = out(reg) _,
-                )
-            }
+// ————————————————————————————— Guest Accesses ————————————————————————————— //
 
-            if success == 0 {
-                Self::write_csr(Csr::Mepc, prev_mepc);
-                Self::write_csr(Csr::Mcause, prev_mcause);
-                Self::write_csr(Csr::Mstatus, prev_mstatus);
-                return Err(());
-            }
+/// Reads bytes with the privileges of the given mode, without address translation.
+///
+/// SAFETY: satp must be Bare, otherwise the address is translated by the hardware.
+unsafe fn read_bytes_physical(src: *const u8, dest: &mut [u8], mode: Mode) -> Result<(), ()> {
+    let mut src = src as usize;
+    let mut success: usize = 1;
+
+    // Save the state of exception-related CSRs, as we might overwrite them if an error occurs
+    let prev_mepc = MetalArch::read_csr(Csr::Mepc);
+    let prev_mcause = MetalArch::read_csr(Csr::Mcause);
+    let prev_mstatus = MetalArch::read_csr(Csr::Mstatus);
+
+    // Set mstatus.MPP to mode
+    let prev_mode = MetalArch::set_mpp(mode);
+    for i in 0..dest.len() {
+        let mut byte_read: u8 = 0;
+        unsafe {
+            asm!(
+            // Try
+            "la {r_mtvec}, 0f",
+            "csrrw {r_mtvec}, mtvec, {r_mtvec}",  // Trap to catch-block if an exception occurs
+
+            // Set the mstatus.MPRV bit to 1
+            "csrs mstatus, {mprv_filter}",
+            // Read byte at src
+            "lb {byte}, 0x00({src})",
+            // Set the mstatus.MPRV bit to 0
+            "csrc mstatus, {mprv_filter}",
+            "j 1f", // Jump to finally if the read was successful
+
+            // Catch
+            ".align 4",
+            "0:",
+            "li {success}, 0",
+            "la {byte}, 1f",
+            "csrw mepc, {byte}",
+            "mret",  // Jump to finally and set mstatus.MPRV to 0
+
+            // Finally
+            ".align 4",
+            "1:",
+            "csrw mtvec, {r_mtvec}", // Restore mtvec
+            src = in(reg) src,
+            mprv_filter = in(reg) mstatus::MPRV_FILTER,
+            byte = inout(reg) byte_read,
+            success = inout(reg) success,
+            r_mtvec = out(reg) _,
+            )
+        }
 
-            dest[i] = byte_read;
-            src += 1;
+        if success == 0 {
+            MetalArch::write_csr(Csr::Mepc, prev_mepc);
+            MetalArch::write_csr(Csr::Mcause, prev_mcause);
+            MetalArch::write_csr(Csr::Mstatus, prev_mstatus);
+            return Err(());
         }
 
-        Self::set_mpp(prev_mode);
-        Ok(())
+        dest[i] = byte_read;
+        src += 1;
     }
 
-    unsafe fn store_bytes_from_mode(src: &mut [u8], dest: *const u8, mode: Mode) -> Result<(), ()> {
-        let mut dest = dest as usize;
-        let mut success: usize = 1;
-
-        // Save the state of exception-related CSRs, as we might overwrite them if an error occurs
-        let prev_mepc = Self::read_csr(Csr::Mepc);
-        let prev_mcause = Self::read_csr(Csr::Mcause);
-        let prev_mstatus = Self::read_csr(Csr::Mstatus);
+    MetalArch::set_mpp(prev_mode);
+    Ok(())
+}
 
-        // Set mstatus.MPP to mode
-        let prev_mode = Self::set_mpp(mode);
-        for i in 0..src.len() {
-            let byte_value: u8 = src[i];
-            unsafe {
-                asm!(
-                // Try
-                "la {r_mtvec}, 0f",
-                "csrrw {r_mtvec}, mtvec, {r_mtvec}",  // Trap to catch-block if an exception occurs
-
-                // Set the mstatus.MPRV bit to 1
-                "csrs mstatus, {mprv_filter}",
-                // Store byte at src
-                "sb {byte}, 0x00({dest})",
-                // Set the mstatus.MPRV bit to 0
-                "csrc mstatus, {mprv_filter}",
-                "j 1f", // Jump to finally if the read was successful
-
-                // Catch
-                ".align 4",
-                "0:",
-                "li {success}, 0",
-                "la {byte}, 1f",
-                "csrw mepc, {byte}",
-                "mret",  // Jump to finally and set mstatus.MPRV to 0
-
-                // Finally
-                ".align 4",
-                "1:",
-                "csrw mtvec, {r_mtvec}", // Restore mtvec
-                dest = in(reg) dest,
-                mprv_filter = in(reg) mstatus::MPRV_FILTER,
-                byte = in(reg) byte_value,
-                success = inout(reg) success,
-                r_mtvec = out(reg) _,
-                )
-            }
-            if success == 0 {
-                Self::write_csr(Csr::Mepc, prev_mepc);
-                Self::write_csr(Csr::Mcause, prev_mcause);
-                Self::write_csr(Csr::Mstatus, prev_mstatus);
-                return Err(());
-            }
-            dest += 1;
+/// Stores bytes with the privileges of the given mode, without address translation.
+///
+/// SAFETY: satp must be Bare, otherwise the address is translated by the hardware.
+unsafe fn store_bytes_physical(src: &mut [u8], dest: *const u8, mode: Mode) -> Result<(), ()> {
+    let mut dest = dest as usize;
+    let mut success: usize = 1;
+
+    // Save the state of exception-related CSRs, as we might overwrite them if an error occurs
+    let prev_mepc = MetalArch::read_csr(Csr::Mepc);
+    let prev_mcause = MetalArch::read_csr(Csr::Mcause);
+    let prev_mstatus = MetalArch::read_csr(Csr::Mstatus);
+
+    // Set mstatus.MPP to mode
+    let prev_mode = MetalArch::set_mpp(mode);
+    for i in 0..src.len() {
+        let byte_value: u8 = src[i];
+        unsafe {
+            asm!(
+            // Try
+            "la {r_mtvec}, 0f",
+            "csrrw {r_mtvec}, mtvec, {r_mtvec}",  // Trap to catch-block if an exception occurs
+
+            // Set the mstatus.MPRV bit to 1
+            "csrs mstatus, {mprv_filter}",
+            // Store byte at src
+            "sb {byte}, 0x00({dest})",
+            // Set the mstatus.MPRV bit to 0
+            "csrc mstatus, {mprv_filter}",
+            "j 1f", // Jump to finally if the read was successful
+
+            // Catch
+            ".align 4",
+            "0:",
+            "li {success}, 0",
+            "la {byte}, 1f",
+            "csrw mepc, {byte}",
+            "mret",  // Jump to finally and set mstatus.MPRV to 0
+
+            // Finally
+            ".align 4",
+            "1:",
+            "csrw mtvec, {r_mtvec}", // Restore mtvec
+            dest = in(reg) dest,
+            mprv_filter = in(reg) mstatus::MPRV_FILTER,
+            byte = in(reg) byte_value,
+            success = inout(reg) success,
+            r_mtvec = out(reg) _,
+            )
+        }
+        if success == 0 {
+            MetalArch::write_csr(Csr::Mepc, prev_mepc);
+            MetalArch::write_csr(Csr::Mcause, prev_mcause);
+            MetalArch::write_csr(Csr::Mstatus, prev_mstatus);
+            return Err(());
         }
+        dest += 1;
+    }
+
+    MetalArch::set_mpp(prev_mode);
+    Ok(())
+}
+
+/// Calls `access` with the physical address of each chunk of `[vaddr, vaddr + len)` contained in
+/// a single page, together with the range of the chunk relative to `vaddr`.
+unsafe fn for_each_physical_chunk(
+    vaddr: usize,
+    len: usize,
+    access_type: AccessType,
+    config: &TranslationConfig,
+    mut access: impl FnMut(usize, core::ops::Range<usize>) -> Result<(), ()>,
+) -> Result<(), ()> {
+    let mut offset = 0;
+    while offset < len {
+        let addr = vaddr + offset;
+        let chunk = (len - offset).min(PAGE_SIZE - addr % PAGE_SIZE);
+        let paddr = page_table::translate(addr, access_type, config, |pte| read_pte(pte))
+            .map_err(|_| ())?;
+        access(paddr, offset..offset + chunk)?;
+        offset += chunk;
+    }
+    Ok(())
+}
+
+/// Translates the address of an emulated load or store with the software page table walker.
+///
+/// Returns the exception to raise if the translation fails. Accesses crossing a page boundary are
+/// reported as misaligned, as the specification allows for misaligned accesses.
+unsafe fn translate_access(
+    vaddr: usize,
+    instr: &Instr,
+    config: &TranslationConfig,
+) -> Result<usize, MCause> {
+    let (access, len, misaligned) = match instr {
+        Instr::Load { len, .. } => (AccessType::Load, len, MCause::LoadAddrMisaligned),
+        Instr::Store { len, .. } => (AccessType::Store, len, MCause::StoreAddrMisaligned),
+        _ => unreachable!("Not a load or store: {:?}", instr),
+    };
+    if config.is_translated() && vaddr % PAGE_SIZE + len.to_bytes() > PAGE_SIZE {
+        return Err(misaligned);
+    }
+    page_table::translate(vaddr, access, config, |pte| read_pte(pte))
+        .map_err(|fault| fault.cause(access))
+}
 
-        Self::set_mpp(prev_mode);
-        Ok(())
+/// Reads a page table entry for the software page table walker.
+///
+/// Like the implicit accesses of the hardware page table walker, the read is checked against the
+/// PMP with S-mode privileges.
+///
+/// SAFETY: satp must be Bare, see [read_bytes_physical].
+unsafe fn read_pte(addr: usize) -> Result<usize, ()> {
+    let mut bytes = [0; core::mem::size_of::<usize>()];
+    read_bytes_physical(addr as *const u8, &mut bytes, Mode::S)?;
+    Ok(usize::from_le_bytes(bytes))
+}
+
+/// Disables all the hardware triggers.
+///
+/// Triggers are enumerated by writing tselect until the value does not read back, or until tdata1
+/// reports that there is no trigger at the selected index.
+///
+/// SAFETY: This function assumes that tselect and tdata1 are implemented.
+unsafe fn disable_hardware_triggers() {
+    let mut idx = 0;
+    loop {
+        MetalArch::write_csr(Csr::Tselect, idx);
+        if MetalArch::read_csr(Csr::Tselect) != idx
+            || tdata1::get_type(MetalArch::read_csr(Csr::Tdata1)) == tdata1::TYPE_NONE
+        {
+            break;
+        }
+        MetalArch::write_csr(Csr::Tdata1, 0);
+        idx += 1;
     }
+    MetalArch::write_csr(Csr::Tselect, 0);
+
+    log::debug!("Disabled {} hardware triggers", idx);
+}
+
+/// Returns true if the satp mode is implemented.
+///
+/// The satp mode is WARL, writes of an unsupported mode leave satp unchanged.
+///
+/// SAFETY: This function assumes that satp is implemented, M-mode accesses are not translated.
+unsafe fn is_satp_mode_supported(mode: usize) -> bool {
+    let prev_satp = MetalArch::write_csr(Csr::Satp, mode << satp::MODE_OFFSET);
+    let supported = MetalArch::read_csr(Csr::Satp) >> satp::MODE_OFFSET == mode;
+    MetalArch::write_csr(Csr::Satp, prev_satp);
+    supported
 }
 
 /// Finds the number of non-zero PMP registers, i.e. the effective number of PMP registers
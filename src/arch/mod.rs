@@ -1,16 +1,21 @@
 //! Architecture specific functions
 //!
-//! All direct interaction with RISC-V specific architecture features should live here. In the
-//! future, we could emulate RISC-V instructions to enable running the monitor in user space, which
-//! would be very helpful for testing purpose.
+//! All direct interaction with RISC-V specific architecture features should live here. In user
+//! space the RISC-V instructions of the guest are interpreted, which enables testing the monitor on
+//! the host.
 
+mod bitfield;
+mod interpreter;
 #[cfg(not(feature = "userspace"))]
 mod metal;
+pub mod page_table;
 pub mod pmp;
 mod registers;
 mod trap;
 mod userspace;
 
+use bitfield::csr_fields;
+pub use bitfield::Field;
 use pmp::{PmpFlush, PmpGroup};
 pub use registers::{Csr, Register};
 pub use trap::{MCause, TrapInfo};
@@ -142,6 +147,14 @@ pub struct ExtensionsCapability {
     pub _has_d_extension: bool,
     /// Quadruple precision floating point extension
     pub _has_q_extension: bool,
+    /// Supervisor-mode timer interrupts extension (stimecmp)
+    pub has_sstc_extension: bool,
+    /// PMP enhancements for memory access and execution prevention on Machine mode (mseccfg)
+    pub has_smepmp_extension: bool,
+    /// 48-bit virtual memory (satp mode Sv48)
+    pub has_sv48_extension: bool,
+    /// 57-bit virtual memory (satp mode Sv57)
+    pub has_sv57_extension: bool,
 }
 
 // ———————————————————————————— Privilege Modes ————————————————————————————— //
@@ -215,6 +228,8 @@ pub mod misa {
     pub const S: usize = 1 << 18;
     /// User mode implemented
     pub const U: usize = 1 << 20;
+    /// Vector extension
+    pub const V: usize = 1 << 21;
     /// Non-standard extensions present
     pub const X: usize = 1 << 23;
 
@@ -226,13 +241,17 @@ pub mod misa {
     pub const DISABLED: usize = {
         // By default we disable compressed instructions for now, because emulation and the
         // decoded assume 4 bytes instructions.
-        // We also disable H mode, because we don't provide support for it right now.
-        // In addition, we disable floating points because we encountered some issues with those
-        // and they will require special handling when context switching from the OS (checking the
-        // mstatus.FS bits).
-        C | D | F | Q
+        // Vectors are disabled for now, only floating points have been tested with the tracking
+        // of mstatus.VS.
+        C | V
     };
 
+    /// Extensions the firmware can enable or disable by writing misa, if supported.
+    ///
+    /// Disabling the other extensions (such as S or H) would require emulating the absence of the
+    /// corresponding privilege modes, writes to their bits are ignored.
+    pub const WRITABLE: usize = A | C | D | F | M | Q | V;
+
     /// Constant to filter out non-writable fields of the misa csr
     pub const MISA_CHANGE_FILTER: usize = 0x0000000003FFFFFF;
 }
@@ -243,6 +262,17 @@ pub mod misa {
 pub mod satp {
     /// Constant to filter out non-writable fields of the satp csr
     pub const SATP_CHANGE_FILTER: usize = 0x00000FFFFFFFFFFF;
+
+    /// MODE
+    pub const MODE_OFFSET: usize = 60;
+    pub const MODE_FILTER: usize = 0b1111 << MODE_OFFSET;
+    pub const MODE_BARE: usize = 0;
+    pub const MODE_SV39: usize = 8;
+    pub const MODE_SV48: usize = 9;
+    pub const MODE_SV57: usize = 10;
+
+    /// PPN
+    pub const PPN_FILTER: usize = 0x00000FFFFFFFFFFF;
 }
 
 // ————————————————————————————— Machine Status ————————————————————————————— //
@@ -262,7 +292,9 @@ pub mod mstatus {
         | TSR_FILTER
         | SXL_FILTER
         | SBE_FILTER
-        | MBE_FILTER;
+        | MBE_FILTER
+        | GVA_FILTER
+        | MPV_FILTER;
 
     /// Constant to filter out WPRI fields of sstatus
     pub const SSTATUS_FILTER: usize = SIE_FILTER
@@ -278,72 +310,57 @@ pub mod mstatus {
         | SD_FILTER;
 
     // Mstatus fields constants
-    /// SIE
-    pub const SIE_OFFSET: usize = 1;
-    pub const SIE_FILTER: usize = 0b1 << SIE_OFFSET;
-    /// MIE
-    pub const MIE_OFFSET: usize = 3;
-    pub const MIE_FILTER: usize = 0b1 << MIE_OFFSET;
-    /// SPIE
-    pub const SPIE_OFFSET: usize = 5;
-    pub const SPIE_FILTER: usize = 0b1 << SPIE_OFFSET;
-    /// UBE
-    pub const UBE_OFFSET: usize = 6;
-    pub const UBE_FILTER: usize = 0b1 << UBE_OFFSET;
-    /// MPIE
-    pub const MPIE_OFFSET: usize = 7;
-    pub const MPIE_FILTER: usize = 0b1 << MPIE_OFFSET;
-    /// SPP
-    pub const SPP_OFFSET: usize = 8;
-    pub const SPP_FILTER: usize = 0b1 << SPP_OFFSET;
-    /// VS
-    pub const VS_OFFSET: usize = 9;
-    pub const VS_FILTER: usize = 0b11 << VS_OFFSET;
-    /// MPP
-    pub const MPP_OFFSET: usize = 11;
-    pub const MPP_FILTER: usize = 0b11 << MPP_OFFSET;
-    /// FS
-    pub const FS_OFFSET: usize = 13;
-    pub const FS_FILTER: usize = 0b11 << FS_OFFSET;
-    /// XS
-    pub const XS_OFFSET: usize = 15;
-    pub const XS_FILTER: usize = 0b11 << XS_OFFSET;
-    /// MPRV
-    pub const MPRV_OFFSET: usize = 17;
-    pub const MPRV_FILTER: usize = 0b1 << MPRV_OFFSET;
-    /// SUM
-    pub const SUM_OFFSET: usize = 18;
-    pub const SUM_FILTER: usize = 0b1 << SUM_OFFSET;
-    /// MXR
-    pub const MXR_OFFSET: usize = 19;
-    pub const MXR_FILTER: usize = 0b1 << MXR_OFFSET;
-    /// TVM
-    pub const TVM_OFFSET: usize = 20;
-    pub const TVM_FILTER: usize = 0b1 << TVM_OFFSET;
-    /// TW
-    pub const TW_OFFSET: usize = 21;
-    pub const TW_FILTER: usize = 0b1 << TW_OFFSET;
-    /// TSR
-    pub const TSR_OFFSET: usize = 22;
-    pub const TSR_FILTER: usize = 0b1 << TSR_OFFSET;
-    /// UXL
-    pub const UXL_OFFSET: usize = 32;
-    pub const UXL_FILTER: usize = 0b11 << UXL_OFFSET;
-    /// SXL
-    pub const SXL_OFFSET: usize = 34;
-    pub const SXL_FILTER: usize = 0b11 << SXL_OFFSET;
-    /// SBE
-    pub const SBE_OFFSET: usize = 36;
-    pub const SBE_FILTER: usize = 0b1 << SBE_OFFSET;
-    /// MBE
-    pub const MBE_OFFSET: usize = 37;
-    pub const MBE_FILTER: usize = 0b1 << MBE_OFFSET;
-    /// MPV
-    pub const MPV_OFFSET: usize = 39;
-    pub const MPV_FILTER: usize = 0b1 << MPV_OFFSET;
-    /// SD
-    pub const SD_OFFSET: usize = 63;
-    pub const SD_FILTER: usize = 0b1 << SD_OFFSET;
+    super::csr_fields! {
+        /// SIE
+        SIE(SIE_OFFSET, SIE_FILTER) = bit 1;
+        /// MIE
+        MIE(MIE_OFFSET, MIE_FILTER) = bit 3;
+        /// SPIE
+        SPIE(SPIE_OFFSET, SPIE_FILTER) = bit 5;
+        /// UBE
+        UBE(UBE_OFFSET, UBE_FILTER) = bit 6;
+        /// MPIE
+        MPIE(MPIE_OFFSET, MPIE_FILTER) = bit 7;
+        /// SPP
+        SPP(SPP_OFFSET, SPP_FILTER) = bit 8;
+        /// VS
+        VS(VS_OFFSET, VS_FILTER) = bits 9..=10;
+        /// MPP
+        MPP(MPP_OFFSET, MPP_FILTER) = bits 11..=12;
+        /// FS
+        FS(FS_OFFSET, FS_FILTER) = bits 13..=14;
+        /// XS
+        XS(XS_OFFSET, XS_FILTER) = bits 15..=16;
+        /// MPRV
+        MPRV(MPRV_OFFSET, MPRV_FILTER) = bit 17;
+        /// SUM
+        SUM(SUM_OFFSET, SUM_FILTER) = bit 18;
+        /// MXR
+        MXR(MXR_OFFSET, MXR_FILTER) = bit 19;
+        /// TVM
+        TVM(TVM_OFFSET, TVM_FILTER) = bit 20;
+        /// TW
+        TW(TW_OFFSET, TW_FILTER) = bit 21;
+        /// TSR
+        TSR(TSR_OFFSET, TSR_FILTER) = bit 22;
+        /// UXL
+        UXL(UXL_OFFSET, UXL_FILTER) = bits 32..=33;
+        /// SXL
+        SXL(SXL_OFFSET, SXL_FILTER) = bits 34..=35;
+        /// SBE
+        SBE(SBE_OFFSET, SBE_FILTER) = bit 36;
+        /// MBE
+        MBE(MBE_OFFSET, MBE_FILTER) = bit 37;
+        /// GVA
+        GVA(GVA_OFFSET, GVA_FILTER) = bit 38;
+        /// MPV
+        MPV(MPV_OFFSET, MPV_FILTER) = bit 39;
+        /// SD
+        SD(SD_OFFSET, SD_FILTER) = bit 63;
+    }
+
+    /// Value of the FS, VS, and XS fields when the state is dirty
+    pub const DIRTY: usize = 0b11;
 }
 
 // ———————————————————————— Machine Interrupt-Enabled ——————————————————————— //
@@ -373,28 +390,77 @@ pub mod mie {
     /// M-mode interrupts.
     pub const MIDELEG_READ_ONLY_ZERO: usize = MSIE_FILTER | MTIE_FILTER | MEIE_FILTER;
 
+    /// The bits in mideleg that are read-only one with the hypervisor extension.
+    ///
+    /// Virtual supervisor and guest external interrupts are always delegated to HS-mode.
+    pub const MIDELEG_HYPERVISOR_READ_ONLY_ONE: usize =
+        VSSIE_FILTER | VSTIE_FILTER | VSEIE_FILTER | SGEIE_FILTER;
+
     // Mie fields constants
-    /// SSIE
-    pub const SSIE_OFFSET: usize = 1;
-    pub const SSIE_FILTER: usize = 0b1 << SSIE_OFFSET;
-    /// MSIE
-    pub const MSIE_OFFSET: usize = 3;
-    pub const MSIE_FILTER: usize = 0b1 << MSIE_OFFSET;
-    /// STIE
-    pub const STIE_OFFSET: usize = 5;
-    pub const STIE_FILTER: usize = 0b1 << STIE_OFFSET;
-    /// MTIE
-    pub const MTIE_OFFSET: usize = 7;
-    pub const MTIE_FILTER: usize = 0b1 << MTIE_OFFSET;
-    /// SEIE
-    pub const SEIE_OFFSET: usize = 9;
-    pub const SEIE_FILTER: usize = 0b1 << SEIE_OFFSET;
-    /// MEIE
-    pub const MEIE_OFFSET: usize = 11;
-    pub const MEIE_FILTER: usize = 0b1 << MEIE_OFFSET;
-    /// LCOFIE
-    pub const LCOFIE_OFFSET: usize = 13;
-    pub const LCOFIE_FILTER: usize = 0b1 << LCOFIE_OFFSET;
+    super::csr_fields! {
+        /// SSIE
+        SSIE(SSIE_OFFSET, SSIE_FILTER) = bit 1;
+        /// VSSIE
+        VSSIE(VSSIE_OFFSET, VSSIE_FILTER) = bit 2;
+        /// MSIE
+        MSIE(MSIE_OFFSET, MSIE_FILTER) = bit 3;
+        /// STIE
+        STIE(STIE_OFFSET, STIE_FILTER) = bit 5;
+        /// VSTIE
+        VSTIE(VSTIE_OFFSET, VSTIE_FILTER) = bit 6;
+        /// MTIE
+        MTIE(MTIE_OFFSET, MTIE_FILTER) = bit 7;
+        /// SEIE
+        SEIE(SEIE_OFFSET, SEIE_FILTER) = bit 9;
+        /// VSEIE
+        VSEIE(VSEIE_OFFSET, VSEIE_FILTER) = bit 10;
+        /// MEIE
+        MEIE(MEIE_OFFSET, MEIE_FILTER) = bit 11;
+        /// SGEIE
+        SGEIE(SGEIE_OFFSET, SGEIE_FILTER) = bit 12;
+        /// LCOFIE
+        LCOFIE(LCOFIE_OFFSET, LCOFIE_FILTER) = bit 13;
+    }
+}
+
+// ——————————————————————— Environment Configuration ———————————————————————— //
+
+pub mod menvcfg {
+    /// STimecmp Enable: enables the Sstc extension for S-mode, which makes mip.STIP read-only and
+    /// driven by the comparison of `time` with `stimecmp`.
+    pub const STCE_OFFSET: usize = 63;
+    pub const STCE_FILTER: usize = 0b1 << STCE_OFFSET;
+    /// Hardware A/D bits Update Enable (Svadu): the page table walks set the accessed and dirty bits
+    /// instead of raising a page fault.
+    pub const ADUE_OFFSET: usize = 61;
+    pub const ADUE_FILTER: usize = 0b1 << ADUE_OFFSET;
+}
+
+// ————————————————————————————— Trigger Data 1 ————————————————————————————— //
+
+#[allow(unused)]
+pub mod tdata1 {
+    /// Type of the trigger
+    pub const TYPE_OFFSET: usize = 60;
+    pub const TYPE_FILTER: usize = 0b1111 << TYPE_OFFSET;
+    /// DMODE, only writable from debug mode
+    pub const DMODE_OFFSET: usize = 59;
+    pub const DMODE_FILTER: usize = 0b1 << DMODE_OFFSET;
+
+    // Trigger types
+    /// There is no trigger at this tselect
+    pub const TYPE_NONE: usize = 0;
+    /// Address/data match trigger
+    pub const TYPE_MCONTROL: usize = 2;
+    /// Address/data match trigger, as defined in Sdtrig 1.0
+    pub const TYPE_MCONTROL6: usize = 6;
+    /// The trigger exists but is disabled
+    pub const TYPE_DISABLED: usize = 15;
+
+    /// Returns the type of the trigger.
+    pub const fn get_type(tdata1: usize) -> usize {
+        (tdata1 & TYPE_FILTER) >> TYPE_OFFSET
+    }
 }
 
 // ———————————————————— Machine Trap-Vector Base-Address ———————————————————— //
@@ -425,31 +491,47 @@ pub mod mtvec {
     }
 }
 
-// ————————————————————————————— Hypervisor Status ————————————————————————————— //
+// ————————————————————————— Machine Counter-Enable ————————————————————————— //
 
-/// Constants for the Machine Status (mstatus) CSR.
+/// Constants for the Machine and Supervisor Counter-Enable (mcounteren and scounteren) CSRs.
+///
+/// The Machine Counter-Inhibit (mcountinhibit) CSR uses the same layout, with TM hardwired to 0.
 #[allow(unused)]
-pub mod hstatus {
-
-    // VSBE
-    pub const VSBE_OFFSET: usize = 5;
-    pub const VSBE_FILTER: usize = 0b1 << VSBE_OFFSET;
-
-    // TVM
-    pub const VTVM_OFFSET: usize = 20;
-    pub const VTVM_FILTER: usize = 0b1 << VTVM_OFFSET;
-
-    // TW
-    pub const VTW_OFFSET: usize = 21;
-    pub const VTW_FILTER: usize = 0b1 << VTW_OFFSET;
+pub mod mcounteren {
+    /// CY
+    pub const CY_OFFSET: usize = 0;
+    pub const CY_FILTER: usize = 0b1 << CY_OFFSET;
+    /// TM
+    pub const TM_OFFSET: usize = 1;
+    pub const TM_FILTER: usize = 0b1 << TM_OFFSET;
+    /// IR
+    pub const IR_OFFSET: usize = 2;
+    pub const IR_FILTER: usize = 0b1 << IR_OFFSET;
+}
 
-    // TSR
-    pub const VTSR_OFFSET: usize = 22;
-    pub const VTSR_FILTER: usize = 0b1 << VTSR_OFFSET;
+// ————————————————————————————— Hypervisor Status ————————————————————————————— //
 
-    // VSXL
-    pub const VSXL_OFFSET: usize = 32;
-    pub const VSXL_FILTER: usize = 0b11 << VSXL_OFFSET;
+/// Constants for the Hypervisor Status (hstatus) CSR.
+#[allow(unused)]
+pub mod hstatus {
+    super::csr_fields! {
+        /// VSBE
+        VSBE(VSBE_OFFSET, VSBE_FILTER) = bit 5;
+        /// GVA
+        GVA(GVA_OFFSET, GVA_FILTER) = bit 6;
+        /// SPV
+        SPV(SPV_OFFSET, SPV_FILTER) = bit 7;
+        /// SPVP
+        SPVP(SPVP_OFFSET, SPVP_FILTER) = bit 8;
+        /// TVM
+        VTVM(VTVM_OFFSET, VTVM_FILTER) = bit 20;
+        /// TW
+        VTW(VTW_OFFSET, VTW_FILTER) = bit 21;
+        /// TSR
+        VTSR(VTSR_OFFSET, VTSR_FILTER) = bit 22;
+        /// VSXL
+        VSXL(VSXL_OFFSET, VSXL_FILTER) = bits 32..=33;
+    }
 }
 
 // ——————————————————————— Width of Access Instructions —————————————————————— //
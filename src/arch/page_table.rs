@@ -0,0 +1,412 @@
+//! Software Page Table Walker
+//!
+//! When the firmware sets `mstatus.MPRV` its loads and stores are translated through the page
+//! tables pointed to by its `satp`, which Miralis must emulate. This module implements the Sv39,
+//! Sv48 and Sv57 translation algorithm of the privileged specification in software, independently of
+//! how the page table entries are read, such that Miralis can translate guest addresses itself and
+//! only rely on the hardware for the PMP checks of the resulting physical accesses.
+//!
+//! The accessed and dirty bits are never updated: as allowed by the specification (Svade), a page
+//! fault is raised instead and the guest is expected to set the bits in its handler. To behave the
+//! same as the hardware, Svadu is not exposed to the firmware (`menvcfg.ADUE` is read-only zero).
+
+use super::mstatus::{MXR_FILTER, SUM_FILTER};
+use super::{satp, ExtensionsCapability, MCause, Mode};
+
+const PAGE_OFFSET_BITS: usize = 12;
+pub const PAGE_SIZE: usize = 1 << PAGE_OFFSET_BITS;
+const VPN_BITS: usize = 9;
+const PTE_SIZE: usize = 8;
+
+mod pte {
+    pub const V: usize = 1 << 0;
+    pub const R: usize = 1 << 1;
+    pub const W: usize = 1 << 2;
+    pub const X: usize = 1 << 3;
+    pub const U: usize = 1 << 4;
+    pub const A: usize = 1 << 6;
+    pub const D: usize = 1 << 7;
+    pub const PPN_OFFSET: usize = 10;
+    pub const PPN_FILTER: usize = ((1 << 44) - 1) << PPN_OFFSET;
+    /// Bits 60:54, reserved for future standard use, and the PBMT (62:61) and N (63) bits which
+    /// are reserved as well because neither Svpbmt nor Svnapot are exposed to the firmware.
+    pub const RESERVED_FILTER: usize = 0x3ff << 54;
+}
+
+// —————————————————————————————— Translation ——————————————————————————————— //
+
+/// The kind of memory access being translated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessType {
+    Load,
+    Store,
+    Execute,
+}
+
+/// A translation failure, to be reported to the guest as the corresponding exception.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TranslationFault {
+    /// The page tables do not allow the access.
+    PageFault,
+    /// A page table entry could not be read.
+    AccessFault,
+}
+
+impl TranslationFault {
+    /// Returns the exception reported to the guest for this fault.
+    pub fn cause(self, access: AccessType) -> MCause {
+        match (self, access) {
+            (TranslationFault::PageFault, AccessType::Load) => MCause::LoadPageFault,
+            (TranslationFault::PageFault, AccessType::Store) => MCause::StorePageFault,
+            (TranslationFault::PageFault, AccessType::Execute) => MCause::InstrPageFault,
+            (TranslationFault::AccessFault, AccessType::Load) => MCause::LoadAccessFault,
+            (TranslationFault::AccessFault, AccessType::Store) => MCause::StoreAccessFault,
+            (TranslationFault::AccessFault, AccessType::Execute) => MCause::InstrAccessFault,
+        }
+    }
+}
+
+/// The state of the hart that determines how addresses are translated.
+#[derive(Clone, Copy, Debug)]
+pub struct TranslationConfig {
+    satp: usize,
+    /// The effective privilege mode of the access.
+    mode: Mode,
+    sum: bool,
+    mxr: bool,
+}
+
+impl TranslationConfig {
+    pub fn new(satp: usize, mode: Mode, mstatus: usize) -> Self {
+        TranslationConfig {
+            satp,
+            mode,
+            sum: mstatus & SUM_FILTER != 0,
+            mxr: mstatus & MXR_FILTER != 0,
+        }
+    }
+
+    /// Returns the number of levels of the page tables, or `None` if addresses are not translated.
+    ///
+    /// The satp is guest state, an unknown mode is reported as an access fault.
+    fn levels(&self) -> Result<Option<usize>, TranslationFault> {
+        if self.mode == Mode::M {
+            return Ok(None);
+        }
+        match self.satp >> satp::MODE_OFFSET {
+            satp::MODE_BARE => Ok(None),
+            satp::MODE_SV39 => Ok(Some(3)),
+            satp::MODE_SV48 => Ok(Some(4)),
+            satp::MODE_SV57 => Ok(Some(5)),
+            _ => Err(TranslationFault::AccessFault),
+        }
+    }
+
+    /// Returns true if addresses are translated through the page tables.
+    pub fn is_translated(&self) -> bool {
+        !matches!(self.levels(), Ok(None))
+    }
+}
+
+// ———————————————————————————— Page Table Walk ————————————————————————————— //
+
+/// Returns true if the satp mode is supported by the hardware, writes of other modes to satp have
+/// no effect.
+pub fn is_supported_satp_mode(satp: usize, extensions: &ExtensionsCapability) -> bool {
+    match satp >> satp::MODE_OFFSET {
+        satp::MODE_BARE | satp::MODE_SV39 => true,
+        satp::MODE_SV48 => extensions.has_sv48_extension,
+        satp::MODE_SV57 => extensions.has_sv57_extension,
+        _ => false,
+    }
+}
+
+/// Translates a virtual address into a physical address.
+///
+/// `read_pte` reads the page table entry at the given physical address, it returns an error if
+/// the entry can not be accessed (e.g. because of the PMP).
+pub fn translate(
+    vaddr: usize,
+    access: AccessType,
+    config: &TranslationConfig,
+    mut read_pte: impl FnMut(usize) -> Result<usize, ()>,
+) -> Result<usize, TranslationFault> {
+    let Some(levels) = config.levels()? else {
+        return Ok(vaddr);
+    };
+
+    // The upper bits of the address must match the most significant bit of the virtual address
+    let va_bits = PAGE_OFFSET_BITS + VPN_BITS * levels;
+    let upper = (vaddr as isize) >> (va_bits - 1);
+    if upper != 0 && upper != -1 {
+        return Err(TranslationFault::PageFault);
+    }
+
+    let mut table = (config.satp & satp::PPN_FILTER) << PAGE_OFFSET_BITS;
+    for level in (0..levels).rev() {
+        let vpn = (vaddr >> (PAGE_OFFSET_BITS + VPN_BITS * level)) & ((1 << VPN_BITS) - 1);
+        let entry = read_pte(table + vpn * PTE_SIZE).map_err(|_| TranslationFault::AccessFault)?;
+
+        if entry & pte::V == 0
+            || (entry & pte::R == 0 && entry & pte::W != 0)
+            || entry & pte::RESERVED_FILTER != 0
+        {
+            return Err(TranslationFault::PageFault);
+        }
+
+        let ppn = (entry & pte::PPN_FILTER) >> pte::PPN_OFFSET;
+        if entry & (pte::R | pte::X) == 0 {
+            // Pointer to the next level, the A, D and U bits are reserved for non-leaf entries
+            if entry & (pte::A | pte::D | pte::U) != 0 {
+                return Err(TranslationFault::PageFault);
+            }
+            table = ppn << PAGE_OFFSET_BITS;
+            continue;
+        }
+
+        check_leaf(entry, access, config)?;
+
+        // Superpages must be aligned to their size
+        let offset_bits = PAGE_OFFSET_BITS + VPN_BITS * level;
+        let superpage_ppn_filter = (1 << (VPN_BITS * level)) - 1;
+        if ppn & superpage_ppn_filter != 0 {
+            return Err(TranslationFault::PageFault);
+        }
+
+        let offset = vaddr & ((1 << offset_bits) - 1);
+        return Ok((ppn << PAGE_OFFSET_BITS) | offset);
+    }
+
+    // The last level contains a pointer
+    Err(TranslationFault::PageFault)
+}
+
+/// Checks the permissions and the accessed and dirty bits of a leaf entry.
+fn check_leaf(
+    entry: usize,
+    access: AccessType,
+    config: &TranslationConfig,
+) -> Result<(), TranslationFault> {
+    let permitted = match access {
+        AccessType::Load => entry & pte::R != 0 || (config.mxr && entry & pte::X != 0),
+        AccessType::Store => entry & pte::W != 0,
+        AccessType::Execute => entry & pte::X != 0,
+    };
+    let user_page = entry & pte::U != 0;
+    let privilege_ok = match config.mode {
+        Mode::U => user_page,
+        Mode::S => !user_page || (config.sum && access != AccessType::Execute),
+        Mode::M => true,
+    };
+    let accessed_ok = entry & pte::A != 0 && (access != AccessType::Store || entry & pte::D != 0);
+
+    if permitted && privilege_ok && accessed_ok {
+        Ok(())
+    } else {
+        Err(TranslationFault::PageFault)
+    }
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROOT: usize = 0x1000;
+    const LEVEL_1: usize = 0x2000;
+    const LEVEL_0: usize = 0x3000;
+    const SATP_SV39: usize = satp::MODE_SV39 << satp::MODE_OFFSET | ROOT >> PAGE_OFFSET_BITS;
+    const SATP_SV48: usize = satp::MODE_SV48 << satp::MODE_OFFSET | ROOT >> PAGE_OFFSET_BITS;
+
+    fn pointer(table: usize) -> usize {
+        (table >> PAGE_OFFSET_BITS) << pte::PPN_OFFSET | pte::V
+    }
+
+    fn leaf(paddr: usize, flags: usize) -> usize {
+        (paddr >> PAGE_OFFSET_BITS) << pte::PPN_OFFSET | flags | pte::V | pte::A
+    }
+
+    /// A small physical memory holding page tables, as (address, entry) pairs.
+    fn memory<'a>(entries: &'a [(usize, usize)]) -> impl FnMut(usize) -> Result<usize, ()> + 'a {
+        |addr| {
+            Ok(entries
+                .iter()
+                .find(|(entry_addr, _)| *entry_addr == addr)
+                .map(|(_, entry)| *entry)
+                .unwrap_or(0))
+        }
+    }
+
+    #[test]
+    fn bare_and_machine_mode() {
+        let bare = TranslationConfig::new(0, Mode::S, 0);
+        assert_eq!(
+            translate(0x8000_1234, AccessType::Load, &bare, memory(&[])),
+            Ok(0x8000_1234)
+        );
+
+        let machine = TranslationConfig::new(SATP_SV39, Mode::M, 0);
+        assert!(!machine.is_translated());
+        assert_eq!(
+            translate(0x1234, AccessType::Store, &machine, memory(&[])),
+            Ok(0x1234)
+        );
+    }
+
+    #[test]
+    fn satp_modes_match_hardware() {
+        let mut extensions = ExtensionsCapability {
+            has_h_extension: false,
+            has_s_extension: true,
+            _has_f_extension: false,
+            _has_d_extension: false,
+            _has_q_extension: false,
+            has_sstc_extension: false,
+            has_smepmp_extension: false,
+            has_sv48_extension: false,
+            has_sv57_extension: false,
+        };
+        assert!(is_supported_satp_mode(0, &extensions));
+        assert!(is_supported_satp_mode(SATP_SV39, &extensions));
+        assert!(!is_supported_satp_mode(SATP_SV48, &extensions));
+        assert!(!is_supported_satp_mode(
+            satp::MODE_SV57 << satp::MODE_OFFSET,
+            &extensions
+        ));
+        assert!(!is_supported_satp_mode(1 << satp::MODE_OFFSET, &extensions));
+
+        extensions.has_sv48_extension = true;
+        assert!(is_supported_satp_mode(SATP_SV48, &extensions));
+    }
+
+    #[test]
+    fn sv39_pages() {
+        // 0x4000_0000 -> 4KiB page at 0x8020_0000, 0x0 -> 1GiB superpage at 0x8000_0000
+        let entries = [
+            (ROOT + PTE_SIZE, pointer(LEVEL_1)),
+            (ROOT, leaf(0x8000_0000, pte::R | pte::W | pte::D)),
+            (LEVEL_1, pointer(LEVEL_0)),
+            (LEVEL_0, leaf(0x8020_0000, pte::R)),
+        ];
+        let config = TranslationConfig::new(SATP_SV39, Mode::S, 0);
+
+        assert_eq!(
+            translate(0x4000_0abc, AccessType::Load, &config, memory(&entries)),
+            Ok(0x8020_0abc)
+        );
+        assert_eq!(
+            translate(0x0012_3458, AccessType::Store, &config, memory(&entries)),
+            Ok(0x8012_3458)
+        );
+        // Read-only page
+        assert_eq!(
+            translate(0x4000_0abc, AccessType::Store, &config, memory(&entries)),
+            Err(TranslationFault::PageFault)
+        );
+        // Unmapped page
+        assert_eq!(
+            translate(0x4000_1000, AccessType::Load, &config, memory(&entries)),
+            Err(TranslationFault::PageFault)
+        );
+        // Svpbmt is not exposed, the PBMT bits are reserved
+        let entries = [(ROOT, leaf(0x8000_0000, pte::R | 1 << 61))];
+        assert_eq!(
+            translate(0x1000, AccessType::Load, &config, memory(&entries)),
+            Err(TranslationFault::PageFault)
+        );
+        // Non-canonical address
+        assert_eq!(
+            translate(
+                0x0000_0040_0000_0000,
+                AccessType::Load,
+                &config,
+                memory(&entries)
+            ),
+            Err(TranslationFault::PageFault)
+        );
+    }
+
+    #[test]
+    fn sv48_misaligned_superpage() {
+        // 2MiB superpage which is not aligned on 2MiB
+        let entries = [
+            (ROOT, pointer(LEVEL_1)),
+            (LEVEL_1, pointer(LEVEL_0)),
+            (LEVEL_0, leaf(0x8000_1000, pte::R | pte::X)),
+        ];
+        let config = TranslationConfig::new(SATP_SV48, Mode::S, 0);
+        assert_eq!(
+            translate(0x1000, AccessType::Load, &config, memory(&entries)),
+            Err(TranslationFault::PageFault)
+        );
+
+        let entries = [
+            (ROOT, pointer(LEVEL_1)),
+            (LEVEL_1, pointer(LEVEL_0)),
+            (LEVEL_0, leaf(0x8020_0000, pte::R | pte::X)),
+        ];
+        assert_eq!(
+            translate(0x1_2345, AccessType::Execute, &config, memory(&entries)),
+            Ok(0x8021_2345)
+        );
+    }
+
+    #[test]
+    fn privileges() {
+        let entries = [
+            (ROOT, pointer(LEVEL_1)),
+            (LEVEL_1, pointer(LEVEL_0)),
+            (LEVEL_0, leaf(0x8000_0000, pte::U | pte::X)),
+            (LEVEL_0 + PTE_SIZE, leaf(0x8000_1000, pte::R | pte::W)),
+        ];
+        let user = TranslationConfig::new(SATP_SV39, Mode::U, 0);
+        let supervisor = TranslationConfig::new(SATP_SV39, Mode::S, 0);
+        let supervisor_sum = TranslationConfig::new(SATP_SV39, Mode::S, SUM_FILTER);
+        let supervisor_mxr = TranslationConfig::new(SATP_SV39, Mode::S, SUM_FILTER | MXR_FILTER);
+
+        assert_eq!(
+            translate(0x10, AccessType::Execute, &user, memory(&entries)),
+            Ok(0x8000_0010)
+        );
+        assert!(translate(0x10, AccessType::Load, &user, memory(&entries)).is_err());
+        assert!(translate(0x1010, AccessType::Load, &user, memory(&entries)).is_err());
+
+        // Supervisor accesses to user pages require SUM, and are never executable
+        assert!(translate(0x10, AccessType::Execute, &supervisor_sum, memory(&entries)).is_err());
+        assert!(translate(0x10, AccessType::Load, &supervisor_sum, memory(&entries)).is_err());
+        assert_eq!(
+            translate(0x10, AccessType::Load, &supervisor_mxr, memory(&entries)),
+            Ok(0x8000_0010)
+        );
+        assert!(translate(0x10, AccessType::Load, &supervisor, memory(&entries)).is_err());
+
+        // The dirty bit is not set
+        assert_eq!(
+            translate(0x1010, AccessType::Store, &supervisor, memory(&entries)),
+            Err(TranslationFault::PageFault)
+        );
+        assert_eq!(
+            translate(0x1010, AccessType::Load, &supervisor, memory(&entries)),
+            Ok(0x8000_1010)
+        );
+    }
+
+    #[test]
+    fn inaccessible_page_table() {
+        let config = TranslationConfig::new(SATP_SV39, Mode::S, 0);
+        let fault = translate(0x1000, AccessType::Store, &config, |_| Err(()));
+        assert_eq!(fault, Err(TranslationFault::AccessFault));
+        assert_eq!(
+            fault.unwrap_err().cause(AccessType::Store),
+            MCause::StoreAccessFault
+        );
+
+        // The satp mode is guest controlled and might not be valid
+        let config = TranslationConfig::new(1 << satp::MODE_OFFSET, Mode::S, 0);
+        assert_eq!(
+            translate(0x1000, AccessType::Load, &config, memory(&[])),
+            Err(TranslationFault::AccessFault)
+        );
+    }
+}
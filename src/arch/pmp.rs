@@ -9,16 +9,20 @@ use core::fmt::Formatter;
 use super::Architecture;
 use crate::arch::pmp::pmpcfg::{INACTIVE, NAPOT, TOR};
 use crate::arch::pmp::pmplayout::{
-    ALL_CATCH_OFFSET, DEVICES_OFFSET, INACTIVE_ENTRY_OFFSET, MIRALIS_OFFSET, MIRALIS_TOTAL_PMP,
-    POLICY_OFFSET, POLICY_SIZE, VIRTUAL_PMP_OFFSET,
+    ALL_CATCH_OFFSET, DEVICES_OFFSET, DEVICES_SIZE, INACTIVE_ENTRY_SIZE, LAST_ENTRY_SIZE,
+    MIRALIS_OFFSET, POLICY_OFFSET, POLICY_SIZE,
 };
 use crate::arch::Arch;
 use crate::config;
+use crate::device::registry::DeviceRegistry;
+use crate::device::PayloadAccess;
+use crate::fault::{self, Fault};
 use crate::platform::{Plat, Platform};
 
 // ——————————————————————————— PMP Configuration ———————————————————————————— //
 
 pub mod pmplayout {
+    use crate::platform::{Plat, Platform};
     use crate::policy::{Policy, PolicyModule};
 
     /// First entry used to catch all pmp entries
@@ -30,21 +34,22 @@ pub mod pmplayout {
     pub const MIRALIS_OFFSET: usize = ALL_CATCH_SIZE;
 
     /// PMP entries used to protect the devices
-    pub const DEVICES_SIZE: usize = 2;
+    pub const DEVICES_SIZE: usize = Plat::NB_VIRT_DEVICES;
     pub const DEVICES_OFFSET: usize = MIRALIS_OFFSET + MIRALIS_SIZE;
 
-    /// PMP entries used by the policy
+    /// PMP entries statically reserved by the policy
+    ///
+    /// Policies can reserve additional entries at init time, which are placed right after these
+    /// ones. The offsets of the following entries are therefore only known at runtime, see
+    /// [PmpGroup::reserve_policy_pmps](super::PmpGroup::reserve_policy_pmps).
     pub const POLICY_SIZE: usize = Policy::NUMBER_PMPS;
     pub const POLICY_OFFSET: usize = DEVICES_OFFSET + DEVICES_SIZE;
 
-    /// Last PMP entry used in to emulate TOR correctly in the firmware
+    /// PMP entry used in to emulate TOR correctly in the firmware, right after the policy entries
     pub const INACTIVE_ENTRY_SIZE: usize = 1;
-    pub const INACTIVE_ENTRY_OFFSET: usize = POLICY_OFFSET + POLICY_SIZE;
 
-    /// Offset at which the virtual PMPs can start
-    pub const VIRTUAL_PMP_OFFSET: usize = INACTIVE_ENTRY_OFFSET + INACTIVE_ENTRY_SIZE;
-    /// At the very end, there is a last PMP entry
-    pub const MIRALIS_TOTAL_PMP: usize = VIRTUAL_PMP_OFFSET + 1;
+    /// The virtual PMPs follow the inactive entry, and at the very end there is a last PMP entry
+    pub const LAST_ENTRY_SIZE: usize = 1;
 }
 
 /// PMP Configuration
@@ -81,6 +86,37 @@ pub mod pmpcfg {
     pub const VALID_BITS: u8 = RWX | NAPOT | L;
 }
 
+// —————————————————————————— Smepmp Configuration —————————————————————————— //
+
+/// Machine Security Configuration
+///
+/// Hold constants for the mseccfg CSR of the Smepmp extension, which lets PMP rules restrict
+/// M-mode accesses.
+pub mod mseccfg {
+    /// Machine Mode Lockdown: locked rules become M-mode only, and M-mode can not execute code
+    /// outside of them. Sticky until reset.
+    pub const MML: usize = 0b001;
+    /// Machine Mode Whitelist Policy: M-mode accesses matching no rule are denied. Sticky until
+    /// reset.
+    pub const MMWP: usize = 0b010;
+    /// Rule Locking Bypass: locked rules can be modified and removed.
+    pub const RLB: usize = 0b100;
+
+    /// Returns the value of mseccfg after writing `value` to `current`.
+    ///
+    /// MML and MMWP can not be cleared once set, and RLB can not be set anymore once it has been
+    /// cleared while some rule is locked.
+    pub const fn write(current: usize, value: usize, has_locked_rules: bool) -> usize {
+        let sticky = current & (MML | MMWP);
+        let rlb = if current & RLB == 0 && has_locked_rules {
+            0
+        } else {
+            value & RLB
+        };
+        sticky | (value & (MML | MMWP)) | rlb
+    }
+}
+
 // —————————————————————————————— PMP Address ——————————————————————————————— //
 
 /// Build a valid NAPOT pmpaddr value from a provided start and size.
@@ -122,8 +158,25 @@ pub struct PmpGroup {
     pub nb_pmp: u8,
     /// Number of virtual PMP available
     pub nb_virt_pmp: usize,
+    /// Number of physical PMP entries backing the virtual PMPs.
+    ///
+    /// Lower than `nb_virt_pmp` when PMP coalescing is enabled and the firmware is exposed more
+    /// entries than left by Miralis.
+    pub virt_pmp_slots: usize,
     /// The offset of the virtual PMP registers, compared to physical PMP.
     pub virt_pmp_offset: usize,
+    /// Number of PMP entries reserved for the policy, starting at `POLICY_OFFSET`.
+    pub policy_pmps: usize,
+    /// Bitmap of the policy PMP entries in use, the statically reserved ones are always in use.
+    policy_pmps_used: u64,
+    /// Whether the hardware implements Smepmp, in which case `mseccfg` is programmed along with
+    /// the PMP entries.
+    smepmp: bool,
+    /// The value of the mseccfg CSR, see [mseccfg].
+    mseccfg: usize,
+    /// Whether the virtual PMP layout has been exposed to the firmware, after which the policy can
+    /// no longer reserve entries.
+    layout_frozen: bool,
 }
 
 /// A struct that can be consumed to flush the caches, making the latest PMP configuration
@@ -160,13 +213,23 @@ impl PmpGroup {
             pmpcfg: [0; 8],
             nb_pmp: nb_pmp as u8,
             nb_virt_pmp: 0,
+            virt_pmp_slots: 0,
             virt_pmp_offset: 0,
+            policy_pmps: POLICY_SIZE,
+            policy_pmps_used: 0,
+            layout_frozen: false,
+            smepmp: false,
+            mseccfg: 0,
         }
     }
 
-    pub fn init_pmp_group(nb_pmp: usize) -> PmpGroup {
+    pub fn init_pmp_group(nb_pmp: usize, virtual_devices: &DeviceRegistry) -> PmpGroup {
         let mut pmp = Self::new(nb_pmp);
-        let virtual_devices = Plat::create_virtual_devices();
+        assert_eq!(
+            virtual_devices.iter().count(),
+            DEVICES_SIZE,
+            "The platform must register as many virtual devices as it declares"
+        );
 
         // Configure PMP registers, if available
         if pmp.nb_pmp >= 8 {
@@ -178,49 +241,135 @@ impl PmpGroup {
             pmp.set_napot(MIRALIS_OFFSET, start, size, pmpcfg::NO_PERMISSIONS);
 
             // Protect virtual devices
-            pmp.set_napot(
-                DEVICES_OFFSET,
-                virtual_devices[0].start_addr,
-                virtual_devices[0].size,
-                pmpcfg::NO_PERMISSIONS,
-            );
-
-            pmp.set_napot(
-                DEVICES_OFFSET + 1,
-                virtual_devices[1].start_addr,
-                virtual_devices[1].size,
-                pmpcfg::NO_PERMISSIONS,
-            );
+            for (idx, device) in virtual_devices.iter().enumerate() {
+                pmp.set_napot(
+                    DEVICES_OFFSET + idx,
+                    device.start_addr,
+                    device.size,
+                    pmpcfg::NO_PERMISSIONS,
+                );
+            }
 
-            // This PMP entry is used by the policy module for its own purpose
+            // These PMP entries are used by the policy module for its own purpose
             #[allow(clippy::reversed_empty_ranges)]
             for idx in 0..POLICY_SIZE {
                 pmp.set_inactive(POLICY_OFFSET + idx, 0);
+                pmp.policy_pmps_used |= 1 << idx;
             }
 
-            // Add an inactive 0 entry so that the next PMP sees 0 with TOR configuration
-            pmp.set_inactive(INACTIVE_ENTRY_OFFSET, 0);
-
             // Finally, set the last PMP to grant access to the whole memory
             pmp.set_napot((pmp.nb_pmp - 1) as usize, 0, usize::MAX, pmpcfg::RWX);
+        }
 
-            // Compute the number of virtual PMPs available
-            // It's whatever is left after setting pmp's for devices, pmp for address translation,
-            // inactive entry and the last pmp to allow all the access
-            let remaining_pmp_entries = pmp.nb_pmp as usize - MIRALIS_TOTAL_PMP;
-            if let Some(max_virt_pmp) = config::VCPU_MAX_PMP {
-                pmp.nb_virt_pmp = core::cmp::min(remaining_pmp_entries, max_virt_pmp);
-            } else {
-                pmp.nb_virt_pmp = remaining_pmp_entries;
-            }
+        pmp.update_layout();
+        pmp
+    }
+
+    /// Places the virtual PMPs after the policy entries, and computes how many are available.
+    fn update_layout(&mut self) {
+        let inactive_entry_offset = POLICY_OFFSET + self.policy_pmps;
+        self.virt_pmp_offset = inactive_entry_offset + INACTIVE_ENTRY_SIZE;
+        if self.nb_pmp < 8 {
+            self.nb_virt_pmp = 0;
+            self.virt_pmp_slots = 0;
+            return;
+        }
+
+        // Add an inactive 0 entry so that the next PMP sees 0 with TOR configuration
+        self.set_inactive(inactive_entry_offset, 0);
+
+        // The number of virtual PMPs available is whatever is left after setting pmp's for
+        // devices, the policy, the inactive entry and the last pmp to allow all the access
+        let remaining_pmp_entries = self.free_pmp_entries();
+        // With coalescing the firmware sees as many PMPs as the hardware implements, and the
+        // virtual regions are merged to fit in the remaining entries.
+        let exposed_pmp_entries = if config::VCPU_PMP_COALESCING {
+            self.nb_pmp as usize
         } else {
-            pmp.nb_virt_pmp = 0;
+            remaining_pmp_entries
+        };
+        if let Some(max_virt_pmp) = config::VCPU_MAX_PMP {
+            self.nb_virt_pmp = core::cmp::min(exposed_pmp_entries, max_virt_pmp);
+        } else {
+            self.nb_virt_pmp = exposed_pmp_entries;
         }
+        self.virt_pmp_slots = core::cmp::min(remaining_pmp_entries, self.nb_virt_pmp);
+    }
 
-        // Finally we can set the PMP offset
-        pmp.virt_pmp_offset = VIRTUAL_PMP_OFFSET;
+    /// Number of physical PMP entries not used by Miralis or the policy.
+    fn free_pmp_entries(&self) -> usize {
+        (self.nb_pmp as usize).saturating_sub(self.virt_pmp_offset + LAST_ENTRY_SIZE)
+    }
 
-        pmp
+    /// Reserves `count` additional PMP entries for the policy, which can then be requested with
+    /// [Self::request_policy_pmp].
+    ///
+    /// Entries can only be reserved before the virtual PMP layout is exposed to the firmware, and
+    /// never at the expense of the entries the firmware is configured with (`vcpu.max_pmp`).
+    pub fn reserve_policy_pmps(&mut self, count: usize) -> Result<(), &'static str> {
+        if self.layout_frozen {
+            return Err("The PMP layout has already been exposed to the firmware");
+        }
+        if self.nb_pmp < 8 {
+            return Err("Not enough PMP entries to reserve entries for the policy");
+        }
+        let guaranteed_virt_pmp = config::VCPU_MAX_PMP.unwrap_or(0);
+        if self.free_pmp_entries() < count + guaranteed_virt_pmp {
+            return Err("Not enough PMP entries left for the firmware");
+        }
+
+        self.policy_pmps += count;
+        self.update_layout();
+        Ok(())
+    }
+
+    /// Requests an unused policy PMP entry, returns its index relative to `POLICY_OFFSET`.
+    ///
+    /// Before the layout is exposed to the firmware a new entry is reserved if none is available,
+    /// afterward only entries previously reserved and released can be handed out.
+    pub fn request_policy_pmp(&mut self) -> Option<usize> {
+        let idx = match (0..self.policy_pmps).find(|idx| self.policy_pmps_used & (1 << idx) == 0) {
+            Some(idx) => idx,
+            None => {
+                self.reserve_policy_pmps(1).ok()?;
+                self.policy_pmps - 1
+            }
+        };
+
+        self.policy_pmps_used |= 1 << idx;
+        self.set_inactive(POLICY_OFFSET + idx, 0);
+        Some(idx)
+    }
+
+    /// Releases a policy PMP entry obtained with [Self::request_policy_pmp].
+    ///
+    /// The entry is deactivated and can be requested again. Before the layout is exposed to the
+    /// firmware, unused entries at the end of the policy range are handed back to the firmware.
+    pub fn release_policy_pmp(&mut self, idx: usize) {
+        assert!(
+            (POLICY_SIZE..self.policy_pmps).contains(&idx),
+            "Policy is releasing a PMP entry it did not request: {}",
+            idx
+        );
+
+        self.policy_pmps_used &= !(1 << idx);
+        self.set_inactive(POLICY_OFFSET + idx, 0);
+        if self.layout_frozen {
+            return;
+        }
+
+        while self.policy_pmps > POLICY_SIZE
+            && self.policy_pmps_used & (1 << (self.policy_pmps - 1)) == 0
+        {
+            self.policy_pmps -= 1;
+        }
+        self.update_layout();
+    }
+
+    /// Prevents further changes to the virtual PMP layout, must be called before exposing the
+    /// number of virtual PMPs to the firmware.
+    pub fn freeze_layout(&mut self) {
+        self.layout_frozen = true;
     }
 
     /// This function builds a PMP Napot entry, note that the caller must only set the permissions bits and don't have to care about the low level formatting details to build the napot entry.
@@ -250,24 +399,71 @@ impl PmpGroup {
     fn set(&mut self, idx: usize, addr: usize, cfg: u8) {
         // Sanitize CFG
         let cfg = cfg & pmpcfg::VALID_BITS;
-        assert!(cfg & pmpcfg::L == 0, "Lock bit not yet supported on PMPs");
+        // Locked entries can only be updated afterward with the rule locking bypass
+        assert!(
+            cfg & pmpcfg::L == 0 || self.mseccfg & mseccfg::RLB != 0,
+            "Lock bit is only supported with Smepmp rule locking bypass"
+        );
 
         self.pmpaddr[idx] = addr;
         self.set_pmpcfg(idx, cfg);
     }
 
     pub fn set_from_policy(&mut self, idx: usize, addr: usize, cfg: u8) {
-        #[allow(clippy::absurd_extreme_comparisons)]
-        if idx >= POLICY_SIZE {
+        if idx >= self.policy_pmps {
             panic!(
                 "Policy isn't writing to its pmp entries index: {} number of registers: {} ",
-                idx, POLICY_SIZE
+                idx, self.policy_pmps
             );
         }
 
         self.set(POLICY_OFFSET + idx, addr, cfg);
     }
 
+    /// Marks the hardware as implementing Smepmp, mseccfg is then written along with the PMP
+    /// entries.
+    pub fn enable_smepmp(&mut self) {
+        self.smepmp = true;
+    }
+
+    /// Returns true if the hardware implements Smepmp.
+    pub fn has_smepmp(&self) -> bool {
+        self.smepmp
+    }
+
+    /// Returns the value of the mseccfg register.
+    pub fn mseccfg(&self) -> usize {
+        self.mseccfg
+    }
+
+    /// Updates mseccfg, with the same restrictions as the hardware on sticky bits.
+    ///
+    /// Setting MML turns the locked entries into M-mode only rules and prevents M-mode from
+    /// executing code outside of them, therefore Miralis must cover its own memory with locked
+    /// entries first.
+    pub fn set_mseccfg(&mut self, value: usize) -> Result<(), &'static str> {
+        if !self.smepmp {
+            return Err("Smepmp is not supported");
+        }
+
+        let has_locked_rules =
+            (0..self.nb_pmp as usize).any(|idx| self.get_cfg(idx) & pmpcfg::L != 0);
+        self.mseccfg = mseccfg::write(self.mseccfg, value, has_locked_rules);
+        Ok(())
+    }
+
+    /// Locks a PMP entry, such that its rule also applies to M-mode, or only to M-mode with MML.
+    ///
+    /// Requires the Smepmp rule locking bypass, such that Miralis can still update the entry.
+    pub fn lock(&mut self, idx: usize) -> Result<(), &'static str> {
+        if self.mseccfg & mseccfg::RLB == 0 {
+            return Err("Locking PMP entries requires the rule locking bypass");
+        }
+
+        self.set_pmpcfg(idx, self.get_cfg(idx) | pmpcfg::L);
+        Ok(())
+    }
+
     /// Returns the array of pmpaddr registers.
     pub fn pmpaddr(&self) -> &[usize; 64] {
         &self.pmpaddr
@@ -328,6 +524,63 @@ impl PmpGroup {
         }
     }
 
+    /// Enables or disables the protection of the virtual devices shared with the payload.
+    ///
+    /// Those devices must only be protected while the firmware runs, the payload accesses the
+    /// physical device directly. Only the pmpcfg is updated, so that a following TOR entry keeps
+    /// the same base address.
+    pub fn protect_shared_devices(&mut self, devices: &DeviceRegistry, protect: bool) {
+        if self.nb_pmp < 8 {
+            // Devices are not protected on systems with few PMPs
+            return;
+        }
+
+        for (idx, device) in devices.iter().enumerate() {
+            if device.payload_access == PayloadAccess::Direct {
+                let cfg = if protect {
+                    NAPOT | pmpcfg::NO_PERMISSIONS
+                } else {
+                    INACTIVE
+                };
+                self.set_pmpcfg(DEVICES_OFFSET + idx, cfg);
+            }
+        }
+    }
+
+    /// Returns true if the firmware is exposed more PMP entries than physically available.
+    pub fn is_overcommitted(&self) -> bool {
+        self.nb_virt_pmp > self.virt_pmp_slots
+    }
+
+    /// Loads the virtual PMP registers into the physical entries reserved for them.
+    ///
+    /// If the vCPU exposes more PMP entries than reserved the virtual regions are coalesced first,
+    /// which fails if they still do not fit.
+    pub fn load_virtual_pmp(
+        &mut self,
+        pmpaddr: &[usize; 64],
+        pmpcfg: &[usize; 8],
+        nb_virt_pmp: usize,
+    ) -> Result<(), PmpOverflow> {
+        if nb_virt_pmp <= self.virt_pmp_slots {
+            self.load_with_offset(pmpaddr, pmpcfg, self.virt_pmp_offset, nb_virt_pmp);
+            return Ok(());
+        }
+
+        let coalesced = coalesce_virtual_pmp(pmpaddr, pmpcfg, nb_virt_pmp, self.virt_pmp_slots)?;
+        log::trace!(
+            "Coalesced {} virtual PMPs into {} entries",
+            nb_virt_pmp,
+            coalesced.nb_entries()
+        );
+        for idx in 0..self.virt_pmp_slots {
+            let (addr, cfg) = coalesced.get(idx);
+            self.pmpaddr[self.virt_pmp_offset + idx] = addr;
+            self.set_pmpcfg(self.virt_pmp_offset + idx, cfg);
+        }
+        Ok(())
+    }
+
     /// Clears `nb_pmp` PMP registers starting from `start`.
     pub fn clear_range(&mut self, start: usize, nb_pmp: usize) {
         for idx in 0..nb_pmp {
@@ -337,6 +590,136 @@ impl PmpGroup {
     }
 }
 
+// ————————————————————————————— PMP Coalescing ————————————————————————————— //
+
+/// Error returned when the virtual PMP regions do not fit in the physical PMP entries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PmpOverflow {
+    /// Start address of the first virtual region that could not be mapped.
+    pub addr: usize,
+}
+
+/// Physical PMP entries enforcing the same permissions as a set of virtual PMP entries.
+pub struct CoalescedPmp {
+    pmpaddr: [usize; 64],
+    cfg: [u8; 64],
+    len: usize,
+}
+
+impl CoalescedPmp {
+    /// Returns the pmpaddr and pmpcfg of an entry, entries past the end are inactive.
+    pub fn get(&self, idx: usize) -> (usize, u8) {
+        if idx < self.len {
+            (self.pmpaddr[idx], self.cfg[idx])
+        } else {
+            (0, INACTIVE)
+        }
+    }
+
+    /// Returns the number of physical entries in use.
+    pub fn nb_entries(&self) -> usize {
+        self.len
+    }
+
+    fn push(&mut self, addr: usize, cfg: u8) {
+        self.pmpaddr[self.len] = addr;
+        self.cfg[self.len] = cfg;
+        self.len += 1;
+    }
+}
+
+/// Computes physical PMP entries enforcing the same permissions as the virtual ones, using at most
+/// `nb_slots` entries.
+///
+/// The virtual entries are decoded into regions in priority order, and consecutive regions with
+/// the same permissions which overlap or are contiguous are merged. Merging does not change the
+/// permissions of any address, because no other entry sits in between in priority order. Each
+/// region is then encoded with a single TOR entry if it starts where the previous entry ends, a
+/// single NA4 or NAPOT entry if naturally aligned, and an inactive base entry followed by a TOR
+/// entry otherwise.
+///
+/// Regions are handled in units of 4 bytes, as in pmpaddr, such that the top of the address space
+/// can be represented. The physical entries are expected to follow an entry with address 0.
+pub fn coalesce_virtual_pmp(
+    pmpaddr: &[usize; 64],
+    pmpcfg: &[usize; 8],
+    nb_virt_pmp: usize,
+    nb_slots: usize,
+) -> Result<CoalescedPmp, PmpOverflow> {
+    let mut regions = [(0usize, 0usize, 0u8); 64];
+    let mut nb_regions = 0;
+    let mut prev_addr = 0;
+
+    // Decode and merge the regions
+    for (idx, &addr) in pmpaddr.iter().enumerate().take(nb_virt_pmp) {
+        let cfg = ((pmpcfg[idx / 8] >> ((idx % 8) * 8)) & 0xff) as u8;
+        let base = prev_addr;
+        prev_addr = addr;
+
+        let (start, end) = match cfg & pmpcfg::A_MASK {
+            pmpcfg::TOR if base < addr => (base, addr),
+            pmpcfg::NA4 => (addr, addr.saturating_add(1)),
+            pmpcfg::NAPOT => {
+                let trailing_ones = addr.trailing_ones() as usize;
+                if trailing_ones >= usize::BITS as usize - 2 {
+                    (0, usize::MAX)
+                } else {
+                    let start = addr & !((1 << trailing_ones) - 1);
+                    (start, start + (1 << (trailing_ones + 1)))
+                }
+            }
+            // Inactive or empty entry
+            _ => continue,
+        };
+        let perms = cfg & pmpcfg::RWX;
+
+        if nb_regions > 0 {
+            let last = &mut regions[nb_regions - 1];
+            if last.2 == perms && last.0 <= end && start <= last.1 {
+                *last = (last.0.min(start), last.1.max(end), perms);
+                continue;
+            }
+        }
+        regions[nb_regions] = (start, end, perms);
+        nb_regions += 1;
+    }
+
+    // Encode the regions
+    let mut coalesced = CoalescedPmp {
+        pmpaddr: [0; 64],
+        cfg: [0; 64],
+        len: 0,
+    };
+    let mut prev_addr = 0;
+    for &(start, end, perms) in &regions[..nb_regions] {
+        let size = end - start;
+        let is_napot = size.is_power_of_two() && start & (size - 1) == 0;
+        let needed = if start == prev_addr || is_napot { 1 } else { 2 };
+        if coalesced.len + needed > nb_slots {
+            return Err(PmpOverflow {
+                addr: start.wrapping_shl(2),
+            });
+        }
+
+        if start == prev_addr {
+            coalesced.push(end, perms | TOR);
+            prev_addr = end;
+        } else if size == 1 {
+            coalesced.push(start, perms | pmpcfg::NA4);
+            prev_addr = start;
+        } else if is_napot {
+            prev_addr = start | ((size >> 1) - 1);
+            coalesced.push(prev_addr, perms | NAPOT);
+        } else {
+            coalesced.push(start, INACTIVE);
+            coalesced.push(end, perms | TOR);
+            prev_addr = end;
+        }
+    }
+
+    Ok(coalesced)
+}
+
 // ————————————————————————————— Memory Segment ————————————————————————————— //
 
 /// A segment of memory.
@@ -530,11 +913,107 @@ mod tests {
             assert_eq!(actual, expected, "Unexpected PMP region")
         }
     }
+
+    #[test]
+    fn coalescing() {
+        use pmpcfg::*;
+
+        // Four contiguous regions, the first three with the same permissions
+        let mut pmps: PmpGroup = PmpGroup::new(8);
+        pmps.set(0, 0x1000 >> 2, INACTIVE);
+        pmps.set(1, 0x2000 >> 2, R | W | TOR);
+        pmps.set(2, 0x3000 >> 2, R | W | TOR);
+        pmps.set(3, (0x3000 >> 2) | 0x1ff, R | W | NAPOT); // [0x3000, 0x4000)
+        pmps.set(4, (0x4000 >> 2) | 0x1ff, R | NAPOT); // [0x4000, 0x5000)
+        let coalesced = coalesce_virtual_pmp(&pmps.pmpaddr, &pmps.pmpcfg, 8, 3).unwrap();
+        assert_eq!(coalesced.nb_entries(), 3);
+        assert_eq!(coalesced.get(0), (0x1000 >> 2, INACTIVE));
+        assert_eq!(coalesced.get(1), (0x4000 >> 2, R | W | TOR));
+        // The last region starts where the previous one ends, a single TOR is enough
+        assert_eq!(coalesced.get(2), (0x5000 >> 2, R | TOR));
+        assert_eq!(coalesced.get(3), (0, INACTIVE));
+
+        // Aligned regions are encoded as NAPOT
+        let mut pmps: PmpGroup = PmpGroup::new(8);
+        pmps.set(0, 0x8000 >> 2, INACTIVE);
+        pmps.set(1, 0x9000 >> 2, RWX | TOR);
+        let coalesced = coalesce_virtual_pmp(&pmps.pmpaddr, &pmps.pmpcfg, 8, 1).unwrap();
+        assert_eq!(coalesced.get(0), ((0x8000 >> 2) | 0x1ff, RWX | NAPOT));
+
+        // Regions with different permissions can not be merged
+        let mut pmps: PmpGroup = PmpGroup::new(8);
+        pmps.set(0, 0x1000 >> 2, INACTIVE);
+        pmps.set(1, 0x3000 >> 2, R | TOR);
+        pmps.set(2, 0x7000 >> 2, W | TOR);
+        assert_eq!(
+            coalesce_virtual_pmp(&pmps.pmpaddr, &pmps.pmpcfg, 8, 2).err(),
+            Some(PmpOverflow { addr: 0x3000 })
+        );
+    }
+
+    #[test]
+    fn smepmp() {
+        use mseccfg::*;
+
+        let mut pmps: PmpGroup = PmpGroup::new(16);
+        assert!(pmps.set_mseccfg(RLB).is_err());
+        assert!(pmps.lock(0).is_err());
+
+        // Entries can be locked with the rule locking bypass
+        pmps.enable_smepmp();
+        pmps.set_mseccfg(RLB).unwrap();
+        pmps.set_tor(1, 0x1000, pmpcfg::R | pmpcfg::X);
+        pmps.lock(1).unwrap();
+        assert_eq!(pmps.get_cfg(1), pmpcfg::L | pmpcfg::TOR | pmpcfg::R | pmpcfg::X);
+
+        // MML and MMWP are sticky, and RLB can not be set again once cleared with locked rules
+        pmps.set_mseccfg(MML | MMWP).unwrap();
+        assert_eq!(pmps.mseccfg(), MML | MMWP);
+        pmps.set_mseccfg(RLB).unwrap();
+        assert_eq!(pmps.mseccfg(), MML | MMWP);
+        assert_eq!(write(0, RLB, false), RLB);
+    }
+
+    #[test]
+    fn policy_pmp_reservation() {
+        let devices = DeviceRegistry::from_platform();
+        let mut pmps = PmpGroup::init_pmp_group(16, &devices);
+        let virt_pmp_offset = pmps.virt_pmp_offset;
+        let virt_pmp_slots = pmps.virt_pmp_slots;
+
+        // Entries requested at init time are taken from the virtual PMPs
+        let first = pmps.request_policy_pmp().unwrap();
+        let second = pmps.request_policy_pmp().unwrap();
+        assert_eq!((first, second), (POLICY_SIZE, POLICY_SIZE + 1));
+        assert_eq!(pmps.virt_pmp_offset, virt_pmp_offset + 2);
+        assert_eq!(pmps.virt_pmp_slots, virt_pmp_slots - 2);
+        assert_eq!(pmps.get_cfg(pmps.virt_pmp_offset - 1), pmpcfg::INACTIVE);
+
+        // Entries released at the end of the policy range are handed back to the firmware
+        pmps.release_policy_pmp(second);
+        assert_eq!(pmps.virt_pmp_offset, virt_pmp_offset + 1);
+
+        // Once the layout is frozen only released entries can be requested again
+        pmps.freeze_layout();
+        pmps.release_policy_pmp(first);
+        assert_eq!(pmps.virt_pmp_offset, virt_pmp_offset + 1);
+        assert_eq!(pmps.request_policy_pmp(), Some(first));
+        assert_eq!(pmps.request_policy_pmp(), None);
+        assert!(pmps.reserve_policy_pmps(1).is_err());
+
+        // The policy can not reserve more entries than available
+        let mut pmps = PmpGroup::init_pmp_group(16, &devices);
+        assert!(pmps.reserve_policy_pmps(virt_pmp_slots + 1).is_err());
+        assert_eq!(pmps.virt_pmp_offset, virt_pmp_offset);
+    }
 }
 
 impl PmpFlush {
     /// Flush the caches, which is required for PMP changes to take effect.
     pub fn flush(self) {
+        if fault::inject(Fault::PmpFlushFailure) {
+            return;
+        }
         unsafe { Arch::sfencevma(None, None) }
     }
 
@@ -125,6 +125,17 @@ pub enum Csr {
     /// Machine bad address or instruction
     Mtval,
 
+    // Unprivileged counters
+    //
+    /// Cycle counter for RDCYCLE instruction
+    Cycle,
+    /// Timer for RDTIME instruction
+    Time,
+    /// Instructions-retired counter for RDINSTRET instruction
+    Instret,
+    /// Hardware performance monitoring counters, shifted by 3 to start at 0
+    Hpmcounter(usize),
+
     // Supervisor mode CSRs
     //
     /// Supervisor status register
@@ -151,6 +162,8 @@ pub enum Csr {
     Satp,
     /// Supervisor-mode context register
     Scontext,
+    /// Supervisor timer compare register (Sstc extension)
+    Stimecmp,
 
     // Hypervisor and Virtual Supervisor CSRs
     //
@@ -233,6 +246,49 @@ impl Csr {
     pub fn is_unknown(self) -> bool {
         self == Csr::Unknown
     }
+
+    /// Returns true if the CSR is a PMP configuration or address register.
+    pub fn is_pmp(self) -> bool {
+        matches!(self, Csr::Pmpcfg(_) | Csr::Pmpaddr(_))
+    }
+
+    /// Returns true if the CSR is a hypervisor or virtual supervisor register.
+    pub fn is_hypervisor(self) -> bool {
+        matches!(
+            self,
+            Csr::Hstatus
+                | Csr::Hedeleg
+                | Csr::Hideleg
+                | Csr::Hvip
+                | Csr::Hip
+                | Csr::Hie
+                | Csr::Hgeip
+                | Csr::Hgeie
+                | Csr::Henvcfg
+                | Csr::Hcounteren
+                | Csr::Htimedelta
+                | Csr::Htval
+                | Csr::Htinst
+                | Csr::Hgatp
+                | Csr::Vsstatus
+                | Csr::Vsie
+                | Csr::Vstvec
+                | Csr::Vsscratch
+                | Csr::Vsepc
+                | Csr::Vscause
+                | Csr::Vstval
+                | Csr::Vsip
+                | Csr::Vsatp
+        )
+    }
+
+    /// Returns true if the CSR is a hardware performance monitoring counter or event selector.
+    pub fn is_hpm(self) -> bool {
+        matches!(
+            self,
+            Csr::Mhpmcounter(_) | Csr::Mhpmevent(_) | Csr::Hpmcounter(_)
+        )
+    }
 }
 
 // —————————————————————————————— Conversions ——————————————————————————————— //
@@ -8,14 +8,18 @@ use core::ptr;
 
 use spin::Mutex;
 
+use super::interpreter::{self, Reservation};
 use super::{
     mie, mstatus, parse_mpp_return_mode, Architecture, Csr, ExtensionsCapability, MCause, Mode,
+    TrapInfo, Width,
 };
 use crate::arch::pmp::PmpFlush;
 use crate::arch::{HardwareCapability, PmpGroup};
+use crate::counters::Counter;
 use crate::decoder::Instr;
 use crate::main;
-use crate::virt::VirtContext;
+use crate::utils::{self, extend_load};
+use crate::virt::{ExecutionMode, RegisterContextGetter, RegisterContextSetter, VirtContext};
 
 static HOST_CTX: Mutex<VirtContext> = Mutex::new(VirtContext::new(
     0,
@@ -26,6 +30,10 @@ static HOST_CTX: Mutex<VirtContext> = Mutex::new(VirtContext::new(
         _has_f_extension: false,
         _has_d_extension: false,
         _has_q_extension: false,
+        has_sstc_extension: false,
+        has_smepmp_extension: false,
+        has_sv48_extension: false,
+        has_sv57_extension: false,
     },
 ));
 
@@ -37,7 +45,12 @@ impl Architecture for HostArch {
         // Use main to avoid "never used" warnings.
         let _ = main;
 
-        todo!()
+        // Same initial state as on the hardware: all exceptions trap to Miralis, without paging
+        unsafe {
+            Self::write_csr(Csr::Medeleg, 0);
+            Self::write_csr(Csr::Mideleg, mie::MIDELEG_READ_ONLY_ONE);
+            Self::write_csr(Csr::Satp, 0);
+        }
     }
 
     fn wfi() {
@@ -71,8 +84,50 @@ impl Architecture for HostArch {
         PmpFlush()
     }
 
-    unsafe fn run_vcpu(_ctx: &mut crate::virt::VirtContext) {
-        todo!()
+    /// Interprets the guest until it traps, see the `interpreter` module.
+    ///
+    /// Interrupts are checked before each instruction, only the machine interrupts that are not
+    /// delegated can trap as the guest never runs in M-mode.
+    unsafe fn run_vcpu(ctx: &mut crate::virt::VirtContext) {
+        // As on the hardware, the firmware only traps on the interrupts it enabled
+        if ctx.mode == Mode::M {
+            let mie = (mstatus::MIE_FILTER & ctx.csr.mstatus) >> mstatus::MIE_OFFSET;
+            let mie_patched = if mie == 1 {
+                ctx.csr.mie & !ctx.csr.mideleg
+            } else {
+                0
+            };
+            Self::write_csr(Csr::Mie, mie_patched);
+        }
+
+        let mut reservation = Reservation::default();
+        let (cause, tval) = loop {
+            if let Some(interrupt) = pending_interrupt() {
+                break (interrupt, 0);
+            }
+            if let Err(trap) = interpreter::step(ctx, &mut reservation) {
+                break (trap.cause, trap.tval);
+            }
+        };
+
+        // Take the trap: save the previous mode and disable interrupts
+        let mode = interpreter::physical_mode(ctx);
+        let mut mstatus = Self::read_csr(Csr::Mstatus);
+        let mie = (mstatus & mstatus::MIE_FILTER) >> mstatus::MIE_OFFSET;
+        mstatus &= !(mstatus::MPP_FILTER | mstatus::MPIE_FILTER | mstatus::MIE_FILTER);
+        mstatus |= (mode.to_bits() << mstatus::MPP_OFFSET) | (mie << mstatus::MPIE_OFFSET);
+        Self::write_csr(Csr::Mstatus, mstatus);
+        Self::write_csr(Csr::Mepc, ctx.pc);
+        Self::write_csr(Csr::Mcause, cause as usize);
+        Self::write_csr(Csr::Mtval, tval);
+
+        ctx.trap_info = TrapInfo {
+            mepc: ctx.pc,
+            mstatus,
+            mcause: cause as usize,
+            mip: Self::read_csr(Csr::Mip),
+            mtval: tval,
+        };
     }
 
     unsafe fn get_raw_faulting_instr(trap_info: &super::TrapInfo) -> usize {
@@ -119,12 +174,16 @@ impl Architecture for HostArch {
                 _has_f_extension: false,
                 _has_d_extension: false,
                 _has_q_extension: false,
+                has_sstc_extension: false,
+                has_smepmp_extension: false,
+                has_sv48_extension: false,
+                has_sv57_extension: false,
             },
         }
     }
 
     fn read_csr(csr: Csr) -> usize {
-        let ctx = HOST_CTX.lock();
+        let mut ctx = HOST_CTX.lock();
         match csr {
             Csr::Mhartid => ctx.csr.marchid,
             Csr::Mstatus => ctx.csr.mstatus,
@@ -138,8 +197,15 @@ impl Architecture for HostArch {
             Csr::Mimpid => ctx.csr.mimpid,
             Csr::Pmpcfg(index) => ctx.csr.pmpcfg[index],
             Csr::Pmpaddr(index) => ctx.csr.pmpaddr[index],
-            Csr::Mcycle => ctx.csr.mcycle,
-            Csr::Minstret => ctx.csr.minstret,
+            // The mock hart does not count cycles nor instructions: its counters are the firmware
+            // views for a physical counter of 0, as `VirtCounters::read` would read them back from
+            // this very mock while it is locked.
+            Csr::Mcycle => ctx
+                .counters
+                .view(ExecutionMode::Firmware, Counter::Cycle, 0),
+            Csr::Minstret => ctx
+                .counters
+                .view(ExecutionMode::Firmware, Counter::Instret, 0),
             Csr::Mhpmcounter(index) => ctx.csr.mhpmcounter[index],
             Csr::Mcountinhibit => ctx.csr.mcountinhibit,
             Csr::Mhpmevent(index) => ctx.csr.mhpmevent[index],
@@ -150,16 +216,17 @@ impl Architecture for HostArch {
             Csr::Medeleg => ctx.csr.medeleg,
             Csr::Mideleg => ctx.csr.mideleg,
             Csr::Mtinst => ctx.csr.mtinst,
-            Csr::Mtval2 => todo!(),
-            Csr::Tselect => todo!(),
-            Csr::Tdata1 => todo!(),
-            Csr::Tdata2 => todo!(),
-            Csr::Tdata3 => todo!(),
-            Csr::Mcontext => todo!(),
-            Csr::Dcsr => todo!(),
-            Csr::Dpc => todo!(),
-            Csr::Dscratch0 => todo!(),
-            Csr::Dscratch1 => todo!(),
+            Csr::Mtval2 => ctx.csr.mtval2,
+            // The userspace hart has no hardware trigger nor debug mode
+            Csr::Tselect => 0,
+            Csr::Tdata1 => 0,
+            Csr::Tdata2 => 0,
+            Csr::Tdata3 => 0,
+            Csr::Mcontext => 0,
+            Csr::Dcsr => 0,
+            Csr::Dpc => 0,
+            Csr::Dscratch0 => 0,
+            Csr::Dscratch1 => 0,
             Csr::Mepc => ctx.csr.mepc,
             Csr::Mcause => ctx.csr.mcause,
             Csr::Mtval => ctx.csr.mtval,
@@ -175,29 +242,38 @@ impl Architecture for HostArch {
             Csr::Sip => ctx.csr.mip & mie::SIE_FILTER,
             Csr::Satp => ctx.csr.satp,
             Csr::Scontext => ctx.csr.scontext,
-            Csr::Hstatus => ctx.csr.hstatus,
-            Csr::Hedeleg => ctx.csr.hedeleg,
-            Csr::Hideleg => ctx.csr.hideleg,
-            Csr::Hvip => ctx.csr.hvip,
-            Csr::Hip => ctx.csr.hip,
-            Csr::Hie => ctx.csr.hie,
-            Csr::Hgeip => ctx.csr.hgeip,
-            Csr::Hgeie => ctx.csr.hgeie,
-            Csr::Henvcfg => ctx.csr.henvcfg,
-            Csr::Hcounteren => ctx.csr.hcounteren,
-            Csr::Htimedelta => ctx.csr.htimedelta,
-            Csr::Htval => ctx.csr.htval,
-            Csr::Htinst => ctx.csr.htinst,
-            Csr::Hgatp => ctx.csr.hgatp,
-            Csr::Vsstatus => ctx.csr.vsstatus,
-            Csr::Vsie => ctx.csr.vsie,
-            Csr::Vstvec => ctx.csr.vstvec,
-            Csr::Vsscratch => ctx.csr.vsscratch,
-            Csr::Vsepc => ctx.csr.vsepc,
-            Csr::Vscause => ctx.csr.vscause,
-            Csr::Vstval => ctx.csr.vstval,
-            Csr::Vsip => ctx.csr.vsip,
-            Csr::Vsatp => ctx.csr.vsatp,
+            Csr::Stimecmp => ctx.csr.stimecmp,
+            Csr::Hstatus
+            | Csr::Hedeleg
+            | Csr::Hideleg
+            | Csr::Hvip
+            | Csr::Hip
+            | Csr::Hie
+            | Csr::Hgeip
+            | Csr::Hgeie
+            | Csr::Henvcfg
+            | Csr::Hcounteren
+            | Csr::Htimedelta
+            | Csr::Htval
+            | Csr::Htinst
+            | Csr::Hgatp
+            | Csr::Vsstatus
+            | Csr::Vsie
+            | Csr::Vstvec
+            | Csr::Vsscratch
+            | Csr::Vsepc
+            | Csr::Vscause
+            | Csr::Vstval
+            | Csr::Vsip
+            | Csr::Vsatp => ctx.h_csr(csr),
+            Csr::Cycle => ctx
+                .counters
+                .view(ExecutionMode::Firmware, Counter::Cycle, 0),
+            Csr::Time => 0,
+            Csr::Instret => ctx
+                .counters
+                .view(ExecutionMode::Firmware, Counter::Instret, 0),
+            Csr::Hpmcounter(index) => ctx.csr.mhpmcounter[index],
             Csr::Unknown => panic!("Unkown csr!"),
         }
     }
@@ -218,8 +294,13 @@ impl Architecture for HostArch {
             Csr::Mimpid => ctx.csr.mimpid = value,
             Csr::Pmpcfg(index) => ctx.csr.pmpcfg[index] = value,
             Csr::Pmpaddr(index) => ctx.csr.pmpaddr[index] = value,
-            Csr::Mcycle => ctx.csr.mcycle = value,
-            Csr::Minstret => ctx.csr.minstret = value,
+            Csr::Mcycle => ctx
+                .counters
+                .write_at(ExecutionMode::Firmware, Counter::Cycle, value, 0),
+            Csr::Minstret => {
+                ctx.counters
+                    .write_at(ExecutionMode::Firmware, Counter::Instret, value, 0)
+            }
             Csr::Mhpmcounter(index) => ctx.csr.mhpmcounter[index] = value,
             Csr::Mcountinhibit => ctx.csr.mcountinhibit = value,
             Csr::Mhpmevent(index) => ctx.csr.mhpmevent[index] = value,
@@ -230,16 +311,16 @@ impl Architecture for HostArch {
             Csr::Medeleg => ctx.csr.medeleg = value,
             Csr::Mideleg => ctx.csr.mideleg = value,
             Csr::Mtinst => ctx.csr.mtinst = value,
-            Csr::Mtval2 => todo!(),
-            Csr::Tselect => todo!(),
-            Csr::Tdata1 => todo!(),
-            Csr::Tdata2 => todo!(),
-            Csr::Tdata3 => todo!(),
-            Csr::Mcontext => todo!(),
-            Csr::Dcsr => todo!(),
-            Csr::Dpc => todo!(),
-            Csr::Dscratch0 => todo!(),
-            Csr::Dscratch1 => todo!(),
+            Csr::Mtval2 => ctx.csr.mtval2 = value,
+            Csr::Tselect => (),
+            Csr::Tdata1 => (),
+            Csr::Tdata2 => (),
+            Csr::Tdata3 => (),
+            Csr::Mcontext => (),
+            Csr::Dcsr => (),
+            Csr::Dpc => (),
+            Csr::Dscratch0 => (),
+            Csr::Dscratch1 => (),
             Csr::Mepc => ctx.csr.mepc = value,
             Csr::Mcause => ctx.csr.mcause = value,
             Csr::Mtval => ctx.csr.mtval = value,
@@ -258,29 +339,31 @@ impl Architecture for HostArch {
             Csr::Sip => ctx.csr.mip = ctx.csr.mip & !mie::SIE_FILTER | value & mie::SIE_FILTER,
             Csr::Satp => ctx.csr.satp = value,
             Csr::Scontext => ctx.csr.scontext = value,
-            Csr::Hstatus => ctx.csr.hstatus = value,
-            Csr::Hedeleg => ctx.csr.hedeleg = value,
-            Csr::Hideleg => ctx.csr.hideleg = value,
-            Csr::Hvip => ctx.csr.hvip = value,
-            Csr::Hip => ctx.csr.hip = value,
-            Csr::Hie => ctx.csr.hie = value,
+            Csr::Stimecmp => ctx.csr.stimecmp = value,
             Csr::Hgeip => {}
-            Csr::Hgeie => ctx.csr.hgeie = value,
-            Csr::Henvcfg => ctx.csr.henvcfg = value,
-            Csr::Hcounteren => ctx.csr.hcounteren = value,
-            Csr::Htimedelta => ctx.csr.htimedelta = value,
-            Csr::Htval => ctx.csr.htval = value,
-            Csr::Htinst => ctx.csr.htinst = value,
-            Csr::Hgatp => ctx.csr.hgatp = value,
-            Csr::Vsstatus => ctx.csr.vsstatus = value,
-            Csr::Vsie => ctx.csr.vsie = value,
-            Csr::Vstvec => ctx.csr.vstvec = value,
-            Csr::Vsscratch => ctx.csr.vsscratch = value,
-            Csr::Vsepc => ctx.csr.vsepc = value,
-            Csr::Vscause => ctx.csr.vscause = value,
-            Csr::Vstval => ctx.csr.vstval = value,
-            Csr::Vsip => ctx.csr.vsip = value,
-            Csr::Vsatp => ctx.csr.vsatp = value,
+            Csr::Hstatus
+            | Csr::Hedeleg
+            | Csr::Hideleg
+            | Csr::Hvip
+            | Csr::Hip
+            | Csr::Hie
+            | Csr::Hgeie
+            | Csr::Henvcfg
+            | Csr::Hcounteren
+            | Csr::Htimedelta
+            | Csr::Htval
+            | Csr::Htinst
+            | Csr::Hgatp
+            | Csr::Vsstatus
+            | Csr::Vsie
+            | Csr::Vstvec
+            | Csr::Vsscratch
+            | Csr::Vsepc
+            | Csr::Vscause
+            | Csr::Vstval
+            | Csr::Vsip
+            | Csr::Vsatp => *ctx.h_csr_mut(csr) = value,
+            Csr::Cycle | Csr::Time | Csr::Instret | Csr::Hpmcounter(_) => (), // Read-only
             Csr::Unknown => panic!("Unkown csr!"),
         }
         prev_val
@@ -294,27 +377,104 @@ impl Architecture for HostArch {
         Self::write_csr(csr, Self::read_csr(csr) | bits_mask);
     }
 
-    unsafe fn handle_virtual_load_store(_instr: Instr, _ctx: &mut VirtContext) {
-        todo!();
+    /// Guest addresses are host addresses, the access is performed without address translation
+    /// nor PMP checks (see the `interpreter` module).
+    unsafe fn handle_virtual_load_store(instr: Instr, ctx: &mut VirtContext) {
+        match instr {
+            Instr::Load {
+                rd,
+                rs1,
+                imm,
+                len,
+                is_compressed,
+                is_unsigned,
+            } => {
+                let value = load(utils::calculate_addr(ctx.get(rs1), imm), len);
+                ctx.set(rd, extend_load(value, len, is_unsigned));
+                ctx.pc += if is_compressed { 2 } else { 4 };
+            }
+            Instr::Hlv {
+                rd,
+                rs1,
+                len,
+                is_unsigned,
+                ..
+            } => {
+                let value = load(ctx.get(rs1), len);
+                ctx.set(rd, extend_load(value, len, is_unsigned));
+                ctx.pc += 4;
+            }
+            Instr::Store {
+                rs2,
+                rs1,
+                imm,
+                len,
+                is_compressed,
+            } => {
+                store(utils::calculate_addr(ctx.get(rs1), imm), len, ctx.get(rs2));
+                ctx.pc += if is_compressed { 2 } else { 4 };
+            }
+            Instr::Hsv { rs1, rs2, len } => {
+                store(ctx.get(rs1), len, ctx.get(rs2));
+                ctx.pc += 4;
+            }
+            _ => panic!("Not a load or store: {:?}", instr),
+        }
     }
 
-    unsafe fn read_bytes_from_mode(
-        _src: *const u8,
-        _dest: &mut [u8],
-        _mode: Mode,
-    ) -> Result<(), ()> {
-        todo!();
+    unsafe fn read_bytes_from_mode(src: *const u8, dest: &mut [u8], _mode: Mode) -> Result<(), ()> {
+        ptr::copy_nonoverlapping(src, dest.as_mut_ptr(), dest.len());
+        Ok(())
     }
 
-    fn install_handler(_: usize) {
-        todo!();
+    /// Traps are returned by `run_vcpu`, the handler is only recorded in mtvec.
+    fn install_handler(handler: usize) {
+        HOST_CTX.lock().csr.mtvec = handler;
     }
 
     unsafe fn store_bytes_from_mode(
-        _src: &mut [u8],
-        _dest: *const u8,
+        src: &mut [u8],
+        dest: *const u8,
         _mode: Mode,
     ) -> Result<(), ()> {
-        todo!()
+        ptr::copy_nonoverlapping(src.as_ptr(), dest as *mut u8, src.len());
+        Ok(())
+    }
+}
+
+/// Reads a value of the given width from a host address.
+unsafe fn load(addr: usize, len: Width) -> usize {
+    match len {
+        Width::Byte => ptr::read(addr as *const u8) as usize,
+        Width::Byte2 => ptr::read_unaligned(addr as *const u16) as usize,
+        Width::Byte4 => ptr::read_unaligned(addr as *const u32) as usize,
+        Width::Byte8 => ptr::read_unaligned(addr as *const u64) as usize,
+    }
+}
+
+/// Writes a value of the given width to a host address.
+unsafe fn store(addr: usize, len: Width, value: usize) {
+    match len {
+        Width::Byte => ptr::write(addr as *mut u8, value as u8),
+        Width::Byte2 => ptr::write_unaligned(addr as *mut u16, value as u16),
+        Width::Byte4 => ptr::write_unaligned(addr as *mut u32, value as u32),
+        Width::Byte8 => ptr::write_unaligned(addr as *mut u64, value as u64),
     }
 }
+
+/// Returns the highest priority machine interrupt that is pending, enabled, and not delegated.
+fn pending_interrupt() -> Option<MCause> {
+    let pending = HostArch::read_csr(Csr::Mip)
+        & HostArch::read_csr(Csr::Mie)
+        & !HostArch::read_csr(Csr::Mideleg);
+    [
+        MCause::MachineExternalInt,
+        MCause::MachineSoftInt,
+        MCause::MachineTimerInt,
+        MCause::SupervisorExternalInt,
+        MCause::SupervisorSoftInt,
+        MCause::SupervisorTimerInt,
+    ]
+    .into_iter()
+    .find(|cause| pending & (1 << MCause::cause_number(*cause as usize)) != 0)
+}
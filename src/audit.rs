@@ -0,0 +1,319 @@
+//! Audit Log
+//!
+//! Miralis records security-relevant events (firmware PMP and delegation changes, policy decisions,
+//! and world switches) into an append-only log kept in its own memory, out of reach of the firmware
+//! and the payload. Each entry is chained to the previous one by a SHA-256 hash, such that any
+//! modification or removal of past entries can be detected by replaying the chain.
+//!
+//! The log is a ring buffer retaining the most recent entries. The payload can copy them with the
+//! `MIRALIS_AUDIT_LOG_READ_FID` call of the Miralis ABI, which is the only access to the log, for
+//! post-incident forensics. The log is disabled if its configured size is 0.
+
+use core::mem::{align_of, size_of};
+
+use miralis_core::abi::audit::{self, AuditEntry};
+use sha2::{Digest, Sha256};
+use spin::Mutex;
+
+use crate::arch::{Arch, Architecture, Register};
+use crate::config::AUDIT_LOG_ENTRIES;
+use crate::platform::{Plat, Platform};
+use crate::virt::{ExecutionMode, RegisterContextGetter, RegisterContextSetter, VirtContext};
+
+const SBI_ERR_NOT_SUPPORTED: isize = -2;
+const SBI_ERR_DENIED: isize = -4;
+const SBI_ERR_INVALID_ADDRESS: isize = -5;
+
+/// The audit log, shared by all harts.
+static AUDIT_LOG: Mutex<AuditLog<AUDIT_LOG_ENTRIES>> = Mutex::new(AuditLog::new());
+
+/// The security-relevant events recorded in the audit log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditEvent {
+    PmpcfgWrite {
+        idx: usize,
+        value: usize,
+    },
+    PmpaddrWrite {
+        idx: usize,
+        value: usize,
+    },
+    MedelegWrite(usize),
+    MidelegWrite(usize),
+    /// The policy overrode the default handling of a trap.
+    PolicyDecision {
+        mcause: usize,
+        eid: usize,
+    },
+    /// Switch to the given world.
+    WorldSwitch(ExecutionMode),
+}
+
+impl AuditEvent {
+    /// Returns the ABI encoding of the event.
+    fn encode(self) -> (u64, [u64; 2]) {
+        match self {
+            AuditEvent::PmpcfgWrite { idx, value } => {
+                (audit::PMPCFG_WRITE, [idx as u64, value as u64])
+            }
+            AuditEvent::PmpaddrWrite { idx, value } => {
+                (audit::PMPADDR_WRITE, [idx as u64, value as u64])
+            }
+            AuditEvent::MedelegWrite(value) => (audit::MEDELEG_WRITE, [value as u64, 0]),
+            AuditEvent::MidelegWrite(value) => (audit::MIDELEG_WRITE, [value as u64, 0]),
+            AuditEvent::PolicyDecision { mcause, eid } => {
+                (audit::POLICY_DECISION, [mcause as u64, eid as u64])
+            }
+            AuditEvent::WorldSwitch(ExecutionMode::Firmware) => (audit::WORLD_SWITCH, [0, 0]),
+            AuditEvent::WorldSwitch(ExecutionMode::Payload) => (audit::WORLD_SWITCH, [1, 0]),
+        }
+    }
+}
+
+// ——————————————————————————————— Audit Log ———————————————————————————————— //
+
+/// A hash-chained ring buffer of audit entries.
+struct AuditLog<const N: usize> {
+    entries: [AuditEntry; N],
+    /// Sequence number of the next entry.
+    next_seq: u64,
+    /// Hash of the last entry.
+    head: [u8; 32],
+}
+
+impl<const N: usize> AuditLog<N> {
+    const fn new() -> Self {
+        const EMPTY: AuditEntry = AuditEntry {
+            seq: 0,
+            hart: 0,
+            kind: 0,
+            args: [0; 2],
+            hash: [0; 32],
+        };
+
+        AuditLog {
+            entries: [EMPTY; N],
+            next_seq: 0,
+            head: [0; 32],
+        }
+    }
+
+    fn append(&mut self, hart: usize, event: AuditEvent) {
+        if N == 0 {
+            return;
+        }
+
+        let (kind, args) = event.encode();
+        let mut entry = AuditEntry {
+            seq: self.next_seq,
+            hart: hart as u64,
+            kind,
+            args,
+            hash: [0; 32],
+        };
+        entry.hash = chain_hash(&self.head, &entry);
+
+        self.head = entry.hash;
+        self.entries[(self.next_seq % N as u64) as usize] = entry;
+        self.next_seq += 1;
+    }
+
+    /// Returns the entry with the given sequence number, if still retained.
+    fn get(&self, seq: u64) -> Option<&AuditEntry> {
+        let oldest = self.next_seq.saturating_sub(N as u64);
+        if seq < oldest || seq >= self.next_seq {
+            return None;
+        }
+        Some(&self.entries[(seq % N as u64) as usize])
+    }
+
+    /// Returns the sequence number of the oldest retained entry.
+    fn oldest_seq(&self) -> u64 {
+        self.next_seq.saturating_sub(N as u64)
+    }
+}
+
+/// Computes the hash of an entry, chained to the hash of the previous entry.
+fn chain_hash(previous: &[u8; 32], entry: &AuditEntry) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(previous);
+    hasher.update(entry.seq.to_le_bytes());
+    hasher.update(entry.hart.to_le_bytes());
+    hasher.update(entry.kind.to_le_bytes());
+    hasher.update(entry.args[0].to_le_bytes());
+    hasher.update(entry.args[1].to_le_bytes());
+    hasher.finalize().into()
+}
+
+// ————————————————————————————————— Access ————————————————————————————————— //
+
+/// Records an event in the audit log.
+#[inline]
+pub fn record(hart: usize, event: AuditEvent) {
+    if AUDIT_LOG_ENTRIES == 0 {
+        return;
+    }
+
+    AUDIT_LOG.lock().append(hart, event);
+}
+
+/// Handles a request from the payload to copy the audit log.
+///
+/// The arguments are the sequence number of the first entry to copy, the physical address of the
+/// destination buffer, and its capacity in entries. On success the number of copied entries is
+/// returned in a1.
+pub fn handle_read(ctx: &mut VirtContext) {
+    let first = ctx.get(Register::X10) as u64;
+    let addr = ctx.get(Register::X11);
+    let capacity = ctx.get(Register::X12);
+
+    let (error, count) = if AUDIT_LOG_ENTRIES == 0 {
+        (SBI_ERR_NOT_SUPPORTED, 0)
+    } else if ctx.mode.to_exec_mode() != ExecutionMode::Payload {
+        (SBI_ERR_DENIED, 0)
+    } else if !is_valid_buffer(addr, capacity) {
+        (SBI_ERR_INVALID_ADDRESS, 0)
+    } else {
+        copy_entries(ctx, first, addr, capacity)
+    };
+
+    ctx.set(Register::X10, error as usize);
+    ctx.set(Register::X11, count);
+    ctx.pc += 4;
+}
+
+/// Copies the entries to the payload buffer, returns the SBI error and the number of copied
+/// entries.
+fn copy_entries(ctx: &VirtContext, first: u64, addr: usize, capacity: usize) -> (isize, usize) {
+    let log = AUDIT_LOG.lock();
+    let first = first.max(log.oldest_seq());
+    let mut count = 0;
+    while count < capacity {
+        let Some(entry) = log.get(first + count as u64) else {
+            break;
+        };
+        let mut bytes = to_bytes(entry);
+        let dest = (addr + count * size_of::<AuditEntry>()) as *const u8;
+        // SAFETY: the entries are stored with the privileges of the payload, the buffer can
+        // therefore not overlap Miralis's or the firmware's memory.
+        if unsafe { Arch::store_bytes_from_mode(&mut bytes, dest, ctx.mode) }.is_err() {
+            return (SBI_ERR_INVALID_ADDRESS, 0);
+        }
+        count += 1;
+    }
+    (0, count)
+}
+
+/// Returns the in-memory representation of an entry, as defined by the ABI.
+fn to_bytes(entry: &AuditEntry) -> [u8; size_of::<AuditEntry>()] {
+    let mut bytes = [0; size_of::<AuditEntry>()];
+    let words = [
+        entry.seq,
+        entry.hart,
+        entry.kind,
+        entry.args[0],
+        entry.args[1],
+    ];
+    for (chunk, word) in bytes.chunks_exact_mut(8).zip(words) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    bytes[words.len() * 8..].copy_from_slice(&entry.hash);
+    bytes
+}
+
+/// Checks that a payload buffer is aligned and does not overlap Miralis's memory.
+fn is_valid_buffer(addr: usize, capacity: usize) -> bool {
+    let Some(end) = capacity
+        .checked_mul(size_of::<AuditEntry>())
+        .and_then(|size| addr.checked_add(size))
+    else {
+        return false;
+    };
+    let (start, size) = Plat::get_miralis_memory_start_and_size();
+    addr % align_of::<AuditEntry>() == 0 && (end <= start || addr >= start + size)
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Verifies the chain of the retained entries, given the hash preceding the first one.
+    fn verify(entries: &[AuditEntry], mut previous: [u8; 32]) -> bool {
+        for entry in entries {
+            if chain_hash(&previous, entry) != entry.hash {
+                return false;
+            }
+            previous = entry.hash;
+        }
+        true
+    }
+
+    fn retained<const N: usize>(log: &AuditLog<N>) -> [AuditEntry; N] {
+        let oldest = log.oldest_seq();
+        core::array::from_fn(|idx| *log.get(oldest + idx as u64).unwrap())
+    }
+
+    #[test]
+    fn chain_is_verifiable() {
+        let mut log: AuditLog<4> = AuditLog::new();
+        log.append(0, AuditEvent::MedelegWrite(0xb109));
+        log.append(1, AuditEvent::WorldSwitch(ExecutionMode::Payload));
+        log.append(0, AuditEvent::PmpaddrWrite { idx: 3, value: 42 });
+        log.append(0, AuditEvent::PolicyDecision { mcause: 9, eid: 1 });
+
+        let mut entries = retained(&log);
+        assert_eq!(entries.map(|entry| entry.seq), [0, 1, 2, 3]);
+        assert_eq!(entries[1].kind, audit::WORLD_SWITCH);
+        assert_eq!(entries[1].args, [1, 0]);
+        assert!(verify(&entries, [0; 32]));
+
+        // Tampering with an entry breaks the chain
+        entries[2].args[1] = 0;
+        assert!(!verify(&entries, [0; 32]));
+    }
+
+    #[test]
+    fn ring_buffer_keeps_recent_entries() {
+        let mut log: AuditLog<2> = AuditLog::new();
+        for value in 0..5 {
+            log.append(0, AuditEvent::MidelegWrite(value));
+        }
+
+        assert_eq!(log.oldest_seq(), 3);
+        assert!(log.get(2).is_none());
+        assert!(log.get(5).is_none());
+
+        let entries = retained(&log);
+        assert_eq!(entries.map(|entry| entry.args[0]), [3, 4]);
+        // The chain still verifies from the hash preceding the oldest retained entry
+        let mut previous: AuditLog<8> = AuditLog::new();
+        for value in 0..3 {
+            previous.append(0, AuditEvent::MidelegWrite(value));
+        }
+        assert!(verify(&entries, previous.head));
+    }
+
+    #[test]
+    fn entries_match_abi_layout() {
+        let mut log: AuditLog<1> = AuditLog::new();
+        log.append(
+            3,
+            AuditEvent::PmpaddrWrite {
+                idx: 2,
+                value: 0x8000,
+            },
+        );
+        let entry = log.get(0).unwrap();
+
+        // SAFETY: the entry is a plain `repr(C)` struct without padding.
+        let raw = unsafe {
+            core::slice::from_raw_parts(
+                entry as *const AuditEntry as *const u8,
+                size_of::<AuditEntry>(),
+            )
+        };
+        assert_eq!(to_bytes(entry).as_slice(), raw);
+    }
+}
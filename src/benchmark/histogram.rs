@@ -0,0 +1,277 @@
+//! Latency histograms
+//!
+//! Interval counters only report the minimum, maximum and mean of a latency, which hides the
+//! tail of its distribution. Histograms count the samples falling in configurable buckets, and
+//! additionally retain the most recent raw samples. The raw samples are dumped as a binary blob
+//! over the debug console, from which the runner computes percentiles.
+
+use config_helpers::parse_usize;
+use spin::Mutex;
+
+use crate::arch::{Arch, Architecture, Csr};
+use crate::config;
+use crate::platform::{Plat, Platform};
+use crate::virt::ExecutionMode;
+
+/// Maximum number of bucket bounds, the last bucket collects the samples above all bounds.
+const MAX_BUCKETS: usize = 32;
+
+/// Bucket bounds used if none are configured, in cycles.
+const DEFAULT_BOUNDS: [&str; 12] = [
+    "64", "128", "256", "512", "1024", "2048", "4096", "8192", "16384", "32768", "65536", "131072",
+];
+
+/// The bucket bounds, and the number of valid bounds.
+const BOUNDS: ([usize; MAX_BUCKETS], usize) = if config::BENCHMARK_HISTOGRAM_BUCKETS.is_empty() {
+    parse_bounds(&DEFAULT_BOUNDS)
+} else {
+    parse_bounds(config::BENCHMARK_HISTOGRAM_BUCKETS)
+};
+
+/// Number of raw samples per line of the binary dump.
+const SAMPLES_PER_LINE: usize = 4;
+
+/// Marks the start of a histogram in the benchmark output, must match the runner.
+const HISTOGRAM_TOKEN: &str = "START HISTOGRAM";
+/// Marks the start of the raw samples of a histogram, must match the runner.
+const SAMPLES_TOKEN: &str = "START SAMPLES";
+/// Marks the end of the raw samples of a histogram, must match the runner.
+const END_SAMPLES_TOKEN: &str = "END SAMPLES";
+
+const NB_LATENCIES: usize = 2;
+
+static HISTOGRAMS: Mutex<[Histogram<{ config::BENCHMARK_HISTOGRAM_SAMPLES }>; NB_LATENCIES]> =
+    Mutex::new([Histogram::new(), Histogram::new()]);
+
+/// The latencies recorded in histograms, in cycles.
+#[derive(PartialEq, Eq, Copy, Clone)]
+pub enum Latency {
+    /// Time to handle a trap from the firmware, including the world switch if any.
+    FirmwareExit = 0,
+    /// Time to handle a trap that switches between the firmware and the payload.
+    WorldSwitch = 1,
+}
+
+impl Latency {
+    const ALL: [Latency; NB_LATENCIES] = [Latency::FirmwareExit, Latency::WorldSwitch];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Latency::FirmwareExit => "firmware_exit",
+            Latency::WorldSwitch => "world_switch",
+        }
+    }
+}
+
+/// Returns the current cycle count, the start of a trap whose latency is recorded with
+/// [record_trap_latency].
+pub fn start_trap_latency() -> usize {
+    if !config::BENCHMARK_HISTOGRAM {
+        return 0;
+    }
+    Arch::read_csr(Csr::Mcycle)
+}
+
+/// Records the latency of the trap that started at `start` in the relevant histograms.
+///
+/// `from` and `to` are the execution modes before and after the trap was handled.
+pub fn record_trap_latency(start: usize, from: ExecutionMode, to: ExecutionMode) {
+    if !config::BENCHMARK_HISTOGRAM {
+        return;
+    }
+
+    let latency = Arch::read_csr(Csr::Mcycle).wrapping_sub(start);
+    let mut histograms = HISTOGRAMS.lock();
+    if from == ExecutionMode::Firmware {
+        histograms[Latency::FirmwareExit as usize].record(latency);
+    }
+    if from != to {
+        histograms[Latency::WorldSwitch as usize].record(latency);
+    }
+}
+
+/// Discards the samples recorded so far.
+pub fn reset() {
+    if !config::BENCHMARK_HISTOGRAM {
+        return;
+    }
+
+    for histogram in HISTOGRAMS.lock().iter_mut() {
+        histogram.clear();
+    }
+}
+
+// ——————————————————————————————— Histograms ——————————————————————————————— //
+
+/// A histogram of latencies, retaining the last `N` raw samples.
+struct Histogram<const N: usize> {
+    /// Number of samples per bucket, bucket `i` counts the samples below `BOUNDS[i]` and above
+    /// the previous bound.
+    counts: [usize; MAX_BUCKETS + 1],
+    /// Ring buffer of the most recent raw samples.
+    samples: [usize; N],
+    /// Total number of recorded samples.
+    nb_samples: usize,
+}
+
+impl<const N: usize> Histogram<N> {
+    const fn new() -> Self {
+        Histogram {
+            counts: [0; MAX_BUCKETS + 1],
+            samples: [0; N],
+            nb_samples: 0,
+        }
+    }
+
+    /// The retained raw samples are not erased, they are overwritten by the next samples.
+    fn clear(&mut self) {
+        self.counts = [0; MAX_BUCKETS + 1];
+        self.nb_samples = 0;
+    }
+
+    fn record(&mut self, value: usize) {
+        self.counts[bucket_index(&BOUNDS.0[..BOUNDS.1], value)] += 1;
+        if N > 0 {
+            self.samples[self.nb_samples % N] = value;
+        }
+        self.nb_samples += 1;
+    }
+
+    /// Returns the number of retained raw samples.
+    fn nb_retained(&self) -> usize {
+        self.nb_samples.min(N)
+    }
+
+    /// Returns the retained raw samples in recording order, split in two parts as the ring buffer
+    /// might wrap around.
+    fn retained_samples(&self) -> [&[usize]; 2] {
+        if self.nb_samples <= N {
+            [&self.samples[..self.nb_samples], &[]]
+        } else {
+            let first = self.nb_samples % N;
+            [&self.samples[first..], &self.samples[..first]]
+        }
+    }
+}
+
+/// Returns the index of the bucket the value falls in.
+fn bucket_index(bounds: &[usize], value: usize) -> usize {
+    bounds.partition_point(|bound| *bound <= value)
+}
+
+const fn parse_bounds(bounds: &[&str]) -> ([usize; MAX_BUCKETS], usize) {
+    assert!(bounds.len() <= MAX_BUCKETS, "Too many histogram buckets");
+    let mut parsed = [0; MAX_BUCKETS];
+    let mut i = 0;
+    while i < bounds.len() {
+        parsed[i] = match parse_usize(Some(bounds[i])) {
+            Some(bound) => bound,
+            None => unreachable!(),
+        };
+        assert!(
+            i == 0 || parsed[i] > parsed[i - 1],
+            "Histogram bucket bounds must be increasing"
+        );
+        i += 1;
+    }
+    (parsed, bounds.len())
+}
+
+// —————————————————————————————— Binary Dump ——————————————————————————————— //
+
+/// Prints the histograms, followed by their raw samples.
+///
+/// The raw samples are encoded as 64 bits little endian integers, printed in hexadecimal as the
+/// debug console only carries text.
+pub fn dump() {
+    if !config::BENCHMARK_HISTOGRAM {
+        return;
+    }
+
+    let histograms = HISTOGRAMS.lock();
+    for latency in Latency::ALL {
+        let histogram = &histograms[latency as usize];
+        let bounds = &BOUNDS.0[..BOUNDS.1];
+
+        benchmark_print!(
+            "{} {} {}",
+            HISTOGRAM_TOKEN,
+            latency.name(),
+            bounds.len() + 1
+        );
+        for (bound, count) in bounds.iter().zip(histogram.counts.iter()) {
+            benchmark_print!("{},{}", bound, count);
+        }
+        benchmark_print!("inf,{}", histogram.counts[bounds.len()]);
+
+        benchmark_print!(
+            "{} {} {}",
+            SAMPLES_TOKEN,
+            latency.name(),
+            histogram.nb_retained()
+        );
+        for samples in histogram.retained_samples() {
+            for line in samples.chunks(SAMPLES_PER_LINE) {
+                benchmark_print!("{}", HexSamples(line));
+            }
+        }
+        benchmark_print!("{} {}", END_SAMPLES_TOKEN, latency.name());
+    }
+}
+
+/// Formats samples as the hexadecimal encoding of their little endian bytes.
+struct HexSamples<'a>(&'a [usize]);
+
+impl core::fmt::Display for HexSamples<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for sample in self.0 {
+            for byte in (*sample as u64).to_le_bytes() {
+                write!(f, "{:02x}", byte)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets() {
+        let bounds = [10, 20, 40];
+        assert_eq!(bucket_index(&bounds, 0), 0);
+        assert_eq!(bucket_index(&bounds, 9), 0);
+        assert_eq!(bucket_index(&bounds, 10), 1);
+        assert_eq!(bucket_index(&bounds, 39), 2);
+        assert_eq!(bucket_index(&bounds, 40), 3);
+        assert_eq!(bucket_index(&bounds, usize::MAX), 3);
+
+        let (parsed, len) = parse_bounds(&["1", "5", "100"]);
+        assert_eq!(&parsed[..len], &[1, 5, 100]);
+    }
+
+    #[test]
+    fn retained_samples() {
+        let mut histogram = Histogram::<3>::new();
+        assert!(histogram.retained_samples().concat().is_empty());
+
+        histogram.record(1);
+        histogram.record(2);
+        assert_eq!(histogram.retained_samples().concat(), [1, 2]);
+
+        // Older samples are overwritten once the ring buffer is full
+        histogram.record(3);
+        histogram.record(4);
+        assert_eq!(histogram.retained_samples().concat(), [2, 3, 4]);
+        assert_eq!(histogram.nb_samples, 4);
+        assert_eq!(histogram.counts.iter().sum::<usize>(), 4);
+
+        assert_eq!(
+            format!("{}", HexSamples(&[1, 0x0203])),
+            "01000000000000000302000000000000"
+        );
+    }
+}
@@ -2,6 +2,13 @@
 //!
 //! This is useful for creating different benchmark on time of execution or
 //! the number of instruction for example.
+//!
+//! The counters enabled in the build configuration can additionally be selected at runtime by the
+//! payload through the vendor SBI extension, for instance to only record a phase of interest of a
+//! long-running workload. All enabled counters are selected at boot.
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use miralis_core::abi_vendor::benchmark as masks;
 use spin::Mutex;
 
 use crate::arch::{Arch, Architecture, Csr};
@@ -21,9 +28,16 @@ macro_rules! benchmark_print {
     ($($arg:tt)*) => (if config::BENCHMARK { $crate::_benchmark_print!("{}\r\n", core::format_args!($($arg)*))})
 }
 
+pub mod histogram;
+
 pub static BENCH: Mutex<Benchmark> = Mutex::new(Benchmark::new());
 
-const NB_COUNTER: usize = 3;
+/// The counters selected at runtime, as a mask of `abi_vendor::benchmark` counters.
+static SELECTED_COUNTERS: AtomicUsize = AtomicUsize::new(masks::ALL_COUNTERS);
+/// The scopes selected at runtime, as a mask of `abi_vendor::benchmark` scopes.
+static SELECTED_SCOPES: AtomicUsize = AtomicUsize::new(masks::ALL_SCOPES);
+
+const NB_COUNTER: usize = 4;
 
 /// Benchmark counters.
 /// This kind of counter aims to be incremented to count occurences of an event.
@@ -32,11 +46,12 @@ pub enum Counter {
     TotalExits = 0,
     FirmwareExits = 1,
     WorldSwitches = 2,
+    ScrubbedPages = 3,
 }
 
 const NB_INTERVAL_COUNTER: usize = 2;
 
-const NB_SCOPES: usize = 2;
+const NB_SCOPES: usize = 3;
 
 /// Benchmark interval counters.
 /// This kind of counter aims to measure difference beetween two events.
@@ -54,11 +69,16 @@ struct IntervalCounterStats {
     max: usize,
     mean: usize,
     sum: usize,
+    /// Whether the interval has been started while the counter was selected.
+    started: bool,
 }
 
+#[derive(Copy, Clone)]
 pub enum Scope {
     HandleTrap,
     RunVCPU,
+    /// The event hooks of the policy, which are also part of the `HandleTrap` scope.
+    PolicyHook,
 }
 
 impl Scope {
@@ -66,6 +86,7 @@ impl Scope {
         match self {
             Self::HandleTrap => 0,
             Self::RunVCPU => 1,
+            Self::PolicyHook => 2,
         }
     }
 
@@ -73,8 +94,14 @@ impl Scope {
         match self {
             Self::HandleTrap => "handle_trap",
             Self::RunVCPU => "run_vcpu",
+            Self::PolicyHook => "policy_hook",
         }
     }
+
+    /// Whether the scope is selected at runtime.
+    fn is_selected(&self) -> bool {
+        SELECTED_SCOPES.load(Ordering::Relaxed) & (1 << self.base()) != 0
+    }
 }
 
 enum Either {
@@ -92,14 +119,31 @@ impl Either {
                 Counter::TotalExits => config::BENCHMARK_NB_EXITS,
                 Counter::FirmwareExits => config::BENCHMARK_NB_FIRMWARE_EXITS,
                 Counter::WorldSwitches => config::BENCHMARK_WORLD_SWITCHES,
+                Counter::ScrubbedPages => config::BENCHMARK_SCRUBBED_PAGES,
             },
             Either::IntervalCounter(c) => match c {
-                IntervalCounter::ExecutionTime => config::BENCHMARK_TIME,
+                // The time is read from the CLINT, which does not exist in user space
+                IntervalCounter::ExecutionTime => {
+                    config::BENCHMARK_TIME && !cfg!(feature = "userspace")
+                }
                 IntervalCounter::InstructionRet => config::BENCHMARK_INSTRUCTION,
             },
         }
     }
 
+    /// Whether the config enabled the counter and the payload did not deselect it.
+    fn is_active(&self) -> bool {
+        self.is_enabled() && SELECTED_COUNTERS.load(Ordering::Relaxed) & self.mask() != 0
+    }
+
+    /// The bit of the counter in the runtime selection mask.
+    fn mask(&self) -> usize {
+        match self {
+            Either::Counter(c) => 1 << *c as usize,
+            Either::IntervalCounter(c) => 1 << (NB_COUNTER + *c as usize),
+        }
+    }
+
     /// Default value of the counter: Usually zero for occurence counters and current
     /// value for interval counters.
     fn reset_value(&self) -> usize {
@@ -119,6 +163,7 @@ impl Either {
                 Counter::TotalExits => "Total exits",
                 Counter::FirmwareExits => "Firmware exits",
                 Counter::WorldSwitches => "World Switches",
+                Counter::ScrubbedPages => "Scrubbed pages",
             },
             Either::IntervalCounter(c) => match c {
                 IntervalCounter::ExecutionTime => " Execution time ",
@@ -146,7 +191,8 @@ impl Benchmark {
                 max: 0,
                 mean: 0,
                 sum: 0,
-            }; NB_INTERVAL_COUNTER * 2],
+                started: false,
+            }; NB_INTERVAL_COUNTER * NB_SCOPES],
 
             counters: [0; NB_COUNTER],
         }
@@ -190,14 +236,16 @@ impl Benchmark {
         for counter in [
             IntervalCounter::ExecutionTime,
             IntervalCounter::InstructionRet,
-        ]
-        .map(Either::IntervalCounter)
-        {
-            if !counter.is_enabled() {
-                continue;
-            }
+        ] {
+            let wrapped_counter = Either::IntervalCounter(counter);
+            let active = wrapped_counter.is_active() && scope.is_selected();
 
-            BENCH.lock().reset(&counter, &scope);
+            let mut bench = BENCH.lock();
+            let index = Self::interval_counter_index(&counter, &scope);
+            bench.interval_counters[index].started = active;
+            if active {
+                bench.reset(&wrapped_counter, &scope);
+            }
         }
     }
 
@@ -213,11 +261,14 @@ impl Benchmark {
         ] {
             let wrapped_counter = Either::IntervalCounter(counter);
 
-            if !wrapped_counter.is_enabled() {
+            // Intervals are only recorded if the counter was selected during the whole interval
+            let mut bench = BENCH.lock();
+            let index = Self::interval_counter_index(&counter, &scope);
+            let started = core::mem::take(&mut bench.interval_counters[index].started);
+            if !started || !wrapped_counter.is_active() || !scope.is_selected() {
                 continue;
             }
 
-            let mut bench = BENCH.lock();
             let value =
                 wrapped_counter.reset_value() - bench.read_interval_counters(&counter, &scope);
 
@@ -225,6 +276,19 @@ impl Benchmark {
         }
     }
 
+    /// Runs `f` between the start and the stop of the interval counters of the scope, returns its
+    /// result.
+    pub fn in_scope<T>(scope: Scope, f: impl FnOnce() -> T) -> T {
+        if !config::BENCHMARK {
+            return f();
+        }
+
+        Self::start_interval_counters(scope);
+        let result = f();
+        Self::stop_interval_counters(scope);
+        result
+    }
+
     fn update_inteval_counter_stats(
         &mut self,
         counter: &IntervalCounter,
@@ -250,13 +314,36 @@ impl Benchmark {
 
         let wrapped_counter = Either::Counter(counter);
 
-        if !wrapped_counter.is_enabled() {
+        if !wrapped_counter.is_active() {
             return;
         }
 
         BENCH.lock().counters[index] += 1;
     }
 
+    /// Selects the counters and scopes recorded from now on, returns the previously selected
+    /// counters.
+    ///
+    /// The masks are made of `abi_vendor::benchmark` constants, unknown bits are rejected.
+    pub fn select(counters: usize, scopes: usize) -> Result<usize, &'static str> {
+        if counters & !masks::ALL_COUNTERS != 0 || scopes & !masks::ALL_SCOPES != 0 {
+            return Err("Unknown benchmark counter or scope");
+        }
+
+        SELECTED_SCOPES.store(scopes, Ordering::Relaxed);
+        Ok(SELECTED_COUNTERS.swap(counters, Ordering::Relaxed))
+    }
+
+    /// Resets all counters and histograms, intervals in progress are not recorded.
+    pub fn reset_counters() {
+        if !config::BENCHMARK {
+            return;
+        }
+
+        *BENCH.lock() = Benchmark::new();
+        histogram::reset();
+    }
+
     /// Print formated string with value of the counters
     pub fn record_counters() {
         if !config::BENCHMARK {
@@ -276,6 +363,7 @@ impl Benchmark {
             Counter::FirmwareExits,
             Counter::TotalExits,
             Counter::WorldSwitches,
+            Counter::ScrubbedPages,
         ] {
             let wrapped_counter = Either::Counter(counter);
             if !wrapped_counter.is_enabled() {
@@ -291,7 +379,7 @@ impl Benchmark {
         }
 
         // Interval counters
-        for scope in [Scope::HandleTrap, Scope::RunVCPU] {
+        for scope in [Scope::HandleTrap, Scope::RunVCPU, Scope::PolicyHook] {
             if !config::BENCHMARK_CSV_FORMAT {
                 benchmark_print!("╔{:─>30}╗", "");
                 benchmark_print!("│{:^30}│", scope.name());
@@ -331,5 +419,51 @@ impl Benchmark {
                 benchmark_print!("╚{:─>30}╝", "");
             }
         }
+
+        histogram::dump();
+    }
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runtime_selection_masks() {
+        // The runtime selection must match the vendor ABI
+        assert_eq!(
+            Either::Counter(Counter::TotalExits).mask(),
+            masks::TOTAL_EXITS
+        );
+        assert_eq!(
+            Either::Counter(Counter::FirmwareExits).mask(),
+            masks::FIRMWARE_EXITS
+        );
+        assert_eq!(
+            Either::Counter(Counter::WorldSwitches).mask(),
+            masks::WORLD_SWITCHES
+        );
+        assert_eq!(
+            Either::Counter(Counter::ScrubbedPages).mask(),
+            masks::SCRUBBED_PAGES
+        );
+        assert_eq!(
+            Either::IntervalCounter(IntervalCounter::ExecutionTime).mask(),
+            masks::EXECUTION_TIME
+        );
+        assert_eq!(
+            Either::IntervalCounter(IntervalCounter::InstructionRet).mask(),
+            masks::INSTRUCTION_RET
+        );
+        assert_eq!(1 << Scope::HandleTrap.base(), masks::SCOPE_HANDLE_TRAP);
+        assert_eq!(1 << Scope::RunVCPU.base(), masks::SCOPE_RUN_VCPU);
+        assert_eq!(1 << Scope::PolicyHook.base(), masks::SCOPE_POLICY_HOOK);
+
+        // Unknown bits are rejected and leave the selection untouched
+        assert!(Benchmark::select(1 << 6, masks::ALL_SCOPES).is_err());
+        assert!(Benchmark::select(masks::ALL_COUNTERS, 1 << 3).is_err());
+        assert!(Scope::RunVCPU.is_selected());
     }
 }
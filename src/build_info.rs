@@ -0,0 +1,162 @@
+//! Build Information
+//!
+//! To tie the logs of a board to an exact build, Miralis embeds the git revision, a hash of the
+//! build configuration, and the set of compiled-in features in the dedicated `.miralis_build_info`
+//! section of its binary. The section holds one `key=value` pair per line and can be extracted from
+//! the ELF with `rust-objcopy --dump-section .miralis_build_info=<file>`.
+//!
+//! The same information is printed at boot, and can be queried by the firmware or the payload with
+//! the `MIRALIS_BUILD_INFO_FID` call of the Miralis ABI.
+
+use crate::arch::{parse_mpp_return_mode, Arch, Architecture, Csr, Register};
+use crate::config::{BUILD_CONFIG_HASH, BUILD_REVISION};
+use crate::virt::{RegisterContextGetter, RegisterContextSetter, VirtContext};
+
+const SBI_ERR_INVALID_ADDRESS: isize = -5;
+
+/// The optional subsystems compiled in, by cargo feature name.
+pub const FEATURES: &[&str] = &[
+    #[cfg(feature = "ace")]
+    "ace",
+    #[cfg(feature = "benchmark")]
+    "benchmark",
+    #[cfg(feature = "debug")]
+    "debug",
+    #[cfg(feature = "trace")]
+    "trace",
+    #[cfg(feature = "policy_keystone")]
+    "policy_keystone",
+    #[cfg(feature = "policy_protect_payload")]
+    "policy_protect_payload",
+    #[cfg(feature = "policy_protect_domains")]
+    "policy_protect_domains",
+    #[cfg(feature = "policy_isolate_payload")]
+    "policy_isolate_payload",
+    #[cfg(feature = "policy_sbi_firewall")]
+    "policy_sbi_firewall",
+    #[cfg(feature = "fault_injection")]
+    "fault_injection",
+];
+
+const BUILD_INFO_SIZE: usize = build_info_size();
+
+/// The build information, kept in the binary even though Miralis reads it only on request.
+#[used]
+#[cfg_attr(not(feature = "userspace"), link_section = ".miralis_build_info")]
+static BUILD_INFO: [u8; BUILD_INFO_SIZE] = build_info();
+
+// ————————————————————————————————— Layout ————————————————————————————————— //
+
+/// Calls `part` with each piece of the build information, in order.
+///
+/// This is a macro rather than a function taking a closure, as closures can not be called in const
+/// contexts.
+macro_rules! for_each_part {
+    ($part:ident => $body:expr) => {
+        let $part = "revision=";
+        $body;
+        let $part = BUILD_REVISION;
+        $body;
+        let $part = "\nconfig=";
+        $body;
+        let $part = BUILD_CONFIG_HASH;
+        $body;
+        let $part = "\nfeatures=";
+        $body;
+        let mut idx = 0;
+        while idx < FEATURES.len() {
+            if idx > 0 {
+                let $part = ",";
+                $body;
+            }
+            let $part = FEATURES[idx];
+            $body;
+            idx += 1;
+        }
+        let $part = "\n";
+        $body;
+    };
+}
+
+const fn build_info_size() -> usize {
+    let mut size = 0;
+    for_each_part!(part => size += part.len());
+    size
+}
+
+const fn build_info() -> [u8; BUILD_INFO_SIZE] {
+    let mut info = [0; BUILD_INFO_SIZE];
+    let mut len = 0;
+    for_each_part!(part => {
+        let bytes = part.as_bytes();
+        let mut idx = 0;
+        while idx < bytes.len() {
+            info[len] = bytes[idx];
+            len += 1;
+            idx += 1;
+        }
+    });
+    info
+}
+
+/// Returns the build information, one `key=value` pair per line.
+pub fn as_str() -> &'static str {
+    // The build information is made of string slices, and is therefore valid UTF-8.
+    core::str::from_utf8(&BUILD_INFO).unwrap_or("")
+}
+
+// —————————————————————————————— Build Query ——————————————————————————————— //
+
+/// Handles a request to copy the build information.
+///
+/// The arguments are the address of the destination buffer and its size in bytes. The buffer is
+/// written with the privileges of the caller, and the full length of the build information is
+/// returned in a1 even if it is truncated.
+pub fn handle_query(ctx: &mut VirtContext) {
+    let addr = ctx.get(Register::X10);
+    let size = ctx.get(Register::X11);
+
+    let mut bytes = BUILD_INFO;
+    let bytes = &mut bytes[..size.min(BUILD_INFO_SIZE)];
+
+    // SAFETY: the bytes are stored with the privileges of the caller, which is the mode saved in
+    // mstatus.MPP by the trap.
+    let mode = parse_mpp_return_mode(Arch::read_csr(Csr::Mstatus));
+    let error = match unsafe { Arch::store_bytes_from_mode(bytes, addr as *const u8, mode) } {
+        Ok(()) => 0,
+        Err(()) => SBI_ERR_INVALID_ADDRESS,
+    };
+
+    ctx.set(Register::X10, error as usize);
+    ctx.set(Register::X11, BUILD_INFO_SIZE);
+    ctx.pc += 4;
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_info_layout() {
+        let info = as_str();
+        assert_eq!(info.len(), BUILD_INFO_SIZE);
+
+        let mut lines = info.lines();
+        assert_eq!(
+            lines.next().and_then(|l| l.strip_prefix("revision=")),
+            Some(BUILD_REVISION)
+        );
+        assert_eq!(
+            lines.next().and_then(|l| l.strip_prefix("config=")),
+            Some(BUILD_CONFIG_HASH)
+        );
+        let features = lines.next().and_then(|l| l.strip_prefix("features="));
+        assert_eq!(
+            features.map(|f| f.split(',').filter(|f| !f.is_empty()).count()),
+            Some(FEATURES.len())
+        );
+        assert_eq!(lines.next(), None);
+    }
+}
@@ -0,0 +1,279 @@
+//! Capability Report
+//!
+//! Miralis prints a report of the detected hardware capabilities and of its own configuration once
+//! at boot, one `key=value` pair per line with the `[capabilities]` prefix, for instance
+//! `[capabilities] isa=rv64imafdcsu` or `[capabilities] pmp=16`.
+//!
+//! The report also records whether the payload runs in S-mode, or in U-mode on cores without
+//! S-mode, and `check_requirements` rejects configurations that can not run on the detected core.
+//!
+//! The same report can be queried at runtime by the firmware or the payload with the
+//! `MIRALIS_CAPABILITIES_FID` call of the Miralis ABI, in which case the lines are returned without
+//! the prefix.
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::arch::{misa, parse_mpp_return_mode, Arch, Architecture, Csr, Register};
+use crate::build_info::FEATURES;
+use crate::config;
+use crate::host::MiralisContext;
+use crate::platform::{Plat, Platform};
+use crate::policy::{Policy, PolicyModule};
+use crate::virt::{RegisterContextGetter, RegisterContextSetter, VirtContext};
+
+/// Prefix of the report lines.
+const REPORT_PREFIX: &str = "[capabilities]";
+
+/// Maximum size of the report returned at runtime, in bytes.
+const MAX_REPORT_SIZE: usize = 1024;
+
+const SBI_ERR_INVALID_ADDRESS: isize = -5;
+
+/// Whether the report has already been printed.
+static REPORTED: AtomicBool = AtomicBool::new(false);
+
+// ————————————————————————————————— Report ————————————————————————————————— //
+
+/// Calls `entry` for each key-value pair of the report.
+fn for_each_entry(mctx: &MiralisContext, mut entry: impl FnMut(&str, fmt::Arguments)) {
+    let misa = Arch::read_csr(Csr::Misa);
+    let hw = &mctx.hw;
+
+    // Platform
+    entry("platform", format_args!("{}", Plat::name()));
+    entry("nb_harts", format_args!("{}", config::PLATFORM_NB_HARTS));
+    entry("policy", format_args!("{}", Policy::name()));
+
+    // Hardware
+    entry("isa", format_args!("{}", Isa(misa)));
+    entry("misa", format_args!("0x{:x}", misa));
+    entry("vmisa", format_args!("0x{:x}", misa & !misa::DISABLED));
+    entry("pmp", format_args!("{}", hw.available_reg.nb_pmp));
+    entry("virt_pmp", format_args!("{}", mctx.pmp.nb_virt_pmp));
+    entry("menvcfg", format_args!("{}", hw.available_reg.menvcfg));
+    entry("senvcfg", format_args!("{}", hw.available_reg.senvcfg));
+    entry("sstc", format_args!("{}", hw.extensions.has_sstc_extension));
+    entry("interrupts", format_args!("0x{:x}", hw.interrupts));
+    entry(
+        "payload_mode",
+        format_args!(
+            "{}",
+            if hw.extensions.has_s_extension {
+                "S"
+            } else {
+                "U"
+            }
+        ),
+    );
+    for device in mctx.shared.devices.iter() {
+        entry(
+            "device",
+            format_args!(
+                "{} 0x{:x}-0x{:x}",
+                device.name,
+                device.start_addr,
+                device.start_addr + device.size
+            ),
+        );
+    }
+
+    // Configuration
+    entry("vcpu_triggers", format_args!("{}", config::VCPU_TRIGGERS));
+    entry(
+        "delegate_perf_counters",
+        format_args!("{}", config::DELEGATE_PERF_COUNTER),
+    );
+    entry(
+        "counter_poll_threshold",
+        format_args!("{}", OptionalUsize(config::COUNTER_POLL_THRESHOLD)),
+    );
+    entry(
+        "max_firmware_exits",
+        format_args!("{}", OptionalUsize(config::MAX_FIRMWARE_EXIT)),
+    );
+    entry("revision", format_args!("{}", config::BUILD_REVISION));
+    entry("config_hash", format_args!("{}", config::BUILD_CONFIG_HASH));
+    entry("features", format_args!("{}", List(FEATURES)));
+    entry("benchmark", format_args!("{}", config::BENCHMARK));
+    entry("coverage", format_args!("{}", config::COVERAGE));
+    entry(
+        "fault_injection",
+        format_args!("{}", cfg!(feature = "fault_injection")),
+    );
+    entry(
+        "audit_log_entries",
+        format_args!("{}", config::AUDIT_LOG_ENTRIES),
+    );
+    entry(
+        "sbi_deny_list",
+        format_args!("{}", List(config::SBI_DENY_LIST)),
+    );
+}
+
+/// Checks that the hardware provides the capabilities required by the configuration.
+pub fn check_requirements(mctx: &MiralisContext) -> Result<(), &'static str> {
+    if Policy::REQUIRES_S_MODE && !mctx.hw.extensions.has_s_extension {
+        return Err("the policy requires S-mode, which is not implemented by this core");
+    }
+    Ok(())
+}
+
+/// Prints the capability report, only the first call has an effect.
+pub fn log_report(mctx: &MiralisContext) {
+    if REPORTED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+
+    for_each_entry(mctx, |key, value| {
+        Plat::debug_print(
+            log::Level::Info,
+            format_args!("{} {}={}\r\n", REPORT_PREFIX, key, value),
+        )
+    });
+}
+
+/// Formats the ISA string from the value of misa, e.g. `rv64imafdc`.
+struct Isa(usize);
+
+impl fmt::Display for Isa {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("rv64")?;
+        for (bit, letter) in ('a'..='z').enumerate() {
+            if self.0 & (1 << bit) != 0 {
+                f.write_char(letter)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Formats an optional value, `none` if absent.
+struct OptionalUsize(Option<usize>);
+
+impl fmt::Display for OptionalUsize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(value) => write!(f, "{}", value),
+            None => f.write_str("none"),
+        }
+    }
+}
+
+/// Formats a list as comma-separated values.
+struct List<'a>(&'a [&'a str]);
+
+impl fmt::Display for List<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, item) in self.0.iter().enumerate() {
+            if idx > 0 {
+                f.write_char(',')?;
+            }
+            f.write_str(item)?;
+        }
+        Ok(())
+    }
+}
+
+// ————————————————————————————— Runtime Query —————————————————————————————— //
+
+/// A fixed-size buffer, which keeps track of the size of the text written past its end.
+struct ReportBuffer {
+    bytes: [u8; MAX_REPORT_SIZE],
+    len: usize,
+}
+
+impl ReportBuffer {
+    fn new() -> Self {
+        ReportBuffer {
+            bytes: [0; MAX_REPORT_SIZE],
+            len: 0,
+        }
+    }
+
+    /// Returns the content of the buffer, truncated to its capacity.
+    fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len.min(MAX_REPORT_SIZE)]
+    }
+
+    /// Same as [Self::as_bytes], but mutable.
+    fn as_bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes[..self.len.min(MAX_REPORT_SIZE)]
+    }
+}
+
+impl fmt::Write for ReportBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            if let Some(slot) = self.bytes.get_mut(self.len) {
+                *slot = byte;
+            }
+            self.len += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Handles a request to copy the capability report.
+///
+/// The arguments are the address of the destination buffer and its size in bytes. The buffer is
+/// written with the privileges of the caller, and the full length of the report is returned in a1
+/// even if the report is truncated.
+pub fn handle_query(ctx: &mut VirtContext, mctx: &MiralisContext) {
+    let addr = ctx.get(Register::X10);
+    let size = ctx.get(Register::X11);
+
+    let mut report = ReportBuffer::new();
+    for_each_entry(mctx, |key, value| {
+        // Writing into the report buffer can not fail
+        writeln!(report, "{}={}", key, value).ok();
+    });
+    let len = report.len;
+    let copied = size.min(report.as_bytes().len());
+    let bytes = &mut report.as_bytes_mut()[..copied];
+
+    // SAFETY: the bytes are stored with the privileges of the caller, which is the mode saved in
+    // mstatus.MPP by the trap.
+    let mode = parse_mpp_return_mode(Arch::read_csr(Csr::Mstatus));
+    let error = match unsafe { Arch::store_bytes_from_mode(bytes, addr as *const u8, mode) } {
+        Ok(()) => 0,
+        Err(()) => SBI_ERR_INVALID_ADDRESS,
+    };
+
+    ctx.set(Register::X10, error as usize);
+    ctx.set(Register::X11, len);
+    ctx.pc += 4;
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_values() {
+        let mut buffer = ReportBuffer::new();
+        write!(
+            buffer,
+            "{}",
+            Isa(misa::I | misa::M | misa::A | misa::S | misa::U)
+        )
+        .unwrap();
+        assert_eq!(buffer.as_bytes(), b"rv64aimsu");
+
+        let mut buffer = ReportBuffer::new();
+        write!(buffer, "{} {}", OptionalUsize(None), List(&["pmu", "0x10"])).unwrap();
+        assert_eq!(buffer.as_bytes(), b"none pmu,0x10");
+    }
+
+    #[test]
+    fn truncate_report() {
+        let mut buffer = ReportBuffer::new();
+        for _ in 0..MAX_REPORT_SIZE {
+            buffer.write_str("ab").unwrap();
+        }
+        assert_eq!(buffer.len, 2 * MAX_REPORT_SIZE);
+        assert_eq!(buffer.as_bytes().len(), MAX_REPORT_SIZE);
+    }
+}
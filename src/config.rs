@@ -15,16 +15,90 @@ use crate::platform::{Plat, Platform};
 /// Maximum number of PMP exposed by the vCPU, no limit if None.
 pub const VCPU_MAX_PMP: Option<usize> = parse_usize(option_env!("MIRALIS_VCPU_MAX_PMP"));
 
+/// If the vCPU exposes as many PMP as the hardware, coalescing the virtual PMP regions to fit in
+/// the entries left by Miralis.
+pub const VCPU_PMP_COALESCING: bool = is_enabled_default_false!("MIRALIS_VCPU_PMP_COALESCING");
+
+/// Number of virtual triggers (tselect/tdata*) exposed by the vCPU.
+///
+/// Virtual triggers can be configured by the firmware but never fire, the hardware triggers are
+/// reserved to external debuggers. Requires the `debug` feature.
+pub const VCPU_TRIGGERS: usize = if cfg!(feature = "debug") {
+    parse_usize_or(option_env!("MIRALIS_VCPU_TRIGGERS"), 0)
+} else {
+    0
+};
+
+/// If firmware accesses to the hardware performance monitoring counters (mhpmcounter,
+/// mhpmevent, and hpmcounter) raise an illegal instruction exception, instead of being emulated
+/// as read-only zero.
+pub const VCPU_TRAP_HPM_COUNTERS: bool =
+    is_enabled_default_false!("MIRALIS_VCPU_TRAP_HPM_COUNTERS");
+
+/// If the firmware and the payload observe the physical mcycle and minstret, instead of per-world
+/// virtual counters.
+pub const VCPU_RAW_COUNTERS: bool = is_enabled_default_false!("MIRALIS_VCPU_RAW_COUNTERS");
+
+/// If the virtual mcycle and minstret are frozen while the hart executes inside Miralis, hiding
+/// the exits from the running world.
+pub const VCPU_FREEZE_COUNTERS: bool = is_enabled_default_false!("MIRALIS_VCPU_FREEZE_COUNTERS");
+
+/// If misaligned loads and stores are emulated by Miralis, instead of being forwarded to the
+/// firmware. Enabled by default on platforms whose cores trap misaligned accesses.
+pub const VCPU_EMULATE_MISALIGNED: bool = match option_env!("MIRALIS_VCPU_EMULATE_MISALIGNED") {
+    Some(value) => !matches!(value.as_bytes(), b"false"),
+    None => Plat::TRAPS_MISALIGNED_ACCESSES,
+};
+
+/// The git revision Miralis was built from, provided by the runner.
+pub const BUILD_REVISION: &str = parse_str_or(option_env!("MIRALIS_BUILD_REVISION"), "unknown");
+
+/// A hash of the build configuration, provided by the runner.
+pub const BUILD_CONFIG_HASH: &str =
+    parse_str_or(option_env!("MIRALIS_BUILD_CONFIG_HASH"), "unknown");
+
 /// The desired log level.
 pub const LOG_LEVEL: Option<&'static str> = option_env!("MIRALIS_LOG_LEVEL");
 
+/// Per-module log filters, e.g. `virt=debug,ace=info`.
+pub const LOG_FILTERS: Option<&'static str> = option_env!("MIRALIS_LOG_FILTERS");
+
 /// If colors in logs are enabled.
 pub const LOG_COLOR: bool = is_enabled!("MIRALIS_LOG_COLOR");
 
+/// If console lines are tagged with the world which produced them.
+pub const LOG_CONSOLE_TAGS: bool = is_enabled_default_false!("MIRALIS_LOG_CONSOLE_TAGS");
+
 /// The maximum number of firmware exits before quitting.
 pub const MAX_FIRMWARE_EXIT: Option<usize> =
     parse_usize(option_env!("MIRALIS_DEBUG_MAX_FIRMWARE_EXITS"));
 
+/// If emulation coverage counters are enabled, requires the `debug` feature.
+pub const COVERAGE: bool =
+    cfg!(feature = "debug") && is_enabled_default_false!("MIRALIS_DEBUG_COVERAGE");
+
+/// If firmware breakpoints stop in the debug stub, requires the `debug` feature.
+pub const DEBUG_STUB: bool =
+    cfg!(feature = "debug") && is_enabled_default_false!("MIRALIS_DEBUG_STUB");
+
+/// If the semihosting calls of the firmware are served by Miralis.
+pub const DEBUG_SEMIHOSTING: bool = is_enabled_default_false!("MIRALIS_DEBUG_SEMIHOSTING");
+
+/// Number of trace events retained per hart, 0 disables tracing. Requires the `trace` feature.
+pub const TRACE_ENTRIES: usize = if cfg!(feature = "trace") {
+    parse_usize_or(option_env!("MIRALIS_DEBUG_TRACE_ENTRIES"), 0)
+} else {
+    0
+};
+
+/// Number of trap records retained per hart, 0 disables the trap log. Requires the `debug`
+/// feature.
+pub const TRAP_LOG_ENTRIES: usize = if cfg!(feature = "debug") {
+    parse_usize_or(option_env!("MIRALIS_DEBUG_TRAP_LOG_ENTRIES"), 0)
+} else {
+    0
+};
+
 /// Log error
 pub const LOG_ERROR: &[&str; str_list_len(option_env!("MIRALIS_LOG_ERROR"))] =
     &parse_str_list(option_env!("MIRALIS_LOG_ERROR"));
@@ -61,13 +135,56 @@ pub const PLATFORM_NB_HARTS: usize = {
 /// Delegate performance counters
 pub const DELEGATE_PERF_COUNTER: bool = is_enabled_default_false!("MIRALIS_DELEGATE_PERF_COUNTER");
 
+/// Number of consecutive exits on the same counter read (e.g. `rdtime`) after which the counter
+/// is passed through to the firmware until its next exit, coalescing polling loops. Disabled if
+/// None.
+pub const COUNTER_POLL_THRESHOLD: Option<usize> =
+    parse_usize(option_env!("MIRALIS_VCPU_COUNTER_POLL_THRESHOLD"));
+
 /// Boot hart id
 #[allow(dead_code)] // Because rust analyzer doesn't understand that it is used in metals.rs
 pub const PLATFORM_BOOT_HART_ID: usize =
     parse_usize_or(option_env!("MIRALIS_PLATFORM_BOOT_HART_ID"), 0);
 
-/// Whether any benchmark is enable
-pub const BENCHMARK: bool = is_enabled!("MIRALIS_BENCHMARK");
+/// Address at which the patched device tree passed to the firmware is written, the device tree
+/// from the previous boot stage is passed unmodified if None.
+pub const PLATFORM_DTB_PATCH_ADDRESS: Option<usize> =
+    parse_usize(option_env!("MIRALIS_PLATFORM_DTB_PATCH_ADDRESS"));
+
+/// Unit addresses of the device nodes removed from the patched device tree
+pub const PLATFORM_DTB_HIDDEN_DEVICES: &[&str;
+     str_list_len(option_env!(
+        "MIRALIS_PLATFORM_DTB_HIDDEN_DEVICES"
+    ))] = &parse_str_list(option_env!("MIRALIS_PLATFORM_DTB_HIDDEN_DEVICES"));
+
+/// Kernel command line set in the `chosen` node of the patched device tree
+pub const PLATFORM_DTB_BOOTARGS: Option<&'static str> =
+    option_env!("MIRALIS_PLATFORM_DTB_BOOTARGS");
+
+/// Load the firmware from the parallel flash rather than expecting it in memory
+pub const PLATFORM_FIRMWARE_PFLASH: bool =
+    is_enabled_default_false!("MIRALIS_PLATFORM_FIRMWARE_PFLASH");
+
+/// Seed of the fault injection pseudo-random generator
+pub const FAULT_INJECTION_SEED: usize =
+    parse_usize_or(option_env!("MIRALIS_FAULT_INJECTION_SEED"), 0x2545f491);
+
+/// Probability of flipping a bit of a CSR read by the firmware, in parts per million
+pub const FAULT_INJECTION_CSR_FLIP_PPM: usize =
+    parse_usize_or(option_env!("MIRALIS_FAULT_INJECTION_CSR_FLIP_PPM"), 0);
+
+/// Probability of delaying a virtual interrupt injection, in parts per million
+pub const FAULT_INJECTION_INTERRUPT_DELAY_PPM: usize = parse_usize_or(
+    option_env!("MIRALIS_FAULT_INJECTION_INTERRUPT_DELAY_PPM"),
+    0,
+);
+
+/// Probability of skipping the cache flush after a PMP update, in parts per million
+pub const FAULT_INJECTION_PMP_FLUSH_PPM: usize =
+    parse_usize_or(option_env!("MIRALIS_FAULT_INJECTION_PMP_FLUSH_PPM"), 0);
+
+/// Whether any benchmark is enable, requires the `benchmark` feature.
+pub const BENCHMARK: bool = cfg!(feature = "benchmark") && is_enabled!("MIRALIS_BENCHMARK");
 
 /// Whether print in csv format or not
 pub const BENCHMARK_CSV_FORMAT: bool = is_enabled!("MIRALIS_BENCHMARK_CSV_FORMAT");
@@ -87,6 +204,26 @@ pub const BENCHMARK_NB_FIRMWARE_EXITS: bool = is_enabled!("MIRALIS_BENCHMARK_NB_
 /// Whether count or not number of world switches
 pub const BENCHMARK_WORLD_SWITCHES: bool = is_enabled!("MIRALIS_BENCHMARK_WORLD_SWITCHES");
 
+/// Whether count or not number of confidential pages zeroed by the ACE page scrubber
+pub const BENCHMARK_SCRUBBED_PAGES: bool = is_enabled!("MIRALIS_BENCHMARK_SCRUBBED_PAGES");
+
+/// Whether the latency histograms of firmware exits and world switches are collected
+pub const BENCHMARK_HISTOGRAM: bool =
+    BENCHMARK && is_enabled_default_false!("MIRALIS_BENCHMARK_HISTOGRAM");
+
+/// Upper bounds of the latency histogram buckets in cycles, default bounds are used if empty
+pub const BENCHMARK_HISTOGRAM_BUCKETS: &[&str;
+     str_list_len(option_env!(
+        "MIRALIS_BENCHMARK_HISTOGRAM_BUCKETS"
+    ))] = &parse_str_list(option_env!("MIRALIS_BENCHMARK_HISTOGRAM_BUCKETS"));
+
+/// Number of raw samples retained per latency histogram and dumped with the results
+pub const BENCHMARK_HISTOGRAM_SAMPLES: usize = if BENCHMARK_HISTOGRAM {
+    parse_usize_or(option_env!("MIRALIS_BENCHMARK_HISTOGRAM_SAMPLES"), 1024)
+} else {
+    0
+};
+
 /// Start address of Miralis
 pub const TARGET_START_ADDRESS: usize =
     parse_usize_or(option_env!("MIRALIS_TARGET_START_ADDRESS"), 0x80000000);
@@ -113,5 +250,56 @@ pub const TARGET_STACK_SIZE: usize =
 #[allow(unused)]
 pub const POLICY_NAME: &str = parse_str_or(option_env!("MIRALIS_POLICY_NAME"), "default_policy");
 
+/// The name of the policy stacked on top of the selected one, if any.
+///
+/// Like [POLICY_NAME], this forces re-compilation when the stacked policy changes.
+pub const POLICY_STACK: &str = parse_str_or(option_env!("MIRALIS_POLICY_STACK"), "none");
+
+/// The SBI extensions hidden from the payload, by name, extension ID, or `vendor`
+pub const SBI_DENY_LIST: &[&str; str_list_len(option_env!("MIRALIS_POLICY_SBI_DENY_LIST"))] =
+    &parse_str_list(option_env!("MIRALIS_POLICY_SBI_DENY_LIST"));
+
+/// The policy options, as `<policy>.<option>=<value>` entries
+pub const POLICY_OPTIONS: &[&str; str_list_len(option_env!("MIRALIS_POLICY_OPTIONS"))] =
+    &parse_str_list(option_env!("MIRALIS_POLICY_OPTIONS"));
+
+/// Number of entries retained by the audit log, the log is disabled if 0
+pub const AUDIT_LOG_ENTRIES: usize =
+    parse_usize_or(option_env!("MIRALIS_POLICY_AUDIT_LOG_ENTRIES"), 0);
+
 /// Size of the payload to hash
 pub const PAYLOAD_HASH_SIZE: usize = parse_usize_or(option_env!("PAYLOAD_HASH_SIZE"), 0x2000000);
+
+/// The device secret from which the ACE security monitor derives sealing keys
+pub const ACE_DEVICE_SECRET: Option<&'static str> = option_env!("MIRALIS_ACE_DEVICE_SECRET");
+
+/// The secret shared by the ACE security monitors between which confidential VMs can be migrated
+pub const ACE_MIGRATION_SECRET: Option<&'static str> = option_env!("MIRALIS_ACE_MIGRATION_SECRET");
+
+/// The guest physical address ranges the boot hart of a VM can start at to be promoted by ACE
+pub const ACE_PROMOTION_ENTRY_POINTS: &[&str;
+     str_list_len(option_env!(
+        "MIRALIS_ACE_PROMOTION_ENTRY_POINTS"
+    ))] = &parse_str_list(option_env!("MIRALIS_ACE_PROMOTION_ENTRY_POINTS"));
+
+/// The guest physical address ranges that must be zero for a VM to be promoted by ACE
+pub const ACE_PROMOTION_ZEROED_REGIONS: &[&str;
+     str_list_len(option_env!(
+        "MIRALIS_ACE_PROMOTION_ZEROED_REGIONS"
+    ))] = &parse_str_list(option_env!("MIRALIS_ACE_PROMOTION_ZEROED_REGIONS"));
+
+/// The physical address ranges dedicated to ACE confidential memory, by default the second half of
+/// the memory is used
+pub const ACE_CONFIDENTIAL_MEMORY: &[&str;
+     str_list_len(option_env!(
+        "MIRALIS_ACE_CONFIDENTIAL_MEMORY"
+    ))] = &parse_str_list(option_env!("MIRALIS_ACE_CONFIDENTIAL_MEMORY"));
+
+/// Whether ACE checks that the FDT of a VM is well formed before promoting it
+pub const ACE_PROMOTION_CHECK_FDT: bool =
+    is_enabled_default_false!("MIRALIS_ACE_PROMOTION_CHECK_FDT");
+
+/// Time, in milliseconds, the hypervisor has to respond to a request of a confidential VM before ACE
+/// reports a failure to the VM
+pub const ACE_RESPONSE_TIMEOUT_MS: usize =
+    parse_usize_or(option_env!("MIRALIS_ACE_RESPONSE_TIMEOUT_MS"), 10_000);
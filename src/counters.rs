@@ -0,0 +1,295 @@
+//! Virtual Cycle and Instret Counters
+//!
+//! Each world observes its own `mcycle` and `minstret`, defined as the physical counters minus a
+//! per-world offset. The physical counters are recorded when a world is switched out, and the
+//! cycles and instructions retired until it is switched back in are added to its offset. A world
+//! therefore only observes its counters advancing while it runs: the other world and the world
+//! switches are hidden, such that deltas measured by a world are not inflated by the other one.
+//! The exits handled by Miralis on behalf of the running world are still accounted to it.
+//!
+//! The counters can also be frozen, for both worlds at once: by the firmware through
+//! `mcountinhibit`, and while the hart executes inside Miralis if `vcpu.freeze_counters` is enabled
+//! (the exits are then hidden from the running world too). The cycles and instructions retired
+//! while frozen are subtracted from the physical counters before applying the per-world offsets,
+//! such that the world switch accounting is unaffected. The machine time is never frozen, as the
+//! `time` CSR and the timer deadlines follow the physical `mtime` (see [crate::timebase]).
+//!
+//! The counters are per hart, as are the physical counters. Builds configured with
+//! `vcpu.raw_counters` expose the physical counters instead, which is useful for benchmarks
+//! measuring the overhead of Miralis. The counters can then not be frozen.
+
+use crate::arch::{mcounteren, Arch, Architecture, Csr};
+use crate::config::{VCPU_FREEZE_COUNTERS, VCPU_RAW_COUNTERS};
+use crate::virt::ExecutionMode;
+
+const NB_WORLDS: usize = 2;
+const NB_COUNTERS: usize = 2;
+
+/// The virtualized counters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Counter {
+    /// `mcycle`, also read as `cycle`.
+    Cycle,
+    /// `minstret`, also read as `instret`.
+    Instret,
+}
+
+impl Counter {
+    const ALL: [Counter; NB_COUNTERS] = [Counter::Cycle, Counter::Instret];
+
+    /// Bit of the counter in `mcountinhibit`.
+    fn inhibit_filter(self) -> usize {
+        match self {
+            Counter::Cycle => mcounteren::CY_FILTER,
+            Counter::Instret => mcounteren::IR_FILTER,
+        }
+    }
+
+    fn index(self) -> usize {
+        match self {
+            Counter::Cycle => 0,
+            Counter::Instret => 1,
+        }
+    }
+
+    fn read_physical(self) -> usize {
+        match self {
+            Counter::Cycle => Arch::read_csr(Csr::Mcycle),
+            Counter::Instret => Arch::read_csr(Csr::Minstret),
+        }
+    }
+}
+
+/// Per-world views of the cycle and instret counters of a hart.
+#[derive(Debug)]
+pub struct VirtCounters {
+    /// Value subtracted from the physical counters, for each world.
+    offsets: [[usize; NB_COUNTERS]; NB_WORLDS],
+    /// Unfrozen counters when each world was last switched out.
+    switched_out: [[usize; NB_COUNTERS]; NB_WORLDS],
+    /// Cycles and instructions retired while the counters were frozen, hidden from both worlds.
+    frozen_total: [usize; NB_COUNTERS],
+    /// Physical counters when they were frozen, if they currently are.
+    frozen_at: [Option<usize>; NB_COUNTERS],
+    /// The value of `mcountinhibit` written by the firmware.
+    inhibit: usize,
+    /// If the hart executes inside Miralis, only tracked with `vcpu.freeze_counters`.
+    in_miralis: bool,
+}
+
+impl VirtCounters {
+    pub const fn new() -> Self {
+        VirtCounters {
+            offsets: [[0; NB_COUNTERS]; NB_WORLDS],
+            switched_out: [[0; NB_COUNTERS]; NB_WORLDS],
+            frozen_total: [0; NB_COUNTERS],
+            frozen_at: [None; NB_COUNTERS],
+            inhibit: 0,
+            in_miralis: false,
+        }
+    }
+
+    fn world_index(world: ExecutionMode) -> usize {
+        match world {
+            ExecutionMode::Firmware => 0,
+            ExecutionMode::Payload => 1,
+        }
+    }
+
+    /// Reads the counter as observed by the given world.
+    pub fn read(&self, world: ExecutionMode, counter: Counter) -> usize {
+        let physical = counter.read_physical();
+        if VCPU_RAW_COUNTERS {
+            return physical;
+        }
+        self.view(world, counter, physical)
+    }
+
+    /// Returns the counter observed by the given world for a physical counter of `physical`.
+    pub fn view(&self, world: ExecutionMode, counter: Counter, physical: usize) -> usize {
+        self.unfrozen(counter, physical)
+            .wrapping_sub(self.offsets[Self::world_index(world)][counter.index()])
+    }
+
+    /// Returns the physical counter minus the time spent frozen, from which the views of the
+    /// worlds are derived.
+    fn unfrozen(&self, counter: Counter, physical: usize) -> usize {
+        let idx = counter.index();
+        self.frozen_at[idx]
+            .unwrap_or(physical)
+            .wrapping_sub(self.frozen_total[idx])
+    }
+
+    /// Sets the counter observed by the given world to `value`.
+    ///
+    /// Writes are ignored when the physical counters are exposed, as Miralis relies on them.
+    pub fn write(&mut self, world: ExecutionMode, counter: Counter, value: usize) {
+        if VCPU_RAW_COUNTERS {
+            return;
+        }
+        self.write_at(world, counter, value, counter.read_physical());
+    }
+
+    /// Same as [Self::write], for a physical counter of `physical`.
+    pub fn write_at(
+        &mut self,
+        world: ExecutionMode,
+        counter: Counter,
+        value: usize,
+        physical: usize,
+    ) {
+        let unfrozen = self.unfrozen(counter, physical);
+        self.offsets[Self::world_index(world)][counter.index()] = unfrozen.wrapping_sub(value);
+    }
+
+    /// Returns true if the counters of the given world differ from the physical counters, in
+    /// which case its accesses must be emulated.
+    pub fn is_offset(&self, world: ExecutionMode) -> bool {
+        !VCPU_RAW_COUNTERS
+            && (self.offsets[Self::world_index(world)] != [0; NB_COUNTERS]
+                || self.frozen_total != [0; NB_COUNTERS]
+                || self.frozen_at.iter().any(Option::is_some))
+    }
+
+    /// Freezes the counters inhibited by the firmware in `mcountinhibit`, for both worlds.
+    ///
+    /// The inhibit bits are ignored when the physical counters are exposed, as Miralis relies on
+    /// them.
+    pub fn set_inhibit(&mut self, mcountinhibit: usize) {
+        if VCPU_RAW_COUNTERS {
+            return;
+        }
+        self.inhibit = mcountinhibit;
+        self.update_frozen(Counter::ALL.map(Counter::read_physical));
+    }
+
+    /// Freezes the counters while the hart executes inside Miralis, if `vcpu.freeze_counters` is
+    /// enabled. Must be called right after leaving the virtual CPU.
+    #[inline]
+    pub fn enter_miralis(&mut self) {
+        if !VCPU_FREEZE_COUNTERS || VCPU_RAW_COUNTERS {
+            return;
+        }
+        self.in_miralis = true;
+        self.update_frozen(Counter::ALL.map(Counter::read_physical));
+    }
+
+    /// Resumes the counters frozen by [Self::enter_miralis]. Must be called right before entering
+    /// the virtual CPU.
+    #[inline]
+    pub fn leave_miralis(&mut self) {
+        if !VCPU_FREEZE_COUNTERS || VCPU_RAW_COUNTERS {
+            return;
+        }
+        self.in_miralis = false;
+        self.update_frozen(Counter::ALL.map(Counter::read_physical));
+    }
+
+    /// Freezes or resumes each counter for physical counters of `physical`, depending on the
+    /// inhibit bits and on whether the hart executes inside Miralis.
+    fn update_frozen(&mut self, physical: [usize; NB_COUNTERS]) {
+        for counter in Counter::ALL {
+            let idx = counter.index();
+            let frozen = self.in_miralis || self.inhibit & counter.inhibit_filter() != 0;
+            match (self.frozen_at[idx], frozen) {
+                (None, true) => self.frozen_at[idx] = Some(physical[idx]),
+                (Some(frozen_at), false) => {
+                    let elapsed = physical[idx].wrapping_sub(frozen_at);
+                    self.frozen_total[idx] = self.frozen_total[idx].wrapping_add(elapsed);
+                    self.frozen_at[idx] = None;
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Updates the offsets on a world switch, from the world `from` to the world `to`.
+    pub fn switch(&mut self, from: ExecutionMode, to: ExecutionMode) {
+        if VCPU_RAW_COUNTERS {
+            return;
+        }
+        let physical = Counter::ALL.map(Counter::read_physical);
+        self.switch_at(from, to, physical);
+    }
+
+    /// Same as [Self::switch], for physical counters of `physical`.
+    fn switch_at(
+        &mut self,
+        from: ExecutionMode,
+        to: ExecutionMode,
+        physical: [usize; NB_COUNTERS],
+    ) {
+        let (from, to) = (Self::world_index(from), Self::world_index(to));
+        for counter in Counter::ALL {
+            let idx = counter.index();
+            let unfrozen = self.unfrozen(counter, physical[idx]);
+            self.switched_out[from][idx] = unfrozen;
+            let elapsed = unfrozen.wrapping_sub(self.switched_out[to][idx]);
+            self.offsets[to][idx] = self.offsets[to][idx].wrapping_add(elapsed);
+        }
+    }
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIRMWARE: ExecutionMode = ExecutionMode::Firmware;
+    const PAYLOAD: ExecutionMode = ExecutionMode::Payload;
+
+    #[test]
+    fn counters_only_advance_in_their_world() {
+        let mut counters = VirtCounters::new();
+        let cycle = |counters: &VirtCounters, world, physical| {
+            counters.view(world, Counter::Cycle, physical)
+        };
+
+        // The firmware boots and runs for 100 cycles before jumping into the payload
+        assert_eq!(cycle(&counters, FIRMWARE, 100), 100);
+        counters.switch_at(FIRMWARE, PAYLOAD, [100, 50]);
+        assert_eq!(cycle(&counters, PAYLOAD, 100), 0);
+        assert_eq!(cycle(&counters, PAYLOAD, 400), 300);
+
+        // The payload time is hidden from the firmware, and conversely
+        counters.switch_at(PAYLOAD, FIRMWARE, [400, 200]);
+        assert_eq!(cycle(&counters, FIRMWARE, 450), 150);
+        assert_eq!(counters.view(FIRMWARE, Counter::Instret, 220), 70);
+        counters.switch_at(FIRMWARE, PAYLOAD, [450, 220]);
+        assert_eq!(cycle(&counters, PAYLOAD, 460), 310);
+        assert_eq!(counters.view(PAYLOAD, Counter::Instret, 230), 160);
+    }
+    #[test]
+    fn frozen_counters() {
+        let mut counters = VirtCounters::new();
+        let cycle = |counters: &VirtCounters, world, physical| {
+            counters.view(world, Counter::Cycle, physical)
+        };
+
+        // The firmware inhibits mcycle, minstret keeps counting
+        counters.inhibit = mcounteren::CY_FILTER;
+        counters.update_frozen([100, 50]);
+        assert_eq!(cycle(&counters, FIRMWARE, 300), 100);
+        assert_eq!(counters.view(FIRMWARE, Counter::Instret, 80), 80);
+
+        // The cycles retired while inhibited are hidden from both worlds, including across world
+        // switches
+        counters.switch_at(FIRMWARE, PAYLOAD, [400, 100]);
+        assert_eq!(cycle(&counters, PAYLOAD, 500), 0);
+        counters.inhibit = 0;
+        counters.update_frozen([600, 120]);
+        assert_eq!(cycle(&counters, PAYLOAD, 650), 50);
+        counters.switch_at(PAYLOAD, FIRMWARE, [700, 130]);
+        assert_eq!(cycle(&counters, FIRMWARE, 700), 100);
+
+        // The time spent inside Miralis is hidden from the running world
+        counters.in_miralis = true;
+        counters.update_frozen([800, 140]);
+        assert_eq!(cycle(&counters, FIRMWARE, 900), 200);
+        counters.in_miralis = false;
+        counters.update_frozen([1000, 150]);
+        assert_eq!(cycle(&counters, FIRMWARE, 1100), 300);
+        assert_eq!(counters.view(FIRMWARE, Counter::Instret, 160), 120);
+    }
+}
@@ -0,0 +1,257 @@
+//! Emulation Coverage
+//!
+//! When enabled in the configuration, Miralis counts how many times each emulation path is taken:
+//! the CSRs accessed by the firmware, the trap causes handled for each world, and the device
+//! offsets accessed through the virtual devices. The counters are printed when Miralis exits, one
+//! per line with the `[coverage]` prefix, such that `runner coverage` can aggregate them over the
+//! integration tests and report the emulation paths that no test firmware reaches.
+//!
+//! Each line has the form `<kind> <count> <path>`, for instance `csr 3 0x300` or
+//! `trap 12 firmware: illegal instruction`.
+//!
+//! All the CSRs emulated by Miralis, trap causes, and virtual devices are printed, including the
+//! ones that were never reached.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use spin::Mutex;
+
+use crate::arch::MCause;
+use crate::config;
+use crate::device::registry::DeviceRegistry;
+use crate::device::VirtDevice;
+use crate::host::MiralisContext;
+use crate::platform::{Plat, Platform};
+use crate::virt::ExecutionMode;
+
+/// Prefix of the coverage lines, must match the runner.
+const COVERAGE_PREFIX: &str = "[coverage]";
+
+const NB_CSRS: usize = 1 << 12;
+const NB_CAUSES: usize = 64;
+const NB_WORLDS: usize = 2;
+const MAX_DEVICE_OFFSETS: usize = 128;
+
+const SYSTEM_OPCODE: usize = 0b1110011;
+
+/// Number of accesses to each CSR number.
+static CSRS: [AtomicUsize; NB_CSRS] = [const { AtomicUsize::new(0) }; NB_CSRS];
+
+/// Bitmap of the CSR numbers emulated by Miralis, computed once at boot.
+static EMULATED_CSRS: [AtomicUsize; NB_CSRS / usize::BITS as usize] =
+    [const { AtomicUsize::new(0) }; NB_CSRS / usize::BITS as usize];
+
+/// Number of traps for each world, exceptions first then interrupts.
+static TRAPS: [[AtomicUsize; 2 * NB_CAUSES]; NB_WORLDS] =
+    [const { [const { AtomicUsize::new(0) }; 2 * NB_CAUSES] }; NB_WORLDS];
+
+/// Number of accesses to each (device, offset) pair.
+static DEVICES: Mutex<[Option<DeviceAccess>; MAX_DEVICE_OFFSETS]> =
+    Mutex::new([None; MAX_DEVICE_OFFSETS]);
+
+#[derive(Clone, Copy)]
+struct DeviceAccess {
+    name: &'static str,
+    offset: usize,
+    count: usize,
+}
+
+// ——————————————————————————————— Recording ———————————————————————————————— //
+
+/// Computes the set of CSRs emulated by Miralis on this hardware.
+pub fn init(mctx: &MiralisContext) {
+    if !config::COVERAGE {
+        return;
+    }
+
+    for csr in 0..NB_CSRS {
+        if !mctx.decode_csr(csr).is_unknown() {
+            EMULATED_CSRS[csr / usize::BITS as usize]
+                .fetch_or(1 << (csr % usize::BITS as usize), Ordering::Relaxed);
+        }
+    }
+}
+
+/// Records a CSR access, if the raw instruction is a CSR instruction.
+#[inline]
+pub fn record_instr(raw: usize) {
+    if !config::COVERAGE {
+        return;
+    }
+
+    if let Some(csr) = csr_number(raw) {
+        CSRS[csr].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Records a trap handled on behalf of the given world.
+#[inline]
+pub fn record_trap(world: ExecutionMode, mcause: usize) {
+    if !config::COVERAGE {
+        return;
+    }
+
+    if let Some(idx) = trap_index(mcause) {
+        TRAPS[world_index(world)][idx].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Records an access to a virtual device.
+#[inline]
+pub fn record_device_access(device: &VirtDevice, offset: usize) {
+    if !config::COVERAGE {
+        return;
+    }
+
+    let mut devices = DEVICES.lock();
+    for entry in devices.iter_mut() {
+        match entry {
+            Some(access) if access.name == device.name && access.offset == offset => {
+                access.count += 1;
+                return;
+            }
+            Some(_) => continue,
+            None => {
+                *entry = Some(DeviceAccess {
+                    name: device.name,
+                    offset,
+                    count: 1,
+                });
+                return;
+            }
+        }
+    }
+    log::debug!(
+        "Coverage: too many device offsets, dropping {}",
+        device.name
+    );
+}
+
+fn csr_number(raw: usize) -> Option<usize> {
+    let funct3 = (raw >> 12) & 0b111;
+    if raw & 0b1111111 == SYSTEM_OPCODE && funct3 != 0 && funct3 != 0b100 {
+        Some((raw >> 20) & 0xfff)
+    } else {
+        None
+    }
+}
+
+fn trap_index(mcause: usize) -> Option<usize> {
+    let number = MCause::cause_number(mcause);
+    if number >= NB_CAUSES {
+        return None;
+    }
+    if MCause::new(mcause).is_interrupt() {
+        Some(NB_CAUSES + number)
+    } else {
+        Some(number)
+    }
+}
+
+fn world_index(world: ExecutionMode) -> usize {
+    match world {
+        ExecutionMode::Firmware => 0,
+        ExecutionMode::Payload => 1,
+    }
+}
+
+// ————————————————————————————————— Report ————————————————————————————————— //
+
+macro_rules! coverage_print {
+    ($($arg:tt)*) => {
+        Plat::debug_print(
+            log::Level::Info,
+            core::format_args!("{} {}\r\n", COVERAGE_PREFIX, core::format_args!($($arg)*)),
+        )
+    };
+}
+
+/// Prints the coverage counters, must be called before exiting.
+pub fn dump() {
+    if !config::COVERAGE {
+        return;
+    }
+
+    for (csr, count) in CSRS.iter().enumerate() {
+        let emulated = EMULATED_CSRS[csr / usize::BITS as usize].load(Ordering::Relaxed)
+            & (1 << (csr % usize::BITS as usize))
+            != 0;
+        let count = count.load(Ordering::Relaxed);
+        if emulated {
+            coverage_print!("csr {} 0x{:x}", count, csr);
+        } else if count > 0 {
+            coverage_print!("unknown-csr {} 0x{:x}", count, csr);
+        }
+    }
+
+    for (world, name) in [
+        (ExecutionMode::Firmware, "firmware"),
+        (ExecutionMode::Payload, "payload"),
+    ] {
+        for (idx, count) in TRAPS[world_index(world)].iter().enumerate() {
+            let mcause = if idx < NB_CAUSES {
+                idx
+            } else {
+                MCause::UserSoftInt as usize | (idx - NB_CAUSES)
+            };
+            let cause = MCause::new(mcause);
+            if matches!(cause, MCause::UnknownException | MCause::UnknownInt) {
+                continue;
+            }
+            coverage_print!(
+                "trap {} {}: {:?}",
+                count.load(Ordering::Relaxed),
+                name,
+                cause
+            );
+        }
+    }
+
+    let devices = DEVICES.lock();
+    for device in DeviceRegistry::from_platform().iter() {
+        let mut reached = false;
+        for access in devices.iter().flatten() {
+            if access.name == device.name {
+                reached = true;
+                coverage_print!(
+                    "device {} {} 0x{:x}",
+                    access.count,
+                    device.name,
+                    access.offset
+                );
+            }
+        }
+        if !reached {
+            coverage_print!("device 0 {}", device.name);
+        }
+    }
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_csr_numbers() {
+        // csrr a0, mstatus
+        assert_eq!(csr_number(0x30002573), Some(0x300));
+        // csrwi mie, 0
+        assert_eq!(csr_number(0x30405073), Some(0x304));
+        // wfi, mret, and ecall are not CSR instructions
+        assert_eq!(csr_number(0x10500073), None);
+        assert_eq!(csr_number(0x30200073), None);
+        assert_eq!(csr_number(0x00000073), None);
+    }
+
+    #[test]
+    fn trap_indices() {
+        assert_eq!(trap_index(MCause::IllegalInstr as usize), Some(2));
+        assert_eq!(
+            trap_index(MCause::MachineTimerInt as usize),
+            Some(NB_CAUSES + 7)
+        );
+        assert_eq!(trap_index(NB_CAUSES), None);
+    }
+}
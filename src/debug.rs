@@ -1,8 +1,17 @@
 //! Debug utils for Miralis
 
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use log::Level;
+use miralis_core::abi::trap_log::{self, TrapRecord};
+
 use crate::_stack_start;
-use crate::arch::{Arch, Architecture, Csr};
-use crate::config::TARGET_STACK_SIZE;
+use crate::arch::{Arch, Architecture, Csr, Mode, Register};
+use crate::config::{PLATFORM_NB_HARTS, TARGET_STACK_SIZE, TRAP_LOG_ENTRIES};
+use crate::virt::{RegisterContextSetter, VirtContext};
+
+const SBI_ERR_NOT_SUPPORTED: isize = -2;
 
 // ————————————————————————————— Logging Utils —————————————————————————————— //
 
@@ -63,12 +72,18 @@ unsafe fn get_max_stack_usage(stack_top: usize, stack_bottom: usize) -> usize {
 
 /// Display debug information related to maximal stack usage
 ///
+/// Does nothing if the `debug` feature is disabled.
+///
 /// # SAFETY:
 /// This function assumes a single-core system for now.
 pub unsafe fn log_stack_usage() {
     /// Percent usage threshold for emitting a warning.
     const WARNING_THRESHOLD: usize = 80;
 
+    if !cfg!(feature = "debug") {
+        return;
+    }
+
     // Get stack usage
     let stack_bottom = &raw const _stack_start as usize;
     let hart_id = Arch::read_csr(Csr::Mhartid);
@@ -100,3 +115,202 @@ pub unsafe fn log_stack_usage() {
         );
     }
 }
+
+// ———————————————————————————————— Trap Log ———————————————————————————————— //
+
+/// The trap log of each hart, which records the most recent traps handled by Miralis.
+///
+/// Unlike the trace buffer of the `trace` feature, which is read from a debugger or a memory dump,
+/// the trap log is dumped on the console (on panic or with `MIRALIS_TRAP_LOG_DUMP_FID`) and can
+/// therefore be collected on hardware. `runner trap-log` decodes the dump into a timeline.
+static TRAP_LOG: [TrapLog<TRAP_LOG_ENTRIES>; PLATFORM_NB_HARTS] =
+    [const { TrapLog::new() }; PLATFORM_NB_HARTS];
+
+const EMPTY_RECORD: TrapRecord = TrapRecord {
+    timestamp: 0,
+    mepc: 0,
+    info: 0,
+};
+
+/// The trap records of a single hart.
+struct TrapLog<const N: usize> {
+    /// Number of records ever written to the log.
+    head: AtomicUsize,
+    records: UnsafeCell<[TrapRecord; N]>,
+}
+
+// SAFETY: a log is only ever accessed by its own hart.
+unsafe impl<const N: usize> Sync for TrapLog<N> {}
+
+impl<const N: usize> TrapLog<N> {
+    const fn new() -> Self {
+        TrapLog {
+            head: AtomicUsize::new(0),
+            records: UnsafeCell::new([EMPTY_RECORD; N]),
+        }
+    }
+
+    /// Appends a record, overwriting the oldest one if the log is full.
+    ///
+    /// SAFETY: must only be called by the hart owning the log.
+    unsafe fn push(&self, record: TrapRecord) {
+        if N == 0 {
+            return;
+        }
+
+        let head = self.head.load(Ordering::Relaxed);
+        (*self.records.get())[head % N] = record;
+        self.head.store(head + 1, Ordering::Relaxed);
+    }
+
+    /// Returns the retained records, from the oldest to the most recent.
+    ///
+    /// SAFETY: must only be called by the hart owning the log.
+    unsafe fn records(&self) -> impl Iterator<Item = TrapRecord> + '_ {
+        let head = self.head.load(Ordering::Relaxed);
+        let records = &*self.records.get();
+        (head.saturating_sub(N)..head).map(move |seq| records[seq % N])
+    }
+}
+
+/// Returns the timestamp at which Miralis starts handling a trap, see [record_trap].
+#[inline]
+pub fn trap_log_timestamp() -> usize {
+    if TRAP_LOG_ENTRIES == 0 {
+        return 0;
+    }
+    Arch::read_csr(Csr::Mcycle)
+}
+
+/// Records a trap once handled, `from` is the virtual mode in which the guest trapped and `start`
+/// the value returned by [trap_log_timestamp] before handling the trap.
+#[inline]
+pub fn record_trap(ctx: &VirtContext, from: Mode, start: usize) {
+    if TRAP_LOG_ENTRIES == 0 {
+        return;
+    }
+    let Some(log) = TRAP_LOG.get(ctx.hart_id) else {
+        return;
+    };
+
+    let cycles = Arch::read_csr(Csr::Mcycle).wrapping_sub(start);
+    let record = TrapRecord {
+        timestamp: start as u64,
+        mepc: ctx.trap_info.mepc as u64,
+        info: pack_info(ctx.trap_info.mcause, from, ctx.mode, cycles),
+    };
+    // SAFETY: each hart only records traps in the log matching its own hart ID.
+    unsafe { log.push(record) };
+}
+
+/// Packs the `info` word of a trap record, see `miralis_core::abi::trap_log`.
+fn pack_info(mcause: usize, from: Mode, to: Mode, cycles: usize) -> u64 {
+    let interrupt = (mcause >> (usize::BITS - 1)) as u64;
+    let cycles = cycles.min(u32::MAX as usize) as u64;
+    (mcause as u64 & trap_log::CODE_FILTER)
+        | (interrupt << trap_log::INTERRUPT_OFFSET)
+        | ((from.to_bits() as u64) << trap_log::FROM_MODE_OFFSET)
+        | ((to.to_bits() as u64) << trap_log::TO_MODE_OFFSET)
+        | (cycles << trap_log::CYCLES_OFFSET)
+}
+
+/// Dumps the trap log of the current hart on the console, one record per line.
+///
+/// Does nothing if the trap log is disabled.
+pub fn dump_trap_log(level: Level) {
+    if TRAP_LOG_ENTRIES == 0 {
+        return;
+    }
+    let hart_id = Arch::read_csr(Csr::Mhartid);
+    let Some(log) = TRAP_LOG.get(hart_id) else {
+        return;
+    };
+
+    log::log!(level, "Trap log of hart {}:", hart_id);
+    // SAFETY: the log is read by the hart owning it.
+    for record in unsafe { log.records() } {
+        log::log!(
+            level,
+            "{} {} {:016x} {:016x} {:016x}",
+            trap_log::LINE_PREFIX,
+            hart_id,
+            record.timestamp,
+            record.mepc,
+            record.info
+        );
+    }
+}
+
+/// Handles the `MIRALIS_TRAP_LOG_DUMP_FID` call of the Miralis ABI.
+pub fn handle_trap_log_dump(ctx: &mut VirtContext) {
+    let error = if TRAP_LOG_ENTRIES == 0 {
+        SBI_ERR_NOT_SUPPORTED
+    } else {
+        dump_trap_log(Level::Info);
+        0
+    };
+
+    ctx.set(Register::X10, error as usize);
+    ctx.set(Register::X11, 0);
+    ctx.pc += 4;
+}
+
+// ——————————————————————————————— Debug Stub ——————————————————————————————— //
+
+/// Called when the firmware hits a breakpoint and the debug stub is enabled.
+///
+/// This function does nothing by itself: debuggers attached to Miralis break on this symbol
+/// (`runner debug` does so automatically when the stub is enabled) and can inspect the state of
+/// the firmware through `ctx`. The firmware resumes after the breakpoint once the debugger
+/// continues.
+#[no_mangle]
+#[inline(never)]
+pub fn miralis_firmware_breakpoint(ctx: &mut VirtContext) {
+    core::hint::black_box(ctx);
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trap_log_wraps_around() {
+        let log = TrapLog::<4>::new();
+        for timestamp in 0..6 {
+            let record = TrapRecord {
+                timestamp,
+                ..EMPTY_RECORD
+            };
+            unsafe { log.push(record) };
+        }
+
+        let timestamps: Vec<u64> = unsafe { log.records() }
+            .map(|record| record.timestamp)
+            .collect();
+        assert_eq!(timestamps, [2, 3, 4, 5]);
+        assert_eq!(unsafe { TrapLog::<0>::new().records() }.count(), 0);
+    }
+
+    #[test]
+    fn trap_record_info() {
+        // Supervisor timer interrupt from the payload's U-mode, resuming in the firmware
+        let info = pack_info((1 << (usize::BITS - 1)) | 5, Mode::U, Mode::M, 1234);
+        assert_eq!(info & trap_log::CODE_FILTER, 5);
+        assert_eq!((info >> trap_log::INTERRUPT_OFFSET) & 1, 1);
+        assert_eq!(
+            (info >> trap_log::FROM_MODE_OFFSET) & trap_log::MODE_FILTER,
+            0
+        );
+        assert_eq!(
+            (info >> trap_log::TO_MODE_OFFSET) & trap_log::MODE_FILTER,
+            3
+        );
+        assert_eq!(info >> trap_log::CYCLES_OFFSET, 1234);
+
+        // Handling cycles saturate
+        let info = pack_info(9, Mode::S, Mode::S, usize::MAX);
+        assert_eq!(info >> trap_log::CYCLES_OFFSET, u32::MAX as u64);
+    }
+}
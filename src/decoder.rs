@@ -4,6 +4,9 @@ use crate::host::MiralisContext;
 
 const OPCODE_MASK: usize = 0b1111111;
 
+/// Number of entries of the decode cache, must be a power of two.
+const DECODE_CACHE_SIZE: usize = 32;
+
 /// A RISC-V instruction.
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Instr {
@@ -48,6 +51,8 @@ pub enum Instr {
     },
     Mret,
     Sret,
+    /// Instruction fence, only traps on cores without the Zifencei extension
+    FenceI,
     /// Fence instructions
     Sfencevma {
         rs1: Register,
@@ -61,6 +66,21 @@ pub enum Instr {
         rs1: Register,
         rs2: Register,
     },
+    /// Hypervisor virtual-machine load (HLV and HLVX)
+    Hlv {
+        rd: Register,
+        rs1: Register,
+        len: Width,
+        is_unsigned: bool,
+        /// HLVX, the access requires execute permission instead of read permission
+        is_exec: bool,
+    },
+    /// Hypervisor virtual-machine store (HSV)
+    Hsv {
+        rs1: Register,
+        rs2: Register,
+        len: Width,
+    },
     /// Load (register-based)
     Load {
         rd: Register,
@@ -86,6 +106,7 @@ pub enum Instr {
 enum Opcode {
     Load,
     Store,
+    MiscMem,
     System,
     Compressed,
     Unknown,
@@ -101,6 +122,7 @@ impl MiralisContext {
             Opcode::System => self.decode_system(raw),
             Opcode::Load => self.decode_load(raw),
             Opcode::Store => self.decode_store(raw),
+            Opcode::MiscMem => self.decode_misc_mem(raw),
             Opcode::Compressed => self.decode_c_reg_based(raw),
             _ => Instr::Unknown,
         }
@@ -115,6 +137,7 @@ impl MiralisContext {
                 match opcode >> 2 {
                     0b00000 => Opcode::Load,
                     0b01000 => Opcode::Store,
+                    0b00011 => Opcode::MiscMem,
                     0b11100 => Opcode::System,
                     _ => Opcode::Unknown,
                 }
@@ -313,6 +336,14 @@ impl MiralisContext {
         }
     }
 
+    fn decode_misc_mem(&self, raw: usize) -> Instr {
+        let func3 = (raw >> 12) & 0b111;
+        match func3 {
+            0b001 => Instr::FenceI,
+            _ => Instr::Unknown,
+        }
+    }
+
     fn decode_system(&self, raw: usize) -> Instr {
         let rd = (raw >> 7) & 0b11111;
         let func3 = (raw >> 12) & 0b111;
@@ -348,6 +379,10 @@ impl MiralisContext {
             };
         }
 
+        if func3 == 0b100 {
+            return self.decode_hypervisor_load_store(raw);
+        }
+
         let csr = self.decode_csr(imm);
         let rd = Register::from(rd);
         match func3 {
@@ -373,7 +408,56 @@ impl MiralisContext {
         }
     }
 
-    fn decode_csr(&self, csr: usize) -> Csr {
+    /// Decodes the hypervisor virtual-machine loads and stores.
+    fn decode_hypervisor_load_store(&self, raw: usize) -> Instr {
+        let rd = Register::from((raw >> 7) & 0b11111);
+        let rs1 = Register::from((raw >> 15) & 0b11111);
+        let rs2 = (raw >> 20) & 0b11111;
+        let func7 = (raw >> 25) & 0b1111111;
+
+        if func7 >> 3 != 0b0110 {
+            return Instr::Unknown;
+        }
+        // Bits 1 and 2 encode the width, bit 0 distinguishes stores from loads
+        let len = Width::from(8 << ((func7 >> 1) & 0b11));
+        if func7 & 0b1 == 1 {
+            return match rd {
+                Register::X0 => Instr::Hsv {
+                    rs1,
+                    rs2: Register::from(rs2),
+                    len,
+                },
+                _ => Instr::Unknown,
+            };
+        }
+
+        match rs2 {
+            0b00000 => Instr::Hlv {
+                rd,
+                rs1,
+                len,
+                is_unsigned: false,
+                is_exec: false,
+            },
+            0b00001 if len != Width::Byte8 => Instr::Hlv {
+                rd,
+                rs1,
+                len,
+                is_unsigned: true,
+                is_exec: false,
+            },
+            0b00011 if len == Width::Byte2 || len == Width::Byte4 => Instr::Hlv {
+                rd,
+                rs1,
+                len,
+                is_unsigned: true,
+                is_exec: true,
+            },
+            _ => Instr::Unknown,
+        }
+    }
+
+    pub fn decode_csr(&self, csr: usize) -> Csr {
         match csr {
             0x300 => Csr::Mstatus,
             0x301 => Csr::Misa,
@@ -440,34 +524,10 @@ impl MiralisContext {
                     Csr::Mtval2
                 }
             }
-            0x7A0 => {
-                if true {
-                    Csr::Unknown
-                } else {
-                    Csr::Tselect
-                }
-            }
-            0x7A1 => {
-                if true {
-                    Csr::Unknown
-                } else {
-                    Csr::Tdata1
-                }
-            }
-            0x7A2 => {
-                if true {
-                    Csr::Unknown
-                } else {
-                    Csr::Tdata2
-                }
-            }
-            0x7A3 => {
-                if true {
-                    Csr::Unknown
-                } else {
-                    Csr::Tdata3
-                }
-            }
+            0x7A0 => Csr::Tselect,
+            0x7A1 => Csr::Tdata1,
+            0x7A2 => Csr::Tdata2,
+            0x7A3 => Csr::Tdata3,
             0x7A8 => {
                 if true {
                     Csr::Unknown
@@ -506,6 +566,11 @@ impl MiralisContext {
             0x342 => Csr::Mcause,
             0x341 => Csr::Mepc,
             0x343 => Csr::Mtval,
+            // Unprivileged counters
+            0xC00 => Csr::Cycle,
+            0xC01 => Csr::Time,
+            0xC02 => Csr::Instret,
+            0xC03..=0xC1F => Csr::Hpmcounter(csr - 0xC03),
             // Supervisor-level CSRs
             0x100 => {
                 if !self.hw.extensions.has_s_extension {
@@ -577,6 +642,13 @@ impl MiralisContext {
                     Csr::Sip
                 }
             }
+            0x14D => {
+                if !self.hw.extensions.has_sstc_extension {
+                    Csr::Unknown
+                } else {
+                    Csr::Stimecmp
+                }
+            }
             0x180 => {
                 if !self.hw.extensions.has_s_extension {
                     Csr::Unknown
@@ -761,6 +833,52 @@ impl MiralisContext {
             }
         }
     }
+
+    /// Decode the raw instruction at `pc`, reusing the previous decoding of the same instruction
+    /// at the same address if still in the decode cache.
+    pub fn decode_cached(&mut self, pc: usize, raw: usize) -> Instr {
+        let entry = DecodeCache::index(pc);
+        if let Some((entry_pc, entry_raw, instr)) = &self.decode_cache.entries[entry] {
+            if *entry_pc == pc && *entry_raw == raw {
+                return instr.clone();
+            }
+        }
+
+        let instr = self.decode(raw);
+        self.decode_cache.entries[entry] = Some((pc, raw, instr.clone()));
+        instr
+    }
+}
+
+// —————————————————————————————— Decode Cache —————————————————————————————— //
+
+/// A direct-mapped cache of decoded instructions, indexed by address.
+///
+/// The firmware often re-executes the same privileged instructions (e.g. when polling a CSR), the
+/// cache saves decoding them on each exit. Entries are tagged with both the address and the raw
+/// instruction, such that an instruction modified in place never hits a stale entry. The cache is
+/// nonetheless flushed on `fence.i`, after which the firmware might execute new code.
+pub struct DecodeCache {
+    /// The address, raw instruction, and decoded instruction of each entry.
+    entries: [Option<(usize, usize, Instr)>; DECODE_CACHE_SIZE],
+}
+
+impl DecodeCache {
+    pub const fn new() -> Self {
+        DecodeCache {
+            entries: [const { None }; DECODE_CACHE_SIZE],
+        }
+    }
+
+    /// Invalidates all entries.
+    pub fn flush(&mut self) {
+        self.entries = [const { None }; DECODE_CACHE_SIZE];
+    }
+
+    /// Instructions are at least 2 bytes aligned.
+    fn index(pc: usize) -> usize {
+        (pc >> 1) & (DECODE_CACHE_SIZE - 1)
+    }
 }
 
 // ————————————————————————————————— Tests —————————————————————————————————— //
@@ -782,6 +900,10 @@ mod tests {
         assert_eq!(mctx.decode(0x00100073), Instr::Ebreak);
         // MRET: Return from machine mode.
         assert_eq!(mctx.decode(0x30200073), Instr::Mret);
+        // FENCE.I: Instruction fence.
+        assert_eq!(mctx.decode(0x0000100f), Instr::FenceI);
+        // FENCE is not emulated.
+        assert_eq!(mctx.decode(0x0ff0000f), Instr::Unknown);
         // SRET: Return from supervisor mode.
         assert_eq!(mctx.decode(0x10200073), Instr::Sret);
         // WFI: Wait for interrupt.
@@ -803,6 +925,80 @@ mod tests {
         );
     }
 
+    #[test]
+    fn decode_cache() {
+        let mut mctx = MiralisContext::new(unsafe { Arch::detect_hardware() });
+        let csrr_mstatus = 0x30002573;
+        let csrr_mie = 0x30402573;
+        let mstatus = Instr::Csrrs {
+            csr: Csr::Mstatus,
+            rd: Register::X10,
+            rs1: Register::X0,
+        };
+
+        assert_eq!(mctx.decode_cached(0x8000_0000, csrr_mstatus), mstatus);
+        assert_eq!(mctx.decode_cached(0x8000_0000, csrr_mstatus), mstatus);
+        // A different instruction at the same address, or at an address mapping to the same entry,
+        // does not hit the cached instruction.
+        assert!(matches!(
+            mctx.decode_cached(0x8000_0000, csrr_mie),
+            Instr::Csrrs { csr: Csr::Mie, .. }
+        ));
+        let aliasing_pc = 0x8000_0000 + 2 * DECODE_CACHE_SIZE;
+        assert_eq!(mctx.decode_cached(aliasing_pc, csrr_mstatus), mstatus);
+
+        mctx.decode_cache.flush();
+        assert!(mctx.decode_cache.entries.iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn hypervisor_load_store_instructions() {
+        let mctx = MiralisContext::new(unsafe { Arch::detect_hardware() });
+
+        // HLV.W: Hypervisor load word.
+        assert_eq!(
+            mctx.decode(0x6805c573),
+            Instr::Hlv {
+                rd: Register::X10,
+                rs1: Register::X11,
+                len: Width::Byte4,
+                is_unsigned: false,
+                is_exec: false,
+            }
+        );
+        // HLVX.HU: Hypervisor load half-word with execute permission.
+        assert_eq!(
+            mctx.decode(0x6435c573),
+            Instr::Hlv {
+                rd: Register::X10,
+                rs1: Register::X11,
+                len: Width::Byte2,
+                is_unsigned: true,
+                is_exec: true,
+            }
+        );
+        // HSV.D: Hypervisor store double-word.
+        assert_eq!(
+            mctx.decode(0x6ea5c073),
+            Instr::Hsv {
+                rs1: Register::X11,
+                rs2: Register::X10,
+                len: Width::Byte8,
+            }
+        );
+        // HSV.B: Hypervisor store byte.
+        assert_eq!(
+            mctx.decode(0x62a5c073),
+            Instr::Hsv {
+                rs1: Register::X11,
+                rs2: Register::X10,
+                len: Width::Byte,
+            }
+        );
+        // There is no unsigned double-word load
+        assert_eq!(mctx.decode(0x6c15c573), Instr::Unknown);
+    }
+
     #[test]
     fn csr_instructions() {
         let mctx = MiralisContext::new(unsafe { Arch::detect_hardware() });
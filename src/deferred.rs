@@ -0,0 +1,115 @@
+//! Deferred Work
+//!
+//! Trap handlers run while the guest is stopped, and the time they take directly adds to the
+//! latency of the trap. Work that does not need to complete before the trap is emulated can instead
+//! be posted to the deferred work queue of the hart, which the main loop drains right before
+//! re-entering the guest. The work is therefore always done before the guest observes its effects,
+//! but after the trap handler returned and the pending interrupts have been injected.
+//!
+//! Work items are coalesced: posting an item that is already pending has no effect, such that the
+//! queue can never overflow.
+
+use crate::benchmark::{Benchmark, Scope};
+use crate::host::MiralisContext;
+use crate::policy::{Policy, PolicyModule};
+use crate::virt::VirtContext;
+
+/// Work that can be deferred until the next guest entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Work {
+    /// Runs the interrupt handler of the policy module, acknowledging a policy MSI broadcast by
+    /// another hart.
+    PolicyInterrupt,
+    /// Zeroes memory released by the policy module, see `PolicyModule::scrub_memory`.
+    ScrubMemory,
+}
+
+impl Work {
+    const COUNT: usize = 2;
+}
+
+/// A FIFO queue of deferred work, without duplicates.
+#[derive(Debug)]
+pub struct WorkQueue {
+    queue: [Option<Work>; Work::COUNT],
+    head: usize,
+    len: usize,
+}
+
+impl WorkQueue {
+    pub const fn new() -> Self {
+        WorkQueue {
+            queue: [None; Work::COUNT],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn is_pending(&self, work: Work) -> bool {
+        (0..self.len).any(|idx| self.queue[(self.head + idx) % Work::COUNT] == Some(work))
+    }
+
+    /// Appends work to the queue, unless it is already pending.
+    pub fn push(&mut self, work: Work) {
+        if self.is_pending(work) {
+            return;
+        }
+        // Items are unique, there is always room for one more
+        let tail = (self.head + self.len) % Work::COUNT;
+        self.queue[tail] = Some(work);
+        self.len += 1;
+    }
+
+    /// Removes the oldest work from the queue.
+    pub fn pop(&mut self) -> Option<Work> {
+        if self.is_empty() {
+            return None;
+        }
+        let work = self.queue[self.head].take();
+        self.head = (self.head + 1) % Work::COUNT;
+        self.len -= 1;
+        work
+    }
+}
+
+/// Does all the deferred work of the hart, in order.
+pub fn drain(ctx: &mut VirtContext, mctx: &mut MiralisContext, policy: &mut Policy) {
+    while let Some(work) = ctx.deferred_work.pop() {
+        log::trace!("Running deferred work: {:?}", work);
+        match work {
+            Work::PolicyInterrupt => {
+                Benchmark::in_scope(Scope::PolicyHook, || policy.on_interrupt(ctx, mctx))
+            }
+            Work::ScrubMemory => {
+                Benchmark::in_scope(Scope::PolicyHook, || policy.scrub_memory(ctx, mctx))
+            }
+        }
+    }
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn work_queue() {
+        let mut queue = WorkQueue::new();
+        assert_eq!(queue.pop(), None);
+
+        // Pending work is coalesced, and the order is preserved
+        queue.push(Work::ScrubMemory);
+        queue.push(Work::PolicyInterrupt);
+        queue.push(Work::ScrubMemory);
+        assert_eq!(queue.pop(), Some(Work::ScrubMemory));
+        queue.push(Work::ScrubMemory);
+        assert_eq!(queue.pop(), Some(Work::PolicyInterrupt));
+        assert_eq!(queue.pop(), Some(Work::ScrubMemory));
+        assert!(queue.is_empty());
+    }
+}
@@ -1,16 +1,17 @@
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use spin::Mutex;
 
 use crate::arch::mie;
 use crate::config::PLATFORM_NB_HARTS;
-use crate::debug;
-use crate::device::{DeviceAccess, Width};
+use crate::device::{DeviceAccess, Trigger, VirtIrqController, Width};
 use crate::driver::clint::{
     MSIP_OFFSET, MSIP_WIDTH, MTIMECMP_OFFSET, MTIMECMP_WIDTH, MTIME_OFFSET,
 };
-use crate::driver::ClintDriver;
-use crate::virt::VirtContext;
+use crate::driver::{ClintDriver, MsipRegisters};
+use crate::timebase::TIMEBASE;
+use crate::timer::{TimerEvent, TimerQueue};
+use crate::virt::{ExecutionMode, VirtContext};
 
 // ————————————————————————————— Virtual CLINT —————————————————————————————— //
 
@@ -19,12 +20,18 @@ pub const CLINT_SIZE: usize = 0x10000;
 /// Represents a virtual CLINT (Core Local Interruptor) device
 #[derive(Debug)]
 pub struct VirtClint {
-    /// A driver for the physical CLINT
+    /// A driver for the physical CLINT, used for mtime and mtimecmp
     driver: &'static Mutex<ClintDriver>,
+    /// The physical MSIP registers, accessed without taking the driver lock
+    msip: &'static MsipRegisters,
     /// Virtual Machine Software Interrupt (MSI) map
     vmsi: [AtomicBool; PLATFORM_NB_HARTS],
     /// Policy Machine Software Interrupt (MSI) map
     policy_msi: [AtomicBool; PLATFORM_NB_HARTS],
+    /// Virtual mtimecmp, as exposed to the firmware
+    vmtimecmp: [AtomicUsize; PLATFORM_NB_HARTS],
+    /// Pending timer events per hart
+    timers: [TimerQueue; PLATFORM_NB_HARTS],
 }
 
 impl DeviceAccess for VirtClint {
@@ -48,13 +55,36 @@ impl DeviceAccess for VirtClint {
     }
 }
 
+impl VirtIrqController for VirtClint {
+    /// The source is the ID of the hart to interrupt.
+    ///
+    /// Edge-triggered lines only raise the virtual MSI, which is then cleared by the firmware.
+    fn set_line(&self, hart: usize, trigger: Trigger, level: bool) {
+        if hart >= PLATFORM_NB_HARTS {
+            log::warn!("Invalid CLINT interrupt line: {}", hart);
+            return;
+        }
+
+        if level {
+            self.vmsi[hart].store(true, Ordering::SeqCst);
+            // The hart updates its virtual mip.MSIP when handling the physical MSI
+            self.msip.write(hart, 1).ok();
+        } else if trigger == Trigger::Level {
+            self.vmsi[hart].store(false, Ordering::SeqCst);
+        }
+    }
+}
+
 impl VirtClint {
     /// Creates a new virtual CLINT device backed by a physical CLINT.
-    pub const fn new(driver: &'static Mutex<ClintDriver>) -> Self {
+    pub const fn new(driver: &'static Mutex<ClintDriver>, msip: &'static MsipRegisters) -> Self {
         Self {
             driver,
+            msip,
             vmsi: [const { AtomicBool::new(false) }; PLATFORM_NB_HARTS],
             policy_msi: [const { AtomicBool::new(false) }; PLATFORM_NB_HARTS],
+            vmtimecmp: [const { AtomicUsize::new(usize::MAX) }; PLATFORM_NB_HARTS],
+            timers: [const { TimerQueue::new() }; PLATFORM_NB_HARTS],
         }
     }
 
@@ -70,18 +100,25 @@ impl VirtClint {
     pub fn read_clint(&self, offset: usize, r_width: Width) -> Result<usize, &'static str> {
         log::trace!("Read from CLINT at offset 0x{:x}", offset);
         self.validate_offset(offset)?;
-        let driver = self.driver.lock();
 
         match (offset, r_width) {
             (o, Width::Byte4) if (MSIP_OFFSET..MTIMECMP_OFFSET).contains(&o) => {
                 let hart = (o - MSIP_OFFSET) / MSIP_WIDTH.to_bytes();
-                driver.read_msip(hart)
+                self.msip.read(hart)
             }
             (o, Width::Byte8) if (MTIMECMP_OFFSET..MTIME_OFFSET).contains(&o) => {
+                // The physical mtimecmp might hold the deadline of another timer source, so we
+                // return the virtual one instead.
                 let hart = (o - MTIMECMP_OFFSET) / MTIMECMP_WIDTH.to_bytes();
-                driver.read_mtimecmp(hart)
+                if hart >= PLATFORM_NB_HARTS {
+                    return Err("Invalid hart when reading MTIMECMP");
+                }
+                Ok(self.vmtimecmp[hart].load(Ordering::SeqCst))
+            }
+            (o, Width::Byte8) if o == MTIME_OFFSET => {
+                let mtime = self.driver.lock().read_mtime();
+                Ok(TIMEBASE.view(ExecutionMode::Firmware, mtime))
             }
-            (o, Width::Byte8) if o == MTIME_OFFSET => Ok(driver.read_mtime()),
             _ => Err("Invalid CLINT offset"),
         }
     }
@@ -99,8 +136,8 @@ impl VirtClint {
             value
         );
         self.validate_offset(offset)?;
-        let mut driver = self.driver.lock();
 
+        // MSIP writes do not take the driver lock, see the documentation of `crate::driver`
         match (offset, w_width) {
             (o, Width::Byte4) if (MSIP_OFFSET..MTIMECMP_OFFSET).contains(&o) => {
                 let hart = (o - MSIP_OFFSET) / MSIP_WIDTH.to_bytes();
@@ -114,10 +151,11 @@ impl VirtClint {
                         if hart == ctx.hart_id {
                             // On the current hart clear mip.MSIE
                             ctx.csr.mip &= !mie::MSIE_FILTER;
+                            ctx.update_pending_interrupts();
                             Ok(())
                         } else {
                             // On remote hart send a physical MSI
-                            driver.write_msip(hart, 1)
+                            self.msip.write(hart, 1)
                         }
                     }
                     1 => {
@@ -126,50 +164,141 @@ impl VirtClint {
                         if hart == ctx.hart_id {
                             // On the current hart set mip.MSIE
                             ctx.csr.mip |= mie::MSIE_FILTER;
+                            ctx.update_pending_interrupts();
                             Ok(())
                         } else {
                             // On remote hart send a physical MSI
-                            driver.write_msip(hart, 1)
+                            self.msip.write(hart, 1)
                         }
                     }
                     _ => unreachable!(),
                 }
             }
             (o, Width::Byte8) if (MTIMECMP_OFFSET..MTIME_OFFSET).contains(&o) => {
-                let mtime = driver.read_mtime();
+                let mut driver = self.driver.lock();
+                let mtime = TIMEBASE.view(ExecutionMode::Firmware, driver.read_mtime());
                 let hart = (o - MTIMECMP_OFFSET) / MTIMECMP_WIDTH.to_bytes();
                 if hart >= PLATFORM_NB_HARTS {
-                    return Err("Invalid hart when writting MSIP");
+                    return Err("Invalid hart when writting MTIMECMP");
                 }
+                self.vmtimecmp[hart].store(value, Ordering::SeqCst);
+                // The firmware deadline is expressed in the firmware time
+                let deadline = TIMEBASE.to_physical(ExecutionMode::Firmware, value);
+
                 if hart != ctx.hart_id {
-                    todo!("Setting mtime for another hart is not yet supported");
+                    // The remote hart receives a physical timer interrupt once the deadline is
+                    // reached, which is then injected as a virtual one. A pending vMTIP might
+                    // however be stale, so we also send a physical MSI for the remote hart to
+                    // re-evaluate its vMTIP, see `firmware_timer_pending`.
+                    self.set_deadline_locked(&mut driver, hart, TimerEvent::Firmware, deadline)?;
+                    return self.msip.write(hart, 1);
                 }
 
                 // Update the virtual `mip` according to the relative ordering of mtime and
                 // mtimecmp.
                 if mtime >= value {
                     ctx.csr.mip |= mie::MTIE_FILTER;
+                    self.set_deadline_locked(&mut driver, hart, TimerEvent::Firmware, usize::MAX)?;
                 } else {
                     // Register a timer to trigger the virtual interrupt once appropriate
-                    driver.write_mtimecmp(hart, value)?;
+                    self.set_deadline_locked(&mut driver, hart, TimerEvent::Firmware, deadline)?;
                     ctx.csr.mip &= !mie::MTIE_FILTER;
                 }
+                ctx.update_pending_interrupts();
 
                 Ok(())
             }
             (o, Width::Byte8) if o == MTIME_OFFSET => {
-                // TODO: when updating mtime we should check on which core the timer should fire.
-                // We don't do it for now so we might loose interrupts.
-                debug::warn_once!(
-                    "Write to mtime not yet fully supported (might cause interrupt loss)"
-                );
-                driver.write_mtime(value);
+                // The physical mtime is shared with Miralis, so the write moves the time of the
+                // firmware and of the payload instead. The firmware deadlines are then
+                // re-computed, the payload deadline is re-armed when switching to the payload.
+                let mut driver = self.driver.lock();
+                TIMEBASE.set_time(driver.read_mtime(), value);
+                for hart in 0..PLATFORM_NB_HARTS {
+                    let deadline = TIMEBASE.to_physical(
+                        ExecutionMode::Firmware,
+                        self.vmtimecmp[hart].load(Ordering::SeqCst),
+                    );
+                    self.set_deadline_locked(&mut driver, hart, TimerEvent::Firmware, deadline)?;
+                }
                 Ok(())
             }
             _ => Err("Invalid CLINT address"),
         }
     }
 
+    /// Register a deadline for the given timer event, replacing the previous one.
+    ///
+    /// The deadline is expressed in physical time, a deadline of usize::MAX cancels the pending
+    /// deadline, if any.
+    pub fn set_deadline(
+        &self,
+        hart: usize,
+        event: TimerEvent,
+        deadline: usize,
+    ) -> Result<(), &'static str> {
+        let mut driver = self.driver.lock();
+        self.set_deadline_locked(&mut driver, hart, event, deadline)
+    }
+
+    /// Returns the pending deadline of the given timer event, usize::MAX if none.
+    pub fn deadline(&self, hart: usize, event: TimerEvent) -> usize {
+        assert!(
+            hart < PLATFORM_NB_HARTS,
+            "Invalid hart ID when reading a timer deadline"
+        );
+        self.timers[hart].get(event)
+    }
+
+    fn set_deadline_locked(
+        &self,
+        driver: &mut ClintDriver,
+        hart: usize,
+        event: TimerEvent,
+        deadline: usize,
+    ) -> Result<(), &'static str> {
+        if hart >= PLATFORM_NB_HARTS {
+            return Err("Invalid hart when setting a timer deadline");
+        }
+        self.timers[hart].set(event, deadline);
+        self.program_next_deadline(driver, hart)
+    }
+
+    /// Program the physical timer with the earliest deadline of the given hart.
+    ///
+    /// This is the only place writing the physical `mtimecmp`.
+    fn program_next_deadline(
+        &self,
+        driver: &mut ClintDriver,
+        hart: usize,
+    ) -> Result<(), &'static str> {
+        driver.write_mtimecmp(hart, self.timers[hart].next_deadline())
+    }
+
+    /// Handles a physical timer interrupt on the given hart.
+    ///
+    /// Returns the mask of [TimerEvent] whose deadline expired, those deadlines are cleared and
+    /// the physical timer is re-programmed for the next pending deadline, if any.
+    pub fn handle_timer_interrupt(&self, hart: usize) -> usize {
+        let mut driver = self.driver.lock();
+        let expired = self.timers[hart].expire(driver.read_mtime());
+
+        self.program_next_deadline(&mut driver, hart)
+            .expect("Failed to write mtimecmp");
+        expired
+    }
+
+    /// Return true if the virtual timer interrupt of the firmware is pending for the given hart,
+    /// that is if the firmware time reached the virtual mtimecmp.
+    pub fn firmware_timer_pending(&self, hart: usize) -> bool {
+        assert!(
+            hart < PLATFORM_NB_HARTS,
+            "Invalid hart ID when reading the virtual timer"
+        );
+        let mtime = TIMEBASE.view(ExecutionMode::Firmware, self.driver.lock().read_mtime());
+        mtime >= self.vmtimecmp[hart].load(Ordering::SeqCst)
+    }
+
     /// Return true if a vMSI is pending for the given hart
     pub fn get_vmsi(&self, hart: usize) -> bool {
         assert!(
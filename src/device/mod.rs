@@ -1,25 +1,53 @@
 //! Base device classes
 
+use core::fmt;
+
 use crate::arch::Width;
 use crate::virt::VirtContext;
 
 pub mod clint;
+pub mod plic;
+pub mod registry;
+pub mod status;
 pub mod tester;
+pub mod uart;
 
 // ———————————————————————————— Virtual Devices ————————————————————————————— //
 
 /// Represents a virtual memory-mapped device
+#[derive(Clone, Copy)]
 pub struct VirtDevice {
     pub start_addr: usize,
     pub size: usize,
     pub name: &'static str,
     pub device_interface: &'static dyn DeviceAccess,
+    pub payload_access: PayloadAccess,
+}
+
+/// How the payload accesses a virtual device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PayloadAccess {
+    /// The device is reserved to the firmware, accesses from the payload are forwarded to the
+    /// firmware as access faults.
+    Denied,
+    /// The payload accesses the physical device directly, the device is only protected while the
+    /// firmware runs.
+    Direct,
+    /// Loads from the payload are emulated, stores are forwarded to the firmware as access faults.
+    ReadOnly,
 }
 
-pub fn find_matching_device(address: usize, devices: &[VirtDevice]) -> Option<&VirtDevice> {
-    devices
-        .iter()
-        .find(|device| address >= device.start_addr && address < device.start_addr + device.size)
+/// The semantic of a write to a virtual device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WriteSemantic {
+    /// The write takes effect before the store instruction retires.
+    NonPosted,
+    /// The store instruction retires immediately, but the effects of the write are deferred until
+    /// right before re-entering the guest.
+    ///
+    /// This is useful for device models whose writes have delayed effects, such as FIFO pushes
+    /// or doorbells.
+    Posted,
 }
 
 pub trait DeviceAccess: Sync + Send {
@@ -36,4 +64,211 @@ pub trait DeviceAccess: Sync + Send {
         value: usize,
         ctx: &mut VirtContext,
     ) -> Result<(), &'static str>;
+
+    /// Returns the semantic of a write at the given offset.
+    ///
+    /// Writes are non-posted by default.
+    fn write_semantic(&self, _offset: usize) -> WriteSemantic {
+        WriteSemantic::NonPosted
+    }
+}
+
+// ———————————————————————————— Interrupt Lines ————————————————————————————— //
+
+/// How an interrupt line signals interrupts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trigger {
+    /// An interrupt is pending while the line is raised, including after the previous one has
+    /// been completed.
+    Level,
+    /// Each rising edge signals a single interrupt.
+    Edge,
+}
+
+/// A virtual interrupt controller, to which virtual devices are connected through an [IrqLine].
+///
+/// The controller routes the interrupt to the world owning the source, and notifies the target
+/// harts.
+pub trait VirtIrqController: Sync + Send {
+    /// Updates the level of an interrupt source, the meaning of source IDs is specific to the
+    /// controller.
+    fn set_line(&self, source: usize, trigger: Trigger, level: bool);
+}
+
+/// A virtual interrupt line, connecting a virtual device to a source of a virtual interrupt
+/// controller.
+#[derive(Clone, Copy)]
+pub struct IrqLine {
+    controller: &'static dyn VirtIrqController,
+    source: usize,
+    trigger: Trigger,
+}
+
+#[allow(dead_code)] // TODO: remove once used by a virtual device
+impl IrqLine {
+    pub const fn new(
+        controller: &'static dyn VirtIrqController,
+        source: usize,
+        trigger: Trigger,
+    ) -> Self {
+        IrqLine {
+            controller,
+            source,
+            trigger,
+        }
+    }
+
+    /// Raises the line, edge-triggered lines signal a single interrupt.
+    pub fn raise(&self) {
+        self.controller.set_line(self.source, self.trigger, true);
+    }
+
+    /// Lowers the line.
+    pub fn lower(&self) {
+        self.controller.set_line(self.source, self.trigger, false);
+    }
+}
+
+impl fmt::Debug for IrqLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IrqLine")
+            .field("source", &self.source)
+            .field("trigger", &self.trigger)
+            .finish()
+    }
+}
+
+// ———————————————————————————— Deferred Effects ———————————————————————————— //
+
+/// Maximum number of posted writes waiting to be applied.
+const MAX_DEFERRED_WRITES: usize = 8;
+
+/// A posted write, applied once the deferred effects are drained.
+#[derive(Clone, Copy)]
+pub struct DeferredWrite {
+    pub device: &'static dyn DeviceAccess,
+    pub name: &'static str,
+    pub offset: usize,
+    pub width: Width,
+    pub value: usize,
+    /// Address of the write, reported if the device rejects it.
+    pub addr: usize,
+    /// Address of the store instruction which posted the write.
+    pub pc: usize,
+}
+
+/// A FIFO queue of posted writes.
+///
+/// The writes are applied in order, before any other access to a virtual device and before
+/// re-entering the guest.
+pub struct DeferredEffects {
+    queue: [Option<DeferredWrite>; MAX_DEFERRED_WRITES],
+    head: usize,
+    len: usize,
+}
+
+impl DeferredEffects {
+    pub const fn new() -> Self {
+        DeferredEffects {
+            queue: [None; MAX_DEFERRED_WRITES],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == MAX_DEFERRED_WRITES
+    }
+
+    /// Append a write to the queue, returns the write back if the queue is full.
+    pub fn push(&mut self, write: DeferredWrite) -> Result<(), DeferredWrite> {
+        if self.is_full() {
+            return Err(write);
+        }
+        let tail = (self.head + self.len) % MAX_DEFERRED_WRITES;
+        self.queue[tail] = Some(write);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Remove the oldest write from the queue.
+    pub fn pop(&mut self) -> Option<DeferredWrite> {
+        if self.is_empty() {
+            return None;
+        }
+        let write = self.queue[self.head].take();
+        self.head = (self.head + 1) % MAX_DEFERRED_WRITES;
+        self.len -= 1;
+        write
+    }
+}
+
+impl fmt::Debug for DeferredWrite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeferredWrite")
+            .field("name", &self.name)
+            .field("offset", &self.offset)
+            .field("width", &self.width)
+            .field("value", &self.value)
+            .field("addr", &self.addr)
+            .field("pc", &self.pc)
+            .finish()
+    }
+}
+
+impl fmt::Debug for DeferredEffects {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeferredEffects")
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::{DeferredEffects, DeferredWrite, MAX_DEFERRED_WRITES};
+    use crate::arch::Width;
+    use crate::device::tester::VirtTestDevice;
+
+    static TEST_DEVICE: VirtTestDevice = VirtTestDevice::new();
+
+    fn write(value: usize) -> DeferredWrite {
+        DeferredWrite {
+            device: &TEST_DEVICE,
+            name: "TEST",
+            offset: 4,
+            width: Width::Byte4,
+            value,
+            addr: 0x1004,
+            pc: 0x8000_0000,
+        }
+    }
+
+    #[test]
+    fn deferred_effects_fifo() {
+        let mut queue = DeferredEffects::new();
+        assert!(queue.is_empty());
+        assert!(queue.pop().is_none());
+
+        // Fill the queue, going around the ring buffer
+        queue.push(write(0)).unwrap();
+        assert_eq!(queue.pop().unwrap().value, 0);
+        for i in 0..MAX_DEFERRED_WRITES {
+            queue.push(write(i)).unwrap();
+        }
+        assert!(queue.is_full());
+        assert!(queue.push(write(42)).is_err());
+
+        // Writes are applied in order
+        for i in 0..MAX_DEFERRED_WRITES {
+            assert_eq!(queue.pop().unwrap().value, i);
+        }
+        assert!(queue.is_empty());
+    }
 }
@@ -0,0 +1,508 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::arch::{mie, Mode};
+use crate::config::PLATFORM_NB_HARTS;
+use crate::device::{DeviceAccess, Trigger, VirtIrqController, Width};
+use crate::driver::plic::{
+    CLAIM_OFFSET, CONTEXT_OFFSET, CONTEXT_STRIDE, ENABLE_OFFSET, ENABLE_STRIDE, MAX_SOURCES,
+    PENDING_OFFSET, PRIORITY_OFFSET, THRESHOLD_OFFSET,
+};
+use crate::driver::{MsipRegisters, PlicDriver};
+use crate::virt::VirtContext;
+
+// —————————————————————————————— Virtual PLIC —————————————————————————————— //
+
+/// Size of the region protected by Miralis, enough for 512 contexts.
+pub const PLIC_SIZE: usize = 0x400000;
+
+/// Number of 32 bits words in a bitmap of interrupt sources.
+const SOURCE_WORDS: usize = MAX_SOURCES / 32;
+
+/// A PLIC context, which is the interrupt target of a hart in a given privilege mode.
+#[derive(Clone, Copy, Debug)]
+pub struct PlicContext {
+    pub hart: usize,
+    pub mode: Mode,
+}
+
+impl PlicContext {
+    pub const fn new(hart: usize, mode: Mode) -> Self {
+        PlicContext { hart, mode }
+    }
+}
+
+/// Returns the contexts of a PLIC with a M-mode and a S-mode context per hart, in this order.
+///
+/// This is the layout used by QEMU virt.
+pub const fn m_s_contexts<const N: usize>() -> [PlicContext; N] {
+    let mut contexts = [PlicContext::new(0, Mode::M); N];
+    let mut idx = 0;
+    while idx < N {
+        let mode = if idx % 2 == 0 { Mode::M } else { Mode::S };
+        contexts[idx] = PlicContext::new(idx / 2, mode);
+        idx += 1;
+    }
+    contexts
+}
+
+/// Returns the contexts of a PLIC whose first hart is a monitor core without S-mode, followed by a
+/// M-mode and a S-mode context per hart.
+///
+/// This is the layout of the SiFive SoCs, such as the FU540 emulated by QEMU sifive_u.
+pub const fn monitor_core_contexts<const N: usize>() -> [PlicContext; N] {
+    let mut contexts = [PlicContext::new(0, Mode::M); N];
+    let mut idx = 1;
+    while idx < N {
+        let mode = if idx % 2 == 1 { Mode::M } else { Mode::S };
+        contexts[idx] = PlicContext::new(idx.div_ceil(2), mode);
+        idx += 1;
+    }
+    contexts
+}
+
+/// The registers of the PLIC, all of them are 32 bits wide.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PlicRegister {
+    Priority(usize),
+    Pending(usize),
+    Enable { context: usize, word: usize },
+    Threshold(usize),
+    Claim(usize),
+}
+
+/// Represents a virtual PLIC (Platform-Level Interrupt Controller) device
+///
+/// The S-mode contexts are used by the payload, and are passed through to the physical PLIC. The
+/// M-mode contexts belong to Miralis and are emulated: the firmware configures virtual enable bits
+/// and thresholds while the physical M-mode contexts are left untouched. Priorities are shared
+/// with the physical PLIC.
+///
+/// The sources of the M-mode contexts are raised by virtual devices through an
+/// [IrqLine](crate::device::IrqLine), and delivered to the firmware as a virtual machine external
+/// interrupt.
+#[derive(Debug)]
+pub struct VirtPlic {
+    /// A driver for the physical PLIC
+    driver: PlicDriver,
+    /// The physical MSIP registers, used to notify the harts of new pending interrupts
+    msip: &'static MsipRegisters,
+    /// The contexts of the PLIC, indexed by context ID
+    contexts: &'static [PlicContext],
+    /// Number of interrupt sources, including the reserved source 0
+    nb_sources: usize,
+    /// Virtual enable bits of the M-mode context of each hart
+    m_enable: [[AtomicU32; SOURCE_WORDS]; PLATFORM_NB_HARTS],
+    /// Virtual threshold of the M-mode context of each hart
+    m_threshold: [AtomicU32; PLATFORM_NB_HARTS],
+    /// Sources pending for the M-mode contexts
+    m_pending: [AtomicU32; SOURCE_WORDS],
+    /// Sources claimed by a M-mode context and not yet completed
+    m_claimed: [AtomicU32; SOURCE_WORDS],
+    /// Level-triggered sources whose line is raised
+    m_level: [AtomicU32; SOURCE_WORDS],
+    /// Edge-triggered sources which fired while claimed, pending again once completed
+    m_latched: [AtomicU32; SOURCE_WORDS],
+}
+
+impl DeviceAccess for VirtPlic {
+    fn read_device(
+        &self,
+        offset: usize,
+        r_width: Width,
+        ctx: &mut VirtContext,
+    ) -> Result<usize, &'static str> {
+        let value = self.read_plic(offset, r_width)?;
+        self.update_meip(ctx)?;
+        Ok(value)
+    }
+
+    fn write_device(
+        &self,
+        offset: usize,
+        w_width: Width,
+        value: usize,
+        ctx: &mut VirtContext,
+    ) -> Result<(), &'static str> {
+        self.write_plic(offset, w_width, value)?;
+        self.update_meip(ctx)
+    }
+}
+
+impl VirtIrqController for VirtPlic {
+    fn set_line(&self, source: usize, trigger: Trigger, level: bool) {
+        if source == 0 || source >= self.nb_sources {
+            log::warn!("Invalid PLIC source: {}", source);
+            return;
+        }
+        let (word, bit) = (source / 32, 1 << (source % 32));
+
+        // As in the PLIC gateways, a source is not forwarded again until completed
+        match (trigger, level) {
+            (Trigger::Level, true) => {
+                self.m_level[word].fetch_or(bit, Ordering::SeqCst);
+                if self.m_claimed[word].load(Ordering::SeqCst) & bit == 0 {
+                    self.m_pending[word].fetch_or(bit, Ordering::SeqCst);
+                }
+            }
+            (Trigger::Level, false) => {
+                self.m_level[word].fetch_and(!bit, Ordering::SeqCst);
+                self.m_pending[word].fetch_and(!bit, Ordering::SeqCst);
+            }
+            (Trigger::Edge, true) => {
+                self.m_latched[word].fetch_or(bit, Ordering::SeqCst);
+                if self.m_claimed[word].load(Ordering::SeqCst) & bit == 0 {
+                    self.release_latched(word, bit);
+                }
+            }
+            (Trigger::Edge, false) => (),
+        }
+
+        // Notify the harts which can take the interrupt, they update their virtual mip.MEIP when
+        // handling the MSI.
+        for hart in 0..PLATFORM_NB_HARTS {
+            if self.has_pending_m_interrupt(hart) {
+                self.msip.write(hart, 1).ok();
+            }
+        }
+    }
+}
+
+impl VirtPlic {
+    /// Creates a new virtual PLIC device backed by a physical PLIC.
+    pub const fn new(
+        driver: PlicDriver,
+        msip: &'static MsipRegisters,
+        contexts: &'static [PlicContext],
+        nb_sources: usize,
+    ) -> Self {
+        assert!(nb_sources <= MAX_SOURCES, "Too many PLIC sources");
+
+        Self {
+            driver,
+            msip,
+            contexts,
+            nb_sources,
+            m_enable: [const { [const { AtomicU32::new(0) }; SOURCE_WORDS] }; PLATFORM_NB_HARTS],
+            m_threshold: [const { AtomicU32::new(0) }; PLATFORM_NB_HARTS],
+            m_pending: [const { AtomicU32::new(0) }; SOURCE_WORDS],
+            m_claimed: [const { AtomicU32::new(0) }; SOURCE_WORDS],
+            m_level: [const { AtomicU32::new(0) }; SOURCE_WORDS],
+            m_latched: [const { AtomicU32::new(0) }; SOURCE_WORDS],
+        }
+    }
+
+    /// Moves the physical PLIC to a new base address, see [PlicDriver::relocate].
+    ///
+    /// SAFETY: the new base address must correspond to a PLIC-compatible device.
+    pub unsafe fn relocate(&self, base: usize) {
+        unsafe { self.driver.relocate(base) };
+    }
+
+    /// Returns true if an interrupt can be claimed by the M-mode context of the given hart.
+    pub fn has_pending_m_interrupt(&self, hart: usize) -> bool {
+        self.next_m_source(hart).is_ok_and(|source| source != 0)
+    }
+
+    /// Updates the virtual mip.MEIP of the current hart.
+    fn update_meip(&self, ctx: &mut VirtContext) -> Result<(), &'static str> {
+        if self.next_m_source(ctx.hart_id)? != 0 {
+            ctx.csr.mip |= mie::MEIE_FILTER;
+        } else {
+            ctx.csr.mip &= !mie::MEIE_FILTER;
+        }
+        ctx.update_pending_interrupts();
+        Ok(())
+    }
+
+    /// Decodes the register at the given offset, only registers of existing sources and contexts
+    /// are valid.
+    fn decode(&self, offset: usize) -> Result<PlicRegister, &'static str> {
+        if offset % 4 != 0 {
+            log::warn!("Unaligned PLIC offset: 0x{:x}", offset);
+            return Err("Unaligned PLIC offset");
+        }
+        let nb_words = self.nb_sources.div_ceil(32);
+        let nb_contexts = self.contexts.len();
+
+        let register = match offset {
+            o if o < PENDING_OFFSET => PlicRegister::Priority((o - PRIORITY_OFFSET) / 4),
+            o if o < ENABLE_OFFSET => PlicRegister::Pending((o - PENDING_OFFSET) / 4),
+            o if o < CONTEXT_OFFSET => PlicRegister::Enable {
+                context: (o - ENABLE_OFFSET) / ENABLE_STRIDE,
+                word: ((o - ENABLE_OFFSET) % ENABLE_STRIDE) / 4,
+            },
+            o => match (o - CONTEXT_OFFSET) % CONTEXT_STRIDE {
+                THRESHOLD_OFFSET => PlicRegister::Threshold((o - CONTEXT_OFFSET) / CONTEXT_STRIDE),
+                CLAIM_OFFSET => PlicRegister::Claim((o - CONTEXT_OFFSET) / CONTEXT_STRIDE),
+                _ => return Err("Invalid PLIC context register"),
+            },
+        };
+
+        let is_valid = match register {
+            PlicRegister::Priority(source) => source < self.nb_sources,
+            PlicRegister::Pending(word) => word < nb_words,
+            PlicRegister::Enable { context, word } => context < nb_contexts && word < nb_words,
+            PlicRegister::Threshold(context) | PlicRegister::Claim(context) => {
+                context < nb_contexts
+            }
+        };
+        if !is_valid {
+            log::warn!("Invalid PLIC offset: 0x{:x}", offset);
+            return Err("Invalid PLIC offset");
+        }
+        Ok(register)
+    }
+
+    /// Returns the hart of the context if it is an emulated M-mode context.
+    fn m_mode_hart(&self, context: usize) -> Result<Option<usize>, &'static str> {
+        let context = self.contexts[context];
+        match context.mode {
+            Mode::M if context.hart < PLATFORM_NB_HARTS => Ok(Some(context.hart)),
+            Mode::M => Err("Invalid hart for PLIC context"),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn read_plic(&self, offset: usize, r_width: Width) -> Result<usize, &'static str> {
+        log::trace!("Read from PLIC at offset 0x{:x}", offset);
+        if r_width != Width::Byte4 {
+            return Err("Invalid PLIC access width");
+        }
+
+        let value = match self.decode(offset)? {
+            // Source 0 does not exist
+            PlicRegister::Priority(0) => 0,
+            PlicRegister::Priority(_) => self.driver.read(offset)?,
+            PlicRegister::Pending(word) => {
+                self.driver.read(offset)? | self.m_pending[word].load(Ordering::SeqCst)
+            }
+            PlicRegister::Enable { context, word } => match self.m_mode_hart(context)? {
+                Some(hart) => self.m_enable[hart][word].load(Ordering::SeqCst),
+                None => self.driver.read(offset)?,
+            },
+            PlicRegister::Threshold(context) => match self.m_mode_hart(context)? {
+                Some(hart) => self.m_threshold[hart].load(Ordering::SeqCst),
+                None => self.driver.read(offset)?,
+            },
+            PlicRegister::Claim(context) => match self.m_mode_hart(context)? {
+                Some(hart) => self.claim_m_mode(hart)?,
+                None => self.driver.read(offset)?,
+            },
+        };
+        Ok(value as usize)
+    }
+
+    pub fn write_plic(
+        &self,
+        offset: usize,
+        w_width: Width,
+        value: usize,
+    ) -> Result<(), &'static str> {
+        log::trace!(
+            "Write to PLIC at offset 0x{:x} with a value 0x{:x}",
+            offset,
+            value
+        );
+        if w_width != Width::Byte4 {
+            return Err("Invalid PLIC access width");
+        }
+        let value = value as u32;
+
+        match self.decode(offset)? {
+            // Source 0 does not exist
+            PlicRegister::Priority(0) => Ok(()),
+            PlicRegister::Priority(_) => self.driver.write(offset, value),
+            // Pending bits are read-only
+            PlicRegister::Pending(_) => Ok(()),
+            PlicRegister::Enable { context, word } => match self.m_mode_hart(context)? {
+                Some(hart) => {
+                    let value = if word == 0 { value & !0b1 } else { value };
+                    self.m_enable[hart][word].store(value, Ordering::SeqCst);
+                    Ok(())
+                }
+                None => self.driver.write(offset, value),
+            },
+            PlicRegister::Threshold(context) => match self.m_mode_hart(context)? {
+                Some(hart) => {
+                    self.m_threshold[hart].store(value, Ordering::SeqCst);
+                    Ok(())
+                }
+                None => self.driver.write(offset, value),
+            },
+            PlicRegister::Claim(context) => match self.m_mode_hart(context)? {
+                Some(hart) => {
+                    self.complete_m_mode(hart, value as usize);
+                    Ok(())
+                }
+                None => self.driver.write(offset, value),
+            },
+        }
+    }
+
+    /// Returns the highest priority source pending for the M-mode context of a hart, 0 if none.
+    ///
+    /// Ties are broken in favor of the lowest source ID, as in the physical PLIC.
+    fn next_m_source(&self, hart: usize) -> Result<usize, &'static str> {
+        let mut best_priority = self.m_threshold[hart].load(Ordering::SeqCst);
+        let mut best_source = 0;
+        for source in 1..self.nb_sources {
+            let (word, bit) = (source / 32, 1 << (source % 32));
+            let candidates = self.m_pending[word].load(Ordering::SeqCst)
+                & self.m_enable[hart][word].load(Ordering::SeqCst);
+            if candidates & bit == 0 {
+                continue;
+            }
+            let priority = self.driver.read(PRIORITY_OFFSET + source * 4)?;
+            if priority > best_priority {
+                best_priority = priority;
+                best_source = source;
+            }
+        }
+        Ok(best_source)
+    }
+
+    /// Claims the highest priority source pending for the M-mode context of a hart, 0 if none.
+    fn claim_m_mode(&self, hart: usize) -> Result<u32, &'static str> {
+        loop {
+            let source = self.next_m_source(hart)?;
+            if source == 0 {
+                return Ok(0);
+            }
+
+            // Another hart might have claimed the same source concurrently
+            let (word, bit) = (source / 32, 1 << (source % 32));
+            if self.m_pending[word].fetch_and(!bit, Ordering::SeqCst) & bit != 0 {
+                self.m_claimed[word].fetch_or(bit, Ordering::SeqCst);
+                return Ok(source as u32);
+            }
+        }
+    }
+
+    /// Completes a source claimed by the M-mode context of a hart.
+    ///
+    /// As in the physical PLIC, the completion is ignored if the source is not enabled for the
+    /// context.
+    fn complete_m_mode(&self, hart: usize, source: usize) {
+        if source == 0 || source >= self.nb_sources {
+            return;
+        }
+        let (word, bit) = (source / 32, 1 << (source % 32));
+        if self.m_enable[hart][word].load(Ordering::SeqCst) & bit == 0 {
+            return;
+        }
+        self.m_claimed[word].fetch_and(!bit, Ordering::SeqCst);
+
+        // The gateway forwards the next interrupt of the source, if any
+        if self.m_level[word].load(Ordering::SeqCst) & bit != 0 {
+            self.m_pending[word].fetch_or(bit, Ordering::SeqCst);
+        }
+        self.release_latched(word, bit);
+    }
+
+    /// Makes a latched edge-triggered interrupt pending, if any.
+    fn release_latched(&self, word: usize, bit: u32) {
+        if self.m_latched[word].fetch_and(!bit, Ordering::SeqCst) & bit != 0 {
+            self.m_pending[word].fetch_or(bit, Ordering::SeqCst);
+        }
+    }
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static CONTEXTS: [PlicContext; 2] = m_s_contexts();
+
+    // SAFETY: the tests only access the emulated M-mode context, never the physical PLIC.
+    static MSIP: MsipRegisters = unsafe { MsipRegisters::new(0) };
+
+    fn plic() -> VirtPlic {
+        // SAFETY: the tests only access the emulated M-mode context, never the physical PLIC.
+        VirtPlic::new(unsafe { PlicDriver::new(0) }, &MSIP, &CONTEXTS, 96)
+    }
+
+    #[test]
+    fn decode_registers() {
+        let plic = plic();
+        assert_eq!(plic.decode(0x4), Ok(PlicRegister::Priority(1)));
+        assert_eq!(plic.decode(0x1008), Ok(PlicRegister::Pending(2)));
+        assert_eq!(
+            plic.decode(0x2084),
+            Ok(PlicRegister::Enable {
+                context: 1,
+                word: 1
+            })
+        );
+        assert_eq!(plic.decode(0x201000), Ok(PlicRegister::Threshold(1)));
+        assert_eq!(plic.decode(0x200004), Ok(PlicRegister::Claim(0)));
+
+        // Sources and contexts that do not exist
+        assert!(plic.decode(0x180).is_err());
+        assert!(plic.decode(0x100c).is_err());
+        assert!(plic.decode(0x2100).is_err());
+        assert!(plic.decode(0x202000).is_err());
+        assert!(plic.decode(0x200008).is_err());
+        assert!(plic.decode(0x2).is_err());
+    }
+
+    #[test]
+    fn context_layouts() {
+        let contexts: [PlicContext; 5] = monitor_core_contexts();
+        let layout = contexts.map(|context| (context.hart, context.mode));
+        assert_eq!(
+            layout,
+            [
+                (0, Mode::M),
+                (1, Mode::M),
+                (1, Mode::S),
+                (2, Mode::M),
+                (2, Mode::S)
+            ]
+        );
+    }
+
+    #[test]
+    fn m_mode_context() {
+        assert_eq!(CONTEXTS[0].mode, Mode::M);
+        assert_eq!(CONTEXTS[1].mode, Mode::S);
+
+        let plic = plic();
+        // Source 0 can not be enabled
+        plic.write_plic(0x2000, Width::Byte4, 0xff).unwrap();
+        assert_eq!(plic.read_plic(0x2000, Width::Byte4), Ok(0xfe));
+        plic.write_plic(0x200000, Width::Byte4, 3).unwrap();
+        assert_eq!(plic.read_plic(0x200000, Width::Byte4), Ok(3));
+
+        // Nothing is pending
+        assert_eq!(plic.read_plic(0x200004, Width::Byte4), Ok(0));
+        assert!(plic.read_plic(0x200004, Width::Byte8).is_err());
+    }
+
+    #[test]
+    fn interrupt_lines() {
+        // Sources are not enabled, so that raising a line does not notify the harts
+        let plic = plic();
+        let pending = |plic: &VirtPlic| plic.m_pending[1].load(Ordering::SeqCst);
+
+        // Level-triggered lines are pending as long as they are raised
+        plic.set_line(33, Trigger::Level, true);
+        assert_eq!(pending(&plic), 0b10);
+        plic.set_line(33, Trigger::Level, false);
+        assert_eq!(pending(&plic), 0);
+
+        // Edges are latched while the source is claimed
+        plic.m_claimed[1].store(0b100, Ordering::SeqCst);
+        plic.set_line(34, Trigger::Edge, true);
+        assert_eq!(pending(&plic), 0);
+        plic.m_enable[0][1].store(0b100, Ordering::SeqCst);
+        plic.complete_m_mode(0, 34);
+        assert_eq!(pending(&plic), 0b100);
+        assert_eq!(plic.m_claimed[1].load(Ordering::SeqCst), 0);
+
+        // Source 0 does not exist
+        plic.set_line(0, Trigger::Level, true);
+        assert_eq!(plic.m_pending[0].load(Ordering::SeqCst), 0);
+    }
+}
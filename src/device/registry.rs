@@ -0,0 +1,119 @@
+//! Virtual Device Registry
+//!
+//! Platforms register their virtual devices together with the MMIO range they cover. Firmware
+//! accesses to those ranges fault and are dispatched to the device whose range contains the
+//! faulting address.
+
+use super::VirtDevice;
+use crate::platform::{Plat, Platform};
+
+/// Maximum number of virtual devices a platform can register.
+pub const MAX_VIRT_DEVICES: usize = 16;
+
+/// The virtual devices of the platform.
+pub struct DeviceRegistry {
+    devices: [Option<VirtDevice>; MAX_VIRT_DEVICES],
+    len: usize,
+}
+
+impl DeviceRegistry {
+    /// Creates an empty registry.
+    pub const fn new() -> Self {
+        DeviceRegistry {
+            devices: [None; MAX_VIRT_DEVICES],
+            len: 0,
+        }
+    }
+
+    /// Returns a registry holding the virtual devices of the platform.
+    pub fn from_platform() -> Self {
+        let mut registry = Self::new();
+        if let Err(err) = Plat::register_virtual_devices(&mut registry) {
+            panic!("Failed to register virtual devices: {}", err);
+        }
+        registry
+    }
+
+    /// Registers a device, its MMIO range must not overlap with the range of another device.
+    pub fn register(&mut self, device: VirtDevice) -> Result<(), &'static str> {
+        let Some(end) = device.start_addr.checked_add(device.size) else {
+            return Err("Device range overflows the address space");
+        };
+        if device.size == 0 {
+            return Err("Device range is empty");
+        }
+        if self.len == MAX_VIRT_DEVICES {
+            return Err("Too many virtual devices");
+        }
+        if self.iter().any(|other| {
+            device.start_addr < other.start_addr + other.size && other.start_addr < end
+        }) {
+            return Err("Device range overlaps with another device");
+        }
+
+        self.devices[self.len] = Some(device);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Returns the device whose MMIO range contains the address, if any.
+    pub fn find(&self, address: usize) -> Option<&VirtDevice> {
+        self.iter().find(|device| {
+            address >= device.start_addr && address < device.start_addr + device.size
+        })
+    }
+
+    /// Iterates over the devices, in registration order.
+    pub fn iter(&self) -> impl Iterator<Item = &VirtDevice> {
+        self.devices[..self.len].iter().flatten()
+    }
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::tester::VirtTestDevice;
+    use crate::device::PayloadAccess;
+
+    static TEST_DEVICE: VirtTestDevice = VirtTestDevice::new();
+
+    fn device(start_addr: usize, size: usize) -> VirtDevice {
+        VirtDevice {
+            start_addr,
+            size,
+            name: "TEST",
+            device_interface: &TEST_DEVICE,
+            payload_access: PayloadAccess::Denied,
+        }
+    }
+
+    #[test]
+    fn register_and_find() {
+        let mut registry = DeviceRegistry::new();
+        registry.register(device(0x1000, 0x100)).unwrap();
+        registry.register(device(0x2000, 0x1000)).unwrap();
+        assert_eq!(registry.iter().count(), 2);
+
+        assert_eq!(registry.find(0x1000).unwrap().start_addr, 0x1000);
+        assert_eq!(registry.find(0x10ff).unwrap().start_addr, 0x1000);
+        assert!(registry.find(0x1100).is_none());
+        assert_eq!(registry.find(0x2800).unwrap().start_addr, 0x2000);
+        assert!(registry.find(0x3000).is_none());
+
+        // Invalid or overlapping ranges are rejected
+        assert!(registry.register(device(0x10f0, 0x20)).is_err());
+        assert!(registry.register(device(0x1f00, 0x200)).is_err());
+        assert!(registry.register(device(0x4000, 0)).is_err());
+        assert!(registry.register(device(usize::MAX, 2)).is_err());
+        assert_eq!(registry.iter().count(), 2);
+
+        for idx in 2..MAX_VIRT_DEVICES {
+            registry
+                .register(device(0x10000 * (idx + 1), 0x10))
+                .unwrap();
+        }
+        assert!(registry.register(device(0x1000_0000, 0x10)).is_err());
+    }
+}
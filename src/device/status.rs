@@ -0,0 +1,191 @@
+//! Firmware Health Status Page
+//!
+//! Miralis publishes health information about the firmware in a read-only page of virtual device
+//! registers, so that the payload OS can monitor the firmware and raise alerts on misbehavior: the
+//! reason of the last reset, the number of firmware and payload exits per hart, and the number of
+//! operations blocked by Miralis or by the policy module.
+//!
+//! The layout of the page is defined in the `status` module of the Miralis ABI. Loads from the
+//! payload are emulated, which requires the payload to map the page at its physical address.
+//! Writes are ignored for the firmware and raise an access fault in the payload.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use miralis_core::abi::status as layout;
+
+use crate::config::PLATFORM_NB_HARTS;
+use crate::device::{DeviceAccess, Width};
+use crate::virt::{ExecutionMode, VirtContext};
+
+pub const STATUS_PAGE_SIZE: usize = layout::SIZE;
+
+const _: () = assert!(
+    layout::HART_OFFSET + layout::HART_STRIDE * PLATFORM_NB_HARTS <= STATUS_PAGE_SIZE,
+    "Too many harts for the status page"
+);
+
+// ———————————————————————————— Health Counters ————————————————————————————— //
+
+/// The reason of a reset, as reported in the status page.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResetReason {
+    ColdBoot = layout::RESET_COLD_BOOT as isize,
+    HartRestart = layout::RESET_HART_RESTART as isize,
+    SuspendResume = layout::RESET_SUSPEND_RESUME as isize,
+}
+
+/// The health counters, shared by all harts.
+static HEALTH: Health = Health::new();
+
+struct Health {
+    reset_reason: AtomicUsize,
+    reset_hart: AtomicUsize,
+    reset_count: AtomicUsize,
+    violations: AtomicUsize,
+    /// Number of exits per hart, indexed by [ExecutionMode].
+    exits: [[AtomicUsize; 2]; PLATFORM_NB_HARTS],
+}
+
+impl Health {
+    const fn new() -> Self {
+        Health {
+            reset_reason: AtomicUsize::new(ResetReason::ColdBoot as usize),
+            reset_hart: AtomicUsize::new(0),
+            reset_count: AtomicUsize::new(0),
+            violations: AtomicUsize::new(0),
+            exits: [const { [const { AtomicUsize::new(0) }; 2] }; PLATFORM_NB_HARTS],
+        }
+    }
+}
+
+/// Records a reset of the given hart.
+pub fn record_reset(reason: ResetReason, hart: usize) {
+    HEALTH
+        .reset_reason
+        .store(reason as usize, Ordering::Relaxed);
+    HEALTH.reset_hart.store(hart, Ordering::Relaxed);
+    HEALTH.reset_count.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records an exit from the given world.
+pub fn record_exit(hart: usize, world: ExecutionMode) {
+    HEALTH.exits[hart][world as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns the number of exits from the given world on a hart, or `None` if the hart does not exist.
+pub fn exits(hart: usize, world: ExecutionMode) -> Option<usize> {
+    HEALTH
+        .exits
+        .get(hart)
+        .map(|exits| exits[world as usize].load(Ordering::Relaxed))
+}
+
+/// Records an operation blocked by Miralis or by the policy module.
+pub fn record_violation() {
+    HEALTH.violations.fetch_add(1, Ordering::Relaxed);
+}
+
+// ————————————————————————————— Virtual Device ————————————————————————————— //
+
+/// The status page device, exposing the health counters.
+#[derive(Debug)]
+pub struct VirtStatusPage {}
+
+impl DeviceAccess for VirtStatusPage {
+    fn read_device(
+        &self,
+        offset: usize,
+        r_width: Width,
+        _ctx: &mut VirtContext,
+    ) -> Result<usize, &'static str> {
+        self.read(offset, r_width)
+    }
+
+    fn write_device(
+        &self,
+        offset: usize,
+        _w_width: Width,
+        _value: usize,
+        _ctx: &mut VirtContext,
+    ) -> Result<(), &'static str> {
+        log::debug!("Ignored write to the status page at offset 0x{:x}", offset);
+        Ok(())
+    }
+}
+
+impl VirtStatusPage {
+    pub const fn new() -> Self {
+        VirtStatusPage {}
+    }
+
+    /// Reads a full register, or one of its halves.
+    pub fn read(&self, offset: usize, width: Width) -> Result<usize, &'static str> {
+        let (shift, mask) = match width {
+            Width::Byte8 if offset % 8 == 0 => (0, u64::MAX),
+            Width::Byte4 if offset % 4 == 0 => ((offset % 8) * 8, u32::MAX as u64),
+            _ => return Err("Invalid status page access"),
+        };
+        Ok(((self.read_register(offset & !0b111) >> shift) & mask) as usize)
+    }
+
+    /// Reads the 64 bits register at the given offset.
+    fn read_register(&self, offset: usize) -> u64 {
+        let load = |counter: &AtomicUsize| counter.load(Ordering::Relaxed) as u64;
+        match offset {
+            layout::MAGIC_OFFSET => layout::MAGIC,
+            layout::VERSION_OFFSET => layout::VERSION,
+            layout::NB_HARTS_OFFSET => PLATFORM_NB_HARTS as u64,
+            layout::RESET_REASON_OFFSET => load(&HEALTH.reset_reason),
+            layout::RESET_HART_OFFSET => load(&HEALTH.reset_hart),
+            layout::RESET_COUNT_OFFSET => load(&HEALTH.reset_count),
+            layout::VIOLATIONS_OFFSET => load(&HEALTH.violations),
+            o if o >= layout::HART_OFFSET => {
+                let hart = (o - layout::HART_OFFSET) / layout::HART_STRIDE;
+                let world = (o - layout::HART_OFFSET) % layout::HART_STRIDE / 8;
+                match HEALTH.exits.get(hart) {
+                    Some(exits) => load(&exits[world]),
+                    None => 0,
+                }
+            }
+            // Reserved registers read as zero
+            _ => 0,
+        }
+    }
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_registers() {
+        let page = VirtStatusPage::new();
+        assert_eq!(
+            page.read(layout::MAGIC_OFFSET, Width::Byte8),
+            Ok(layout::MAGIC as usize)
+        );
+        // 4 bytes loads read each half of a register
+        assert_eq!(
+            page.read(layout::MAGIC_OFFSET, Width::Byte4),
+            Ok(u32::from_le_bytes(*b"MIRA") as usize)
+        );
+        assert_eq!(
+            page.read(layout::MAGIC_OFFSET + 4, Width::Byte4),
+            Ok(u32::from_le_bytes(*b"STAT") as usize)
+        );
+        assert!(page.read(0x4, Width::Byte8).is_err());
+        assert!(page.read(0x8, Width::Byte2).is_err());
+
+        // Exit counters are per hart and per world
+        let payload_exits = layout::HART_OFFSET + 8;
+        let before = page.read(payload_exits, Width::Byte8);
+        record_exit(0, ExecutionMode::Payload);
+        assert_eq!(
+            page.read(payload_exits, Width::Byte8),
+            before.map(|exits| exits + 1)
+        );
+        assert_eq!(page.read(0x800, Width::Byte8), Ok(0));
+    }
+}
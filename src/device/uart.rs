@@ -0,0 +1,211 @@
+//! Virtual UART
+//!
+//! The firmware and Miralis share the platform UART. Firmware accesses to the UART are trapped and
+//! forwarded to the physical device, except for the transmitted characters which are buffered per
+//! hart and printed one line at a time together with Miralis's own log output, such that lines
+//! from different harts and worlds are not interleaved.
+//!
+//! When `LOG_CONSOLE_TAGS` is enabled each line is prefixed by the world which produced it. The
+//! output of the firmware while serving an SBI call from the payload (e.g. the SBI console) is
+//! tagged as `[Payload]`, other firmware output as `[Firmware]`. The payload accesses the physical
+//! UART directly, its direct output is therefore not tagged.
+
+use core::fmt::{self, Write};
+
+use log::Level;
+use spin::Mutex;
+
+use crate::arch::{parse_mpp_return_mode, MCause, Mode};
+use crate::config::{LOG_CONSOLE_TAGS, PLATFORM_NB_HARTS};
+use crate::device::{DeviceAccess, Width, WriteSemantic};
+use crate::driver::{uart, UartDriver};
+use crate::platform::{Plat, Platform};
+use crate::virt::{ExecutionMode, VirtContext};
+
+/// Maximum length of a line, longer lines are split.
+const LINE_SIZE: usize = 128;
+
+// —————————————————————————————— Line Buffers —————————————————————————————— //
+
+/// The characters of the current line.
+#[derive(Debug)]
+struct LineBuffer {
+    bytes: [u8; LINE_SIZE],
+    len: usize,
+}
+
+impl LineBuffer {
+    const fn new() -> Self {
+        LineBuffer {
+            bytes: [0; LINE_SIZE],
+            len: 0,
+        }
+    }
+
+    /// Appends a character, returns true if the line is complete and must be printed.
+    fn push(&mut self, byte: u8) -> bool {
+        match byte {
+            b'\n' => true,
+            // Lines are terminated when printed
+            b'\r' => false,
+            _ => {
+                self.bytes[self.len] = byte;
+                self.len += 1;
+                self.len == LINE_SIZE
+            }
+        }
+    }
+
+    /// Returns the current line and starts a new one.
+    fn take(&mut self) -> Line<'_> {
+        let len = self.len;
+        self.len = 0;
+        Line(&self.bytes[..len])
+    }
+}
+
+/// A line of console output, non-ASCII characters are replaced.
+struct Line<'a>(&'a [u8]);
+
+impl fmt::Display for Line<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for &byte in self.0 {
+            if byte.is_ascii() {
+                f.write_char(byte as char)?;
+            } else {
+                f.write_char(char::REPLACEMENT_CHARACTER)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// —————————————————————————————— Virtual UART —————————————————————————————— //
+
+/// A virtual 16550-compatible UART.
+#[derive(Debug)]
+pub struct VirtUart {
+    /// A driver for the physical UART
+    driver: UartDriver,
+    /// Pending line for each hart, indexed by the world on behalf of which the firmware runs
+    lines: [[Mutex<LineBuffer>; 2]; PLATFORM_NB_HARTS],
+}
+
+impl DeviceAccess for VirtUart {
+    fn read_device(
+        &self,
+        offset: usize,
+        r_width: Width,
+        _ctx: &mut VirtContext,
+    ) -> Result<usize, &'static str> {
+        let value = self.driver.read(offset, r_width)?;
+        if self.driver.register(offset) == uart::LSR {
+            // Characters are buffered, the transmitter is always ready
+            Ok(value | uart::LSR_THRE | uart::LSR_TEMT)
+        } else {
+            Ok(value)
+        }
+    }
+
+    fn write_device(
+        &self,
+        offset: usize,
+        w_width: Width,
+        value: usize,
+        ctx: &mut VirtContext,
+    ) -> Result<(), &'static str> {
+        if self.driver.register(offset) != uart::THR
+            || self.driver.read_register(uart::LCR)? & uart::LCR_DLAB != 0
+        {
+            return self.driver.write(offset, w_width, value);
+        }
+
+        let world = Self::output_world(ctx);
+        let mut line = self.lines[ctx.hart_id][world as usize].lock();
+        if line.push(value as u8) {
+            let line = line.take();
+            match (LOG_CONSOLE_TAGS, world) {
+                (false, _) => Plat::debug_print(Level::Info, format_args!("{}\n", line)),
+                (true, ExecutionMode::Firmware) => {
+                    Plat::debug_print(Level::Info, format_args!("[Firmware] {}\n", line))
+                }
+                (true, ExecutionMode::Payload) => {
+                    Plat::debug_print(Level::Info, format_args!("[Payload] {}\n", line))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Transmitted characters and interrupt enables are posted: a character followed by an update
+    /// of IER (e.g. enabling the transmitter empty interrupt) takes effect as a whole before the
+    /// firmware resumes, as the hardware raises the interrupt once the character left the FIFO.
+    ///
+    /// With LCR.DLAB set the same offsets hold the divisor latch, writes to LCR are non-posted and
+    /// therefore apply the posted writes first, such that the order of the accesses is preserved.
+    fn write_semantic(&self, offset: usize) -> WriteSemantic {
+        match self.driver.register(offset) {
+            uart::THR | uart::IER => WriteSemantic::Posted,
+            _ => WriteSemantic::NonPosted,
+        }
+    }
+}
+
+impl VirtUart {
+    pub const fn new(driver: UartDriver) -> Self {
+        Self {
+            driver,
+            lines: [const { [const { Mutex::new(LineBuffer::new()) }; 2] }; PLATFORM_NB_HARTS],
+        }
+    }
+
+    /// Moves the physical UART to a new base address, see [UartDriver::relocate].
+    ///
+    /// SAFETY: the new base address must correspond to a UART with the same register layout.
+    pub unsafe fn relocate(&self, base: usize) {
+        unsafe { self.driver.relocate(base) };
+    }
+
+    /// Returns the world on behalf of which the firmware is running.
+    ///
+    /// The firmware serves an SBI call from the payload if its last trap is an ecall coming from
+    /// the payload.
+    fn output_world(ctx: &VirtContext) -> ExecutionMode {
+        let from_payload = parse_mpp_return_mode(ctx.csr.mstatus) != Mode::M;
+        let is_ecall = ctx.csr.mcause == MCause::EcallFromSMode as usize
+            || ctx.csr.mcause == MCause::EcallFromUMode as usize;
+        if from_payload && is_ecall {
+            ExecutionMode::Payload
+        } else {
+            ExecutionMode::Firmware
+        }
+    }
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_buffer() {
+        let mut line = LineBuffer::new();
+        for &byte in b"Hello\r" {
+            assert!(!line.push(byte));
+        }
+        assert!(line.push(b'\n'));
+        assert_eq!(line.take().to_string(), "Hello");
+        assert_eq!(line.len, 0);
+
+        // Long lines are split
+        for _ in 1..LINE_SIZE {
+            assert!(!line.push(b'a'));
+        }
+        assert!(line.push(b'a'));
+        assert_eq!(line.take().to_string().len(), LINE_SIZE);
+
+        line.push(0xff);
+        assert_eq!(line.take().to_string(), "\u{fffd}");
+    }
+}
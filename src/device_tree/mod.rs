@@ -0,0 +1,473 @@
+//! Device Tree
+//!
+//! The previous boot stage passes a flattened device tree (FDT) describing the board. Miralis
+//! reads the addresses of the devices it drives from it, so that the same binary boots on boards
+//! with different memory maps, and falls back to the defaults of the platform for the devices it
+//! can not find.
+//!
+//! The device tree passed to the firmware can also be [patched](patch) to hide the resources used
+//! by Miralis and the policy.
+
+pub mod patch;
+
+use config_helpers::parse_usize;
+use fdt_rs::prelude::{FallibleIterator, PropReader};
+use flattened_device_tree::error::FdtError;
+use flattened_device_tree::FlattenedDeviceTree;
+use patch::DtbPatch;
+use spin::Once;
+
+use crate::config;
+use crate::platform::{Plat, Platform};
+use crate::policy::{Policy, PolicyModule};
+
+/// Compatible strings of the CLINT.
+const CLINT_COMPATIBLE: &[&str] = &["riscv,clint0", "sifive,clint0"];
+/// Compatible strings of the PLIC.
+const PLIC_COMPATIBLE: &[&str] = &["riscv,plic0", "sifive,plic-1.0.0"];
+/// Compatible strings of the 16550-compatible UARTs.
+const UART_COMPATIBLE: &[&str] = &["ns16550a", "ns16550", "snps,dw-apb-uart"];
+
+/// The device layout discovered from the device tree passed at boot.
+static DEVICE_LAYOUT: Once<Result<DeviceLayout, FdtError>> = Once::new();
+
+/// The layout used when no device tree could be parsed.
+static EMPTY_LAYOUT: DeviceLayout = DeviceLayout::empty();
+
+/// The address of the device tree passed to the firmware.
+static FIRMWARE_DEVICE_TREE: Once<usize> = Once::new();
+
+/// Space available for the patched device tree, in addition to the size of the original one.
+const PATCH_EXTRA_SIZE: usize = 0x1000;
+
+/// Maximum length of the kernel command line set by the patch.
+const MAX_BOOTARGS_LEN: usize = 256;
+
+// ———————————————————————————— Device Discovery ———————————————————————————— //
+
+/// A physical memory region, as described by a `reg` property.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MmioRegion {
+    pub base: usize,
+    pub size: usize,
+}
+
+/// The regions of the devices found in the device tree, `None` if a device is not present.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceLayout {
+    pub clint: Option<MmioRegion>,
+    pub plic: Option<MmioRegion>,
+    pub uart: Option<MmioRegion>,
+    pub memory: Option<MmioRegion>,
+    /// The `clock-frequency` of the UART, its input clock in Hz.
+    pub uart_clock_frequency: Option<usize>,
+    /// The `current-speed` of the UART, the baud rate configured by the previous boot stage.
+    pub uart_baud_rate: Option<usize>,
+}
+
+impl DeviceLayout {
+    const fn empty() -> Self {
+        DeviceLayout {
+            clint: None,
+            plic: None,
+            uart: None,
+            memory: None,
+            uart_clock_frequency: None,
+            uart_baud_rate: None,
+        }
+    }
+}
+
+/// Discovers the devices from the device tree, must be called once during boot before the
+/// platform is initialized.
+///
+/// The logger is not initialized yet at that point, the outcome is reported by [log_layout].
+pub fn init(device_tree_blob_addr: usize) {
+    if device_tree_blob_addr == 0 {
+        return;
+    }
+
+    // SAFETY: the previous boot stage passes a valid device tree in a1 when the address is not
+    // null, as mandated by the RISC-V boot conventions.
+    DEVICE_LAYOUT.call_once(|| unsafe { discover_devices(device_tree_blob_addr) });
+}
+
+/// Returns the discovered device layout, which is empty if discovery failed or did not run.
+pub fn layout() -> &'static DeviceLayout {
+    match DEVICE_LAYOUT.get() {
+        Some(Ok(layout)) => layout,
+        _ => &EMPTY_LAYOUT,
+    }
+}
+
+/// Logs the discovered device layout.
+pub fn log_layout() {
+    match DEVICE_LAYOUT.get() {
+        None => {
+            log::info!("No device tree, using platform defaults");
+            return;
+        }
+        Some(Err(err)) => {
+            log::warn!("Device discovery failed, using platform defaults: {}", err);
+            return;
+        }
+        Some(Ok(_)) => {}
+    }
+
+    let layout = layout();
+    let devices = [
+        ("CLINT", layout.clint),
+        ("PLIC", layout.plic),
+        ("UART", layout.uart),
+        ("Memory", layout.memory),
+    ];
+    for (name, region) in devices {
+        match region {
+            Some(region) => log::info!(
+                "{:<6} discovered at 0x{:x} (size 0x{:x})",
+                name,
+                region.base,
+                region.size
+            ),
+            None => log::info!("{:<6} not found in device tree", name),
+        }
+    }
+}
+
+/// Walks the device tree and records the first CLINT, PLIC, UART and memory nodes.
+///
+/// SAFETY: the address must point to a valid flattened device tree.
+unsafe fn discover_devices(device_tree_blob_addr: usize) -> Result<DeviceLayout, FdtError> {
+    let fdt = unsafe { FlattenedDeviceTree::from_raw_pointer(device_tree_blob_addr as *const u8)? };
+    let mut layout = DeviceLayout::empty();
+
+    let mut nodes = fdt.inner.nodes();
+    while let Some(node) = nodes.next()? {
+        let mut compatible = None;
+        let mut is_memory = false;
+        let mut region = None;
+        let mut clock_frequency = None;
+        let mut current_speed = None;
+
+        let mut props = node.props();
+        while let Some(prop) = props.next()? {
+            match prop.name()? {
+                "compatible" => {
+                    let mut strings = prop.iter_str();
+                    while let Some(string) = strings.next()? {
+                        compatible = compatible.or(match_compatible(string));
+                    }
+                }
+                "device_type" => is_memory = prop.str()? == "memory",
+                "reg" => region = parse_reg(prop.propbuf()),
+                "clock-frequency" => clock_frequency = parse_cell(prop.propbuf()),
+                "current-speed" => current_speed = parse_cell(prop.propbuf()),
+                _ => {}
+            }
+        }
+
+        if compatible == Some(Device::Uart) && layout.uart.is_none() && region.is_some() {
+            layout.uart_clock_frequency = clock_frequency;
+            layout.uart_baud_rate = current_speed;
+        }
+
+        let slot = match (compatible, is_memory) {
+            (_, true) => &mut layout.memory,
+            (Some(Device::Clint), _) => &mut layout.clint,
+            (Some(Device::Plic), _) => &mut layout.plic,
+            (Some(Device::Uart), _) => &mut layout.uart,
+            (None, false) => continue,
+        };
+        if slot.is_none() {
+            *slot = region;
+        }
+    }
+
+    Ok(layout)
+}
+
+/// The devices looked for in the device tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Device {
+    Clint,
+    Plic,
+    Uart,
+}
+
+/// Returns the device matching a compatible string, if any.
+fn match_compatible(compatible: &str) -> Option<Device> {
+    if CLINT_COMPATIBLE.contains(&compatible) {
+        Some(Device::Clint)
+    } else if PLIC_COMPATIBLE.contains(&compatible) {
+        Some(Device::Plic)
+    } else if UART_COMPATIBLE.contains(&compatible) {
+        Some(Device::Uart)
+    } else {
+        None
+    }
+}
+
+/// Writes the regions of the nodes compatible with the given string to `regions`, in device tree
+/// order, and returns their number. Nodes beyond the capacity of `regions` are ignored.
+///
+/// SAFETY: the address must point to a valid flattened device tree.
+pub unsafe fn find_compatible_regions(
+    device_tree_blob_addr: usize,
+    compatible: &str,
+    regions: &mut [MmioRegion],
+) -> Result<usize, FdtError> {
+    let fdt = unsafe { FlattenedDeviceTree::from_raw_pointer(device_tree_blob_addr as *const u8)? };
+
+    let mut count = 0;
+    let mut nodes = fdt.inner.compatible_nodes(compatible);
+    while let Some(node) = nodes.next()? {
+        if count == regions.len() {
+            break;
+        }
+        let reg = node.props().find(|prop| Ok(prop.name()? == "reg"))?;
+        if let Some(region) = reg.and_then(|reg| parse_reg(reg.propbuf())) {
+            regions[count] = region;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Copies the property `name` of the first node compatible with `compatible` into `value`.
+///
+/// Returns the size of the property, which is larger than `value` if the property was truncated,
+/// or `None` if the property is not present.
+///
+/// # Safety
+///
+/// `device_tree_blob_addr` must point to a valid device tree.
+pub unsafe fn find_compatible_property(
+    device_tree_blob_addr: usize,
+    compatible: &str,
+    name: &str,
+    value: &mut [u8],
+) -> Result<Option<usize>, FdtError> {
+    let fdt = unsafe { FlattenedDeviceTree::from_raw_pointer(device_tree_blob_addr as *const u8)? };
+
+    let Some(node) = fdt.inner.compatible_nodes(compatible).next()? else {
+        return Ok(None);
+    };
+    let Some(property) = node.props().find(|prop| Ok(prop.name()? == name))? else {
+        return Ok(None);
+    };
+    let property = property.propbuf();
+    let size = property.len().min(value.len());
+    value[..size].copy_from_slice(&property[..size]);
+    Ok(Some(property.len()))
+}
+
+/// Parses a property holding a single 32 bits cell.
+fn parse_cell(property: &[u8]) -> Option<usize> {
+    Some(u32::from_be_bytes(property.try_into().ok()?) as usize)
+}
+
+/// Parses the first entry of a `reg` property.
+///
+/// Addresses and sizes are assumed to be two cells wide, as is the case on 64 bits RISC-V
+/// platforms.
+fn parse_reg(reg: &[u8]) -> Option<MmioRegion> {
+    let base = u64::from_be_bytes(reg.get(0..8)?.try_into().ok()?);
+    let size = u64::from_be_bytes(reg.get(8..16)?.try_into().ok()?);
+    Some(MmioRegion {
+        base: base as usize,
+        size: size as usize,
+    })
+}
+
+// —————————————————————————— Firmware Device Tree —————————————————————————— //
+
+/// Returns the address of the device tree passed to the firmware.
+///
+/// If a patch address is configured, the device tree is patched by the first hart calling this
+/// function, and all harts pass the patched device tree. The original device tree is passed if
+/// patching fails.
+pub fn firmware_device_tree(device_tree_blob_addr: usize, policy: &Policy) -> usize {
+    let Some(patch_addr) = config::PLATFORM_DTB_PATCH_ADDRESS else {
+        return device_tree_blob_addr;
+    };
+    if device_tree_blob_addr == 0 {
+        return device_tree_blob_addr;
+    }
+
+    // SAFETY: the previous boot stage passes a valid device tree, and the configuration
+    // guarantees the memory at the patch address is free.
+    *FIRMWARE_DEVICE_TREE.call_once(|| unsafe {
+        match patch_device_tree(device_tree_blob_addr, patch_addr, policy) {
+            Ok(size) => {
+                log::info!("Patched device tree at 0x{:x} ({} bytes)", patch_addr, size);
+                patch_addr
+            }
+            Err(err) => {
+                log::warn!(
+                    "Failed to patch the device tree, passing it unmodified: {}",
+                    err
+                );
+                device_tree_blob_addr
+            }
+        }
+    })
+}
+
+/// Writes a patched copy of the device tree, returns its size.
+///
+/// SAFETY: the source address must point to a valid device tree, and the memory at the
+/// destination address must be free.
+unsafe fn patch_device_tree(
+    src_addr: usize,
+    dst_addr: usize,
+    policy: &Policy,
+) -> Result<usize, &'static str> {
+    let (miralis_start, miralis_size) = Plat::get_miralis_memory_start_and_size();
+    let mut patch = DtbPatch::new();
+    patch.reserve_memory(MmioRegion {
+        base: miralis_start,
+        size: miralis_size,
+    })?;
+    if let Some((base, size)) = policy.reserved_memory() {
+        patch.remove_memory(MmioRegion { base, size })?;
+    }
+    for device in config::PLATFORM_DTB_HIDDEN_DEVICES {
+        let address = parse_usize(Some(*device)).ok_or("Invalid hidden device address")?;
+        patch.hide_device(address)?;
+    }
+
+    // Property strings are null-terminated
+    let mut bootargs = [0; MAX_BOOTARGS_LEN + 1];
+    if let Some(args) = config::PLATFORM_DTB_BOOTARGS {
+        let args = args.as_bytes();
+        if args.len() > MAX_BOOTARGS_LEN {
+            return Err("Kernel command line is too long");
+        }
+        bootargs[..args.len()].copy_from_slice(args);
+        patch.chosen_property("bootargs", &bootargs[..args.len() + 1])?;
+    }
+
+    let src_size = unsafe { FlattenedDeviceTree::total_size(src_addr as *const u8) }
+        .map_err(|_| "Invalid device tree address")?;
+    let dst_size = src_size + PATCH_EXTRA_SIZE;
+    if dst_addr < src_addr + src_size && src_addr < dst_addr + dst_size {
+        return Err("Patched device tree overlaps with the original one");
+    }
+
+    // SAFETY: the source covers the original device tree, and the destination is free memory
+    // that does not overlap with it.
+    let src = unsafe { core::slice::from_raw_parts(src_addr as *const u8, src_size) };
+    let dst = unsafe { core::slice::from_raw_parts_mut(dst_addr as *mut u8, dst_size) };
+    patch.apply(src, dst)
+}
+
+// ———————————————————————————— Memory Division ————————————————————————————— //
+
+fn read_unaligned_u64(ptr: *const u8) -> u64 {
+    // Step 1: Create a temporary array to hold the bytes
+    let mut buf = [0u8; 8]; // For u64, we need 8 bytes
+
+    // Step 2: Read the bytes from the unaligned pointer
+    unsafe {
+        // Copy bytes from the pointer into the buffer
+        for i in 0..8 {
+            buf[i] = *ptr.add(i); // Use add(i) to get each byte
+        }
+    }
+
+    // Step 3: Convert the byte array to u64
+    u64::from_be_bytes(buf) // Convert to u64 (you can use from_be_bytes if needed)
+}
+
+fn write_unaligned_u64(ptr: *mut u8, value: u64) {
+    // Step 1: Convert the u64 value to a byte array
+    let bytes = value.to_be_bytes(); // You can use to_be_bytes() or to_ne_bytes() depending on endianness
+
+    // Step 2: Write the bytes to the unaligned pointer
+    unsafe {
+        for i in 0..8 {
+            *ptr.add(i) = bytes[i]; // Use add(i) to get each byte's address
+        }
+    }
+}
+
+/// Returns a pointer to the `reg` property of the memory node, holding the base and the size of
+/// the memory.
+fn memory_region_reg(device_tree_blob_addr: usize) -> Result<*const u8, FdtError> {
+    let fdt: FlattenedDeviceTree;
+    unsafe { fdt = FlattenedDeviceTree::from_raw_pointer(device_tree_blob_addr as *const u8)? }
+
+    let mem_prop = fdt
+        .inner
+        .props()
+        .find(|p| Ok(p.name()? == "device_type" && p.str()? == "memory"))?
+        .ok_or_else(|| FdtError::NoMemoryNode())?;
+
+    let reg_prop = mem_prop
+        .node()
+        .props()
+        .find(|p| Ok(p.name().unwrap_or("empty") == "reg"))?
+        .ok_or_else(|| FdtError::NoMemoryNode())?;
+
+    Ok(reg_prop.propbuf().as_ptr())
+}
+
+pub fn divide_memory_region_size(device_tree_blob_addr: usize) -> Result<(), FdtError> {
+    let reg = memory_region_reg(device_tree_blob_addr)?;
+
+    unsafe {
+        let ptr: *const u8 = reg.add(8);
+
+        let memory_size = read_unaligned_u64(ptr);
+        write_unaligned_u64(ptr as *mut u8, memory_size / 2);
+    }
+
+    Ok(())
+}
+
+/// Shrinks the memory region such that it ends at or before `end`, the memory region is left
+/// unchanged if it already does.
+pub fn limit_memory_region_end(device_tree_blob_addr: usize, end: usize) -> Result<(), FdtError> {
+    let reg = memory_region_reg(device_tree_blob_addr)?;
+
+    unsafe {
+        let memory_base = read_unaligned_u64(reg);
+        let ptr: *const u8 = reg.add(8);
+        let memory_size = read_unaligned_u64(ptr);
+
+        let limited_size = (end as u64).saturating_sub(memory_base);
+        if limited_size < memory_size {
+            write_unaligned_u64(ptr as *mut u8, limited_size);
+        }
+    }
+
+    Ok(())
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_device_nodes() {
+        assert_eq!(match_compatible("riscv,clint0"), Some(Device::Clint));
+        assert_eq!(match_compatible("sifive,plic-1.0.0"), Some(Device::Plic));
+        assert_eq!(match_compatible("ns16550a"), Some(Device::Uart));
+        assert_eq!(match_compatible("virtio,mmio"), None);
+
+        let reg = [0, 0, 0, 0, 0x0c, 0, 0, 0, 0, 0, 0, 0, 0, 0x60, 0, 0];
+        assert_eq!(
+            parse_reg(&reg),
+            Some(MmioRegion {
+                base: 0xc000000,
+                size: 0x600000
+            })
+        );
+        assert_eq!(parse_reg(&reg[..12]), None);
+
+        assert_eq!(parse_cell(&[0x01, 0x6e, 0x36, 0x00]), Some(24_000_000));
+        assert_eq!(parse_cell(&reg[..8]), None);
+    }
+}
@@ -0,0 +1,669 @@
+//! Device Tree Patching
+//!
+//! The device tree passed by the previous boot stage describes the whole board, including the
+//! memory used by Miralis and by the policy. Before handing it to the firmware, Miralis rewrites
+//! it to reserve or remove memory regions, hide the devices it owns, and inject properties in the
+//! `chosen` node.
+//!
+//! The patch is applied while copying the blob: the memory reservation block is extended, hidden
+//! nodes are skipped, and new nodes and properties are emitted at the end of their parent.
+
+use core::fmt::{self, Write};
+
+use super::MmioRegion;
+
+const FDT_MAGIC: u32 = 0xd00dfeed;
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+/// Size of the header of a flattened device tree, for version 17.
+const HEADER_SIZE: usize = 40;
+/// The version of the patched device tree, and the oldest compatible version.
+const FDT_VERSION: u32 = 17;
+const FDT_LAST_COMP_VERSION: u32 = 16;
+
+/// Maximum number of entries of each kind in a patch.
+const MAX_ENTRIES: usize = 8;
+/// Maximum size of the property names added to the strings block.
+const MAX_EXTRA_STRINGS: usize = 256;
+
+/// Name of the nodes holding the removed memory regions.
+const REMOVED_MEMORY_NODE: &str = "miralis";
+
+// ——————————————————————————— Device Tree Patch ———————————————————————————— //
+
+/// A set of modifications to apply to a device tree.
+#[derive(Debug, Default)]
+pub struct DtbPatch<'a> {
+    /// Regions added to the memory reservation block.
+    reserved: [Option<MmioRegion>; MAX_ENTRIES],
+    /// Regions added as `no-map` nodes of `/reserved-memory`.
+    removed: [Option<MmioRegion>; MAX_ENTRIES],
+    /// Unit addresses of the nodes to remove.
+    hidden: [Option<usize>; MAX_ENTRIES],
+    /// Properties of the `/chosen` node, replacing existing properties with the same name.
+    chosen: [Option<(&'a str, &'a [u8])>; MAX_ENTRIES],
+}
+
+impl<'a> DtbPatch<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reserves a memory region, the OS must not allocate it but can still map it.
+    pub fn reserve_memory(&mut self, region: MmioRegion) -> Result<(), &'static str> {
+        push(&mut self.reserved, region)
+    }
+
+    /// Removes a memory region, the OS must neither allocate nor map it.
+    pub fn remove_memory(&mut self, region: MmioRegion) -> Result<(), &'static str> {
+        push(&mut self.removed, region)
+    }
+
+    /// Hides the device nodes with the given unit address.
+    pub fn hide_device(&mut self, address: usize) -> Result<(), &'static str> {
+        push(&mut self.hidden, address)
+    }
+
+    /// Sets a property of the `/chosen` node, which is created if needed.
+    pub fn chosen_property(&mut self, name: &'a str, value: &'a [u8]) -> Result<(), &'static str> {
+        push(&mut self.chosen, (name, value))
+    }
+
+    /// Writes the patched copy of the device tree `src` to `dst`, returns the size of the patched
+    /// device tree.
+    pub fn apply(&self, src: &[u8], dst: &mut [u8]) -> Result<usize, &'static str> {
+        let header = Header::parse(src)?;
+        let strings = src
+            .get(header.off_dt_strings..header.off_dt_strings + header.size_dt_strings)
+            .ok_or("Invalid device tree strings block")?;
+        let mut strings = Strings::new(strings);
+        let mut out = BlobWriter::new(dst);
+        out.skip(HEADER_SIZE)?;
+
+        // Memory reservation block
+        let off_mem_rsvmap = out.pos;
+        let mut rsvmap = BlobReader::new(src, header.off_mem_rsvmap);
+        loop {
+            let (address, size) = (rsvmap.u64()?, rsvmap.u64()?);
+            if address == 0 && size == 0 {
+                break;
+            }
+            out.u64(address)?;
+            out.u64(size)?;
+        }
+        for region in self.reserved.iter().flatten() {
+            out.u64(region.base as u64)?;
+            out.u64(region.size as u64)?;
+        }
+        out.u64(0)?;
+        out.u64(0)?;
+
+        // Structure block
+        let off_dt_struct = out.pos;
+        let struct_block = src
+            .get(..header.off_dt_struct + header.size_dt_struct)
+            .ok_or("Invalid device tree structure block")?;
+        self.patch_structure(
+            BlobReader::new(struct_block, header.off_dt_struct),
+            &mut out,
+            &mut strings,
+        )?;
+        let size_dt_struct = out.pos - off_dt_struct;
+
+        // Strings block
+        let off_dt_strings = out.pos;
+        out.bytes(strings.original)?;
+        out.bytes(&strings.extra[..strings.len])?;
+        let size_dt_strings = out.pos - off_dt_strings;
+        let total_size = out.pos;
+
+        let mut out = BlobWriter::new(dst);
+        for field in [
+            FDT_MAGIC,
+            total_size as u32,
+            off_dt_struct as u32,
+            off_dt_strings as u32,
+            off_mem_rsvmap as u32,
+            FDT_VERSION,
+            FDT_LAST_COMP_VERSION,
+            header.boot_cpuid_phys,
+            size_dt_strings as u32,
+            size_dt_struct as u32,
+        ] {
+            out.u32(field)?;
+        }
+
+        Ok(total_size)
+    }
+
+    /// Copies the structure block, applying the patch along the way.
+    fn patch_structure(
+        &self,
+        mut src: BlobReader,
+        out: &mut BlobWriter,
+        strings: &mut Strings,
+    ) -> Result<(), &'static str> {
+        let mut depth = 0;
+        // Depth of the hidden node being skipped, if any
+        let mut hidden_depth = None;
+        // Whether we are in the node and whether its new content has been emitted
+        let mut in_chosen = false;
+        let mut chosen_done = false;
+        let mut in_reserved_memory = false;
+        let mut reserved_memory_done = self.removed.iter().all(Option::is_none);
+
+        loop {
+            let token = src.u32()?;
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = src.name()?;
+                    depth += 1;
+                    if hidden_depth.is_some() {
+                        continue;
+                    }
+                    if depth > 1 && self.is_hidden(name) {
+                        hidden_depth = Some(depth);
+                        continue;
+                    }
+                    if depth == 3 && in_chosen && !chosen_done {
+                        // Properties must precede the sub-nodes
+                        self.emit_chosen_properties(out, strings)?;
+                        chosen_done = true;
+                    }
+                    if depth == 2 {
+                        in_chosen = name == "chosen";
+                        in_reserved_memory = name == "reserved-memory";
+                    }
+                    out.begin_node(format_args!("{}", name))?;
+                }
+                FDT_END_NODE => {
+                    depth -= 1;
+                    if let Some(hidden) = hidden_depth {
+                        if depth < hidden {
+                            hidden_depth = None;
+                        }
+                        continue;
+                    }
+                    if depth == 1 && in_chosen && !chosen_done {
+                        self.emit_chosen_properties(out, strings)?;
+                        chosen_done = true;
+                    }
+                    if depth == 1 && in_reserved_memory && !reserved_memory_done {
+                        self.emit_removed_memory(out, strings)?;
+                        reserved_memory_done = true;
+                    }
+                    if depth == 1 {
+                        in_chosen = false;
+                        in_reserved_memory = false;
+                    }
+                    if depth == 0 {
+                        // End of the root node, create the missing nodes
+                        if !chosen_done && self.chosen.iter().any(Option::is_some) {
+                            out.begin_node(format_args!("chosen"))?;
+                            self.emit_chosen_properties(out, strings)?;
+                            out.u32(FDT_END_NODE)?;
+                        }
+                        if !reserved_memory_done {
+                            out.begin_node(format_args!("reserved-memory"))?;
+                            out.prop(strings.offset("#address-cells")?, &2u32.to_be_bytes())?;
+                            out.prop(strings.offset("#size-cells")?, &2u32.to_be_bytes())?;
+                            out.prop(strings.offset("ranges")?, &[])?;
+                            self.emit_removed_memory(out, strings)?;
+                            out.u32(FDT_END_NODE)?;
+                        }
+                    }
+                    out.u32(FDT_END_NODE)?;
+                }
+                FDT_PROP => {
+                    let len = src.u32()? as usize;
+                    let nameoff = src.u32()?;
+                    let value = src.bytes(len)?;
+                    if hidden_depth.is_some() {
+                        continue;
+                    }
+                    if in_chosen && depth == 2 && self.is_chosen(strings.get(nameoff)?) {
+                        // Replaced by the patch
+                        continue;
+                    }
+                    out.prop(nameoff, value)?;
+                }
+                FDT_NOP => {}
+                FDT_END => {
+                    out.u32(FDT_END)?;
+                    return Ok(());
+                }
+                _ => return Err("Invalid device tree token"),
+            }
+        }
+    }
+
+    fn emit_chosen_properties(
+        &self,
+        out: &mut BlobWriter,
+        strings: &mut Strings,
+    ) -> Result<(), &'static str> {
+        for (name, value) in self.chosen.iter().flatten() {
+            out.prop(strings.offset(name)?, value)?;
+        }
+        Ok(())
+    }
+
+    fn emit_removed_memory(
+        &self,
+        out: &mut BlobWriter,
+        strings: &mut Strings,
+    ) -> Result<(), &'static str> {
+        for region in self.removed.iter().flatten() {
+            let mut reg = [0; 16];
+            reg[..8].copy_from_slice(&(region.base as u64).to_be_bytes());
+            reg[8..].copy_from_slice(&(region.size as u64).to_be_bytes());
+
+            out.begin_node(format_args!("{}@{:x}", REMOVED_MEMORY_NODE, region.base))?;
+            out.prop(strings.offset("reg")?, &reg)?;
+            out.prop(strings.offset("no-map")?, &[])?;
+            out.u32(FDT_END_NODE)?;
+        }
+        Ok(())
+    }
+
+    fn is_hidden(&self, node_name: &str) -> bool {
+        unit_address(node_name).is_some_and(|address| self.hidden.contains(&Some(address)))
+    }
+
+    fn is_chosen(&self, name: &str) -> bool {
+        self.chosen
+            .iter()
+            .flatten()
+            .any(|(chosen, _)| *chosen == name)
+    }
+}
+
+fn push<T>(entries: &mut [Option<T>], value: T) -> Result<(), &'static str> {
+    let slot = entries
+        .iter_mut()
+        .find(|entry| entry.is_none())
+        .ok_or("Too many device tree patch entries")?;
+    *slot = Some(value);
+    Ok(())
+}
+
+/// Returns the unit address of a node, e.g. `0x10000000` for `serial@10000000`.
+fn unit_address(node_name: &str) -> Option<usize> {
+    let (_, address) = node_name.split_once('@')?;
+    let address = address.split(',').next()?;
+    usize::from_str_radix(address, 16).ok()
+}
+
+/// The fields of the device tree header used by the patch.
+struct Header {
+    off_dt_struct: usize,
+    off_dt_strings: usize,
+    off_mem_rsvmap: usize,
+    boot_cpuid_phys: u32,
+    size_dt_strings: usize,
+    size_dt_struct: usize,
+}
+
+impl Header {
+    fn parse(blob: &[u8]) -> Result<Self, &'static str> {
+        let mut reader = BlobReader::new(blob, 0);
+        if reader.u32()? != FDT_MAGIC {
+            return Err("Invalid device tree magic");
+        }
+        let total_size = reader.u32()? as usize;
+        if total_size > blob.len() {
+            return Err("Truncated device tree");
+        }
+        let off_dt_struct = reader.u32()? as usize;
+        let off_dt_strings = reader.u32()? as usize;
+        let off_mem_rsvmap = reader.u32()? as usize;
+        if reader.u32()? < FDT_VERSION {
+            return Err("Unsupported device tree version");
+        }
+        let _last_comp_version = reader.u32()?;
+        Ok(Header {
+            off_dt_struct,
+            off_dt_strings,
+            off_mem_rsvmap,
+            boot_cpuid_phys: reader.u32()?,
+            size_dt_strings: reader.u32()? as usize,
+            size_dt_struct: reader.u32()? as usize,
+        })
+    }
+}
+
+/// The strings block of the patched device tree: the original strings followed by the names of
+/// the new properties.
+struct Strings<'a> {
+    original: &'a [u8],
+    extra: [u8; MAX_EXTRA_STRINGS],
+    len: usize,
+}
+
+impl<'a> Strings<'a> {
+    fn new(original: &'a [u8]) -> Self {
+        Strings {
+            original,
+            extra: [0; MAX_EXTRA_STRINGS],
+            len: 0,
+        }
+    }
+
+    /// Returns the string at the given offset of the original strings block.
+    fn get(&self, offset: u32) -> Result<&'a str, &'static str> {
+        let string = self
+            .original
+            .get(offset as usize..)
+            .ok_or("Invalid device tree string offset")?;
+        let end = string
+            .iter()
+            .position(|c| *c == 0)
+            .ok_or("Unterminated device tree string")?;
+        core::str::from_utf8(&string[..end]).map_err(|_| "Invalid device tree string")
+    }
+
+    /// Returns the offset of a string, which is added to the block if not already present.
+    fn offset(&mut self, name: &str) -> Result<u32, &'static str> {
+        let name = name.as_bytes();
+        let offset = match find_string(self.original, name) {
+            Some(offset) => offset,
+            None => match find_string(&self.extra[..self.len], name) {
+                Some(offset) => self.original.len() + offset,
+                None => {
+                    let end = self.len + name.len() + 1;
+                    if end > MAX_EXTRA_STRINGS {
+                        return Err("Too many new device tree strings");
+                    }
+                    self.extra[self.len..end - 1].copy_from_slice(name);
+                    self.extra[end - 1] = 0;
+                    let offset = self.original.len() + self.len;
+                    self.len = end;
+                    offset
+                }
+            },
+        };
+        Ok(offset as u32)
+    }
+}
+
+/// Returns the offset of a null-terminated string in a strings block.
+fn find_string(block: &[u8], name: &[u8]) -> Option<usize> {
+    let mut offset = 0;
+    for string in block.split(|c| *c == 0) {
+        if string == name && offset + string.len() < block.len() {
+            return Some(offset);
+        }
+        offset += string.len() + 1;
+    }
+    None
+}
+
+// ————————————————————————— Blob Reader and Writer ————————————————————————— //
+
+/// Reads big endian values from a device tree blob.
+struct BlobReader<'a> {
+    blob: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BlobReader<'a> {
+    fn new(blob: &'a [u8], pos: usize) -> Self {
+        BlobReader { blob, pos }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], &'static str> {
+        let bytes = self
+            .blob
+            .get(self.pos..self.pos + len)
+            .ok_or("Truncated device tree")?;
+        self.pos = (self.pos + len).next_multiple_of(4);
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, &'static str> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, &'static str> {
+        Ok(((self.u32()? as u64) << 32) | self.u32()? as u64)
+    }
+
+    /// Reads the null-terminated name of a node.
+    fn name(&mut self) -> Result<&'a str, &'static str> {
+        let rest = self.blob.get(self.pos..).ok_or("Truncated device tree")?;
+        let len = rest
+            .iter()
+            .position(|c| *c == 0)
+            .ok_or("Unterminated device tree node name")?;
+        let name = self.bytes(len + 1)?;
+        core::str::from_utf8(&name[..len]).map_err(|_| "Invalid device tree node name")
+    }
+}
+
+/// Writes big endian values to a device tree blob.
+struct BlobWriter<'a> {
+    blob: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> BlobWriter<'a> {
+    fn new(blob: &'a mut [u8]) -> Self {
+        BlobWriter { blob, pos: 0 }
+    }
+
+    fn skip(&mut self, len: usize) -> Result<(), &'static str> {
+        if self.pos + len > self.blob.len() {
+            return Err("Patched device tree does not fit in the buffer");
+        }
+        self.pos += len;
+        Ok(())
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) -> Result<(), &'static str> {
+        let start = self.pos;
+        self.skip(bytes.len())?;
+        self.blob[start..self.pos].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Pads with zeroes up to the next 4 bytes boundary.
+    fn align(&mut self) -> Result<(), &'static str> {
+        while self.pos % 4 != 0 {
+            self.bytes(&[0])?;
+        }
+        Ok(())
+    }
+
+    fn u32(&mut self, value: u32) -> Result<(), &'static str> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    fn u64(&mut self, value: u64) -> Result<(), &'static str> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    fn begin_node(&mut self, name: fmt::Arguments) -> Result<(), &'static str> {
+        self.u32(FDT_BEGIN_NODE)?;
+        self.write_fmt(name)
+            .map_err(|_| "Patched device tree does not fit in the buffer")?;
+        self.bytes(&[0])?;
+        self.align()
+    }
+
+    fn prop(&mut self, nameoff: u32, value: &[u8]) -> Result<(), &'static str> {
+        self.u32(FDT_PROP)?;
+        self.u32(value.len() as u32)?;
+        self.u32(nameoff)?;
+        self.bytes(value)?;
+        self.align()
+    }
+}
+
+impl Write for BlobWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.bytes(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a device tree with a memory node, a UART and a `chosen` node.
+    fn build_device_tree(blob: &mut [u8]) -> usize {
+        let strings = b"reg\0bootargs\0compatible\0";
+        let mut out = BlobWriter::new(blob);
+        out.skip(HEADER_SIZE).unwrap();
+        let off_mem_rsvmap = out.pos;
+        out.u64(0).unwrap();
+        out.u64(0).unwrap();
+
+        let off_dt_struct = out.pos;
+        out.begin_node(format_args!("")).unwrap();
+        out.begin_node(format_args!("memory@80000000")).unwrap();
+        out.prop(0, &[0, 0, 0, 0, 0x80, 0, 0, 0, 0, 0, 0, 0, 0x80, 0, 0, 0])
+            .unwrap();
+        out.u32(FDT_END_NODE).unwrap();
+        out.u32(FDT_NOP).unwrap();
+        out.begin_node(format_args!("serial@10000000")).unwrap();
+        out.prop(13, b"ns16550a\0").unwrap();
+        out.u32(FDT_END_NODE).unwrap();
+        out.begin_node(format_args!("chosen")).unwrap();
+        out.prop(4, b"console=ttyS0\0").unwrap();
+        out.u32(FDT_END_NODE).unwrap();
+        out.u32(FDT_END_NODE).unwrap();
+        out.u32(FDT_END).unwrap();
+        let size_dt_struct = out.pos - off_dt_struct;
+
+        let off_dt_strings = out.pos;
+        out.bytes(strings).unwrap();
+        let total_size = out.pos;
+
+        let mut out = BlobWriter::new(blob);
+        for field in [
+            FDT_MAGIC,
+            total_size as u32,
+            off_dt_struct as u32,
+            off_dt_strings as u32,
+            off_mem_rsvmap as u32,
+            FDT_VERSION,
+            FDT_LAST_COMP_VERSION,
+            0,
+            strings.len() as u32,
+            size_dt_struct as u32,
+        ] {
+            out.u32(field).unwrap();
+        }
+        total_size
+    }
+
+    /// Returns the nodes of a device tree as `(depth, name)`, and its properties as
+    /// `(node name, property name, value)`.
+    fn walk<'a>(
+        blob: &'a [u8],
+        nodes: &mut [(usize, &'a str)],
+        props: &mut [(&'a str, &'a str, &'a [u8])],
+    ) -> (usize, usize) {
+        let header = Header::parse(blob).unwrap();
+        let strings = Strings::new(
+            &blob[header.off_dt_strings..header.off_dt_strings + header.size_dt_strings],
+        );
+        let mut reader = BlobReader::new(blob, header.off_dt_struct);
+        let (mut nb_nodes, mut nb_props, mut depth) = (0, 0, 0);
+        let mut current = "";
+        loop {
+            match reader.u32().unwrap() {
+                FDT_BEGIN_NODE => {
+                    depth += 1;
+                    current = reader.name().unwrap();
+                    nodes[nb_nodes] = (depth, current);
+                    nb_nodes += 1;
+                }
+                FDT_END_NODE => depth -= 1,
+                FDT_PROP => {
+                    let len = reader.u32().unwrap() as usize;
+                    let name = strings.get(reader.u32().unwrap()).unwrap();
+                    props[nb_props] = (current, name, reader.bytes(len).unwrap());
+                    nb_props += 1;
+                }
+                FDT_END => return (nb_nodes, nb_props),
+                token => panic!("Unexpected token {}", token),
+            }
+        }
+    }
+
+    #[test]
+    fn patch_device_tree() {
+        let mut src = [0; 512];
+        let src_size = build_device_tree(&mut src);
+        let src = &src[..src_size];
+
+        // An empty patch only drops the NOP tokens
+        let mut dst = [0; 1024];
+        let size = DtbPatch::new().apply(src, &mut dst).unwrap();
+        assert_eq!(size, src_size - 4);
+
+        let mut patch = DtbPatch::new();
+        let miralis = MmioRegion {
+            base: 0x80000000,
+            size: 0x200000,
+        };
+        let confidential = MmioRegion {
+            base: 0xc0000000,
+            size: 0x40000000,
+        };
+        patch.reserve_memory(miralis).unwrap();
+        patch.remove_memory(confidential).unwrap();
+        patch.hide_device(0x10000000).unwrap();
+        patch.chosen_property("bootargs", b"quiet\0").unwrap();
+        patch.chosen_property("miralis", b"\0").unwrap();
+        let size = patch.apply(src, &mut dst).unwrap();
+        let dst = &dst[..size];
+
+        let header = Header::parse(dst).unwrap();
+        let mut rsvmap = BlobReader::new(dst, header.off_mem_rsvmap);
+        assert_eq!(rsvmap.u64().unwrap(), 0x80000000);
+        assert_eq!(rsvmap.u64().unwrap(), 0x200000);
+        assert_eq!((rsvmap.u64().unwrap(), rsvmap.u64().unwrap()), (0, 0));
+
+        let mut nodes = [(0, ""); 8];
+        let mut props = [("", "", &[][..]); 16];
+        let (nb_nodes, nb_props) = walk(dst, &mut nodes, &mut props);
+        assert_eq!(
+            nodes[..nb_nodes],
+            [
+                (1, ""),
+                (2, "memory@80000000"),
+                (2, "chosen"),
+                (2, "reserved-memory"),
+                (3, "miralis@c0000000"),
+            ]
+        );
+
+        // The existing bootargs are replaced
+        let chosen = props[..nb_props]
+            .iter()
+            .filter(|(node, _, _)| *node == "chosen")
+            .map(|(_, name, value)| (*name, *value));
+        assert!(chosen.eq([("bootargs", &b"quiet\0"[..]), ("miralis", &b"\0"[..])]));
+        assert!(props[..nb_props].contains(&(
+            "miralis@c0000000",
+            "reg",
+            &[0, 0, 0, 0, 0xc0, 0, 0, 0, 0, 0, 0, 0, 0x40, 0, 0, 0][..]
+        )));
+        assert!(props[..nb_props].contains(&("miralis@c0000000", "no-map", &[][..])));
+        assert!(!props[..nb_props]
+            .iter()
+            .any(|(_, name, _)| *name == "compatible"));
+
+        // The buffer must be large enough
+        assert!(patch.apply(src, &mut [0; 128]).is_err());
+    }
+}
@@ -1,8 +1,22 @@
 //! Base driver class
+//!
+//! ## CLINT access contexts
+//!
+//! The physical CLINT is shared by all harts, and is accessed through two paths:
+//!
+//! - The `mtime` and `mtimecmp` registers are accessed through the [ClintDriver], which lives
+//!   behind a lock. The lock must only be taken from Miralis with interrupts disabled, that is
+//!   while handling a trap or during boot, such that a hart holding the lock can not be
+//!   interrupted and try to take it again. This is checked in debug builds.
+//! - The MSIP registers are accessed through [MsipRegisters], without taking the lock. Each MSIP
+//!   register is an aligned 32 bits word written with a single store, concurrent writes from
+//!   several harts are therefore ordered by the hardware. IPIs can be sent and cleared from any
+//!   context, including while another hart holds the driver lock.
 
 use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
-use crate::arch::{Arch, Architecture, Csr};
+use crate::arch::{mstatus, Arch, Architecture, Csr, Width};
 use crate::config::{self, PLATFORM_NB_HARTS};
 
 pub mod clint {
@@ -17,10 +31,104 @@ pub mod clint {
     pub const _MTIME_WIDTH: Width = Width::Byte8;
 }
 
-#[derive(Clone, Debug)]
+pub mod plic {
+    pub const PRIORITY_OFFSET: usize = 0x0;
+    pub const PENDING_OFFSET: usize = 0x1000;
+    pub const ENABLE_OFFSET: usize = 0x2000;
+    pub const ENABLE_STRIDE: usize = 0x80;
+    pub const CONTEXT_OFFSET: usize = 0x200000;
+    pub const CONTEXT_STRIDE: usize = 0x1000;
+    pub const THRESHOLD_OFFSET: usize = 0x0;
+    pub const CLAIM_OFFSET: usize = 0x4;
+
+    /// Maximum number of interrupt sources, including the reserved source 0.
+    pub const MAX_SOURCES: usize = 1024;
+    /// Maximum size of the PLIC address space.
+    pub const MAX_SIZE: usize = 0x4000000;
+}
+
+pub mod uart {
+    /// Transmit holding register (write), or receive buffer register (read).
+    pub const THR: usize = 0;
+    /// Divisor latch, low byte (when LCR.DLAB is set).
+    pub const DLL: usize = 0;
+    /// Divisor latch, high byte (when LCR.DLAB is set).
+    pub const DLM: usize = 1;
+    /// Interrupt enable register.
+    pub const IER: usize = 1;
+    /// FIFO control register (write).
+    pub const FCR: usize = 2;
+    /// Line control register.
+    pub const LCR: usize = 3;
+    /// Modem control register.
+    pub const MCR: usize = 4;
+    /// Line status register.
+    pub const LSR: usize = 5;
+
+    /// 8 data bits, no parity, 1 stop bit.
+    pub const LCR_8N1: usize = 0x03;
+    /// Divisor latch access bit, registers 0 and 1 hold the baud rate divisor when set.
+    pub const LCR_DLAB: usize = 0x80;
+    /// Enable the FIFOs and clear both of them.
+    pub const FCR_ENABLE_AND_CLEAR: usize = 0x07;
+    /// The transmit holding register is empty.
+    pub const LSR_THRE: usize = 0x20;
+    /// The transmitter is empty.
+    pub const LSR_TEMT: usize = 0x40;
+
+    /// Returns the baud rate divisor for the given input clock, rounded to the nearest integer.
+    pub const fn divisor(clock_frequency: usize, baud_rate: usize) -> usize {
+        let divisor = (clock_frequency + 8 * baud_rate) / (16 * baud_rate);
+        if divisor == 0 {
+            1
+        } else {
+            divisor
+        }
+    }
+}
+
+pub mod sifive_uart {
+    /// Transmit data register, bit 31 is set while the transmit FIFO is full.
+    pub const TXDATA_OFFSET: usize = 0x0;
+    /// Transmit control register.
+    pub const TXCTRL_OFFSET: usize = 0x8;
+
+    pub const TXDATA_FULL: u32 = 1 << 31;
+    /// Enable the transmitter, with a single stop bit and no watermark interrupt.
+    pub const TXCTRL_TXEN: u32 = 1;
+}
+
+pub mod ccache {
+    /// Configuration register, holding the geometry of the cache.
+    pub const CONFIG_OFFSET: usize = 0x0;
+    /// Index of the last enabled way.
+    pub const WAY_ENABLE_OFFSET: usize = 0x8;
+
+    /// Returns the number of ways from the configuration register.
+    pub const fn nb_ways(config: u32) -> usize {
+        ((config >> 8) & 0xff) as usize
+    }
+}
+
+/// Enables all the ways of a SiFive L2 cache controller.
+///
+/// Only the first way is enabled at reset. Enabling ways is idempotent, they can not be disabled
+/// until the next reset.
+///
+/// SAFETY: the base address must be the base address of a SiFive L2 cache controller.
+pub unsafe fn enable_l2_cache_ways(base: usize) {
+    let config = ptr::read_volatile((base + ccache::CONFIG_OFFSET) as *const u32);
+    let nb_ways = ccache::nb_ways(config);
+    if nb_ways > 0 {
+        let way_enable = (base + ccache::WAY_ENABLE_OFFSET) as *mut u32;
+        ptr::write_volatile(way_enable, (nb_ways - 1) as u32);
+    }
+}
+
+#[derive(Debug)]
 pub struct ClintDriver {
     /// The base address of the physical CLINT.
-    base: usize,
+    base: AtomicUsize,
 }
 
 impl ClintDriver {
@@ -31,15 +139,38 @@ impl ClintDriver {
     /// is initialized with the same base address and that no other code is accessing the CLINT
     /// device.
     pub const unsafe fn new(base: usize) -> Self {
-        Self { base }
+        Self {
+            base: AtomicUsize::new(base),
+        }
+    }
+
+    /// Moves the driver to a new base address, such as one discovered from the device tree.
+    ///
+    /// SAFETY: the same assumptions as for [ClintDriver::new] apply to the new base address.
+    pub unsafe fn relocate(&self, base: usize) {
+        self.base.store(base, Ordering::Relaxed);
     }
 
     fn add_base_offset(&self, offset: usize) -> usize {
-        self.base.checked_add(offset).expect("Invalid offset")
+        self.base
+            .load(Ordering::Relaxed)
+            .checked_add(offset)
+            .expect("Invalid offset")
+    }
+
+    /// Checks that the driver is accessed from a context that may hold the CLINT lock.
+    ///
+    /// See the module documentation for the allowed contexts.
+    fn debug_assert_lock_context(&self) {
+        debug_assert!(
+            Arch::read_csr(Csr::Mstatus) & mstatus::MIE_FILTER == 0,
+            "The CLINT lock must not be held with interrupts enabled"
+        );
     }
 
     /// Read the current value of the machine timer (mtime)
     pub fn read_mtime(&self) -> usize {
+        self.debug_assert_lock_context();
         let pointer = self.add_base_offset(clint::MTIME_OFFSET);
 
         // SAFETY: We derive a valid memory address assuming the base points to a valid CLINT
@@ -51,7 +182,9 @@ impl ClintDriver {
     }
 
     /// Write a new value to the machine timer (mtime)
+    #[allow(unused)]
     pub fn write_mtime(&mut self, time: usize) {
+        self.debug_assert_lock_context();
         let pointer = self.add_base_offset(clint::MTIME_OFFSET);
 
         // SAFETY: We derive a valid memory address assuming the base points to a valid CLINT
@@ -61,6 +194,7 @@ impl ClintDriver {
     }
 
     ///  Read the value of the machine timer compare (mtimecmp) for a specific hart
+    #[allow(dead_code)]
     pub fn read_mtimecmp(&self, hart: usize) -> Result<usize, &'static str> {
         if hart >= config::PLATFORM_NB_HARTS {
             log::warn!(
@@ -70,6 +204,7 @@ impl ClintDriver {
             );
             return Err("Out of bounds MTIMECMP read attempt");
         }
+        self.debug_assert_lock_context();
         let pointer =
             self.add_base_offset(clint::MTIMECMP_OFFSET + hart * clint::MTIMECMP_WIDTH.to_bytes());
 
@@ -90,6 +225,7 @@ impl ClintDriver {
             );
             return Err("Out of bounds MTIMECMP write attempt");
         }
+        self.debug_assert_lock_context();
         let pointer =
             self.add_base_offset(clint::MTIMECMP_OFFSET + hart * clint::MTIMECMP_WIDTH.to_bytes());
 
@@ -100,9 +236,47 @@ impl ClintDriver {
         log::trace!("MTIMECMP value written: 0x{:x}", deadline);
         Ok(())
     }
+}
+
+/// The machine software interrupt (MSIP) registers of a CLINT.
+///
+/// Unlike the [ClintDriver] the MSIP registers can be accessed without a lock, see the module
+/// documentation.
+#[derive(Debug)]
+pub struct MsipRegisters {
+    /// The base address of the physical CLINT.
+    base: AtomicUsize,
+}
+
+impl MsipRegisters {
+    /// Creates a handle to the MSIP registers of a CLINT device from its base address.
+    ///
+    /// SAFETY: this function assumes that the base address corresponds to the base address of a
+    /// CLINT-compatible device, and that the MSIP registers are only accessed with single aligned
+    /// 32 bits loads and stores.
+    pub const unsafe fn new(base: usize) -> Self {
+        Self {
+            base: AtomicUsize::new(base),
+        }
+    }
+
+    /// Moves the MSIP registers to a new base address, such as one discovered from the device
+    /// tree.
+    ///
+    /// SAFETY: the same assumptions as for [MsipRegisters::new] apply to the new base address.
+    pub unsafe fn relocate(&self, base: usize) {
+        self.base.store(base, Ordering::Relaxed);
+    }
+
+    fn pointer(&self, hart: usize) -> usize {
+        self.base
+            .load(Ordering::Relaxed)
+            .checked_add(clint::MSIP_OFFSET + hart * clint::MSIP_WIDTH.to_bytes())
+            .expect("Invalid offset")
+    }
 
     /// Read the value of the machine software interrupt (msip) for a specific hart.
-    pub fn read_msip(&self, hart: usize) -> Result<usize, &'static str> {
+    pub fn read(&self, hart: usize) -> Result<usize, &'static str> {
         if hart >= config::PLATFORM_NB_HARTS {
             log::warn!(
                 "Tried to read MSIP for hart {}, but only {} hart(s) are available",
@@ -111,12 +285,10 @@ impl ClintDriver {
             );
             return Err("Out of bounds MSIP read attempt");
         }
-        let pointer =
-            self.add_base_offset(clint::MSIP_OFFSET + hart * clint::MSIP_WIDTH.to_bytes());
 
         // SAFETY: We checked that the number of hart is within the platform limit, which ensures
         // the read is contained within the MSIP area of the CLINT.
-        let msip = unsafe { ptr::read_volatile((pointer) as *const u32) };
+        let msip = unsafe { ptr::read_volatile(self.pointer(hart) as *const u32) };
         log::trace!("MSIP value: 0x{:x}", msip);
         if (msip >> 1) != 0 {
             log::warn!("Upper 31 bits of MSIP value are not zero!");
@@ -125,7 +297,7 @@ impl ClintDriver {
     }
 
     /// Write a new value to the machine software interrupt (msip) for a specific hart.
-    pub fn write_msip(&mut self, hart: usize, msip: u32) -> Result<(), &'static str> {
+    pub fn write(&self, hart: usize, msip: u32) -> Result<(), &'static str> {
         if hart >= PLATFORM_NB_HARTS {
             log::warn!(
                 "Tried to write MSIP for hart {}, but only {} hart(s) are available",
@@ -135,33 +307,264 @@ impl ClintDriver {
             return Err("Out of bounds MSIP write attempt");
         }
         let msip_value = msip & 0x1;
-        let pointer =
-            self.add_base_offset(clint::MSIP_OFFSET + hart * clint::MSIP_WIDTH.to_bytes());
 
         // SAFETY: We checked that the number of hart is within the platform limit, which ensures
-        // the read is contained within the MSIP area of the CLINT. Moreover, we take `self`
-        // with a &mut reference to enforce aliasing rules.
-        unsafe { ptr::write_volatile((pointer) as *mut u32, msip_value) };
+        // the write is contained within the MSIP area of the CLINT. The write is a single aligned
+        // 32 bits store, which can not be torn by concurrent writes from other harts.
+        unsafe { ptr::write_volatile(self.pointer(hart) as *mut u32, msip_value) };
         log::trace!("MSIP value written: 0x{:x} for hart {hart}", msip_value);
         Ok(())
     }
 
     /// Create a pending MSI interrupts for each harts of the platform, including the current one.
-    pub fn trigger_msi_on_all_harts(&mut self) {
+    pub fn trigger_msi_on_all_harts(&self) {
         for i in 0..PLATFORM_NB_HARTS {
-            self.write_msip(i, 1).unwrap();
+            self.write(i, 1).unwrap();
         }
     }
 
     /// Create a pending MSI interrupts for each harts of the platform, except the current one.
     #[allow(dead_code)]
-    pub fn trigger_msi_on_all_other_harts(&mut self) {
-        let current_hart: usize = Arch::read_csr(Csr::Marchid);
+    pub fn trigger_msi_on_all_other_harts(&self) {
+        let current_hart: usize = Arch::read_csr(Csr::Mhartid);
 
         for i in 0..PLATFORM_NB_HARTS {
             if i != current_hart {
-                self.write_msip(i, 1).unwrap();
+                self.write(i, 1).unwrap();
+            }
+        }
+    }
+}
+
+/// A driver for the physical PLIC.
+///
+/// The PLIC registers are 32 bits wide and accessed with single aligned loads and stores, and the
+/// claim and complete operations are atomic in hardware. The driver can therefore be used from
+/// all harts without a lock.
+#[derive(Debug)]
+pub struct PlicDriver {
+    /// The base address of the physical PLIC.
+    base: AtomicUsize,
+}
+
+impl PlicDriver {
+    /// Creates a new PLIC driver from the base address of the PLIC device.
+    ///
+    /// SAFETY: this function assumes that the base address corresponds to the base address of a
+    /// PLIC-compatible device, and that no other code is accessing the PLIC device.
+    pub const unsafe fn new(base: usize) -> Self {
+        Self {
+            base: AtomicUsize::new(base),
+        }
+    }
+
+    /// Moves the driver to a new base address, such as one discovered from the device tree.
+    ///
+    /// SAFETY: the same assumptions as for [PlicDriver::new] apply to the new base address.
+    pub unsafe fn relocate(&self, base: usize) {
+        self.base.store(base, Ordering::Relaxed);
+    }
+
+    fn pointer(&self, offset: usize) -> Result<usize, &'static str> {
+        if offset % 4 != 0 || offset >= plic::MAX_SIZE {
+            log::warn!("Invalid PLIC register offset: 0x{:x}", offset);
+            return Err("Invalid PLIC register offset");
+        }
+        Ok(self
+            .base
+            .load(Ordering::Relaxed)
+            .checked_add(offset)
+            .expect("Invalid offset"))
+    }
+
+    /// Read the 32 bits register at the given offset.
+    pub fn read(&self, offset: usize) -> Result<u32, &'static str> {
+        let pointer = self.pointer(offset)?;
+
+        // SAFETY: We checked that the offset is aligned and within the PLIC address space, assuming
+        // the base points to a valid PLIC device.
+        let value = unsafe { ptr::read_volatile(pointer as *const u32) };
+        log::trace!("PLIC value at 0x{:x}: 0x{:x}", offset, value);
+        Ok(value)
+    }
+
+    /// Write the 32 bits register at the given offset.
+    pub fn write(&self, offset: usize, value: u32) -> Result<(), &'static str> {
+        let pointer = self.pointer(offset)?;
+
+        // SAFETY: We checked that the offset is aligned and within the PLIC address space, assuming
+        // the base points to a valid PLIC device.
+        unsafe { ptr::write_volatile(pointer as *mut u32, value) };
+        log::trace!("PLIC value written at 0x{:x}: 0x{:x}", offset, value);
+        Ok(())
+    }
+}
+
+/// A driver for 16550-compatible UARTs.
+#[derive(Debug)]
+pub struct UartDriver {
+    /// The base address of the physical UART.
+    base: AtomicUsize,
+    /// Registers are spaced by `1 << reg_shift` bytes.
+    reg_shift: usize,
+    /// The width of the registers.
+    reg_width: Width,
+}
+
+impl UartDriver {
+    /// Creates a new UART driver from the base address of the UART device.
+    ///
+    /// SAFETY: this function assumes that the base address corresponds to the base address of a
+    /// 16550-compatible device with the given register layout.
+    pub const unsafe fn new(base: usize, reg_shift: usize, reg_width: Width) -> Self {
+        Self {
+            base: AtomicUsize::new(base),
+            reg_shift,
+            reg_width,
+        }
+    }
+
+    /// Moves the driver to a new base address, such as one discovered from the device tree.
+    ///
+    /// SAFETY: the same assumptions as for [UartDriver::new] apply to the new base address.
+    pub unsafe fn relocate(&self, base: usize) {
+        self.base.store(base, Ordering::Relaxed);
+    }
+
+    /// Returns the index of the register at the given offset.
+    pub fn register(&self, offset: usize) -> usize {
+        offset >> self.reg_shift
+    }
+
+    fn pointer(&self, offset: usize, width: Width) -> Result<usize, &'static str> {
+        if offset % width.to_bytes() != 0 || self.register(offset) > 7 {
+            log::warn!("Invalid UART register offset: 0x{:x}", offset);
+            return Err("Invalid UART register offset");
+        }
+        Ok(self
+            .base
+            .load(Ordering::Relaxed)
+            .checked_add(offset)
+            .expect("Invalid offset"))
+    }
+
+    /// Reads the given register.
+    pub fn read_register(&self, reg: usize) -> Result<usize, &'static str> {
+        self.read(reg << self.reg_shift, self.reg_width)
+    }
+
+    /// Writes the given register.
+    pub fn write_register(&self, reg: usize, value: usize) -> Result<(), &'static str> {
+        self.write(reg << self.reg_shift, self.reg_width, value)
+    }
+
+    /// Configures the UART for 8N1 transmission with the given baud rate divisor, with the FIFOs
+    /// enabled and interrupts disabled.
+    pub fn init(&self, divisor: usize) -> Result<(), &'static str> {
+        self.write_register(uart::IER, 0)?;
+        self.write_register(uart::LCR, uart::LCR_DLAB)?;
+        self.write_register(uart::DLL, divisor & 0xff)?;
+        self.write_register(uart::DLM, (divisor >> 8) & 0xff)?;
+        self.write_register(uart::LCR, uart::LCR_8N1)?;
+        self.write_register(uart::FCR, uart::FCR_ENABLE_AND_CLEAR)?;
+        self.write_register(uart::MCR, 0)
+    }
+
+    /// Transmits a byte, waiting for the transmit holding register to be empty.
+    pub fn put_byte(&self, byte: u8) -> Result<(), &'static str> {
+        while self.read_register(uart::LSR)? & uart::LSR_THRE == 0 {
+            core::hint::spin_loop();
+        }
+        self.write_register(uart::THR, byte as usize)
+    }
+
+    /// Reads the register at the given offset with an access of the given width.
+    pub fn read(&self, offset: usize, width: Width) -> Result<usize, &'static str> {
+        let pointer = self.pointer(offset, width)?;
+
+        // SAFETY: We checked that the offset is aligned and within the UART registers, assuming
+        // the base points to a valid UART device.
+        let value = unsafe {
+            match width {
+                Width::Byte => ptr::read_volatile(pointer as *const u8) as usize,
+                Width::Byte2 => ptr::read_volatile(pointer as *const u16) as usize,
+                Width::Byte4 => ptr::read_volatile(pointer as *const u32) as usize,
+                Width::Byte8 => ptr::read_volatile(pointer as *const u64) as usize,
+            }
+        };
+        Ok(value)
+    }
+
+    /// Writes the register at the given offset with an access of the given width.
+    pub fn write(&self, offset: usize, width: Width, value: usize) -> Result<(), &'static str> {
+        let pointer = self.pointer(offset, width)?;
+
+        // SAFETY: We checked that the offset is aligned and within the UART registers, assuming
+        // the base points to a valid UART device.
+        unsafe {
+            match width {
+                Width::Byte => ptr::write_volatile(pointer as *mut u8, value as u8),
+                Width::Byte2 => ptr::write_volatile(pointer as *mut u16, value as u16),
+                Width::Byte4 => ptr::write_volatile(pointer as *mut u32, value as u32),
+                Width::Byte8 => ptr::write_volatile(pointer as *mut u64, value as u64),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A driver for the UART of the SiFive SoCs, such as the FU540 and FU740.
+#[derive(Debug)]
+pub struct SifiveUartDriver {
+    /// The base address of the physical UART.
+    base: AtomicUsize,
+}
+
+impl SifiveUartDriver {
+    /// Creates a new UART driver from the base address of the UART device.
+    ///
+    /// SAFETY: this function assumes that the base address corresponds to the base address of a
+    /// SiFive UART.
+    pub const unsafe fn new(base: usize) -> Self {
+        Self {
+            base: AtomicUsize::new(base),
+        }
+    }
+
+    /// Moves the driver to a new base address, such as one discovered from the device tree.
+    ///
+    /// SAFETY: the same assumptions as for [SifiveUartDriver::new] apply to the new base address.
+    pub unsafe fn relocate(&self, base: usize) {
+        self.base.store(base, Ordering::Relaxed);
+    }
+
+    fn pointer(&self, offset: usize) -> *mut u32 {
+        (self.base.load(Ordering::Relaxed) + offset) as *mut u32
+    }
+
+    /// Enables the transmitter.
+    ///
+    /// The baud rate divisor depends on the frequency of the peripheral clock, which is configured
+    /// by the previous boot stage together with the divisor. The divisor is therefore left as is.
+    pub fn init(&self) {
+        // SAFETY: the base address points to a SiFive UART, whose registers are 32 bits wide.
+        unsafe {
+            ptr::write_volatile(
+                self.pointer(sifive_uart::TXCTRL_OFFSET),
+                sifive_uart::TXCTRL_TXEN,
+            )
+        };
+    }
+
+    /// Transmits a byte, waiting for space in the transmit FIFO.
+    pub fn put_byte(&self, byte: u8) {
+        let txdata = self.pointer(sifive_uart::TXDATA_OFFSET);
+        // SAFETY: the base address points to a SiFive UART, whose registers are 32 bits wide.
+        unsafe {
+            while ptr::read_volatile(txdata) & sifive_uart::TXDATA_FULL != 0 {
+                core::hint::spin_loop();
             }
+            ptr::write_volatile(txdata, byte as u32);
         }
     }
 }
@@ -0,0 +1,137 @@
+//! Hardware Errata
+//!
+//! Some harts deviate from the specification, or are booted by previous stages that do not follow
+//! the usual conventions. Rather than special-casing platforms inline, the workarounds are listed
+//! here together with the cores they affect, identified by their mvendorid, marchid and mimpid.
+
+use core::ops::RangeInclusive;
+
+use crate::arch::{Arch, Architecture, Csr};
+
+/// The JEDEC vendor ID of SiFive.
+const SIFIVE_MVENDORID: usize = 0x489;
+
+/// The architecture ID of the SiFive U7 series, such as the U74 cores of the JH7110.
+const SIFIVE_U7_MARCHID: usize = 0x8000000000000007;
+
+/// The list of known errata.
+const ERRATA: &[Erratum] = &[Erratum {
+    // The boot stages of the VisionFive2 (JH7110) do not pass the hart ID in a0.
+    name: "sifive-u7-boot-hart-id",
+    mvendorid: SIFIVE_MVENDORID,
+    marchid: SIFIVE_U7_MARCHID,
+    mimpid: 0..=usize::MAX,
+    workarounds: Workarounds {
+        hart_id_from_mhartid: true,
+    },
+}];
+
+// ————————————————————————————— Hart Identity —————————————————————————————— //
+
+/// The identity of a hart, as reported by its machine information registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HartIdentity {
+    pub mvendorid: usize,
+    pub marchid: usize,
+    pub mimpid: usize,
+}
+
+impl HartIdentity {
+    /// Reads the identity of the current hart.
+    pub fn read() -> Self {
+        HartIdentity {
+            mvendorid: Arch::read_csr(Csr::Mvendorid),
+            marchid: Arch::read_csr(Csr::Marchid),
+            mimpid: Arch::read_csr(Csr::Mimpid),
+        }
+    }
+}
+
+// ————————————————————————————————— Errata ————————————————————————————————— //
+
+/// An erratum and the cores it affects.
+struct Erratum {
+    /// Name of the erratum, used for logging.
+    name: &'static str,
+    mvendorid: usize,
+    marchid: usize,
+    /// The affected implementation IDs.
+    mimpid: RangeInclusive<usize>,
+    /// The workarounds required by the erratum.
+    workarounds: Workarounds,
+}
+
+impl Erratum {
+    fn affects(&self, id: &HartIdentity) -> bool {
+        self.mvendorid == id.mvendorid
+            && self.marchid == id.marchid
+            && self.mimpid.contains(&id.mimpid)
+    }
+}
+
+/// The workarounds enabled on a hart.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Workarounds {
+    /// The hart ID passed in a0 by the previous boot stage is not reliable, read mhartid instead.
+    pub hart_id_from_mhartid: bool,
+}
+
+impl Workarounds {
+    /// Returns the workarounds required by the errata affecting the given hart.
+    pub fn detect(id: &HartIdentity) -> Self {
+        active_errata(id).fold(Workarounds::default(), |workarounds, erratum| {
+            workarounds.union(&erratum.workarounds)
+        })
+    }
+
+    /// Returns the ID of the current hart, given the ID passed by the previous boot stage.
+    pub fn hart_id(&self, boot_hart_id: usize) -> usize {
+        if self.hart_id_from_mhartid {
+            Arch::read_csr(Csr::Mhartid)
+        } else {
+            boot_hart_id
+        }
+    }
+
+    fn union(&self, other: &Self) -> Self {
+        Workarounds {
+            hart_id_from_mhartid: self.hart_id_from_mhartid || other.hart_id_from_mhartid,
+        }
+    }
+}
+
+fn active_errata(id: &HartIdentity) -> impl Iterator<Item = &'static Erratum> + '_ {
+    ERRATA.iter().filter(move |erratum| erratum.affects(id))
+}
+
+/// Logs the errata affecting the given hart.
+pub fn log_active(id: &HartIdentity) {
+    for erratum in active_errata(id) {
+        log::info!("Erratum enabled: {}", erratum.name);
+    }
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_errata() {
+        let u74 = HartIdentity {
+            mvendorid: SIFIVE_MVENDORID,
+            marchid: SIFIVE_U7_MARCHID,
+            mimpid: 0x4210427,
+        };
+        assert!(Workarounds::detect(&u74).hart_id_from_mhartid);
+
+        let unknown = HartIdentity {
+            mvendorid: 0,
+            marchid: 0,
+            mimpid: 0,
+        };
+        assert_eq!(Workarounds::detect(&unknown), Workarounds::default());
+        assert_eq!(Workarounds::detect(&unknown).hart_id(3), 3);
+    }
+}
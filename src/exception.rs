@@ -0,0 +1,132 @@
+//! Supervisor Exception Injection
+//!
+//! Miralis sometimes needs to raise an exception in the payload on its own: when the emulation of
+//! an access fails (e.g. a misaligned access to an unmapped page), when a policy denies an access,
+//! or when an exception delegated by the firmware still trapped to Miralis because it might need
+//! emulation. In all those cases the exception is delivered to the payload's supervisor exactly as
+//! the hardware would have done had it been delegated: the cause, trap value, and faulting pc are
+//! reported in `scause`, `stval`, and `sepc`, interrupts are disabled in `sstatus`, and the payload
+//! resumes at the base of `stvec` (exceptions are never vectored).
+//!
+//! Exceptions raised while the payload runs virtualized are delivered to the VS-mode if the
+//! hypervisor delegated them in `hedeleg`, and to the HS-mode otherwise.
+//!
+//! The payload runs natively, its supervisor CSRs therefore live in the physical registers until
+//! the next world switch: the injection must happen while handling a trap from the payload.
+
+use crate::arch::{hstatus, mstatus, Arch, Architecture, Csr, MCause, Mode};
+use crate::virt::VirtContext;
+
+/// Mask of the MODE field of `stvec` and `vstvec`.
+const TVEC_MODE_FILTER: usize = 0b11;
+
+/// Delivers a synchronous exception to the supervisor of the payload.
+///
+/// The exception is reported at the current pc of the payload, with `tval` as trap value. The
+/// context is updated to resume in the supervisor trap handler.
+pub fn inject_payload_exception(ctx: &mut VirtContext, cause: MCause, tval: usize) {
+    debug_assert!(!cause.is_interrupt(), "Only exceptions can be injected");
+    debug_assert!(ctx.mode != Mode::M, "The payload never runs in M-mode");
+    let code = cause as usize;
+    log::trace!(
+        "Injecting {:?} into the payload at 0x{:x} (tval 0x{:x})",
+        cause,
+        ctx.pc,
+        tval
+    );
+
+    // SAFETY: the payload CSRs are loaded in the physical registers while handling one of its
+    // traps, and the trap handler returns to the supervisor through the updated mstatus.MPP.
+    unsafe {
+        if ctx.virtualized && Arch::read_csr(Csr::Hedeleg) & (1 << code) != 0 {
+            let vsstatus = trap_sstatus(Arch::read_csr(Csr::Vsstatus), ctx.mode);
+            Arch::write_csr(Csr::Vsstatus, vsstatus);
+            Arch::write_csr(Csr::Vsepc, ctx.pc);
+            Arch::write_csr(Csr::Vscause, code);
+            Arch::write_csr(Csr::Vstval, tval);
+            ctx.pc = Arch::read_csr(Csr::Vstvec) & !TVEC_MODE_FILTER;
+        } else {
+            if ctx.extensions.has_h_extension {
+                let mut hstatus = Arch::read_csr(Csr::Hstatus);
+                hstatus = hstatus::SPV.set(hstatus, ctx.virtualized as usize);
+                if ctx.virtualized {
+                    hstatus = hstatus::SPVP.set(hstatus, (ctx.mode == Mode::S) as usize);
+                }
+                hstatus = hstatus::GVA.set(hstatus, 0);
+                Arch::write_csr(Csr::Hstatus, hstatus);
+                Arch::write_csr(Csr::Htval, 0);
+                Arch::write_csr(Csr::Htinst, 0);
+                Arch::clear_csr_bits(Csr::Mstatus, mstatus::MPV_FILTER);
+                ctx.virtualized = false;
+            }
+
+            let sstatus = trap_sstatus(Arch::read_csr(Csr::Sstatus), ctx.mode);
+            Arch::write_csr(Csr::Sstatus, sstatus);
+            Arch::write_csr(Csr::Sepc, ctx.pc);
+            Arch::write_csr(Csr::Scause, code);
+            Arch::write_csr(Csr::Stval, tval);
+            ctx.pc = Arch::read_csr(Csr::Stvec) & !TVEC_MODE_FILTER;
+        }
+
+        Arch::set_mpp(Mode::S);
+    }
+    ctx.mode = Mode::S;
+}
+
+/// Returns true if the firmware delegated the exception to the payload's supervisor.
+pub fn is_delegated_to_payload(ctx: &VirtContext, cause: MCause) -> bool {
+    ctx.extensions.has_s_extension
+        && !cause.is_interrupt()
+        && ctx.csr.medeleg & (1 << cause as usize) != 0
+}
+
+/// Returns the value of `sstatus` (or `vsstatus`) after taking a trap from `mode`.
+fn trap_sstatus(sstatus: usize, mode: Mode) -> usize {
+    let sstatus = mstatus::SPIE.set(sstatus, mstatus::SIE.get(sstatus));
+    let sstatus = mstatus::SPP.set(sstatus, (mode == Mode::S) as usize);
+    mstatus::SIE.set(sstatus, 0)
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trap_sstatus_update() {
+        let sstatus = mstatus::SIE_FILTER;
+        let from_user = trap_sstatus(sstatus, Mode::U);
+        assert_eq!(from_user, mstatus::SPIE_FILTER);
+
+        let from_supervisor = trap_sstatus(mstatus::SPIE_FILTER, Mode::S);
+        assert_eq!(from_supervisor, mstatus::SPP_FILTER);
+    }
+
+    #[test]
+    fn inject_exception() {
+        let hw = unsafe { Arch::detect_hardware() };
+        let mut ctx = VirtContext::new(0, hw.available_reg.nb_pmp, hw.extensions.clone());
+        let (stvec, pc, tval) = (0x8020_0101, 0x8040_1234, 0x8060_0003);
+        ctx.mode = Mode::U;
+        ctx.pc = pc;
+        unsafe {
+            Arch::write_csr(Csr::Stvec, stvec);
+            Arch::write_csr(Csr::Sstatus, mstatus::SIE_FILTER);
+        }
+
+        inject_payload_exception(&mut ctx, MCause::LoadAddrMisaligned, tval);
+        assert_eq!(ctx.mode, Mode::S);
+        assert_eq!(ctx.pc, 0x8020_0100, "Exceptions are never vectored");
+        assert_eq!(Arch::read_csr(Csr::Sepc), pc);
+        assert_eq!(
+            Arch::read_csr(Csr::Scause),
+            MCause::LoadAddrMisaligned as usize
+        );
+        assert_eq!(Arch::read_csr(Csr::Stval), tval);
+        let sstatus = Arch::read_csr(Csr::Sstatus);
+        assert_eq!(sstatus & mstatus::SIE_FILTER, 0);
+        assert_ne!(sstatus & mstatus::SPIE_FILTER, 0);
+        assert_eq!(sstatus & mstatus::SPP_FILTER, 0);
+    }
+}
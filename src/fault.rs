@@ -0,0 +1,119 @@
+//! Fault Injection
+//!
+//! When built with the `fault_injection` feature, Miralis injects faults at a few points of the
+//! virtualization layer to test the robustness of the firmware and of Miralis's own recovery
+//! paths:
+//!
+//! - Flipping a random bit of the CSR values read by the firmware.
+//! - Delaying the injection of a virtual interrupt to a later exit.
+//! - Skipping the cache flush after a PMP update.
+//!
+//! Each fault is injected with a configurable probability, expressed in parts per million. The
+//! decisions are driven by a pseudo-random generator initialized from the configured seed, such
+//! that a failing run can be replayed. Without the feature the injection points are no-ops.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::config;
+
+/// Whether fault injection is compiled in.
+const ENABLED: bool = cfg!(feature = "fault_injection");
+
+/// The faults that can be injected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Flip a bit of a CSR value read by the firmware.
+    CsrBitFlip,
+    /// Delay the injection of a pending virtual interrupt.
+    InterruptDelay,
+    /// Do not flush the caches after a PMP update.
+    PmpFlushFailure,
+}
+
+impl Fault {
+    /// Probability of injecting the fault, in parts per million.
+    const fn probability(self) -> usize {
+        match self {
+            Fault::CsrBitFlip => config::FAULT_INJECTION_CSR_FLIP_PPM,
+            Fault::InterruptDelay => config::FAULT_INJECTION_INTERRUPT_DELAY_PPM,
+            Fault::PmpFlushFailure => config::FAULT_INJECTION_PMP_FLUSH_PPM,
+        }
+    }
+}
+
+/// State of the pseudo-random generator, shared by all harts.
+static STATE: AtomicUsize = AtomicUsize::new(config::FAULT_INJECTION_SEED | 1);
+
+/// A xorshift step, the state must never be zero.
+const fn xorshift(mut x: usize) -> usize {
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    x
+}
+
+fn next_random() -> usize {
+    let previous = STATE
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |x| Some(xorshift(x)))
+        .unwrap();
+    xorshift(previous)
+}
+
+/// Returns true if the fault must be injected at this point.
+#[inline]
+pub fn inject(fault: Fault) -> bool {
+    if !ENABLED || fault.probability() == 0 {
+        return false;
+    }
+
+    let injected = next_random() % 1_000_000 < fault.probability();
+    if injected {
+        log::debug!("Injecting fault: {:?}", fault);
+    }
+    injected
+}
+
+/// Returns the CSR value to expose to the firmware, with a bit flipped if a fault is injected.
+#[inline]
+pub fn csr_read(value: usize) -> usize {
+    if inject(Fault::CsrBitFlip) {
+        value ^ (1 << (next_random() % usize::BITS as usize))
+    } else {
+        value
+    }
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generator_is_deterministic() {
+        let mut a = 1;
+        let mut b = 1;
+        for _ in 0..1000 {
+            a = xorshift(a);
+            b = xorshift(b);
+            assert_eq!(a, b);
+            assert_ne!(a, 0);
+        }
+    }
+
+    #[test]
+    fn no_fault_without_probability() {
+        for fault in [
+            Fault::CsrBitFlip,
+            Fault::InterruptDelay,
+            Fault::PmpFlushFailure,
+        ] {
+            if !ENABLED || fault.probability() == 0 {
+                assert!(!inject(fault));
+            }
+        }
+        if !ENABLED {
+            assert_eq!(csr_read(0x42), 0x42);
+        }
+    }
+}
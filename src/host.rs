@@ -1,11 +1,56 @@
 //! Host (Miralis) Context
 //!
-//! This module exposes the host context as [MiralisCtx], which holds Miralis's own configuration registers.
+//! This module exposes the host context, split in two parts with distinct ownership:
+//!
+//! - The [SharedContext] holds the state common to all harts. It is initialized once during boot
+//!   and is either immutable or protected by a lock afterward.
+//! - The [MiralisContext] holds the state of a single hart, such as its PMP configuration and
+//!   hardware capabilities. It is owned by the hart and accessed without synchronization.
+
+use spin::{Mutex, Once};
 
+use crate::arch::pmp::pmplayout::POLICY_SIZE;
 use crate::arch::pmp::PmpGroup;
 use crate::arch::HardwareCapability;
-use crate::device;
-use crate::platform::{Plat, Platform};
+use crate::decoder::DecodeCache;
+use crate::device::registry::DeviceRegistry;
+
+/// The shared context, initialized by the first hart creating its Miralis context.
+static SHARED_CONTEXT: Once<SharedContext> = Once::new();
+
+// ————————————————————————————— Shared Context ————————————————————————————— //
+
+/// A memory region protected by the policy, backed by one of the policy PMP entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PolicyRegion {
+    pub start: usize,
+    pub size: usize,
+}
+
+/// The state shared by all harts.
+pub struct SharedContext {
+    /// The virtual devices, protected with PMP. Immutable after boot.
+    pub devices: DeviceRegistry,
+    /// The memory regions the policy protects on all harts.
+    ///
+    /// Harts install the regions in their own PMP configuration, a hart updating the regions
+    /// must notify the others (e.g. with a policy interrupt).
+    pub policy_regions: Mutex<[Option<PolicyRegion>; POLICY_SIZE]>,
+}
+
+impl SharedContext {
+    /// Returns the shared context, initializing it on first call.
+    ///
+    /// The platform must be initialized before the first call.
+    pub fn get() -> &'static SharedContext {
+        SHARED_CONTEXT.call_once(|| SharedContext {
+            devices: DeviceRegistry::from_platform(),
+            policy_regions: Mutex::new([None; POLICY_SIZE]),
+        })
+    }
+}
+
+// ———————————————————————————— Per-Hart Context ———————————————————————————— //
 
 /// The Miralis Context, holding configuration registers for Miralis.
 pub struct MiralisContext {
@@ -13,17 +58,25 @@ pub struct MiralisContext {
     pub pmp: PmpGroup,
     /// Hardware capabilities of the core (hart).
     pub hw: HardwareCapability,
-    /// List of device with PMP
-    pub devices: [device::VirtDevice; 2],
+    /// The state shared with the other harts.
+    pub shared: &'static SharedContext,
+    /// The recently decoded firmware instructions.
+    pub decode_cache: DecodeCache,
 }
 
 impl MiralisContext {
     /// Creates a new Miralis context with default values.
     pub fn new(hw: HardwareCapability) -> Self {
+        let shared = SharedContext::get();
+        let mut pmp = PmpGroup::init_pmp_group(hw.available_reg.nb_pmp, &shared.devices);
+        if hw.extensions.has_smepmp_extension {
+            pmp.enable_smepmp();
+        }
         Self {
-            pmp: PmpGroup::init_pmp_group(hw.available_reg.nb_pmp),
+            pmp,
             hw,
-            devices: Plat::create_virtual_devices(),
+            shared,
+            decode_cache: DecodeCache::new(),
         }
     }
 }
@@ -1,11 +1,38 @@
 //! Structured logging implementation
+//!
+//! In addition to the global log level, the log level of individual modules can be set with a
+//! filter specification such as `virt=debug,ace=info,policy=trace`, where modules are relative to
+//! the `miralis` crate and a directive without module sets the global level. The most specific
+//! filter matching a module applies. The initial filters come from the configuration, and can be
+//! updated at runtime with the `MIRALIS_LOG_FILTER_FID` call of the Miralis ABI when the `debug`
+//! feature is enabled, which avoids rebuilding Miralis to debug on hardware.
 
+use core::str::FromStr;
 use core::sync::atomic::{AtomicBool, Ordering};
 
 use log::{Level, LevelFilter, Metadata, Record};
+use spin::RwLock;
 
+use crate::arch::{parse_mpp_return_mode, Arch, Architecture, Csr, Register};
 use crate::config;
-use crate::platform::{Plat, Platform};
+use crate::platform::{self, Plat, Platform};
+use crate::virt::{RegisterContextGetter, RegisterContextSetter, VirtContext};
+
+/// Maximum number of per-module filters.
+const MAX_FILTERS: usize = 16;
+
+/// Maximum length of the module of a filter, in bytes.
+const MAX_MODULE_LEN: usize = 48;
+
+/// Maximum size of a filter specification passed at runtime, in bytes.
+const MAX_SPEC_SIZE: usize = 256;
+
+const SBI_ERR_NOT_SUPPORTED: isize = -2;
+const SBI_ERR_INVALID_PARAM: isize = -3;
+const SBI_ERR_INVALID_ADDRESS: isize = -5;
+
+/// The active log filters, shared by all harts.
+static FILTERS: RwLock<Filters> = RwLock::new(Filters::new());
 
 // ————————————————————————————————— Logger ————————————————————————————————— //
 
@@ -52,15 +79,19 @@ impl Logger {
         specific_module_enabled
     }
 
-    fn filter_by_global_level(&self, metadata: &Metadata) -> bool {
-        Self::GLOBAL_LOG_LEVEL >= metadata.level()
+    fn filter_by_level(&self, metadata: &Metadata) -> bool {
+        if platform::is_panic_context() {
+            // The filters might be locked by the panicking hart, only errors are logged
+            return metadata.level() <= Level::Error;
+        }
+        FILTERS.read().level_for(metadata.target()) >= metadata.level()
     }
 }
 
 impl log::Log for Logger {
     fn enabled(&self, metadata: &Metadata) -> bool {
         // We filter depending on the current log level.
-        self.filter_by_global_level(metadata) || self.filter_by_module(metadata)
+        self.filter_by_level(metadata) || self.filter_by_module(metadata)
     }
 
     fn log(&self, record: &Record) {
@@ -74,7 +105,12 @@ impl log::Log for Logger {
                 Plat::debug_print(
                     record.level(),
                     format_args!(
-                        "[{} | {}] {}\n",
+                        "{}[{} | {}] {}\n",
+                        if config::LOG_CONSOLE_TAGS {
+                            "[Miralis] "
+                        } else {
+                            ""
+                        },
                         level_display(record.level()),
                         record.target(),
                         record.args()
@@ -93,6 +129,12 @@ pub fn init() {
         Ok(_) => {
             log::set_logger(&Logger {}).unwrap();
             log::set_max_level(LevelFilter::Trace);
+            if let Some(spec) = config::LOG_FILTERS {
+                let result = FILTERS.write().apply(spec);
+                if let Err(err) = result {
+                    log::warn!("Invalid log filters '{}': {}", spec, err);
+                }
+            }
         }
         Err(_) => {
             log::warn!("Logger is already initialized, skipping init");
@@ -100,6 +142,148 @@ pub fn init() {
     };
 }
 
+// ———————————————————————————————— Filters ————————————————————————————————— //
+
+/// The log level of a module and its sub-modules.
+#[derive(Clone, Copy, Debug)]
+struct Filter {
+    module: [u8; MAX_MODULE_LEN],
+    len: usize,
+    level: LevelFilter,
+}
+
+impl Filter {
+    fn module(&self) -> &str {
+        // SAFETY: the module is copied from a valid string slice.
+        unsafe { core::str::from_utf8_unchecked(&self.module[..self.len]) }
+    }
+
+    /// Returns true if the filter applies to the target, which is relative to the `miralis` crate.
+    fn matches(&self, target: &str) -> bool {
+        target
+            .strip_prefix(self.module())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+    }
+}
+
+/// The global log level and the per-module filters.
+#[derive(Clone, Copy, Debug)]
+struct Filters {
+    global: LevelFilter,
+    filters: [Option<Filter>; MAX_FILTERS],
+}
+
+impl Filters {
+    const fn new() -> Self {
+        Filters {
+            global: Logger::GLOBAL_LOG_LEVEL,
+            filters: [None; MAX_FILTERS],
+        }
+    }
+
+    /// Applies a comma-separated list of `module=level` or `level` directives.
+    ///
+    /// Filters for new modules are added, while existing ones are updated. The filters are left
+    /// unchanged if any of the directives is invalid.
+    fn apply(&mut self, spec: &str) -> Result<(), &'static str> {
+        let mut updated = *self;
+        for directive in spec.split(',').map(str::trim) {
+            if directive.is_empty() {
+                continue;
+            }
+            match directive.split_once('=') {
+                Some((module, level)) => updated.set(module.trim(), parse_level(level.trim())?)?,
+                None => updated.global = parse_level(directive)?,
+            }
+        }
+        *self = updated;
+        Ok(())
+    }
+
+    fn set(&mut self, module: &str, level: LevelFilter) -> Result<(), &'static str> {
+        let module = module.strip_prefix("miralis::").unwrap_or(module);
+        if module.is_empty() || module.len() > MAX_MODULE_LEN {
+            return Err("invalid module");
+        }
+        if let Some(filter) = self
+            .filters
+            .iter_mut()
+            .flatten()
+            .find(|filter| filter.module() == module)
+        {
+            filter.level = level;
+            return Ok(());
+        }
+
+        let slot = self
+            .filters
+            .iter_mut()
+            .find(|filter| filter.is_none())
+            .ok_or("too many filters")?;
+        let mut filter = Filter {
+            module: [0; MAX_MODULE_LEN],
+            len: module.len(),
+            level,
+        };
+        filter.module[..module.len()].copy_from_slice(module.as_bytes());
+        *slot = Some(filter);
+        Ok(())
+    }
+
+    /// Returns the level of the most specific filter matching the target, or the global level.
+    fn level_for(&self, target: &str) -> LevelFilter {
+        let target = target.strip_prefix("miralis::").unwrap_or(target);
+        self.filters
+            .iter()
+            .flatten()
+            .filter(|filter| filter.matches(target))
+            .max_by_key(|filter| filter.len)
+            .map_or(self.global, |filter| filter.level)
+    }
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, &'static str> {
+    LevelFilter::from_str(level).map_err(|_| "invalid level")
+}
+
+/// Handles a request to update the log filters, the arguments are the address and length of a
+/// filter specification.
+pub fn handle_set_filters(ctx: &mut VirtContext) {
+    let addr = ctx.get(Register::X10);
+    let size = ctx.get(Register::X11);
+
+    let error = if !cfg!(feature = "debug") {
+        SBI_ERR_NOT_SUPPORTED
+    } else if size > MAX_SPEC_SIZE {
+        SBI_ERR_INVALID_PARAM
+    } else {
+        let mut buffer = [0u8; MAX_SPEC_SIZE];
+        let bytes = &mut buffer[..size];
+        // SAFETY: the specification is read with the privileges of the caller, which is the mode
+        // saved in mstatus.MPP by the trap.
+        let mode = parse_mpp_return_mode(Arch::read_csr(Csr::Mstatus));
+        match unsafe { Arch::read_bytes_from_mode(addr as *const u8, bytes, mode) } {
+            Err(()) => SBI_ERR_INVALID_ADDRESS,
+            Ok(()) => {
+                let result = core::str::from_utf8(bytes)
+                    .map_err(|_| "not utf-8")
+                    .and_then(|spec| FILTERS.write().apply(spec));
+                match result {
+                    Ok(()) => 0,
+                    Err(err) => {
+                        log::warn!("Invalid log filters: {}", err);
+                        SBI_ERR_INVALID_PARAM
+                    }
+                }
+            }
+        }
+    };
+
+    ctx.set(Register::X10, error as usize);
+    ctx.set(Register::X11, 0);
+    ctx.pc += 4;
+}
+
 // ————————————————————————————————— Utils —————————————————————————————————— //
 
 fn level_display(level: Level) -> &'static str {
@@ -123,9 +307,13 @@ fn level_display(level: Level) -> &'static str {
     }
 }
 
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
 #[cfg(test)]
 mod tests {
-    use crate::logger::Logger;
+    use log::LevelFilter;
+
+    use crate::logger::{Filters, Logger, MAX_FILTERS};
 
     #[test]
     fn test_in_list() {
@@ -137,4 +325,35 @@ mod tests {
         assert!(Logger::contains_target(&["car", "train", "boat"], "train"));
         assert!(Logger::contains_target(&["car", "train", "boat"], "boat"));
     }
+
+    #[test]
+    fn module_filters() {
+        let mut filters = Filters::new();
+        filters
+            .apply("warn, virt=debug,virt::emulator=trace,miralis::policy=off")
+            .unwrap();
+        assert_eq!(filters.level_for("miralis::main"), LevelFilter::Warn);
+        assert_eq!(filters.level_for("miralis::virt"), LevelFilter::Debug);
+        assert_eq!(filters.level_for("miralis::virt::csr"), LevelFilter::Debug);
+        assert_eq!(
+            filters.level_for("miralis::virt::emulator"),
+            LevelFilter::Trace
+        );
+        assert_eq!(filters.level_for("miralis::virtio"), LevelFilter::Warn);
+        assert_eq!(filters.level_for("miralis::policy::ace"), LevelFilter::Off);
+
+        // Existing filters are updated
+        filters.apply("virt=error").unwrap();
+        assert_eq!(filters.level_for("miralis::virt::csr"), LevelFilter::Error);
+
+        // Invalid specifications leave the filters unchanged
+        assert!(filters.apply("ace=info,virt=loud").is_err());
+        assert_eq!(filters.level_for("miralis::ace"), LevelFilter::Warn);
+        assert!(filters.apply("=info").is_err());
+
+        let too_many =
+            (0..MAX_FILTERS).fold(String::new(), |spec, i| spec + &format!("m{}=info,", i));
+        assert!(filters.apply(&too_many).is_err());
+        assert_eq!(filters.level_for("miralis::m0"), LevelFilter::Warn);
+    }
 }
@@ -15,32 +15,59 @@
     asm
 )]
 
+// The heap is only used by the ACE security monitor.
+#[cfg(feature = "ace")]
 extern crate alloc;
 
+#[cfg(feature = "ace")]
 mod ace;
 mod arch;
+mod audit;
 mod benchmark;
+mod build_info;
+mod capabilities;
 mod config;
+mod counters;
+mod coverage;
 mod debug;
 mod decoder;
+mod deferred;
 mod device;
 mod device_tree;
 mod driver;
+mod errata;
+mod exception;
+mod fault;
 mod host;
 mod logger;
+mod memory_layout;
+mod misaligned;
+#[cfg(feature = "ace")]
 mod monitor_switch;
+mod panic_report;
+mod pmp_view;
 mod platform;
 mod policy;
+mod sbi;
+mod semihosting;
+mod suspend;
+mod timebase;
+mod timer;
+mod trace;
 mod utils;
+mod vendor;
 mod virt;
 
 use core::arch::asm;
 use log::__private_api::log;
 use log::info;
 use arch::{Arch, Architecture};
-use benchmark::{Benchmark, Counter, Scope};
+use audit::AuditEvent;
+use benchmark::{histogram, Benchmark, Counter, Scope};
 use config::PLATFORM_NAME;
-use platform::{init, Plat, Platform};
+use device::status::{self, ResetReason};
+use errata::{HartIdentity, Workarounds};
+use platform::{init, init_hart, Plat, Platform};
 use policy::{Policy, PolicyModule};
 
 // Defined in the linker script
@@ -77,35 +104,56 @@ use crate::virt::{
 
 use crate::config::DELEGATE_PERF_COUNTER;
 
-pub(crate) extern "C" fn main(_hart_id: usize, device_tree_blob_addr: usize) -> ! {
-    // On the VisionFive2 board there is an issue with a hart_id
-    // Identification, so we have to reassign it for now
-    let hart_id = Arch::read_csr(Csr::Mhartid);
-
-    init();
-    log::info!("Hello, world!");
-    log::info!("Platform name: {}", Plat::name());
-    log::info!("Policy module: {}", Policy::name());
-    log::info!("Hart ID: {}", hart_id);
-    log::debug!("misa:    0x{:x}", Arch::read_csr(Csr::Misa));
-    log::debug!(
-        "vmisa:   0x{:x}",
-        Arch::read_csr(Csr::Misa) & !misa::DISABLED
-    );
-    log::debug!("mstatus: 0x{:x}", Arch::read_csr(Csr::Mstatus));
-    log::info!("DTS address: 0x{:x}", device_tree_blob_addr);
-
-    log::info!("Preparing jump into firmware");
-    let firmware_addr = Plat::load_firmware();
-    log::debug!("Firmware loaded at: {:x}", firmware_addr);
+pub(crate) extern "C" fn main(boot_hart_id: usize, device_tree_blob_addr: usize) -> ! {
+    let hart_identity = HartIdentity::read();
+    let hart_id = Workarounds::detect(&hart_identity).hart_id(boot_hart_id);
+
+    let firmware_addr = match platform::firmware_address() {
+        // The hart is brought online after boot: the platform is already initialized and the
+        // firmware loaded, only the per-hart state needs to be set up.
+        Some(firmware_addr) => {
+            init_hart();
+            status::record_reset(ResetReason::HartRestart, hart_id);
+            log::info!("Hart {} brought online after boot", hart_id);
+            firmware_addr
+        }
+        None if platform::is_boot_hart(hart_id) => {
+            let firmware_addr = boot(hart_id, &hart_identity, device_tree_blob_addr);
+            platform::wake_secondary_harts(hart_id);
+            firmware_addr
+        }
+        // Secondary harts wait for the boot hart to finish the global initialization
+        None => {
+            init_hart();
+            let firmware_addr = platform::park_secondary_hart(hart_id);
+            log::info!("Hart {} woken up", hart_id);
+            firmware_addr
+        }
+    };
 
     // Detect hardware capabilities
     // SAFETY: this must happen before hardware initialization
     let hw = unsafe { Arch::detect_hardware() };
     // Initialize Miralis's own context
     let mut mctx = MiralisContext::new(hw);
+    if let Err(err) = capabilities::check_requirements(&mctx) {
+        log::error!("Unsupported hardware: {}", err);
+        Plat::exit_failure();
+    }
+    coverage::init(&mctx);
 
     let mut policy: Policy = Policy::init(&mut mctx, device_tree_blob_addr);
+    // The policy might have reserved PMP entries, the layout is final from now on
+    mctx.pmp.freeze_layout();
+    if !platform::check_virt_pmp_consistency(mctx.pmp.nb_virt_pmp) {
+        log::error!(
+            "Hart {} has {} virtual PMP entries, which differs from other harts",
+            hart_id,
+            mctx.pmp.nb_virt_pmp
+        );
+        Plat::exit_failure();
+    }
+    capabilities::log_report(&mctx);
 
     // Initialize the virtual context and configure architecture
     let mut ctx = VirtContext::new(hart_id, mctx.pmp.nb_virt_pmp, mctx.hw.extensions.clone());
@@ -117,7 +165,10 @@ pub(crate) extern "C" fn main(_hart_id: usize, device_tree_blob_addr: usize) ->
 
         // Configure the firmware context
         ctx.set(Register::X10, hart_id);
-        ctx.set(Register::X11, device_tree_blob_addr);
+        ctx.set(
+            Register::X11,
+            device_tree::firmware_device_tree(device_tree_blob_addr, &policy),
+        );
         ctx.set_csr(
             Csr::Misa,
             Arch::read_csr(Csr::Misa) & !misa::DISABLED,
@@ -139,21 +190,52 @@ pub(crate) extern "C" fn main(_hart_id: usize, device_tree_blob_addr: usize) ->
         Plat::exit_success();
     }
 
+    if !platform::mark_hart_online(hart_id) {
+        log::warn!("Hart {} was already online", hart_id);
+    }
     main_loop(&mut ctx, &mut mctx, &mut policy);
 }
 
+/// Initialize the platform and load the firmware, returns the address of the firmware.
+fn boot(hart_id: usize, hart_identity: &HartIdentity, device_tree_blob_addr: usize) -> usize {
+    init(device_tree_blob_addr);
+    log::info!("Hello, world!");
+    for line in build_info::as_str().lines() {
+        log::info!("Build {}", line);
+    }
+    log::info!("Hart ID: {}", hart_id);
+    errata::log_active(hart_identity);
+    log::info!("DTS address: 0x{:x}", device_tree_blob_addr);
+
+    log::info!("Preparing jump into firmware");
+    let firmware_addr = Plat::load_firmware();
+    log::debug!("Firmware loaded at: {:x}", firmware_addr);
+    status::record_reset(ResetReason::ColdBoot, hart_id);
+    platform::publish_firmware_address(firmware_addr);
+
+    firmware_addr
+}
+
 fn main_loop(ctx: &mut VirtContext, mctx: &mut MiralisContext, policy: &mut Policy) -> ! {
     loop {
+        deferred::drain(ctx, mctx, policy);
+
         Benchmark::start_interval_counters(Scope::RunVCPU);
 
+        trace::enter_vcpu(ctx);
+        ctx.counters.leave_miralis();
         unsafe {
             Arch::run_vcpu(ctx);
         }
+        ctx.counters.enter_miralis();
+        trace::exit_vcpu(ctx);
 
         Benchmark::stop_interval_counters(Scope::RunVCPU);
         Benchmark::start_interval_counters(Scope::HandleTrap);
 
+        let (trap_mode, trap_start) = (ctx.mode, debug::trap_log_timestamp());
         handle_trap(ctx, mctx, policy);
+        debug::record_trap(ctx, trap_mode, trap_start);
 
         Benchmark::stop_interval_counters(Scope::HandleTrap);
         Benchmark::increment_counter(Counter::TotalExits);
@@ -170,6 +252,7 @@ fn handle_trap(ctx: &mut VirtContext, mctx: &mut MiralisContext, policy: &mut Po
     if let Some(max_exit) = config::MAX_FIRMWARE_EXIT {
         if ctx.nb_exits + 1 >= max_exit {
             log::error!("Reached maximum number of exits: {}", ctx.nb_exits);
+            coverage::dump();
             Plat::exit_failure();
         }
     }
@@ -182,9 +265,12 @@ fn handle_trap(ctx: &mut VirtContext, mctx: &mut MiralisContext, policy: &mut Po
 
     // Perform emulation
     let exec_mode = ctx.mode.to_exec_mode();
+    let start = histogram::start_trap_latency();
 
     // Keep track of the number of exit
     ctx.nb_exits += 1;
+    status::record_exit(ctx.hart_id, exec_mode);
+    coverage::record_trap(exec_mode, ctx.trap_info.mcause);
     match exec_mode {
         ExecutionMode::Firmware => ctx.handle_firmware_trap(mctx, policy),
         ExecutionMode::Payload => ctx.handle_payload_trap(mctx, policy),
@@ -198,6 +284,9 @@ fn handle_trap(ctx: &mut VirtContext, mctx: &mut MiralisContext, policy: &mut Po
         Benchmark::increment_counter(Counter::WorldSwitches);
     }
 
+    // Apply posted device writes, which might raise interrupts
+    ctx.drain_deferred_effects();
+
     // Inject interrupts if required
     ctx.check_and_inject_interrupts();
 
@@ -209,7 +298,10 @@ fn handle_trap(ctx: &mut VirtContext, mctx: &mut MiralisContext, policy: &mut Po
                 ctx.trap_info.get_cause()
             );
             unsafe { ctx.switch_from_firmware_to_payload(mctx) };
-            policy.switch_from_firmware_to_payload(ctx, mctx);
+            Benchmark::in_scope(Scope::PolicyHook, || {
+                policy.switch_from_firmware_to_payload(ctx, mctx)
+            });
+            audit::record(ctx.hart_id, AuditEvent::WorldSwitch(ExecutionMode::Payload));
 
             unsafe {
                 // Commit the PMP to hardware
@@ -222,7 +314,10 @@ fn handle_trap(ctx: &mut VirtContext, mctx: &mut MiralisContext, policy: &mut Po
                 ctx.trap_info.get_cause()
             );
             unsafe { ctx.switch_from_payload_to_firmware(mctx) };
-            policy.switch_from_payload_to_firmware(ctx, mctx);
+            Benchmark::in_scope(Scope::PolicyHook, || {
+                policy.switch_from_payload_to_firmware(ctx, mctx)
+            });
+            audit::record(ctx.hart_id, AuditEvent::WorldSwitch(ExecutionMode::Firmware));
 
             unsafe {
                 // Commit the PMP to hardware
@@ -231,6 +326,8 @@ fn handle_trap(ctx: &mut VirtContext, mctx: &mut MiralisContext, policy: &mut Po
         }
         _ => {} // No execution mode transition
     }
+
+    histogram::record_trap_latency(start, exec_mode, ctx.mode.to_exec_mode());
 }
 
 /// Handle the trap coming from miralis
@@ -249,8 +346,10 @@ fn handle_miralis_trap(ctx: &mut VirtContext) {
 #[panic_handler]
 #[cfg(not(test))]
 fn panic(info: &core::panic::PanicInfo) -> ! {
+    platform::enter_panic_context();
     log::error!("Panicked at {:#?} ", info);
     unsafe { debug::log_stack_usage() };
+    debug::dump_trap_log(log::Level::Error);
     Plat::exit_failure();
 }
 
@@ -367,7 +466,7 @@ mod tests {
     fn handle_trap_state() {
         let hw = unsafe { Arch::detect_hardware() };
         let mut mctx = MiralisContext::new(hw);
-        let mut policy = Policy::init(0x0);
+        let mut policy = Policy::init(&mut mctx, 0);
         let mut ctx = VirtContext::new(0, mctx.hw.available_reg.nb_pmp, mctx.hw.extensions.clone());
 
         // Firmware is running
@@ -400,10 +499,59 @@ mod tests {
         assert_eq!(ctx.csr.mie, 1, "mie must not change");
         assert_eq!(ctx.csr.mideleg, 0, "mideleg must not change");
         assert_eq!(ctx.csr.mepc, 0x80200042);
+        assert_eq!(ctx.csr.mtval, 0x80200042, "mtval must hold the breakpoint address");
         assert_eq!(
             (ctx.csr.mstatus & mstatus::MPIE_FILTER) >> mstatus::MPIE_OFFSET,
             0b1,
             "mstatus.MPIE must be set to trap_info.mstatus.MPIE"
         );
     }
+
+    /// Runs a small firmware end to end: the instructions are interpreted by the userspace
+    /// architecture, and the traps handled by Miralis as on the hardware.
+    #[test]
+    fn firmware_end_to_end() {
+        let firmware: [u32; 16] = [
+            0x00000297, // auipc t0, 0
+            0x02c28293, // addi t0, t0, 44
+            0x30529073, // csrw mtvec, t0
+            0x04200513, // li a0, 0x42
+            0x34051073, // csrw mscratch, a0
+            0x340025f3, // csrr a1, mscratch
+            0x00100073, // ebreak
+            0x342026f3, // csrr a3, mcause
+            0x34002773, // done: csrr a4, mscratch
+            0x0000006f, // j .
+            0x00000013, // nop
+            0x34102373, // handler: csrr t1, mepc
+            0x00430313, // addi t1, t1, 4
+            0x34131073, // csrw mepc, t1
+            0x00160613, // addi a2, a2, 1
+            0x30200073, // mret
+        ];
+        let done = &firmware[8] as *const u32 as usize;
+
+        let hw = unsafe { Arch::detect_hardware() };
+        let mut mctx = MiralisContext::new(hw);
+        let mut policy = Policy::init(&mut mctx, 0);
+        let mut ctx = VirtContext::new(0, mctx.hw.available_reg.nb_pmp, mctx.hw.extensions.clone());
+        ctx.mode = Mode::M;
+        ctx.pc = firmware.as_ptr() as usize;
+
+        for _ in 0..16 {
+            unsafe { Arch::run_vcpu(&mut ctx) };
+            handle_trap(&mut ctx, &mut mctx, &mut policy);
+            if ctx.trap_info.mepc == done {
+                break;
+            }
+        }
+
+        assert_eq!(ctx.pc, done + 4, "the firmware must reach the end");
+        assert_eq!(ctx.mode, Mode::M);
+        assert_eq!(ctx.csr.mtvec, &firmware[11] as *const u32 as usize);
+        assert_eq!(ctx.regs[11], 0x42, "mscratch must be emulated");
+        assert_eq!(ctx.regs[12], 1, "the breakpoint must be handled by the firmware");
+        assert_eq!(ctx.regs[13], MCause::Breakpoint as usize);
+        assert_eq!(ctx.regs[14], 0x42);
+    }
 }
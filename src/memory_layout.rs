@@ -0,0 +1,142 @@
+//! Memory Layout
+//!
+//! The firmware and the payload can query the effective memory layout with the
+//! `MIRALIS_MEMORY_LAYOUT_FID` call of the Miralis ABI: the memory used by Miralis, the load
+//! addresses of the firmware and of the payload, and the memory reserved by the policy (e.g.
+//! confidential memory). This lets guests reserve those regions themselves, rather than relying on
+//! device tree patching which some bootloaders ignore.
+
+use core::mem::size_of;
+
+use miralis_core::abi::memory_layout::{self, MemoryRegion};
+
+use crate::arch::{parse_mpp_return_mode, Arch, Architecture, Csr, Register};
+use crate::config::TARGET_PAYLOAD_ADDRESS;
+use crate::platform::{self, Plat, Platform};
+use crate::policy::{Policy, PolicyModule};
+use crate::virt::{RegisterContextGetter, RegisterContextSetter, VirtContext};
+
+/// Maximum number of regions in the layout.
+const MAX_REGIONS: usize = 4;
+
+const SBI_ERR_INVALID_ADDRESS: isize = -5;
+
+/// The memory regions, in the order in which they are reported.
+struct Layout {
+    regions: [MemoryRegion; MAX_REGIONS],
+    len: usize,
+}
+
+impl Layout {
+    /// Builds the layout from the start and size of each region.
+    ///
+    /// The size of the firmware is not known, it is assumed to extend up to the payload if the
+    /// payload is loaded after it.
+    fn new(
+        miralis: (usize, usize),
+        firmware_addr: Option<usize>,
+        payload_addr: usize,
+        reserved: Option<(usize, usize)>,
+    ) -> Self {
+        let mut layout = Layout {
+            regions: [MemoryRegion {
+                start: 0,
+                size: 0,
+                kind: 0,
+            }; MAX_REGIONS],
+            len: 0,
+        };
+
+        layout.push(memory_layout::MIRALIS, miralis.0, miralis.1);
+        if let Some(firmware_addr) = firmware_addr {
+            let size = payload_addr.saturating_sub(firmware_addr);
+            layout.push(memory_layout::FIRMWARE, firmware_addr, size);
+        }
+        layout.push(memory_layout::PAYLOAD, payload_addr, 0);
+        if let Some((start, size)) = reserved {
+            layout.push(memory_layout::RESERVED, start, size);
+        }
+
+        layout
+    }
+
+    fn push(&mut self, kind: u64, start: usize, size: usize) {
+        self.regions[self.len] = MemoryRegion {
+            start: start as u64,
+            size: size as u64,
+            kind,
+        };
+        self.len += 1;
+    }
+}
+
+/// Handles a request to copy the memory layout.
+///
+/// The arguments are the address of the destination buffer and its capacity in regions. The buffer
+/// is written with the privileges of the caller, and the total number of regions is returned in
+/// a1 even if they do not all fit in the buffer.
+pub fn handle_query(ctx: &mut VirtContext, policy: &Policy) {
+    let addr = ctx.get(Register::X10);
+    let capacity = ctx.get(Register::X11);
+
+    let mut layout = Layout::new(
+        Plat::get_miralis_memory_start_and_size(),
+        platform::firmware_address(),
+        TARGET_PAYLOAD_ADDRESS,
+        policy.reserved_memory(),
+    );
+    let count = capacity.min(layout.len);
+    // SAFETY: the regions are plain data, and the slice covers `count` of them.
+    let bytes = unsafe {
+        core::slice::from_raw_parts_mut(
+            layout.regions.as_mut_ptr() as *mut u8,
+            count * size_of::<MemoryRegion>(),
+        )
+    };
+
+    // SAFETY: the bytes are stored with the privileges of the caller, which is the mode saved in
+    // mstatus.MPP by the trap.
+    let mode = parse_mpp_return_mode(Arch::read_csr(Csr::Mstatus));
+    let error = match unsafe { Arch::store_bytes_from_mode(bytes, addr as *const u8, mode) } {
+        Ok(()) => 0,
+        Err(()) => SBI_ERR_INVALID_ADDRESS,
+    };
+
+    ctx.set(Register::X10, error as usize);
+    ctx.set(Register::X11, layout.len);
+    ctx.pc += 4;
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_layout() {
+        let layout = Layout::new(
+            (0x80000000, 0x100000),
+            Some(0x80200000),
+            0x80400000,
+            Some((0xc0000000, 0x40000000)),
+        );
+        assert_eq!(layout.len, 4);
+        assert_eq!(
+            layout
+                .regions
+                .map(|region| (region.kind, region.start, region.size)),
+            [
+                (memory_layout::MIRALIS, 0x80000000, 0x100000),
+                (memory_layout::FIRMWARE, 0x80200000, 0x200000),
+                (memory_layout::PAYLOAD, 0x80400000, 0),
+                (memory_layout::RESERVED, 0xc0000000, 0x40000000),
+            ]
+        );
+
+        // The firmware size is unknown if the payload is loaded before it
+        let layout = Layout::new((0x80000000, 0x100000), Some(0x80400000), 0x80200000, None);
+        assert_eq!(layout.len, 3);
+        assert_eq!(layout.regions[1].size, 0);
+    }
+}
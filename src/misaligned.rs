@@ -0,0 +1,126 @@
+//! Misaligned Access Emulation
+//!
+//! Some cores do not support misaligned loads and stores in hardware and raise an address
+//! misaligned exception instead, which the firmware is expected to fix up by performing the access
+//! byte by byte. When enabled (see the `vcpu.emulate_misaligned` configuration, which defaults to
+//! the platform's [TRAPS_MISALIGNED_ACCESSES](crate::platform::Platform::TRAPS_MISALIGNED_ACCESSES))
+//! Miralis performs the access on behalf of the trapping world and resumes it, sparing a trip
+//! through the firmware trap handler.
+//!
+//! The access is performed with the privileges and address translation of the trapping mode, it is
+//! therefore subject to the same PMP and page table checks as the original access. Accesses that
+//! can not be emulated (e.g. floating point or atomic accesses, or accesses that fault) are
+//! handled as usual.
+
+use crate::arch::{parse_mpp_return_mode, Arch, Architecture, Mode};
+use crate::decoder::Instr;
+use crate::host::MiralisContext;
+use crate::utils::extend_load;
+use crate::virt::{RegisterContextGetter, RegisterContextSetter, VirtContext};
+
+/// Emulates the misaligned load or store that trapped, returns true on success.
+///
+/// On success the access has been performed and the pc points to the next instruction, otherwise
+/// the context is left untouched.
+pub fn emulate(ctx: &mut VirtContext, mctx: &MiralisContext) -> bool {
+    let mode = parse_mpp_return_mode(ctx.trap_info.mstatus);
+    let Some((raw, instr_len)) = fetch_instr(ctx.trap_info.mepc, mode) else {
+        return false;
+    };
+
+    match mctx.decode(raw) {
+        Instr::Load {
+            rd,
+            rs1,
+            imm,
+            len,
+            is_unsigned,
+            ..
+        } => {
+            let addr = ctx.get(rs1).wrapping_add_signed(imm);
+            let mut bytes = [0u8; 8];
+            let dest = &mut bytes[..len.to_bytes()];
+            // SAFETY: the access is performed with the privileges of the trapping mode.
+            if unsafe { Arch::read_bytes_from_mode(addr as *const u8, dest, mode) }.is_err() {
+                return false;
+            }
+            ctx.set(
+                rd,
+                extend_load(usize::from_le_bytes(bytes), len, is_unsigned),
+            );
+        }
+        Instr::Store {
+            rs2, rs1, imm, len, ..
+        } => {
+            let addr = ctx.get(rs1).wrapping_add_signed(imm);
+            let mut bytes = ctx.get(rs2).to_le_bytes();
+            let src = &mut bytes[..len.to_bytes()];
+            // SAFETY: the access is performed with the privileges of the trapping mode.
+            if unsafe { Arch::store_bytes_from_mode(src, addr as *const u8, mode) }.is_err() {
+                return false;
+            }
+        }
+        _ => return false,
+    }
+
+    ctx.pc += instr_len;
+    true
+}
+
+/// Reads the instruction at `pc` with the privileges of `mode`, returns it with its length.
+///
+/// The instruction is read byte-wise, as with compressed instructions it might itself be
+/// misaligned.
+fn fetch_instr(pc: usize, mode: Mode) -> Option<(usize, usize)> {
+    let mut bytes = [0u8; 4];
+    // SAFETY: the instruction is read with the privileges of the trapping mode.
+    unsafe { Arch::read_bytes_from_mode(pc as *const u8, &mut bytes[..2], mode) }.ok()?;
+    let len = instr_len(bytes[0]);
+    if len == 4 {
+        // SAFETY: as above.
+        unsafe { Arch::read_bytes_from_mode((pc + 2) as *const u8, &mut bytes[2..], mode) }.ok()?;
+    }
+    Some((u32::from_le_bytes(bytes) as usize, len))
+}
+
+/// Returns the length of the instruction starting with the given byte.
+fn instr_len(first_byte: u8) -> usize {
+    if first_byte & 0b11 == 0b11 {
+        4
+    } else {
+        2
+    }
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::Width;
+
+    #[test]
+    fn misaligned_helpers() {
+        assert_eq!(instr_len(0x83), 4); // ld
+        assert_eq!(instr_len(0x08), 2); // c.lw
+        assert_eq!(instr_len(0x00), 2);
+
+        assert_eq!(
+            extend_load(0xdead_beef_ffff_ff80, Width::Byte, false),
+            usize::MAX - 0x7f
+        );
+        assert_eq!(extend_load(0xdead_beef_ffff_ff80, Width::Byte, true), 0x80);
+        assert_eq!(
+            extend_load(0x1234_8000, Width::Byte2, false),
+            0xffff_ffff_ffff_8000
+        );
+        assert_eq!(
+            extend_load(0xffff_ffff_8000_0000, Width::Byte4, true),
+            0x8000_0000
+        );
+        assert_eq!(
+            extend_load(0x8000_0000_0000_0000, Width::Byte8, false),
+            0x8000_0000_0000_0000
+        );
+    }
+}
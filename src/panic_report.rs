@@ -0,0 +1,112 @@
+//! Guest Panic Reports
+//!
+//! The firmware and the payload can report a panic with the `MIRALIS_PANIC_FID` call of the Miralis
+//! ABI, passing the address and length of their panic message. Miralis logs the message together
+//! with the register context of the caller, which is the state of the guest panic handler when it
+//! issued the call, and its own view of the virtual trap CSRs, before exiting with an error. That
+//! way a crash of a test firmware can be debugged from the Miralis log alone.
+
+use crate::arch::{parse_mpp_return_mode, Arch, Architecture, Csr, Register};
+use crate::platform::{Plat, Platform};
+use crate::virt::{ExecutionMode, RegisterContextGetter, VirtContext};
+use crate::{coverage, debug};
+
+/// Maximum size of a panic message, longer messages are truncated.
+const MAX_MESSAGE_SIZE: usize = 512;
+
+/// Handles a panic report, logs the message and the guest context and exits with an error.
+pub fn handle_report(ctx: &VirtContext) -> ! {
+    let addr = ctx.get(Register::X10);
+    let size = ctx.get(Register::X11).min(MAX_MESSAGE_SIZE);
+
+    let mut buffer = [0u8; MAX_MESSAGE_SIZE];
+    let bytes = &mut buffer[..size];
+    // SAFETY: the message is read with the privileges of the caller, which is the mode saved in
+    // mstatus.MPP by the trap.
+    let mode = parse_mpp_return_mode(Arch::read_csr(Csr::Mstatus));
+    let message = match unsafe { Arch::read_bytes_from_mode(addr as *const u8, bytes, mode) } {
+        Ok(()) => utf8_prefix(bytes),
+        Err(()) => "note: invalid message address",
+    };
+
+    let who = match ctx.mode.to_exec_mode() {
+        ExecutionMode::Firmware => "Firmware",
+        ExecutionMode::Payload => "Payload",
+    };
+    log::error!("{} panicked on hart {}", who, ctx.hart_id);
+    for line in message.lines() {
+        log::error!("> {}", line);
+    }
+    log_context(ctx);
+
+    unsafe { debug::log_stack_usage() };
+    coverage::dump();
+    Plat::exit_failure();
+}
+
+/// Logs the registers of the guest and its virtual trap CSRs.
+fn log_context(ctx: &VirtContext) {
+    let csr = &ctx.csr;
+    log::error!(
+        "  pc:     0x{:<16x}  mode:   {:?}  exits: {}",
+        ctx.pc,
+        ctx.mode,
+        ctx.nb_exits
+    );
+    log::error!(
+        "  mcause: 0x{:<16x}  mepc:   0x{:<16x}  mtval: 0x{:x}",
+        csr.mcause,
+        csr.mepc,
+        csr.mtval
+    );
+    log::error!(
+        "  scause: 0x{:<16x}  sepc:   0x{:<16x}  stval: 0x{:x}",
+        csr.scause,
+        csr.sepc,
+        csr.stval
+    );
+    for (row, regs) in ctx.regs.chunks(4).enumerate() {
+        let base = row * 4;
+        log::error!(
+            "  x{:<2} {:<16x}  x{:<2} {:<16x}  x{:<2} {:<16x}  x{:<2} {:x}",
+            base,
+            regs[0],
+            base + 1,
+            regs[1],
+            base + 2,
+            regs[2],
+            base + 3,
+            regs[3]
+        );
+    }
+}
+
+/// Returns the longest valid UTF-8 prefix of the bytes.
+///
+/// The message might have been truncated in the middle of a multi-byte character.
+fn utf8_prefix(bytes: &[u8]) -> &str {
+    match core::str::from_utf8(bytes) {
+        Ok(message) => message,
+        // SAFETY: the bytes up to `valid_up_to` have been checked to be valid UTF-8.
+        Err(err) => unsafe { core::str::from_utf8_unchecked(&bytes[..err.valid_up_to()]) },
+    }
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncated_messages() {
+        assert_eq!(
+            utf8_prefix(b"panicked at src/main.rs:4:5"),
+            "panicked at src/main.rs:4:5"
+        );
+        assert_eq!(utf8_prefix("caf\u{e9}".as_bytes()), "café");
+        // The last character is cut in half
+        assert_eq!(utf8_prefix(&"caf\u{e9}".as_bytes()[..4]), "caf");
+        assert_eq!(utf8_prefix(&[0xff, b'a']), "");
+    }
+}
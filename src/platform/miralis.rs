@@ -6,13 +6,18 @@ use log::Level;
 use miralis_abi::{failure, miralis_log_fmt, success};
 use spin::Mutex;
 
+use crate::arch::Width;
 use crate::config::{
     PLATFORM_NB_HARTS, TARGET_FIRMWARE_ADDRESS, TARGET_PAYLOAD_ADDRESS, TARGET_STACK_SIZE,
 };
 use crate::device::clint::{VirtClint, CLINT_SIZE};
+use crate::device::plic::{self, PlicContext, VirtPlic, PLIC_SIZE};
+use crate::device::registry::DeviceRegistry;
+use crate::device::status::{VirtStatusPage, STATUS_PAGE_SIZE};
 use crate::device::tester::{VirtTestDevice, TEST_DEVICE_SIZE};
-use crate::device::{self, VirtDevice};
-use crate::driver::ClintDriver;
+use crate::device::uart::VirtUart;
+use crate::device::{PayloadAccess, VirtDevice};
+use crate::driver::{ClintDriver, MsipRegisters, PlicDriver, UartDriver};
 use crate::{Platform, _stack_start, _start_address};
 // —————————————————————————— Platform Parameters ——————————————————————————— //
 
@@ -20,27 +25,58 @@ const MIRALIS_START_ADDR: usize = TARGET_FIRMWARE_ADDRESS;
 const FIRMWARE_START_ADDR: usize = TARGET_PAYLOAD_ADDRESS;
 const CLINT_BASE: usize = 0x2000000;
 const TEST_DEVICE_BASE: usize = 0x3000000;
+const PLIC_BASE: usize = 0xc000000;
+const SERIAL_PORT_BASE_ADDRESS: usize = 0x10000000;
+const STATUS_PAGE_BASE: usize = 0x3001000;
 
 // ———————————————————————————— Platform Devices ———————————————————————————— //
 
 /// The physical CLINT driver.
 ///
 /// SAFETY: this is the only CLINT device driver that we create, and the platform code does not
-/// otherwise access the CLINT.
+/// otherwise access the CLINT, except for the MSIP registers below.
 static CLINT_MUTEX: Mutex<ClintDriver> = unsafe { Mutex::new(ClintDriver::new(CLINT_BASE)) };
 
+/// The MSIP registers of the physical CLINT, accessed without the driver lock.
+///
+/// SAFETY: the MSIP registers are only accessed through this handle, with single aligned stores.
+static CLINT_MSIP: MsipRegisters = unsafe { MsipRegisters::new(CLINT_BASE) };
+
 /// The virtual CLINT device.
-static VIRT_CLINT: VirtClint = VirtClint::new(&CLINT_MUTEX);
+static VIRT_CLINT: VirtClint = VirtClint::new(&CLINT_MUTEX, &CLINT_MSIP);
+
+/// The PLIC contexts, a M-mode and a S-mode context per hart.
+static PLIC_CONTEXTS: [PlicContext; 2 * PLATFORM_NB_HARTS] = plic::m_s_contexts();
+
+/// The virtual PLIC device.
+///
+/// SAFETY: this is the only PLIC device driver that we create.
+static VIRT_PLIC: VirtPlic = VirtPlic::new(
+    unsafe { PlicDriver::new(PLIC_BASE) },
+    &CLINT_MSIP,
+    &PLIC_CONTEXTS,
+    96,
+);
 
 /// The virtual test device.
 static VIRT_TEST_DEVICE: VirtTestDevice = VirtTestDevice::new();
 
+/// The firmware health status page.
+static VIRT_STATUS_PAGE: VirtStatusPage = VirtStatusPage::new();
+
+/// The virtual UART, shared by the firmware and Miralis.
+///
+/// SAFETY: the UART is only accessed by Miralis to print its logs, and through this device.
+static VIRT_UART: VirtUart =
+    VirtUart::new(unsafe { UartDriver::new(SERIAL_PORT_BASE_ADDRESS, 0, Width::Byte) });
+
 // ———————————————————————————————— Platform ———————————————————————————————— //
 
 pub struct MiralisPlatform {}
 
 impl Platform for MiralisPlatform {
     const NB_HARTS: usize = usize::MAX;
+    const NB_VIRT_DEVICES: usize = 5;
 
     fn name() -> &'static str {
         "Miralis"
@@ -49,6 +85,7 @@ impl Platform for MiralisPlatform {
     fn init() {}
 
     fn debug_print(level: Level, args: fmt::Arguments) {
+        // Logs are forwarded to the host Miralis without locking, which is safe in panic context
         miralis_log_fmt(level, args)
     }
 
@@ -84,29 +121,63 @@ impl Platform for MiralisPlatform {
         usize::MAX
     }
 
-    fn create_virtual_devices() -> [VirtDevice; 2] {
-        let virtual_clint: device::VirtDevice = VirtDevice {
+    fn register_virtual_devices(registry: &mut DeviceRegistry) -> Result<(), &'static str> {
+        registry.register(VirtDevice {
             start_addr: CLINT_BASE,
             size: CLINT_SIZE,
             name: "CLINT",
             device_interface: &VIRT_CLINT,
-        };
+            payload_access: PayloadAccess::Denied,
+        })?;
 
-        let virtual_test_device: device::VirtDevice = VirtDevice {
+        registry.register(VirtDevice {
             start_addr: TEST_DEVICE_BASE,
             size: TEST_DEVICE_SIZE,
             name: "TEST",
             device_interface: &VIRT_TEST_DEVICE,
-        };
-
-        [virtual_clint, virtual_test_device]
+            payload_access: PayloadAccess::Denied,
+        })?;
+
+        registry.register(VirtDevice {
+            start_addr: PLIC_BASE,
+            size: PLIC_SIZE,
+            name: "PLIC",
+            device_interface: &VIRT_PLIC,
+            payload_access: PayloadAccess::Direct,
+        })?;
+
+        registry.register(VirtDevice {
+            start_addr: STATUS_PAGE_BASE,
+            size: STATUS_PAGE_SIZE,
+            name: "STATUS",
+            device_interface: &VIRT_STATUS_PAGE,
+            payload_access: PayloadAccess::ReadOnly,
+        })?;
+
+        registry.register(VirtDevice {
+            start_addr: SERIAL_PORT_BASE_ADDRESS,
+            size: 0x100,
+            name: "UART",
+            device_interface: &VIRT_UART,
+            payload_access: PayloadAccess::Direct,
+        })?;
+
+        Ok(())
     }
 
     fn get_clint() -> &'static Mutex<ClintDriver> {
         &CLINT_MUTEX
     }
 
+    fn get_msip() -> &'static MsipRegisters {
+        &CLINT_MSIP
+    }
+
     fn get_vclint() -> &'static VirtClint {
         &VIRT_CLINT
     }
+
+    fn get_vplic() -> &'static VirtPlic {
+        &VIRT_PLIC
+    }
 }
@@ -1,18 +1,25 @@
 mod miralis;
+mod pflash;
+pub mod unmatched;
 pub mod virt;
 pub mod visionfive2;
 
 use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use config_select::select_env;
 use log::Level;
 use spin::Mutex;
 
 // Re-export virt platform by default for now
-use crate::arch::{Arch, Architecture};
+use crate::arch::{mie, Arch, Architecture, Csr};
+use crate::config::{PLATFORM_BOOT_HART_ID, PLATFORM_NB_HARTS};
 use crate::device::clint::VirtClint;
-use crate::driver::ClintDriver;
-use crate::{device, logger};
+use crate::device::plic::VirtPlic;
+use crate::device::registry::DeviceRegistry;
+use crate::driver::{ClintDriver, MsipRegisters};
+use crate::suspend::SuspendKind;
+use crate::{device_tree, logger};
 
 /// Export the current platform.
 ///
@@ -22,18 +29,29 @@ use crate::{device, logger};
 pub type Plat = select_env!["MIRALIS_PLATFORM_NAME":
     "miralis"     => miralis::MiralisPlatform
     "visionfive2" => visionfive2::VisionFive2Platform
+    "unmatched"   => unmatched::UnmatchedPlatform
     _             => virt::VirtPlatform
 ];
 
 pub trait Platform {
     fn name() -> &'static str;
+    /// Initialize the platform devices, the [device layout](crate::device_tree::layout)
+    /// discovered from the device tree is available at that point.
     fn init();
+    /// Print to the debug console.
+    ///
+    /// Once in [panic context](enter_panic_context) the console must be written without taking
+    /// any lock, as the panicking hart (or a halted one) might hold it.
     fn debug_print(level: Level, args: fmt::Arguments);
     fn exit_success() -> !;
     fn exit_failure() -> !;
-    fn create_virtual_devices() -> [device::VirtDevice; 2];
+    /// Registers the virtual devices of the platform, each device is protected by its own PMP
+    /// entry.
+    fn register_virtual_devices(registry: &mut DeviceRegistry) -> Result<(), &'static str>;
     fn get_clint() -> &'static Mutex<ClintDriver>;
+    fn get_msip() -> &'static MsipRegisters;
     fn get_vclint() -> &'static VirtClint;
+    fn get_vplic() -> &'static VirtPlic;
 
     /// Signal a pending policy interrupt on all cores and trigger an MSI.
     ///
@@ -43,7 +61,16 @@ pub trait Platform {
         Self::get_vclint().set_all_policy_msi();
 
         // Fire physical clint
-        Self::get_clint().lock().trigger_msi_on_all_harts();
+        Self::get_msip().trigger_msi_on_all_harts();
+    }
+
+    /// Enter the low-power state requested by the firmware on the current hart.
+    ///
+    /// Returns once the hart wakes up. Platforms without a dedicated suspend driver wait for an
+    /// interrupt, which is a valid implementation of all suspend states.
+    fn suspend_hart(kind: SuspendKind) {
+        let _ = kind;
+        Arch::wfi();
     }
 
     /// Load the firmware (virtual M-mode software) and return its address.
@@ -56,12 +83,148 @@ pub trait Platform {
     fn get_max_valid_address() -> usize;
 
     const NB_HARTS: usize;
+
+    /// The number of virtual devices registered by the platform.
+    const NB_VIRT_DEVICES: usize;
+
+    /// Whether the cores raise an exception on misaligned loads and stores, instead of supporting
+    /// them in hardware. Selects the default of the `vcpu.emulate_misaligned` configuration.
+    const TRAPS_MISALIGNED_ACCESSES: bool = false;
 }
 
-pub fn init() {
+/// Initializes the platform devices and the logger, the device addresses are discovered from the
+/// device tree when possible.
+pub fn init(device_tree_blob_addr: usize) {
+    device_tree::init(device_tree_blob_addr);
     Plat::init();
     logger::init();
+    device_tree::log_layout();
+    init_hart();
+}
 
+/// Initialize the calling hart only, the platform devices and logger are left untouched.
+pub fn init_hart() {
     // Trap handler
     Arch::init();
 }
+
+// ————————————————————————————— Panic Context —————————————————————————————— //
+
+/// Set once a hart panicked.
+static PANIC_CONTEXT: AtomicBool = AtomicBool::new(false);
+
+/// Enters the panic context, from then on the debug console is written without synchronization.
+///
+/// Output from multiple harts might interleave, but the panic message is not lost (nor does the
+/// panic handler deadlock) if the console lock was held when the panic occurred.
+pub fn enter_panic_context() {
+    PANIC_CONTEXT.store(true, Ordering::SeqCst);
+}
+
+/// Returns true if a hart panicked.
+pub fn is_panic_context() -> bool {
+    PANIC_CONTEXT.load(Ordering::Relaxed)
+}
+
+// ————————————————————————————— SMP Bring-Up ——————————————————————————————— //
+
+/// Number of virtual PMP entries of the first hart to set up its context, `usize::MAX` until then.
+static NB_VIRT_PMP: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Returns true if the hart is in charge of the global initialization.
+pub fn is_boot_hart(hart_id: usize) -> bool {
+    hart_id == PLATFORM_BOOT_HART_ID
+}
+
+/// Parks a secondary hart until the boot hart has initialized the platform and loaded the
+/// firmware, returns the address of the firmware.
+///
+/// The hart waits for an interrupt with only machine software interrupts enabled, and is woken up
+/// by the boot hart with `wake_secondary_harts`. Global interrupts are disabled in M-mode, so the
+/// wake-up interrupt does not trap and is cleared before returning. Harts beyond the configured
+/// number of harts are parked forever.
+pub fn park_secondary_hart(hart_id: usize) -> usize {
+    // SAFETY: the hart does not run the firmware yet, interrupts are only used for wake-up here.
+    let prev_mie = unsafe { Arch::write_csr(Csr::Mie, mie::MSIE_FILTER) };
+
+    let firmware_addr = loop {
+        if hart_id < PLATFORM_NB_HARTS {
+            if let Some(firmware_addr) = firmware_address() {
+                break firmware_addr;
+            }
+        }
+        Arch::wfi();
+    };
+
+    Plat::get_msip()
+        .write(hart_id, 0)
+        .expect("Failed to clear msip");
+    // SAFETY: restore the interrupt configuration from before parking.
+    unsafe { Arch::write_csr(Csr::Mie, prev_mie) };
+
+    firmware_addr
+}
+
+/// Wakes up the secondary harts parked in `park_secondary_hart`.
+///
+/// Must be called by the boot hart after publishing the firmware address.
+pub fn wake_secondary_harts(boot_hart_id: usize) {
+    let msip = Plat::get_msip();
+    for hart in (0..PLATFORM_NB_HARTS).filter(|&hart| hart != boot_hart_id) {
+        msip.write(hart, 1).expect("Failed to write msip");
+    }
+}
+
+/// Checks that all harts expose the same number of virtual PMP entries to the firmware.
+///
+/// The firmware configures the PMP of all harts the same way, a hart with fewer entries than the
+/// others would silently drop part of the configuration. Returns false on a mismatch.
+pub fn check_virt_pmp_consistency(nb_virt_pmp: usize) -> bool {
+    match NB_VIRT_PMP.compare_exchange(usize::MAX, nb_virt_pmp, Ordering::SeqCst, Ordering::SeqCst)
+    {
+        Ok(_) => true,
+        Err(expected) => expected == nb_virt_pmp,
+    }
+}
+
+// ————————————————————————————— Hart Hot-Plug —————————————————————————————— //
+
+/// Bitmap of the harts that joined the main loop.
+static ONLINE_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Address of the firmware, published once it has been loaded. Zero until then.
+static FIRMWARE_ADDR: AtomicUsize = AtomicUsize::new(0);
+
+/// Record that the firmware has been loaded at `firmware_addr`.
+///
+/// Harts entering Miralis afterward (e.g. started by the firmware through HSM long after boot)
+/// are considered late harts: they must not re-initialize the platform nor re-load the firmware.
+pub fn publish_firmware_address(firmware_addr: usize) {
+    FIRMWARE_ADDR.store(firmware_addr, Ordering::SeqCst);
+}
+
+/// Returns the address of the firmware if it has already been loaded, that is if the calling hart
+/// is brought online after boot.
+pub fn firmware_address() -> Option<usize> {
+    match FIRMWARE_ADDR.load(Ordering::SeqCst) {
+        0 => None,
+        addr => Some(addr),
+    }
+}
+
+/// Mark a hart as online, returns false if it already was.
+pub fn mark_hart_online(hart_id: usize) -> bool {
+    assert!(hart_id < Plat::NB_HARTS, "Invalid hart ID: {}", hart_id);
+    let mask = 1 << hart_id;
+    ONLINE_HARTS.fetch_or(mask, Ordering::SeqCst) & mask == 0
+}
+
+/// Returns true if the hart joined the main loop.
+pub fn is_hart_online(hart_id: usize) -> bool {
+    hart_id < usize::BITS as usize && ONLINE_HARTS.load(Ordering::SeqCst) & (1 << hart_id) != 0
+}
+
+/// Returns the number of harts that joined the main loop.
+pub fn nb_online_harts() -> usize {
+    ONLINE_HARTS.load(Ordering::SeqCst).count_ones() as usize
+}
@@ -0,0 +1,114 @@
+//! Firmware images stored in a parallel flash
+//!
+//! Instead of loading the firmware directly in memory, the runner can place it in a flash device,
+//! as is the case on most boards. The flash holds a small header followed by the firmware image:
+//!
+//! | Offset | Size | Content                                        |
+//! |--------|------|------------------------------------------------|
+//! | 0x0    | 8    | The magic value `MIRALIS\0`                    |
+//! | 0x8    | 8    | The size of the image, in bytes                |
+//! | 0x10   | 8    | The 64 bits FNV-1a checksum of the image       |
+//! | 0x100  | size | The firmware image                             |
+//!
+//! All fields are little endian. The platform copies the image to the firmware address and checks
+//! its checksum before jumping into the firmware, a corrupted image is never executed.
+
+/// Magic value at the start of the flash.
+pub const MAGIC: [u8; 8] = *b"MIRALIS\0";
+
+/// Offset of the image within the flash.
+pub const IMAGE_OFFSET: usize = 0x100;
+
+const SIZE_OFFSET: usize = 0x8;
+const CHECKSUM_OFFSET: usize = 0x10;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Returns the FNV-1a checksum of the bytes.
+pub fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// Validates the header and the checksum, returns the firmware image stored in the flash.
+pub fn parse(flash: &[u8]) -> Result<&[u8], &'static str> {
+    let field = |offset: usize| {
+        flash
+            .get(offset..offset + 8)
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+            .ok_or("Flash too small for the firmware header")
+    };
+
+    if flash.get(..MAGIC.len()) != Some(&MAGIC[..]) {
+        return Err("No firmware image in flash");
+    }
+    let size = field(SIZE_OFFSET)? as usize;
+    let expected = field(CHECKSUM_OFFSET)?;
+    let image = flash
+        .get(IMAGE_OFFSET..IMAGE_OFFSET.saturating_add(size))
+        .ok_or("Firmware image larger than the flash")?;
+    if checksum(image) != expected {
+        return Err("Invalid firmware image checksum");
+    }
+    Ok(image)
+}
+
+/// Copies the firmware image stored in the flash to `dest`, returns the size of the image.
+///
+/// # Safety
+///
+/// The flash must be mapped and readable from `flash_base` to `flash_base + flash_size`, and the
+/// memory at `dest` must be free and large enough for the image.
+pub unsafe fn load(
+    flash_base: usize,
+    flash_size: usize,
+    dest: usize,
+) -> Result<usize, &'static str> {
+    let flash = core::slice::from_raw_parts(flash_base as *const u8, flash_size);
+    let image = parse(flash)?;
+    core::ptr::copy_nonoverlapping(image.as_ptr(), dest as *mut u8, image.len());
+    log::info!(
+        "Loaded firmware from flash at 0x{:x}: {} bytes, checksum 0x{:016x}",
+        flash_base,
+        image.len(),
+        checksum(image)
+    );
+    Ok(image.len())
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flash(image: &[u8], checksum: u64) -> Vec<u8> {
+        let mut flash = vec![0xff; IMAGE_OFFSET + image.len() + 16];
+        flash[..8].copy_from_slice(&MAGIC);
+        flash[SIZE_OFFSET..SIZE_OFFSET + 8].copy_from_slice(&(image.len() as u64).to_le_bytes());
+        flash[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 8].copy_from_slice(&checksum.to_le_bytes());
+        flash[IMAGE_OFFSET..IMAGE_OFFSET + image.len()].copy_from_slice(image);
+        flash
+    }
+
+    #[test]
+    fn pflash_image() {
+        // Reference values of the FNV-1a hash
+        assert_eq!(checksum(b""), 0xcbf29ce484222325);
+        assert_eq!(checksum(b"a"), 0xaf63dc4c8601ec8c);
+
+        let image = [0x13, 0x00, 0x00, 0x00, 0x6f, 0x00, 0x00, 0x00];
+        let valid = flash(&image, checksum(&image));
+        assert_eq!(parse(&valid), Ok(&image[..]));
+
+        // Corrupted images, headers, and erased flashes are rejected
+        let mut corrupted = valid.clone();
+        corrupted[IMAGE_OFFSET] ^= 1;
+        assert!(parse(&corrupted).is_err());
+        assert!(parse(&flash(&image, 0)).is_err());
+        assert!(parse(&valid[..IMAGE_OFFSET + 4]).is_err());
+        assert!(parse(&[0xff; 0x200]).is_err());
+    }
+}
@@ -0,0 +1,253 @@
+//! SiFive HiFive Unmatched board
+//!
+//! The board is built around the FU740 SoC, with one S7 monitor core (hart 0, without S-mode) and
+//! four U74 application cores (harts 1 to 4). The firmware is loaded in DRAM by the previous boot
+//! stage (U-Boot SPL), which also configures the clocks and the baud rate of the UART.
+//!
+//! The SiFive UART is not 16550-compatible and is therefore not virtualized: the firmware accesses
+//! it directly, and its output might interleave with the logs of Miralis.
+
+use core::fmt::Write;
+use core::{fmt, hint};
+
+use log::Level;
+use spin::Mutex;
+
+use crate::arch::{Arch, Architecture, Mode};
+use crate::config::{
+    PLATFORM_NB_HARTS, TARGET_FIRMWARE_ADDRESS, TARGET_STACK_SIZE, TARGET_START_ADDRESS,
+};
+use crate::device::clint::{VirtClint, CLINT_SIZE};
+use crate::device::plic::{PlicContext, VirtPlic, PLIC_SIZE};
+use crate::device::registry::DeviceRegistry;
+use crate::device::status::{VirtStatusPage, STATUS_PAGE_SIZE};
+use crate::device::tester::{VirtTestDevice, TEST_DEVICE_SIZE};
+use crate::device::{PayloadAccess, VirtDevice};
+use crate::driver::{self, ClintDriver, MsipRegisters, PlicDriver, SifiveUartDriver};
+use crate::{Platform, _stack_start, _start_address};
+
+// —————————————————————————— Platform Parameters ——————————————————————————— //
+
+const MIRALIS_START_ADDR: usize = TARGET_START_ADDRESS;
+const FIRMWARE_START_ADDR: usize = TARGET_FIRMWARE_ADDRESS;
+
+const CLINT_BASE: usize = 0x2000000;
+const PLIC_BASE: usize = 0xc000000;
+const SERIAL_PORT_BASE_ADDRESS: usize = 0x10010000;
+
+// The virtual devices are placed in a hole of the memory map, 0x3000000 is the DMA controller
+const TEST_DEVICE_BASE: usize = 0x6000000;
+const STATUS_PAGE_BASE: usize = 0x6001000;
+
+// ——————————————————————————— FU740 Memory Map ————————————————————————————— //
+
+/// Start of the DRAM, the board has 16 GiB.
+const DRAM_BASE: usize = 0x80000000;
+/// The L2 cache controller, shared by all cores.
+const L2_CACHE_BASE: usize = 0x2010000;
+/// Number of PLIC interrupt sources, including the reserved source 0.
+const NB_PLIC_SOURCES: usize = 70;
+
+// ———————————————————————————— Platform Devices ———————————————————————————— //
+
+/// The physical CLINT driver.
+///
+/// SAFETY: this is the only CLINT device driver that we create, and the platform code does not
+/// otherwise access the CLINT, except for the MSIP registers below.
+static CLINT_MUTEX: Mutex<ClintDriver> = unsafe { Mutex::new(ClintDriver::new(CLINT_BASE)) };
+
+/// The MSIP registers of the physical CLINT, accessed without the driver lock.
+///
+/// SAFETY: the MSIP registers are only accessed through this handle, with single aligned stores.
+static CLINT_MSIP: MsipRegisters = unsafe { MsipRegisters::new(CLINT_BASE) };
+
+/// The virtual CLINT device.
+static VIRT_CLINT: VirtClint = VirtClint::new(&CLINT_MUTEX, &CLINT_MSIP);
+
+/// The PLIC contexts of the FU740, the S7 monitor core (hart 0) has no S-mode context.
+static PLIC_CONTEXTS: [PlicContext; 9] = [
+    PlicContext::new(0, Mode::M),
+    PlicContext::new(1, Mode::M),
+    PlicContext::new(1, Mode::S),
+    PlicContext::new(2, Mode::M),
+    PlicContext::new(2, Mode::S),
+    PlicContext::new(3, Mode::M),
+    PlicContext::new(3, Mode::S),
+    PlicContext::new(4, Mode::M),
+    PlicContext::new(4, Mode::S),
+];
+
+/// The virtual PLIC device.
+///
+/// SAFETY: this is the only PLIC device driver that we create.
+static VIRT_PLIC: VirtPlic = VirtPlic::new(
+    unsafe { PlicDriver::new(PLIC_BASE) },
+    &CLINT_MSIP,
+    &PLIC_CONTEXTS,
+    NB_PLIC_SOURCES,
+);
+
+/// The virtual test device.
+static VIRT_TEST_DEVICE: VirtTestDevice = VirtTestDevice::new();
+
+/// The firmware health status page.
+static VIRT_STATUS_PAGE: VirtStatusPage = VirtStatusPage::new();
+
+/// The UART used by Miralis to print its logs.
+///
+/// SAFETY: Miralis only transmits through this driver.
+static UART: SifiveUartDriver = unsafe { SifiveUartDriver::new(SERIAL_PORT_BASE_ADDRESS) };
+
+pub static WRITER: Mutex<Writer> = Mutex::new(Writer::new(&UART));
+
+// ———————————————————————————————— Platform ———————————————————————————————— //
+
+pub struct UnmatchedPlatform {}
+
+impl Platform for UnmatchedPlatform {
+    const NB_HARTS: usize = 5;
+    const NB_VIRT_DEVICES: usize = 4;
+    // The U74 cores do not support misaligned accesses in hardware
+    const TRAPS_MISALIGNED_ACCESSES: bool = true;
+
+    fn name() -> &'static str {
+        "HiFive Unmatched board"
+    }
+
+    fn init() {
+        UART.init();
+        // SAFETY: the L2 cache controller is part of the FU740 memory map, and is only accessed
+        // during boot by the boot hart.
+        unsafe { driver::enable_l2_cache_ways(L2_CACHE_BASE) };
+        WRITER.lock().write_str("\r\n").unwrap();
+    }
+
+    fn debug_print(_level: Level, args: fmt::Arguments) {
+        if super::is_panic_context() {
+            // The writer holds no state, a fresh one bypasses the lock
+            let mut writer = Writer::new(&UART);
+            let _ = writer.write_fmt(args);
+            let _ = writer.write_str("\r\n");
+            return;
+        }
+
+        let mut writer = WRITER.lock();
+        writer.write_fmt(args).unwrap();
+        writer.write_str("\r\n").unwrap();
+    }
+
+    fn exit_success() -> ! {
+        loop {
+            Arch::wfi();
+            hint::spin_loop();
+        }
+    }
+
+    fn exit_failure() -> ! {
+        loop {
+            Arch::wfi();
+            hint::spin_loop();
+        }
+    }
+
+    fn load_firmware() -> usize {
+        // The firmware is loaded by the previous boot stage
+        const {
+            assert!(
+                FIRMWARE_START_ADDR >= DRAM_BASE,
+                "The firmware must be located in DRAM"
+            )
+        };
+        FIRMWARE_START_ADDR
+    }
+
+    fn get_miralis_memory_start_and_size() -> (usize, usize) {
+        let size: usize;
+        // SAFETY: The unsafe block is required to get the address of the stack and start of
+        // Miralis, which are external values defined by the linker.
+        // We also ensure that `size` is non-negative and within reasonable bounds
+        unsafe {
+            size = (_stack_start as usize)
+                .checked_sub(_start_address as usize)
+                .and_then(|diff| diff.checked_add(TARGET_STACK_SIZE * PLATFORM_NB_HARTS))
+                .unwrap();
+        }
+
+        (MIRALIS_START_ADDR, size.next_power_of_two())
+    }
+
+    fn get_max_valid_address() -> usize {
+        usize::MAX
+    }
+
+    fn register_virtual_devices(registry: &mut DeviceRegistry) -> Result<(), &'static str> {
+        registry.register(VirtDevice {
+            start_addr: CLINT_BASE,
+            size: CLINT_SIZE,
+            name: "CLINT",
+            device_interface: &VIRT_CLINT,
+            payload_access: PayloadAccess::Denied,
+        })?;
+
+        registry.register(VirtDevice {
+            start_addr: TEST_DEVICE_BASE,
+            size: TEST_DEVICE_SIZE,
+            name: "TEST",
+            device_interface: &VIRT_TEST_DEVICE,
+            payload_access: PayloadAccess::Denied,
+        })?;
+
+        registry.register(VirtDevice {
+            start_addr: PLIC_BASE,
+            size: PLIC_SIZE,
+            name: "PLIC",
+            device_interface: &VIRT_PLIC,
+            payload_access: PayloadAccess::Direct,
+        })?;
+
+        registry.register(VirtDevice {
+            start_addr: STATUS_PAGE_BASE,
+            size: STATUS_PAGE_SIZE,
+            name: "STATUS",
+            device_interface: &VIRT_STATUS_PAGE,
+            payload_access: PayloadAccess::ReadOnly,
+        })?;
+
+        Ok(())
+    }
+
+    fn get_clint() -> &'static Mutex<ClintDriver> {
+        &CLINT_MUTEX
+    }
+
+    fn get_msip() -> &'static MsipRegisters {
+        &CLINT_MSIP
+    }
+
+    fn get_vclint() -> &'static VirtClint {
+        &VIRT_CLINT
+    }
+
+    fn get_vplic() -> &'static VirtPlic {
+        &VIRT_PLIC
+    }
+}
+
+pub struct Writer {
+    uart: &'static SifiveUartDriver,
+}
+
+impl Writer {
+    pub const fn new(uart: &'static SifiveUartDriver) -> Self {
+        Writer { uart }
+    }
+}
+
+impl fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.uart.put_byte(byte);
+        }
+        Ok(())
+    }
+}
@@ -1,22 +1,34 @@
 //! QEMU Virt board
+//!
+//! The platform also supports the Spike simulator and QEMU's `sifive_u` machine, which emulates
+//! the FU540 SoC. The `sifive_u` machine has a different memory map and its first hart is a
+//! monitor core without S-mode, which helps catching addresses and hart layouts hardcoded for the
+//! `virt` machine. Its UART is not 16550-compatible and is not virtualized, the firmware accesses
+//! it directly.
 
 use core::fmt::Write;
 use core::{fmt, ptr};
 
+use config_helpers::str_eq;
 use log::Level;
 use spin::Mutex;
 use uart_16550::MmioSerialPort;
 
-use super::Platform;
+use super::{pflash, Platform};
+use crate::arch::Width;
 use crate::config::{
-    PLATFORM_NAME, PLATFORM_NB_HARTS, TARGET_FIRMWARE_ADDRESS, TARGET_STACK_SIZE,
-    TARGET_START_ADDRESS,
+    PLATFORM_FIRMWARE_PFLASH, PLATFORM_NAME, PLATFORM_NB_HARTS, TARGET_FIRMWARE_ADDRESS,
+    TARGET_STACK_SIZE, TARGET_START_ADDRESS,
 };
 use crate::device::clint::{VirtClint, CLINT_SIZE};
+use crate::device::plic::{self, PlicContext, VirtPlic, PLIC_SIZE};
+use crate::device::registry::DeviceRegistry;
+use crate::device::status::{VirtStatusPage, STATUS_PAGE_SIZE};
 use crate::device::tester::{VirtTestDevice, TEST_DEVICE_SIZE};
-use crate::device::{self, VirtDevice};
-use crate::driver::ClintDriver;
-use crate::{_stack_start, _start_address};
+use crate::device::uart::VirtUart;
+use crate::device::{PayloadAccess, VirtDevice};
+use crate::driver::{ClintDriver, MsipRegisters, PlicDriver, SifiveUartDriver, UartDriver};
+use crate::{_stack_start, _start_address, device_tree};
 
 const SERIAL_PORT_BASE_ADDRESS: usize = 0x10000000;
 const TEST_MMIO_ADDRESS: usize = 0x100000;
@@ -24,6 +36,20 @@ const MIRALIS_START_ADDR: usize = TARGET_START_ADDRESS;
 const FIRMWARE_START_ADDR: usize = TARGET_FIRMWARE_ADDRESS;
 const CLINT_BASE: usize = 0x2000000;
 const TEST_DEVICE_BASE: usize = 0x3000000;
+const PLIC_BASE: usize = 0xc000000;
+const STATUS_PAGE_BASE: usize = 0x3001000;
+/// The second flash bank, the first one is reserved by QEMU for booting.
+const PFLASH_BASE: usize = 0x22000000;
+const PFLASH_SIZE: usize = 0x2000000;
+
+// ———————————————————————— QEMU sifive_u Parameters ———————————————————————— //
+
+/// Whether the board is QEMU's `sifive_u` machine.
+const SIFIVE_U: bool = str_eq(PLATFORM_NAME, "sifive_u");
+
+const SIFIVE_U_SERIAL_PORT_BASE_ADDRESS: usize = 0x10010000;
+/// Number of PLIC interrupt sources of the FU540, including the reserved source 0.
+const SIFIVE_U_NB_PLIC_SOURCES: usize = 54;
 
 // —————————————————————————— Spike Parameters ——————————————————————————— //
 
@@ -44,38 +70,112 @@ static SERIAL_PORT: Mutex<Option<MmioSerialPort>> = Mutex::new(None);
 /// The physical CLINT driver.
 ///
 /// SAFETY: this is the only CLINT device driver that we create, and the platform code does not
-/// otherwise access the CLINT.
+/// otherwise access the CLINT, except for the MSIP registers below.
 static CLINT_MUTEX: Mutex<ClintDriver> = unsafe { Mutex::new(ClintDriver::new(CLINT_BASE)) };
 
+/// The MSIP registers of the physical CLINT, accessed without the driver lock.
+///
+/// SAFETY: the MSIP registers are only accessed through this handle, with single aligned stores.
+static CLINT_MSIP: MsipRegisters = unsafe { MsipRegisters::new(CLINT_BASE) };
+
 /// The virtual CLINT device.
-static VIRT_CLINT: VirtClint = VirtClint::new(&CLINT_MUTEX);
+static VIRT_CLINT: VirtClint = VirtClint::new(&CLINT_MUTEX, &CLINT_MSIP);
+
+/// The PLIC contexts, a M-mode and a S-mode context per hart.
+static PLIC_CONTEXTS: [PlicContext; 2 * PLATFORM_NB_HARTS] = plic::m_s_contexts();
+
+/// The PLIC contexts of `sifive_u`, the monitor core (hart 0) has no S-mode context.
+static SIFIVE_U_PLIC_CONTEXTS: [PlicContext; 2 * PLATFORM_NB_HARTS - 1] =
+    plic::monitor_core_contexts();
+
+/// The virtual PLIC device.
+///
+/// SAFETY: this is the only PLIC device driver that we create.
+static VIRT_PLIC: VirtPlic = if SIFIVE_U {
+    VirtPlic::new(
+        unsafe { PlicDriver::new(PLIC_BASE) },
+        &CLINT_MSIP,
+        &SIFIVE_U_PLIC_CONTEXTS,
+        SIFIVE_U_NB_PLIC_SOURCES,
+    )
+} else {
+    VirtPlic::new(
+        unsafe { PlicDriver::new(PLIC_BASE) },
+        &CLINT_MSIP,
+        &PLIC_CONTEXTS,
+        96,
+    )
+};
 
 /// The virtual test device.
 static VIRT_TEST_DEVICE: VirtTestDevice = VirtTestDevice::new();
 
+/// The firmware health status page.
+static VIRT_STATUS_PAGE: VirtStatusPage = VirtStatusPage::new();
+
+/// The virtual UART, shared by the firmware and Miralis.
+///
+/// SAFETY: the UART is only accessed by Miralis to print its logs, and through this device.
+static VIRT_UART: VirtUart =
+    VirtUart::new(unsafe { UartDriver::new(SERIAL_PORT_BASE_ADDRESS, 0, Width::Byte) });
+
+/// The UART of `sifive_u`, only used by Miralis to print its logs.
+static SIFIVE_UART: SifiveUartDriver =
+    unsafe { SifiveUartDriver::new(SIFIVE_U_SERIAL_PORT_BASE_ADDRESS) };
+
 // ———————————————————————————————— Platform ———————————————————————————————— //
 
 pub struct VirtPlatform {}
 
 impl Platform for VirtPlatform {
     const NB_HARTS: usize = usize::MAX;
+    const NB_VIRT_DEVICES: usize = 5;
 
     fn name() -> &'static str {
         match PLATFORM_NAME {
             "spike" => "Spike",
+            "sifive_u" => "QEMU sifive_u",
             _ => "QEMU virt",
         }
     }
 
     fn init() {
+        // SAFETY: the addresses are either the defaults of the board or read from the device tree,
+        // and the devices have not been accessed yet.
+        unsafe {
+            CLINT_MUTEX.lock().relocate(clint_base());
+            CLINT_MSIP.relocate(clint_base());
+            VIRT_PLIC.relocate(plic_base());
+            VIRT_UART.relocate(uart_base());
+        }
+
+        if SIFIVE_U {
+            SIFIVE_UART.init();
+            return;
+        }
+
         // Serial
         let mut uart = SERIAL_PORT.lock();
-        let mut mmio = unsafe { MmioSerialPort::new(SERIAL_PORT_BASE_ADDRESS) };
+        let mut mmio = unsafe { MmioSerialPort::new(uart_base()) };
         mmio.init();
         *uart = Some(mmio);
     }
 
     fn debug_print(_level: Level, args: fmt::Arguments) {
+        if SIFIVE_U {
+            // The driver holds no state and does not need to be locked
+            let _ = SifiveWriter.write_fmt(args);
+            return;
+        }
+
+        if super::is_panic_context() {
+            // SAFETY: the UART has been initialized during boot, concurrent writes might only
+            // interleave characters.
+            let mut serial_port = unsafe { MmioSerialPort::new(uart_base()) };
+            let _ = serial_port.write_fmt(args);
+            return;
+        }
+
         let mut serial_port = SERIAL_PORT.lock();
         if let Some(ref mut serial_port) = serial_port.as_mut() {
             serial_port
@@ -99,7 +199,15 @@ impl Platform for VirtPlatform {
     }
 
     fn load_firmware() -> usize {
-        // We directly load the firmware from QEMU, nothing to do here.
+        if PLATFORM_FIRMWARE_PFLASH {
+            // SAFETY: the flash is mapped at a fixed address on the virt board, and the firmware
+            // memory is not used by Miralis.
+            if let Err(err) = unsafe { pflash::load(PFLASH_BASE, PFLASH_SIZE, FIRMWARE_START_ADDR) }
+            {
+                panic!("Failed to load the firmware from flash: {}", err);
+            }
+        }
+        // Otherwise QEMU directly loads the firmware in memory, nothing to do here.
         FIRMWARE_START_ADDR
     }
 
@@ -122,31 +230,102 @@ impl Platform for VirtPlatform {
         usize::MAX
     }
 
-    fn create_virtual_devices() -> [VirtDevice; 2] {
-        let virtual_clint: device::VirtDevice = VirtDevice {
-            start_addr: CLINT_BASE,
+    fn register_virtual_devices(registry: &mut DeviceRegistry) -> Result<(), &'static str> {
+        registry.register(VirtDevice {
+            start_addr: clint_base(),
             size: CLINT_SIZE,
             name: "CLINT",
             device_interface: &VIRT_CLINT,
-        };
+            payload_access: PayloadAccess::Denied,
+        })?;
 
-        let virtual_test_device: device::VirtDevice = VirtDevice {
+        registry.register(VirtDevice {
             start_addr: TEST_DEVICE_BASE,
             size: TEST_DEVICE_SIZE,
             name: "TEST",
             device_interface: &VIRT_TEST_DEVICE,
-        };
+            payload_access: PayloadAccess::Denied,
+        })?;
+
+        registry.register(VirtDevice {
+            start_addr: plic_base(),
+            size: PLIC_SIZE,
+            name: "PLIC",
+            device_interface: &VIRT_PLIC,
+            payload_access: PayloadAccess::Direct,
+        })?;
+
+        registry.register(VirtDevice {
+            start_addr: STATUS_PAGE_BASE,
+            size: STATUS_PAGE_SIZE,
+            name: "STATUS",
+            device_interface: &VIRT_STATUS_PAGE,
+            payload_access: PayloadAccess::ReadOnly,
+        })?;
+
+        if SIFIVE_U {
+            return Ok(());
+        }
+
+        registry.register(VirtDevice {
+            start_addr: uart_base(),
+            size: 0x100,
+            name: "UART",
+            device_interface: &VIRT_UART,
+            payload_access: PayloadAccess::Direct,
+        })?;
 
-        [virtual_clint, virtual_test_device]
+        Ok(())
     }
 
     fn get_clint() -> &'static Mutex<ClintDriver> {
         &CLINT_MUTEX
     }
 
+    fn get_msip() -> &'static MsipRegisters {
+        &CLINT_MSIP
+    }
+
     fn get_vclint() -> &'static VirtClint {
         &VIRT_CLINT
     }
+
+    fn get_vplic() -> &'static VirtPlic {
+        &VIRT_PLIC
+    }
+}
+
+/// The base address of the CLINT, as discovered from the device tree.
+fn clint_base() -> usize {
+    device_tree::layout()
+        .clint
+        .map_or(CLINT_BASE, |clint| clint.base)
+}
+
+/// The base address of the PLIC, as discovered from the device tree.
+fn plic_base() -> usize {
+    device_tree::layout()
+        .plic
+        .map_or(PLIC_BASE, |plic| plic.base)
+}
+
+/// The base address of the UART, as discovered from the device tree.
+fn uart_base() -> usize {
+    device_tree::layout()
+        .uart
+        .map_or(SERIAL_PORT_BASE_ADDRESS, |uart| uart.base)
+}
+
+/// Writes to the UART of `sifive_u`.
+struct SifiveWriter;
+
+impl Write for SifiveWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            SIFIVE_UART.put_byte(byte);
+        }
+        Ok(())
+    }
 }
 
 /// Exit the QEMU emulator.
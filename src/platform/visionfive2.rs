@@ -1,21 +1,31 @@
-//! QEMU Virt board
+//! StarFive VisionFive 2 board
+//!
+//! The board is built around the JH7110 SoC, with one S7 monitor core (hart 0, without S-mode)
+//! and four U74 application cores (harts 1 to 4). The boot stages jump into Miralis on all harts,
+//! the secondary harts are parked and released by the boot hart through the CLINT once the
+//! firmware is loaded. The platform configures the UART and the L2 cache itself, such that
+//! Miralis does not depend on the state left by the vendor SPL.
 
-use core::arch::asm;
 use core::fmt::Write;
-use core::{fmt, hint, ptr};
+use core::{fmt, hint};
 
 use log::Level;
 use spin::Mutex;
 
-use crate::arch::{Arch, Architecture};
+use crate::arch::{Arch, Architecture, Mode, Width};
 use crate::config::{
     PLATFORM_NB_HARTS, TARGET_FIRMWARE_ADDRESS, TARGET_STACK_SIZE, TARGET_START_ADDRESS,
 };
 use crate::device::clint::{VirtClint, CLINT_SIZE};
+use crate::device::plic::{PlicContext, VirtPlic, PLIC_SIZE};
+use crate::device::registry::DeviceRegistry;
+use crate::device::status::{VirtStatusPage, STATUS_PAGE_SIZE};
 use crate::device::tester::{VirtTestDevice, TEST_DEVICE_SIZE};
-use crate::device::{self, VirtDevice};
-use crate::driver::ClintDriver;
-use crate::{Platform, _stack_start, _start_address};
+use crate::device::uart::VirtUart;
+use crate::device::{PayloadAccess, VirtDevice};
+use crate::driver::{self, uart, ClintDriver, MsipRegisters, PlicDriver, UartDriver};
+use crate::{device_tree, Platform, _stack_start, _start_address};
+
 // —————————————————————————— Platform Parameters ——————————————————————————— //
 
 const SERIAL_PORT_BASE_ADDRESS: usize = 0x10000000;
@@ -24,20 +34,79 @@ const FIRMWARE_START_ADDR: usize = TARGET_FIRMWARE_ADDRESS;
 
 const CLINT_BASE: usize = 0x2000000;
 const TEST_DEVICE_BASE: usize = 0x3000000;
+const PLIC_BASE: usize = 0xc000000;
+const STATUS_PAGE_BASE: usize = 0x3001000;
+
+// ——————————————————————————— JH7110 Memory Map ———————————————————————————— //
+
+/// Start of the DRAM, the size depends on the board variant (2, 4 or 8 GiB).
+const DRAM_BASE: usize = 0x40000000;
+/// The L2 cache controller, shared by all cores.
+const L2_CACHE_BASE: usize = 0x2010000;
+/// Input clock of the UARTs, if not described by the device tree.
+const UART_CLOCK_FREQUENCY: usize = 24_000_000;
+/// Baud rate of the console, if not described by the device tree.
+const UART_BAUD_RATE: usize = 115_200;
 
 // ———————————————————————————— Platform Devices ———————————————————————————— //
 
 /// The physical CLINT driver.
 ///
 /// SAFETY: this is the only CLINT device driver that we create, and the platform code does not
-/// otherwise access the CLINT.
+/// otherwise access the CLINT, except for the MSIP registers below.
 static CLINT_MUTEX: Mutex<ClintDriver> = unsafe { Mutex::new(ClintDriver::new(CLINT_BASE)) };
 
+/// The MSIP registers of the physical CLINT, accessed without the driver lock.
+///
+/// SAFETY: the MSIP registers are only accessed through this handle, with single aligned stores.
+static CLINT_MSIP: MsipRegisters = unsafe { MsipRegisters::new(CLINT_BASE) };
+
 /// The virtual CLINT device.
-static VIRT_CLINT: VirtClint = VirtClint::new(&CLINT_MUTEX);
+static VIRT_CLINT: VirtClint = VirtClint::new(&CLINT_MUTEX, &CLINT_MSIP);
+
+/// The PLIC contexts of the JH7110, the S7 monitor core (hart 0) has no S-mode context.
+static PLIC_CONTEXTS: [PlicContext; 9] = [
+    PlicContext::new(0, Mode::M),
+    PlicContext::new(1, Mode::M),
+    PlicContext::new(1, Mode::S),
+    PlicContext::new(2, Mode::M),
+    PlicContext::new(2, Mode::S),
+    PlicContext::new(3, Mode::M),
+    PlicContext::new(3, Mode::S),
+    PlicContext::new(4, Mode::M),
+    PlicContext::new(4, Mode::S),
+];
+
+/// The virtual PLIC device.
+///
+/// SAFETY: this is the only PLIC device driver that we create.
+static VIRT_PLIC: VirtPlic = VirtPlic::new(
+    unsafe { PlicDriver::new(PLIC_BASE) },
+    &CLINT_MSIP,
+    &PLIC_CONTEXTS,
+    136,
+);
+
 /// The virtual test device.
 static VIRT_TEST_DEVICE: VirtTestDevice = VirtTestDevice::new();
-pub static WRITER: Mutex<Writer> = Mutex::new(Writer::new(SERIAL_PORT_BASE_ADDRESS));
+
+/// The firmware health status page.
+static VIRT_STATUS_PAGE: VirtStatusPage = VirtStatusPage::new();
+
+/// The virtual UART, shared by the firmware and Miralis.
+///
+/// SAFETY: the UART is only accessed by Miralis to print its logs, and through this device.
+static VIRT_UART: VirtUart =
+    VirtUart::new(unsafe { UartDriver::new(SERIAL_PORT_BASE_ADDRESS, 2, Width::Byte4) });
+
+/// The UART used by Miralis to print its logs, the registers are 32 bits wide and spaced by 4
+/// bytes.
+///
+/// SAFETY: Miralis only transmits through this driver, the firmware accesses the UART through the
+/// virtual UART.
+static UART: UartDriver = unsafe { UartDriver::new(SERIAL_PORT_BASE_ADDRESS, 2, Width::Byte4) };
+
+pub static WRITER: Mutex<Writer> = Mutex::new(Writer::new(&UART));
 
 // ———————————————————————————————— Platform ———————————————————————————————— //
 
@@ -45,20 +114,41 @@ pub struct VisionFive2Platform {}
 
 impl Platform for VisionFive2Platform {
     const NB_HARTS: usize = 5;
+    const NB_VIRT_DEVICES: usize = 5;
+    // The U74 cores do not support misaligned accesses in hardware
+    const TRAPS_MISALIGNED_ACCESSES: bool = true;
 
     fn name() -> &'static str {
         "VisionFive 2 board"
     }
 
     fn init() {
-        let mut writer = WRITER.lock();
-        // NOTE: for now we assume the uart has been initialized by the previous boot stage (U-boot
-        // SPL)
-        // uart_init(SERIAL_PORT_BASE_ADDRESS);
-        writer.write_char('\n');
+        let uart_base = device_tree::layout()
+            .uart
+            .map_or(SERIAL_PORT_BASE_ADDRESS, |uart| uart.base);
+        // SAFETY: the address is either the default of the board or read from the device tree,
+        // and the UART has not been accessed yet.
+        unsafe {
+            UART.relocate(uart_base);
+            VIRT_UART.relocate(uart_base);
+        }
+
+        uart_init();
+        // SAFETY: the L2 cache controller is part of the JH7110 memory map, and is only accessed
+        // during boot by the boot hart.
+        unsafe { driver::enable_l2_cache_ways(L2_CACHE_BASE) };
+        WRITER.lock().write_str("\r\n").unwrap();
     }
 
     fn debug_print(_level: Level, args: fmt::Arguments) {
+        if super::is_panic_context() {
+            // The writer holds no state, a fresh one bypasses the lock
+            let mut writer = Writer::new(&UART);
+            let _ = writer.write_fmt(args);
+            let _ = writer.write_str("\r\n");
+            return;
+        }
+
         let mut writer = WRITER.lock();
         writer.write_fmt(args).unwrap();
         writer.write_str("\r\n").unwrap();
@@ -79,6 +169,13 @@ impl Platform for VisionFive2Platform {
     }
 
     fn load_firmware() -> usize {
+        // The firmware is loaded by the previous boot stage
+        const {
+            assert!(
+                FIRMWARE_START_ADDR >= DRAM_BASE,
+                "The firmware must be located in DRAM"
+            )
+        };
         FIRMWARE_START_ADDR
     }
 
@@ -101,136 +198,96 @@ impl Platform for VisionFive2Platform {
         usize::MAX
     }
 
-    fn create_virtual_devices() -> [VirtDevice; 2] {
-        let virtual_clint: device::VirtDevice = VirtDevice {
+    fn register_virtual_devices(registry: &mut DeviceRegistry) -> Result<(), &'static str> {
+        registry.register(VirtDevice {
             start_addr: CLINT_BASE,
             size: CLINT_SIZE,
             name: "CLINT",
             device_interface: &VIRT_CLINT,
-        };
+            payload_access: PayloadAccess::Denied,
+        })?;
 
-        let virtual_test_device: device::VirtDevice = VirtDevice {
+        registry.register(VirtDevice {
             start_addr: TEST_DEVICE_BASE,
             size: TEST_DEVICE_SIZE,
             name: "TEST",
             device_interface: &VIRT_TEST_DEVICE,
-        };
+            payload_access: PayloadAccess::Denied,
+        })?;
+
+        registry.register(VirtDevice {
+            start_addr: PLIC_BASE,
+            size: PLIC_SIZE,
+            name: "PLIC",
+            device_interface: &VIRT_PLIC,
+            payload_access: PayloadAccess::Direct,
+        })?;
+
+        registry.register(VirtDevice {
+            start_addr: STATUS_PAGE_BASE,
+            size: STATUS_PAGE_SIZE,
+            name: "STATUS",
+            device_interface: &VIRT_STATUS_PAGE,
+            payload_access: PayloadAccess::ReadOnly,
+        })?;
+
+        registry.register(VirtDevice {
+            start_addr: SERIAL_PORT_BASE_ADDRESS,
+            size: 0x10000,
+            name: "UART",
+            device_interface: &VIRT_UART,
+            payload_access: PayloadAccess::Direct,
+        })?;
 
-        [virtual_clint, virtual_test_device]
+        Ok(())
     }
 
     fn get_clint() -> &'static Mutex<ClintDriver> {
         &CLINT_MUTEX
     }
 
+    fn get_msip() -> &'static MsipRegisters {
+        &CLINT_MSIP
+    }
+
     fn get_vclint() -> &'static VirtClint {
         &VIRT_CLINT
     }
+
+    fn get_vplic() -> &'static VirtPlic {
+        &VIRT_PLIC
+    }
 }
 
 pub struct Writer {
-    serial_port_base_addr: usize,
+    uart: &'static UartDriver,
 }
 
 impl Writer {
-    pub const fn new(serial_port_base_addr: usize) -> Self {
-        Writer {
-            serial_port_base_addr,
-        }
-    }
-
-    fn write_char(&mut self, c: char) {
-        // const LSR_OFFSET: usize = 0x05;
-        // const LSR_THRE: u8 = 0x20;
-
-        unsafe {
-            // Wait until THR (Transmitter Holding Register) is ready
-            // For now that's disabled, on the board this bit of LSR always reads as 0
-            // Which leads to an infinite wait cycle
-
-            // while ptr::read_volatile((self.serial_port_base_addr + LSR_OFFSET) as *const u8)
-            //     & LSR_THRE
-            //     == 0
-            // {}
-
-            ptr::write_volatile(self.serial_port_base_addr as *mut char, c);
-            for _n in 1..1000001 {
-                asm!("nop");
-            }
-        }
+    pub const fn new(uart: &'static UartDriver) -> Self {
+        Writer { uart }
     }
 }
 
 impl fmt::Write for Writer {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        for c in s.chars() {
-            self.write_char(c);
+        for byte in s.bytes() {
+            self.uart.put_byte(byte).map_err(|_| fmt::Error)?;
         }
         Ok(())
     }
 }
 
-// NOTE: for now this function is not used, as we assume the previous boot stage (U-boot SPL) will
-// initialize the uart for us.
-#[allow(dead_code)]
-fn uart_init(serial_port_base_addr: usize) {
-    let reg_lcr = 0x03;
-    let reg_brdl = 0x00;
-    let reg_brdh = 0x01;
-    let reg_mdc = 0x04;
-    let reg_fcr = 0x02;
-    let reg_ier = 0x01;
-
-    let lcr_dlab = 0x80;
-    let lcr_cs8 = 0x03;
-    let lcr_1_stb = 0x00;
-    let lcr_pdis = 0x00;
-    let fcr_fifo = 0x01;
-    let fcr_mode1 = 0x08;
-    let fcr_fifo_8 = 0x80;
-    let fcr_rcvrclr = 0x02;
-    let fcr_xmitclr = 0x04;
-
-    let divisor = 0x01;
-
-    // Read LCR and cache its value
-    let lcr_cache = unsafe { ptr::read_volatile((serial_port_base_addr + reg_lcr) as *const u8) };
-
-    // Enable DLAB (Divisor Latch Access Bit) to set the baud rate divisor
-    unsafe {
-        ptr::write_volatile(
-            (serial_port_base_addr + reg_lcr) as *mut u8,
-            lcr_dlab | lcr_cache,
-        );
-        ptr::write_volatile(
-            (serial_port_base_addr + reg_brdl) as *mut u8,
-            (divisor & 0xFF) as u8,
-        );
-        ptr::write_volatile(
-            (serial_port_base_addr + reg_brdh) as *mut u8,
-            ((divisor >> 8) & 0xFF) as u8,
-        );
-        ptr::write_volatile((serial_port_base_addr + reg_lcr) as *mut u8, lcr_cache);
-        // Restore LCR
-    }
-
-    // Configure UART: 8 data bits, 1 stop bit, no parity
-    unsafe {
-        ptr::write_volatile(
-            (serial_port_base_addr + reg_lcr) as *mut u8,
-            lcr_cs8 | lcr_1_stb | lcr_pdis,
-        );
+// ———————————————————————————— Platform Bring-Up ——————————————————————————— //
 
-        // Disable flow control
-        ptr::write_volatile((serial_port_base_addr + reg_mdc) as *mut u8, 0);
-
-        // Configure FIFO: enabled, mode 0, generate interrupt at 8th byte, clear receive and transmit buffers
-        ptr::write_volatile(
-            (serial_port_base_addr + reg_fcr) as *mut u8,
-            fcr_fifo | fcr_mode1 | fcr_fifo_8 | fcr_rcvrclr | fcr_xmitclr,
-        );
-
-        // Disable UART interrupts
-        ptr::write_volatile((serial_port_base_addr + reg_ier) as *mut u8, 0);
-    }
+/// Configures the UART from the clock and baud rate described by the device tree.
+///
+/// The previous boot stages usually leave the UART configured, but the divisor is recomputed
+/// rather than trusted, such that Miralis can print its logs from any boot path.
+fn uart_init() {
+    let layout = device_tree::layout();
+    let clock_frequency = layout.uart_clock_frequency.unwrap_or(UART_CLOCK_FREQUENCY);
+    let baud_rate = layout.uart_baud_rate.unwrap_or(UART_BAUD_RATE);
+    UART.init(uart::divisor(clock_frequency, baud_rate))
+        .expect("Failed to initialize the UART");
 }
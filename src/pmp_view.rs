@@ -0,0 +1,197 @@
+//! PMP View
+//!
+//! The firmware and the payload can dump the PMP entries in effect with the `MIRALIS_PMP_VIEW_FID`
+//! call of the Miralis ABI, which helps understanding why an access faulted without attaching a
+//! debugger. The view lists the physical entries reserved by Miralis, the devices, and the policy,
+//! with the virtual entries programmed by the firmware in place of the physical entries backing
+//! them. Entries are listed by decreasing priority, as the hardware matches them.
+//!
+//! The view reveals the memory protected by the policy, the call is therefore only available in
+//! builds with the `debug` feature.
+
+use core::mem::size_of;
+
+use miralis_core::abi::pmp_view::{self, PmpEntry};
+
+use crate::arch::pmp::pmplayout::{DEVICES_OFFSET, POLICY_OFFSET};
+use crate::arch::pmp::{pmpcfg, PmpGroup};
+use crate::arch::{parse_mpp_return_mode, Arch, Architecture, Csr, Register};
+use crate::virt::{RegisterContextGetter, RegisterContextSetter, VirtContext};
+
+const SBI_ERR_NOT_SUPPORTED: isize = -2;
+const SBI_ERR_INVALID_ADDRESS: isize = -5;
+
+/// Calls `f` on each entry of the view, by decreasing priority.
+fn for_each_entry(
+    pmp: &PmpGroup,
+    virt_pmpaddr: &[usize; 64],
+    virt_pmpcfg: &[usize; 8],
+    nb_virt_pmp: usize,
+    mut f: impl FnMut(PmpEntry),
+) {
+    let virt_slots = pmp.virt_pmp_offset..pmp.virt_pmp_offset + pmp.virt_pmp_slots;
+    let mut prev_addr = 0;
+    for idx in 0..pmp.nb_pmp as usize {
+        if idx == virt_slots.start {
+            let mut virt_prev_addr = 0;
+            for (virt_idx, &addr) in virt_pmpaddr.iter().enumerate().take(nb_virt_pmp) {
+                let cfg = (virt_pmpcfg[virt_idx / 8] >> ((virt_idx % 8) * 8)) as u8;
+                f(entry(
+                    virt_idx,
+                    addr,
+                    cfg,
+                    virt_prev_addr,
+                    pmp_view::VIRTUAL,
+                ));
+                virt_prev_addr = addr;
+            }
+        }
+
+        let addr = pmp.pmpaddr()[idx];
+        if !virt_slots.contains(&idx) {
+            f(entry(
+                idx,
+                addr,
+                pmp.get_cfg(idx),
+                prev_addr,
+                owner(pmp, idx),
+            ));
+        }
+        prev_addr = addr;
+    }
+}
+
+/// Returns the owner of a physical entry.
+fn owner(pmp: &PmpGroup, idx: usize) -> u64 {
+    if (DEVICES_OFFSET..POLICY_OFFSET).contains(&idx) {
+        pmp_view::DEVICE
+    } else if (POLICY_OFFSET..POLICY_OFFSET + pmp.policy_pmps).contains(&idx) {
+        pmp_view::POLICY
+    } else {
+        pmp_view::MIRALIS
+    }
+}
+
+/// Decodes the region matched by an entry, `prev_addr` is the address of the previous entry.
+fn entry(index: usize, addr: usize, cfg: u8, prev_addr: usize, owner: u64) -> PmpEntry {
+    // Regions are decoded in units of 4 bytes, as in pmpaddr
+    let (start, end) = match cfg & pmpcfg::A_MASK {
+        pmpcfg::TOR if prev_addr < addr => (prev_addr, addr),
+        pmpcfg::NA4 => (addr, addr.saturating_add(1)),
+        pmpcfg::NAPOT => {
+            let trailing_ones = addr.trailing_ones() as usize;
+            if trailing_ones >= usize::BITS as usize - 2 {
+                (0, usize::MAX)
+            } else {
+                let start = addr & !((1 << trailing_ones) - 1);
+                (start, start + (1 << (trailing_ones + 1)))
+            }
+        }
+        // Inactive or empty entry
+        _ => (0, 0),
+    };
+
+    PmpEntry {
+        index: index as u64,
+        addr: addr as u64,
+        cfg: cfg as u64,
+        start: (start as u64) << 2,
+        size: ((end - start) as u64).saturating_mul(4),
+        owner,
+    }
+}
+
+/// Handles a request to copy the PMP view.
+///
+/// The arguments are the address of the destination buffer and its capacity in entries. The
+/// buffer is written with the privileges of the caller, and the total number of entries is
+/// returned in a1 even if they do not all fit in the buffer.
+pub fn handle_query(ctx: &mut VirtContext, pmp: &PmpGroup) {
+    if !cfg!(feature = "debug") {
+        ctx.set(Register::X10, SBI_ERR_NOT_SUPPORTED as usize);
+        ctx.set(Register::X11, 0);
+        ctx.pc += 4;
+        return;
+    }
+
+    let addr = ctx.get(Register::X10);
+    let capacity = ctx.get(Register::X11);
+    // SAFETY: the entries are stored with the privileges of the caller, which is the mode saved in
+    // mstatus.MPP by the trap.
+    let mode = parse_mpp_return_mode(Arch::read_csr(Csr::Mstatus));
+
+    let mut count = 0;
+    let mut error = 0;
+    for_each_entry(
+        pmp,
+        &ctx.csr.pmpaddr,
+        &ctx.csr.pmpcfg,
+        ctx.nb_pmp,
+        |entry| {
+            if count < capacity && error == 0 {
+                // SAFETY: the entry is plain data, made of integers without padding.
+                let mut bytes =
+                    unsafe { core::mem::transmute::<PmpEntry, [u8; size_of::<PmpEntry>()]>(entry) };
+                let dest = (addr + count * size_of::<PmpEntry>()) as *const u8;
+                if unsafe { Arch::store_bytes_from_mode(&mut bytes, dest, mode) }.is_err() {
+                    error = SBI_ERR_INVALID_ADDRESS;
+                }
+            }
+            count += 1;
+        },
+    );
+
+    ctx.set(Register::X10, error as usize);
+    ctx.set(Register::X11, count);
+    ctx.pc += 4;
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::registry::DeviceRegistry;
+
+    #[test]
+    fn pmp_view() {
+        let devices = DeviceRegistry::from_platform();
+        let mut pmp = PmpGroup::init_pmp_group(16, &devices);
+        let policy_pmp = POLICY_OFFSET + pmp.request_policy_pmp().unwrap();
+        pmp.set_napot(policy_pmp, 0xc0000000, 0x1000, pmpcfg::R);
+
+        // The firmware protects a page with a TOR entry, and allows everything else
+        let mut virt_pmpaddr = [0; 64];
+        let mut virt_pmpcfg = [0; 8];
+        virt_pmpaddr[0] = 0x80000000 >> 2;
+        virt_pmpaddr[1] = 0x80001000 >> 2;
+        virt_pmpaddr[2] = usize::MAX;
+        virt_pmpcfg[0] =
+            ((pmpcfg::TOR as usize) << 8) | ((pmpcfg::NAPOT | pmpcfg::RWX) as usize) << 16;
+
+        let mut entries = Vec::new();
+        for_each_entry(&pmp, &virt_pmpaddr, &virt_pmpcfg, 3, |entry| {
+            entries.push(entry)
+        });
+
+        // All physical entries but the virtual slots are listed, with the virtual entries
+        assert_eq!(entries.len(), 16 - pmp.virt_pmp_slots + 3);
+        assert_eq!(entries[0].owner, pmp_view::MIRALIS);
+        assert_eq!(entries[DEVICES_OFFSET].owner, pmp_view::DEVICE);
+
+        let policy = entries[policy_pmp];
+        assert_eq!(
+            (policy.owner, policy.start, policy.size),
+            (pmp_view::POLICY, 0xc0000000, 0x1000)
+        );
+
+        let first_virtual = pmp.virt_pmp_offset;
+        let tor = entries[first_virtual + 1];
+        assert_eq!((tor.owner, tor.index), (pmp_view::VIRTUAL, 1));
+        assert_eq!((tor.start, tor.size), (0x80000000, 0x1000));
+        let napot = entries[first_virtual + 2];
+        assert_eq!((napot.start, napot.size), (0, u64::MAX));
+        assert_eq!(entries[first_virtual].size, 0);
+        assert_eq!(entries.last().unwrap().owner, pmp_view::MIRALIS);
+    }
+}
@@ -4,19 +4,27 @@ use core::sync::atomic::{AtomicBool, Ordering};
 use core::sync::atomic::Ordering::SeqCst;
 use crate::ace::core::architecture::control_status_registers::ReadWriteRiscvCsr;
 use crate::ace::core::architecture::CSR;
-use crate::ace::core::control_data::HardwareHart;
+use crate::ace::core::control_data::{ControlDataStorage, HardwareHart};
 use crate::ace::core::initialization::{ace_setup_this_hart, HARTS_STATES};
+use crate::ace::core::memory_layout::{MemoryLayout, MAX_CONFIDENTIAL_MEMORY_REGIONS};
+use crate::ace::core::page_allocator::PageAllocator;
 use crate::arch::{parse_mpp_return_mode, Arch, Architecture};
-use crate::device_tree::divide_memory_region_size;
+use crate::config::ACE_CONFIDENTIAL_MEMORY;
+use crate::deferred::Work;
+use crate::device_tree::{divide_memory_region_size, limit_memory_region_end};
 use crate::host::MiralisContext;
 use crate::monitor_switch::{
     address_to_miralis_context, address_to_policy, address_to_virt_context,
     overwrite_hardware_hart_with_virtctx, overwrite_virtctx_with_hardware_hart,
 };
+use crate::policy::options::PolicyOption;
+use crate::platform::{Plat, Platform};
 use crate::policy::{Policy, PolicyHookResult, PolicyModule};
+use crate::timer::TimerEvent;
 use crate::virt::VirtContext;
 use crate::{ace, handle_trap, main_loop};
 
+#[cfg(not(feature = "userspace"))]
 extern "C" {
     // Assembly function that is an entry point to the security monitor from the hypervisor or a virtual machine.
     fn enter_from_hypervisor_or_vm_asm() -> !;
@@ -25,13 +33,26 @@ extern "C" {
     /// never returns to KVM with other state. For example, only a subset of exceptions/interrupts can be handled by KVM.
     /// KVM kill the vcpu if it receives unexpected exception because it does not know what to do with it.
     fn exit_to_hypervisor_asm() -> !;
+}
+
+#[cfg(feature = "userspace")]
+use crate::ace::core::architecture::riscv::userspace::{
+    self, enter_from_hypervisor_or_vm_asm, exit_to_hypervisor_asm, HostExit,
+};
 
+extern "C" {
     // Miralis raw trap handler
     fn _raw_trap_handler();
 }
 
 /// The ACE policy, which colocates the ACE security monitor with Miralis
-pub struct AcePolicy {}
+pub struct AcePolicy {
+    idle_scrubbed_pages: usize,
+}
+
+/// Maximum number of 4KiB pages zeroed by the page scrubber each time the firmware waits for an interrupt
+const IDLE_SCRUBBED_PAGES: PolicyOption<usize> =
+    PolicyOption::new("ace", "idle-scrubbed-pages", 64);
 
 /// This functions transfers the control to the ACE security monitor from Miralis
 fn miralis_to_ace_ctx_switch(
@@ -92,7 +113,12 @@ fn miralis_to_ace_ctx_switch(
     }
 }
 
+#[cfg_attr(feature = "userspace", allow(unreachable_code))]
 pub fn ace_to_miralis_ctx_switch(ace_ctx: &mut HardwareHart) -> ! {
+    // There is no Miralis to switch to on the host, the request leaves the security monitor instead
+    #[cfg(feature = "userspace")]
+    userspace::exit(HostExit::Miralis);
+
     // Step 0: Get miralis contexts
     let ctx: &mut VirtContext = address_to_virt_context(ace_ctx.ctx_ptr);
     let mctx: &mut MiralisContext = address_to_miralis_context(ace_ctx.mctx_ptr);
@@ -149,6 +175,10 @@ pub fn ace_to_miralis_ctx_switch(ace_ctx: &mut HardwareHart) -> ! {
     // Step 3: Change trap handler - install Miralis trap handler
     Arch::install_handler(_raw_trap_handler as usize);
 
+    // The security monitor might have released pages, they are scrubbed before re-entering the
+    // guest
+    ctx.defer(Work::ScrubMemory);
+
     // Step 4: Jump in the Miralis trap handler - and enter the main loop
     log::debug!("Payload -> Firmware {:?}", ctx.trap_info);
     handle_trap(ctx, mctx, policy);
@@ -162,10 +192,12 @@ impl PolicyModule for AcePolicy {
     fn init(mctx: &mut MiralisContext, device_tree_blob_addr: usize) -> Self {
         // INIT ACE
         if mctx.hw.hart == 0 {
-            // Step 1: Break forward tree
-            match divide_memory_region_size(device_tree_blob_addr) {
-                Ok(_) => log::debug!("Splitted the device tree with success"),
-                Err(e) => log::error!("Failed to split the device tree {:?}", e),
+            // Step 1: Break forward tree, unless the confidential memory regions are configured
+            if ACE_CONFIDENTIAL_MEMORY.is_empty() {
+                match divide_memory_region_size(device_tree_blob_addr) {
+                    Ok(_) => log::debug!("Splitted the device tree with success"),
+                    Err(e) => log::error!("Failed to split the device tree {:?}", e),
+                }
             }
 
             // Step 2: Initialise
@@ -173,6 +205,17 @@ impl PolicyModule for AcePolicy {
                 Ok(_) => log::info!("Initialized ACE security monitor."),
                 Err(e) => log::error!("Error occurred: {:?}", e),
             }
+
+            // Step 2b: Hide the configured confidential memory regions from the payload
+            if !ACE_CONFIDENTIAL_MEMORY.is_empty() {
+                let (confidential_memory_start, _) =
+                    MemoryLayout::read().confidential_memory_boundary();
+                if let Err(e) =
+                    limit_memory_region_end(device_tree_blob_addr, confidential_memory_start)
+                {
+                    log::error!("Failed to shrink the device tree memory {:?}", e);
+                }
+            }
             SETUP_READY.store(true, Ordering::SeqCst);
         } else {
             while !SETUP_READY.load(Ordering::SeqCst) {
@@ -185,7 +228,9 @@ impl PolicyModule for AcePolicy {
         ace_setup_this_hart(mctx);
         // END INIT ACE
 
-        AcePolicy {}
+        AcePolicy {
+            idle_scrubbed_pages: IDLE_SCRUBBED_PAGES.read(device_tree_blob_addr),
+        }
     }
 
     fn name() -> &'static str {
@@ -222,5 +267,34 @@ impl PolicyModule for AcePolicy {
         todo!("Implement on_interrupt for ace security monitor")
     }
 
-    const NUMBER_PMPS: usize = 2;
+    /// Cancels the requests of confidential harts the hypervisor did not respond to in time, even
+    /// if the hypervisor never resumes them.
+    fn on_timer(&mut self, ctx: &mut VirtContext, _mctx: &mut MiralisContext) {
+        let vclint = Plat::get_vclint();
+        let now = Plat::get_clint().lock().read_mtime();
+        let next_deadline = ControlDataStorage::expire_resumable_operations(now);
+        if let Err(error) = vclint.set_deadline(ctx.hart_id, TimerEvent::Policy, next_deadline) {
+            log::warn!("Failed to arm the resumable operation deadline: {}", error);
+        }
+    }
+
+    /// Zeroes the pages released by confidential VMs while the hart is idle.
+    fn on_idle(&mut self, _ctx: &mut VirtContext, _mctx: &mut MiralisContext) {
+        PageAllocator::scrub_dirty_pages(self.idle_scrubbed_pages);
+    }
+
+    /// Zeroes the pages released by confidential VMs since the last exit of the security monitor.
+    fn scrub_memory(&mut self, _ctx: &mut VirtContext, _mctx: &mut MiralisContext) {
+        PageAllocator::scrub_dirty_pages(self.idle_scrubbed_pages);
+    }
+
+    fn reserved_memory(&self) -> Option<(usize, usize)> {
+        let (start, end) = MemoryLayout::read().confidential_memory_boundary();
+        Some((start, end - start))
+    }
+
+    /// Two entries for every confidential memory region
+    const NUMBER_PMPS: usize = 2 * MAX_CONFIDENTIAL_MEMORY_REGIONS;
+    const REQUIRES_S_MODE: bool = true;
+    const HOSTS_ACE: bool = true;
 }
@@ -0,0 +1,260 @@
+//! The isolate payload policy, which prevents the firmware from accessing the memory of the
+//! payload once the payload has booted.
+//!
+//! The protected regions are declared in the device tree passed at boot, as nodes compatible with
+//! `miralis,isolated-memory` whose `reg` property holds the region. If the device tree declares
+//! none, the memory from the payload start address up to the end of the memory is protected.
+//!
+//! The firmware legitimately needs to access payload buffers passed to some SBI services, such as
+//! the debug console. For those services the policy grants the firmware temporary access to the
+//! buffer, until the firmware returns to the payload.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::arch::pmp::pmpcfg;
+use crate::arch::pmp::pmplayout::POLICY_OFFSET;
+use crate::arch::{Arch, Architecture, MCause, Register};
+use crate::config::TARGET_PAYLOAD_ADDRESS;
+use crate::device_tree::{self, MmioRegion};
+use crate::host::{MiralisContext, PolicyRegion};
+use crate::platform::{Plat, Platform};
+use crate::policy::options::PolicyOption;
+use crate::policy::PolicyModule;
+use crate::virt::{ExecutionMode, RegisterContextGetter, VirtContext};
+
+/// Compatible string of the device tree nodes declaring protected regions.
+const ISOLATED_MEMORY_COMPATIBLE: &str = "miralis,isolated-memory";
+
+/// Maximum number of protected regions, each is backed by two PMP entries.
+const MAX_REGIONS: usize = 2;
+
+/// PMP entries (two) granting the firmware access to a payload buffer.
+const GRANT_OFFSET: usize = POLICY_OFFSET;
+/// PMP entries (two per region) denying the firmware access to the protected regions.
+const REGIONS_OFFSET: usize = POLICY_OFFSET + 2;
+
+/// Maximum size of a buffer the firmware is granted access to.
+const MAX_GRANT_SIZE: PolicyOption<usize> =
+    PolicyOption::new("isolate_payload", "max-grant-size", 0x1000);
+
+/// Set once the payload booted, from then on the regions are protected.
+static PAYLOAD_BOOTED: AtomicBool = AtomicBool::new(false);
+
+/// An SBI service whose arguments include a payload buffer.
+struct SharedBufferService {
+    eid: usize,
+    fid: usize,
+    /// Register holding the start address of the buffer.
+    address: Register,
+    /// Register holding the size of the buffer.
+    size: Register,
+}
+
+/// The SBI services the firmware is granted access to a payload buffer for.
+const SHARED_BUFFER_SERVICES: &[SharedBufferService] = &[
+    // Debug console write
+    SharedBufferService {
+        eid: 0x4442434E,
+        fid: 0,
+        address: Register::X11,
+        size: Register::X10,
+    },
+    // Debug console read
+    SharedBufferService {
+        eid: 0x4442434E,
+        fid: 1,
+        address: Register::X11,
+        size: Register::X10,
+    },
+];
+
+/// The isolate payload policy module.
+pub struct IsolatePayloadPolicy {
+    max_grant_size: usize,
+}
+
+impl PolicyModule for IsolatePayloadPolicy {
+    fn init(mctx: &mut MiralisContext, device_tree_blob_addr: usize) -> Self {
+        let mut regions = mctx.shared.policy_regions.lock();
+        if regions.iter().all(Option::is_none) {
+            for (slot, region) in regions.iter_mut().zip(
+                protected_regions(device_tree_blob_addr)
+                    .into_iter()
+                    .flatten(),
+            ) {
+                log::info!(
+                    "Isolating payload memory 0x{:x}-0x{:x}",
+                    region.start,
+                    region.start.saturating_add(region.size)
+                );
+                *slot = Some(region);
+            }
+        }
+
+        IsolatePayloadPolicy {
+            max_grant_size: MAX_GRANT_SIZE.read(device_tree_blob_addr),
+        }
+    }
+
+    fn name() -> &'static str {
+        "Isolate Payload Policy"
+    }
+
+    fn switch_from_payload_to_firmware(
+        &mut self,
+        ctx: &mut VirtContext,
+        mctx: &mut MiralisContext,
+    ) {
+        lock_regions(mctx);
+        let grant = shared_buffer(ctx, self.max_grant_size);
+        if let Some(buffer) = grant {
+            log::trace!(
+                "Granting firmware access to 0x{:x}-0x{:x}",
+                buffer.start,
+                buffer.start + buffer.size
+            );
+        }
+        set_grant(mctx, grant);
+    }
+
+    fn switch_from_firmware_to_payload(
+        &mut self,
+        _ctx: &mut VirtContext,
+        mctx: &mut MiralisContext,
+    ) {
+        // The payload has full access to its own memory
+        set_grant(mctx, None);
+        for idx in 0..2 * MAX_REGIONS {
+            mctx.pmp.set_inactive(REGIONS_OFFSET + idx, 0);
+        }
+
+        if PAYLOAD_BOOTED
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            // Lock the memory on the harts still running the firmware
+            Plat::broadcast_policy_interrupt();
+        }
+    }
+
+    // A policy interrupt signals that the payload booted
+    fn on_interrupt(&mut self, ctx: &mut VirtContext, mctx: &mut MiralisContext) {
+        if ctx.mode.to_exec_mode() == ExecutionMode::Firmware {
+            lock_regions(mctx);
+            // SAFETY: the PMP configuration is the one of the firmware, which is currently running.
+            unsafe { Arch::write_pmp(&mctx.pmp).flush() };
+        }
+    }
+
+    const NUMBER_PMPS: usize = 2 + 2 * MAX_REGIONS;
+    const REQUIRES_S_MODE: bool = true;
+}
+
+/// Returns the regions to protect, as declared in the device tree.
+fn protected_regions(device_tree_blob_addr: usize) -> [Option<PolicyRegion>; MAX_REGIONS] {
+    let mut declared = [MmioRegion { base: 0, size: 0 }; MAX_REGIONS];
+    let count = match device_tree_blob_addr {
+        0 => 0,
+        // SAFETY: the previous boot stage passes a valid device tree when the address is not null.
+        _ => unsafe {
+            device_tree::find_compatible_regions(
+                device_tree_blob_addr,
+                ISOLATED_MEMORY_COMPATIBLE,
+                &mut declared,
+            )
+        }
+        .unwrap_or_else(|err| {
+            log::warn!("Failed to read the isolated memory regions: {}", err);
+            0
+        }),
+    };
+
+    let mut regions = [None; MAX_REGIONS];
+    if count == 0 {
+        // Protect the memory from the payload start address onward
+        let end = device_tree::layout()
+            .memory
+            .map_or(usize::MAX, |memory| memory.base + memory.size);
+        regions[0] = Some(PolicyRegion {
+            start: TARGET_PAYLOAD_ADDRESS,
+            size: end.saturating_sub(TARGET_PAYLOAD_ADDRESS),
+        });
+    }
+    for (slot, region) in regions.iter_mut().zip(&declared[..count]) {
+        *slot = Some(PolicyRegion {
+            start: region.base,
+            size: region.size,
+        });
+    }
+    regions
+}
+
+/// Denies the firmware all accesses to the protected regions, once the payload booted.
+fn lock_regions(mctx: &mut MiralisContext) {
+    let booted = PAYLOAD_BOOTED.load(Ordering::SeqCst);
+    let regions = mctx.shared.policy_regions.lock();
+    for (idx, region) in regions.iter().take(MAX_REGIONS).enumerate() {
+        let start_idx = REGIONS_OFFSET + 2 * idx;
+        match region {
+            Some(region) if booted => {
+                mctx.pmp.set_inactive(start_idx, region.start);
+                mctx.pmp.set_tor(
+                    start_idx + 1,
+                    region.start.saturating_add(region.size),
+                    pmpcfg::NO_PERMISSIONS,
+                );
+            }
+            _ => {
+                mctx.pmp.set_inactive(start_idx, 0);
+                mctx.pmp.set_inactive(start_idx + 1, 0);
+            }
+        }
+    }
+}
+
+/// Configures the PMP entries granting the firmware access to a payload buffer.
+///
+/// The grant entries come before the protected regions, and therefore take precedence.
+fn set_grant(mctx: &mut MiralisContext, buffer: Option<PolicyRegion>) {
+    match buffer {
+        Some(buffer) => {
+            mctx.pmp.set_inactive(GRANT_OFFSET, buffer.start);
+            mctx.pmp.set_tor(
+                GRANT_OFFSET + 1,
+                buffer.start + buffer.size,
+                pmpcfg::R | pmpcfg::W,
+            );
+        }
+        None => {
+            mctx.pmp.set_inactive(GRANT_OFFSET, 0);
+            mctx.pmp.set_inactive(GRANT_OFFSET + 1, 0);
+        }
+    }
+}
+
+/// Returns the payload buffer the firmware needs to access to serve the trap, if any.
+///
+/// This is the hook deciding which SBI services are legitimate: only the buffers of the services
+/// listed in [SHARED_BUFFER_SERVICES] are granted, up to `max_grant_size` bytes.
+fn shared_buffer(ctx: &VirtContext, max_grant_size: usize) -> Option<PolicyRegion> {
+    if ctx.trap_info.get_cause() != MCause::EcallFromSMode {
+        return None;
+    }
+
+    let (eid, fid) = (ctx.get(Register::X17), ctx.get(Register::X16));
+    let service = SHARED_BUFFER_SERVICES
+        .iter()
+        .find(|service| service.eid == eid && service.fid == fid)?;
+    let buffer = PolicyRegion {
+        start: ctx.get(service.address),
+        size: ctx.get(service.size),
+    };
+
+    if buffer.size == 0
+        || buffer.size > max_grant_size
+        || buffer.start.checked_add(buffer.size).is_none()
+    {
+        return None;
+    }
+    Some(buffer)
+}
@@ -233,4 +233,5 @@ impl PolicyModule for KeystonePolicy {
     fn on_interrupt(&mut self, _ctx: &mut VirtContext, _mctx: &mut MiralisContext) {}
 
     const NUMBER_PMPS: usize = 0;
+    const REQUIRES_S_MODE: bool = true;
 }
@@ -2,23 +2,140 @@
 //!
 //! This modules holds the definitions of policy modules for Miralis.
 
+//!
+//! Each policy can be compiled out with its cargo feature, selecting a policy which is not compiled
+//! in is a build error. ACE is the default policy when enabled, the default policy otherwise.
+//!
+//! A second policy can be stacked on top of the selected one (see [stack]), such as the SBI
+//! firewall on top of a memory protection policy.
+
 use config_select::select_env;
 
+use crate::config::{POLICY_NAME, POLICY_STACK};
 use crate::host::MiralisContext;
+use crate::suspend::SuspendRequest;
 use crate::virt::VirtContext;
 
+#[cfg(feature = "ace")]
 pub mod ace;
 mod default;
+#[cfg(feature = "policy_isolate_payload")]
+mod isolate_payload;
+#[cfg(feature = "policy_keystone")]
 mod keystone;
+#[cfg(any(
+    feature = "ace",
+    feature = "policy_isolate_payload",
+    feature = "policy_sbi_firewall",
+    test
+))]
+mod options;
+#[cfg(feature = "policy_protect_domains")]
+mod protect_domains;
+#[cfg(feature = "policy_protect_payload")]
 mod protect_payload;
+#[cfg(feature = "policy_sbi_firewall")]
+mod sbi_firewall;
+mod stack;
+#[cfg(test)]
+mod testing;
+
+/// The active policy: the selected policy, with the stacked policy (if any) invoked after it.
+pub type Policy = stack::PolicyStack<SelectedPolicy, StackedPolicy>;
 
-pub type Policy = select_env!["MIRALIS_POLICY_NAME":
+#[cfg(feature = "ace")]
+type SelectedPolicy = select_env!["MIRALIS_POLICY_NAME":
     "keystone" => keystone::KeystonePolicy
     "protect_payload" => protect_payload::ProtectPayloadPolicy
+    "protect_domains" => protect_domains::ProtectDomainsPolicy
+    "isolate_payload" => isolate_payload::IsolatePayloadPolicy
+    "sbi_firewall" => sbi_firewall::SbiFirewallPolicy
     _ => ace::AcePolicy
-    // _          => default::DefaultPolicy
 ];
 
+#[cfg(not(feature = "ace"))]
+type SelectedPolicy = select_env!["MIRALIS_POLICY_NAME":
+    "keystone" => keystone::KeystonePolicy
+    "protect_payload" => protect_payload::ProtectPayloadPolicy
+    "protect_domains" => protect_domains::ProtectDomainsPolicy
+    "isolate_payload" => isolate_payload::IsolatePayloadPolicy
+    "sbi_firewall" => sbi_firewall::SbiFirewallPolicy
+    _ => default::DefaultPolicy
+];
+
+/// The policy stacked on top of the selected one, the default policy does nothing.
+type StackedPolicy = select_env!["MIRALIS_POLICY_STACK":
+    "keystone" => keystone::KeystonePolicy
+    "protect_payload" => protect_payload::ProtectPayloadPolicy
+    "protect_domains" => protect_domains::ProtectDomainsPolicy
+    "isolate_payload" => isolate_payload::IsolatePayloadPolicy
+    "sbi_firewall" => sbi_firewall::SbiFirewallPolicy
+    _ => default::DefaultPolicy
+];
+
+/// Returns true if the policy is either the selected or the stacked policy.
+pub const fn is_selected(name: &str) -> bool {
+    const fn equals(a: &[u8], b: &[u8]) -> bool {
+        if a.len() != b.len() {
+            return false;
+        }
+        let mut idx = 0;
+        while idx < a.len() {
+            if a[idx] != b[idx] {
+                return false;
+            }
+            idx += 1;
+        }
+        true
+    }
+
+    equals(POLICY_NAME.as_bytes(), name.as_bytes())
+        || equals(POLICY_STACK.as_bytes(), name.as_bytes())
+}
+
+// Selecting a policy which is compiled out is reported with an explicit error.
+
+#[cfg(not(feature = "policy_keystone"))]
+const _: () = assert!(
+    !is_selected("keystone"),
+    "The keystone policy requires the `policy_keystone` feature"
+);
+
+#[cfg(not(feature = "policy_protect_payload"))]
+const _: () = assert!(
+    !is_selected("protect_payload"),
+    "The protect payload policy requires the `policy_protect_payload` feature"
+);
+
+#[cfg(not(feature = "policy_protect_domains"))]
+const _: () = assert!(
+    !is_selected("protect_domains"),
+    "The protect domains policy requires the `policy_protect_domains` feature"
+);
+
+#[cfg(not(feature = "policy_isolate_payload"))]
+const _: () = assert!(
+    !is_selected("isolate_payload"),
+    "The isolate payload policy requires the `policy_isolate_payload` feature"
+);
+
+#[cfg(not(feature = "policy_sbi_firewall"))]
+const _: () = assert!(
+    !is_selected("sbi_firewall"),
+    "The SBI firewall policy requires the `policy_sbi_firewall` feature"
+);
+
+#[cfg(not(feature = "ace"))]
+const _: () = assert!(
+    !matches!(POLICY_NAME.as_bytes(), b"ace"),
+    "The ACE policy requires the `ace` feature"
+);
+
+const _: () = assert!(
+    !matches!(POLICY_STACK.as_bytes(), b"ace"),
+    "The ACE policy can not be stacked"
+);
+
 /// The result of a call into a policy hook function
 ///
 /// A policy module can either overwrite standard Miralis emulation, or ignore an event and let
@@ -50,7 +167,12 @@ impl PolicyHookResult {
 /// therefore without any policy the firmware is not restricted in any way.
 /// The role of a policy module is to enforce a set of policies on the firmware, for instance
 /// restricting which memory is accessible to the firmware, how which `ecall`s are intercepted.
+///
+/// A policy is instantiated on each hart, and its hooks receive the [MiralisContext] of the
+/// calling hart. State that must be consistent across harts, such as the memory regions protected
+/// by the policy, belongs to the [shared context](crate::host::SharedContext).
 pub trait PolicyModule {
+    /// Creates the policy of the calling hart.
     fn init(mctx: &mut MiralisContext, device_tree_blob_addr: usize) -> Self;
     fn name() -> &'static str;
 
@@ -102,6 +224,41 @@ pub trait PolicyModule {
         PolicyHookResult::Ignore
     }
 
+    /// Validate a request from the payload to suspend the current hart or the whole system.
+    ///
+    /// The request is forwarded to the firmware if allowed, otherwise the payload receives
+    /// `SBI_ERR_DENIED`.
+    fn allow_suspend(&mut self, request: &SuspendRequest) -> bool {
+        let _ = request;
+        true
+    }
+
+    /// Hide an SBI extension from the payload, in addition to the configured deny-list.
+    ///
+    /// Calls to hidden extensions return `SBI_ERR_NOT_SUPPORTED` without reaching the firmware.
+    fn hide_sbi_extension(&mut self, eid: usize) -> bool {
+        let _ = eid;
+        false
+    }
+
+    /// Whether Miralis may emulate a trapping misaligned load or store, rather than forwarding the
+    /// trap to the firmware.
+    ///
+    /// Only called if misaligned emulation is enabled, the access is performed with the privileges
+    /// of the trapping mode.
+    fn allow_misaligned_emulation(&mut self, ctx: &VirtContext) -> bool {
+        let _ = ctx;
+        true
+    }
+
+    /// Returns the start and size of a memory region reserved by the policy, if any.
+    ///
+    /// The region is reported in the memory layout exposed to the firmware and the payload, such
+    /// that they do not try to use it.
+    fn reserved_memory(&self) -> Option<(usize, usize)> {
+        None
+    }
+
     fn switch_from_payload_to_firmware(&mut self, ctx: &mut VirtContext, mctx: &mut MiralisContext);
 
     fn switch_from_firmware_to_payload(&mut self, ctx: &mut VirtContext, mctx: &mut MiralisContext);
@@ -114,5 +271,49 @@ pub trait PolicyModule {
     /// synchronisation is critical for security.
     fn on_interrupt(&mut self, ctx: &mut VirtContext, mctx: &mut MiralisContext);
 
+    /// Callback for the policy timer.
+    ///
+    /// Called on the hart on which the deadline registered for `TimerEvent::Policy` with
+    /// `VirtClint::set_deadline` expired. The deadline is cleared before the call, periodic
+    /// policies (e.g. watchdogs) must register the next one.
+    fn on_timer(&mut self, ctx: &mut VirtContext, mctx: &mut MiralisContext) {
+        let _ = ctx;
+        let _ = mctx;
+    }
+
+    /// Callback for idle time.
+    ///
+    /// Called when the firmware waits for an interrupt, right before the hart is put to sleep.
+    /// Policies can use it for background work, which must be short as it delays the handling of
+    /// the interrupt waking up the hart.
+    fn on_idle(&mut self, ctx: &mut VirtContext, mctx: &mut MiralisContext) {
+        let _ = ctx;
+        let _ = mctx;
+    }
+
+    /// Callback for deferred memory scrubbing.
+    ///
+    /// Called before re-entering the guest after the policy posted `Work::ScrubMemory` with
+    /// `VirtContext::defer`. Policies releasing memory from their trap handlers can use it to zero
+    /// the released memory without delaying the handling of the trap.
+    fn scrub_memory(&mut self, ctx: &mut VirtContext, mctx: &mut MiralisContext) {
+        let _ = ctx;
+        let _ = mctx;
+    }
+
+    /// Number of PMP entries statically reserved for the policy, starting at `POLICY_OFFSET`.
+    ///
+    /// Policies needing a number of entries only known at runtime can set it to zero and request
+    /// entries from `mctx.pmp` instead (see `PmpGroup::request_policy_pmp`).
     const NUMBER_PMPS: usize;
+
+    /// Whether the policy relies on the payload running in S-mode.
+    ///
+    /// Miralis refuses to boot a policy requiring S-mode on cores that only implement M and U
+    /// modes, rather than silently running without the expected protections.
+    const REQUIRES_S_MODE: bool = false;
+
+    /// Whether the policy hosts the ACE security monitor, which implements the CoVE SBI extensions
+    /// (NACL, COVH, COVI and COVG).
+    const HOSTS_ACE: bool = false;
 }
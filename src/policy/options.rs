@@ -0,0 +1,203 @@
+//! Policy configuration options.
+//!
+//! Policy modules declare their tunables (e.g. sizes or default actions) as typed
+//! [PolicyOption]s rather than hardcoded constants. The value of an option is read when the policy
+//! is initialized, from the first of the following sources defining it:
+//!
+//! 1. The device tree passed at boot, as a property of the node compatible with
+//!    `miralis,policy-options`. The property is named `<policy>.<option>`, for instance
+//!    `isolate_payload.max-grant-size = <0x2000>;`.
+//! 2. The build configuration, as an entry `<policy>.<option>=<value>` of the `policy.options`
+//!    list, for instance `"isolate_payload.max-grant-size=0x2000"`.
+//! 3. The default value declared by the policy.
+//!
+//! Values that can not be parsed are reported and ignored, the next source is used instead.
+
+use core::fmt::Debug;
+
+use crate::config::POLICY_OPTIONS;
+use crate::device_tree;
+
+/// Compatible string of the device tree node holding the policy options.
+const OPTIONS_COMPATIBLE: &str = "miralis,policy-options";
+
+/// Maximum length of the name of a device tree property holding an option.
+const MAX_PROPERTY_NAME_LEN: usize = 64;
+
+/// Maximum size of a device tree property holding an option.
+const MAX_PROPERTY_SIZE: usize = 16;
+
+/// A typed configuration option of a policy module.
+pub struct PolicyOption<T> {
+    /// The name of the policy, as used to select it in the build configuration.
+    policy: &'static str,
+    /// The name of the option, in kebab case.
+    name: &'static str,
+    default: T,
+}
+
+/// The type of the value of a policy option.
+pub trait OptionValue: Copy + Debug {
+    /// Parses the value from an entry of the build configuration.
+    fn parse(value: &str) -> Option<Self>;
+
+    /// Parses the value from a device tree property.
+    fn from_property(property: &[u8]) -> Option<Self>;
+}
+
+impl<T: OptionValue> PolicyOption<T> {
+    pub const fn new(policy: &'static str, name: &'static str, default: T) -> Self {
+        PolicyOption {
+            policy,
+            name,
+            default,
+        }
+    }
+
+    /// Reads the value of the option, see the [module documentation](self) for the sources.
+    pub fn read(&self, device_tree_blob_addr: usize) -> T {
+        let value = self
+            .read_device_tree(device_tree_blob_addr)
+            .or_else(|| self.read_build_config(&POLICY_OPTIONS[..]))
+            .unwrap_or(self.default);
+        log::debug!("Policy option {}.{}: {:?}", self.policy, self.name, value);
+        value
+    }
+
+    fn read_build_config(&self, entries: &[&str]) -> Option<T> {
+        let value = entries.iter().find_map(|entry| {
+            let (key, value) = entry.split_once('=')?;
+            self.is_named(key.trim()).then_some(value.trim())
+        })?;
+        self.check(T::parse(value), "build configuration")
+    }
+
+    fn read_device_tree(&self, device_tree_blob_addr: usize) -> Option<T> {
+        if device_tree_blob_addr == 0 {
+            return None;
+        }
+
+        let mut name = [0u8; MAX_PROPERTY_NAME_LEN];
+        let name = self.property_name(&mut name)?;
+        let mut property = [0u8; MAX_PROPERTY_SIZE];
+        // SAFETY: the previous boot stage passes a valid device tree when the address is not null.
+        let size = unsafe {
+            device_tree::find_compatible_property(
+                device_tree_blob_addr,
+                OPTIONS_COMPATIBLE,
+                name,
+                &mut property,
+            )
+        }
+        .unwrap_or_else(|err| {
+            log::warn!("Failed to read the policy options: {}", err);
+            None
+        })?;
+        self.check(
+            property.get(..size).and_then(T::from_property),
+            "device tree",
+        )
+    }
+
+    /// Returns true if `key` is the name of this option, prefixed by the name of its policy.
+    fn is_named(&self, key: &str) -> bool {
+        key.split_once('.') == Some((self.policy, self.name))
+    }
+
+    /// Writes the name of the device tree property holding the option into `buffer`.
+    fn property_name<'a>(&self, buffer: &'a mut [u8]) -> Option<&'a str> {
+        let len = self.policy.len() + 1 + self.name.len();
+        let name = buffer.get_mut(..len)?;
+        let (policy, option) = name.split_at_mut(self.policy.len());
+        policy.copy_from_slice(self.policy.as_bytes());
+        option[0] = b'.';
+        option[1..].copy_from_slice(self.name.as_bytes());
+        core::str::from_utf8(name).ok()
+    }
+
+    /// Reports values that are defined but can not be parsed.
+    fn check(&self, value: Option<T>, source: &str) -> Option<T> {
+        if value.is_none() {
+            log::warn!(
+                "Invalid value for policy option {}.{} in the {}",
+                self.policy,
+                self.name,
+                source
+            );
+        }
+        value
+    }
+}
+
+impl OptionValue for usize {
+    /// Values are written in decimal, or in hexadecimal with a `0x` prefix.
+    fn parse(value: &str) -> Option<Self> {
+        match value.strip_prefix("0x") {
+            Some(hex) => usize::from_str_radix(hex, 16).ok(),
+            None => value.parse().ok(),
+        }
+    }
+
+    /// Properties are either one or two cells wide.
+    fn from_property(property: &[u8]) -> Option<Self> {
+        match property.len() {
+            4 => Some(u32::from_be_bytes(property.try_into().ok()?) as usize),
+            8 => Some(u64::from_be_bytes(property.try_into().ok()?) as usize),
+            _ => None,
+        }
+    }
+}
+
+impl OptionValue for bool {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => None,
+        }
+    }
+
+    /// As is conventional for device tree flags, an empty property is true. Otherwise the property
+    /// holds a cell, which is true if not zero.
+    fn from_property(property: &[u8]) -> Option<Self> {
+        if property.is_empty() {
+            return Some(true);
+        }
+        usize::from_property(property).map(|value| value != 0)
+    }
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policy_options() {
+        const SIZE: PolicyOption<usize> = PolicyOption::new("test", "size", 0x1000);
+        const FLAG: PolicyOption<bool> = PolicyOption::new("test", "flag", false);
+        let entries = ["other.size=8", "test.size = 0x2000", "test.flag=yes"];
+
+        // The first entry named after the option is used, invalid values are ignored
+        assert_eq!(SIZE.read_build_config(&entries), Some(0x2000));
+        assert_eq!(FLAG.read_build_config(&entries), None);
+        assert_eq!(SIZE.read_build_config(&[]), None);
+        assert_eq!(SIZE.read(0), 0x1000);
+
+        let mut buffer = [0u8; MAX_PROPERTY_NAME_LEN];
+        assert_eq!(SIZE.property_name(&mut buffer), Some("test.size"));
+        assert_eq!(SIZE.property_name(&mut buffer[..4]), None);
+
+        // Device tree properties
+        assert_eq!(usize::from_property(&[0, 0, 0x20, 0]), Some(0x2000));
+        assert_eq!(
+            usize::from_property(&[0, 0, 0, 1, 0, 0, 0, 0]),
+            Some(1 << 32)
+        );
+        assert_eq!(usize::from_property(&[0, 1]), None);
+        assert_eq!(bool::from_property(&[]), Some(true));
+        assert_eq!(bool::from_property(&[0, 0, 0, 0]), Some(false));
+        assert_eq!(usize::parse("42"), Some(42));
+    }
+}
@@ -0,0 +1,210 @@
+//! The protect domains policy, which lets the payload protect multiple memory domains from the
+//! firmware.
+//!
+//! This policy generalizes the protect payload policy: instead of locking the whole payload memory
+//! at once, the payload registers independent protection domains (e.g. kernel secrets, userspace
+//! secrets, or per-container regions) through the protect domains SBI extension. Each domain is a
+//! naturally aligned power-of-two region backed by one PMP entry, which denies all accesses while
+//! the firmware runs and is disabled while the payload runs.
+//!
+//! Domains are shared by all harts, and stored as the policy regions of the
+//! [SharedContext](crate::host::SharedContext). Domains must lie within the payload memory. Harts
+//! running the firmware when a domain is registered are notified with a policy interrupt, and the
+//! registration returns to the payload only once all online harts acknowledged it, such that the
+//! domain is enforced everywhere by then.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use miralis_core::abi_protect_domains;
+
+use crate::arch::pmp::pmplayout::POLICY_OFFSET;
+use crate::arch::pmp::{build_napot, pmpcfg};
+use crate::arch::{Arch, Architecture, Register};
+use crate::config::{PLATFORM_NB_HARTS, TARGET_PAYLOAD_ADDRESS};
+use crate::device_tree;
+use crate::host::{MiralisContext, PolicyRegion, SharedContext};
+use crate::platform::{self, Plat, Platform};
+use crate::policy::{PolicyHookResult, PolicyModule};
+use crate::sbi::{self, SbiExtension};
+use crate::virt::{ExecutionMode, RegisterContextGetter, RegisterContextSetter, VirtContext};
+
+/// Maximum number of domains, one PMP entry is reserved for each.
+const MAX_DOMAINS: usize = 4;
+
+const SBI_ERR_FAILED: isize = -1;
+const SBI_ERR_INVALID_PARAM: isize = -3;
+const SBI_ERR_INVALID_ADDRESS: isize = -5;
+
+/// Generation of the domains, incremented by each registration.
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// The latest generation of the domains acknowledged by each hart.
+static ACKNOWLEDGED: [AtomicUsize; PLATFORM_NB_HARTS] =
+    [const { AtomicUsize::new(0) }; PLATFORM_NB_HARTS];
+
+/// The protect domains policy module.
+pub struct ProtectDomainsPolicy {}
+
+impl PolicyModule for ProtectDomainsPolicy {
+    fn init(_mctx: &mut MiralisContext, _device_tree_blob_addr: usize) -> Self {
+        ProtectDomainsPolicy {}
+    }
+
+    fn name() -> &'static str {
+        "Protect Domains Policy"
+    }
+
+    fn ecall_from_payload(
+        &mut self,
+        mctx: &mut MiralisContext,
+        ctx: &mut VirtContext,
+    ) -> PolicyHookResult {
+        if sbi::route(ctx.get(Register::X17)) != Some(SbiExtension::ProtectDomains) {
+            return PolicyHookResult::Ignore;
+        }
+
+        let result = match ctx.get(Register::X16) {
+            abi_protect_domains::MIRALIS_PROTECT_DOMAINS_REGISTER_FID => {
+                let (start, size) = (ctx.get(Register::X10), ctx.get(Register::X11));
+                register(mctx.shared, ctx.hart_id, start, size)
+            }
+            abi_protect_domains::MIRALIS_PROTECT_DOMAINS_UNREGISTER_FID => {
+                unregister(mctx.shared, ctx.get(Register::X10))
+            }
+            _ => Err(SBI_ERR_INVALID_PARAM),
+        };
+
+        match result {
+            Ok(value) => {
+                ctx.set(Register::X10, 0);
+                ctx.set(Register::X11, value);
+            }
+            Err(error) => {
+                ctx.set(Register::X10, error as usize);
+                ctx.set(Register::X11, 0);
+            }
+        }
+        ctx.pc += 4;
+        PolicyHookResult::Overwrite
+    }
+
+    fn switch_from_payload_to_firmware(
+        &mut self,
+        _ctx: &mut VirtContext,
+        mctx: &mut MiralisContext,
+    ) {
+        lock_domains(mctx);
+    }
+
+    fn switch_from_firmware_to_payload(
+        &mut self,
+        _ctx: &mut VirtContext,
+        mctx: &mut MiralisContext,
+    ) {
+        // The payload has full access to its own domains
+        for idx in 0..MAX_DOMAINS {
+            mctx.pmp.set_inactive(POLICY_OFFSET + idx, 0);
+        }
+    }
+
+    // A policy interrupt signals that the domains changed
+    fn on_interrupt(&mut self, ctx: &mut VirtContext, mctx: &mut MiralisContext) {
+        // Read the generation first, the domains installed below are at least as recent
+        let generation = GENERATION.load(Ordering::SeqCst);
+        if ctx.mode.to_exec_mode() == ExecutionMode::Firmware {
+            lock_domains(mctx);
+            // SAFETY: the PMP configuration is the one of the firmware, which is currently running.
+            unsafe { Arch::write_pmp(&mctx.pmp).flush() };
+        }
+        // Harts running the payload install the domains on the next switch to the firmware
+        ACKNOWLEDGED[ctx.hart_id].fetch_max(generation, Ordering::SeqCst);
+    }
+
+    const NUMBER_PMPS: usize = MAX_DOMAINS;
+}
+
+/// Installs one PMP entry denying all accesses for each registered domain.
+fn lock_domains(mctx: &mut MiralisContext) {
+    let domains = mctx.shared.policy_regions.lock();
+    for (idx, domain) in domains.iter().enumerate() {
+        match domain {
+            Some(domain) => mctx.pmp.set_napot(
+                POLICY_OFFSET + idx,
+                domain.start,
+                domain.size,
+                pmpcfg::NO_PERMISSIONS,
+            ),
+            None => mctx.pmp.set_inactive(POLICY_OFFSET + idx, 0),
+        }
+    }
+}
+
+fn register(
+    shared: &SharedContext,
+    hart: usize,
+    start: usize,
+    size: usize,
+) -> Result<usize, isize> {
+    if build_napot(start, size).is_none() {
+        return Err(SBI_ERR_INVALID_PARAM);
+    }
+    // Only the payload can be protected, the firmware would not be able to run otherwise.
+    if !is_payload_memory(start, size) {
+        return Err(SBI_ERR_INVALID_ADDRESS);
+    }
+
+    let mut domains = shared.policy_regions.lock();
+    let Some(id) = domains.iter().position(Option::is_none) else {
+        log::warn!("No free protection domain");
+        return Err(SBI_ERR_FAILED);
+    };
+    domains[id] = Some(PolicyRegion { start, size });
+    drop(domains);
+
+    log::debug!(
+        "Registered protection domain {}: 0x{:x}-0x{:x}",
+        id,
+        start,
+        start + size
+    );
+    let generation = GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    Plat::broadcast_policy_interrupt();
+    wait_for_acknowledgements(hart, generation);
+    Ok(id)
+}
+
+/// Returns true if the region lies within the payload memory.
+fn is_payload_memory(start: usize, size: usize) -> bool {
+    let end = device_tree::layout()
+        .memory
+        .map_or(usize::MAX, |memory| memory.base + memory.size);
+    start >= TARGET_PAYLOAD_ADDRESS && start.checked_add(size).is_some_and(|top| top <= end)
+}
+
+/// Waits until all the online harts acknowledged the given generation of the domains.
+fn wait_for_acknowledgements(hart: usize, generation: usize) {
+    for other in (0..PLATFORM_NB_HARTS).filter(|&other| platform::is_hart_online(other)) {
+        loop {
+            // The calling hart runs the payload and installs the domains on the next switch to
+            // the firmware. It acknowledges concurrent registrations as well, otherwise two harts
+            // registering at the same time would wait for each other.
+            ACKNOWLEDGED[hart].fetch_max(GENERATION.load(Ordering::SeqCst), Ordering::SeqCst);
+            if ACKNOWLEDGED[other].load(Ordering::SeqCst) >= generation {
+                break;
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+
+fn unregister(shared: &SharedContext, id: usize) -> Result<usize, isize> {
+    let mut domains = shared.policy_regions.lock();
+    match domains.get_mut(id) {
+        Some(domain @ Some(_)) => {
+            *domain = None;
+            log::debug!("Unregistered protection domain {}", id);
+            Ok(0)
+        }
+        _ => Err(SBI_ERR_INVALID_PARAM),
+    }
+}
@@ -1,20 +1,27 @@
 //! The protect payload policy, it protects the payload.
+//!
+//! The payload is measured before its first instruction runs. The payload can then request an
+//! attestation report, binding the measurement of the payload and the Miralis build to a nonce, with
+//! the `MIRALIS_PROTECT_PAYLOAD_REPORT_FID` call.
 
 use core::slice;
 use core::sync::atomic::{AtomicBool, Ordering};
 
-use miralis_core::abi_protect_payload;
+use miralis_core::abi_protect_payload::{self, report};
+use spin::Once;
 use tiny_keccak::{Hasher, Sha3};
 
 use crate::arch::pmp::pmpcfg;
 use crate::arch::pmp::pmplayout::POLICY_OFFSET;
 use crate::arch::{parse_mpp_return_mode, Arch, Architecture, Csr, MCause, Register};
+use crate::build_info;
 use crate::config::{PAYLOAD_HASH_SIZE, TARGET_PAYLOAD_ADDRESS};
 use crate::decoder::Instr;
 use crate::host::MiralisContext;
 use crate::platform::{Plat, Platform};
 use crate::policy::{PolicyHookResult, PolicyModule};
-use crate::virt::{RegisterContextGetter, VirtContext};
+use crate::sbi::{self, SbiExtension};
+use crate::virt::{RegisterContextGetter, RegisterContextSetter, VirtContext};
 
 const LINUX_LOCK_PAYLOAD_HASH: [u8; 32] = [
     241, 90, 158, 184, 200, 210, 145, 178, 30, 80, 200, 161, 56, 120, 75, 241, 68, 38, 21, 2, 248,
@@ -26,8 +33,14 @@ const TEST_POLICY_PAYLOAD: [u8; 32] = [
     166, 149, 149, 158, 179, 237, 5, 158, 54, 245, 69,
 ];
 
+const SBI_ERR_DENIED: isize = -4;
+const SBI_ERR_INVALID_ADDRESS: isize = -5;
+
 static FIRST_JUMP: AtomicBool = AtomicBool::new(true);
 
+/// The measurement of the payload, taken on the first switch to the payload.
+static PAYLOAD_MEASUREMENT: Once<[u8; 32]> = Once::new();
+
 /// The protect payload policy module, which allow the payload to protect himself from the firmware at some point in time and enfore a boundary between the two components.
 pub struct ProtectPayloadPolicy {
     protected: bool,
@@ -120,6 +133,7 @@ impl PolicyModule for ProtectPayloadPolicy {
             Plat::broadcast_policy_interrupt();
 
             let hashed_value = hash_payload(PAYLOAD_HASH_SIZE, ctx.pc);
+            PAYLOAD_MEASUREMENT.call_once(|| hashed_value);
 
             let not_linux_payload = hashed_value != LINUX_LOCK_PAYLOAD_HASH;
             let not_test_payload = hashed_value != TEST_POLICY_PAYLOAD;
@@ -143,6 +157,7 @@ impl PolicyModule for ProtectPayloadPolicy {
     }
 
     const NUMBER_PMPS: usize = 2;
+    const REQUIRES_S_MODE: bool = true;
 }
 
 impl ProtectPayloadPolicy {
@@ -239,23 +254,54 @@ impl ProtectPayloadPolicy {
         ctx: &mut VirtContext,
         mctx: &mut MiralisContext,
     ) -> PolicyHookResult {
-        if !self.is_policy_call(ctx) {
+        if sbi::route(ctx.get(Register::X17)) != Some(SbiExtension::ProtectPayload) {
             return PolicyHookResult::Ignore;
         }
 
-        log::info!("Locking payload from payload");
-        self.lock(mctx, ctx);
+        match ctx.get(Register::X16) {
+            abi_protect_payload::MIRALIS_PROTECT_PAYLOAD_LOCK_FID => {
+                log::info!("Locking payload from payload");
+                self.lock(mctx, ctx);
+            }
+            abi_protect_payload::MIRALIS_PROTECT_PAYLOAD_REPORT_FID => self.handle_report(ctx),
+            _ => return PolicyHookResult::Ignore,
+        }
         ctx.pc += 4;
         PolicyHookResult::Overwrite
     }
 
-    fn is_policy_call(&mut self, ctx: &VirtContext) -> bool {
-        let policy_eid: bool =
-            ctx.get(Register::X17) == abi_protect_payload::MIRALIS_PROTECT_PAYLOAD_EID;
-        let lock_fid: bool =
-            ctx.get(Register::X16) == abi_protect_payload::MIRALIS_PROTECT_PAYLOAD_LOCK_FID;
+    /// Copies the attestation report into the buffer of the caller, bound to the nonce it provides.
+    ///
+    /// The report is denied until the payload has been measured. On success the size of the report
+    /// is returned in a1.
+    fn handle_report(&mut self, ctx: &mut VirtContext) {
+        let report_addr = ctx.get(Register::X10);
+        let nonce_addr = ctx.get(Register::X11);
+        let mode = parse_mpp_return_mode(ctx.trap_info.mstatus);
+
+        let error = match PAYLOAD_MEASUREMENT.get() {
+            None => SBI_ERR_DENIED,
+            Some(payload_measurement) => {
+                let mut nonce = [0u8; report::NONCE_SIZE];
+                // SAFETY: the buffers are accessed with the privileges of the caller.
+                let result = unsafe {
+                    Arch::read_bytes_from_mode(nonce_addr as *const u8, &mut nonce, mode).and_then(
+                        |()| {
+                            let mut bytes = build_report(payload_measurement, &nonce);
+                            Arch::store_bytes_from_mode(&mut bytes, report_addr as *const u8, mode)
+                        },
+                    )
+                };
+                if result.is_ok() {
+                    0
+                } else {
+                    SBI_ERR_INVALID_ADDRESS
+                }
+            }
+        };
 
-        policy_eid && lock_fid
+        ctx.set(Register::X10, error as usize);
+        ctx.set(Register::X11, report::SIZE);
     }
 
     fn lock(&mut self, _mctx: &mut MiralisContext, _ctx: &mut VirtContext) {
@@ -350,3 +396,123 @@ fn hash_payload(size_to_hash: usize, pc_start: usize) -> [u8; 32] {
         hashed_value
     }
 }
+
+// ——————————————————————————— Attestation Report ——————————————————————————— //
+
+/// Builds the attestation report, see `abi_protect_payload::report` for the layout.
+fn build_report(
+    payload_measurement: &[u8; report::MEASUREMENT_SIZE],
+    nonce: &[u8; report::NONCE_SIZE],
+) -> [u8; report::SIZE] {
+    let build_measurement = sha3(&[build_info::as_str().as_bytes()]);
+    let chain = sha3(&[&build_measurement, payload_measurement]);
+    let digest = sha3(&[&chain, nonce]);
+
+    let mut bytes = [0u8; report::SIZE];
+    let mut write = |offset: usize, value: &[u8]| {
+        bytes[offset..offset + value.len()].copy_from_slice(value);
+    };
+    write(report::MAGIC_OFFSET, &report::MAGIC.to_le_bytes());
+    write(report::VERSION_OFFSET, &report::VERSION.to_le_bytes());
+    write(report::BUILD_MEASUREMENT_OFFSET, &build_measurement);
+    write(report::PAYLOAD_MEASUREMENT_OFFSET, payload_measurement);
+    write(report::NONCE_OFFSET, nonce);
+    write(report::DIGEST_OFFSET, &digest);
+    bytes
+}
+
+/// Returns the SHA3-256 digest of the concatenation of the parts.
+fn sha3(parts: &[&[u8]]) -> [u8; report::MEASUREMENT_SIZE] {
+    let mut hasher = Sha3::v256();
+    parts.iter().for_each(|part| hasher.update(part));
+    let mut hashed_value = [0u8; report::MEASUREMENT_SIZE];
+    hasher.finalize(&mut hashed_value);
+    hashed_value
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::testing::{Decision, Event, PolicyHarness};
+
+    #[test]
+    fn firmware_is_locked_out() {
+        // The payload is not loaded in memory, skip its measurement on the first switch
+        FIRST_JUMP.store(false, Ordering::SeqCst);
+        let mut harness = PolicyHarness::<ProtectPayloadPolicy>::init();
+
+        // Only the ecall arguments are forwarded to the firmware, and the memory is locked
+        harness.ctx.set(Register::X10, 0x42);
+        harness.ctx.set(Register::X5, 0xdead);
+        harness.step(Event::SwitchToFirmware(MCause::EcallFromSMode));
+        assert_eq!(harness.ctx.get(Register::X10), 0x42);
+        assert_eq!(harness.ctx.get(Register::X5), 0);
+        assert_eq!(
+            harness.policy_pmp(1).1,
+            pmpcfg::TOR | pmpcfg::NO_PERMISSIONS
+        );
+
+        // Only the return values are forwarded to the payload, and the memory is unlocked
+        harness.ctx.set(Register::X10, 0);
+        harness.ctx.set(Register::X5, 0xbad);
+        harness.step(Event::SwitchToPayload);
+        assert_eq!(harness.ctx.get(Register::X10), 0);
+        assert_eq!(harness.ctx.get(Register::X5), 0xdead);
+        assert_eq!(harness.policy_pmp(1).1, pmpcfg::TOR | pmpcfg::RWX);
+
+        // Other harts lock the memory with a policy MSI
+        harness.step(Event::Interrupt);
+        assert_eq!(
+            harness.policy_pmp(1).1,
+            pmpcfg::TOR | pmpcfg::NO_PERMISSIONS
+        );
+
+        // Ecalls to other extensions are left to Miralis
+        let decision = harness.step(Event::EcallFromPayload { eid: 0x10, fid: 0 });
+        assert_eq!(decision, Decision::Ignore);
+
+        // No report is available as long as the payload has not been measured
+        let decision = harness.step(Event::EcallFromPayload {
+            eid: abi_protect_payload::MIRALIS_PROTECT_PAYLOAD_EID,
+            fid: abi_protect_payload::MIRALIS_PROTECT_PAYLOAD_REPORT_FID,
+        });
+        assert_eq!(decision, Decision::Overwrite);
+        assert_eq!(harness.ctx.get(Register::X10), SBI_ERR_DENIED as usize);
+    }
+
+    #[test]
+    fn attestation_report() {
+        let payload_measurement = [0x42; report::MEASUREMENT_SIZE];
+        let nonce = [0x7; report::NONCE_SIZE];
+        let bytes = build_report(&payload_measurement, &nonce);
+        let field = |offset: usize, size: usize| &bytes[offset..offset + size];
+
+        assert_eq!(field(report::MAGIC_OFFSET, 8), b"MIRAREPT");
+        assert_eq!(
+            field(report::VERSION_OFFSET, 8),
+            &report::VERSION.to_le_bytes()
+        );
+        assert_eq!(
+            field(report::PAYLOAD_MEASUREMENT_OFFSET, report::MEASUREMENT_SIZE),
+            &payload_measurement
+        );
+        assert_eq!(field(report::NONCE_OFFSET, report::NONCE_SIZE), &nonce);
+
+        // A verifier recomputes the digest from the expected measurements and its nonce
+        let build_measurement = field(report::BUILD_MEASUREMENT_OFFSET, report::MEASUREMENT_SIZE);
+        let chain = sha3(&[build_measurement, &payload_measurement]);
+        assert_eq!(
+            field(report::DIGEST_OFFSET, report::MEASUREMENT_SIZE),
+            &sha3(&[&chain, &nonce])
+        );
+
+        // The digest depends on the nonce
+        let other = build_report(&payload_measurement, &[0; report::NONCE_SIZE]);
+        assert_ne!(
+            other[report::DIGEST_OFFSET..],
+            bytes[report::DIGEST_OFFSET..]
+        );
+    }
+}
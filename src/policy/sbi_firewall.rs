@@ -0,0 +1,204 @@
+//! The SBI firewall policy, which filters the SBI calls from the payload before they reach the
+//! virtualized firmware.
+//!
+//! Calls are matched against the [RULES] table by extension and function ID, the first matching
+//! rule decides whether the call is forwarded. Denied calls return `SBI_ERR_NOT_SUPPORTED` to the
+//! payload, and extensions denied as a whole are also reported as unavailable when probed. The
+//! Base extension is always allowed, as required by the SBI specification.
+//!
+//! Calls not matching any rule are allowed, unless the `sbi_firewall.deny-by-default` option is
+//! set (see [options](crate::policy::options)).
+
+use core::ops::RangeInclusive;
+
+use miralis_core::abi_vendor::MIRALIS_VENDOR_EID;
+
+use crate::arch::Register;
+use crate::device::status;
+use crate::host::MiralisContext;
+use crate::policy::options::PolicyOption;
+use crate::policy::{PolicyHookResult, PolicyModule};
+use crate::sbi::{ext, VENDOR_EIDS};
+use crate::virt::{RegisterContextGetter, RegisterContextSetter, VirtContext};
+
+const SBI_ERR_NOT_SUPPORTED: isize = -2;
+
+/// All function IDs of an extension.
+const ALL_FIDS: RangeInclusive<usize> = 0..=usize::MAX;
+
+/// The firewall rules, the first rule matching a call decides whether it is forwarded.
+const RULES: &[Rule] = &[
+    // The payload can not reset or shut down the system
+    Rule::deny(ext::SRST..=ext::SRST, ALL_FIDS),
+    // Only the Miralis vendor extension is reachable among the vendor extensions
+    Rule::allow(MIRALIS_VENDOR_EID..=MIRALIS_VENDOR_EID, ALL_FIDS),
+    Rule::deny(VENDOR_EIDS, ALL_FIDS),
+];
+
+/// Whether calls not matching any rule are denied, rather than allowed.
+const DENY_BY_DEFAULT: PolicyOption<bool> =
+    PolicyOption::new("sbi_firewall", "deny-by-default", false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    Allow,
+    Deny,
+}
+
+/// A firewall rule, matching a range of extension IDs and function IDs.
+struct Rule {
+    eids: RangeInclusive<usize>,
+    fids: RangeInclusive<usize>,
+    action: Action,
+}
+
+impl Rule {
+    const fn allow(eids: RangeInclusive<usize>, fids: RangeInclusive<usize>) -> Self {
+        Rule {
+            eids,
+            fids,
+            action: Action::Allow,
+        }
+    }
+
+    const fn deny(eids: RangeInclusive<usize>, fids: RangeInclusive<usize>) -> Self {
+        Rule {
+            eids,
+            fids,
+            action: Action::Deny,
+        }
+    }
+
+    fn matches(&self, eid: usize, fid: usize) -> bool {
+        self.eids.contains(&eid) && self.fids.contains(&fid)
+    }
+}
+
+/// Returns the action to apply to a call, `default` is applied if no rule matches.
+fn evaluate(rules: &[Rule], default: Action, eid: usize, fid: usize) -> Action {
+    if eid == ext::BASE {
+        return Action::Allow;
+    }
+    rules
+        .iter()
+        .find(|rule| rule.matches(eid, fid))
+        .map_or(default, |rule| rule.action)
+}
+
+/// Returns true if all the functions of the extension are denied.
+///
+/// This is conservative: an extension is only considered denied if the first rule matching it
+/// denies all its function IDs, or if no rule matches it and calls are denied by default.
+fn denies_extension(rules: &[Rule], default: Action, eid: usize) -> bool {
+    if eid == ext::BASE {
+        return false;
+    }
+    rules
+        .iter()
+        .find(|rule| rule.eids.contains(&eid))
+        .map_or(default == Action::Deny, |rule| {
+            rule.action == Action::Deny && rule.fids == ALL_FIDS
+        })
+}
+
+/// The SBI firewall policy module.
+pub struct SbiFirewallPolicy {
+    /// The action applied to calls not matching any rule.
+    default_action: Action,
+}
+
+impl PolicyModule for SbiFirewallPolicy {
+    fn init(_mctx: &mut MiralisContext, device_tree_blob_addr: usize) -> Self {
+        let default_action = if DENY_BY_DEFAULT.read(device_tree_blob_addr) {
+            Action::Deny
+        } else {
+            Action::Allow
+        };
+        SbiFirewallPolicy { default_action }
+    }
+
+    fn name() -> &'static str {
+        "SBI Firewall Policy"
+    }
+
+    fn ecall_from_payload(
+        &mut self,
+        _mctx: &mut MiralisContext,
+        ctx: &mut VirtContext,
+    ) -> PolicyHookResult {
+        let eid = ctx.get(Register::X17);
+        let fid = ctx.get(Register::X16);
+        if evaluate(RULES, self.default_action, eid, fid) == Action::Allow {
+            return PolicyHookResult::Ignore;
+        }
+
+        log::debug!("SBI firewall denied call 0x{:x}:0x{:x}", eid, fid);
+        status::record_violation();
+        ctx.set(Register::X10, SBI_ERR_NOT_SUPPORTED as usize);
+        ctx.set(Register::X11, 0);
+        ctx.pc += 4;
+        PolicyHookResult::Overwrite
+    }
+
+    fn hide_sbi_extension(&mut self, eid: usize) -> bool {
+        denies_extension(RULES, self.default_action, eid)
+    }
+
+    fn switch_from_payload_to_firmware(&mut self, _: &mut VirtContext, _: &mut MiralisContext) {}
+
+    fn switch_from_firmware_to_payload(&mut self, _: &mut VirtContext, _: &mut MiralisContext) {}
+
+    fn on_interrupt(&mut self, _ctx: &mut VirtContext, _mctx: &mut MiralisContext) {}
+
+    const NUMBER_PMPS: usize = 0;
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn firewall_rules() {
+        let rules = [
+            Rule::allow(ext::HSM..=ext::HSM, 0..=1),
+            Rule::deny(ext::HSM..=ext::HSM, ALL_FIDS),
+            Rule::deny(0x0900_0000..=0x09FF_FFFF, ALL_FIDS),
+            Rule::deny(ext::BASE..=ext::BASE, ALL_FIDS),
+        ];
+
+        // Function-level rules
+        assert_eq!(evaluate(&rules, Action::Allow, ext::HSM, 0), Action::Allow);
+        assert_eq!(evaluate(&rules, Action::Allow, ext::HSM, 1), Action::Allow);
+        assert_eq!(evaluate(&rules, Action::Allow, ext::HSM, 3), Action::Deny);
+        assert!(!denies_extension(&rules, Action::Allow, ext::HSM));
+
+        // Extension-level rules
+        assert_eq!(
+            evaluate(&rules, Action::Allow, 0x0900_0042, 7),
+            Action::Deny
+        );
+        assert!(denies_extension(&rules, Action::Allow, 0x0900_0042));
+
+        // Unmatched calls and the Base extension are allowed
+        assert_eq!(evaluate(&rules, Action::Allow, ext::IPI, 0), Action::Allow);
+        assert!(!denies_extension(&rules, Action::Allow, ext::IPI));
+        assert_eq!(evaluate(&rules, Action::Allow, ext::BASE, 3), Action::Allow);
+        assert!(!denies_extension(&rules, Action::Allow, ext::BASE));
+
+        // Unmatched calls can be denied by default, but not the Base extension
+        assert_eq!(evaluate(&rules, Action::Deny, ext::IPI, 0), Action::Deny);
+        assert!(denies_extension(&rules, Action::Deny, ext::IPI));
+        assert_eq!(evaluate(&rules, Action::Deny, ext::HSM, 0), Action::Allow);
+        assert_eq!(evaluate(&rules, Action::Deny, ext::BASE, 0), Action::Allow);
+        assert!(!denies_extension(&rules, Action::Deny, ext::BASE));
+
+        // The default table keeps the Miralis vendor extension reachable
+        assert_eq!(
+            evaluate(RULES, Action::Allow, MIRALIS_VENDOR_EID, 0),
+            Action::Allow
+        );
+        assert_eq!(evaluate(RULES, Action::Allow, ext::SRST, 0), Action::Deny);
+    }
+}
@@ -0,0 +1,211 @@
+//! Policy stacking, combining two policy modules into one.
+//!
+//! The stack invokes the hooks of its first policy, then of its second. For hooks handling an
+//! event, the first policy overwriting the event handles it and the second one is not called, such
+//! that an event is never handled twice. For hooks granting a permission, both policies must
+//! agree. Stacks can be nested to combine more than two policies.
+
+use crate::host::MiralisContext;
+use crate::policy::{PolicyHookResult, PolicyModule};
+use crate::suspend::SuspendRequest;
+use crate::virt::VirtContext;
+
+/// Two policy modules, invoked in order.
+pub struct PolicyStack<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A: PolicyModule, B: PolicyModule> PolicyModule for PolicyStack<A, B> {
+    fn init(mctx: &mut MiralisContext, device_tree_blob_addr: usize) -> Self {
+        let first = A::init(mctx, device_tree_blob_addr);
+        let second = B::init(mctx, device_tree_blob_addr);
+        log::debug!("Policy stack: {} then {}", A::name(), B::name());
+        PolicyStack { first, second }
+    }
+
+    /// The name of the stack is the name of its first policy.
+    fn name() -> &'static str {
+        A::name()
+    }
+
+    fn ecall_from_firmware(
+        &mut self,
+        mctx: &mut MiralisContext,
+        ctx: &mut VirtContext,
+    ) -> PolicyHookResult {
+        match self.first.ecall_from_firmware(mctx, ctx) {
+            PolicyHookResult::Overwrite => PolicyHookResult::Overwrite,
+            PolicyHookResult::Ignore => self.second.ecall_from_firmware(mctx, ctx),
+        }
+    }
+
+    fn ecall_from_payload(
+        &mut self,
+        mctx: &mut MiralisContext,
+        ctx: &mut VirtContext,
+    ) -> PolicyHookResult {
+        match self.first.ecall_from_payload(mctx, ctx) {
+            PolicyHookResult::Overwrite => PolicyHookResult::Overwrite,
+            PolicyHookResult::Ignore => self.second.ecall_from_payload(mctx, ctx),
+        }
+    }
+
+    fn trap_from_firmware(
+        &mut self,
+        mctx: &mut MiralisContext,
+        ctx: &mut VirtContext,
+    ) -> PolicyHookResult {
+        match self.first.trap_from_firmware(mctx, ctx) {
+            PolicyHookResult::Overwrite => PolicyHookResult::Overwrite,
+            PolicyHookResult::Ignore => self.second.trap_from_firmware(mctx, ctx),
+        }
+    }
+
+    fn trap_from_payload(
+        &mut self,
+        mctx: &mut MiralisContext,
+        ctx: &mut VirtContext,
+    ) -> PolicyHookResult {
+        match self.first.trap_from_payload(mctx, ctx) {
+            PolicyHookResult::Overwrite => PolicyHookResult::Overwrite,
+            PolicyHookResult::Ignore => self.second.trap_from_payload(mctx, ctx),
+        }
+    }
+
+    fn allow_suspend(&mut self, request: &SuspendRequest) -> bool {
+        self.first.allow_suspend(request) && self.second.allow_suspend(request)
+    }
+
+    fn hide_sbi_extension(&mut self, eid: usize) -> bool {
+        self.first.hide_sbi_extension(eid) || self.second.hide_sbi_extension(eid)
+    }
+
+    fn allow_misaligned_emulation(&mut self, ctx: &VirtContext) -> bool {
+        self.first.allow_misaligned_emulation(ctx) && self.second.allow_misaligned_emulation(ctx)
+    }
+
+    /// Only a single reserved region is reported, the one of the first policy reserving memory.
+    fn reserved_memory(&self) -> Option<(usize, usize)> {
+        self.first
+            .reserved_memory()
+            .or_else(|| self.second.reserved_memory())
+    }
+
+    fn switch_from_payload_to_firmware(
+        &mut self,
+        ctx: &mut VirtContext,
+        mctx: &mut MiralisContext,
+    ) {
+        self.first.switch_from_payload_to_firmware(ctx, mctx);
+        self.second.switch_from_payload_to_firmware(ctx, mctx);
+    }
+
+    fn switch_from_firmware_to_payload(
+        &mut self,
+        ctx: &mut VirtContext,
+        mctx: &mut MiralisContext,
+    ) {
+        self.first.switch_from_firmware_to_payload(ctx, mctx);
+        self.second.switch_from_firmware_to_payload(ctx, mctx);
+    }
+
+    fn on_interrupt(&mut self, ctx: &mut VirtContext, mctx: &mut MiralisContext) {
+        self.first.on_interrupt(ctx, mctx);
+        self.second.on_interrupt(ctx, mctx);
+    }
+
+    fn on_timer(&mut self, ctx: &mut VirtContext, mctx: &mut MiralisContext) {
+        self.first.on_timer(ctx, mctx);
+        self.second.on_timer(ctx, mctx);
+    }
+
+    fn on_idle(&mut self, ctx: &mut VirtContext, mctx: &mut MiralisContext) {
+        self.first.on_idle(ctx, mctx);
+        self.second.on_idle(ctx, mctx);
+    }
+
+    fn scrub_memory(&mut self, ctx: &mut VirtContext, mctx: &mut MiralisContext) {
+        self.first.scrub_memory(ctx, mctx);
+        self.second.scrub_memory(ctx, mctx);
+    }
+
+    /// Policies use the PMP entries starting at `POLICY_OFFSET`, hence at most one of the stacked
+    /// policies can statically reserve PMP entries. The others must request them at runtime.
+    const NUMBER_PMPS: usize = {
+        assert!(
+            A::NUMBER_PMPS == 0 || B::NUMBER_PMPS == 0,
+            "At most one of the stacked policies can use PMP entries"
+        );
+        A::NUMBER_PMPS + B::NUMBER_PMPS
+    };
+    const REQUIRES_S_MODE: bool = A::REQUIRES_S_MODE || B::REQUIRES_S_MODE;
+    const HOSTS_ACE: bool = A::HOSTS_ACE || B::HOSTS_ACE;
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::{Arch, Architecture};
+
+    /// A policy overwriting the payload ecalls and hiding a single extension.
+    struct Tester<const EID: usize> {
+        ecalls: usize,
+    }
+
+    impl<const EID: usize> PolicyModule for Tester<EID> {
+        fn init(_mctx: &mut MiralisContext, _device_tree_blob_addr: usize) -> Self {
+            Tester { ecalls: 0 }
+        }
+
+        fn name() -> &'static str {
+            "Tester"
+        }
+
+        fn ecall_from_payload(
+            &mut self,
+            _mctx: &mut MiralisContext,
+            _ctx: &mut VirtContext,
+        ) -> PolicyHookResult {
+            self.ecalls += 1;
+            PolicyHookResult::Overwrite
+        }
+
+        fn hide_sbi_extension(&mut self, eid: usize) -> bool {
+            eid == EID
+        }
+
+        fn switch_from_payload_to_firmware(&mut self, _: &mut VirtContext, _: &mut MiralisContext) {
+        }
+
+        fn switch_from_firmware_to_payload(&mut self, _: &mut VirtContext, _: &mut MiralisContext) {
+        }
+
+        fn on_interrupt(&mut self, _ctx: &mut VirtContext, _mctx: &mut MiralisContext) {}
+
+        const NUMBER_PMPS: usize = 0;
+    }
+
+    #[test]
+    fn stacked_hooks() {
+        let hw = unsafe { Arch::detect_hardware() };
+        let mut mctx = MiralisContext::new(hw);
+        let mut ctx = VirtContext::new(0, mctx.hw.available_reg.nb_pmp, mctx.hw.extensions.clone());
+        let mut stack = PolicyStack::<Tester<1>, Tester<2>>::init(&mut mctx, 0);
+
+        // Only the first policy handles the event
+        assert!(stack.ecall_from_payload(&mut mctx, &mut ctx).overwrites());
+        assert_eq!(stack.first.ecalls, 1);
+        assert_eq!(stack.second.ecalls, 0);
+
+        // Extensions hidden by any of the policies are hidden
+        assert!(stack.hide_sbi_extension(1));
+        assert!(stack.hide_sbi_extension(2));
+        assert!(!stack.hide_sbi_extension(3));
+
+        assert_eq!(PolicyStack::<Tester<1>, Tester<2>>::name(), "Tester");
+        assert_eq!(PolicyStack::<Tester<1>, Tester<2>>::NUMBER_PMPS, 0);
+    }
+}
@@ -0,0 +1,209 @@
+//! Test harness for policy modules.
+//!
+//! The harness drives a policy module through a scripted sequence of synthetic events (traps,
+//! ecalls, and world switches) and records the decision of each hook. Tests can then assert the
+//! decisions, the registers of the vCPU, and the PMP entries programmed by the policy, without
+//! running Miralis on QEMU.
+//!
+//! Only the policy hooks are called: when a policy ignores an event the harness does not emulate
+//! it, the next event of the script starts from the state left by the policy.
+
+use crate::arch::pmp::pmplayout::POLICY_OFFSET;
+use crate::arch::{mstatus, Arch, Architecture, MCause, Mode, Register};
+use crate::host::MiralisContext;
+use crate::policy::{PolicyHookResult, PolicyModule};
+use crate::virt::{RegisterContextSetter, VirtContext};
+
+/// A synthetic event delivered to the policy.
+#[derive(Debug, Clone, Copy)]
+pub enum Event {
+    /// An ecall from the payload (S-mode) with the given extension and function IDs.
+    EcallFromPayload { eid: usize, fid: usize },
+    /// An ecall from the firmware with the given extension and function IDs.
+    EcallFromFirmware { eid: usize, fid: usize },
+    /// A trap other than an ecall from the payload.
+    TrapFromPayload(MCause),
+    /// A trap other than an ecall from the firmware.
+    TrapFromFirmware(MCause),
+    /// The payload traps with the given cause and Miralis switches to the firmware.
+    SwitchToFirmware(MCause),
+    /// The firmware returns to the payload.
+    SwitchToPayload,
+    /// A policy MSI.
+    Interrupt,
+    /// The policy timer expired.
+    Timer,
+    /// The firmware waits for an interrupt.
+    Idle,
+}
+
+/// The decision of the policy for an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// The policy handled the event.
+    Overwrite,
+    /// The policy let Miralis handle the event.
+    Ignore,
+    /// The event is a notification, the policy takes no decision.
+    Notified,
+}
+
+impl From<PolicyHookResult> for Decision {
+    fn from(result: PolicyHookResult) -> Self {
+        match result {
+            PolicyHookResult::Overwrite => Decision::Overwrite,
+            PolicyHookResult::Ignore => Decision::Ignore,
+        }
+    }
+}
+
+/// A policy module with its own Miralis context and vCPU.
+pub struct PolicyHarness<P> {
+    pub policy: P,
+    pub mctx: MiralisContext,
+    pub ctx: VirtContext,
+}
+
+impl<P: PolicyModule> PolicyHarness<P> {
+    /// Creates the policy, as on a hart booting Miralis.
+    pub fn init() -> Self {
+        let hw = unsafe { Arch::detect_hardware() };
+        let mut mctx = MiralisContext::new(hw);
+        let ctx = VirtContext::new(0, mctx.hw.available_reg.nb_pmp, mctx.hw.extensions.clone());
+        let policy = P::init(&mut mctx, 0);
+        PolicyHarness { policy, mctx, ctx }
+    }
+
+    /// Delivers the events in order, returns the decision of the policy for each of them.
+    pub fn run(&mut self, script: &[Event]) -> Vec<Decision> {
+        script.iter().map(|event| self.step(*event)).collect()
+    }
+
+    /// Delivers a single event to the policy.
+    pub fn step(&mut self, event: Event) -> Decision {
+        let PolicyHarness { policy, mctx, ctx } = self;
+        match event {
+            Event::EcallFromPayload { eid, fid } => {
+                trap(ctx, MCause::EcallFromSMode, Mode::S);
+                set_ecall_ids(ctx, eid, fid);
+                match policy.trap_from_payload(mctx, ctx) {
+                    PolicyHookResult::Overwrite => Decision::Overwrite,
+                    PolicyHookResult::Ignore => policy.ecall_from_payload(mctx, ctx).into(),
+                }
+            }
+            Event::EcallFromFirmware { eid, fid } => {
+                trap(ctx, MCause::EcallFromUMode, Mode::M);
+                set_ecall_ids(ctx, eid, fid);
+                match policy.trap_from_firmware(mctx, ctx) {
+                    PolicyHookResult::Overwrite => Decision::Overwrite,
+                    PolicyHookResult::Ignore => policy.ecall_from_firmware(mctx, ctx).into(),
+                }
+            }
+            Event::TrapFromPayload(cause) => {
+                trap(ctx, cause, Mode::S);
+                policy.trap_from_payload(mctx, ctx).into()
+            }
+            Event::TrapFromFirmware(cause) => {
+                trap(ctx, cause, Mode::M);
+                policy.trap_from_firmware(mctx, ctx).into()
+            }
+            Event::SwitchToFirmware(cause) => {
+                trap(ctx, cause, Mode::S);
+                policy.switch_from_payload_to_firmware(ctx, mctx);
+                ctx.mode = Mode::M;
+                Decision::Notified
+            }
+            Event::SwitchToPayload => {
+                ctx.mode = Mode::S;
+                policy.switch_from_firmware_to_payload(ctx, mctx);
+                Decision::Notified
+            }
+            Event::Interrupt => {
+                policy.on_interrupt(ctx, mctx);
+                Decision::Notified
+            }
+            Event::Timer => {
+                policy.on_timer(ctx, mctx);
+                Decision::Notified
+            }
+            Event::Idle => {
+                policy.on_idle(ctx, mctx);
+                Decision::Notified
+            }
+        }
+    }
+
+    /// Returns the address and configuration of a PMP entry reserved for the policy.
+    pub fn policy_pmp(&self, idx: usize) -> (usize, u8) {
+        let idx = POLICY_OFFSET + idx;
+        (self.mctx.pmp.pmpaddr()[idx], self.mctx.pmp.get_cfg(idx))
+    }
+}
+
+/// Fills the trap information as the hardware would for a trap from the given virtual mode.
+///
+/// The firmware runs in U-mode, its traps are therefore reported with MPP set to U-mode.
+fn trap(ctx: &mut VirtContext, cause: MCause, mode: Mode) {
+    let mpp = match mode {
+        Mode::M => Mode::U,
+        mode => mode,
+    };
+    ctx.mode = mode;
+    ctx.trap_info.mcause = cause as usize;
+    ctx.trap_info.mepc = ctx.pc;
+    ctx.trap_info.mtval = 0;
+    ctx.trap_info.mstatus =
+        (ctx.trap_info.mstatus & !mstatus::MPP_FILTER) | (mpp.to_bits() << mstatus::MPP_OFFSET);
+}
+
+fn set_ecall_ids(ctx: &mut VirtContext, eid: usize, fid: usize) {
+    ctx.set(Register::X17, eid);
+    ctx.set(Register::X16, fid);
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::default::DefaultPolicy;
+    use crate::virt::RegisterContextGetter;
+
+    #[test]
+    fn scripted_default_policy() {
+        let mut harness = PolicyHarness::<DefaultPolicy>::init();
+        let decisions = harness.run(&[
+            Event::EcallFromPayload { eid: 0x10, fid: 0 },
+            Event::SwitchToFirmware(MCause::EcallFromSMode),
+            Event::EcallFromFirmware { eid: 0x10, fid: 0 },
+            Event::TrapFromFirmware(MCause::IllegalInstr),
+            Event::SwitchToPayload,
+            Event::TrapFromPayload(MCause::LoadAddrMisaligned),
+            Event::Interrupt,
+            Event::Timer,
+            Event::Idle,
+        ]);
+
+        // The default policy lets Miralis handle all events
+        assert_eq!(
+            decisions,
+            [
+                Decision::Ignore,
+                Decision::Notified,
+                Decision::Ignore,
+                Decision::Ignore,
+                Decision::Notified,
+                Decision::Ignore,
+                Decision::Notified,
+                Decision::Notified,
+                Decision::Notified,
+            ]
+        );
+        assert_eq!(harness.ctx.mode, Mode::S);
+        assert_eq!(harness.ctx.get(Register::X17), 0x10);
+        assert_eq!(
+            harness.ctx.trap_info.mcause,
+            MCause::LoadAddrMisaligned as usize
+        );
+    }
+}
@@ -0,0 +1,385 @@
+//! SBI Routing
+//!
+//! Miralis, its policy modules and the ACE security monitor each expose SBI extensions. This
+//! module holds the single table mapping SBI extension IDs to the extension handling them, such
+//! that adding an extension only requires a new entry in [SBI_ROUTES]. Extensions can be gated by
+//! the configuration, in which case calls to their extension ID are treated as unknown.
+//!
+//! Independently of the routing, the configuration and the policy can hide extensions from the
+//! payload: calls to a hidden extension return `SBI_ERR_NOT_SUPPORTED` and probing it reports it
+//! as unavailable, even if the firmware implements it.
+
+use core::ops::RangeInclusive;
+
+use config_helpers::str_eq;
+use miralis_core::{abi, abi_protect_domains, abi_protect_payload, abi_vendor};
+
+use crate::arch::Register;
+use crate::config::SBI_DENY_LIST;
+use crate::device::status;
+use crate::policy::{self, Policy, PolicyModule};
+use crate::suspend::SUSP_EID;
+use crate::virt::{RegisterContextGetter, RegisterContextSetter, VirtContext};
+
+/// Whether the ACE security monitor is active, that is whether the selected policy hosts it.
+const ACE_ENABLED: bool = <Policy as PolicyModule>::HOSTS_ACE;
+
+/// Whether the protect payload policy is active.
+const PROTECT_PAYLOAD_ENABLED: bool = policy::is_selected("protect_payload");
+
+/// Whether the protect domains policy is active.
+const PROTECT_DOMAINS_ENABLED: bool = policy::is_selected("protect_domains");
+
+// ——————————————————————————————— SBI Routes ——————————————————————————————— //
+
+/// Extension IDs of the standard and CoVE extensions, which do not depend on ACE being compiled in.
+pub mod ext {
+    pub const BASE: usize = 0x10;
+    pub const TIME: usize = 0x54494D45;
+    pub const IPI: usize = 0x735049;
+    pub const RFENCE: usize = 0x52464E43;
+    pub const HSM: usize = 0x48534D;
+    pub const SRST: usize = 0x53525354;
+    pub const DBCN: usize = 0x4442434E;
+    pub const NACL: usize = 0x4E41434C;
+    pub const COVH: usize = 0x434F5648;
+    pub const COVI: usize = 0x434F5649;
+    pub const COVG: usize = 0x434F5647;
+}
+
+/// The SBI extensions known to Miralis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SbiExtension {
+    Base,
+    Ipi,
+    Rfence,
+    Hsm,
+    Srst,
+    /// System suspend, see [crate::suspend].
+    Susp,
+    /// Nested acceleration, implemented by ACE for the hypervisor.
+    Nacl,
+    /// CoVE host extension, implemented by ACE for the hypervisor.
+    Covh,
+    /// CoVE interrupt extension, implemented by ACE.
+    Covi,
+    /// CoVE guest extension, implemented by ACE for confidential VMs.
+    Covg,
+    /// The Miralis ABI, see [miralis_core::abi].
+    Miralis,
+    /// The Miralis vendor extension for the payload, see [crate::vendor].
+    Vendor,
+    /// The protect payload policy ABI, see [miralis_core::abi_protect_payload].
+    ProtectPayload,
+    /// The protect domains policy ABI, see [miralis_core::abi_protect_domains].
+    ProtectDomains,
+}
+
+/// An entry of the SBI routing table.
+#[derive(Debug, Clone, Copy)]
+pub struct SbiRoute {
+    /// The SBI extension ID, passed in a7.
+    pub eid: usize,
+    /// The extension handling calls to `eid`.
+    pub extension: SbiExtension,
+    /// Whether the extension is enabled by the current configuration.
+    pub enabled: bool,
+}
+
+impl SbiRoute {
+    const fn new(eid: usize, extension: SbiExtension, enabled: bool) -> Self {
+        SbiRoute {
+            eid,
+            extension,
+            enabled,
+        }
+    }
+}
+
+/// The SBI routing table.
+pub const SBI_ROUTES: &[SbiRoute] = &[
+    SbiRoute::new(abi::MIRALIS_EID, SbiExtension::Miralis, true),
+    SbiRoute::new(abi_vendor::MIRALIS_VENDOR_EID, SbiExtension::Vendor, true),
+    SbiRoute::new(
+        abi_protect_payload::MIRALIS_PROTECT_PAYLOAD_EID,
+        SbiExtension::ProtectPayload,
+        PROTECT_PAYLOAD_ENABLED,
+    ),
+    SbiRoute::new(
+        abi_protect_domains::MIRALIS_PROTECT_DOMAINS_EID,
+        SbiExtension::ProtectDomains,
+        PROTECT_DOMAINS_ENABLED,
+    ),
+    SbiRoute::new(ext::BASE, SbiExtension::Base, true),
+    SbiRoute::new(ext::IPI, SbiExtension::Ipi, true),
+    SbiRoute::new(ext::RFENCE, SbiExtension::Rfence, true),
+    SbiRoute::new(ext::HSM, SbiExtension::Hsm, true),
+    SbiRoute::new(ext::SRST, SbiExtension::Srst, true),
+    SbiRoute::new(SUSP_EID, SbiExtension::Susp, true),
+    SbiRoute::new(ext::NACL, SbiExtension::Nacl, ACE_ENABLED),
+    SbiRoute::new(ext::COVH, SbiExtension::Covh, ACE_ENABLED),
+    SbiRoute::new(ext::COVI, SbiExtension::Covi, ACE_ENABLED),
+    SbiRoute::new(ext::COVG, SbiExtension::Covg, ACE_ENABLED),
+];
+
+/// Returns the extension handling the given SBI extension ID, if any is enabled.
+pub fn route(eid: usize) -> Option<SbiExtension> {
+    SBI_ROUTES
+        .iter()
+        .find(|route| route.eid == eid && route.enabled)
+        .map(|route| route.extension)
+}
+
+// ————————————————————————————— SBI Deny-List —————————————————————————————— //
+
+const SBI_ERR_NOT_SUPPORTED: isize = -2;
+
+/// The Base extension `probe_extension` function ID.
+const PROBE_EXTENSION_FID: usize = 3;
+
+/// Extension IDs reserved for vendor extensions, denied as a whole by the `vendor` entry.
+pub const VENDOR_EIDS: RangeInclusive<usize> = 0x0900_0000..=0x09FF_FFFF;
+
+/// Names of the standard extensions accepted in the deny-list.
+const EXTENSION_NAMES: &[(&str, usize)] = &[
+    ("base", ext::BASE),
+    ("time", ext::TIME),
+    ("ipi", ext::IPI),
+    ("rfence", ext::RFENCE),
+    ("hsm", ext::HSM),
+    ("srst", ext::SRST),
+    ("pmu", 0x504D55),
+    ("dbcn", ext::DBCN),
+    ("susp", SUSP_EID),
+    ("cppc", 0x43505043),
+    ("nacl", ext::NACL),
+    ("sta", 0x535441),
+];
+
+/// An entry of the deny-list, parsed from the configuration at build time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DenyEntry {
+    /// An empty entry, which matches no extension.
+    Empty,
+    /// All the vendor extensions.
+    Vendor,
+    /// A single extension ID.
+    Extension(usize),
+}
+
+impl DenyEntry {
+    fn matches(self, eid: usize) -> bool {
+        match self {
+            DenyEntry::Empty => false,
+            DenyEntry::Vendor => VENDOR_EIDS.contains(&eid),
+            DenyEntry::Extension(id) => id == eid,
+        }
+    }
+}
+
+/// The deny-list of the configuration.
+const DENY_LIST: [DenyEntry; SBI_DENY_LIST.len()] = parse_deny_list(SBI_DENY_LIST);
+
+const fn parse_deny_list<const LEN: usize>(entries: &[&str; LEN]) -> [DenyEntry; LEN] {
+    let mut list = [DenyEntry::Empty; LEN];
+    let mut i = 0;
+    while i < LEN {
+        list[i] = parse_deny_entry(entries[i]);
+        i += 1;
+    }
+    list
+}
+
+/// Parses a deny-list entry.
+///
+/// Entries are either the name of a standard extension, `vendor` for all vendor extensions, or an
+/// extension ID in hexadecimal. Invalid entries fail the build.
+const fn parse_deny_entry(entry: &str) -> DenyEntry {
+    if entry.is_empty() {
+        return DenyEntry::Empty;
+    }
+    if str_eq(entry, "vendor") {
+        return DenyEntry::Vendor;
+    }
+    let mut i = 0;
+    while i < EXTENSION_NAMES.len() {
+        if str_eq(entry, EXTENSION_NAMES[i].0) {
+            return DenyEntry::Extension(EXTENSION_NAMES[i].1);
+        }
+        i += 1;
+    }
+
+    let mut bytes = entry.as_bytes();
+    if let [b'0', b'x', rest @ ..] = bytes {
+        bytes = rest;
+    }
+    if bytes.is_empty() {
+        panic!("Invalid entry in the SBI deny-list");
+    }
+    let mut eid: usize = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let digit = match bytes[i] {
+            b'0'..=b'9' => bytes[i] - b'0',
+            b'a'..=b'f' => bytes[i] - b'a' + 10,
+            b'A'..=b'F' => bytes[i] - b'A' + 10,
+            _ => panic!("Invalid entry in the SBI deny-list"),
+        };
+        eid = match eid.checked_mul(16) {
+            Some(eid) => eid + digit as usize,
+            None => panic!("Invalid entry in the SBI deny-list"),
+        };
+        i += 1;
+    }
+    DenyEntry::Extension(eid)
+}
+
+/// Returns the extension replacing a legacy extension (EIDs 0x00 to 0x0F), if any.
+///
+/// Hiding an extension also hides the legacy calls providing the same service, otherwise the
+/// payload could reach it through the legacy interface.
+const fn legacy_replacement(eid: usize) -> Option<usize> {
+    match eid {
+        0x00 => Some(ext::TIME),
+        0x01 | 0x02 => Some(ext::DBCN),
+        0x03 | 0x04 => Some(ext::IPI),
+        0x05..=0x07 => Some(ext::RFENCE),
+        0x08 => Some(ext::SRST),
+        _ => None,
+    }
+}
+
+/// Returns true if an extension is hidden by the deny-list.
+fn deny_list_hides(deny_list: &[DenyEntry], eid: usize) -> bool {
+    let replacement = legacy_replacement(eid);
+    deny_list
+        .iter()
+        .any(|entry| entry.matches(eid) || replacement.is_some_and(|id| entry.matches(id)))
+}
+
+/// Returns true if the extension is hidden from the payload.
+///
+/// The Base extension can not be hidden, as it is required by the SBI specification.
+fn is_hidden(eid: usize, policy: &mut Policy) -> bool {
+    if eid == ext::BASE {
+        return false;
+    }
+    deny_list_hides(&DENY_LIST, eid)
+        || policy.hide_sbi_extension(eid)
+        || legacy_replacement(eid).is_some_and(|id| policy.hide_sbi_extension(id))
+}
+
+/// Filters an ecall from the payload against the hidden extensions.
+///
+/// Returns true if the call has been answered on behalf of the firmware, in which case the ecall
+/// must not be forwarded.
+pub fn filter_payload_call(ctx: &mut VirtContext, policy: &mut Policy) -> bool {
+    let eid = ctx.get(Register::X17);
+    let fid = ctx.get(Register::X16);
+
+    let (error, value) = if is_hidden(eid, policy) {
+        log::debug!("Payload called hidden SBI extension 0x{:x}", eid);
+        status::record_violation();
+        (SBI_ERR_NOT_SUPPORTED, 0)
+    } else if eid == ext::BASE
+        && fid == PROBE_EXTENSION_FID
+        && is_hidden(ctx.get(Register::X10), policy)
+    {
+        // Report the extension as not available
+        (0, 0)
+    } else {
+        return false;
+    };
+
+    ctx.set(Register::X10, error as usize);
+    ctx.set(Register::X11, value);
+    ctx.pc += 4;
+    true
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extension_ids_are_unique() {
+        for (idx, route) in SBI_ROUTES.iter().enumerate() {
+            for other in &SBI_ROUTES[idx + 1..] {
+                assert_ne!(route.eid, other.eid, "{:?} and {:?}", route, other);
+            }
+        }
+    }
+
+    #[test]
+    fn routing() {
+        assert_eq!(route(abi::MIRALIS_EID), Some(SbiExtension::Miralis));
+        assert_eq!(route(ext::HSM), Some(SbiExtension::Hsm));
+        assert_eq!(
+            route(abi_vendor::MIRALIS_VENDOR_EID),
+            Some(SbiExtension::Vendor)
+        );
+        assert_eq!(route(0xdead_beef), None);
+
+        for entry in SBI_ROUTES.iter().filter(|route| !route.enabled) {
+            assert_eq!(route(entry.eid), None);
+        }
+    }
+
+    #[test]
+    fn deny_list_entries() {
+        assert_eq!(parse_deny_entry("srst"), DenyEntry::Extension(ext::SRST));
+        assert_eq!(parse_deny_entry("vendor"), DenyEntry::Vendor);
+        assert_eq!(parse_deny_entry("0x48534D"), DenyEntry::Extension(ext::HSM));
+        assert_eq!(parse_deny_entry("48534d"), DenyEntry::Extension(ext::HSM));
+        assert_eq!(parse_deny_entry(""), DenyEntry::Empty);
+
+        assert!(DenyEntry::Vendor.matches(0x0900_0042));
+        assert!(!DenyEntry::Vendor.matches(abi::MIRALIS_EID));
+        assert!(DenyEntry::Vendor.matches(abi_vendor::MIRALIS_VENDOR_EID));
+        assert!(!DenyEntry::Extension(ext::SRST).matches(ext::HSM));
+        assert!(!DenyEntry::Empty.matches(0));
+    }
+
+    #[test]
+    #[should_panic]
+    fn invalid_deny_list_entry() {
+        parse_deny_entry("not-an-extension");
+    }
+
+    #[test]
+    fn legacy_extensions_are_hidden() {
+        let deny_list = [
+            DenyEntry::Extension(ext::SRST),
+            DenyEntry::Extension(ext::IPI),
+        ];
+        assert!(deny_list_hides(&deny_list, ext::SRST));
+        // Legacy shutdown and send_ipi
+        assert!(deny_list_hides(&deny_list, 0x08));
+        assert!(deny_list_hides(&deny_list, 0x04));
+        // Legacy set_timer and remote_fence_i
+        assert!(!deny_list_hides(&deny_list, 0x00));
+        assert!(!deny_list_hides(&deny_list, 0x05));
+        assert!(!deny_list_hides(&deny_list, 0x09));
+    }
+
+    #[test]
+    #[cfg(feature = "ace")]
+    fn extension_ids_match_ace() {
+        use crate::ace::core::architecture::riscv::sbi::{
+            BaseExtension, CovgExtension, CovhExtension, CoviExtension, HsmExtension, IpiExtension,
+            NaclExtension, RfenceExtension, SrstExtension,
+        };
+
+        assert_eq!(ext::BASE, BaseExtension::EXTID);
+        assert_eq!(ext::IPI, IpiExtension::EXTID);
+        assert_eq!(ext::RFENCE, RfenceExtension::EXTID);
+        assert_eq!(ext::HSM, HsmExtension::EXTID);
+        assert_eq!(ext::SRST, SrstExtension::EXTID);
+        assert_eq!(ext::NACL, NaclExtension::EXTID);
+        assert_eq!(ext::COVH, CovhExtension::EXTID);
+        assert_eq!(ext::COVI, CoviExtension::EXTID);
+        assert_eq!(ext::COVG, CovgExtension::EXTID);
+    }
+}
@@ -0,0 +1,216 @@
+//! RISC-V Semihosting
+//!
+//! Semihosting lets a program perform I/O on the host through a debugger or an emulator, without
+//! relying on any device. A semihosting call is an `ebreak` surrounded by two specific no-ops, the
+//! operation is passed in `a0`, its parameter in `a1`, and the result is returned in `a0`. When
+//! enabled (see the `debug.semihosting` configuration) Miralis serves the semihosting calls of the
+//! firmware, such that test firmware can print characters and report its exit code on platforms
+//! without a UART model.
+//!
+//! Only the console output and exit operations are supported, other operations fail and return
+//! -1. Breakpoints that are not semihosting calls are handled as usual.
+
+use core::mem::size_of;
+
+use log::Level;
+
+use crate::arch::{parse_mpp_return_mode, Arch, Architecture, Mode, Register};
+use crate::platform::{Plat, Platform};
+use crate::virt::{RegisterContextGetter, RegisterContextSetter, VirtContext};
+
+/// `slli zero, zero, 0x1f`, the no-op preceding the `ebreak`.
+const ENTRY_NOP: u32 = 0x01f01013;
+/// The uncompressed `ebreak`, semihosting calls never use `c.ebreak`.
+const EBREAK: u32 = 0x00100073;
+/// `srai zero, zero, 0x7`, the no-op following the `ebreak`.
+const EXIT_NOP: u32 = 0x40705013;
+
+/// Exit reason of an application terminating normally.
+const ADP_STOPPED_APPLICATION_EXIT: usize = 0x20026;
+
+/// File descriptors of the standard output and standard error.
+const STDOUT: usize = 1;
+const STDERR: usize = 2;
+
+/// Maximum length of a string printed by `SYS_WRITE0`, longer strings are truncated.
+const MAX_STRING_LEN: usize = 4096;
+
+/// The supported semihosting operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operation {
+    /// Prints the character pointed to by the parameter.
+    WriteC,
+    /// Prints the null-terminated string pointed to by the parameter.
+    Write0,
+    /// Writes to a file, only the standard output and error are supported.
+    Write,
+    /// Exits with the reason and subcode stored in the block pointed to by the parameter.
+    Exit,
+    /// Same as `Exit`, the extended version only differs on 32 bits targets.
+    ExitExtended,
+}
+
+impl Operation {
+    fn from_number(number: usize) -> Option<Self> {
+        match number {
+            0x03 => Some(Operation::WriteC),
+            0x04 => Some(Operation::Write0),
+            0x05 => Some(Operation::Write),
+            0x18 => Some(Operation::Exit),
+            0x20 => Some(Operation::ExitExtended),
+            _ => None,
+        }
+    }
+}
+
+/// Serves the semihosting call at the firmware breakpoint, returns false if the breakpoint is not
+/// a semihosting call.
+///
+/// On success the pc points to the instruction following the `ebreak`.
+pub fn handle_firmware_call(ctx: &mut VirtContext) -> bool {
+    let mode = parse_mpp_return_mode(ctx.trap_info.mstatus);
+    let pc = ctx.trap_info.mepc;
+    if !is_semihosting_call(pc, mode) {
+        return false;
+    }
+
+    let number = ctx.get(Register::X10);
+    let parameter = ctx.get(Register::X11);
+    let result = match Operation::from_number(number) {
+        Some(operation) => perform(operation, parameter, mode),
+        None => {
+            log::debug!("Unsupported semihosting operation 0x{:x}", number);
+            None
+        }
+    };
+
+    ctx.set(Register::X10, result.unwrap_or(usize::MAX));
+    ctx.pc = pc + 4;
+    true
+}
+
+/// Returns true if the `ebreak` at `pc` is a semihosting call.
+fn is_semihosting_call(pc: usize, mode: Mode) -> bool {
+    let sequence = [pc.wrapping_sub(4), pc, pc.wrapping_add(4)].map(|addr| read_u32(addr, mode));
+    sequence == [Some(ENTRY_NOP), Some(EBREAK), Some(EXIT_NOP)]
+}
+
+/// Performs the operation, returns the value of `a0` or None on failure.
+fn perform(operation: Operation, parameter: usize, mode: Mode) -> Option<usize> {
+    match operation {
+        Operation::WriteC => {
+            let byte = read_bytes::<1>(parameter, mode)?;
+            print(&byte);
+            // The value of a0 is not specified, it is left untouched
+            Some(parameter)
+        }
+        Operation::Write0 => {
+            let mut bytes = [0u8; 64];
+            let mut len = 0;
+            // Read byte per byte, the string might end right before an inaccessible page
+            for addr in parameter..parameter + MAX_STRING_LEN {
+                let [byte] = read_bytes::<1>(addr, mode)?;
+                if byte == 0 {
+                    break;
+                }
+                bytes[len] = byte;
+                len += 1;
+                if len == bytes.len() {
+                    print(&bytes);
+                    len = 0;
+                }
+            }
+            print(&bytes[..len]);
+            Some(parameter)
+        }
+        Operation::Write => {
+            let [fd, buffer, len] = read_block::<3>(parameter, mode)?;
+            if fd != STDOUT && fd != STDERR {
+                return None;
+            }
+            let mut bytes = [0u8; 64];
+            for offset in (0..len).step_by(bytes.len()) {
+                let size = (len - offset).min(bytes.len());
+                let chunk = &mut bytes[..size];
+                // SAFETY: the access is performed with the privileges of the firmware.
+                unsafe { Arch::read_bytes_from_mode((buffer + offset) as *const u8, chunk, mode) }
+                    .ok()?;
+                print(chunk);
+            }
+            // Returns the number of bytes not written
+            Some(0)
+        }
+        Operation::Exit | Operation::ExitExtended => {
+            let [reason, subcode] = read_block::<2>(parameter, mode)?;
+            if is_success(reason, subcode) {
+                log::info!("Semihosting exit: success");
+                Plat::exit_success();
+            } else {
+                log::error!("Semihosting exit: reason 0x{:x}, code {}", reason, subcode);
+                Plat::exit_failure();
+            }
+        }
+    }
+}
+
+/// Returns true if the exit reason and subcode indicate a successful termination.
+fn is_success(reason: usize, subcode: usize) -> bool {
+    reason == ADP_STOPPED_APPLICATION_EXIT && subcode == 0
+}
+
+/// Prints the output of the firmware, invalid UTF-8 sequences are replaced.
+fn print(bytes: &[u8]) {
+    for chunk in bytes.utf8_chunks() {
+        let replacement = if chunk.invalid().is_empty() {
+            ""
+        } else {
+            "\u{FFFD}"
+        };
+        Plat::debug_print(
+            Level::Info,
+            format_args!("{}{}", chunk.valid(), replacement),
+        );
+    }
+}
+
+fn read_bytes<const N: usize>(addr: usize, mode: Mode) -> Option<[u8; N]> {
+    let mut bytes = [0u8; N];
+    // SAFETY: the access is performed with the privileges of the firmware.
+    unsafe { Arch::read_bytes_from_mode(addr as *const u8, &mut bytes, mode) }.ok()?;
+    Some(bytes)
+}
+
+fn read_u32(addr: usize, mode: Mode) -> Option<u32> {
+    read_bytes::<4>(addr, mode).map(u32::from_le_bytes)
+}
+
+/// Reads a parameter block of `N` words.
+fn read_block<const N: usize>(addr: usize, mode: Mode) -> Option<[usize; N]> {
+    let mut block = [0; N];
+    for (idx, word) in block.iter_mut().enumerate() {
+        let bytes = read_bytes::<{ size_of::<usize>() }>(addr + idx * size_of::<usize>(), mode)?;
+        *word = usize::from_le_bytes(bytes);
+    }
+    Some(block)
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn semihosting_operations() {
+        assert_eq!(Operation::from_number(0x03), Some(Operation::WriteC));
+        assert_eq!(Operation::from_number(0x04), Some(Operation::Write0));
+        assert_eq!(Operation::from_number(0x18), Some(Operation::Exit));
+        // SYS_OPEN is not supported
+        assert_eq!(Operation::from_number(0x01), None);
+
+        assert!(is_success(ADP_STOPPED_APPLICATION_EXIT, 0));
+        assert!(!is_success(ADP_STOPPED_APPLICATION_EXIT, 1));
+        // ADP_Stopped_RunTimeErrorUnknown
+        assert!(!is_success(0x20023, 0));
+    }
+}
@@ -0,0 +1,228 @@
+//! CPU Idle States
+//!
+//! The payload enters low-power states through the SBI HSM `hart_suspend` and the SUSP
+//! `system_suspend` calls, both implemented by the firmware. Miralis intercepts those calls to
+//! validate them against the specification and the policy before forwarding them to the firmware.
+//!
+//! Once the firmware actually suspends the hart, by executing `wfi` while a suspend request is
+//! pending, Miralis lets the platform suspend driver enter the low-power state and restores its own
+//! hart configuration (trap handler, delegation, and PMP) on wake up, as that configuration might
+//! be lost by non-retentive states.
+
+use crate::arch::{Arch, Architecture, Register};
+use crate::device::status::{self, ResetReason};
+use crate::host::MiralisContext;
+use crate::platform::{self, Plat, Platform};
+use crate::policy::{Policy, PolicyModule};
+use crate::sbi::SbiExtension;
+use crate::virt::{RegisterContextGetter, RegisterContextSetter, VirtContext};
+
+/// The SBI System Suspend extension ID.
+pub const SUSP_EID: usize = 0x53555350;
+/// The SBI System Suspend `system_suspend` function ID.
+pub const SYSTEM_SUSPEND_FID: usize = 0x0;
+/// The SBI HSM `hart_suspend` function ID.
+pub const HART_SUSPEND_FID: usize = 0x3;
+
+const SBI_ERR_INVALID_PARAM: isize = -3;
+const SBI_ERR_DENIED: isize = -4;
+const SBI_ERR_INVALID_ADDRESS: isize = -5;
+
+// ————————————————————————————— Suspend Requests ————————————————————————————— //
+
+/// The low-power state requested by the payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuspendKind {
+    /// A hart suspend preserving the hart state, execution resumes after the `ecall`.
+    Retentive(u32),
+    /// A hart suspend losing the hart state, execution resumes at the requested address.
+    NonRetentive(u32),
+    /// A system suspend, execution resumes at the requested address.
+    System(u32),
+}
+
+impl SuspendKind {
+    /// Returns true if the hart state is lost and execution resumes at the resume address.
+    pub fn is_non_retentive(self) -> bool {
+        !matches!(self, SuspendKind::Retentive(_))
+    }
+}
+
+/// A suspend request, decoded from the arguments of the SBI call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SuspendRequest {
+    pub kind: SuspendKind,
+    pub resume_addr: usize,
+    pub opaque: usize,
+}
+
+impl SuspendRequest {
+    /// Decodes an HSM `hart_suspend` or SUSP `system_suspend` call.
+    ///
+    /// Returns `Ok(None)` if the call is not a suspend request, and the SBI error to return to the
+    /// payload if the requested state is reserved by the specification.
+    pub fn decode(
+        extension: Option<SbiExtension>,
+        fid: usize,
+        args: [usize; 3],
+    ) -> Result<Option<Self>, isize> {
+        let [ty, resume_addr, opaque] = args;
+        let is_hart_suspend = match (extension, fid) {
+            (Some(SbiExtension::Hsm), HART_SUSPEND_FID) => true,
+            (Some(SbiExtension::Susp), SYSTEM_SUSPEND_FID) => false,
+            _ => return Ok(None),
+        };
+
+        // Suspend types are 32 bits wide.
+        let ty = u32::try_from(ty).map_err(|_| SBI_ERR_INVALID_PARAM)?;
+        let kind = match (is_hart_suspend, ty) {
+            (true, 0x0000_0000 | 0x1000_0000..=0x7fff_ffff) => SuspendKind::Retentive(ty),
+            (true, 0x8000_0000 | 0x9000_0000..=0xffff_ffff) => SuspendKind::NonRetentive(ty),
+            (false, 0x0000_0000 | 0x8000_0000..=0xffff_ffff) => SuspendKind::System(ty),
+            _ => return Err(SBI_ERR_INVALID_PARAM),
+        };
+
+        Ok(Some(SuspendRequest {
+            kind,
+            resume_addr,
+            opaque,
+        }))
+    }
+
+    /// Returns true if the resume address points inside Miralis's own memory.
+    fn resumes_in_miralis(&self) -> bool {
+        let (start, size) = Plat::get_miralis_memory_start_and_size();
+        self.kind.is_non_retentive() && (start..start + size).contains(&self.resume_addr)
+    }
+}
+
+// ———————————————————————————— Suspend Emulation ————————————————————————————— //
+
+/// Validates a suspend call from the payload.
+///
+/// Returns true if the call must be forwarded to the firmware, otherwise the error has already
+/// been returned to the payload.
+pub fn handle_payload_call(ctx: &mut VirtContext, policy: &mut Policy) -> bool {
+    let extension = crate::sbi::route(ctx.get(Register::X17));
+    let fid = ctx.get(Register::X16);
+    let args = [
+        ctx.get(Register::X10),
+        ctx.get(Register::X11),
+        ctx.get(Register::X12),
+    ];
+
+    let error = match SuspendRequest::decode(extension, fid, args) {
+        Ok(None) => return true,
+        Ok(Some(request)) if request.resumes_in_miralis() => {
+            status::record_violation();
+            SBI_ERR_INVALID_ADDRESS
+        }
+        Ok(Some(request)) if !policy.allow_suspend(&request) => {
+            status::record_violation();
+            SBI_ERR_DENIED
+        }
+        Ok(Some(request)) => {
+            log::debug!("Hart {} suspend request: {:x?}", ctx.hart_id, request);
+            ctx.pending_suspend = Some(request);
+            return true;
+        }
+        Err(error) => error,
+    };
+
+    log::debug!(
+        "Rejected suspend request on hart {}: {}",
+        ctx.hart_id,
+        error
+    );
+    ctx.set(Register::X10, error as usize);
+    ctx.set(Register::X11, 0);
+    ctx.pc += 4;
+    false
+}
+
+/// Suspends the current hart on behalf of the firmware.
+///
+/// Returns once the hart wakes up, after restoring Miralis's configuration of the hart.
+pub fn suspend_hart(request: SuspendRequest, mctx: &mut MiralisContext) {
+    Plat::suspend_hart(request.kind);
+
+    if request.kind.is_non_retentive() {
+        status::record_reset(ResetReason::SuspendResume, mctx.hw.hart);
+        platform::init_hart();
+        // SAFETY: the PMP configuration is the one installed before the suspend, and the firmware
+        // is still the running world.
+        unsafe { Arch::write_pmp(&mctx.pmp).flush() };
+    }
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(
+        extension: SbiExtension,
+        fid: usize,
+        ty: usize,
+    ) -> Result<Option<SuspendKind>, isize> {
+        SuspendRequest::decode(Some(extension), fid, [ty, 0x8020_0000, 0])
+            .map(|request| request.map(|request| request.kind))
+    }
+
+    #[test]
+    fn hart_suspend_types() {
+        let hsm = SbiExtension::Hsm;
+        assert_eq!(
+            decode(hsm, HART_SUSPEND_FID, 0),
+            Ok(Some(SuspendKind::Retentive(0)))
+        );
+        assert_eq!(
+            decode(hsm, HART_SUSPEND_FID, 0x8000_0000),
+            Ok(Some(SuspendKind::NonRetentive(0x8000_0000)))
+        );
+        assert_eq!(
+            decode(hsm, HART_SUSPEND_FID, 0x1000_0000),
+            Ok(Some(SuspendKind::Retentive(0x1000_0000)))
+        );
+        assert_eq!(
+            decode(hsm, HART_SUSPEND_FID, 0x9000_0001),
+            Ok(Some(SuspendKind::NonRetentive(0x9000_0001)))
+        );
+        assert_eq!(
+            decode(hsm, HART_SUSPEND_FID, 0x1),
+            Err(SBI_ERR_INVALID_PARAM)
+        );
+        assert_eq!(
+            decode(hsm, HART_SUSPEND_FID, 0x8000_0001),
+            Err(SBI_ERR_INVALID_PARAM)
+        );
+        assert_eq!(
+            decode(hsm, HART_SUSPEND_FID, 1 << 32),
+            Err(SBI_ERR_INVALID_PARAM)
+        );
+
+        // Other HSM calls are not suspend requests.
+        assert_eq!(decode(hsm, 0x0, 0), Ok(None));
+    }
+
+    #[test]
+    fn system_suspend_types() {
+        let susp = SbiExtension::Susp;
+        assert_eq!(
+            decode(susp, SYSTEM_SUSPEND_FID, 0),
+            Ok(Some(SuspendKind::System(0)))
+        );
+        assert_eq!(
+            decode(susp, SYSTEM_SUSPEND_FID, 0x8000_0000),
+            Ok(Some(SuspendKind::System(0x8000_0000)))
+        );
+        assert_eq!(
+            decode(susp, SYSTEM_SUSPEND_FID, 0x1),
+            Err(SBI_ERR_INVALID_PARAM)
+        );
+        assert_eq!(decode(susp, 0x1, 0), Ok(None));
+        assert!(SuspendKind::System(0).is_non_retentive());
+        assert!(!SuspendKind::Retentive(0).is_non_retentive());
+    }
+}
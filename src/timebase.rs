@@ -0,0 +1,161 @@
+//! Virtual Timebase
+//!
+//! The firmware and the payload each observe their own view of the machine time, defined as the
+//! physical `mtime` plus a per-world offset. All time reads performed on behalf of a world go
+//! through this module, such that both worlds observe a consistent time base.
+//!
+//! A write to `mtime` moves the time of both worlds by the same amount, as a write to the physical
+//! `mtime` would, and any difference between the offsets of the worlds (set by a policy) is
+//! preserved. Each view is therefore monotonic, and switching worlds never moves time backward,
+//! unless the firmware explicitly writes an earlier time, in which case time restarts from the
+//! written value in both worlds.
+//!
+//! Offsets are never applied to the physical `mtime`, such that deadlines registered by Miralis
+//! and the policies are not affected.
+
+use core::sync::atomic::{AtomicIsize, Ordering};
+
+use crate::platform::{Plat, Platform};
+use crate::virt::ExecutionMode;
+
+/// The virtual timebase, shared by all harts as `mtime` is.
+pub static TIMEBASE: VirtTimebase = VirtTimebase::new();
+
+const NB_WORLDS: usize = 2;
+
+/// Per-world views of the machine time.
+#[derive(Debug)]
+pub struct VirtTimebase {
+    /// Offset added to the physical time, for each world.
+    offsets: [AtomicIsize; NB_WORLDS],
+}
+
+impl VirtTimebase {
+    pub const fn new() -> Self {
+        VirtTimebase {
+            offsets: [const { AtomicIsize::new(0) }; NB_WORLDS],
+        }
+    }
+
+    fn index(world: ExecutionMode) -> usize {
+        match world {
+            ExecutionMode::Firmware => 0,
+            ExecutionMode::Payload => 1,
+        }
+    }
+
+    /// Reads the current time as observed by the given world.
+    pub fn read(&self, world: ExecutionMode) -> usize {
+        let mtime = Plat::get_clint().lock().read_mtime();
+        self.view(world, mtime)
+    }
+
+    /// Returns the time observed by the given world for a physical time of `mtime`.
+    pub fn view(&self, world: ExecutionMode, mtime: usize) -> usize {
+        apply_offset(mtime, self.offset(world))
+    }
+
+    /// Emulates a write of `value` to `mtime` by the firmware, at a physical time of `mtime`.
+    ///
+    /// The firmware observes `value`, and the time of the payload moves by the same amount.
+    pub fn set_time(&self, mtime: usize, value: usize) {
+        let firmware_offset = (value as i128 - mtime as i128) as isize;
+        let delta = firmware_offset.wrapping_sub(self.offset(ExecutionMode::Firmware));
+        for offset in &self.offsets {
+            offset.fetch_add(delta, Ordering::SeqCst);
+        }
+    }
+
+    /// Returns the offset of the given world relative to the physical time.
+    pub fn offset(&self, world: ExecutionMode) -> isize {
+        self.offsets[Self::index(world)].load(Ordering::SeqCst)
+    }
+
+    /// Sets the offset of the given world relative to the physical time.
+    pub fn set_offset(&self, world: ExecutionMode, offset: isize) {
+        self.offsets[Self::index(world)].store(offset, Ordering::SeqCst);
+    }
+
+    /// Converts a deadline expressed in the time of the given world into a physical deadline.
+    ///
+    /// A deadline of usize::MAX means no deadline and is preserved.
+    pub fn to_physical(&self, world: ExecutionMode, deadline: usize) -> usize {
+        if deadline == usize::MAX {
+            return usize::MAX;
+        }
+        apply_offset(deadline, -self.offset(world)).min(usize::MAX - 1)
+    }
+}
+
+fn apply_offset(time: usize, offset: isize) -> usize {
+    (time as i128 + offset as i128).clamp(0, usize::MAX as i128) as usize
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIRMWARE: ExecutionMode = ExecutionMode::Firmware;
+    const PAYLOAD: ExecutionMode = ExecutionMode::Payload;
+
+    #[test]
+    fn offsets_are_per_world() {
+        let timebase = VirtTimebase::new();
+        timebase.set_offset(PAYLOAD, 1000);
+
+        assert_eq!(timebase.view(FIRMWARE, 200), 200);
+        assert_eq!(timebase.view(PAYLOAD, 200), 1200);
+        assert_eq!(timebase.to_physical(FIRMWARE, 1500), 1500);
+        assert_eq!(timebase.to_physical(PAYLOAD, 1500), 500);
+        assert_eq!(timebase.to_physical(PAYLOAD, usize::MAX), usize::MAX);
+    }
+
+    #[test]
+    fn mtime_writes_apply_to_both_worlds() {
+        let timebase = VirtTimebase::new();
+        timebase.set_time(100, 1100);
+        assert_eq!(timebase.view(FIRMWARE, 200), 1200);
+        assert_eq!(timebase.view(PAYLOAD, 200), 1200);
+        assert_eq!(timebase.to_physical(PAYLOAD, 1500), 500);
+
+        // The difference between the worlds is preserved
+        timebase.set_offset(PAYLOAD, timebase.offset(FIRMWARE) + 50);
+        timebase.set_time(300, 0);
+        assert_eq!(timebase.view(FIRMWARE, 400), 100);
+        assert_eq!(timebase.view(PAYLOAD, 400), 150);
+    }
+
+    #[test]
+    fn monotonic_across_world_switches() {
+        let timebase = VirtTimebase::new();
+        let mut last = 0;
+        let mut mtime = 0;
+
+        for step in 0..100 {
+            mtime += 7;
+            // Periodically move the time forward
+            if step % 10 == 3 {
+                timebase.set_time(mtime, timebase.view(FIRMWARE, mtime) + 1000);
+            }
+            // Alternate between the worlds
+            let world = [FIRMWARE, PAYLOAD][step % 2];
+            let time = timebase.view(world, mtime);
+            assert!(time >= last, "{:?} time went backward", world);
+            last = time;
+        }
+    }
+
+    #[test]
+    fn backward_writes_do_not_freeze_time() {
+        let timebase = VirtTimebase::new();
+        assert_eq!(timebase.view(FIRMWARE, 1000), 1000);
+
+        // Warm reset of the firmware time, time restarts from the written value
+        timebase.set_time(1000, 0);
+        assert_eq!(timebase.view(FIRMWARE, 1500), 500);
+        assert_eq!(timebase.view(PAYLOAD, 1500), 500);
+        assert_eq!(timebase.to_physical(FIRMWARE, 1200), 2200);
+    }
+}
@@ -0,0 +1,139 @@
+//! Timer Events
+//!
+//! Each hart has a single physical `mtimecmp`, which Miralis multiplexes between all the users of
+//! the machine timer: the virtual timers of the firmware and of the payload, the deadlines of the
+//! policy module (e.g. watchdogs), and the events internal to Miralis such as memory scrubbing or
+//! deferred work.
+//!
+//! Each user registers its next deadline in the event queue of the hart, keyed on the physical
+//! CLINT time. The queue is the only place deciding the next value of the physical `mtimecmp`,
+//! which always holds the earliest pending deadline.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// The users of the physical machine timer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimerEvent {
+    /// Deadlines internal to Miralis, such as memory scrubbing or deferred work.
+    Miralis = 0,
+    /// The virtual `mtimecmp` of the firmware.
+    Firmware = 1,
+    /// Deadlines of the payload, emulated by the policy for instance.
+    Payload = 2,
+    /// Deadlines of the policy module, such as watchdogs.
+    Policy = 3,
+}
+
+impl TimerEvent {
+    pub const COUNT: usize = 4;
+
+    /// All the events, in the order in which expired events are dispatched.
+    pub const ALL: [TimerEvent; Self::COUNT] = [
+        TimerEvent::Miralis,
+        TimerEvent::Firmware,
+        TimerEvent::Payload,
+        TimerEvent::Policy,
+    ];
+
+    /// Returns the bit corresponding to this event in a mask of events.
+    pub const fn mask(self) -> usize {
+        1 << (self as usize)
+    }
+}
+
+/// The pending deadlines of a hart, one per event.
+///
+/// Deadlines are expressed in physical time, usize::MAX if none. The queue can be updated from
+/// remote harts, for instance when the firmware writes the `mtimecmp` of another hart.
+#[derive(Debug)]
+pub struct TimerQueue {
+    deadlines: [AtomicUsize; TimerEvent::COUNT],
+}
+
+impl TimerQueue {
+    pub const fn new() -> Self {
+        TimerQueue {
+            deadlines: [const { AtomicUsize::new(usize::MAX) }; TimerEvent::COUNT],
+        }
+    }
+
+    /// Registers the deadline of an event, replacing the previous one.
+    ///
+    /// A deadline of usize::MAX cancels the pending deadline, if any.
+    pub fn set(&self, event: TimerEvent, deadline: usize) {
+        self.deadlines[event as usize].store(deadline, Ordering::SeqCst);
+    }
+
+    /// Returns the pending deadline of an event, usize::MAX if none.
+    pub fn get(&self, event: TimerEvent) -> usize {
+        self.deadlines[event as usize].load(Ordering::SeqCst)
+    }
+
+    /// Returns the earliest pending deadline, usize::MAX if none.
+    pub fn next_deadline(&self) -> usize {
+        self.deadlines
+            .iter()
+            .map(|deadline| deadline.load(Ordering::SeqCst))
+            .min()
+            .unwrap_or(usize::MAX)
+    }
+
+    /// Removes the events whose deadline is reached at time `now`.
+    ///
+    /// Returns the mask of [TimerEvent] that expired.
+    pub fn expire(&self, now: usize) -> usize {
+        let mut expired = 0;
+        for event in TimerEvent::ALL {
+            let deadline = &self.deadlines[event as usize];
+            let value = deadline.load(Ordering::SeqCst);
+            // The deadline might be updated concurrently, in which case it is kept
+            if value <= now
+                && deadline
+                    .compare_exchange(value, usize::MAX, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+            {
+                expired |= event.mask();
+            }
+        }
+        expired
+    }
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn earliest_deadline() {
+        let queue = TimerQueue::new();
+        assert_eq!(queue.next_deadline(), usize::MAX);
+
+        queue.set(TimerEvent::Firmware, 300);
+        queue.set(TimerEvent::Policy, 200);
+        assert_eq!(queue.next_deadline(), 200);
+
+        // Cancelling a deadline moves to the next one
+        queue.set(TimerEvent::Policy, usize::MAX);
+        assert_eq!(queue.next_deadline(), 300);
+    }
+
+    #[test]
+    fn expire_events() {
+        let queue = TimerQueue::new();
+        queue.set(TimerEvent::Miralis, 100);
+        queue.set(TimerEvent::Payload, 150);
+        queue.set(TimerEvent::Firmware, 500);
+
+        assert_eq!(queue.expire(50), 0);
+        assert_eq!(
+            queue.expire(150),
+            TimerEvent::Miralis.mask() | TimerEvent::Payload.mask()
+        );
+        // Expired events are removed from the queue
+        assert_eq!(queue.get(TimerEvent::Miralis), usize::MAX);
+        assert_eq!(queue.next_deadline(), 500);
+        assert_eq!(queue.expire(150), 0);
+    }
+}
@@ -0,0 +1,166 @@
+//! Execution Tracing
+//!
+//! With the `trace` feature and a non-zero `debug.trace_entries` configuration, Miralis records an
+//! event right before entering the virtual CPU and right after leaving it. The events are written
+//! to a trace buffer in Miralis' memory with the layout defined in `miralis_core::abi::trace`, such
+//! that external tools can read it from a debugger or a memory dump without any cooperation from
+//! Miralis. The buffer is exported as the `MIRALIS_TRACE_BUFFER` symbol.
+//!
+//! The `runner trace` subcommand converts a dump of the buffer into a timeline that can be
+//! imported in Perfetto.
+
+use core::cell::UnsafeCell;
+use core::ptr;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use miralis_core::abi::trace::{self, TraceEvent, TraceHeader};
+
+use crate::arch::{Arch, Architecture, Csr};
+use crate::config::{PLATFORM_NB_HARTS, TRACE_ENTRIES};
+use crate::virt::{ExecutionMode, VirtContext};
+
+/// The trace buffer, exported such that external tools can locate it.
+#[no_mangle]
+static MIRALIS_TRACE_BUFFER: TraceBuffer = TraceBuffer::new();
+
+const EMPTY_EVENT: TraceEvent = TraceEvent {
+    timestamp: 0,
+    kind: 0,
+    args: [0; 2],
+};
+
+/// The in-memory trace buffer, its layout must match `miralis_core::abi::trace`.
+#[repr(C)]
+struct TraceBuffer {
+    header: TraceHeader,
+    rings: [HartRing<TRACE_ENTRIES>; PLATFORM_NB_HARTS],
+}
+
+impl TraceBuffer {
+    const fn new() -> Self {
+        TraceBuffer {
+            header: TraceHeader {
+                magic: trace::MAGIC,
+                version: trace::VERSION,
+                nb_harts: PLATFORM_NB_HARTS as u64,
+                entries_per_hart: TRACE_ENTRIES as u64,
+            },
+            rings: [const { HartRing::new() }; PLATFORM_NB_HARTS],
+        }
+    }
+}
+
+/// The events of a single hart.
+#[repr(C)]
+struct HartRing<const N: usize> {
+    /// Number of events ever written to the ring.
+    head: AtomicU64,
+    events: UnsafeCell<[TraceEvent; N]>,
+}
+
+// SAFETY: a ring is only ever written by its own hart, other harts never access it.
+unsafe impl<const N: usize> Sync for HartRing<N> {}
+
+impl<const N: usize> HartRing<N> {
+    const fn new() -> Self {
+        HartRing {
+            head: AtomicU64::new(0),
+            events: UnsafeCell::new([EMPTY_EVENT; N]),
+        }
+    }
+
+    /// Appends an event, overwriting the oldest one if the ring is full.
+    ///
+    /// SAFETY: must only be called by the hart owning the ring.
+    unsafe fn push(&self, event: TraceEvent) {
+        if N == 0 {
+            return;
+        }
+
+        let head = self.head.load(Ordering::Relaxed);
+        let slot = (self.events.get() as *mut TraceEvent).add(head as usize % N);
+        // The buffer is read concurrently by external tools, make sure the write is performed
+        ptr::write_volatile(slot, event);
+        // Publish the event once written
+        self.head.store(head + 1, Ordering::Release);
+    }
+}
+
+// ————————————————————————————————— Hooks —————————————————————————————————— //
+
+/// Records that the hart is about to enter the virtual CPU.
+#[inline]
+pub fn enter_vcpu(ctx: &VirtContext) {
+    let world = match ctx.mode.to_exec_mode() {
+        ExecutionMode::Firmware => 0,
+        ExecutionMode::Payload => 1,
+    };
+    record(ctx.hart_id, trace::RUN_VCPU_ENTER, [ctx.pc as u64, world]);
+}
+
+/// Records that the hart just left the virtual CPU.
+#[inline]
+pub fn exit_vcpu(ctx: &VirtContext) {
+    record(
+        ctx.hart_id,
+        trace::RUN_VCPU_EXIT,
+        [ctx.trap_info.mcause as u64, ctx.trap_info.mtval as u64],
+    );
+}
+
+fn record(hart_id: usize, kind: u64, args: [u64; 2]) {
+    if TRACE_ENTRIES == 0 {
+        return;
+    }
+    let Some(ring) = MIRALIS_TRACE_BUFFER.rings.get(hart_id) else {
+        return;
+    };
+
+    let event = TraceEvent {
+        timestamp: Arch::read_csr(Csr::Mcycle) as u64,
+        kind,
+        args,
+    };
+    // SAFETY: each hart only records events in the ring matching its own hart ID.
+    unsafe { ring.push(event) };
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(timestamp: u64) -> TraceEvent {
+        TraceEvent {
+            timestamp,
+            kind: trace::RUN_VCPU_ENTER,
+            args: [0; 2],
+        }
+    }
+
+    #[test]
+    fn ring_wraps_around() {
+        let ring = HartRing::<4>::new();
+        for timestamp in 0..6 {
+            unsafe { ring.push(event(timestamp)) };
+        }
+
+        assert_eq!(ring.head.load(Ordering::Relaxed), 6);
+        let events = unsafe { &*ring.events.get() };
+        let timestamps: [u64; 4] = core::array::from_fn(|idx| events[idx].timestamp);
+        assert_eq!(timestamps, [4, 5, 2, 3]);
+    }
+
+    #[test]
+    fn buffer_layout() {
+        use core::mem::{offset_of, size_of};
+
+        // External tools rely on the layout documented in `miralis_core::abi::trace`
+        assert_eq!(size_of::<TraceHeader>(), 4 * 8);
+        assert_eq!(size_of::<TraceEvent>(), 4 * 8);
+        assert_eq!(offset_of!(TraceBuffer, rings), size_of::<TraceHeader>());
+        assert_eq!(offset_of!(HartRing<4>, events), 8);
+        assert_eq!(size_of::<HartRing<4>>(), 8 + 4 * size_of::<TraceEvent>());
+    }
+}
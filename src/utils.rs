@@ -28,6 +28,23 @@ pub fn sign_extend(value: usize, width: Width) -> usize {
     }
 }
 
+/// Extends a value loaded with the given width to the register width.
+///
+/// Signed loads (`lb`, `lh`, `lw`) are sign-extended and unsigned loads (`lbu`, `lhu`, `lwu`) are
+/// zero-extended. Bits beyond the width are ignored, such that devices returning a full register
+/// for a narrower access do not leak them into the destination register.
+pub fn extend_load(value: usize, width: Width, is_unsigned: bool) -> usize {
+    let value = match width {
+        Width::Byte8 => value,
+        _ => value & ((1 << width.to_bits()) - 1),
+    };
+    if is_unsigned {
+        value
+    } else {
+        sign_extend(value, width)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -54,4 +71,37 @@ mod tests {
         assert_eq!(sign_extend(0x00000000, Width::Byte4), 0x00000000);
         assert_eq!(sign_extend(0x0000ffff, Width::Byte4), 0x0000ffff);
     }
+    #[test]
+    fn load_extension() {
+        // The upper bits returned by the device are not part of the access
+        let value = 0xdead_beef_8765_8281;
+
+        // lb and lbu
+        assert_eq!(
+            extend_load(value, Width::Byte, false),
+            0xffff_ffff_ffff_ff81
+        );
+        assert_eq!(extend_load(value, Width::Byte, true), 0x81);
+        assert_eq!(extend_load(0x7f, Width::Byte, false), 0x7f);
+
+        // lh and lhu
+        assert_eq!(
+            extend_load(value, Width::Byte2, false),
+            0xffff_ffff_ffff_8281
+        );
+        assert_eq!(extend_load(value, Width::Byte2, true), 0x8281);
+        assert_eq!(extend_load(0xffff_7fff, Width::Byte2, false), 0x7fff);
+
+        // lw and lwu
+        assert_eq!(
+            extend_load(value, Width::Byte4, false),
+            0xffff_ffff_8765_8281
+        );
+        assert_eq!(extend_load(value, Width::Byte4, true), 0x8765_8281);
+        assert_eq!(extend_load(0x1_7fff_ffff, Width::Byte4, false), 0x7fff_ffff);
+
+        // ld
+        assert_eq!(extend_load(value, Width::Byte8, false), value);
+        assert_eq!(extend_load(value, Width::Byte8, true), value);
+    }
 }
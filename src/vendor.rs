@@ -0,0 +1,144 @@
+//! Miralis Vendor SBI Extension
+//!
+//! The vendor extension lets the payload query Miralis (its version, the number of firmware exits
+//! and the name of the active policy) and request services such as selecting, resetting, or
+//! dumping the benchmark counters. The extension ID and function IDs are defined in the `abi_vendor` module of the
+//! Miralis ABI.
+//!
+//! Calls are handled by Miralis and are never forwarded to the firmware. Miralis also answers the
+//! Base extension probes of the vendor extension on behalf of the firmware, which is not aware of
+//! it.
+
+use config_helpers::parse_usize_or;
+use miralis_core::abi_vendor;
+
+use crate::arch::{parse_mpp_return_mode, Arch, Architecture, Csr, Register};
+use crate::benchmark::Benchmark;
+use crate::config;
+use crate::device::status;
+use crate::policy::{Policy, PolicyModule};
+use crate::virt::{ExecutionMode, RegisterContextGetter, RegisterContextSetter, VirtContext};
+
+const SBI_ERR_NOT_SUPPORTED: isize = -2;
+const SBI_ERR_INVALID_PARAM: isize = -3;
+const SBI_ERR_INVALID_ADDRESS: isize = -5;
+
+/// The Base extension ID.
+const BASE_EID: usize = 0x10;
+/// The Base extension `probe_extension` function ID.
+const PROBE_EXTENSION_FID: usize = 3;
+
+/// Maximum number of bytes of the policy name copied to the payload.
+const MAX_POLICY_NAME_SIZE: usize = 64;
+
+/// The version of Miralis, as returned by `MIRALIS_VENDOR_GET_VERSION_FID`.
+const VERSION: usize = encode_version(
+    parse_usize_or(Some(env!("CARGO_PKG_VERSION_MAJOR")), 0),
+    parse_usize_or(Some(env!("CARGO_PKG_VERSION_MINOR")), 0),
+    parse_usize_or(Some(env!("CARGO_PKG_VERSION_PATCH")), 0),
+);
+
+const fn encode_version(major: usize, minor: usize, patch: usize) -> usize {
+    (major << 24) | ((minor & 0xfff) << 12) | (patch & 0xfff)
+}
+
+// —————————————————————————————— Vendor Calls —————————————————————————————— //
+
+/// Handles a call to the vendor extension, or a probe of the vendor extension, from the payload.
+///
+/// Returns true if the call has been answered, in which case the ecall must not be forwarded to
+/// the firmware.
+pub fn handle_payload_call(ctx: &mut VirtContext) -> bool {
+    let eid = ctx.get(Register::X17);
+    let fid = ctx.get(Register::X16);
+
+    let (error, value) = if eid == abi_vendor::MIRALIS_VENDOR_EID {
+        handle_call(ctx, fid)
+    } else if eid == BASE_EID
+        && fid == PROBE_EXTENSION_FID
+        && ctx.get(Register::X10) == abi_vendor::MIRALIS_VENDOR_EID
+    {
+        // The extension is available, the value is its version
+        (0, VERSION)
+    } else {
+        return false;
+    };
+
+    ctx.set(Register::X10, error as usize);
+    ctx.set(Register::X11, value);
+    ctx.pc += 4;
+    true
+}
+
+/// Executes a vendor call, returns the SBI error and value.
+fn handle_call(ctx: &mut VirtContext, fid: usize) -> (isize, usize) {
+    match fid {
+        abi_vendor::MIRALIS_VENDOR_GET_VERSION_FID => (0, VERSION),
+        abi_vendor::MIRALIS_VENDOR_GET_FIRMWARE_EXITS_FID => {
+            match status::exits(ctx.get(Register::X10), ExecutionMode::Firmware) {
+                Some(exits) => (0, exits),
+                None => (SBI_ERR_INVALID_PARAM, 0),
+            }
+        }
+        abi_vendor::MIRALIS_VENDOR_GET_POLICY_NAME_FID => copy_policy_name(ctx),
+        abi_vendor::MIRALIS_VENDOR_DUMP_BENCHMARK_FID if config::BENCHMARK => {
+            Benchmark::record_counters();
+            (0, 0)
+        }
+        abi_vendor::MIRALIS_VENDOR_SET_BENCHMARK_FID if config::BENCHMARK => {
+            match Benchmark::select(ctx.get(Register::X10), ctx.get(Register::X11)) {
+                Ok(previous) => (0, previous),
+                Err(_) => (SBI_ERR_INVALID_PARAM, 0),
+            }
+        }
+        abi_vendor::MIRALIS_VENDOR_RESET_BENCHMARK_FID if config::BENCHMARK => {
+            Benchmark::reset_counters();
+            (0, 0)
+        }
+        _ => {
+            log::debug!(
+                "Unsupported vendor call 0x{:x} on hart {}",
+                fid,
+                ctx.hart_id
+            );
+            (SBI_ERR_NOT_SUPPORTED, 0)
+        }
+    }
+}
+
+/// Copies the name of the policy to the buffer passed by the payload, returns the SBI error and
+/// the full length of the name.
+fn copy_policy_name(ctx: &mut VirtContext) -> (isize, usize) {
+    let name = Policy::name().as_bytes();
+    let addr = ctx.get(Register::X10);
+    let size = ctx.get(Register::X11).min(name.len());
+
+    let mut bytes = [0; MAX_POLICY_NAME_SIZE];
+    let bytes = &mut bytes[..size.min(MAX_POLICY_NAME_SIZE)];
+    bytes.copy_from_slice(&name[..bytes.len()]);
+
+    // SAFETY: the bytes are stored with the privileges of the payload, which is the mode saved in
+    // mstatus.MPP by the trap.
+    let mode = parse_mpp_return_mode(Arch::read_csr(Csr::Mstatus));
+    match unsafe { Arch::store_bytes_from_mode(bytes, addr as *const u8, mode) } {
+        Ok(()) => (0, name.len()),
+        Err(()) => (SBI_ERR_INVALID_ADDRESS, 0),
+    }
+}
+
+// ————————————————————————————————— Tests —————————————————————————————————— //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_encoding() {
+        assert_eq!(encode_version(0, 1, 0), 0x1000);
+        assert_eq!(encode_version(1, 2, 3), 0x0100_2003);
+        assert_eq!(
+            VERSION >> 24,
+            parse_usize_or(Some(env!("CARGO_PKG_VERSION_MAJOR")), 0)
+        );
+    }
+}
@@ -3,21 +3,35 @@
 use miralis_core::abi;
 
 use crate::arch::mstatus::{MBE_FILTER, SBE_FILTER, UBE_FILTER};
-use crate::arch::pmp::pmpcfg;
 use crate::arch::pmp::pmpcfg::NO_PERMISSIONS;
+use crate::arch::pmp::{self, pmpcfg};
 use crate::arch::{
-    hstatus, mie, misa, mstatus, mtvec, parse_mpp_return_mode, satp, Arch, Architecture, Csr,
-    ExtensionsCapability, MCause, Mode, Register, TrapInfo,
+    hstatus, mcounteren, menvcfg, mie, misa, mstatus, mtvec, page_table, parse_mpp_return_mode,
+    satp, tdata1, Arch, Architecture, Csr, ExtensionsCapability, MCause, Mode, Register, TrapInfo,
 };
-use crate::benchmark::Benchmark;
-use crate::config::DELEGATE_PERF_COUNTER;
+use crate::audit::AuditEvent;
+use crate::benchmark::{Benchmark, Scope};
+use crate::config::{
+    COUNTER_POLL_THRESHOLD, DEBUG_SEMIHOSTING, DEBUG_STUB, DELEGATE_PERF_COUNTER,
+    VCPU_EMULATE_MISALIGNED, VCPU_TRAP_HPM_COUNTERS, VCPU_TRIGGERS,
+};
+use crate::counters::{Counter, VirtCounters};
 use crate::decoder::Instr;
-use crate::device::VirtDevice;
+use crate::deferred::{Work, WorkQueue};
+use crate::device::{DeferredEffects, DeferredWrite, PayloadAccess, VirtDevice, WriteSemantic};
+use crate::fault::Fault;
 use crate::host::MiralisContext;
 use crate::platform::{Plat, Platform};
 use crate::policy::{Policy, PolicyModule};
-use crate::utils::sign_extend;
-use crate::{debug, device, utils};
+use crate::sbi::SbiExtension;
+use crate::suspend::{self, SuspendRequest};
+use crate::timebase::TIMEBASE;
+use crate::timer::TimerEvent;
+use crate::utils::extend_load;
+use crate::{
+    audit, build_info, capabilities, coverage, debug, exception, fault, logger, memory_layout,
+    misaligned, panic_report, pmp_view, sbi, semihosting, utils, vendor,
+};
 
 /// The execution mode, either virtualized firmware or native payload.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -29,6 +43,11 @@ pub enum ExecutionMode {
 }
 
 /// The context of a virtual firmware.
+///
+/// The context is read and written on every exit, the fields are therefore ordered by how often
+/// they are accessed: the fields touched on every exit come first and share the first cache lines,
+/// followed by the hot CSRs at the start of [VirtCsr]. The offsets of `host_stack`, `regs`, `pc`
+/// and `trap_info` are also relied upon by the context switch assembly.
 #[derive(Debug)]
 #[repr(C)]
 pub struct VirtContext {
@@ -40,18 +59,59 @@ pub struct VirtContext {
     pub(crate) pc: usize,
     /// Information on the trap that ocurred, used to handle traps
     pub(crate) trap_info: TrapInfo,
-    /// Virtual Control and Status Registers
-    pub(crate) csr: VirtCsr,
     /// Current privilege mode
     pub(crate) mode: Mode,
+    /// Whether the payload runs in a virtualized mode (VS or VU-mode) of the hypervisor extension
+    pub(crate) virtualized: bool,
+    /// Number of exists to Miralis
+    pub(crate) nb_exits: usize,
+    /// Cached `mie & mip & !mideleg`, the set of interrupts that are enabled, pending, and not
+    /// delegated to S-mode.
+    ///
+    /// This mask must be refreshed with `update_pending_interrupts` whenever one of the three
+    /// underlying CSRs is modified.
+    pub(crate) pending_interrupts: usize,
+    /// Hart ID
+    pub(crate) hart_id: usize,
+    /// Virtual Control and Status Registers
+    pub(crate) csr: VirtCsr,
     /// Number of virtual PMPs
     pub(crate) nb_pmp: usize,
     /// Availables RISC-V extensions
     pub(crate) extensions: ExtensionsCapability,
-    /// Hart ID
-    pub(crate) hart_id: usize,
-    /// Number of exists to Miralis
-    pub(crate) nb_exits: usize,
+    /// State of the counter polling detection, used to coalesce exits.
+    pub(crate) counter_polling: CounterPolling,
+    /// Posted device writes, applied before re-entering the guest
+    pub(crate) deferred_effects: DeferredEffects,
+    /// Work deferred until the next guest entry, see the `deferred` module.
+    pub(crate) deferred_work: WorkQueue,
+    /// Suspend request from the payload, forwarded to the firmware and not yet completed.
+    pub(crate) pending_suspend: Option<SuspendRequest>,
+    /// Whether the hypervisor CSRs of the payload are still only in the physical registers, see
+    /// `sync_lazy_h_csrs`.
+    ///
+    /// Only the hypervisor and virtual supervisor CSRs are saved lazily, on payload SBI calls
+    /// forwarded to the firmware: the trap entry and the floating point state are not affected.
+    /// The CSRs are only reachable through [VirtContext::h_csr] and [VirtContext::h_csr_mut],
+    /// which save them on first access.
+    lazy_h_csrs: bool,
+    /// The virtual cycle and instret counters of the firmware and the payload.
+    pub(crate) counters: VirtCounters,
+}
+
+/// Tracks firmware loops polling an unprivileged counter (e.g. `rdtime`).
+///
+/// Reading a counter from the virtualized firmware causes an exit, which is dramatic for firmware
+/// busy-waiting on the timer. Once a loop is detected the counter is temporarily passed through to
+/// the firmware, until its next exit.
+#[derive(Debug)]
+pub struct CounterPolling {
+    /// PC of the last counter read.
+    pc: usize,
+    /// Number of exits caused by counter reads at `pc`.
+    hits: usize,
+    /// Counters currently passed through to the firmware, as a mcounteren mask.
+    passthrough: usize,
 }
 
 impl VirtContext {
@@ -74,8 +134,6 @@ impl VirtContext {
                 mvendorid: 0,
                 marchid: 0,
                 mimpid: 0,
-                mcycle: 0,
-                minstret: 0,
                 mcountinhibit: 0,
                 mcounteren: 0,
                 menvcfg: 0,
@@ -96,6 +154,7 @@ impl VirtContext {
                 stval: 0,
                 satp: 0,
                 scontext: 0,
+                stimecmp: usize::MAX,
                 medeleg: 0,
                 mideleg: mie::MIDELEG_READ_ONLY_ONE,
                 hstatus: 0,
@@ -127,9 +186,12 @@ impl VirtContext {
                 pmpaddr: [0; 64],
                 mhpmcounter: [0; 29],
                 mhpmevent: [0; 29],
+                tselect: 0,
+                tdata: [[tdata1::TYPE_DISABLED << tdata1::TYPE_OFFSET, 0, 0]; VCPU_TRIGGERS],
             },
             pc: 0,
             mode: Mode::M,
+            virtualized: false,
             nb_pmp: nb_pmp_registers_left,
             trap_info: TrapInfo {
                 mepc: 0,
@@ -141,33 +203,140 @@ impl VirtContext {
             nb_exits: 0,
             hart_id,
             extensions: available_extension,
+            pending_interrupts: 0,
+            counter_polling: CounterPolling {
+                pc: 0,
+                hits: 0,
+                passthrough: 0,
+            },
+            deferred_effects: DeferredEffects::new(),
+            deferred_work: WorkQueue::new(),
+            pending_suspend: None,
+            lazy_h_csrs: false,
+            counters: VirtCounters::new(),
+        }
+    }
+
+    /// Recompute the cached pending interrupts mask.
+    ///
+    /// Must be called after each update of the virtual `mie`, `mip`, or `mideleg`.
+    #[inline]
+    pub fn update_pending_interrupts(&mut self) {
+        self.pending_interrupts = self.csr.mie & self.csr.mip & !self.csr.mideleg;
+    }
+
+    /// Detect firmware loops polling a counter CSR.
+    ///
+    /// After `COUNTER_POLL_THRESHOLD` reads of a counter from the same instruction the counter is
+    /// passed through, so that the next iterations of the loop execute without exits.
+    fn track_counter_polling(&mut self, instr: &Instr, mctx: &MiralisContext) {
+        let Some(threshold) = COUNTER_POLL_THRESHOLD else {
+            return;
+        };
+
+        // Only `time` matches its virtual value, unless the firmware time is offset, the cycle and
+        // instret counters are exposed only when performance counters are delegated.
+        let mask = match instr {
+            Instr::Csrrs {
+                csr,
+                rs1: Register::X0,
+                ..
+            } => match csr {
+                Csr::Time if TIMEBASE.offset(ExecutionMode::Firmware) == 0 => mcounteren::TM_FILTER,
+                Csr::Cycle if DELEGATE_PERF_COUNTER => mcounteren::CY_FILTER,
+                Csr::Instret if DELEGATE_PERF_COUNTER => mcounteren::IR_FILTER,
+                _ => return,
+            },
+            _ => return,
+        };
+
+        if self.counter_polling.pc == self.pc {
+            self.counter_polling.hits += 1;
+        } else {
+            self.counter_polling.pc = self.pc;
+            self.counter_polling.hits = 1;
+        }
+
+        if self.counter_polling.hits < threshold {
+            return;
+        }
+
+        log::trace!(
+            "Counter polling detected at 0x{:x}, passing through {:?}",
+            self.pc,
+            instr
+        );
+        self.counter_polling.hits = 0;
+        self.counter_polling.passthrough |= mask;
+        unsafe {
+            Arch::set_csr_bits(Csr::Mcounteren, mask);
+            if mctx.hw.extensions.has_s_extension {
+                Arch::set_csr_bits(Csr::Scounteren, mask);
+            }
+        }
+    }
+
+    /// Returns the given tdata register of the selected virtual trigger.
+    fn get_tdata(&self, idx: usize) -> usize {
+        match self.csr.tdata.get(self.csr.tselect) {
+            Some(tdata) => tdata[idx],
+            None => 0,
+        }
+    }
+
+    /// Sets the given tdata register of the selected virtual trigger.
+    ///
+    /// Virtual triggers are never installed in hardware, so that the firmware can not plant
+    /// breakpoints firing inside Miralis or the payload.
+    fn set_tdata(&mut self, idx: usize, value: usize) {
+        if let Some(tdata) = self.csr.tdata.get_mut(self.csr.tselect) {
+            tdata[idx] = value;
+        }
+    }
+
+    /// Revoke the counters passed through by `track_counter_polling`, if any.
+    fn end_counter_passthrough(&mut self, mctx: &MiralisContext) {
+        let mask = self.counter_polling.passthrough;
+        if mask == 0 {
+            return;
+        }
+
+        self.counter_polling.passthrough = 0;
+        unsafe {
+            Arch::clear_csr_bits(Csr::Mcounteren, mask);
+            if mctx.hw.extensions.has_s_extension {
+                Arch::clear_csr_bits(Csr::Scounteren, mask);
+            }
         }
     }
 }
 
 /// Control and Status Registers (CSR) for a virtual firmware.
+///
+/// The CSRs accessed on most exits (to compute pending interrupts, or to emulate traps and
+/// `mret`) come first, and the struct is aligned so that they share a single cache line.
 #[derive(Debug)]
-#[repr(C)]
+#[repr(C, align(64))]
 pub struct VirtCsr {
-    pub misa: usize,
+    pub mstatus: usize,
     pub mie: usize,
     pub mip: usize,
+    pub mideleg: usize,
+    pub medeleg: usize,
     pub mtvec: usize,
+    pub mepc: usize,
+    pub mcause: usize,
+    pub mtval: usize,
+    pub mscratch: usize,
+    pub misa: usize,
     pub mvendorid: usize,
     pub marchid: usize,
     pub mimpid: usize,
-    pub mcycle: usize,
-    pub minstret: usize,
-    pub mscratch: usize,
     pub mcountinhibit: usize,
     pub mcounteren: usize,
     pub menvcfg: usize,
     pub mseccfg: usize,
-    pub mcause: usize,
-    pub mepc: usize,
-    pub mtval: usize,
     pub mtval2: usize,
-    pub mstatus: usize,
     pub mtinst: usize,
     pub mconfigptr: usize,
     pub stvec: usize,
@@ -179,37 +348,41 @@ pub struct VirtCsr {
     pub stval: usize,
     pub satp: usize,
     pub scontext: usize,
-    pub medeleg: usize,
-    pub mideleg: usize,
-    pub hstatus: usize,
-    pub hedeleg: usize,
-    pub hideleg: usize,
-    pub hvip: usize,
-    pub hip: usize,
-    pub hie: usize,
-    pub hgeip: usize,
-    pub hgeie: usize,
-    pub henvcfg: usize,
+    pub stimecmp: usize,
+    // The hypervisor CSRs are private, as they are saved lazily. Other modules access them through
+    // `VirtContext::h_csr` and `VirtContext::h_csr_mut`.
+    hstatus: usize,
+    hedeleg: usize,
+    hideleg: usize,
+    hvip: usize,
+    hip: usize,
+    hie: usize,
+    hgeip: usize,
+    hgeie: usize,
+    henvcfg: usize,
     pub henvcfgh: usize,
-    pub hcounteren: usize,
-    pub htimedelta: usize,
+    hcounteren: usize,
+    htimedelta: usize,
     pub htimedeltah: usize,
-    pub htval: usize,
-    pub htinst: usize,
-    pub hgatp: usize,
-    pub vsstatus: usize,
-    pub vsie: usize,
-    pub vstvec: usize,
-    pub vsscratch: usize,
-    pub vsepc: usize,
-    pub vscause: usize,
-    pub vstval: usize,
-    pub vsip: usize,
-    pub vsatp: usize,
+    htval: usize,
+    htinst: usize,
+    hgatp: usize,
+    vsstatus: usize,
+    vsie: usize,
+    vstvec: usize,
+    vsscratch: usize,
+    vsepc: usize,
+    vscause: usize,
+    vstval: usize,
+    vsip: usize,
+    vsatp: usize,
     pub pmpcfg: [usize; 8],
     pub pmpaddr: [usize; 64],
     pub mhpmcounter: [usize; 29],
     pub mhpmevent: [usize; 29],
+    pub tselect: usize,
+    /// The tdata1, tdata2, and tdata3 registers of each virtual trigger.
+    pub tdata: [[usize; 3]; VCPU_TRIGGERS],
 }
 
 impl VirtCsr {
@@ -246,7 +419,11 @@ impl VirtContext {
                     Arch::write_csr(Csr::Mie, self.csr.mie);
                 }
 
-                Arch::wfi();
+                // The firmware suspends the hart on behalf of the payload
+                match self.pending_suspend.take() {
+                    Some(request) => suspend::suspend_hart(request, mctx),
+                    None => Arch::wfi(),
+                }
                 self.pc += 4;
             }
             Instr::Csrrw { csr, .. }
@@ -255,44 +432,30 @@ impl VirtContext {
             | Instr::Csrrwi { csr, .. }
             | Instr::Csrrsi { csr, .. }
             | Instr::Csrrci { csr, .. }
-                if csr.is_unknown() =>
+                if csr.is_unknown() || (csr.is_hpm() && VCPU_TRAP_HPM_COUNTERS) =>
             {
                 self.emulate_jump_trap_handler();
             }
-            Instr::Csrrw { csr, rd, rs1 } => {
-                let tmp = self.get(csr);
-                self.set_csr(csr, self.get(rs1), mctx);
-                self.set(rd, tmp);
-                self.pc += 4;
-            }
-            Instr::Csrrs { csr, rd, rs1 } => {
-                let tmp = self.get(csr);
-                self.set_csr(csr, tmp | self.get(rs1), mctx);
-                self.set(rd, tmp);
-                self.pc += 4;
-            }
-            Instr::Csrrwi { csr, rd, uimm } => {
-                self.set(rd, self.get(csr));
-                self.set_csr(csr, *uimm, mctx);
-                self.pc += 4;
-            }
-            Instr::Csrrsi { csr, rd, uimm } => {
-                let tmp = self.get(csr);
-                self.set_csr(csr, tmp | uimm, mctx);
-                self.set(rd, tmp);
-                self.pc += 4;
-            }
-            Instr::Csrrc { csr, rd, rs1 } => {
-                let tmp = self.get(csr);
-                self.set_csr(csr, tmp & !self.get(rs1), mctx);
-                self.set(rd, tmp);
-                self.pc += 4;
+            Instr::Csrrw { csr, .. }
+            | Instr::Csrrs { csr, .. }
+            | Instr::Csrrc { csr, .. }
+            | Instr::Csrrwi { csr, .. }
+            | Instr::Csrrsi { csr, .. }
+            | Instr::Csrrci { csr, .. }
+                if csr.is_pmp() && mctx.pmp.is_overcommitted() =>
+            {
+                self.emulate_coalesced_pmp_instr(instr, mctx);
             }
-            Instr::Csrrci { csr, rd, uimm } => {
-                let tmp = self.get(csr);
-                self.set_csr(csr, tmp & !uimm, mctx);
-                self.set(rd, tmp);
-                self.pc += 4;
+            Instr::Csrrw { csr, .. }
+            | Instr::Csrrs { csr, .. }
+            | Instr::Csrrc { csr, .. }
+            | Instr::Csrrwi { csr, .. }
+            | Instr::Csrrsi { csr, .. }
+            | Instr::Csrrci { csr, .. } => {
+                if csr.is_hypervisor() {
+                    self.sync_lazy_h_csrs();
+                }
+                self.emulate_csr_instr(instr, mctx)
             }
             Instr::Mret => {
                 match parse_mpp_return_mode(self.csr.mstatus) {
@@ -333,8 +496,10 @@ impl VirtContext {
                     }
                 }
                 // Modify mstatus
-                // ONLY WITH HYPERVISOR EXTENSION : MPV = 0,
-                if false {
+                // ONLY WITH HYPERVISOR EXTENSION : V = MPV (if returning to S or U-mode), MPV = 0
+                if self.csr.misa & misa::H != 0 {
+                    self.virtualized =
+                        self.mode != Mode::M && self.csr.mstatus & mstatus::MPV_FILTER != 0;
                     VirtCsr::set_csr_field(
                         &mut self.csr.mstatus,
                         mstatus::MPV_OFFSET,
@@ -368,6 +533,12 @@ impl VirtContext {
                 // Jump back to firmware
                 self.pc = self.csr.mepc;
             }
+            Instr::FenceI => {
+                // Only cores without Zifencei trap on fence.i, the decode cache is the only
+                // instruction cache to invalidate.
+                mctx.decode_cache.flush();
+                self.pc += 4;
+            }
             Instr::Sfencevma { rs1, rs2 } => unsafe {
                 let vaddr = match rs1 {
                     Register::X0 => None,
@@ -380,6 +551,13 @@ impl VirtContext {
                 Arch::sfencevma(vaddr, asid);
                 self.pc += 4;
             },
+            Instr::Hlv { .. } | Instr::Hsv { .. } if self.csr.misa & misa::H == 0 => {
+                // The instruction is illegal without the hypervisor extension
+                self.emulate_jump_trap_handler();
+            }
+            Instr::Hlv { .. } | Instr::Hsv { .. } => unsafe {
+                Arch::handle_virtual_load_store(instr.clone(), self);
+            },
             Instr::Hfencegvma { rs1, rs2 } => unsafe {
                 let vaddr = match rs1 {
                     Register::X0 => None,
@@ -415,9 +593,9 @@ impl VirtContext {
 
     /// Handles a load instruction.
     ///
-    /// Calculates the memory address, reads the value from the device,
-    /// sign-extends (normal load) or zero-extends (unsigned load) it to 64 bits if necessary,
-    /// applies a mask and writes the value to the device.
+    /// Calculates the memory address, reads the value from the device, then sign-extends (normal
+    /// load) or zero-extends (unsigned load) it to 64 bits and writes it to the destination
+    /// register.
     ///
     /// - Normal load&store instructions are 4 bytes long.
     /// - The immediate (`imm`) value can be positive or negative.
@@ -438,16 +616,13 @@ impl VirtContext {
 
                 match device.device_interface.read_device(offset, *len, self) {
                     Ok(value) => {
-                        let value = if !is_unsigned {
-                            sign_extend(value, *len)
-                        } else {
-                            value
-                        };
-
-                        self.set(*rd, value);
+                        self.set(*rd, extend_load(value, *len, *is_unsigned));
                         self.pc += if *is_compressed { 2 } else { 4 };
                     }
-                    Err(err) => panic!("Error reading {}: {}", device.name, err),
+                    Err(err) => {
+                        log::warn!("Error reading {}: {}", device.name, err);
+                        self.emulate_device_access_fault(MCause::LoadAccessFault, address);
+                    }
                 }
             }
             _ => panic!("Not a load instruction in a load handler"),
@@ -486,40 +661,159 @@ impl VirtContext {
                     );
                 }
 
-                match device
-                    .device_interface
-                    .write_device(offset, *len, value & mask, self)
-                {
+                let result = match device.device_interface.write_semantic(offset) {
+                    WriteSemantic::NonPosted => {
+                        if self.drain_deferred_effects() {
+                            // The firmware handles the rejected write first, this store is
+                            // executed again once the firmware returns.
+                            return;
+                        }
+                        device
+                            .device_interface
+                            .write_device(offset, *len, value & mask, self)
+                    }
+                    WriteSemantic::Posted => {
+                        let write = DeferredWrite {
+                            device: device.device_interface,
+                            name: device.name,
+                            offset,
+                            width: *len,
+                            value: value & mask,
+                            addr: address,
+                            pc: self.pc,
+                        };
+                        if self.defer_write(write) {
+                            return;
+                        }
+                        Ok(())
+                    }
+                };
+
+                match result {
                     Ok(()) => {
                         // Update the program counter (pc) based on compression
                         self.pc += if *is_compressed { 2 } else { 4 };
                     }
-                    Err(err) => panic!("Error writing {}: {}", device.name, err),
+                    Err(err) => {
+                        log::warn!("Error writing {}: {}", device.name, err);
+                        self.emulate_device_access_fault(MCause::StoreAccessFault, address);
+                    }
                 }
             }
             _ => panic!("Not a store instruction in a store handler"),
         }
     }
 
+    /// Routes an access fault from the firmware to the virtual device whose MMIO range contains the
+    /// faulting address.
+    ///
+    /// Returns false if no device matches the address, in which case the fault is not handled.
+    fn dispatch_device_access_fault(&mut self, mctx: &MiralisContext) -> bool {
+        let Some(device) = mctx.shared.devices.find(self.trap_info.mtval) else {
+            return false;
+        };
+
+        let raw = unsafe { Arch::get_raw_faulting_instr(&self.trap_info) };
+        if self.is_disabled_compressed_instr(raw) {
+            log::trace!("Compressed device access with misa.C disabled");
+            self.emulate_illegal_instr(raw);
+            return true;
+        }
+        let instr = mctx.decode(raw);
+        log::trace!(
+            "Accessed devices: {} | With instr: {:?}",
+            device.name,
+            instr
+        );
+        self.handle_device_access_fault(&instr, device);
+        true
+    }
+
     pub fn handle_device_access_fault(&mut self, instr: &Instr, device: &VirtDevice) {
+        coverage::record_device_access(device, self.trap_info.mtval - device.start_addr);
         match instr {
-            Instr::Load { .. } => self.handle_load(device, instr),
+            Instr::Load { .. } => {
+                // Loads must observe the effects of previous posted writes
+                if !self.drain_deferred_effects() {
+                    self.handle_load(device, instr)
+                }
+            }
             Instr::Store { .. } => self.handle_store(device, instr),
             _ => todo!("Instruction not yet implemented: {:?}", instr),
         }
     }
 
+    /// Queue a posted write, draining the queue first if it is full.
+    ///
+    /// Returns true if draining the queue injected an access fault, in which case the write is
+    /// not queued.
+    fn defer_write(&mut self, write: DeferredWrite) -> bool {
+        if self.deferred_effects.is_full() && self.drain_deferred_effects() {
+            return true;
+        }
+        self.deferred_effects
+            .push(write)
+            .unwrap_or_else(|_| panic!("Failed to defer write to {}", write.name));
+        false
+    }
+
+    /// Posts work to be done before re-entering the guest, see the `deferred` module.
+    pub fn defer(&mut self, work: Work) {
+        self.deferred_work.push(work);
+    }
+
+    /// Apply all pending posted writes, in order.
+    ///
+    /// A write rejected by its device is reported to the firmware as an access fault of the store
+    /// which posted it, the writes posted after it are discarded. Returns true if such an access
+    /// fault was injected, in which case the pc is already at the firmware trap handler.
+    pub fn drain_deferred_effects(&mut self) -> bool {
+        while let Some(write) = self.deferred_effects.pop() {
+            if let Err(err) =
+                write
+                    .device
+                    .write_device(write.offset, write.width, write.value, self)
+            {
+                log::warn!("Error writing {}: {}", write.name, err);
+                self.deferred_effects = DeferredEffects::new();
+                self.trap_info.mepc = write.pc;
+                self.emulate_device_access_fault(MCause::StoreAccessFault, write.addr);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Reports a virtual device access rejected by the device to the firmware as an access fault.
+    fn emulate_device_access_fault(&mut self, cause: MCause, addr: usize) {
+        self.trap_info.mcause = cause as usize;
+        self.trap_info.mtval = addr;
+        self.emulate_jump_trap_handler();
+    }
+
     /// Check if an interrupt should be injected in virtual M-mode.
     ///
     /// If an interrupt is injected, jumps to the firmware trap handler.
+    ///
+    /// This function is called on every exit, the common case (no interrupt pending) is handled
+    /// with a single comparison against the cached pending interrupts mask.
     pub fn check_and_inject_interrupts(&mut self) {
+        if self.pending_interrupts == 0 {
+            // No enabled interrupt pending
+            return;
+        }
+        if fault::inject(Fault::InterruptDelay) {
+            // The interrupt remains pending and is injected on a later exit
+            return;
+        }
         if self.csr.mstatus & mstatus::MIE_FILTER == 0 && self.mode == Mode::M {
             // Interrupts are disabled while in M-mode if mstatus.MIE is 0
             return;
         }
         let Some(next_int) = get_next_interrupt(self.csr.mie, self.csr.mip, self.csr.mideleg)
         else {
-            // No enabled interrupt pending
+            // The cached mask is stale, this should never happen
+            debug_assert!(false, "Stale pending interrupts mask");
             return;
         };
 
@@ -530,6 +824,17 @@ impl VirtContext {
             mstatus::MPP_FILTER,
             self.mode.to_bits(),
         );
+        if self.csr.misa & misa::H != 0 {
+            VirtCsr::set_csr_field(
+                &mut self.csr.mstatus,
+                mstatus::MPV_OFFSET,
+                mstatus::MPV_FILTER,
+                self.virtualized as usize,
+            );
+            self.csr.mtval2 = 0;
+            self.csr.mtinst = 0;
+        }
+        self.virtualized = false;
         let mpie = (self.csr.mstatus & mstatus::MIE_FILTER) >> mstatus::MIE_OFFSET;
         VirtCsr::set_csr_field(
             &mut self.csr.mstatus,
@@ -559,6 +864,13 @@ impl VirtContext {
         self.csr.mstatus = self.trap_info.mstatus;
         self.csr.mtval = self.trap_info.mtval;
         self.csr.mepc = self.trap_info.mepc;
+        // The guest physical address and the transformed instruction of the last trap, if any.
+        // mstatus.MPV and mstatus.GVA are already reported by the trap's mstatus.
+        if self.extensions.has_h_extension {
+            self.csr.mtval2 = Arch::read_csr(Csr::Mtval2);
+            self.csr.mtinst = Arch::read_csr(Csr::Mtinst);
+        }
+        self.virtualized = false;
         // Real mip.SEIE bit should not be different from virtual mip.SEIE as it is read-only in S-Mode or U-Mode.
         // But csrr is modified for SEIE and return the logical-OR of SEIE and the interrupt signal from interrupt
         // controller. (refer to documentation for further detail).
@@ -570,6 +882,7 @@ impl VirtContext {
         let hw_mip_bits = self.trap_info.mip & !(mie::SEIE_FILTER | mie::MIDELEG_READ_ONLY_ZERO);
         let sw_mip_bits = self.csr.mip & (mie::SEIE_FILTER | mie::MIDELEG_READ_ONLY_ZERO);
         self.csr.mip = hw_mip_bits | sw_mip_bits;
+        self.update_pending_interrupts();
 
         match self.mode {
             Mode::M => {
@@ -591,6 +904,110 @@ impl VirtContext {
         self.set_pc_to_mtvec();
     }
 
+    /// Updates the virtual mstatus.FS and mstatus.VS with the state reported by the last firmware
+    /// trap.
+    ///
+    /// The firmware operates on the FP and vector registers of the payload, which are never saved
+    /// nor restored by Miralis. The hardware marks the state as dirty when the firmware writes those
+    /// registers, the virtual mstatus is only updated lazily on the next firmware exit.
+    fn sync_fp_vector_state(&mut self) {
+        let filter = mstatus::FS_FILTER | mstatus::VS_FILTER;
+        let mstatus = self.csr.mstatus & !filter | self.trap_info.mstatus & filter;
+        self.csr.mstatus = with_dirty_summary(mstatus);
+    }
+
+    /// Emulates a CSR instruction.
+    fn emulate_csr_instr(&mut self, instr: &Instr, mctx: &mut MiralisContext) {
+        match instr {
+            Instr::Csrrw { csr, rd, rs1 } => {
+                let tmp = self.get(csr);
+                self.set_csr(csr, self.get(rs1), mctx);
+                self.set(rd, fault::csr_read(tmp));
+                self.pc += 4;
+            }
+            Instr::Csrrs { csr, rd, rs1 } => {
+                let tmp = self.get(csr);
+                self.set_csr(csr, tmp | self.get(rs1), mctx);
+                self.set(rd, fault::csr_read(tmp));
+                self.pc += 4;
+            }
+            Instr::Csrrwi { csr, rd, uimm } => {
+                self.set(rd, fault::csr_read(self.get(csr)));
+                self.set_csr(csr, *uimm, mctx);
+                self.pc += 4;
+            }
+            Instr::Csrrsi { csr, rd, uimm } => {
+                let tmp = self.get(csr);
+                self.set_csr(csr, tmp | uimm, mctx);
+                self.set(rd, fault::csr_read(tmp));
+                self.pc += 4;
+            }
+            Instr::Csrrc { csr, rd, rs1 } => {
+                let tmp = self.get(csr);
+                self.set_csr(csr, tmp & !self.get(rs1), mctx);
+                self.set(rd, fault::csr_read(tmp));
+                self.pc += 4;
+            }
+            Instr::Csrrci { csr, rd, uimm } => {
+                let tmp = self.get(csr);
+                self.set_csr(csr, tmp & !uimm, mctx);
+                self.set(rd, fault::csr_read(tmp));
+                self.pc += 4;
+            }
+            _ => unreachable!("Not a CSR instruction: {:?}", instr),
+        }
+    }
+
+    /// Emulates a CSR instruction accessing the PMP registers while the firmware is exposed more
+    /// PMP entries than physically available.
+    ///
+    /// The virtual regions must then be coalesced to fit in the physical entries. If they do not
+    /// fit the instruction has no effect and the firmware receives a store access fault, with
+    /// mtval holding the start address of the first region that could not be mapped.
+    fn emulate_coalesced_pmp_instr(&mut self, instr: &Instr, mctx: &mut MiralisContext) {
+        let regs = self.regs;
+        let pmpaddr = self.csr.pmpaddr;
+        let pmpcfg = self.csr.pmpcfg;
+        self.emulate_csr_instr(instr, mctx);
+
+        if let Err(overflow) = pmp::coalesce_virtual_pmp(
+            &self.csr.pmpaddr,
+            &self.csr.pmpcfg,
+            self.nb_pmp,
+            mctx.pmp.virt_pmp_slots,
+        ) {
+            log::warn!(
+                "Virtual PMP regions do not fit in {} physical PMPs, rejecting region at 0x{:x}",
+                mctx.pmp.virt_pmp_slots,
+                overflow.addr
+            );
+            self.regs = regs;
+            self.csr.pmpaddr = pmpaddr;
+            self.csr.pmpcfg = pmpcfg;
+            self.trap_info.mcause = MCause::StoreAccessFault as usize;
+            self.trap_info.mtval = overflow.addr;
+            self.emulate_jump_trap_handler();
+        }
+    }
+
+    /// Emulates an illegal instruction exception in the firmware.
+    fn emulate_illegal_instr(&mut self, raw: usize) {
+        self.trap_info.mcause = MCause::IllegalInstr as usize;
+        self.trap_info.mtval = raw;
+        self.emulate_jump_trap_handler();
+    }
+
+    /// Returns true if the instruction is compressed while the firmware disabled the C extension.
+    ///
+    /// The hardware still executes compressed instructions, they are only rejected when emulated.
+    /// If Miralis hides the C extension altogether the firmware did not disable it, and compressed
+    /// instructions remain accepted.
+    fn is_disabled_compressed_instr(&self, raw: usize) -> bool {
+        let is_compressed = raw & 0b11 != 0b11;
+        let can_toggle_c = Arch::read_csr(Csr::Misa) & !misa::DISABLED & misa::C != 0;
+        is_compressed && can_toggle_c && self.csr.misa & misa::C == 0
+    }
+
     /// Set the program counter (PC) to `mtvec`, amulating a jump to the trap handler.
     ///
     /// This function checks the `mcause` CSR to select the right entry point if `mtvec` is in
@@ -615,33 +1032,34 @@ impl VirtContext {
 
     /// Handles a machine timer interrupt
     ///
-    /// TODO: for now we assume that all M-mode timer interrupts are issued from the
-    /// firmware (in-band interrupts), so we just set the bit in `vmip`.
-    /// In the future we might want to support timer interrupts for Miralis' own purpose
-    /// (out-of-band interrupts). Once we add such support we should disambiguate
-    /// interrupts here.
-    fn handle_machine_timer_interrupt(&mut self, mctx: &mut MiralisContext) {
-        let mut clint = Plat::get_clint().lock();
-        clint
-            .write_mtimecmp(mctx.hw.hart, usize::MAX)
-            .expect("Failed to write mtimecmp");
-        drop(clint); // Release the lock early
-
-        self.csr.mip |= mie::MTIE_FILTER;
+    /// The physical timer is multiplexed between Miralis, the firmware and the payload: we
+    /// dispatch the interrupt to the sources whose deadline expired.
+    fn handle_machine_timer_interrupt(&mut self, mctx: &mut MiralisContext, policy: &mut Policy) {
+        let expired = Plat::get_vclint().handle_timer_interrupt(mctx.hw.hart);
+
+        if expired & TimerEvent::Firmware.mask() != 0 {
+            self.csr.mip |= mie::MTIE_FILTER;
+            self.update_pending_interrupts();
+        }
+        if expired & TimerEvent::Payload.mask() != 0 {
+            // Same as a firmware implementing the SBI timer extension: signal the payload through
+            // the supervisor timer interrupt.
+            unsafe { Arch::set_csr_bits(Csr::Mip, mie::STIE_FILTER) };
+        }
+        if expired & TimerEvent::Policy.mask() != 0 {
+            Benchmark::in_scope(Scope::PolicyHook, || policy.on_timer(self, mctx));
+        }
+        if expired & TimerEvent::Miralis.mask() != 0 {
+            log::trace!("Miralis timer deadline reached");
+        }
     }
 
     /// Handles a machine software interrupt trap
-    fn handle_machine_software_interrupt(
-        &mut self,
-        mctx: &mut MiralisContext,
-        policy: &mut Policy,
-    ) {
+    fn handle_machine_software_interrupt(&mut self, mctx: &mut MiralisContext) {
         // Clear the interrupt
-        let mut clint = Plat::get_clint().lock();
-        clint
-            .write_msip(mctx.hw.hart, 0)
+        Plat::get_msip()
+            .write(mctx.hw.hart, 0)
             .expect("Failed to write msip");
-        drop(clint); // Release the lock early
 
         // Check if a virtual MSI is pending
         let vclint = Plat::get_vclint();
@@ -651,28 +1069,57 @@ impl VirtContext {
             self.csr.mip &= !mie::MSIE_FILTER;
         }
 
-        // Check if a policy MSI is pending
+        // Writes to the mtimecmp of a remote hart notify the hart with an MSI, as the vMTIP might
+        // need to be cleared
+        if vclint.firmware_timer_pending(self.hart_id) {
+            self.csr.mip |= mie::MTIE_FILTER;
+        } else {
+            self.csr.mip &= !mie::MTIE_FILTER;
+        }
+
+        // Virtual devices notify the hart with an MSI when raising a PLIC interrupt line
+        if Plat::get_vplic().has_pending_m_interrupt(self.hart_id) {
+            self.csr.mip |= mie::MEIE_FILTER;
+        } else {
+            self.csr.mip &= !mie::MEIE_FILTER;
+        }
+        self.update_pending_interrupts();
+
+        // Check if a policy MSI is pending, the policy handles it before re-entering the guest
         if vclint.get_policy_msi(self.hart_id) {
             vclint.clear_policy_msi(self.hart_id);
-            policy.on_interrupt(self, mctx);
+            self.defer(Work::PolicyInterrupt);
         }
     }
 
     /// Handle the trap coming from the firmware
     pub fn handle_firmware_trap(&mut self, mctx: &mut MiralisContext, policy: &mut Policy) {
-        if policy.trap_from_firmware(mctx, self).overwrites() {
+        // Any exit ends the polling loop, if one was detected.
+        self.end_counter_passthrough(mctx);
+        self.sync_fp_vector_state();
+
+        if Benchmark::in_scope(Scope::PolicyHook, || policy.trap_from_firmware(mctx, self))
+            .overwrites()
+        {
             log::trace!("Catching trap in the policy module");
+            self.audit_policy_decision();
             return;
         }
 
         let cause = self.trap_info.get_cause();
         match cause {
-            MCause::EcallFromUMode if policy.ecall_from_firmware(mctx, self).overwrites() => {
+            MCause::EcallFromUMode
+                if Benchmark::in_scope(Scope::PolicyHook, || {
+                    policy.ecall_from_firmware(mctx, self)
+                })
+                .overwrites() =>
+            {
                 // Nothing to do, the policy module handles those ecalls
                 log::trace!("Catching E-call from firmware in the policy module");
+                self.audit_policy_decision();
             }
-            MCause::EcallFromUMode if self.get(Register::X17) == abi::MIRALIS_EID => {
-                self.handle_ecall()
+            MCause::EcallFromUMode if self.sbi_extension() == Some(SbiExtension::Miralis) => {
+                self.handle_ecall(mctx, policy)
             }
             MCause::EcallFromUMode => {
                 todo!("ecall is not yet supported for EID other than Miralis ABI");
@@ -682,26 +1129,22 @@ impl VirtContext {
             }
             MCause::IllegalInstr => {
                 let instr = unsafe { Arch::get_raw_faulting_instr(&self.trap_info) };
-                let instr = mctx.decode(instr);
+                coverage::record_instr(instr);
+                let instr = mctx.decode_cached(self.trap_info.mepc, instr);
                 log::trace!("Faulting instruction: {:?}", instr);
+                self.track_counter_polling(&instr, mctx);
+                if instr == Instr::Wfi && self.pending_suspend.is_none() {
+                    Benchmark::in_scope(Scope::PolicyHook, || policy.on_idle(self, mctx));
+                }
                 self.emulate_privileged_instr(&instr, mctx);
             }
             MCause::Breakpoint => {
-                self.emulate_jump_trap_handler();
+                self.handle_firmware_breakpoint();
             }
             MCause::StoreAccessFault | MCause::LoadAccessFault => {
                 // PMP faults
-                if let Some(device) =
-                    device::find_matching_device(self.trap_info.mtval, &mctx.devices)
-                {
-                    let instr = unsafe { Arch::get_raw_faulting_instr(&self.trap_info) };
-                    let instr = mctx.decode(instr);
-                    log::trace!(
-                        "Accessed devices: {} | With instr: {:?}",
-                        device.name,
-                        instr
-                    );
-                    self.handle_device_access_fault(&instr, device);
+                if self.dispatch_device_access_fault(mctx) {
+                    // The access has been emulated by a virtual device
                 } else if (self.csr.mstatus & mstatus::MPRV_FILTER) >> mstatus::MPRV_OFFSET == 1 {
                     // TODO: make sure virtual address does not get around PMP protection
                     let instr = unsafe { Arch::get_raw_faulting_instr(&self.trap_info) };
@@ -727,15 +1170,20 @@ impl VirtContext {
                 self.emulate_jump_trap_handler();
             }
             MCause::MachineTimerInt => {
-                self.handle_machine_timer_interrupt(mctx);
+                self.handle_machine_timer_interrupt(mctx, policy);
             }
             MCause::MachineSoftInt => {
                 log::info!("Machine soft int");
-                self.handle_machine_software_interrupt(mctx, policy);
+                self.handle_machine_software_interrupt(mctx);
             }
             MCause::MachineExternalInt => {
                 todo!("Virtualize machine external interrupt")
             }
+            MCause::LoadAddrMisaligned | MCause::StoreAddrMisaligned
+                if self.emulate_misaligned_access(mctx, policy) =>
+            {
+                log::trace!("Emulated misaligned access from firmware");
+            }
             MCause::LoadAddrMisaligned
             | MCause::StoreAddrMisaligned
             | MCause::InstrAddrMisaligned => self.emulate_jump_trap_handler(),
@@ -759,38 +1207,288 @@ impl VirtContext {
         }
     }
 
+    /// Handles a breakpoint from the firmware.
+    ///
+    /// Virtual triggers are never installed in hardware, breakpoints from the firmware are
+    /// therefore always raised by an `ebreak` (or `c.ebreak`). With the debug stub enabled the
+    /// breakpoint is reported to the attached debugger and the firmware then resumes after the
+    /// instruction, otherwise the exception is injected into the firmware. Semihosting calls are
+    /// served by Miralis when enabled.
+    fn handle_firmware_breakpoint(&mut self) {
+        if DEBUG_SEMIHOSTING && semihosting::handle_firmware_call(self) {
+            return;
+        }
+
+        if DEBUG_STUB {
+            let instr = unsafe { Arch::get_raw_faulting_instr(&self.trap_info) };
+            log::debug!("Firmware breakpoint at 0x{:x}", self.trap_info.mepc);
+            debug::miralis_firmware_breakpoint(self);
+            self.pc += if instr & 0b11 == 0b11 { 4 } else { 2 };
+            return;
+        }
+
+        // The hardware reports either zero or the address of the instruction, the virtual
+        // firmware always sees the address.
+        self.trap_info.mtval = self.trap_info.mepc;
+        self.emulate_jump_trap_handler();
+    }
+
     /// Handle the trap coming from the payload
     pub fn handle_payload_trap(&mut self, mctx: &mut MiralisContext, policy: &mut Policy) {
         // Update the current mode
         self.mode = parse_mpp_return_mode(self.trap_info.mstatus);
+        self.virtualized = self.trap_info.mstatus & mstatus::MPV_FILTER != 0;
 
-        if policy.trap_from_payload(mctx, self).overwrites() {
+        if Benchmark::in_scope(Scope::PolicyHook, || policy.trap_from_payload(mctx, self))
+            .overwrites()
+        {
             log::trace!("Catching trap in the policy module");
+            self.audit_policy_decision();
             return;
         }
 
         // Handle the exit.
         // We only care about ecalls and virtualized interrupts.
+        if self.is_payload_sbi_call(mctx) {
+            self.handle_payload_sbi_call(mctx, policy);
+            return;
+        }
         match self.trap_info.get_cause() {
-            MCause::EcallFromSMode if policy.ecall_from_payload(mctx, self).overwrites() => {
-                // Nothing to do, the Policy module handles those ecalls
-                log::trace!("Catching E-call from payload in the policy module");
+            MCause::IllegalInstr if self.emulate_payload_counter_read(mctx) => {
+                log::trace!("Emulated counter read from payload");
+            }
+            MCause::IllegalInstr if self.emulate_payload_stimecmp(mctx) => {
+                log::trace!("Emulated stimecmp access from payload");
+            }
+            MCause::LoadAccessFault if self.emulate_payload_device_load(mctx) => {
+                log::trace!("Emulated device load from payload");
             }
-            MCause::EcallFromSMode if self.get(Register::X17) == abi::MIRALIS_EID => {
-                self.handle_ecall()
+            MCause::LoadAddrMisaligned | MCause::StoreAddrMisaligned
+                if self.emulate_misaligned_access(mctx, policy) =>
+            {
+                log::trace!("Emulated misaligned access from payload");
             }
             MCause::MachineTimerInt => {
-                self.handle_machine_timer_interrupt(mctx);
+                self.handle_machine_timer_interrupt(mctx, policy);
+            }
+            MCause::MachineSoftInt => {
+                self.handle_machine_software_interrupt(mctx);
+            }
+            // Delegated exceptions that Miralis did not need to emulate, see
+            // `delegated_exceptions`.
+            cause if exception::is_delegated_to_payload(self, cause) => {
+                exception::inject_payload_exception(self, cause, self.trap_info.mtval);
+            }
+            _ => self.emulate_jump_trap_handler(),
+        }
+    }
+
+    /// Emulates a trapping misaligned load or store, if enabled and allowed by the policy.
+    ///
+    /// Returns true if the access has been emulated.
+    fn emulate_misaligned_access(&mut self, mctx: &MiralisContext, policy: &mut Policy) -> bool {
+        VCPU_EMULATE_MISALIGNED
+            && policy.allow_misaligned_emulation(self)
+            && misaligned::emulate(self, mctx)
+    }
+
+    /// Returns true if the current payload trap is an SBI call.
+    ///
+    /// On cores without S-mode the payload runs in U-mode, in which case its ecalls are SBI calls
+    /// as there is no supervisor to handle them.
+    fn is_payload_sbi_call(&self, mctx: &MiralisContext) -> bool {
+        match self.trap_info.get_cause() {
+            MCause::EcallFromSMode => true,
+            MCause::EcallFromUMode => !mctx.hw.extensions.has_s_extension,
+            _ => false,
+        }
+    }
+
+    /// Handles an SBI call from the payload.
+    fn handle_payload_sbi_call(&mut self, mctx: &mut MiralisContext, policy: &mut Policy) {
+        if sbi::filter_payload_call(self, policy) {
+            log::trace!("Denied E-call to a hidden SBI extension");
+            self.audit_policy_decision();
+        } else if Benchmark::in_scope(Scope::PolicyHook, || policy.ecall_from_payload(mctx, self))
+            .overwrites()
+        {
+            // Nothing to do, the Policy module handles those ecalls
+            log::trace!("Catching E-call from payload in the policy module");
+            self.audit_policy_decision();
+        } else if self.sbi_extension() == Some(SbiExtension::Miralis) {
+            self.handle_ecall(mctx, policy)
+        } else if vendor::handle_payload_call(self) {
+            log::trace!("Handled vendor E-call from payload");
+        } else if suspend::handle_payload_call(self, policy) {
+            self.emulate_jump_trap_handler();
+        }
+    }
+
+    /// Emulates a read of the `time`, `cycle`, or `instret` CSR from the payload, which traps when
+    /// the payload counters are offset.
+    ///
+    /// Returns false if the faulting instruction is not a read of an offset counter allowed by the
+    /// firmware.
+    fn emulate_payload_counter_read(&mut self, mctx: &mut MiralisContext) -> bool {
+        let time_offset = TIMEBASE.offset(ExecutionMode::Payload) != 0;
+        let counters_offset = self.counters.is_offset(ExecutionMode::Payload);
+        if !time_offset && !counters_offset {
+            return false;
+        }
+
+        let instr = unsafe { Arch::get_raw_faulting_instr(&self.trap_info) };
+        let (rd, value) = match mctx.decode(instr) {
+            Instr::Csrrs {
+                csr: Csr::Time,
+                rd,
+                rs1: Register::X0,
+            } if time_offset => (rd, TIMEBASE.read(ExecutionMode::Payload)),
+            Instr::Csrrs {
+                csr: Csr::Cycle,
+                rd,
+                rs1: Register::X0,
+            } if counters_offset && self.csr.mcounteren & mcounteren::CY_FILTER != 0 => (
+                rd,
+                self.counters.read(ExecutionMode::Payload, Counter::Cycle),
+            ),
+            Instr::Csrrs {
+                csr: Csr::Instret,
+                rd,
+                rs1: Register::X0,
+            } if counters_offset && self.csr.mcounteren & mcounteren::IR_FILTER != 0 => (
+                rd,
+                self.counters.read(ExecutionMode::Payload, Counter::Instret),
+            ),
+            _ => return false,
+        };
+        self.set(rd, value);
+        self.pc += 4;
+        true
+    }
+
+    /// Emulates an access to `stimecmp` from the payload, which traps when the firmware enabled
+    /// Sstc but `stimecmp` can not be passed through.
+    ///
+    /// The payload deadline is multiplexed on the physical timer and signaled through mip.STIP,
+    /// as the hardware would. Returns false if the faulting instruction does not access
+    /// `stimecmp`.
+    fn emulate_payload_stimecmp(&mut self, mctx: &mut MiralisContext) -> bool {
+        if self.csr.menvcfg & menvcfg::STCE_FILTER == 0 || self.is_stimecmp_passthrough() {
+            return false;
+        }
+
+        let instr = unsafe { Arch::get_raw_faulting_instr(&self.trap_info) };
+        let (rd, value) = match mctx.decode(instr) {
+            Instr::Csrrw {
+                csr: Csr::Stimecmp,
+                rd,
+                rs1,
+            } => (rd, self.get(rs1)),
+            Instr::Csrrs {
+                csr: Csr::Stimecmp,
+                rd,
+                rs1: Register::X0,
+            } => (rd, self.csr.stimecmp),
+            _ => return false,
+        };
+
+        let previous = self.csr.stimecmp;
+        self.csr.stimecmp = value;
+        if self.arm_payload_stimecmp(mctx.hw.hart) {
+            unsafe { Arch::set_csr_bits(Csr::Mip, mie::STIE_FILTER) };
+        } else {
+            unsafe { Arch::clear_csr_bits(Csr::Mip, mie::STIE_FILTER) };
+        }
+        self.set(rd, previous);
+        self.pc += 4;
+        true
+    }
+
+    /// Returns true if the payload accesses the native `stimecmp`.
+    ///
+    /// The native `stimecmp` is compared against the physical time, it can only be passed through
+    /// if the firmware enabled Sstc and the payload time is not offset.
+    fn is_stimecmp_passthrough(&self) -> bool {
+        self.csr.menvcfg & menvcfg::STCE_FILTER != 0 && TIMEBASE.offset(ExecutionMode::Payload) == 0
+    }
+
+    /// Registers the virtual `stimecmp` as the payload timer deadline.
+    ///
+    /// Returns true if the deadline is already reached, in which case the supervisor timer
+    /// interrupt is pending.
+    fn arm_payload_stimecmp(&self, hart: usize) -> bool {
+        let deadline = TIMEBASE.to_physical(ExecutionMode::Payload, self.csr.stimecmp);
+        Plat::get_vclint()
+            .set_deadline(hart, TimerEvent::Payload, deadline)
+            .expect("Failed to set the payload timer deadline");
+        TIMEBASE.read(ExecutionMode::Payload) >= self.csr.stimecmp
+    }
+
+    /// Emulates a load from the payload to a virtual device readable by the payload.
+    ///
+    /// The device is matched against the faulting address, which is only the physical address if
+    /// the payload maps the device at its physical address. Returns false if the load does not
+    /// target such a device or fails, in which case the fault is forwarded to the firmware.
+    fn emulate_payload_device_load(&mut self, mctx: &mut MiralisContext) -> bool {
+        let Some(device) = mctx.shared.devices.find(self.trap_info.mtval) else {
+            return false;
+        };
+        if device.payload_access != PayloadAccess::ReadOnly {
+            return false;
+        }
+
+        // The instruction is fetched through the address translation of the payload
+        let mut raw = [0u8; 4];
+        let fetched = unsafe {
+            Arch::read_bytes_from_mode(self.trap_info.mepc as *const u8, &mut raw, self.mode)
+        };
+        if fetched.is_err() {
+            return false;
+        }
+        let Instr::Load {
+            rd,
+            rs1,
+            imm,
+            len,
+            is_compressed,
+            is_unsigned,
+        } = mctx.decode(u32::from_le_bytes(raw) as usize)
+        else {
+            return false;
+        };
+
+        let offset = utils::calculate_addr(self.get(rs1), imm) - device.start_addr;
+        match device.device_interface.read_device(offset, len, self) {
+            Ok(value) => {
+                self.set(rd, extend_load(value, len, is_unsigned));
+                self.pc += if is_compressed { 2 } else { 4 };
+                true
             }
-            MCause::MachineSoftInt => {
-                self.handle_machine_software_interrupt(mctx, policy);
+            Err(err) => {
+                log::debug!("Invalid payload access to {}: {}", device.name, err);
+                false
             }
-            _ => self.emulate_jump_trap_handler(),
         }
     }
 
+    /// Records in the audit log that the policy took over the handling of the current trap.
+    fn audit_policy_decision(&self) {
+        audit::record(
+            self.hart_id,
+            AuditEvent::PolicyDecision {
+                mcause: self.trap_info.mcause,
+                eid: self.get(Register::X17),
+            },
+        );
+    }
+
+    /// Returns the SBI extension targeted by the current ecall, if known.
+    fn sbi_extension(&self) -> Option<SbiExtension> {
+        sbi::route(self.get(Register::X17))
+    }
+
     /// Ecalls may come from firmware or payload, resulting in different handling.
-    fn handle_ecall(&mut self) {
+    fn handle_ecall(&mut self, mctx: &MiralisContext, policy: &Policy) {
         let fid = self.get(Register::X16);
         match fid {
             abi::MIRALIS_FAILURE_FID => {
@@ -798,12 +1496,14 @@ impl VirtContext {
                 log::error!("  pc:    0x{:x}", self.pc);
                 log::error!("  exits: {}", self.nb_exits);
                 unsafe { debug::log_stack_usage() };
+                coverage::dump();
                 Plat::exit_failure();
             }
             abi::MIRALIS_SUCCESS_FID => {
                 log::info!("Success!");
                 log::info!("Number of exits: {}", self.nb_exits);
                 unsafe { debug::log_stack_usage() };
+                coverage::dump();
                 Plat::exit_success();
             }
             abi::MIRALIS_LOG_FID => {
@@ -836,13 +1536,54 @@ impl VirtContext {
                 Benchmark::record_counters();
                 Plat::exit_success();
             }
+            abi::MIRALIS_AUDIT_LOG_READ_FID => audit::handle_read(self),
+            abi::MIRALIS_CAPABILITIES_FID => capabilities::handle_query(self, mctx),
+            abi::MIRALIS_MEMORY_LAYOUT_FID => memory_layout::handle_query(self, policy),
+            abi::MIRALIS_PANIC_FID => panic_report::handle_report(self),
+            abi::MIRALIS_LOG_FILTER_FID => logger::handle_set_filters(self),
+            abi::MIRALIS_BUILD_INFO_FID => build_info::handle_query(self),
+            abi::MIRALIS_PMP_VIEW_FID => pmp_view::handle_query(self, &mctx.pmp),
+            abi::MIRALIS_TRAP_LOG_DUMP_FID => debug::handle_trap_log_dump(self),
             _ => panic!("Invalid Miralis FID: 0x{:x}", fid),
         }
     }
 
+    /// Returns the payload exceptions delivered directly to S-mode, as a medeleg mask.
+    ///
+    /// The exceptions delegated by the firmware are delegated in hardware too, such that they
+    /// reach the payload without exiting to Miralis, except for the causes Miralis might need to
+    /// emulate. Those keep trapping to Miralis, which forwards them to the firmware if no emulation
+    /// is needed. Policies can further restrict the delegation from their
+    /// `switch_from_firmware_to_payload` hook.
+    fn delegated_exceptions(&self, mctx: &MiralisContext) -> usize {
+        let mut emulated = 0;
+        if TIMEBASE.offset(ExecutionMode::Payload) != 0
+            || self.counters.is_offset(ExecutionMode::Payload)
+            || (self.csr.menvcfg & menvcfg::STCE_FILTER != 0 && !self.is_stimecmp_passthrough())
+        {
+            emulated |= 1 << MCause::IllegalInstr as usize;
+        }
+        if mctx
+            .shared
+            .devices
+            .iter()
+            .any(|device| device.payload_access == PayloadAccess::ReadOnly)
+        {
+            emulated |= 1 << MCause::LoadAccessFault as usize;
+        }
+        if VCPU_EMULATE_MISALIGNED {
+            emulated |= 1 << MCause::LoadAddrMisaligned as usize;
+            emulated |= 1 << MCause::StoreAddrMisaligned as usize;
+        }
+        self.csr.medeleg & !emulated
+    }
+
     /// Loads the S-mode CSR registers into the physical registers configures M-mode registers for
     /// payload execution.
     pub unsafe fn switch_from_firmware_to_payload(&mut self, mctx: &mut MiralisContext) {
+        self.counters
+            .switch(ExecutionMode::Firmware, ExecutionMode::Payload);
+
         let mut mstatus = self.csr.mstatus; // We need to set the next mode bits before mret
         VirtCsr::set_csr_field(
             &mut mstatus,
@@ -850,19 +1591,59 @@ impl VirtContext {
             mstatus::MPP_FILTER,
             self.mode.to_bits(),
         );
+        // Enter VS or VU-mode if the firmware returned to a virtualized payload
+        if mctx.hw.extensions.has_h_extension {
+            VirtCsr::set_csr_field(
+                &mut mstatus,
+                mstatus::MPV_OFFSET,
+                mstatus::MPV_FILTER,
+                self.virtualized as usize,
+            );
+        }
 
         if mctx.hw.available_reg.senvcfg {
             Arch::write_csr(Csr::Senvcfg, self.csr.senvcfg);
         }
 
         if mctx.hw.available_reg.menvcfg {
-            Arch::write_csr(Csr::Menvcfg, self.csr.menvcfg);
+            // Sstc is emulated if `stimecmp` can not be passed through, see
+            // `emulate_payload_stimecmp`.
+            let menvcfg = if self.is_stimecmp_passthrough() {
+                self.csr.menvcfg
+            } else {
+                self.csr.menvcfg & !menvcfg::STCE_FILTER
+            };
+            Arch::write_csr(Csr::Menvcfg, menvcfg);
+        }
+
+        if self.is_stimecmp_passthrough() {
+            Arch::write_csr(Csr::Stimecmp, self.csr.stimecmp);
+        } else if self.csr.menvcfg & menvcfg::STCE_FILTER != 0 {
+            let pending = self.arm_payload_stimecmp(mctx.hw.hart);
+            VirtCsr::set_csr_field(
+                &mut self.csr.mip,
+                mie::STIE_OFFSET,
+                mie::STIE_FILTER,
+                pending as usize,
+            );
         }
 
+        // A suspend request is completed once the firmware returns to the payload
+        self.pending_suspend = None;
+
         Arch::write_csr(Csr::Mstatus, mstatus & !mstatus::MIE_FILTER);
         Arch::write_csr(Csr::Mideleg, self.csr.mideleg);
-        Arch::write_csr(Csr::Medeleg, self.csr.medeleg);
-        Arch::write_csr(Csr::Mcounteren, self.csr.mcounteren);
+        Arch::write_csr(Csr::Medeleg, self.delegated_exceptions(mctx));
+        // The native counters do not account for the payload offsets, reads are trapped and
+        // emulated instead.
+        let mut emulated_counters = 0;
+        if TIMEBASE.offset(ExecutionMode::Payload) != 0 {
+            emulated_counters |= mcounteren::TM_FILTER;
+        }
+        if self.counters.is_offset(ExecutionMode::Payload) {
+            emulated_counters |= mcounteren::CY_FILTER | mcounteren::IR_FILTER;
+        }
+        Arch::write_csr(Csr::Mcounteren, self.csr.mcounteren & !emulated_counters);
 
         // NOTE: `mip` mut be set _after_ `menvcfg`, because `menvcfg` might change which bits in
         // `mip` are writeable. For more information see the Sstc extension specification.
@@ -880,8 +1661,8 @@ impl VirtContext {
             Arch::write_csr(Csr::Stval, self.csr.stval);
         }
 
-        // If H extension is present - save the registers
-        if mctx.hw.extensions.has_h_extension {
+        // If H extension is present - restore the registers, unless they have not been saved
+        if mctx.hw.extensions.has_h_extension && !core::mem::take(&mut self.lazy_h_csrs) {
             Arch::write_csr(Csr::Hstatus, self.csr.hstatus);
             Arch::write_csr(Csr::Hedeleg, self.csr.hedeleg);
             Arch::write_csr(Csr::Hideleg, self.csr.hideleg);
@@ -892,6 +1673,7 @@ impl VirtContext {
             Arch::write_csr(Csr::Hgeie, self.csr.hgeie);
             Arch::write_csr(Csr::Henvcfg, self.csr.henvcfg);
             Arch::write_csr(Csr::Hcounteren, self.csr.hcounteren);
+            Arch::write_csr(Csr::Htimedelta, self.csr.htimedelta);
             Arch::write_csr(Csr::Htval, self.csr.htval);
             Arch::write_csr(Csr::Htinst, self.csr.htinst);
             Arch::write_csr(Csr::Hgatp, self.csr.hgatp);
@@ -907,13 +1689,13 @@ impl VirtContext {
             Arch::write_csr(Csr::Vsatp, self.csr.vsatp);
         }
 
-        // Load virtual PMP registers into Miralis's own registers
-        mctx.pmp.load_with_offset(
-            &self.csr.pmpaddr,
-            &self.csr.pmpcfg,
-            mctx.pmp.virt_pmp_offset,
-            self.nb_pmp,
-        );
+        // Load virtual PMP registers into Miralis's own registers, PMP writes are rejected by
+        // `emulate_coalesced_pmp_instr` if the configuration does not fit.
+        mctx.pmp
+            .load_virtual_pmp(&self.csr.pmpaddr, &self.csr.pmpcfg, self.nb_pmp)
+            .expect("Virtual PMP configuration does not fit in the physical PMPs");
+        // The payload accesses the shared devices directly
+        mctx.pmp.protect_shared_devices(&mctx.shared.devices, false);
         // Deny all addresses by default if at least one PMP is implemented
         if self.nb_pmp > 0 {
             let last_pmp_idx = mctx.pmp.nb_pmp as usize - 1;
@@ -925,6 +1707,9 @@ impl VirtContext {
     /// Loads the S-mode CSR registers into the virtual context and install sensible values (mostly
     /// 0) for running the virtual firmware in U-mode.
     pub unsafe fn switch_from_payload_to_firmware(&mut self, mctx: &mut MiralisContext) {
+        self.counters
+            .switch(ExecutionMode::Payload, ExecutionMode::Firmware);
+
         // Now save M-mode registers which are (partially) exposed as S-mode registers.
         // For mstatus we read the current value and clear the two MPP bits to jump into U-mode
         // (virtual firmware) during the next mret.
@@ -946,6 +1731,7 @@ impl VirtContext {
             Arch::read_csr(Csr::Mip) & !(mie::SEIE_FILTER | mie::MIDELEG_READ_ONLY_ZERO);
         let mip_sw_bits = self.csr.mip & (mie::SEIE_FILTER | mie::MIDELEG_READ_ONLY_ZERO);
         self.csr.mip = mip_hw_bits | mip_sw_bits;
+        self.update_pending_interrupts();
 
         let delegate_perf_counter_mask: usize = if DELEGATE_PERF_COUNTER { 1 } else { 0 };
 
@@ -956,7 +1742,18 @@ impl VirtContext {
         }
 
         if mctx.hw.available_reg.menvcfg {
-            self.csr.menvcfg = Arch::write_csr(Csr::Menvcfg, 0);
+            // STCE is cleared while Sstc is emulated, the virtual value is preserved
+            let physical_menvcfg = Arch::write_csr(Csr::Menvcfg, 0);
+            if physical_menvcfg & menvcfg::STCE_FILTER != 0 {
+                self.csr.stimecmp = Arch::read_csr(Csr::Stimecmp);
+            } else if self.csr.menvcfg & menvcfg::STCE_FILTER != 0 {
+                // The emulated payload timer does not fire while the firmware runs
+                Plat::get_vclint()
+                    .set_deadline(mctx.hw.hart, TimerEvent::Payload, usize::MAX)
+                    .expect("Failed to clear the payload timer deadline");
+            }
+            self.csr.menvcfg =
+                physical_menvcfg & !menvcfg::STCE_FILTER | self.csr.menvcfg & menvcfg::STCE_FILTER;
         }
 
         // If S extension is present - save the registers
@@ -972,39 +1769,103 @@ impl VirtContext {
             self.csr.stval = Arch::write_csr(Csr::Stval, 0);
         }
 
-        // If H extension is present - save the registers
+        // The hypervisor CSRs are rarely used by the firmware while handling a payload SBI call,
+        // they are only saved when first accessed.
         if mctx.hw.extensions.has_h_extension {
-            self.csr.hstatus = Arch::read_csr(Csr::Hstatus);
-            self.csr.hedeleg = Arch::read_csr(Csr::Hedeleg);
-            self.csr.hideleg = Arch::read_csr(Csr::Hideleg);
-            self.csr.hvip = Arch::read_csr(Csr::Hvip);
-            self.csr.hip = Arch::read_csr(Csr::Hip);
-            self.csr.hie = Arch::read_csr(Csr::Hie);
-            self.csr.hgeip = Arch::read_csr(Csr::Hgeip); // Read only register, this write will have no effect
-            self.csr.hgeie = Arch::read_csr(Csr::Hgeie);
-            self.csr.henvcfg = Arch::read_csr(Csr::Henvcfg);
-            self.csr.hcounteren = Arch::read_csr(Csr::Hcounteren);
-            self.csr.htval = Arch::read_csr(Csr::Htval);
-            self.csr.htinst = Arch::read_csr(Csr::Htinst);
-            self.csr.hgatp = Arch::read_csr(Csr::Hgatp);
-
-            self.csr.vsstatus = Arch::read_csr(Csr::Vsstatus);
-            self.csr.vsie = Arch::read_csr(Csr::Vsie);
-            self.csr.vstvec = Arch::read_csr(Csr::Vstvec);
-            self.csr.vsscratch = Arch::read_csr(Csr::Vsscratch);
-            self.csr.vsepc = Arch::read_csr(Csr::Vsepc);
-            self.csr.vscause = Arch::read_csr(Csr::Vscause);
-            self.csr.vstval = Arch::read_csr(Csr::Vstval);
-            self.csr.vsip = Arch::read_csr(Csr::Vsip);
-            self.csr.vsatp = Arch::read_csr(Csr::Vsatp);
+            if self.trap_info.get_cause() == MCause::EcallFromSMode {
+                self.lazy_h_csrs = true;
+            } else {
+                self.save_h_csrs();
+            }
         }
 
         // Remove Firmware PMP from the hardware
-        mctx.pmp.clear_range(mctx.pmp.virt_pmp_offset, self.nb_pmp);
+        mctx.pmp
+            .clear_range(mctx.pmp.virt_pmp_offset, mctx.pmp.virt_pmp_slots);
+        // Accesses to the shared devices are emulated for the firmware
+        mctx.pmp.protect_shared_devices(&mctx.shared.devices, true);
         // Allow all addresses by default
         let last_pmp_idx = mctx.pmp.nb_pmp as usize - 1;
         mctx.pmp.set_napot(last_pmp_idx, 0, usize::MAX, pmpcfg::RWX);
     }
+
+    /// Saves the hypervisor and virtual supervisor CSRs of the payload into the virtual context.
+    ///
+    /// The firmware can not access those registers, hence the payload values stay in the physical
+    /// registers while the firmware runs.
+    fn save_h_csrs(&mut self) {
+        self.csr.hstatus = Arch::read_csr(Csr::Hstatus);
+        self.csr.hedeleg = Arch::read_csr(Csr::Hedeleg);
+        self.csr.hideleg = Arch::read_csr(Csr::Hideleg);
+        self.csr.hvip = Arch::read_csr(Csr::Hvip);
+        self.csr.hip = Arch::read_csr(Csr::Hip);
+        self.csr.hie = Arch::read_csr(Csr::Hie);
+        self.csr.hgeip = Arch::read_csr(Csr::Hgeip); // Read only register, this write will have no effect
+        self.csr.hgeie = Arch::read_csr(Csr::Hgeie);
+        self.csr.henvcfg = Arch::read_csr(Csr::Henvcfg);
+        self.csr.hcounteren = Arch::read_csr(Csr::Hcounteren);
+        self.csr.htimedelta = Arch::read_csr(Csr::Htimedelta);
+        self.csr.htval = Arch::read_csr(Csr::Htval);
+        self.csr.htinst = Arch::read_csr(Csr::Htinst);
+        self.csr.hgatp = Arch::read_csr(Csr::Hgatp);
+
+        self.csr.vsstatus = Arch::read_csr(Csr::Vsstatus);
+        self.csr.vsie = Arch::read_csr(Csr::Vsie);
+        self.csr.vstvec = Arch::read_csr(Csr::Vstvec);
+        self.csr.vsscratch = Arch::read_csr(Csr::Vsscratch);
+        self.csr.vsepc = Arch::read_csr(Csr::Vsepc);
+        self.csr.vscause = Arch::read_csr(Csr::Vscause);
+        self.csr.vstval = Arch::read_csr(Csr::Vstval);
+        self.csr.vsip = Arch::read_csr(Csr::Vsip);
+        self.csr.vsatp = Arch::read_csr(Csr::Vsatp);
+    }
+
+    /// Saves the hypervisor CSRs of the payload if the world switch skipped them, must be called
+    /// before accessing those registers in the virtual context.
+    fn sync_lazy_h_csrs(&mut self) {
+        if core::mem::take(&mut self.lazy_h_csrs) {
+            self.save_h_csrs();
+        }
+    }
+
+    /// Returns the value of a hypervisor or virtual supervisor CSR in the virtual context.
+    pub fn h_csr(&mut self, csr: Csr) -> usize {
+        *self.h_csr_mut(csr)
+    }
+
+    /// Returns a reference to a hypervisor or virtual supervisor CSR in the virtual context.
+    ///
+    /// The CSRs are saved first if the world switch skipped them, such that the value is never
+    /// stale. Panics if the CSR is not a hypervisor CSR (see [Csr::is_hypervisor]).
+    pub fn h_csr_mut(&mut self, csr: Csr) -> &mut usize {
+        self.sync_lazy_h_csrs();
+        match csr {
+            Csr::Hstatus => &mut self.csr.hstatus,
+            Csr::Hedeleg => &mut self.csr.hedeleg,
+            Csr::Hideleg => &mut self.csr.hideleg,
+            Csr::Hvip => &mut self.csr.hvip,
+            Csr::Hip => &mut self.csr.hip,
+            Csr::Hie => &mut self.csr.hie,
+            Csr::Hgeip => &mut self.csr.hgeip,
+            Csr::Hgeie => &mut self.csr.hgeie,
+            Csr::Henvcfg => &mut self.csr.henvcfg,
+            Csr::Hcounteren => &mut self.csr.hcounteren,
+            Csr::Htimedelta => &mut self.csr.htimedelta,
+            Csr::Htval => &mut self.csr.htval,
+            Csr::Htinst => &mut self.csr.htinst,
+            Csr::Hgatp => &mut self.csr.hgatp,
+            Csr::Vsstatus => &mut self.csr.vsstatus,
+            Csr::Vsie => &mut self.csr.vsie,
+            Csr::Vstvec => &mut self.csr.vstvec,
+            Csr::Vsscratch => &mut self.csr.vsscratch,
+            Csr::Vsepc => &mut self.csr.vsepc,
+            Csr::Vscause => &mut self.csr.vscause,
+            Csr::Vstval => &mut self.csr.vstval,
+            Csr::Vsip => &mut self.csr.vsip,
+            Csr::Vsatp => &mut self.csr.vsatp,
+            _ => panic!("{:?} is not a hypervisor CSR", csr),
+        }
+    }
 }
 
 // ———————————————————————— Register Setters/Getters ———————————————————————— //
@@ -1044,6 +1905,10 @@ impl RegisterContextSetter<Register> for VirtContext {
 
 impl RegisterContextGetter<Csr> for VirtContext {
     fn get(&self, register: Csr) -> usize {
+        assert!(
+            !(self.lazy_h_csrs && register.is_hypervisor()),
+            "Hypervisor CSRs must be saved before being read"
+        );
         match register {
             Csr::Mhartid => self.hart_id,
             Csr::Mstatus => self.csr.mstatus & mstatus::MSTATUS_FILTER,
@@ -1064,7 +1929,14 @@ impl RegisterContextGetter<Csr> for VirtContext {
                 // To properly emulate this we should treat `csrrs(i)` and `csrrc(i)` differently
                 // when accessing `mip`. For now we simply choose the easy solution and hide the
                 // hardware bit from the virtualized firmware.
-                self.csr.mip
+                //
+                // With Sstc enabled mip.STIP reflects whether the payload time reached `stimecmp`.
+                if self.csr.menvcfg & menvcfg::STCE_FILTER != 0 {
+                    let pending = TIMEBASE.read(ExecutionMode::Payload) >= self.csr.stimecmp;
+                    self.csr.mip & !mie::STIE_FILTER | (pending as usize) << mie::STIE_OFFSET
+                } else {
+                    self.csr.mip
+                }
             }
             Csr::Mtvec => self.csr.mtvec,
             Csr::Mscratch => self.csr.mscratch,
@@ -1090,8 +1962,10 @@ impl RegisterContextGetter<Csr> for VirtContext {
                 }
                 self.csr.pmpaddr[pmp_addr_idx]
             }
-            Csr::Mcycle => self.csr.mcycle,
-            Csr::Minstret => self.csr.minstret,
+            Csr::Mcycle => self.counters.read(ExecutionMode::Firmware, Counter::Cycle),
+            Csr::Minstret => self
+                .counters
+                .read(ExecutionMode::Firmware, Counter::Instret),
             Csr::Mhpmcounter(n) => self.csr.mhpmcounter[n],
             Csr::Mcountinhibit => self.csr.mcountinhibit,
             Csr::Mhpmevent(n) => self.csr.mhpmevent[n],
@@ -1114,16 +1988,17 @@ impl RegisterContextGetter<Csr> for VirtContext {
                     panic!("Mtval exists only in H mode")
                 }
             }
-            Csr::Tdata1 => todo!(),                 // TODO : normal read
-            Csr::Tdata2 => todo!(),                 // TODO : normal read
-            Csr::Tdata3 => todo!(),                 // TODO : normal read
+            Csr::Tselect => self.csr.tselect,
+            // Reads as type 0 (no trigger) when no virtual trigger is exposed
+            Csr::Tdata1 => self.get_tdata(0),
+            Csr::Tdata2 => self.get_tdata(1),
+            Csr::Tdata3 => self.get_tdata(2),
             Csr::Mcontext => todo!(),               // TODO : normal read
             Csr::Dcsr => todo!(),                   // TODO : normal read
             Csr::Dpc => todo!(),                    // TODO : normal read
             Csr::Dscratch0 => todo!(),              // TODO : normal read
             Csr::Dscratch1 => todo!(),              // TODO : normal read
             Csr::Mconfigptr => self.csr.mconfigptr, // Read-only
-            Csr::Tselect => todo!(), // TODO : NO INFORMATION IN THE SPECIFICATION : read debug-mode specification
             Csr::Mepc => self.csr.mepc,
             Csr::Mcause => self.csr.mcause,
             Csr::Mtval => self.csr.mtval,
@@ -1140,7 +2015,8 @@ impl RegisterContextGetter<Csr> for VirtContext {
             Csr::Sip => self.get(Csr::Mip) & mie::SIE_FILTER,
             Csr::Satp => self.csr.satp,
             Csr::Scontext => self.csr.scontext,
-            Csr::Hstatus => self.csr.hstatus, // TODO : Add support for H-Mode
+            Csr::Stimecmp => self.csr.stimecmp,
+            Csr::Hstatus => self.csr.hstatus,
             Csr::Hedeleg => self.csr.hedeleg,
             Csr::Hideleg => self.csr.hideleg,
             Csr::Hvip => self.csr.hvip,
@@ -1185,6 +2061,13 @@ impl RegisterContextGetter<Csr> for VirtContext {
                 }
             }
             Csr::Vsatp => self.csr.vsatp,
+            // Unprivileged counters
+            Csr::Cycle => self.counters.read(ExecutionMode::Firmware, Counter::Cycle),
+            Csr::Time => TIMEBASE.read(ExecutionMode::Firmware),
+            Csr::Instret => self
+                .counters
+                .read(ExecutionMode::Firmware, Counter::Instret),
+            Csr::Hpmcounter(n) => self.csr.mhpmcounter[n],
             // Unknown
             Csr::Unknown => panic!("Tried to access unknown CSR: {:?}", register),
         }
@@ -1193,6 +2076,9 @@ impl RegisterContextGetter<Csr> for VirtContext {
 
 impl HwRegisterContextSetter<Csr> for VirtContext {
     fn set_csr(&mut self, register: Csr, value: usize, mctx: &mut MiralisContext) {
+        if register.is_hypervisor() {
+            self.sync_lazy_h_csrs();
+        }
         let hw = &mctx.hw;
         match register {
             Csr::Mhartid => (), // Read-only
@@ -1200,17 +2086,7 @@ impl HwRegisterContextSetter<Csr> for VirtContext {
                 // TODO: create some constant values
                 let mut new_value = value & mstatus::MSTATUS_FILTER; //self.csr.mstatus;
                                                                      // MPP : 11 : write legal : 0,1,3
-                let mpp = (value & mstatus::MPP_FILTER) >> mstatus::MPP_OFFSET;
-                VirtCsr::set_csr_field(
-                    &mut new_value,
-                    mstatus::MPP_OFFSET,
-                    mstatus::MPP_FILTER,
-                    if mpp == 0 || mpp == 1 || mpp == 3 {
-                        mpp
-                    } else {
-                        0
-                    },
-                );
+                new_value = mstatus::MPP.legalize(new_value, 0, |mpp| mpp != 2);
                 // SXL : 34 : read-only : MX-LEN = 64
                 let mxl: usize = 2;
                 VirtCsr::set_csr_field(
@@ -1291,8 +2167,9 @@ impl HwRegisterContextSetter<Csr> for VirtContext {
                         );
                     }
                 }
-                // FS : 13 : read-only 0 (NO S-MODE, F extension)
-                if !mctx.hw.extensions.has_s_extension {
+                // FS : 13 : read-only 0 (NO S-MODE, NO F extension)
+                // With S-mode the field also holds the state of the payload, it stays writable.
+                if !mctx.hw.extensions.has_s_extension && self.csr.misa & misa::F == 0 {
                     VirtCsr::set_csr_field(
                         &mut new_value,
                         mstatus::FS_OFFSET,
@@ -1300,47 +2177,68 @@ impl HwRegisterContextSetter<Csr> for VirtContext {
                         0,
                     );
                 }
-                // VS : 9 : read-only 0 (v registers)
-                VirtCsr::set_csr_field(&mut new_value, mstatus::VS_OFFSET, mstatus::VS_FILTER, 0);
+                // VS : 9 : read-only 0 (NO V extension)
+                if self.csr.misa & misa::V == 0 {
+                    VirtCsr::set_csr_field(
+                        &mut new_value,
+                        mstatus::VS_OFFSET,
+                        mstatus::VS_FILTER,
+                        0,
+                    );
+                }
+                // MPV & GVA : 39 & 38 : read-only 0 (NO H extension)
+                if self.csr.misa & misa::H == 0 {
+                    new_value &= !(mstatus::MPV_FILTER | mstatus::GVA_FILTER);
+                }
                 // XS : 15 : read-only 0 (NO FS nor VS)
                 VirtCsr::set_csr_field(&mut new_value, mstatus::XS_OFFSET, mstatus::XS_FILTER, 0);
-                // SD : 63 : read-only 0 (if NO FS/VS/XS)
-                VirtCsr::set_csr_field(
-                    &mut new_value,
-                    mstatus::SD_OFFSET,
-                    mstatus::SD_FILTER,
-                    if mctx.hw.extensions.has_s_extension {
-                        let fs: usize = (value & mstatus::FS_FILTER) >> mstatus::FS_OFFSET;
-                        if fs != 0 {
-                            0b1
-                        } else {
-                            0b0
-                        }
-                    } else {
-                        0
-                    },
-                );
+                // SD : 63 : read-only, summarizes the dirty state of FS/VS/XS
+                let new_value = with_dirty_summary(new_value);
+
+                // The FP and vector registers are shared with the payload, as on native hardware.
+                // The firmware runs with the FS and VS fields it configured, such that its own
+                // floating point and vector instructions are enabled accordingly.
+                unsafe {
+                    Arch::clear_csr_bits(Csr::Mstatus, mstatus::FS_FILTER | mstatus::VS_FILTER);
+                    Arch::set_csr_bits(
+                        Csr::Mstatus,
+                        new_value & (mstatus::FS_FILTER | mstatus::VS_FILTER),
+                    );
+                }
 
                 self.csr.mstatus = new_value;
             }
             Csr::Misa => {
                 // misa shows the extensions available : we cannot have more than possible in hardware
                 let arch_misa: usize = Arch::read_csr(Csr::Misa);
-                // Update misa to a legal value
-                self.csr.misa =
-                    (value & arch_misa & misa::MISA_CHANGE_FILTER & !misa::DISABLED) | misa::MXL;
+                // Update misa to a legal value, the write takes effect before the next instruction
+                self.csr.misa = legalize_misa(self.csr.misa, value, arch_misa, self.pc + 4);
 
-                if (self.csr.misa & misa::S) == 0 && mctx.hw.extensions.has_s_extension {
-                    panic!("Miralis doesn't support deactivating the S mode extension, please implement the feature")
+                // The state of disabled extensions becomes read-only zero
+                if !mctx.hw.extensions.has_s_extension && self.csr.misa & misa::F == 0 {
+                    self.csr.mstatus &= !mstatus::FS_FILTER;
                 }
-
-                if (self.csr.misa & misa::H) == 0 && mctx.hw.extensions.has_h_extension {
-                    panic!("Miralis doesn't support deactivating the H mode extension, please implement the feature")
+                if self.csr.misa & misa::V == 0 {
+                    self.csr.mstatus &= !mstatus::VS_FILTER;
+                }
+                self.csr.mstatus = with_dirty_summary(self.csr.mstatus);
+                unsafe {
+                    Arch::clear_csr_bits(
+                        Csr::Mstatus,
+                        !self.csr.mstatus & (mstatus::FS_FILTER | mstatus::VS_FILTER),
+                    );
                 }
             }
-            Csr::Mie => self.csr.mie = value & hw.interrupts & mie::MIE_WRITE_FILTER,
+            Csr::Mie => {
+                self.csr.mie = value & hw.interrupts & mie::MIE_WRITE_FILTER;
+                self.update_pending_interrupts();
+            }
             Csr::Mip => {
-                let value = value & hw.interrupts & mie::MIP_WRITE_FILTER;
+                let mut value = value & hw.interrupts & mie::MIP_WRITE_FILTER;
+                // mip.STIP is read-only when Sstc is enabled
+                if self.csr.menvcfg & menvcfg::STCE_FILTER != 0 {
+                    value = value & !mie::STIE_FILTER | self.csr.mip & mie::STIE_FILTER;
+                }
 
                 // If the firmware wants to read the mip register after cleaning vmip.SEIP, and we don't sync
                 // vmip.SEIP with mip.SEIP, it can't know if there is an interrupt signal from the interrupt
@@ -1359,6 +2257,7 @@ impl HwRegisterContextSetter<Csr> for VirtContext {
                     }
                 }
                 self.csr.mip = value | (self.csr.mip & mie::MIDELEG_READ_ONLY_ZERO);
+                self.update_pending_interrupts();
             }
             Csr::Mtvec => self.csr.mtvec = value,
             Csr::Mscratch => self.csr.mscratch = value,
@@ -1381,6 +2280,13 @@ impl HwRegisterContextSetter<Csr> for VirtContext {
                 self.csr.pmpcfg[pmp_cfg_idx / 2] = Csr::PMP_CFG_LEGAL_MASK
                     & value
                     & VirtCsr::get_pmp_cfg_filter(pmp_cfg_idx, self.nb_pmp);
+                audit::record(
+                    self.hart_id,
+                    AuditEvent::PmpcfgWrite {
+                        idx: pmp_cfg_idx,
+                        value: self.csr.pmpcfg[pmp_cfg_idx / 2],
+                    },
+                );
             }
             Csr::Pmpaddr(pmp_addr_idx) => {
                 if pmp_addr_idx >= mctx.hw.available_reg.nb_pmp {
@@ -1388,20 +2294,61 @@ impl HwRegisterContextSetter<Csr> for VirtContext {
                     return;
                 }
                 self.csr.pmpaddr[pmp_addr_idx] = Csr::PMP_ADDR_LEGAL_MASK & value;
+                audit::record(
+                    self.hart_id,
+                    AuditEvent::PmpaddrWrite {
+                        idx: pmp_addr_idx,
+                        value: self.csr.pmpaddr[pmp_addr_idx],
+                    },
+                );
             }
-            Csr::Mcycle => (),                                      // Read-only 0
-            Csr::Minstret => (),                                    // Read-only 0
-            Csr::Mhpmcounter(_counter_idx) => (),                   // Read-only 0
-            Csr::Mcountinhibit => (),                               // Read-only 0
-            Csr::Mhpmevent(_event_idx) => (),                       // Read-only 0
+            Csr::Mcycle => self
+                .counters
+                .write(ExecutionMode::Firmware, Counter::Cycle, value),
+            Csr::Minstret => self
+                .counters
+                .write(ExecutionMode::Firmware, Counter::Instret, value),
+            Csr::Mhpmcounter(_counter_idx) => (), // Read-only 0
+            Csr::Mcountinhibit => {
+                // Only CY and IR, the HPM counters are read-only 0
+                self.csr.mcountinhibit = value & (mcounteren::CY_FILTER | mcounteren::IR_FILTER);
+                self.counters.set_inhibit(self.csr.mcountinhibit);
+            }
+            Csr::Mhpmevent(_event_idx) => (), // Read-only 0
             Csr::Mcounteren => self.csr.mcounteren = value & 0b111, // Only show IR, TM and CY (for cycle, time and instret counters)
-            Csr::Menvcfg => self.csr.menvcfg = value,
-            Csr::Mseccfg => self.csr.mseccfg = value,
-            Csr::Mconfigptr => (),                    // Read-only
-            Csr::Medeleg => self.csr.medeleg = value, //TODO : some values need to be read-only 0
+            Csr::Menvcfg => {
+                // The page table walks emulated by Miralis do not update the A/D bits, ADUE is
+                // therefore read-only zero such that the hardware behaves the same
+                let value = value & !menvcfg::ADUE_FILTER;
+                // STCE is read-only zero without Sstc
+                self.csr.menvcfg = if mctx.hw.extensions.has_sstc_extension {
+                    value
+                } else {
+                    value & !menvcfg::STCE_FILTER
+                }
+            }
+            Csr::Mseccfg => {
+                // Only implemented with Smepmp, the firmware can not lock its virtual PMPs
+                if mctx.hw.extensions.has_smepmp_extension {
+                    self.csr.mseccfg = pmp::mseccfg::write(self.csr.mseccfg, value, false);
+                    if self.csr.mseccfg & pmp::mseccfg::MML != 0 {
+                        debug::warn_once!("Smepmp machine mode lockdown is not yet supported");
+                    }
+                }
+            }
+            Csr::Mconfigptr => (), // Read-only
+            Csr::Medeleg => {
+                self.csr.medeleg = value; //TODO : some values need to be read-only 0
+                audit::record(self.hart_id, AuditEvent::MedelegWrite(self.csr.medeleg));
+            }
             Csr::Mideleg => {
                 self.csr.mideleg = (value & hw.interrupts & !mie::MIDELEG_READ_ONLY_ZERO)
                     | mie::MIDELEG_READ_ONLY_ONE;
+                if mctx.hw.extensions.has_h_extension {
+                    self.csr.mideleg |= mie::MIDELEG_HYPERVISOR_READ_ONLY_ONE;
+                }
+                audit::record(self.hart_id, AuditEvent::MidelegWrite(self.csr.mideleg));
+                self.update_pending_interrupts();
             }
             Csr::Mtinst => {
                 if mctx.hw.extensions.has_h_extension {
@@ -1417,13 +2364,27 @@ impl HwRegisterContextSetter<Csr> for VirtContext {
                     panic!("Mtval2 exists only in H mode")
                 }
             } // TODO : Must be able to hold 0 and may hold an arbitrary number of 2-bit-shifted guest physical addresses, written alongside mtval, this register should not exist in a system without hypervisor extension
-            Csr::Tselect => todo!(), // Read-only 0 when no triggers are implemented
-            Csr::Tdata1 => todo!(),  // TODO : NO INFORMATION IN THE SPECIFICATION
-            Csr::Tdata2 => todo!(),  // TODO : NO INFORMATION IN THE SPECIFICATION
-            Csr::Tdata3 => todo!(),  // TODO : NO INFORMATION IN THE SPECIFICATION
+            Csr::Tselect => {
+                // Out of range values are ignored, so that the firmware reads back a different
+                // value and detects the number of triggers.
+                if value < self.csr.tdata.len() {
+                    self.csr.tselect = value;
+                }
+            }
+            Csr::Tdata1 => {
+                let value = match tdata1::get_type(value) {
+                    // The firmware can not enter debug mode, so it can not set DMODE
+                    tdata1::TYPE_MCONTROL | tdata1::TYPE_MCONTROL6 => value & !tdata1::DMODE_FILTER,
+                    // Unsupported types disable the trigger
+                    _ => tdata1::TYPE_DISABLED << tdata1::TYPE_OFFSET,
+                };
+                self.set_tdata(0, value);
+            }
+            Csr::Tdata2 => self.set_tdata(1, value),
+            Csr::Tdata3 => self.set_tdata(2, value),
             Csr::Mcontext => todo!(), // TODO : NO INFORMATION IN THE SPECIFICATION
-            Csr::Dcsr => todo!(),    // TODO : NO INFORMATION IN THE SPECIFICATION
-            Csr::Dpc => todo!(),     // TODO : NO INFORMATION IN THE SPECIFICATION
+            Csr::Dcsr => todo!(),     // TODO : NO INFORMATION IN THE SPECIFICATION
+            Csr::Dpc => todo!(),      // TODO : NO INFORMATION IN THE SPECIFICATION
             Csr::Dscratch0 => todo!(), // TODO : NO INFORMATION IN THE SPECIFICATION
             Csr::Dscratch1 => todo!(), // TODO : NO INFORMATION IN THE SPECIFICATION
             Csr::Mepc => {
@@ -1488,9 +2449,13 @@ impl HwRegisterContextSetter<Csr> for VirtContext {
                 self.set_csr(Csr::Mip, mip | (value & mie::SIE_FILTER), mctx);
             }
             Csr::Satp => {
-                self.csr.satp = value & satp::SATP_CHANGE_FILTER;
+                // Writes with an unsupported mode have no effect, ASIDs are not implemented
+                if page_table::is_supported_satp_mode(value, &self.extensions) {
+                    self.csr.satp = value & (satp::MODE_FILTER | satp::SATP_CHANGE_FILTER);
+                }
             }
             Csr::Scontext => todo!("No information in the specification"),
+            Csr::Stimecmp => self.csr.stimecmp = value,
             Csr::Hstatus => {
                 let mut value = value;
 
@@ -1504,23 +2469,12 @@ impl HwRegisterContextSetter<Csr> for VirtContext {
                 );
 
                 if !mctx.hw.extensions.has_s_extension {
-                    // VTSR is read only if S-mode is not present
-                    VirtCsr::set_csr_field(
-                        &mut value,
-                        hstatus::VTSR_OFFSET,
-                        hstatus::VTSR_FILTER,
-                        0,
-                    );
-                    // VTVM is read only if S-mode is not present
-                    VirtCsr::set_csr_field(
-                        &mut value,
-                        hstatus::VTVM_OFFSET,
-                        hstatus::VTVM_FILTER,
-                        0,
-                    );
+                    // VTSR and VTVM are read only if S-mode is not present
+                    value = hstatus::VTSR.set(value, 0);
+                    value = hstatus::VTVM.set(value, 0);
                     // VTW is read only if H mode is the lowest priviledge mode
                     // and U-mode must exist in Miralis
-                    VirtCsr::set_csr_field(&mut value, hstatus::VTW_FILTER, hstatus::VTW_FILTER, 0);
+                    value = hstatus::VTW.set(value, 0);
                 }
 
                 // We don't implement the feature as it is a very niche one
@@ -1584,6 +2538,11 @@ impl HwRegisterContextSetter<Csr> for VirtContext {
                 self.csr.vsip = value & write_vsip_mask
             }
             Csr::Vsatp => self.csr.vsatp = value,
+            // Unprivileged counters
+            Csr::Cycle => (),         // Read-only
+            Csr::Time => (),          // Read-only
+            Csr::Instret => (),       // Read-only
+            Csr::Hpmcounter(_) => (), // Read-only
             // Unknown
             Csr::Unknown => panic!("Tried to access unknown CSR: {:?}", register),
         }
@@ -1626,6 +2585,39 @@ where
     }
 }
 
+/// Returns mstatus with the SD bit set if and only if one of FS, VS, or XS is dirty.
+fn with_dirty_summary(mstatus: usize) -> usize {
+    let fs = (mstatus & mstatus::FS_FILTER) >> mstatus::FS_OFFSET;
+    let vs = (mstatus & mstatus::VS_FILTER) >> mstatus::VS_OFFSET;
+    let xs = (mstatus & mstatus::XS_FILTER) >> mstatus::XS_OFFSET;
+    if fs == mstatus::DIRTY || vs == mstatus::DIRTY || xs == mstatus::DIRTY {
+        mstatus | mstatus::SD_FILTER
+    } else {
+        mstatus & !mstatus::SD_FILTER
+    }
+}
+
+/// Returns the legal value of the virtual misa after the firmware writes `value`.
+///
+/// Only the extensions of `misa::WRITABLE` supported by both the hardware and Miralis can be
+/// toggled, the other supported extensions are always enabled. As required by the specification D
+/// depends on F and Q on D, and a write disabling C is suppressed if the next instruction is not
+/// 4-byte aligned.
+fn legalize_misa(current: usize, value: usize, hw_misa: usize, next_pc: usize) -> usize {
+    let supported = hw_misa & misa::MISA_CHANGE_FILTER & !misa::DISABLED;
+    let mut legal = (supported & !misa::WRITABLE) | (value & supported & misa::WRITABLE);
+    if legal & misa::F == 0 {
+        legal &= !misa::D;
+    }
+    if legal & misa::D == 0 {
+        legal &= !misa::Q;
+    }
+    if legal & misa::C == 0 && current & misa::C != 0 && next_pc % 4 != 0 {
+        return current;
+    }
+    legal | misa::MXL
+}
+
 /// Return the ID of the next interrupt to be delivered, if any.
 fn get_next_interrupt(mie: usize, mip: usize, mideleg: usize) -> Option<usize> {
     let ints = mie & mip & !mideleg;
@@ -1642,13 +2634,204 @@ fn get_next_interrupt(mie: usize, mip: usize, mideleg: usize) -> Option<usize> {
 
 #[cfg(test)]
 mod tests {
+    use core::mem::offset_of;
     use core::usize;
 
-    use super::get_next_interrupt;
-    use crate::arch::{mie, mstatus, Arch, Architecture, Csr, Mode};
+    use super::{get_next_interrupt, legalize_misa, with_dirty_summary, VirtCsr};
+    use crate::arch::{
+        menvcfg, mie, misa, mstatus, tdata1, Arch, Architecture, Csr, MCause, Mode, Register,
+        TrapInfo, Width,
+    };
+    use crate::config::{VCPU_RAW_COUNTERS, VCPU_TRIGGERS};
+    use crate::counters::Counter;
+    use crate::decoder::Instr;
+    use crate::device::{DeviceAccess, PayloadAccess, VirtDevice, WriteSemantic};
     use crate::host::MiralisContext;
-    use crate::virt::VirtContext;
-    use crate::HwRegisterContextSetter;
+    use crate::virt::{ExecutionMode, RegisterContextSetter, VirtContext};
+    use crate::{HwRegisterContextSetter, RegisterContextGetter};
+
+    /// A device returning a full register on every read, whatever the width of the access.
+    struct WideDevice;
+
+    impl DeviceAccess for WideDevice {
+        fn read_device(
+            &self,
+            _offset: usize,
+            _r_width: Width,
+            _ctx: &mut VirtContext,
+        ) -> Result<usize, &'static str> {
+            Ok(0xdead_beef_8765_8281)
+        }
+
+        fn write_device(
+            &self,
+            _offset: usize,
+            _w_width: Width,
+            _value: usize,
+            _ctx: &mut VirtContext,
+        ) -> Result<(), &'static str> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn device_load_extension() {
+        static WIDE_DEVICE: WideDevice = WideDevice;
+        let device = VirtDevice {
+            start_addr: 0x1000,
+            size: 0x100,
+            name: "WIDE",
+            device_interface: &WIDE_DEVICE,
+            payload_access: PayloadAccess::Denied,
+        };
+        let hw = unsafe { Arch::detect_hardware() };
+        let mctx = MiralisContext::new(hw);
+        let mut ctx = VirtContext::new(0, mctx.hw.available_reg.nb_pmp, mctx.hw.extensions.clone());
+        ctx.set(Register::X11, 0x1000);
+
+        let mut load = |len, is_unsigned| {
+            let instr = Instr::Load {
+                rd: Register::X10,
+                rs1: Register::X11,
+                imm: 0x8,
+                len,
+                is_compressed: false,
+                is_unsigned,
+            };
+            ctx.handle_load(&device, &instr);
+            ctx.get(Register::X10)
+        };
+
+        assert_eq!(load(Width::Byte, false), 0xffff_ffff_ffff_ff81);
+        assert_eq!(load(Width::Byte, true), 0x81);
+        assert_eq!(load(Width::Byte2, false), 0xffff_ffff_ffff_8281);
+        assert_eq!(load(Width::Byte2, true), 0x8281);
+        assert_eq!(load(Width::Byte4, false), 0xffff_ffff_8765_8281);
+        assert_eq!(load(Width::Byte4, true), 0x8765_8281);
+        assert_eq!(load(Width::Byte8, false), 0xdead_beef_8765_8281);
+    }
+
+    /// A device posting its writes, except at offset 0x20, which rejects writes at offset 0x10.
+    struct PostedDevice;
+
+    impl DeviceAccess for PostedDevice {
+        fn read_device(
+            &self,
+            _offset: usize,
+            _r_width: Width,
+            _ctx: &mut VirtContext,
+        ) -> Result<usize, &'static str> {
+            Ok(0)
+        }
+
+        fn write_device(
+            &self,
+            offset: usize,
+            _w_width: Width,
+            _value: usize,
+            _ctx: &mut VirtContext,
+        ) -> Result<(), &'static str> {
+            if offset == 0x10 {
+                return Err("Invalid offset");
+            }
+            Ok(())
+        }
+
+        fn write_semantic(&self, offset: usize) -> WriteSemantic {
+            if offset == 0x20 {
+                WriteSemantic::NonPosted
+            } else {
+                WriteSemantic::Posted
+            }
+        }
+    }
+
+    #[test]
+    fn posted_write_fault() {
+        static POSTED_DEVICE: PostedDevice = PostedDevice;
+        let device = VirtDevice {
+            start_addr: 0x1000,
+            size: 0x100,
+            name: "POSTED",
+            device_interface: &POSTED_DEVICE,
+            payload_access: PayloadAccess::Denied,
+        };
+        let hw = unsafe { Arch::detect_hardware() };
+        let mctx = MiralisContext::new(hw);
+        let mut ctx = VirtContext::new(0, mctx.hw.available_reg.nb_pmp, mctx.hw.extensions.clone());
+        ctx.csr.mtvec = 0x8000_0000;
+        ctx.set(Register::X11, 0x1000);
+        ctx.pc = 0x8020_0000;
+
+        let store = |imm| Instr::Store {
+            rs2: Register::X10,
+            rs1: Register::X11,
+            imm,
+            len: Width::Byte4,
+            is_compressed: false,
+        };
+
+        // Writes are posted, the stores retire immediately
+        ctx.handle_store(&device, &store(0x0));
+        ctx.handle_store(&device, &store(0x10));
+        ctx.handle_store(&device, &store(0x4));
+        assert_eq!(ctx.pc, 0x8020_000c);
+        assert!(!ctx.deferred_effects.is_empty());
+
+        // The rejected write is reported on the store which posted it
+        assert!(ctx.drain_deferred_effects());
+        assert!(ctx.deferred_effects.is_empty());
+        assert_eq!(ctx.csr.mcause, MCause::StoreAccessFault as usize);
+        assert_eq!(ctx.csr.mtval, 0x1010);
+        assert_eq!(ctx.csr.mepc, 0x8020_0004);
+        assert_eq!(ctx.pc, 0x8000_0000);
+        assert!(!ctx.drain_deferred_effects());
+
+        // A non-posted store drains the rejected write and does not retire
+        ctx.pc = 0x8020_0000;
+        ctx.handle_store(&device, &store(0x10));
+        ctx.handle_store(&device, &store(0x20));
+        assert_eq!(ctx.csr.mepc, 0x8020_0000);
+        assert_eq!(ctx.pc, 0x8000_0000);
+
+        // Neither does a posted store draining a full queue
+        ctx.pc = 0x8020_0000;
+        ctx.handle_store(&device, &store(0x10));
+        while !ctx.deferred_effects.is_full() {
+            ctx.handle_store(&device, &store(0x4));
+        }
+        ctx.handle_store(&device, &store(0x4));
+        assert!(ctx.deferred_effects.is_empty());
+        assert_eq!(ctx.csr.mepc, 0x8020_0000);
+        assert_eq!(ctx.pc, 0x8000_0000);
+    }
+
+    #[test]
+    fn delegated_exceptions() {
+        let hw = unsafe { Arch::detect_hardware() };
+        let mut mctx = MiralisContext::new(hw);
+        let mut ctx = VirtContext::new(0, mctx.hw.available_reg.nb_pmp, mctx.hw.extensions.clone());
+        let illegal_instr = 1 << MCause::IllegalInstr as usize;
+        let page_faults = (1 << MCause::LoadPageFault as usize)
+            | (1 << MCause::StorePageFault as usize)
+            | (1 << MCause::InstrPageFault as usize);
+        ctx.csr.medeleg = illegal_instr | page_faults;
+
+        // Delegated exceptions are delivered directly to the payload
+        unsafe { ctx.switch_from_firmware_to_payload(&mut mctx) }
+        assert_eq!(
+            Arch::read_csr(Csr::Medeleg),
+            ctx.delegated_exceptions(&mctx)
+        );
+        assert_eq!(ctx.delegated_exceptions(&mctx) & page_faults, page_faults);
+
+        // Illegal instructions trap to Miralis while the payload counters are emulated
+        ctx.counters
+            .write(ExecutionMode::Payload, Counter::Instret, usize::MAX);
+        if !VCPU_RAW_COUNTERS {
+            assert_eq!(ctx.delegated_exceptions(&mctx), page_faults);
+        }
+    }
 
     /// We test value of mstatus.MPP.
     /// When switching from firmware to payload,
@@ -1700,6 +2883,40 @@ mod tests {
         );
     }
 
+    /// Payload SBI calls forwarded to the firmware do not save the hypervisor CSRs, they are only
+    /// saved when the firmware accesses them.
+    #[test]
+    fn lazy_hypervisor_csrs() {
+        let hw = unsafe { Arch::detect_hardware() };
+        let mut mctx = MiralisContext::new(hw);
+        mctx.hw.extensions.has_h_extension = true;
+        let mut ctx = VirtContext::new(0, mctx.hw.available_reg.nb_pmp, mctx.hw.extensions.clone());
+
+        unsafe { Arch::write_csr(Csr::Vsscratch, 0x42) };
+        ctx.trap_info.mcause = MCause::EcallFromSMode as usize;
+        unsafe { ctx.switch_from_payload_to_firmware(&mut mctx) }
+        assert!(ctx.lazy_h_csrs);
+        assert_eq!(ctx.csr.vsscratch, 0);
+
+        // The first access saves the registers
+        ctx.set_csr(Csr::Vstvec, 0x1000, &mut mctx);
+        assert!(!ctx.lazy_h_csrs);
+        assert_eq!(ctx.get(Csr::Vsscratch), 0x42);
+        assert_eq!(ctx.get(Csr::Vstvec), 0x1000);
+
+        // So do reads through the accessors
+        unsafe { Arch::write_csr(Csr::Vsepc, 0x80) };
+        unsafe { ctx.switch_from_payload_to_firmware(&mut mctx) }
+        assert!(ctx.lazy_h_csrs);
+        assert_eq!(ctx.h_csr(Csr::Vsepc), 0x80);
+        assert!(!ctx.lazy_h_csrs);
+
+        // Other traps save the registers during the world switch
+        ctx.trap_info.mcause = MCause::IllegalInstr as usize;
+        unsafe { ctx.switch_from_payload_to_firmware(&mut mctx) }
+        assert!(!ctx.lazy_h_csrs);
+    }
+
     /// We test value of mideleg when switching from payload to firmware.
     /// Mideleg must always be 0 when executing the firware.
     #[test]
@@ -1747,6 +2964,97 @@ mod tests {
         );
     }
 
+    /// The firmware must be able to enumerate the virtual triggers, and must never see or configure
+    /// the hardware ones.
+    #[test]
+    fn virtual_triggers() {
+        let hw = unsafe { Arch::detect_hardware() };
+        let mut mctx = MiralisContext::new(hw);
+        let mut ctx = VirtContext::new(0, mctx.hw.available_reg.nb_pmp, mctx.hw.extensions.clone());
+
+        // Selecting a trigger past the last one has no effect
+        ctx.set_csr(Csr::Tselect, VCPU_TRIGGERS, &mut mctx);
+        assert_eq!(ctx.get(Csr::Tselect), 0);
+
+        if VCPU_TRIGGERS == 0 {
+            // Type 0 means that there is no trigger
+            ctx.set_csr(Csr::Tdata1, usize::MAX, &mut mctx);
+            assert_eq!(ctx.get(Csr::Tdata1), 0);
+            return;
+        }
+
+        // The firmware can not set DMODE
+        let mcontrol6 = (tdata1::TYPE_MCONTROL6 << tdata1::TYPE_OFFSET) | 0b1000100;
+        ctx.set_csr(Csr::Tdata1, mcontrol6 | tdata1::DMODE_FILTER, &mut mctx);
+        assert_eq!(ctx.get(Csr::Tdata1), mcontrol6);
+
+        // Unsupported types disable the trigger
+        ctx.set_csr(Csr::Tdata1, 0, &mut mctx);
+        assert_eq!(
+            tdata1::get_type(ctx.get(Csr::Tdata1)),
+            tdata1::TYPE_DISABLED
+        );
+    }
+
+    #[test]
+    fn payload_sbi_calls_without_s_mode() {
+        let hw = unsafe { Arch::detect_hardware() };
+        let mut mctx = MiralisContext::new(hw);
+        let mut ctx = VirtContext::new(0, mctx.hw.available_reg.nb_pmp, mctx.hw.extensions.clone());
+
+        // With S-mode, ecalls from U-mode target the payload's kernel
+        ctx.trap_info.mcause = MCause::EcallFromUMode as usize;
+        assert!(!ctx.is_payload_sbi_call(&mctx));
+        ctx.trap_info.mcause = MCause::EcallFromSMode as usize;
+        assert!(ctx.is_payload_sbi_call(&mctx));
+
+        // Without S-mode the payload runs in U-mode, its ecalls are SBI calls
+        mctx.hw.extensions.has_s_extension = false;
+        ctx.trap_info.mcause = MCause::EcallFromUMode as usize;
+        assert!(ctx.is_payload_sbi_call(&mctx));
+        ctx.trap_info.mcause = MCause::IllegalInstr as usize;
+        assert!(!ctx.is_payload_sbi_call(&mctx));
+    }
+
+    #[test]
+    fn dirty_summary() {
+        let fs_dirty = mstatus::DIRTY << mstatus::FS_OFFSET;
+        let fs_clean = 0b10 << mstatus::FS_OFFSET;
+        let vs_dirty = mstatus::DIRTY << mstatus::VS_OFFSET;
+
+        assert_eq!(with_dirty_summary(fs_dirty), fs_dirty | mstatus::SD_FILTER);
+        assert_eq!(with_dirty_summary(vs_dirty), vs_dirty | mstatus::SD_FILTER);
+        // SD is cleared once the state is no longer dirty
+        assert_eq!(with_dirty_summary(fs_clean | mstatus::SD_FILTER), fs_clean);
+    }
+
+    #[test]
+    fn misa_writes() {
+        let hw_misa = misa::MXL | misa::I | misa::M | misa::A | misa::C | misa::F | misa::D;
+        let supported = hw_misa & !misa::DISABLED;
+
+        // Extensions can not be enabled if not supported, nor the base ISA disabled
+        assert_eq!(legalize_misa(0, usize::MAX, hw_misa, 4), supported);
+        assert_eq!(
+            legalize_misa(supported, 0, hw_misa, 4),
+            supported & !misa::WRITABLE
+        );
+
+        // Writable extensions can be toggled
+        let no_atomics = legalize_misa(supported, supported & !misa::A, hw_misa, 4);
+        assert_eq!(no_atomics, supported & !misa::A);
+        assert_eq!(legalize_misa(no_atomics, supported, hw_misa, 4), supported);
+
+        // D depends on F
+        let hw_misa = hw_misa & !misa::F;
+        assert_eq!(legalize_misa(0, misa::D, hw_misa, 4) & misa::D, 0);
+
+        // Disabling C is suppressed if the next instruction is not aligned
+        let current = misa::MXL | misa::I | misa::C;
+        assert_eq!(legalize_misa(current, misa::I, current, 6), current);
+        assert_eq!(legalize_misa(current, misa::I, current, 8) & misa::C, 0);
+    }
+
     #[test]
     fn next_interrupt() {
         assert_eq!(get_next_interrupt(0b000, 0b000, 0b000), None);
@@ -1760,4 +3068,141 @@ mod tests {
         assert_eq!(get_next_interrupt(0b010, 0b011, 0b000), Some(1));
         assert_eq!(get_next_interrupt(0b011, 0b011, 0b001), Some(1));
     }
+
+    /// The cached pending interrupts mask must follow updates of mie, mip, and mideleg.
+    #[test]
+    fn pending_interrupts_mask() {
+        let hw = unsafe { Arch::detect_hardware() };
+        let mut mctx = MiralisContext::new(hw);
+        let mut ctx = VirtContext::new(0, mctx.hw.available_reg.nb_pmp, mctx.hw.extensions.clone());
+        assert_eq!(ctx.pending_interrupts, 0);
+
+        // The machine timer interrupt is raised by the hardware
+        ctx.csr.mip = mie::MTIE_FILTER;
+        ctx.update_pending_interrupts();
+        assert_eq!(ctx.pending_interrupts, 0, "MTIE is not enabled yet");
+
+        ctx.set_csr(Csr::Mie, mie::MTIE_FILTER | mie::SEIE_FILTER, &mut mctx);
+        assert_eq!(ctx.pending_interrupts, mie::MTIE_FILTER);
+
+        ctx.set_csr(Csr::Mip, mie::SEIE_FILTER, &mut mctx);
+        assert_eq!(
+            ctx.pending_interrupts,
+            mie::MTIE_FILTER,
+            "SEIE is delegated to S-mode"
+        );
+
+        ctx.set_csr(Csr::Mie, 0, &mut mctx);
+        assert_eq!(ctx.pending_interrupts, 0);
+    }
+
+    /// menvcfg.STCE is only writable with Sstc, in which case mip.STIP becomes read-only.
+    #[test]
+    fn sstc_menvcfg() {
+        let hw = unsafe { Arch::detect_hardware() };
+        let mut mctx = MiralisContext::new(hw);
+        let mut ctx = VirtContext::new(0, mctx.hw.available_reg.nb_pmp, mctx.hw.extensions.clone());
+
+        mctx.hw.extensions.has_sstc_extension = false;
+        ctx.set_csr(Csr::Menvcfg, menvcfg::STCE_FILTER, &mut mctx);
+        assert_eq!(ctx.csr.menvcfg, 0, "STCE is read-only zero without Sstc");
+        ctx.set_csr(Csr::Mip, mie::STIE_FILTER, &mut mctx);
+        assert_eq!(ctx.csr.mip & mie::STIE_FILTER, mie::STIE_FILTER);
+
+        mctx.hw.extensions.has_sstc_extension = true;
+        ctx.set_csr(Csr::Menvcfg, menvcfg::STCE_FILTER, &mut mctx);
+        assert_eq!(ctx.csr.menvcfg, menvcfg::STCE_FILTER);
+        ctx.set_csr(Csr::Mip, 0, &mut mctx);
+        assert_eq!(
+            ctx.csr.mip & mie::STIE_FILTER,
+            mie::STIE_FILTER,
+            "STIP is read-only with Sstc"
+        );
+    }
+
+    /// mseccfg is only writable with Smepmp, and MML and MMWP are sticky.
+    #[test]
+    fn smepmp_mseccfg() {
+        use crate::arch::pmp::mseccfg;
+
+        let hw = unsafe { Arch::detect_hardware() };
+        let mut mctx = MiralisContext::new(hw);
+        let mut ctx = VirtContext::new(0, mctx.hw.available_reg.nb_pmp, mctx.hw.extensions.clone());
+
+        mctx.hw.extensions.has_smepmp_extension = false;
+        ctx.set_csr(Csr::Mseccfg, mseccfg::MMWP, &mut mctx);
+        assert_eq!(
+            ctx.get(Csr::Mseccfg),
+            0,
+            "mseccfg is read-only zero without Smepmp"
+        );
+
+        mctx.hw.extensions.has_smepmp_extension = true;
+        ctx.set_csr(Csr::Mseccfg, mseccfg::MMWP | mseccfg::RLB, &mut mctx);
+        assert_eq!(ctx.get(Csr::Mseccfg), mseccfg::MMWP | mseccfg::RLB);
+        ctx.set_csr(Csr::Mseccfg, 0, &mut mctx);
+        assert_eq!(ctx.get(Csr::Mseccfg), mseccfg::MMWP, "MMWP is sticky");
+    }
+
+    #[test]
+    fn hpm_counters_read_only_zero() {
+        let hw = unsafe { Arch::detect_hardware() };
+        let mut mctx = MiralisContext::new(hw);
+        let mut ctx = VirtContext::new(0, mctx.hw.available_reg.nb_pmp, mctx.hw.extensions.clone());
+
+        for csr in [Csr::Mhpmcounter(0), Csr::Mhpmevent(28), Csr::Hpmcounter(28)] {
+            ctx.set_csr(csr, usize::MAX, &mut mctx);
+            assert_eq!(ctx.get(csr), 0, "{:?} is read-only zero", csr);
+        }
+        assert_eq!(mctx.decode_csr(0xC03), Csr::Hpmcounter(0));
+        assert_eq!(mctx.decode_csr(0xC1F), Csr::Hpmcounter(28));
+    }
+
+    /// The context switch assembly (`_run_vcpu` and `_raw_trap_handler`) hardcodes the offsets of
+    /// the first fields, and the fields touched on every exit must stay within the first cache
+    /// lines.
+    #[test]
+    fn virt_context_layout() {
+        const CACHE_LINE: usize = 64;
+        // host_stack, regs, pc, trap_info, and the hot CSRs
+        const HOT_CACHE_LINES: usize = 7;
+
+        assert_eq!(offset_of!(VirtContext, host_stack), 0);
+        assert_eq!(offset_of!(VirtContext, regs), 8);
+        assert_eq!(offset_of!(VirtContext, pc), 8 + 8 * 32);
+        assert_eq!(offset_of!(VirtContext, trap_info), 8 + 8 * 32 + 8);
+        assert_eq!(offset_of!(TrapInfo, mepc), 0);
+        assert_eq!(offset_of!(TrapInfo, mstatus), 8);
+        assert_eq!(offset_of!(TrapInfo, mcause), 8 * 2);
+        assert_eq!(offset_of!(TrapInfo, mip), 8 * 3);
+        assert_eq!(offset_of!(TrapInfo, mtval), 8 * 4);
+
+        let hot_end = HOT_CACHE_LINES * CACHE_LINE;
+        for end in [
+            offset_of!(VirtContext, mode) + 1,
+            offset_of!(VirtContext, virtualized) + 1,
+            offset_of!(VirtContext, nb_exits) + 8,
+            offset_of!(VirtContext, pending_interrupts) + 8,
+            offset_of!(VirtContext, hart_id) + 8,
+        ] {
+            assert!(end <= hot_end);
+        }
+
+        // The hot CSRs share a single cache line
+        let csr = offset_of!(VirtContext, csr);
+        assert_eq!(csr % CACHE_LINE, 0);
+        assert!(csr + CACHE_LINE <= hot_end);
+        for offset in [
+            offset_of!(VirtCsr, mstatus),
+            offset_of!(VirtCsr, mie),
+            offset_of!(VirtCsr, mip),
+            offset_of!(VirtCsr, mideleg),
+            offset_of!(VirtCsr, medeleg),
+            offset_of!(VirtCsr, mtvec),
+            offset_of!(VirtCsr, mepc),
+            offset_of!(VirtCsr, mcause),
+        ] {
+            assert!(offset + 8 <= CACHE_LINE);
+        }
+    }
 }